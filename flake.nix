@@ -23,7 +23,8 @@
           src = ./.;
           filter = path: type:
             (craneLib.filterCargoSources path type)
-            || (pkgs.lib.hasSuffix ".svg" (toString path));
+            || (pkgs.lib.hasSuffix ".svg" (toString path))
+            || (pkgs.lib.hasSuffix ".yaml" (toString path));
         };
 
         commonArgs = {
@@ -6,11 +6,229 @@
 }: let
   cfg = config.programs.slykey;
   yaml = pkgs.formats.yaml {};
+  expansionToYaml = rule:
+    {
+      inherit (rule) trigger expansion;
+    }
+    // lib.optionalAttrs (rule.apps != []) {
+      apps = rule.apps;
+    }
+    // lib.optionalAttrs (rule.excludeApps != []) {
+      exclude_apps = rule.excludeApps;
+    }
+    // lib.optionalAttrs (rule.injectMode != null) {
+      inject_mode = rule.injectMode;
+    }
+    // lib.optionalAttrs (rule.charDelayMs != null) {
+      char_delay_ms = rule.charDelayMs;
+    }
+    // lib.optionalAttrs (rule.notify != null) {
+      notify = rule.notify;
+    }
+    // lib.optionalAttrs (rule.notifyMessage != null) {
+      notify_message = rule.notifyMessage;
+    }
+    // lib.optionalAttrs (rule.label != null) {
+      label = rule.label;
+    }
+    // lib.optionalAttrs (!rule.enabled) {
+      enabled = false;
+    }
+    // lib.optionalAttrs (rule.group != null) {
+      group = rule.group;
+    }
+    // lib.optionalAttrs (rule.keepBoundary != null) {
+      keep_boundary = rule.keepBoundary;
+    }
+    // lib.optionalAttrs (rule.boundaryPosition != null) {
+      boundary_position = rule.boundaryPosition;
+    }
+    // lib.optionalAttrs (rule.stripTrailingNewline != null) {
+      strip_trailing_newline = rule.stripTrailingNewline;
+    }
+    // lib.optionalAttrs (rule.newline != "text") {
+      inherit (rule) newline;
+    };
+  snippetToYaml = snippet:
+    {
+      inherit (snippet) title content;
+    }
+    // lib.optionalAttrs (snippet.group != null) {
+      group = snippet.group;
+    }
+    // lib.optionalAttrs snippet.separatorAfter {
+      separator_after = true;
+    }
+    // lib.optionalAttrs (snippet.mode != null) {
+      mode = snippet.mode;
+    }
+    // lib.optionalAttrs (snippet.typeDelayMs != null) {
+      type_delay_ms = snippet.typeDelayMs;
+    };
+  expansionType = lib.types.submodule {
+    options = {
+      trigger = lib.mkOption {
+        type = lib.types.str;
+        description = "Typed trigger text.";
+        example = "sig;";
+      };
+      expansion = lib.mkOption {
+        type = lib.types.str;
+        description = "Text or macro sequence to emit.";
+        example = "Thanks, Tyler{{KEY:ENTER}}";
+      };
+      apps = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [];
+        description = "Only fire when the focused window's WM_CLASS matches one of these (case-insensitive).";
+        example = ["Alacritty"];
+      };
+      excludeApps = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [];
+        description = "Never fire when the focused window's WM_CLASS matches one of these (case-insensitive).";
+        example = ["firefox"];
+      };
+      injectMode = lib.mkOption {
+        type = lib.types.nullOr (lib.types.enum [
+          "type"
+          "clipboard"
+        ]);
+        default = null;
+        description = "Per-rule override of the global injectMode.";
+      };
+      charDelayMs = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.unsigned;
+        default = null;
+        description = "Per-rule override of output.charDelayMs.";
+      };
+      notify = lib.mkOption {
+        type = lib.types.nullOr lib.types.bool;
+        default = null;
+        description = "Per-rule override of notifications.onExpansion.";
+      };
+      notifyMessage = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = "Notification body for this rule instead of the trigger; template macros are rendered.";
+      };
+      label = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = "Name shown instead of the trigger in notifications, slykey list, the picker and warnings (at most 120 characters).";
+        example = "Support reply — shipping delay";
+      };
+      enabled = lib.mkOption {
+        type = lib.types.bool;
+        default = true;
+        description = "Set to false to keep the rule in the config without it firing.";
+      };
+      group = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = "Group name used to switch related rules on and off together (see disabledGroups).";
+        example = "xmas";
+      };
+      keepBoundary = lib.mkOption {
+        type = lib.types.nullOr (lib.types.either lib.types.bool (lib.types.enum ["smart"]));
+        default = null;
+        description = ''
+          In boundary mode, retype the boundary character after the expansion (true, the
+          default), swallow it (false) or swallow it only when the expansion ends in
+          whitespace ("smart").
+        '';
+      };
+      boundaryPosition = lib.mkOption {
+        type = lib.types.nullOr (lib.types.enum ["before" "after"]);
+        default = null;
+        description = "Overrides the global boundaryPosition for this rule.";
+      };
+      stripTrailingNewline = lib.mkOption {
+        type = lib.types.nullOr lib.types.bool;
+        default = null;
+        description = "Overrides the global stripTrailingNewline for this rule.";
+      };
+      newline = lib.mkOption {
+        type = lib.types.enum ["text" "enter" "shift_enter" "strip"];
+        default = "text";
+        description = ''
+          How newlines in the expansion are typed: as text, as Enter presses, as
+          Shift+Enter (for chat apps where Enter sends) or joined with a space.
+        '';
+      };
+    };
+  };
+  snippetType = lib.types.submodule {
+    options = {
+      title = lib.mkOption {
+        type = lib.types.str;
+        description = "Menu item label shown in the tray context menu.";
+        example = "Work email";
+      };
+      content = lib.mkOption {
+        type = lib.types.str;
+        description = "Text copied to clipboard (or typed, with mode = \"type\") when this menu item is clicked.";
+        example = "tyler@company.com";
+      };
+      group = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = "Optional submenu name; snippets with the same group are shown together.";
+        example = "Work";
+      };
+      separatorAfter = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Draw a separator below this item in its menu.";
+      };
+      mode = lib.mkOption {
+        type = lib.types.nullOr (lib.types.enum ["clipboard" "type"]);
+        default = null;
+        description = ''
+          "clipboard" (slykey's default) copies the content; "type" types it into the focused
+          window, including action macros such as {{KEY:ENTER}}.
+        '';
+      };
+      typeDelayMs = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.unsigned;
+        default = null;
+        description = "With mode = \"type\", milliseconds to wait before typing (slykey defaults to 600).";
+      };
+    };
+  };
+  profileToYaml = profile:
+    lib.optionalAttrs (profile.expansions != []) {
+      expansions = map expansionToYaml profile.expansions;
+    }
+    // lib.optionalAttrs (profile.snippets != []) {
+      snippets = map snippetToYaml profile.snippets;
+    }
+    // lib.optionalAttrs (profile.globals != {}) {
+      globals = profile.globals;
+    }
+    // lib.optionalAttrs (profile.hotkey != null) {
+      hotkey = profile.hotkey;
+    };
   generatedConfig = yaml.generate "slykey-config.yaml" ({
-      expansions = cfg.expansions;
+      expansions = map expansionToYaml cfg.expansions;
+    }
+    // lib.optionalAttrs (cfg.include != []) {
+      include = cfg.include;
+    }
+    // lib.optionalAttrs (cfg.expansionsDir != null) {
+      expansions_dir = cfg.expansionsDir;
+    }
+    // lib.optionalAttrs cfg.expandEnv {
+      expand_env = true;
+    }
+    // lib.optionalAttrs (!cfg.stripTrailingNewline) {
+      strip_trailing_newline = false;
+    }
+    // lib.optionalAttrs (cfg.disabledGroups != []) {
+      disabled_groups = cfg.disabledGroups;
     }
     // lib.optionalAttrs (cfg.snippets != []) {
-      snippets = cfg.snippets;
+      snippets = map snippetToYaml cfg.snippets;
     }
     // lib.optionalAttrs (cfg.matchBehavior != null) {
       match_behavior = cfg.matchBehavior;
@@ -18,9 +236,95 @@
     // lib.optionalAttrs (cfg.boundaryChars != null) {
       boundary_chars = cfg.boundaryChars;
     }
+    // lib.optionalAttrs (cfg.boundaryPosition != null) {
+      boundary_position = cfg.boundaryPosition;
+    }
+    // lib.optionalAttrs (cfg.preferLongest != null) {
+      prefer_longest = cfg.preferLongest;
+    }
+    // lib.optionalAttrs (cfg.pauseHotkey != null) {
+      pause_hotkey = cfg.pauseHotkey;
+    }
+    // lib.optionalAttrs (cfg.pickerHotkey != null) {
+      picker_hotkey = cfg.pickerHotkey;
+    }
+    // lib.optionalAttrs (cfg.historySize != null) {
+      history_size = cfg.historySize;
+    }
+    // lib.optionalAttrs (cfg.bufferSlack != null) {
+      buffer_slack = cfg.bufferSlack;
+    }
+    // lib.optionalAttrs (cfg.pendingExpansionTimeoutMs != null) {
+      pending_expansion_timeout_ms = cfg.pendingExpansionTimeoutMs;
+    }
+    // lib.optionalAttrs cfg.autoCapitalize {
+      auto_capitalize = true;
+    }
+    // lib.optionalAttrs (cfg.autoCapitalizeExceptions != []) {
+      auto_capitalize_exceptions = cfg.autoCapitalizeExceptions;
+    }
+    // lib.optionalAttrs (cfg.autocorrectFile != null) {
+      autocorrect_file = cfg.autocorrectFile;
+    }
+    // lib.optionalAttrs (cfg.injectMode != null) {
+      inject_mode = cfg.injectMode;
+    }
+    // lib.optionalAttrs (cfg.paste != null) {
+      paste = {
+        shortcut = cfg.paste.shortcut;
+        restore_delay_ms = cfg.paste.restoreDelayMs;
+      };
+    }
+    // lib.optionalAttrs (cfg.output != null) {
+      output = {
+        char_delay_ms = cfg.output.charDelayMs;
+        chunk_size = cfg.output.chunkSize;
+        unicode_fallback = cfg.output.unicodeFallback;
+        action_delay_ms = cfg.output.actionDelayMs;
+      };
+    }
+    // lib.optionalAttrs (cfg.tray.editCommand != null) {
+      tray.edit_command = cfg.tray.editCommand;
+    }
+    // lib.optionalAttrs (cfg.picker.command != null || cfg.picker.focusDelayMs != null) {
+      picker = lib.filterAttrs (_: value: value != null) {
+        command = cfg.picker.command;
+        focus_delay_ms = cfg.picker.focusDelayMs;
+      };
+    }
+    // lib.optionalAttrs (!cfg.security.allowCommands || cfg.security.commandAllowlist != null || cfg.security.confirmCommands) {
+      security =
+        lib.optionalAttrs (!cfg.security.allowCommands) {
+          allow_commands = false;
+        }
+        // lib.optionalAttrs (cfg.security.commandAllowlist != null) {
+          command_allowlist = cfg.security.commandAllowlist;
+        }
+        // lib.optionalAttrs cfg.security.confirmCommands {
+          confirm_commands = true;
+        };
+    }
+    // lib.optionalAttrs (cfg.expansion.maxDepth != null || cfg.expansion.maxOutputBytes != null) {
+      expansion = lib.filterAttrs (_: value: value != null) {
+        max_depth = cfg.expansion.maxDepth;
+        max_output_bytes = cfg.expansion.maxOutputBytes;
+      };
+    }
     // lib.optionalAttrs (cfg.globals != {}) {
       globals = cfg.globals;
     }
+    // lib.optionalAttrs (cfg.totp != {}) {
+      totp = cfg.totp;
+    }
+    // lib.optionalAttrs (cfg.profiles != {}) {
+      profiles = lib.mapAttrs (_: profileToYaml) cfg.profiles;
+    }
+    // lib.optionalAttrs (cfg.defaultProfile != null) {
+      default_profile = cfg.defaultProfile;
+    }
+    // lib.optionalAttrs (cfg.profileRules != []) {
+      profile_rules = cfg.profileRules;
+    }
     // lib.optionalAttrs (cfg.notifications != null) {
       notifications =
         lib.optionalAttrs (cfg.notifications.onExpansion) {
@@ -28,6 +332,16 @@
         }
         // lib.optionalAttrs (cfg.notifications.onSnippetCopy) {
           on_snippet_copy = true;
+        }
+        // lib.optionalAttrs (cfg.notifications.onReload) {
+          on_reload = true;
+        }
+        // lib.optionalAttrs (cfg.notifications.onPreview) {
+          on_preview = true;
+        }
+        // {
+          min_interval_ms = cfg.notifications.minIntervalMs;
+          coalesce = cfg.notifications.coalesce;
         };
     });
 in {
@@ -42,39 +356,13 @@ in {
     };
 
     expansions = lib.mkOption {
-      type = lib.types.listOf (lib.types.submodule {
-        options = {
-          trigger = lib.mkOption {
-            type = lib.types.str;
-            description = "Typed trigger text.";
-            example = "sig;";
-          };
-          expansion = lib.mkOption {
-            type = lib.types.str;
-            description = "Text or macro sequence to emit.";
-            example = "Thanks, Tyler{{KEY:ENTER}}";
-          };
-        };
-      });
+      type = lib.types.listOf expansionType;
       default = [];
       description = "Expansion rules written into the generated slykey YAML config.";
     };
 
     snippets = lib.mkOption {
-      type = lib.types.listOf (lib.types.submodule {
-        options = {
-          title = lib.mkOption {
-            type = lib.types.str;
-            description = "Menu item label shown in the tray context menu.";
-            example = "Work email";
-          };
-          content = lib.mkOption {
-            type = lib.types.str;
-            description = "Text copied to clipboard when this menu item is clicked.";
-            example = "tyler@company.com";
-          };
-        };
-      });
+      type = lib.types.listOf snippetType;
       default = [];
       description = "Optional clipboard snippets exposed in the tray context menu.";
     };
@@ -94,6 +382,267 @@ in {
       description = "Optional boundary character set used when matchBehavior is boundary.";
     };
 
+    boundaryPosition = lib.mkOption {
+      type = lib.types.nullOr (lib.types.enum [
+        "before"
+        "after"
+      ]);
+      default = null;
+      description = ''
+        Whether a retyped boundary goes before or after the caret moves at the end of an
+        expansion (slykey defaults to after).
+      '';
+    };
+
+    preferLongest = lib.mkOption {
+      type = lib.types.nullOr lib.types.bool;
+      default = null;
+      description = "Whether the longest matching trigger wins over config order (slykey defaults to true).";
+    };
+
+    pauseHotkey = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Optional shortcut that toggles expansion on and off.";
+      example = "ctrl+alt+p";
+    };
+
+    pickerHotkey = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Optional shortcut that opens the searchable snippet picker.";
+      example = "ctrl+alt+space";
+    };
+
+    historySize = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.unsigned;
+      default = null;
+      description = "Number of recent expansions kept in memory (slykey defaults to 20).";
+    };
+
+    bufferSlack = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.unsigned;
+      default = null;
+      description = "Typed characters remembered beyond the longest trigger (slykey defaults to 8).";
+    };
+
+    pendingExpansionTimeoutMs = lib.mkOption {
+      type = lib.types.nullOr lib.types.ints.unsigned;
+      default = null;
+      description = "Milliseconds an expansion waits for held modifiers to be released before it is dropped, 0 for no limit (slykey defaults to 2000).";
+    };
+
+    autoCapitalize = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = "Uppercase a lowercase letter typed at the start of a sentence.";
+    };
+
+    autoCapitalizeExceptions = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [];
+      description = "Abbreviations after which autoCapitalize leaves the next word alone.";
+      example = ["e.g." "i.e."];
+    };
+
+    autocorrectFile = lib.mkOption {
+      type = lib.types.nullOr (lib.types.either lib.types.str (lib.types.submodule {
+        options = {
+          path = lib.mkOption {
+            type = lib.types.str;
+            description = "Tab-separated typo<TAB>correction file.";
+          };
+          enabled = lib.mkOption {
+            type = lib.types.bool;
+            default = true;
+            description = "Whether the dictionary is loaded.";
+          };
+        };
+      }));
+      default = null;
+      description = ''
+        Tab-separated typo<TAB>correction file whose typos are fixed as whole
+        words, or { path, enabled }. Use an absolute path or one starting with
+        ~/, as the generated config lives in the Nix store.
+      '';
+      example = "~/.config/slykey/typos.tsv";
+    };
+
+    injectMode = lib.mkOption {
+      type = lib.types.nullOr (lib.types.enum [
+        "type"
+        "clipboard"
+      ]);
+      default = null;
+      description = "How expansion text is emitted: typed key by key, or pasted via the clipboard (slykey defaults to type).";
+    };
+
+    paste = lib.mkOption {
+      type = lib.types.nullOr (lib.types.submodule {
+        options = {
+          shortcut = lib.mkOption {
+            type = lib.types.enum [
+              "ctrl_v"
+              "shift_insert"
+              "ctrl_shift_v"
+            ];
+            default = "ctrl_v";
+            description = "Shortcut sent to paste in clipboard inject mode.";
+          };
+          restoreDelayMs = lib.mkOption {
+            type = lib.types.ints.unsigned;
+            default = 150;
+            description = "Milliseconds to wait after pasting before restoring the previous clipboard.";
+          };
+        };
+      });
+      default = null;
+      description = "Optional clipboard paste settings.";
+    };
+
+    output = lib.mkOption {
+      type = lib.types.nullOr (lib.types.submodule {
+        options = {
+          charDelayMs = lib.mkOption {
+            type = lib.types.ints.unsigned;
+            default = 1;
+            description = "Milliseconds to pause after each typed chunk.";
+          };
+          chunkSize = lib.mkOption {
+            type = lib.types.ints.unsigned;
+            default = 0;
+            description = "Characters typed per chunk; 0 types each text run at once.";
+          };
+          unicodeFallback = lib.mkOption {
+            type = lib.types.enum ["skip" "replace" "paste"];
+            default = "skip";
+            description = "What to do with characters that cannot be typed: leave them out, type `?` instead, or paste the rest of the text.";
+          };
+          actionDelayMs = lib.mkOption {
+            type = lib.types.ints.unsigned;
+            default = 0;
+            description = "Milliseconds to pause between consecutive actions, except next to an explicit sleep.";
+          };
+        };
+      });
+      default = null;
+      description = "Optional pacing for typed expansion text.";
+    };
+
+    tray = {
+      editCommand = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = ''
+          Command used by the tray's "Edit config…" item; `{path}` is replaced
+          by the config path, which is appended when missing. Defaults to xdg-open.
+        '';
+        example = "alacritty -e nvim {path}";
+      };
+    };
+
+    picker = {
+      command = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        description = ''
+          dmenu-style command for the snippet picker: entry titles on stdin,
+          the chosen title on stdout. Defaults to the tray's popup.
+        '';
+        example = "rofi -dmenu -i -p snippet";
+      };
+      focusDelayMs = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.unsigned;
+        default = null;
+        description = "Milliseconds to wait after a pick before typing it (slykey defaults to 150).";
+      };
+    };
+
+    security = {
+      allowCommands = lib.mkOption {
+        type = lib.types.bool;
+        default = true;
+        description = "Whether {{CMD:...}} macros may run commands.";
+      };
+      commandAllowlist = lib.mkOption {
+        type = lib.types.nullOr (lib.types.listOf lib.types.str);
+        default = null;
+        description = ''
+          Programs {{CMD:...}} macros may run, matched against the command's
+          first word. Null allows any.
+        '';
+        example = [ "date" "git" "pass" ];
+      };
+      confirmCommands = lib.mkOption {
+        type = lib.types.bool;
+        default = false;
+        description = "Only run a command when its trigger is typed a second time within 5 seconds.";
+      };
+    };
+
+    expansion = {
+      maxDepth = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.positive;
+        default = null;
+        description = "How deeply globals and {{EXPAND:...}} rules may nest (slykey defaults to 16).";
+      };
+      maxOutputBytes = lib.mkOption {
+        type = lib.types.nullOr lib.types.ints.positive;
+        default = null;
+        description = "Largest text in bytes an expansion may render to (slykey defaults to 1 MiB).";
+      };
+    };
+
+    include = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [];
+      description = ''
+        Extra config files (paths or globs) whose expansions, snippets and
+        globals are merged in. The generated config lives in the Nix store, so
+        use absolute paths.
+      '';
+      example = ["/home/me/.config/slykey/work.yaml" "/home/me/.config/slykey/personal/*.yaml"];
+    };
+
+    expansionsDir = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = ''
+        Directory whose *.yaml files each hold one expansion rule or a list of
+        them, merged after `expansions`. Use an absolute path or one starting
+        with ~/, as the generated config lives in the Nix store.
+      '';
+      example = "~/.config/slykey/expansions";
+    };
+
+    disabledGroups = lib.mkOption {
+      type = lib.types.listOf lib.types.str;
+      default = [];
+      description = ''
+        Rule groups that start out switched off. `slykey enable-group <name>`
+        turns one on in the running daemon until the next reload.
+      '';
+      example = ["xmas"];
+    };
+
+    expandEnv = lib.mkOption {
+      type = lib.types.bool;
+      default = false;
+      description = ''
+        Substitute `''${VAR}` and a leading `~/` in expansions, globals, snippet
+        contents and boundaryChars when the config is loaded.
+      '';
+    };
+
+    stripTrailingNewline = lib.mkOption {
+      type = lib.types.bool;
+      default = true;
+      description = ''
+        Drop one trailing newline, as a multi-line Nix string ends with, from
+        every expansion and snippet content when the config is loaded.
+      '';
+    };
+
     globals = lib.mkOption {
       type = lib.types.attrsOf lib.types.str;
       default = {};
@@ -104,6 +653,79 @@ in {
       };
     };
 
+    profiles = lib.mkOption {
+      type = lib.types.attrsOf (lib.types.submodule {
+        options = {
+          expansions = lib.mkOption {
+            type = lib.types.listOf expansionType;
+            default = [];
+            description = "Expansion rules that only fire while this profile is active.";
+          };
+          snippets = lib.mkOption {
+            type = lib.types.listOf snippetType;
+            default = [];
+            description = "Tray snippets shown while this profile is active.";
+          };
+          globals = lib.mkOption {
+            type = lib.types.attrsOf lib.types.str;
+            default = {};
+            description = "Template macro globals that replace same-named top-level ones in this profile.";
+          };
+          hotkey = lib.mkOption {
+            type = lib.types.nullOr lib.types.str;
+            default = null;
+            description = "Optional shortcut that switches to this profile.";
+            example = "ctrl+alt+1";
+          };
+        };
+      });
+      default = {};
+      description = ''
+        Named rule sets applied on top of the top-level ones, one at a time.
+        Switch with `slykey profile <name>`, the tray's Profile menu or a
+        profile hotkey.
+      '';
+    };
+
+    defaultProfile = lib.mkOption {
+      type = lib.types.nullOr lib.types.str;
+      default = null;
+      description = "Profile active when slykey starts.";
+      example = "work";
+    };
+
+    profileRules = lib.mkOption {
+      type = lib.types.listOf (lib.types.submodule {
+        options = {
+          app = lib.mkOption {
+            type = lib.types.str;
+            description = "WM_CLASS of the focused window (case-insensitive).";
+            example = "Slack";
+          };
+          profile = lib.mkOption {
+            type = lib.types.str;
+            description = "Profile in force while that window has focus.";
+            example = "casual";
+          };
+        };
+      });
+      default = [];
+      description = ''
+        Profiles that take over while certain applications have focus; the
+        first matching rule wins, and the selected profile applies elsewhere.
+      '';
+    };
+
+    totp = lib.mkOption {
+      type = lib.types.attrsOf lib.types.str;
+      default = {};
+      description = ''
+        Base32 TOTP secrets for `{{TOTP:account}}`, keyed by account. These end
+        up in the world-readable Nix store; prefer
+        `slykey secret set totp/<account>`.
+      '';
+    };
+
     notifications = lib.mkOption {
       type = lib.types.nullOr (lib.types.submodule {
         options = {
@@ -117,6 +739,26 @@ in {
             default = true;
             description = "Send a desktop notification when a tray snippet is copied.";
           };
+          onReload = lib.mkOption {
+            type = lib.types.bool;
+            default = false;
+            description = "Send a desktop notification when the config is reloaded, summarizing what changed.";
+          };
+          onPreview = lib.mkOption {
+            type = lib.types.bool;
+            default = false;
+            description = "Send a desktop notification naming the expansion once only one trigger can still match what was typed.";
+          };
+          minIntervalMs = lib.mkOption {
+            type = lib.types.ints.unsigned;
+            default = 2000;
+            description = "Minimum milliseconds between expansion notifications; 0 disables the limit.";
+          };
+          coalesce = lib.mkOption {
+            type = lib.types.bool;
+            default = true;
+            description = "Summarize expansions held back by minIntervalMs in the next notification.";
+          };
         };
       });
       default = null;
@@ -127,8 +769,8 @@ in {
   config = lib.mkIf cfg.enable {
     assertions = [
       {
-        assertion = cfg.expansions != [];
-        message = "programs.slykey.expansions must include at least one rule when enabled.";
+        assertion = cfg.expansions != [] || cfg.include != [];
+        message = "programs.slykey.expansions must include at least one rule (or include files that do) when enabled.";
       }
     ];
 
@@ -142,9 +784,13 @@ in {
       };
 
       Service = {
+        Type = "notify";
         ExecStart = "${lib.getExe cfg.package} --config ${generatedConfig} run";
         Restart = "on-failure";
         RestartSec = 2;
+        # Key handling waits for {{CMD:...}}; give commands that may run
+        # longer than this a timeout below it, e.g. {{CMD:timeout=20s:...}}.
+        WatchdogSec = 30;
       };
 
       Install = {
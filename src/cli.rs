@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[command(name = "slykey", version, about = "Minimal text expansion CLI")]
@@ -12,15 +12,264 @@ pub struct Cli {
     #[arg(short = 'c', long, global = true, value_name = "PATH")]
     pub config: Option<PathBuf>,
 
-    /// Enable debug logging for trigger matching internals.
+    /// Most verbose log level written (RUST_LOG, if set, takes precedence).
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Info)]
+    pub log_level: LogLevel,
+
+    /// Append logs to this file instead of stderr; rotated daily as PATH.YYYY-MM-DD.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Same as --log-level debug: also logs trigger matching internals.
     #[arg(long, global = true)]
     pub debug: bool,
 }
 
+impl Cli {
+    /// `--log-level`, raised to debug by `--debug`.
+    pub fn effective_log_level(&self) -> LogLevel {
+        if self.debug {
+            self.log_level.max(LogLevel::Debug)
+        } else {
+            self.log_level
+        }
+    }
+}
+
 #[derive(Debug, Clone, Subcommand)]
 pub enum Commands {
     /// Run key listener and trigger expansion output.
-    Run,
+    Run {
+        /// Stop an already running instance and take its place.
+        #[arg(long)]
+        replace: bool,
+        /// With --replace, take over even if the running instance does not stop.
+        #[arg(long, requires = "replace")]
+        force: bool,
+        /// Detect triggers and log what would be typed, without sending any keys.
+        #[arg(long)]
+        dry_run: bool,
+        /// With --dry-run, also show what would be typed as a notification.
+        #[arg(long, requires = "dry_run")]
+        notify: bool,
+        /// Append every key event to PATH as JSON lines, for `slykey replay`.
+        #[arg(long, value_name = "PATH")]
+        record: Option<PathBuf>,
+        /// With --record, mask the characters of words that look like passwords.
+        #[arg(long, requires = "record")]
+        record_redact: bool,
+    },
     /// Load and validate config, then exit.
     ValidateConfig,
+    /// Write a commented starter config to the default path (or --config),
+    /// as TOML or JSON when that path ends in .toml or .json.
+    Init {
+        /// Overwrite an existing config file.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Write a systemd user service running `slykey run` with this binary and config.
+    InstallService {
+        /// Print the unit to stdout instead of writing ~/.config/systemd/user/slykey.service.
+        #[arg(long)]
+        print: bool,
+        /// Overwrite an existing service file.
+        #[arg(long, conflicts_with = "print")]
+        force: bool,
+    },
+    /// Print per-trigger usage statistics.
+    Stats,
+    /// Check the config, X11, D-Bus and tray setup and print remediation hints.
+    Doctor {
+        /// Print the report as JSON (handy for bug reports).
+        #[arg(long)]
+        json: bool,
+    },
+    /// Render an expansion and print its actions without injecting keys.
+    Test {
+        /// Trigger of the configured rule to render.
+        #[arg(long, conflicts_with = "text", required_unless_present = "text")]
+        trigger: Option<String>,
+        /// Arbitrary expansion text to render.
+        #[arg(long)]
+        text: Option<String>,
+    },
+    /// Measure matcher throughput on synthetic rules and keystrokes.
+    Bench {
+        /// Number of generated rules.
+        #[arg(long, default_value_t = 1000)]
+        rules: usize,
+        /// Number of key presses streamed through the engine.
+        #[arg(long, default_value_t = 100_000)]
+        keystrokes: usize,
+        /// Print the results as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Feed events recorded with `run --record` to the config and print the expansions that fire.
+    Replay {
+        /// Recording to replay.
+        path: PathBuf,
+    },
+    /// Show the running daemon's version, config path, rule count and pause state.
+    Status {
+        /// Print one JSON object for status bars: `running`, `paused`,
+        /// `config`, `rules`, `profile`, `expansions_today` and `version`.
+        /// When no daemon answers, prints `{"running":false}` and exits with 1.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Pause expansion in the running daemon.
+    Pause,
+    /// Resume expansion in the running daemon.
+    Resume,
+    /// Make the running daemon reload its config file.
+    Reload,
+    /// Stop the running daemon.
+    Stop,
+    /// Switch on the rules of a group in the running daemon (until the next reload).
+    EnableGroup {
+        /// Group name as set on the rules' `group` key.
+        group: String,
+    },
+    /// Switch off the rules of a group in the running daemon (until the next reload).
+    DisableGroup {
+        /// Group name as set on the rules' `group` key.
+        group: String,
+    },
+    /// Switch the running daemon to a profile, or show the active one.
+    Profile {
+        /// Profile name as listed under `profiles`.
+        name: Option<String>,
+    },
+    /// Append an expansion rule (or, with --snippet, a tray snippet) to the config file.
+    Add {
+        /// Trigger of the new expansion rule.
+        #[arg(long, required_unless_present = "snippet", conflicts_with = "snippet")]
+        trigger: Option<String>,
+        /// Expansion text of the new rule.
+        #[arg(long, required_unless_present = "snippet", conflicts_with = "snippet")]
+        expansion: Option<String>,
+        /// Add a tray snippet instead of an expansion rule.
+        #[arg(long)]
+        snippet: bool,
+        /// Title of the new tray snippet.
+        #[arg(long, requires = "snippet", required_if_eq("snippet", "true"))]
+        title: Option<String>,
+        /// Content of the new tray snippet.
+        #[arg(long, requires = "snippet", required_if_eq("snippet", "true"))]
+        content: Option<String>,
+    },
+    /// Remove an expansion rule or tray snippet from the config file.
+    Remove {
+        /// Trigger of the expansion rule to remove, top-level and in every profile.
+        #[arg(
+            long,
+            required_unless_present = "snippet_title",
+            conflicts_with = "snippet_title"
+        )]
+        trigger: Option<String>,
+        /// Title of the tray snippet to remove.
+        #[arg(long)]
+        snippet_title: Option<String>,
+        /// Write the config even if no expansion is left, with a commented placeholder.
+        #[arg(long)]
+        force_empty: bool,
+        /// Print what would be removed without writing the config.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the expansion rules in force at startup (top-level plus the default profile).
+    List {
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+        /// List the `autocorrect_file` typos and their corrections instead.
+        #[arg(long)]
+        autocorrect: bool,
+    },
+    /// Print a shell completion script to stdout.
+    Completions {
+        /// Shell to complete for.
+        shell: CompletionShell,
+    },
+    /// Print every expansion, snippet and global of the resolved config.
+    Export {
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout.
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Convert rules from another text expander (or a `slykey export`) and print them as slykey YAML.
+    Import {
+        /// Format of the files being imported.
+        #[arg(long, value_enum)]
+        format: ImportFormat,
+        /// File to import; for espanso also a directory searched recursively for .yml/.yaml files.
+        path: PathBuf,
+        /// Append the converted rules to the config file instead of printing them.
+        #[arg(long)]
+        write: bool,
+    },
+    /// Manage the keyring entries `{{SECRET:name}}` macros read.
+    Secret {
+        #[command(subcommand)]
+        command: SecretCommand,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum SecretCommand {
+    /// Store a secret, read from stdin or typed at a prompt.
+    Set {
+        /// Name used in `{{SECRET:name}}`.
+        name: String,
+    },
+    /// Delete a stored secret.
+    Rm {
+        /// Name used in `{{SECRET:name}}`.
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Stable-keyed JSON that `import --format slykey-json` reads back.
+    Json,
+    /// One row per expansion, snippet and global.
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// Triggers with a one-line expansion preview.
+    Table,
+    /// One trigger per line and nothing else, as shell completion reads it.
+    PlainTriggers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CompletionShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    /// espanso match files (`match/*.yml`).
+    Espanso,
+    /// JSON written by `slykey export`.
+    SlykeyJson,
 }
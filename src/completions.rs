@@ -0,0 +1,193 @@
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+
+use crate::cli::{Cli, CompletionShell};
+
+/// Options completed with the configured triggers, as (subcommand, option).
+const TRIGGER_OPTIONS: &[(&str, &str)] = &[("test", "--trigger"), ("remove", "--trigger")];
+
+/// Wraps clap's `_slykey`: after a trigger option, completes what
+/// `slykey list --format plain-triggers` prints for the `-c`/`--config` on
+/// the command line being completed.
+const BASH_TRIGGERS: &str = r#"
+_slykey_triggers() {
+    local i config=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -c|--config) config=(--config "${COMP_WORDS[i+1]}") ;;
+        esac
+    done
+    slykey "${config[@]}" list --format plain-triggers 2>/dev/null
+}
+
+_slykey_with_triggers() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local subcommand="" i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -c|--config|--log-level|--log-file) ((i++)) ;;
+            -*) ;;
+            *) subcommand="${COMP_WORDS[i]}"; break ;;
+        esac
+    done
+    case "${subcommand} ${prev}" in
+        __CASES__)
+            local IFS=$'\n'
+            COMPREPLY=($(compgen -W "$(_slykey_triggers)" -- "${cur}"))
+            return 0
+            ;;
+    esac
+    _slykey "$@"
+}
+
+if [[ "${BASH_VERSINFO[0]}" -eq 4 && "${BASH_VERSINFO[1]}" -ge 4 || "${BASH_VERSINFO[0]}" -gt 4 ]]; then
+    complete -F _slykey_with_triggers -o nosort -o bashdefault -o default slykey
+else
+    complete -F _slykey_with_triggers -o bashdefault -o default slykey
+fi
+"#;
+
+const ZSH_TRIGGERS: &str = r#"_slykey_triggers() {
+    local -a triggers config
+    local i
+    for ((i = 2; i < CURRENT; i++)); do
+        case $words[i] in
+            (-c|--config) config=(--config $words[i+1]) ;;
+        esac
+    done
+    triggers=(${(f)"$(slykey $config list --format plain-triggers 2>/dev/null)"})
+    compadd -a triggers
+}
+
+"#;
+
+/// Where clap's zsh script hands over to `_slykey`; helpers go before it.
+const ZSH_DISPATCH: &str = "if [ \"$funcstack[1]\" = \"_slykey\" ]; then";
+
+/// The completion script for `shell`. Bash, zsh and fish also complete
+/// trigger values by running `slykey list --format plain-triggers` with the
+/// config being completed for.
+pub fn completion_script(shell: CompletionShell) -> String {
+    let generator = match shell {
+        CompletionShell::Bash => Shell::Bash,
+        CompletionShell::Zsh => Shell::Zsh,
+        CompletionShell::Fish => Shell::Fish,
+    };
+    let mut script = Vec::new();
+    generate(generator, &mut Cli::command(), "slykey", &mut script);
+    let script = String::from_utf8(script).expect("completion scripts are UTF-8");
+
+    match shell {
+        CompletionShell::Bash => with_bash_triggers(script),
+        CompletionShell::Zsh => with_zsh_triggers(script),
+        CompletionShell::Fish => with_fish_triggers(script),
+    }
+}
+
+/// Registers a wrapper around clap's `_slykey` that answers trigger options
+/// itself.
+fn with_bash_triggers(mut script: String) -> String {
+    let cases = TRIGGER_OPTIONS
+        .iter()
+        .map(|(subcommand, option)| format!("\"{subcommand} {option}\""))
+        .collect::<Vec<_>>()
+        .join("|");
+    script.push_str(&BASH_TRIGGERS.replace("__CASES__", &cases));
+    script
+}
+
+/// Points the trigger options' `_arguments` specs at `_slykey_triggers`.
+fn with_zsh_triggers(mut script: String) -> String {
+    for (subcommand, option) in TRIGGER_OPTIONS {
+        let Some(section) = script.find(&format!("\n({subcommand})\n")) else {
+            continue;
+        };
+        let Some(spec) = script[section..]
+            .find(&format!("{option}=["))
+            .map(|offset| section + offset)
+        else {
+            continue;
+        };
+        let line_end = script[spec..]
+            .find('\n')
+            .map_or(script.len(), |offset| spec + offset);
+        if let Some(action) = script[spec..line_end].rfind(":_default'") {
+            let action = spec + action;
+            script.replace_range(action..action + ":_default".len(), ":_slykey_triggers");
+        }
+    }
+    if let Some(dispatch) = script.find(ZSH_DISPATCH) {
+        script.insert_str(dispatch, ZSH_TRIGGERS);
+    }
+    script
+}
+
+fn with_fish_triggers(mut script: String) -> String {
+    for (subcommand, option) in TRIGGER_OPTIONS {
+        script.push_str(&format!(
+            "complete -c slykey -n \"__fish_slykey_using_subcommand {subcommand}\" -l {} -f -a \"(slykey list --format plain-triggers 2>/dev/null)\"\n",
+            option.trim_start_matches('-')
+        ));
+    }
+    script
+}
+
+#[cfg(test)]
+mod tests {
+    use super::completion_script;
+    use crate::cli::CompletionShell;
+
+    #[test]
+    fn generates_every_shell_script() {
+        for shell in [
+            CompletionShell::Bash,
+            CompletionShell::Zsh,
+            CompletionShell::Fish,
+        ] {
+            let script = completion_script(shell);
+            assert!(script.contains("completions"), "{shell:?}");
+            assert!(script.contains("list --format plain-triggers"), "{shell:?}");
+        }
+    }
+
+    #[test]
+    fn completes_triggers_only_where_a_configured_trigger_is_expected() {
+        let bash = completion_script(CompletionShell::Bash);
+        assert!(
+            bash.contains("        \"test --trigger\"|\"remove --trigger\")\n"),
+            "{bash}"
+        );
+        assert!(bash.contains("complete -F _slykey_with_triggers"));
+
+        let zsh = completion_script(CompletionShell::Zsh);
+        let test_section = &zsh[zsh.find("\n(test)\n").expect("test section")..];
+        let trigger_spec = test_section
+            .lines()
+            .find(|line| line.contains("--trigger=["))
+            .expect("trigger spec");
+        assert!(
+            trigger_spec.ends_with(":TRIGGER:_slykey_triggers' \\"),
+            "{trigger_spec}"
+        );
+        let remove_section = &zsh[zsh.find("\n(remove)\n").expect("remove section")..];
+        assert!(remove_section
+            .lines()
+            .find(|line| line.contains("--trigger=["))
+            .expect("remove trigger spec")
+            .ends_with(":_slykey_triggers' \\"));
+        let add_section = &zsh[zsh.find("\n(add)\n").expect("add section")..];
+        assert!(add_section
+            .lines()
+            .find(|line| line.contains("--trigger=["))
+            .expect("add trigger spec")
+            .ends_with(":_default' \\"));
+        let helper = zsh.find("_slykey_triggers() {").expect("helper");
+        assert!(helper < zsh.find(super::ZSH_DISPATCH).expect("dispatch"));
+
+        let fish = completion_script(CompletionShell::Fish);
+        assert!(fish.contains(
+            "complete -c slykey -n \"__fish_slykey_using_subcommand test\" -l trigger -f -a \"(slykey list --format plain-triggers 2>/dev/null)\"\n"
+        ));
+        assert!(fish.contains("__fish_slykey_using_subcommand remove\" -l trigger -f"));
+    }
+}
@@ -1,52 +1,475 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::core::autocorrect::{read_dictionary, Correction};
+use crate::core::env_expand::expand_env;
+use crate::core::expansion::{
+    apply_newline_mode, parse_expansion_actions_with_context, render_template_macros_with_context,
+    static_command_macros, ExpansionContext, OutputAction, RenderLimits, DEFAULT_MAX_DEPTH,
+    DEFAULT_MAX_OUTPUT_BYTES,
+};
+use crate::core::hotkey::Hotkey;
+use crate::core::shell_command::{
+    check_allowlist, split_command_options, CommandRunner, DenyAllRunner, GuardedRunner,
+    ShellRunner,
+};
+use crate::core::totp::decode_base32;
+use crate::io::output::SinkCapabilities;
+
+/// Parsed `slykey.yaml`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct AppConfig {
+    /// Further files (paths or globs, relative to this file's directory)
+    /// whose expansions, snippets and globals are merged in after this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Directory (relative to this file's directory, or starting with `~/`)
+    /// whose `*.yaml` files each hold one expansion rule or a list of them,
+    /// merged in file name order right after `expansions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expansions_dir: Option<String>,
+    /// Substitute `${VAR}` and a leading `~/` in expansions, globals,
+    /// snippet contents and `boundary_chars` at load time.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub expand_env: bool,
+    /// Drop one trailing newline, as a YAML `|` block leaves, from every
+    /// expansion and snippet content at load time.
+    #[serde(
+        default = "default_strip_trailing_newline",
+        skip_serializing_if = "is_default_strip_trailing_newline"
+    )]
+    pub strip_trailing_newline: bool,
+    /// Rule `group`s whose expansions start out switched off.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub expansions: Vec<ExpansionRule>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub snippets: Vec<MenuSnippet>,
-    #[serde(default)]
-    pub globals: HashMap<String, String>,
-    #[serde(default)]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_globals",
+        skip_serializing_if = "IndexMap::is_empty"
+    )]
+    pub globals: Globals,
+    /// Base32 TOTP secrets by account, for `{{TOTP:account}}` without the
+    /// keyring. Stored in plain text.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub totp: HashMap<String, String>,
+    /// Named rule sets, one of which may be active on top of the top-level
+    /// expansions, snippets and globals.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_profiles",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Profile active at startup; without one only top-level rules apply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    /// Profiles that take over while certain applications have focus; the
+    /// first rule matching the focused window wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profile_rules: Vec<ProfileRule>,
+    #[serde(default, skip_serializing_if = "is_default")]
     pub notifications: NotificationConfig,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_default")]
     pub match_behavior: MatchBehavior,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub boundary_chars: Option<String>,
-    #[serde(default)]
+    /// Where a retyped boundary goes when the expansion ends by moving the
+    /// caret; see [`BoundaryPosition`].
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub boundary_position: BoundaryPosition,
+    #[serde(default, skip_serializing_if = "is_default")]
     pub watch: bool,
+    #[serde(
+        default = "default_prefer_longest",
+        skip_serializing_if = "is_default_prefer_longest"
+    )]
+    pub prefer_longest: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pause_hotkey: Option<String>,
+    /// Hotkey that opens the snippet picker, e.g. `ctrl+alt+space`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picker_hotkey: Option<String>,
+    #[serde(
+        default = "default_history_size",
+        skip_serializing_if = "is_default_history_size"
+    )]
+    pub history_size: usize,
+    /// Characters of context kept in the typed buffer beyond the longest trigger.
+    #[serde(
+        default = "default_buffer_slack",
+        skip_serializing_if = "is_default_buffer_slack"
+    )]
+    pub buffer_slack: usize,
+    /// How long an expansion held back by a pressed modifier waits for the
+    /// release before it is dropped; 0 waits indefinitely.
+    #[serde(
+        default = "default_pending_expansion_timeout_ms",
+        skip_serializing_if = "is_default_pending_expansion_timeout_ms"
+    )]
+    pub pending_expansion_timeout_ms: u64,
+    /// Uppercase a lowercase letter typed at the start of a sentence: after
+    /// `. `, `! ` or `? `, or first thing after Enter.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub auto_capitalize: bool,
+    /// Abbreviations such as `e.g.` after which `auto_capitalize` leaves the
+    /// next word alone; matched case-insensitively against the typed text.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_capitalize_exceptions: Vec<String>,
+    /// Tab-separated `typo<TAB>correction` file whose typos are fixed as
+    /// whole words once a boundary character follows them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub autocorrect_file: Option<AutocorrectFile>,
+    /// Corrections read from `autocorrect_file` by [`AppConfig::load`].
+    #[serde(skip)]
+    pub autocorrect: Arc<Vec<Correction>>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub inject_mode: InjectMode,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub paste: PasteConfig,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub output: OutputConfig,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tray: TrayConfig,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub picker: PickerConfig,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub security: SecurityConfig,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub expansion: ExpansionConfig,
 }
 
+/// A config together with the file it was read from.
 #[derive(Debug, Clone)]
 pub struct LoadedConfig {
     pub path: PathBuf,
+    /// Files pulled in through `include`, in merge order.
+    pub includes: Vec<PathBuf>,
+    /// The resolved `expansions_dir`, watched for added and removed files.
+    pub expansions_dir: Option<PathBuf>,
+    /// Rule files read from `expansions_dir`, in merge order.
+    pub expansion_files: Vec<PathBuf>,
+    /// The resolved `autocorrect_file`, when it is enabled.
+    pub autocorrect_file: Option<PathBuf>,
     pub config: AppConfig,
+    /// Problems that don't make the config invalid, e.g. unset variables
+    /// left as written by `expand_env`.
+    pub warnings: Vec<String>,
+}
+
+impl LoadedConfig {
+    /// The main config file followed by every included file, every rule
+    /// file from `expansions_dir` and the `autocorrect_file`.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        std::iter::once(self.path.clone())
+            .chain(self.includes.iter().cloned())
+            .chain(self.expansion_files.iter().cloned())
+            .chain(self.autocorrect_file.iter().cloned())
+            .collect()
+    }
+}
+
+/// The part of the schema an included file may set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludedConfig {
+    #[serde(default)]
+    expansions: Vec<ExpansionRule>,
+    #[serde(default)]
+    snippets: Vec<MenuSnippet>,
+    #[serde(default, deserialize_with = "deserialize_globals")]
+    globals: Globals,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// One `trigger` -> `expansion` entry.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct ExpansionRule {
     pub trigger: String,
     pub expansion: String,
+    /// Human-readable name shown instead of the trigger in notifications,
+    /// `slykey list`, the picker and lint warnings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub apps: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_apps: Vec<String>,
+    /// Overrides the global `inject_mode` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inject_mode: Option<InjectMode>,
+    /// Overrides `output.char_delay_ms` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub char_delay_ms: Option<u64>,
+    /// Overrides `notifications.on_expansion` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
+    /// Notification body instead of the trigger; template macros are rendered.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_message: Option<String>,
+    /// `false` keeps the rule in the config without it ever firing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Name used to switch related rules on and off together.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// In `boundary` mode, whether the boundary character that completed the
+    /// trigger is retyped after the expansion (`true`, the default), swallowed
+    /// (`false`) or swallowed only when the expansion ends in whitespace
+    /// (`smart`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_boundary: Option<KeepBoundary>,
+    /// Overrides the global `boundary_position` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boundary_position: Option<BoundaryPosition>,
+    /// Overrides the global `strip_trailing_newline` for this rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_trailing_newline: Option<bool>,
+    /// How newlines in the expansion are typed.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub newline: NewlineMode,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Longest `label` an expansion rule may have, in characters.
+pub const MAX_LABEL_CHARS: usize = 120;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct MenuSnippet {
     pub title: String,
     pub content: String,
+    /// Tray submenu to list this snippet under; top level when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Draws a separator after this snippet in its menu.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub separator_after: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mode: SnippetMode,
+    /// With `mode: type`, how long to wait before typing so the window the
+    /// menu took focus from can get it back; 600 ms when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub type_delay_ms: Option<u64>,
+}
+
+impl MenuSnippet {
+    pub fn type_delay_ms(&self) -> u64 {
+        self.type_delay_ms
+            .unwrap_or_else(default_snippet_type_delay_ms)
+    }
+}
+
+/// How clicking a tray snippet inserts it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SnippetMode {
+    /// Copy the content, with template macros rendered, to the clipboard.
+    #[default]
+    Clipboard,
+    /// Type the content into the focused window, action macros included.
+    Type,
+}
+
+/// Rules that only apply while their profile is active. Its globals replace
+/// same-named (case-insensitive) top-level ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expansions: Vec<ExpansionRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snippets: Vec<MenuSnippet>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_globals",
+        skip_serializing_if = "IndexMap::is_empty"
+    )]
+    pub globals: Globals,
+    /// Hotkey that switches to this profile, e.g. `ctrl+alt+1`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hotkey: Option<String>,
+}
+
+/// Activates `profile` while the focused window's WM_CLASS matches `app`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileRule {
+    pub app: String,
+    pub profile: String,
+}
+
+impl ProfileRule {
+    /// Compares `app` case-insensitively against the focused window's WM_CLASS names.
+    pub fn matches(&self, window_classes: &[String]) -> bool {
+        window_classes
+            .iter()
+            .any(|class| class.eq_ignore_ascii_case(self.app.trim()))
+    }
+}
+
+/// `autocorrect_file`, written either as a bare path or as
+/// `{ path, enabled }`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "AutocorrectFileSpec", into = "AutocorrectFileSpec")]
+pub struct AutocorrectFile {
+    /// Relative to the config's directory, or starting with `~/`.
+    pub path: String,
+    /// `false` keeps the file in the config without loading it.
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum AutocorrectFileSpec {
+    Path(String),
+    Table {
+        path: String,
+        #[serde(default = "default_autocorrect_enabled")]
+        enabled: bool,
+    },
+}
+
+impl From<AutocorrectFileSpec> for AutocorrectFile {
+    fn from(spec: AutocorrectFileSpec) -> Self {
+        match spec {
+            AutocorrectFileSpec::Path(path) => Self {
+                path,
+                enabled: true,
+            },
+            AutocorrectFileSpec::Table { path, enabled } => Self { path, enabled },
+        }
+    }
+}
+
+impl From<AutocorrectFile> for AutocorrectFileSpec {
+    fn from(file: AutocorrectFile) -> Self {
+        if file.enabled {
+            Self::Path(file.path)
+        } else {
+            Self::Table {
+                path: file.path,
+                enabled: false,
+            }
+        }
+    }
+}
+
+/// Reads `profiles` like a plain map, except that a name given twice is an
+/// error instead of the later entry silently replacing the earlier one.
+fn deserialize_profiles<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Profile>, D::Error> {
+    struct ProfilesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for ProfilesVisitor {
+        type Value = BTreeMap<String, Profile>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a map of profile names to profiles")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut profiles = BTreeMap::new();
+            while let Some((name, profile)) = map.next_entry::<String, Profile>()? {
+                if profiles.contains_key(&name) {
+                    return Err(serde::de::Error::custom(format!(
+                        "duplicate profile name found: {name}"
+                    )));
+                }
+                profiles.insert(name, profile);
+            }
+            Ok(profiles)
+        }
+    }
+
+    deserializer.deserialize_map(ProfilesVisitor)
+}
+
+/// `{{NAME}}` values in declaration order, which decides the winner when a
+/// macro name matches several globals case-insensitively.
+pub type Globals = IndexMap<String, String>;
+
+/// Reads `globals` keeping declaration order, rejecting a name given twice
+/// instead of letting the later value silently win.
+fn deserialize_globals<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Globals, D::Error> {
+    struct GlobalsVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for GlobalsVisitor {
+        type Value = Globals;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a map of global names to values")
+        }
+
+        fn visit_map<A: serde::de::MapAccess<'de>>(
+            self,
+            mut map: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut globals = Globals::new();
+            while let Some((name, value)) = map.next_entry::<String, String>()? {
+                if globals.contains_key(&name) {
+                    return Err(serde::de::Error::custom(format!(
+                        "duplicate global found: {name}"
+                    )));
+                }
+                globals.insert(name, value);
+            }
+            Ok(globals)
+        }
+    }
+
+    deserializer.deserialize_map(GlobalsVisitor)
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct NotificationConfig {
     #[serde(default)]
     pub on_expansion: bool,
     #[serde(default)]
     pub on_snippet_copy: bool,
+    /// Report config reloads (what changed, or why it failed).
+    #[serde(default)]
+    pub on_reload: bool,
+    /// Say which expansion the typed text is about to complete once only one
+    /// trigger can still match.
+    #[serde(default)]
+    pub on_preview: bool,
+    /// Minimum time between two expansion notifications.
+    #[serde(default = "default_notification_min_interval_ms")]
+    pub min_interval_ms: u64,
+    /// Summarize expansions that were held back by `min_interval_ms` in the
+    /// next notification instead of dropping them.
+    #[serde(default = "default_notification_coalesce")]
+    pub coalesce: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            on_expansion: false,
+            on_snippet_copy: false,
+            on_reload: false,
+            on_preview: false,
+            min_interval_ms: default_notification_min_interval_ms(),
+            coalesce: default_notification_coalesce(),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum MatchBehavior {
     #[default]
@@ -54,27 +477,739 @@ pub enum MatchBehavior {
     Boundary,
 }
 
+/// How expansion text reaches the focused window.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InjectMode {
+    /// Simulate a key press per character.
+    #[default]
+    Type,
+    /// Put the text on the clipboard and send the paste shortcut.
+    Clipboard,
+}
+
+/// Value of `keep_boundary`: `true`, `false` or `"smart"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeepBoundary {
+    #[default]
+    Keep,
+    Swallow,
+    /// Swallow only when the expansion already ends with whitespace or Enter.
+    Smart,
+}
+
+impl Serialize for KeepBoundary {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Keep => serializer.serialize_bool(true),
+            Self::Swallow => serializer.serialize_bool(false),
+            Self::Smart => serializer.serialize_str("smart"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeepBoundary {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct KeepBoundaryVisitor;
+
+        impl serde::de::Visitor<'_> for KeepBoundaryVisitor {
+            type Value = KeepBoundary;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("true, false or \"smart\"")
+            }
+
+            fn visit_bool<E: serde::de::Error>(self, keep: bool) -> Result<KeepBoundary, E> {
+                Ok(if keep {
+                    KeepBoundary::Keep
+                } else {
+                    KeepBoundary::Swallow
+                })
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<KeepBoundary, E> {
+                match value {
+                    "smart" => Ok(KeepBoundary::Smart),
+                    _ => Err(E::invalid_value(serde::de::Unexpected::Str(value), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(KeepBoundaryVisitor)
+    }
+}
+
+/// Value of a rule's `newline`: how line breaks in its expansion are typed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineMode {
+    /// Typed as text, like any other character.
+    #[default]
+    Text,
+    /// Each newline is an Enter press.
+    Enter,
+    /// Each newline is Shift+Enter, for chat apps where Enter sends.
+    ShiftEnter,
+    /// Lines are joined with a space.
+    Strip,
+}
+
+/// Where a retyped boundary goes relative to `{{MOVE_CARET:...}}` moves at
+/// the end of an expansion.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryPosition {
+    /// Type the boundary first, so the caret moves account for it.
+    Before,
+    /// Type the boundary where the caret ends up.
+    #[default]
+    After,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PasteShortcut {
+    #[default]
+    CtrlV,
+    ShiftInsert,
+    CtrlShiftV,
+}
+
+/// Settings for `inject_mode: clipboard`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PasteConfig {
+    #[serde(default)]
+    pub shortcut: PasteShortcut,
+    /// How long to wait after pasting before restoring the previous clipboard.
+    #[serde(default = "default_paste_restore_delay_ms")]
+    pub restore_delay_ms: u64,
+}
+
+impl Default for PasteConfig {
+    fn default() -> Self {
+        Self {
+            shortcut: PasteShortcut::default(),
+            restore_delay_ms: default_paste_restore_delay_ms(),
+        }
+    }
+}
+
+/// Pacing for typed text, for applications that drop fast input.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OutputConfig {
+    /// Pause after each typed chunk.
+    #[serde(default = "default_output_char_delay_ms")]
+    pub char_delay_ms: u64,
+    /// Characters typed per chunk; 0 types each text run in one go.
+    #[serde(default)]
+    pub chunk_size: usize,
+    /// What to do with characters the backend fails to type.
+    #[serde(default)]
+    pub unicode_fallback: UnicodeFallback,
+    /// Pause between consecutive actions, e.g. text and the key after it.
+    #[serde(default)]
+    pub action_delay_ms: u64,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            char_delay_ms: default_output_char_delay_ms(),
+            chunk_size: 0,
+            unicode_fallback: UnicodeFallback::default(),
+            action_delay_ms: 0,
+        }
+    }
+}
+
+/// Handling for characters that cannot be typed, e.g. emoji or characters
+/// missing from the keymap.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnicodeFallback {
+    /// Leave the character out.
+    #[default]
+    Skip,
+    /// Type `?` in its place.
+    Replace,
+    /// Paste it and the rest of the text run through the clipboard.
+    Paste,
+}
+
+/// Tray menu settings.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct TrayConfig {
+    /// Command for "Edit config…"; `{path}` is replaced by the config path,
+    /// which is appended when the placeholder is missing. Defaults to `xdg-open`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edit_command: Option<String>,
+}
+
+/// Snippet picker settings.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PickerConfig {
+    /// dmenu-style command (e.g. `rofi -dmenu -i`) that reads entry titles
+    /// on stdin and prints the chosen one. Without it the tray's built-in
+    /// popup is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Pause before typing the pick, so focus is back in the target window.
+    #[serde(
+        default = "default_picker_focus_delay_ms",
+        skip_serializing_if = "is_default_picker_focus_delay_ms"
+    )]
+    pub focus_delay_ms: u64,
+}
+
+impl Default for PickerConfig {
+    fn default() -> Self {
+        Self {
+            command: None,
+            focus_delay_ms: default_picker_focus_delay_ms(),
+        }
+    }
+}
+
+/// Limits on what expansions may do.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SecurityConfig {
+    /// Whether `{{CMD:...}}` macros may run commands. When false they fail
+    /// instead, so a shared config cannot run anything.
+    #[serde(
+        default = "default_allow_commands",
+        skip_serializing_if = "is_default_allow_commands"
+    )]
+    pub allow_commands: bool,
+    /// Programs `{{CMD:...}}` may run, matched against the command's first
+    /// word (after any `NAME=value` assignments). Unset allows any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command_allowlist: Option<Vec<String>>,
+    /// Only run a command after its trigger is typed a second time within
+    /// [`COMMAND_CONFIRM_WINDOW`].
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub confirm_commands: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            allow_commands: default_allow_commands(),
+            command_allowlist: None,
+            confirm_commands: false,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Wraps `runner` so it obeys `allow_commands` and `command_allowlist`.
+    /// `confirm_commands` is up to the engine, which knows which expansion
+    /// asked for a command.
+    pub fn guard(&self, runner: Arc<dyn CommandRunner>) -> Arc<dyn CommandRunner> {
+        if !self.allow_commands {
+            return Arc::new(DenyAllRunner);
+        }
+        match &self.command_allowlist {
+            Some(allowlist) => Arc::new(GuardedRunner::new(runner, allowlist.clone())),
+            None => runner,
+        }
+    }
+
+    /// The runner for commands the user asked for on the command line
+    /// (`slykey test`): the allowlist applies, confirmation does not.
+    pub fn command_runner(&self) -> Arc<dyn CommandRunner> {
+        self.guard(Arc::new(ShellRunner))
+    }
+}
+
+/// Bounds on rendering an expansion's macros.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ExpansionConfig {
+    /// `expansion.max_depth`: how deep a global may refer to another global,
+    /// or an `{{EXPAND:...}}` to another rule, before rendering fails.
+    #[serde(
+        default = "default_max_depth",
+        skip_serializing_if = "is_default_max_depth"
+    )]
+    pub max_depth: usize,
+    /// Largest text, in bytes, an expansion may render to, command output
+    /// included.
+    #[serde(
+        default = "default_max_output_bytes",
+        skip_serializing_if = "is_default_max_output_bytes"
+    )]
+    pub max_output_bytes: usize,
+}
+
+impl Default for ExpansionConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: default_max_depth(),
+            max_output_bytes: default_max_output_bytes(),
+        }
+    }
+}
+
+impl ExpansionConfig {
+    pub fn limits(&self) -> RenderLimits {
+        RenderLimits {
+            max_depth: self.max_depth,
+            max_output_bytes: self.max_output_bytes,
+        }
+    }
+}
+
+/// How long `security.confirm_commands` waits for the trigger to be typed again.
+pub const COMMAND_CONFIRM_WINDOW: Duration = Duration::from_secs(5);
+
+impl ExpansionRule {
+    /// How the rule is shown to people: its `label`, or else its trigger.
+    pub fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.trigger)
+    }
+
+    /// Whether the rule may fire: not `enabled: false` and not in one of
+    /// `disabled_groups`.
+    pub fn is_active(&self, disabled_groups: &HashSet<String>) -> bool {
+        self.enabled != Some(false)
+            && !self
+                .group
+                .as_ref()
+                .is_some_and(|group| disabled_groups.contains(group))
+    }
+
+    pub fn has_app_filter(&self) -> bool {
+        !self.apps.is_empty() || !self.exclude_apps.is_empty()
+    }
+
+    /// Checks `apps`/`exclude_apps` case-insensitively against the focused window's WM_CLASS names.
+    pub fn allows_app(&self, window_classes: &[String]) -> bool {
+        let matches_any = |apps: &[String]| {
+            apps.iter().any(|app| {
+                window_classes
+                    .iter()
+                    .any(|class| class.eq_ignore_ascii_case(app.trim()))
+            })
+        };
+
+        (self.apps.is_empty() || matches_any(&self.apps)) && !matches_any(&self.exclude_apps)
+    }
+}
+
 impl AppConfig {
+    /// Reads the config from `config_path_override` or the default lookup
+    /// locations, then merges in its `expansions_dir` and `include` files.
     pub fn load(config_path_override: Option<PathBuf>) -> Result<LoadedConfig> {
-        let path = if let Some(path) = config_path_override {
-            path
-        } else {
-            resolve_default_config_path()?
-        };
+        let path = resolve_config_path(config_path_override)?;
 
         let raw = std::fs::read_to_string(&path)
             .with_context(|| format!("failed to read config: {}", path.display()))?;
-        let config: AppConfig = serde_yaml::from_str(&raw)
-            .with_context(|| format!("failed to parse YAML config: {}", path.display()))?;
+        let format = ConfigFormat::from_path(&path);
+        let mut config: AppConfig = format.parse(&raw).with_context(|| {
+            format!(
+                "failed to parse {} config: {}",
+                format.name(),
+                path.display()
+            )
+        })?;
+
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        let includes = resolve_includes(base_dir, &config.include, &path)?;
+        let mut trigger_files: HashMap<String, PathBuf> = config
+            .expansions
+            .iter()
+            .map(|rule| (rule.trigger.clone(), path.clone()))
+            .collect();
+
+        let expansions_dir = config
+            .expansions_dir
+            .as_deref()
+            .map(|dir| resolve_config_relative(base_dir, dir, dirs::home_dir().as_deref()));
+        let expansion_files = match &expansions_dir {
+            Some(dir) => expansion_dir_files(dir)?,
+            None => Vec::new(),
+        };
+        for file in &expansion_files {
+            let rules = read_rule_file(file)?;
+            record_trigger_files(&rules, file, &mut trigger_files)?;
+            config.expansions.extend(rules);
+        }
+
+        for include in &includes {
+            let raw = std::fs::read_to_string(include).with_context(|| {
+                format!("failed to read included config: {}", include.display())
+            })?;
+            let format = ConfigFormat::from_path(include);
+            let included: IncludedConfig = format.parse(&raw).with_context(|| {
+                format!(
+                    "failed to parse included {} config: {}",
+                    format.name(),
+                    include.display()
+                )
+            })?;
+            config.merge_include(included, include, &mut trigger_files)?;
+        }
+
+        config.strip_trailing_newlines();
+
+        let mut warnings = config.unknown_disabled_group_warnings();
+        warnings.extend(config.command_allowlist_warnings());
+
+        let autocorrect_file = config
+            .autocorrect_file
+            .as_ref()
+            .filter(|file| file.enabled)
+            .map(|file| resolve_config_relative(base_dir, &file.path, dirs::home_dir().as_deref()));
+        if let Some(file) = &autocorrect_file {
+            let dictionary = read_dictionary(file)?;
+            warnings.extend(dictionary.warnings);
+            let (corrections, shadowed) =
+                config.drop_typos_shadowed_by_triggers(dictionary.corrections);
+            warnings.extend(shadowed);
+            config.autocorrect = Arc::new(corrections);
+        }
+        if config.expand_env {
+            let lookup = |name: &str| std::env::var(name).ok();
+            let missing = config.expand_env_values(&lookup, dirs::home_dir().as_deref());
+            if !missing.is_empty() {
+                warnings.push(format!(
+                    "unset environment variables left as written: {}",
+                    missing.join(", ")
+                ));
+            }
+        }
+
+        Ok(LoadedConfig {
+            path,
+            includes,
+            expansions_dir,
+            expansion_files,
+            autocorrect_file,
+            config,
+            warnings,
+        })
+    }
+
+    /// Drops the corrections whose typo is also a trigger, top-level or in a
+    /// profile (compared case-insensitively), with a warning for each: the
+    /// explicit rule wins.
+    fn drop_typos_shadowed_by_triggers(
+        &self,
+        corrections: Vec<Correction>,
+    ) -> (Vec<Correction>, Vec<String>) {
+        let triggers: HashSet<String> = self
+            .expansions
+            .iter()
+            .chain(
+                self.profiles
+                    .values()
+                    .flat_map(|profile| &profile.expansions),
+            )
+            .map(|rule| rule.trigger.to_lowercase())
+            .collect();
+        let mut warnings = Vec::new();
+        let corrections = corrections
+            .into_iter()
+            .filter(|correction| {
+                let shadowed = triggers.contains(&correction.typo);
+                if shadowed {
+                    warnings.push(format!(
+                        "autocorrect typo '{}' is also a trigger, skipped",
+                        correction.typo
+                    ));
+                }
+                !shadowed
+            })
+            .collect();
+        (corrections, warnings)
+    }
+
+    /// Drops one trailing newline from each expansion whose rule (or else the
+    /// global `strip_trailing_newline`) asks for it, and from every snippet
+    /// content when the global setting is on. Profiles are included.
+    fn strip_trailing_newlines(&mut self) {
+        let strip = self.strip_trailing_newline;
+        let rules = self.expansions.iter_mut().chain(
+            self.profiles
+                .values_mut()
+                .flat_map(|profile| &mut profile.expansions),
+        );
+        for rule in rules {
+            if rule.strip_trailing_newline.unwrap_or(strip) {
+                strip_trailing_newline(&mut rule.expansion);
+            }
+        }
+        if strip {
+            let snippets = self.snippets.iter_mut().chain(
+                self.profiles
+                    .values_mut()
+                    .flat_map(|profile| &mut profile.snippets),
+            );
+            for snippet in snippets {
+                strip_trailing_newline(&mut snippet.content);
+            }
+        }
+    }
+
+    /// One warning per `disabled_groups` entry that no rule belongs to.
+    pub fn unknown_disabled_group_warnings(&self) -> Vec<String> {
+        self.disabled_groups
+            .iter()
+            .filter(|group| {
+                !self
+                    .expansions
+                    .iter()
+                    .chain(
+                        self.profiles
+                            .values()
+                            .flat_map(|profile| &profile.expansions),
+                    )
+                    .any(|rule| rule.group.as_ref() == Some(*group))
+            })
+            .map(|group| {
+                format!("disabled_groups lists '{group}', but no expansion is in that group")
+            })
+            .collect()
+    }
+
+    /// One warning per `{{CMD:...}}` macro with a fixed command that
+    /// `security.command_allowlist` would block. Commands built from other
+    /// macros are only checked when they run.
+    pub fn command_allowlist_warnings(&self) -> Vec<String> {
+        let Some(allowlist) = &self.security.command_allowlist else {
+            return Vec::new();
+        };
+        let sources = self
+            .expansions
+            .iter()
+            .map(|rule| (format!("expansion '{}'", rule.trigger), &rule.expansion))
+            .chain(
+                self.snippets
+                    .iter()
+                    .map(|snippet| (format!("snippet '{}'", snippet.title), &snippet.content)),
+            )
+            .chain(
+                self.globals
+                    .iter()
+                    .map(|(name, value)| (format!("global '{name}'"), value)),
+            );
+
+        sources
+            .flat_map(|(source, text)| {
+                static_command_macros(text)
+                    .into_iter()
+                    .filter_map(|value| {
+                        // Bad options are reported by validation.
+                        let (options, command) = split_command_options(value).ok()?;
+                        let err = check_allowlist(command, &options, allowlist).err()?;
+                        Some(format!("{source}: {err}"))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Runs [`expand_env`] over every expansion, `notify_message`, global,
+    /// snippet content (top-level and in profiles) and `boundary_chars`.
+    /// Returns the unset variable names, sorted.
+    fn expand_env_values(
+        &mut self,
+        lookup: &dyn Fn(&str) -> Option<String>,
+        home: Option<&Path>,
+    ) -> Vec<String> {
+        let mut missing = Vec::new();
+        let mut expand = |value: &mut String| {
+            let expanded = expand_env(value, lookup, home);
+            *value = expanded.text;
+            missing.extend(expanded.missing);
+        };
+
+        for rule in &mut self.expansions {
+            expand(&mut rule.expansion);
+            if let Some(message) = &mut rule.notify_message {
+                expand(message);
+            }
+        }
+        for value in self.globals.values_mut() {
+            expand(value);
+        }
+        for snippet in &mut self.snippets {
+            expand(&mut snippet.content);
+        }
+        for profile in self.profiles.values_mut() {
+            for rule in &mut profile.expansions {
+                expand(&mut rule.expansion);
+                if let Some(message) = &mut rule.notify_message {
+                    expand(message);
+                }
+            }
+            for value in profile.globals.values_mut() {
+                expand(value);
+            }
+            for snippet in &mut profile.snippets {
+                expand(&mut snippet.content);
+            }
+        }
+        if let Some(boundary_chars) = &mut self.boundary_chars {
+            expand(boundary_chars);
+        }
+
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+
+    /// Appends an included file's entries. Its globals replace same-named
+    /// (case-insensitive) earlier ones; a trigger already defined in another
+    /// file is an error naming both files.
+    fn merge_include(
+        &mut self,
+        included: IncludedConfig,
+        include_path: &Path,
+        trigger_files: &mut HashMap<String, PathBuf>,
+    ) -> Result<()> {
+        record_trigger_files(&included.expansions, include_path, trigger_files)?;
+        self.expansions.extend(included.expansions);
+        self.snippets.extend(included.snippets);
+        merge_globals(&mut self.globals, included.globals);
+        Ok(())
+    }
+
+    /// The rules in force while `profile` is active: the top-level ones
+    /// followed by the profile's, whose globals replace same-named
+    /// (case-insensitive) top-level ones. `None` or an unknown name gives
+    /// just the top-level rules.
+    pub fn effective(&self, profile: Option<&str>) -> AppConfig {
+        let mut config = self.clone();
+        if let Some(profile) = profile.and_then(|name| self.profiles.get(name)) {
+            config.expansions.extend(profile.expansions.iter().cloned());
+            config.snippets.extend(profile.snippets.iter().cloned());
+            merge_globals(&mut config.globals, profile.globals.clone());
+        }
+        config
+    }
 
-        Ok(LoadedConfig { path, config })
+    /// The profile of the first `profile_rules` entry matching the focused
+    /// window, if any.
+    pub fn profile_for_window(&self, window_classes: &[String]) -> Option<&str> {
+        self.profile_rules
+            .iter()
+            .find(|rule| rule.matches(window_classes))
+            .map(|rule| rule.profile.as_str())
     }
 
+    /// Profile names, sorted.
+    pub fn profile_names(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// Checks invariants serde cannot express (non-empty, unique triggers, valid hotkey, ...).
+    /// Triggers, snippet titles and globals must be unique within the
+    /// top-level rules and within each profile's [`effective`](Self::effective)
+    /// set; two profiles may reuse a trigger.
     pub fn validate(&self) -> Result<()> {
-        if self.expansions.is_empty() {
+        if !self.has_expansions() {
             bail!("config must include at least one expansion");
         }
+        self.validate_allowing_no_expansions()
+    }
+
+    /// Whether any expansion rule is configured, top-level or in a profile.
+    pub fn has_expansions(&self) -> bool {
+        !self.expansions.is_empty()
+            || self
+                .profiles
+                .values()
+                .any(|profile| !profile.expansions.is_empty())
+    }
+
+    /// [`validate`](Self::validate) minus the at-least-one-expansion check,
+    /// for configs written by `slykey remove --force-empty`.
+    pub fn validate_allowing_no_expansions(&self) -> Result<()> {
+        self.validate_rules()?;
+
+        let mut seen_profile_names = HashSet::new();
+        for name in self.profiles.keys() {
+            if name.trim().is_empty() || name.contains(char::is_whitespace) {
+                bail!("profile name cannot be empty or contain whitespace: '{name}'");
+            }
+            if !seen_profile_names.insert(name.to_lowercase()) {
+                bail!("duplicate profile name found (case-insensitive): {name}");
+            }
+            self.effective(Some(name))
+                .validate_rules()
+                .with_context(|| format!("invalid profile '{name}'"))?;
+        }
+        if let Some(name) = &self.default_profile {
+            if !self.profiles.contains_key(name) {
+                bail!(
+                    "default_profile '{name}' is not one of the profiles: {}",
+                    self.profile_names().join(", ")
+                );
+            }
+        }
+
+        for rule in &self.profile_rules {
+            if rule.app.trim().is_empty() {
+                bail!(
+                    "profile_rules app cannot be empty for profile: {}",
+                    rule.profile
+                );
+            }
+            if !self.profiles.contains_key(&rule.profile) {
+                bail!(
+                    "profile_rules entry for app '{}' names unknown profile '{}'",
+                    rule.app,
+                    rule.profile
+                );
+            }
+        }
+
+        for (account, secret) in &self.totp {
+            // The error names the account only; the secret stays out of logs.
+            decode_base32(secret)
+                .with_context(|| format!("invalid totp secret for account: {account}"))?;
+        }
+
+        if self.expansion.max_depth == 0 {
+            bail!("expansion.max_depth must be at least 1");
+        }
+        if self.expansion.max_output_bytes == 0 {
+            bail!("expansion.max_output_bytes must be at least 1");
+        }
+
+        let pause_hotkey = self
+            .pause_hotkey
+            .as_deref()
+            .map(|hotkey| Hotkey::parse(hotkey).context("invalid pause_hotkey"))
+            .transpose()?;
+        let mut hotkeys: Vec<(Hotkey, String)> = pause_hotkey
+            .map(|hotkey| (hotkey, "pause_hotkey".to_string()))
+            .into_iter()
+            .collect();
+        if let Some(hotkey) = &self.picker_hotkey {
+            let picker_hotkey = Hotkey::parse(hotkey).context("invalid picker_hotkey")?;
+            if pause_hotkey == Some(picker_hotkey) {
+                bail!("picker_hotkey and pause_hotkey cannot be the same key combination");
+            }
+            hotkeys.push((picker_hotkey, "picker_hotkey".to_string()));
+        }
+        for (name, profile) in &self.profiles {
+            let Some(hotkey) = &profile.hotkey else {
+                continue;
+            };
+            let hotkey = Hotkey::parse(hotkey)
+                .with_context(|| format!("invalid hotkey for profile '{name}'"))?;
+            if let Some((_, other)) = hotkeys.iter().find(|(used, _)| *used == hotkey) {
+                bail!("hotkey of profile '{name}' is already used by {other}");
+            }
+            hotkeys.push((hotkey, format!("profile '{name}'")));
+        }
+
+        Ok(())
+    }
 
+    /// The per-rule-set part of [`validate`](Self::validate).
+    fn validate_rules(&self) -> Result<()> {
         let mut seen = HashSet::new();
         for rule in &self.expansions {
             if rule.trigger.is_empty() {
@@ -83,6 +1218,31 @@ impl AppConfig {
             if !seen.insert(rule.trigger.clone()) {
                 bail!("duplicate trigger found: {}", rule.trigger);
             }
+            if rule
+                .apps
+                .iter()
+                .chain(&rule.exclude_apps)
+                .any(|app| app.trim().is_empty())
+            {
+                bail!("app filter cannot be empty for trigger: {}", rule.trigger);
+            }
+            if let Some(label) = &rule.label {
+                if label.trim().is_empty() {
+                    bail!("label cannot be empty for trigger: {}", rule.trigger);
+                }
+                if label.chars().count() > MAX_LABEL_CHARS {
+                    bail!(
+                        "label is longer than {MAX_LABEL_CHARS} characters for trigger: {}",
+                        rule.trigger
+                    );
+                }
+            }
+            if rule.notify == Some(false) && rule.notify_message.is_some() {
+                bail!(
+                    "notify_message is set but notify is false for trigger: {}",
+                    rule.trigger
+                );
+            }
         }
 
         let mut seen_titles = HashSet::new();
@@ -93,13 +1253,35 @@ impl AppConfig {
             if snippet.content.is_empty() {
                 bail!("snippet content cannot be empty");
             }
-            if !seen_titles.insert(snippet.title.clone()) {
-                bail!("duplicate snippet title found: {}", snippet.title);
+            if snippet
+                .group
+                .as_deref()
+                .is_some_and(|group| group.trim().is_empty())
+            {
+                bail!(
+                    "snippet group cannot be empty for snippet: {}",
+                    snippet.title
+                );
+            }
+            if snippet.type_delay_ms.is_some() && snippet.mode != SnippetMode::Type {
+                bail!(
+                    "type_delay_ms is set but mode is not type for snippet: {}",
+                    snippet.title
+                );
+            }
+            if !seen_titles.insert((snippet.group.as_deref(), snippet.title.as_str())) {
+                match &snippet.group {
+                    Some(group) => bail!(
+                        "duplicate snippet title found: {} (group {group})",
+                        snippet.title
+                    ),
+                    None => bail!("duplicate snippet title found: {}", snippet.title),
+                }
             }
         }
 
         let mut seen_global_names = HashSet::new();
-        for (name, _value) in &self.globals {
+        for name in self.globals.keys() {
             let trimmed = name.trim();
             if trimmed.is_empty() {
                 bail!("global macro name cannot be empty");
@@ -115,134 +1297,3341 @@ impl AppConfig {
         Ok(())
     }
 
-    pub fn boundary_chars(&self) -> &str {
+    /// [`validate`](Self::validate) plus a dry-run render of every expansion,
+    /// snippet and global, so macro typos and global cycles are caught before
+    /// the daemon uses them. Lists every broken entry, not just the first.
+    pub fn validate_deep(&self) -> Result<()> {
+        self.validate()?;
+
+        let ctx = ExpansionContext {
+            rules: &self.expansions,
+            dry_run: true,
+            runner: &DenyAllRunner,
+            limits: self.expansion.limits(),
+            ..ExpansionContext::new(&self.globals)
+        };
+        let mut errors = Vec::new();
+        for rule in &self.expansions {
+            if let Err(err) = parse_expansion_actions_with_context(&rule.expansion, &ctx) {
+                errors.push(format!("expansion '{}': {err:#}", rule.trigger));
+            }
+        }
+        for snippet in &self.snippets {
+            if let Err(err) = check_snippet_content(snippet, &ctx) {
+                errors.push(format!("snippet '{}': {err:#}", snippet.title));
+            }
+        }
+        for (name, value) in &self.globals {
+            if let Err(err) = parse_expansion_actions_with_context(value, &ctx) {
+                errors.push(format!("global '{name}': {err:#}"));
+            }
+        }
+
+        // A profile's own entries, rendered with the globals in force while
+        // it is active.
+        for (profile_name, profile) in &self.profiles {
+            let effective = self.effective(Some(profile_name));
+            let ctx = ExpansionContext {
+                rules: &effective.expansions,
+                dry_run: true,
+                runner: &DenyAllRunner,
+                limits: self.expansion.limits(),
+                ..ExpansionContext::new(&effective.globals)
+            };
+            for rule in &profile.expansions {
+                if let Err(err) = parse_expansion_actions_with_context(&rule.expansion, &ctx) {
+                    errors.push(format!(
+                        "profile '{profile_name}' expansion '{}': {err:#}",
+                        rule.trigger
+                    ));
+                }
+            }
+            for snippet in &profile.snippets {
+                if let Err(err) = check_snippet_content(snippet, &ctx) {
+                    errors.push(format!(
+                        "profile '{profile_name}' snippet '{}': {err:#}",
+                        snippet.title
+                    ));
+                }
+            }
+            for (name, value) in &profile.globals {
+                if let Err(err) = parse_expansion_actions_with_context(value, &ctx) {
+                    errors.push(format!("profile '{profile_name}' global '{name}': {err:#}"));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        bail!(
+            "{} invalid macro(s):\n  {}",
+            errors.len(),
+            errors.join("\n  ")
+        )
+    }
+
+    /// Rules that load fine but will not fire as written, in the top-level
+    /// set and in each profile's [`effective`](Self::effective) set. Unlike
+    /// [`validate`](Self::validate) findings these are only printed.
+    pub fn lint(&self) -> Vec<ValidationWarning> {
+        let mut warnings = lint_rules(self);
+        for name in self.profiles.keys() {
+            for warning in lint_rules(&self.effective(Some(name))) {
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Warnings for enabled rules that use something an output sink with
+    /// `capabilities` cannot do, top-level rules and each profile's alike.
+    /// Expansions are rendered as in a dry run, so commands don't run.
+    pub fn capability_warnings(&self, capabilities: &SinkCapabilities) -> Vec<ValidationWarning> {
+        let mut warnings = capability_warnings(self, capabilities);
+        for name in self.profiles.keys() {
+            for warning in capability_warnings(&self.effective(Some(name)), capabilities) {
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+        }
+        warnings
+    }
+
+    /// `warning` as printed, led by the label of the rule it is about so
+    /// cryptic triggers can be told apart.
+    pub fn describe_warning(&self, warning: &ValidationWarning) -> String {
+        if matches!(warning, ValidationWarning::DuplicateLabel { .. }) {
+            return warning.to_string();
+        }
+        let label = self
+            .expansions
+            .iter()
+            .chain(
+                self.profiles
+                    .values()
+                    .flat_map(|profile| &profile.expansions),
+            )
+            .find(|rule| rule.trigger == warning.trigger())
+            .and_then(|rule| rule.label.as_deref());
+        match label {
+            Some(label) => format!("{label}: {warning}"),
+            None => warning.to_string(),
+        }
+    }
+
+    pub fn boundary_chars(&self) -> &str {
         self.boundary_chars
             .as_deref()
             .unwrap_or(" \t\n.,;:!?)]}>'\"")
     }
 }
 
-fn resolve_default_config_path() -> Result<PathBuf> {
-    let cwd_file = std::env::current_dir()?.join("slykey.yaml");
-    if cwd_file.exists() {
-        return Ok(cwd_file);
+/// A trigger that will surprise its user; see [`AppConfig::lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationWarning {
+    /// In immediate mode `shadowed_by` is typed, and fires, before `trigger`
+    /// is complete.
+    FiresFirst {
+        trigger: String,
+        shadowed_by: String,
+    },
+    /// `shadowed_by` is a suffix of `trigger` listed before it, and with
+    /// `prefer_longest: false` the earlier rule wins.
+    SuffixListedFirst {
+        trigger: String,
+        shadowed_by: String,
+    },
+    /// In boundary mode typing `boundary` inside `trigger` already ends a
+    /// word: `shadowed_by` fires there, or with a trailing boundary
+    /// character `trigger` needs a second boundary to fire.
+    BoundaryInTrigger {
+        trigger: String,
+        boundary: char,
+        shadowed_by: Option<String>,
+    },
+    WhitespaceOnly {
+        trigger: String,
+    },
+    /// The output sink cannot do `feature`, which `trigger` uses.
+    Unsupported {
+        trigger: String,
+        feature: OutputFeature,
+    },
+    /// `trigger` has the same `label` as `shared_with`, listed before it.
+    DuplicateLabel {
+        trigger: String,
+        label: String,
+        shared_with: String,
+    },
+}
+
+/// Something an expansion may need from the output sink; see
+/// [`SinkCapabilities`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFeature {
+    Paste,
+    KeyCombos,
+    UnicodeText,
+}
+
+impl ValidationWarning {
+    pub fn trigger(&self) -> &str {
+        match self {
+            Self::FiresFirst { trigger, .. }
+            | Self::SuffixListedFirst { trigger, .. }
+            | Self::BoundaryInTrigger { trigger, .. }
+            | Self::WhitespaceOnly { trigger }
+            | Self::Unsupported { trigger, .. }
+            | Self::DuplicateLabel { trigger, .. } => trigger,
+        }
     }
+}
 
-    let home_config = dirs::config_dir()
-        .context("unable to resolve config directory from environment")?
-        .join("slykey")
-        .join("config.yaml");
-    if home_config.exists() {
-        return Ok(home_config);
+impl std::fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FiresFirst {
+                trigger,
+                shadowed_by,
+            } => write!(
+                f,
+                "trigger '{trigger}' never fires: in immediate mode typing it completes \
+                 '{shadowed_by}' first, which expands before '{trigger}' is finished"
+            ),
+            Self::SuffixListedFirst {
+                trigger,
+                shadowed_by,
+            } => write!(
+                f,
+                "trigger '{trigger}' never fires: it ends with '{shadowed_by}', which is \
+                 listed earlier and wins with prefer_longest: false"
+            ),
+            Self::BoundaryInTrigger {
+                trigger,
+                boundary,
+                shadowed_by: Some(shadowed_by),
+            } => write!(
+                f,
+                "trigger '{trigger}' never fires: '{boundary}' is a boundary character, so in \
+                 boundary mode typing it expands '{shadowed_by}' before '{trigger}' is finished"
+            ),
+            Self::BoundaryInTrigger {
+                trigger,
+                boundary,
+                shadowed_by: None,
+            } => write!(
+                f,
+                "trigger '{trigger}' ends with boundary character '{boundary}': in boundary \
+                 mode typing it only ends the word, so '{trigger}' expands after a second \
+                 boundary character"
+            ),
+            Self::WhitespaceOnly { trigger } => write!(
+                f,
+                "trigger {trigger:?} consists only of whitespace: ordinary typing fires it, \
+                 and Enter and Tab never count toward a trigger"
+            ),
+            Self::Unsupported {
+                trigger,
+                feature: OutputFeature::Paste,
+            } => write!(
+                f,
+                "trigger '{trigger}' uses inject_mode: clipboard, but the output backend \
+                 cannot paste, so it is typed instead"
+            ),
+            Self::Unsupported {
+                trigger,
+                feature: OutputFeature::KeyCombos,
+            } => write!(
+                f,
+                "trigger '{trigger}' presses a key chord, which the output backend cannot \
+                 send, so it fails when it fires"
+            ),
+            Self::Unsupported {
+                trigger,
+                feature: OutputFeature::UnicodeText,
+            } => write!(
+                f,
+                "trigger '{trigger}' types non-ASCII text, which the output backend may not \
+                 be able to type"
+            ),
+            Self::DuplicateLabel {
+                trigger,
+                label,
+                shared_with,
+            } => write!(
+                f,
+                "trigger '{trigger}' has the label '{label}', like '{shared_with}': \
+                 notifications and lists cannot tell them apart"
+            ),
+        }
     }
+}
 
-    bail!(
-        "no config file found; expected one of:\n- {}\n- {}",
-        cwd_file.display(),
-        home_config.display()
-    );
+/// [`AppConfig::capability_warnings`] for the rules of one set.
+fn capability_warnings(
+    config: &AppConfig,
+    capabilities: &SinkCapabilities,
+) -> Vec<ValidationWarning> {
+    let ctx = ExpansionContext {
+        rules: &config.expansions,
+        dry_run: true,
+        runner: &DenyAllRunner,
+        limits: config.expansion.limits(),
+        ..ExpansionContext::new(&config.globals)
+    };
+    let mut warnings = Vec::new();
+    for rule in config
+        .expansions
+        .iter()
+        .filter(|rule| rule.enabled != Some(false))
+    {
+        let unsupported = |feature| ValidationWarning::Unsupported {
+            trigger: rule.trigger.clone(),
+            feature,
+        };
+        if !capabilities.paste
+            && rule.inject_mode.unwrap_or(config.inject_mode) == InjectMode::Clipboard
+        {
+            warnings.push(unsupported(OutputFeature::Paste));
+        }
+        // Rules that fail to render are reported by `validate_deep`.
+        let Ok(actions) = parse_expansion_actions_with_context(&rule.expansion, &ctx) else {
+            continue;
+        };
+        let actions = apply_newline_mode(actions, rule.newline);
+        if !capabilities.key_combos && actions.iter().any(OutputAction::presses_chord) {
+            warnings.push(unsupported(OutputFeature::KeyCombos));
+        }
+        if !capabilities.unicode_text
+            && actions
+                .iter()
+                .any(|action| matches!(action, OutputAction::Text(text) if !text.is_ascii()))
+        {
+            warnings.push(unsupported(OutputFeature::UnicodeText));
+        }
+    }
+    warnings
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{AppConfig, ExpansionRule, MatchBehavior, MenuSnippet, NotificationConfig};
-    use std::collections::HashMap;
+/// [`AppConfig::lint`] for the rules of one set.
+fn lint_rules(config: &AppConfig) -> Vec<ValidationWarning> {
+    let rules: Vec<&ExpansionRule> = config
+        .expansions
+        .iter()
+        .filter(|rule| rule.enabled != Some(false))
+        .collect();
+    let boundary = config.match_behavior == MatchBehavior::Boundary;
+    let mut warnings = Vec::new();
+    let mut labels: HashMap<&str, &str> = HashMap::new();
 
-    fn sample_rule(trigger: &str, expansion: &str) -> ExpansionRule {
-        ExpansionRule {
-            trigger: trigger.to_string(),
-            expansion: expansion.to_string(),
+    for (index, rule) in rules.iter().enumerate() {
+        let trigger = rule.trigger.as_str();
+        if let Some(label) = &rule.label {
+            match labels.get(label.as_str()) {
+                Some(shared_with) => warnings.push(ValidationWarning::DuplicateLabel {
+                    trigger: trigger.to_string(),
+                    label: label.clone(),
+                    shared_with: shared_with.to_string(),
+                }),
+                None => {
+                    labels.insert(label, trigger);
+                }
+            }
+        }
+        if trigger.trim().is_empty() {
+            warnings.push(ValidationWarning::WhitespaceOnly {
+                trigger: trigger.to_string(),
+            });
+            continue;
+        }
+
+        let suffix_listed_first = rules[..index]
+            .iter()
+            .find(|other| other.trigger.len() < trigger.len() && trigger.ends_with(&other.trigger));
+        if let Some(other) = suffix_listed_first.filter(|_| !config.prefer_longest) {
+            warnings.push(ValidationWarning::SuffixListedFirst {
+                trigger: trigger.to_string(),
+                shadowed_by: other.trigger.clone(),
+            });
+            continue;
+        }
+
+        if boundary {
+            if let Some(warning) = lint_boundary_chars(config, &rules, trigger) {
+                warnings.push(warning);
+            }
+            continue;
+        }
+
+        // A shorter trigger ending before this one's last character fires
+        // first, unless it is a prefix that prefer_longest holds back.
+        let fires_first = trigger
+            .char_indices()
+            .skip(1)
+            .map(|(end, _)| &trigger[..end])
+            .find_map(|typed| {
+                rules.iter().find(|other| {
+                    typed.ends_with(&other.trigger)
+                        && !(config.prefer_longest && typed == other.trigger)
+                })
+            });
+        if let Some(other) = fires_first {
+            warnings.push(ValidationWarning::FiresFirst {
+                trigger: trigger.to_string(),
+                shadowed_by: other.trigger.clone(),
+            });
         }
     }
+    warnings
+}
 
-    fn sample_snippet(title: &str, content: &str) -> MenuSnippet {
-        MenuSnippet {
-            title: title.to_string(),
-            content: content.to_string(),
+fn lint_boundary_chars(
+    config: &AppConfig,
+    rules: &[&ExpansionRule],
+    trigger: &str,
+) -> Option<ValidationWarning> {
+    // A leading boundary character only ends the word typed before it.
+    let (last, _) = trigger.char_indices().last()?;
+    trigger
+        .char_indices()
+        .skip(1)
+        .filter(|(_, c)| config.boundary_chars().contains(*c))
+        .find_map(|(position, boundary)| {
+            let typed = &trigger[..position];
+            let shadowed_by = rules
+                .iter()
+                .find(|other| typed.ends_with(&other.trigger))
+                .map(|other| other.trigger.clone());
+            (shadowed_by.is_some() || position == last).then(|| {
+                ValidationWarning::BoundaryInTrigger {
+                    trigger: trigger.to_string(),
+                    boundary,
+                    shadowed_by,
+                }
+            })
+        })
+}
+
+/// Inserts `globals`, replacing same-named (case-insensitive) entries.
+fn merge_globals(target: &mut Globals, globals: Globals) {
+    for (name, value) in globals {
+        target.retain(|existing, _| !existing.trim().eq_ignore_ascii_case(name.trim()));
+        target.insert(name, value);
+    }
+}
+
+/// Renders a snippet the way clicking it would, as actions when it is typed.
+/// The tray has no rule set, so `{{EXPAND:...}}` fails there.
+fn check_snippet_content(snippet: &MenuSnippet, ctx: &ExpansionContext) -> Result<()> {
+    let ctx = &ExpansionContext { rules: &[], ..*ctx };
+    match snippet.mode {
+        SnippetMode::Clipboard => {
+            render_template_macros_with_context(&snippet.content, ctx)?;
+        }
+        SnippetMode::Type => {
+            parse_expansion_actions_with_context(&snippet.content, ctx)?;
         }
     }
+    Ok(())
+}
 
-    #[test]
-    fn validate_rejects_empty_expansions() {
-        let cfg = AppConfig {
-            expansions: vec![],
-            snippets: vec![],
-            globals: HashMap::new(),
-            notifications: NotificationConfig::default(),
-            match_behavior: MatchBehavior::Immediate,
-            boundary_chars: None,
-            watch: false,
-        };
+fn default_prefer_longest() -> bool {
+    true
+}
 
-        let err = cfg.validate().expect_err("empty config should fail");
-        assert!(err.to_string().contains("at least one expansion"));
+fn default_strip_trailing_newline() -> bool {
+    true
+}
+
+fn default_snippet_type_delay_ms() -> u64 {
+    600
+}
+
+fn default_history_size() -> usize {
+    20
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn is_default_prefer_longest(value: &bool) -> bool {
+    *value == default_prefer_longest()
+}
+
+fn is_default_strip_trailing_newline(value: &bool) -> bool {
+    *value == default_strip_trailing_newline()
+}
+
+fn is_default_history_size(value: &usize) -> bool {
+    *value == default_history_size()
+}
+
+fn default_buffer_slack() -> usize {
+    8
+}
+
+fn is_default_buffer_slack(value: &usize) -> bool {
+    *value == default_buffer_slack()
+}
+
+fn default_autocorrect_enabled() -> bool {
+    true
+}
+
+fn default_pending_expansion_timeout_ms() -> u64 {
+    2000
+}
+
+fn is_default_pending_expansion_timeout_ms(value: &u64) -> bool {
+    *value == default_pending_expansion_timeout_ms()
+}
+
+fn default_picker_focus_delay_ms() -> u64 {
+    150
+}
+
+fn is_default_picker_focus_delay_ms(value: &u64) -> bool {
+    *value == default_picker_focus_delay_ms()
+}
+
+fn default_max_depth() -> usize {
+    DEFAULT_MAX_DEPTH
+}
+
+fn is_default_max_depth(value: &usize) -> bool {
+    *value == default_max_depth()
+}
+
+fn default_max_output_bytes() -> usize {
+    DEFAULT_MAX_OUTPUT_BYTES
+}
+
+fn is_default_max_output_bytes(value: &usize) -> bool {
+    *value == default_max_output_bytes()
+}
+
+fn default_allow_commands() -> bool {
+    true
+}
+
+fn is_default_allow_commands(value: &bool) -> bool {
+    *value == default_allow_commands()
+}
+
+fn default_paste_restore_delay_ms() -> u64 {
+    150
+}
+
+fn default_output_char_delay_ms() -> u64 {
+    1
+}
+
+fn default_notification_min_interval_ms() -> u64 {
+    2000
+}
+
+fn default_notification_coalesce() -> bool {
+    true
+}
+
+/// Returns `config_path_override` or the first existing default config location.
+pub fn resolve_config_path(config_path_override: Option<PathBuf>) -> Result<PathBuf> {
+    match config_path_override {
+        Some(path) => Ok(path),
+        None => resolve_default_config_path(),
     }
+}
 
-    #[test]
-    fn validate_rejects_duplicate_triggers() {
-        let cfg = AppConfig {
-            expansions: vec![sample_rule(";a", "alpha"), sample_rule(";a", "again")],
-            snippets: vec![],
-            globals: HashMap::new(),
-            notifications: NotificationConfig::default(),
-            match_behavior: MatchBehavior::Immediate,
-            boundary_chars: None,
-            watch: false,
+/// Config file syntax, picked from the file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// `.toml` and `.json` select those formats; anything else is YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => Self::Toml,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yaml => "YAML",
+            Self::Toml => "TOML",
+            Self::Json => "JSON",
+        }
+    }
+
+    /// Parses `raw`, turning errors into a snippet of the offending lines
+    /// (see [`format_parse_error`]) plus a hint for common shape mistakes.
+    fn parse<T: DeserializeOwned>(self, raw: &str) -> Result<T> {
+        let mut message = match self {
+            Self::Yaml => match serde_yaml::from_str(raw) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let location = err
+                        .location()
+                        .map(|location| (location.line(), location.column()));
+                    format_parse_error(raw, location, &err.to_string())
+                }
+            },
+            Self::Json => match serde_json::from_str(raw) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    format_parse_error(raw, Some((err.line(), err.column())), &err.to_string())
+                }
+            },
+            // toml already quotes the offending line in its message.
+            Self::Toml => match toml::from_str(raw) {
+                Ok(value) => return Ok(value),
+                Err(err) => err.to_string().trim_end().to_string(),
+            },
         };
+        if let Some(hint) = self.document(raw).as_ref().and_then(config_shape_hint) {
+            message.push_str(&format!("\nhint: {hint}"));
+        }
+        Err(anyhow::anyhow!(message))
+    }
 
-        let err = cfg.validate().expect_err("duplicate trigger should fail");
-        assert!(err.to_string().contains("duplicate trigger"));
+    /// The untyped document, if `raw` is at least syntactically valid.
+    fn document(self, raw: &str) -> Option<serde_yaml::Value> {
+        match self {
+            Self::Yaml => serde_yaml::from_str(raw).ok(),
+            Self::Toml => toml::from_str(raw).ok(),
+            Self::Json => serde_json::from_str(raw).ok(),
+        }
     }
 
-    #[test]
-    fn boundary_chars_uses_default_when_unset() {
-        let cfg = AppConfig {
-            expansions: vec![sample_rule(";a", "alpha")],
-            snippets: vec![],
-            globals: HashMap::new(),
-            notifications: NotificationConfig::default(),
-            match_behavior: MatchBehavior::Immediate,
-            boundary_chars: None,
-            watch: false,
+    /// Commented example rule appended to a config saved without expansions;
+    /// JSON has no comments.
+    fn expansion_placeholder(self) -> Option<&'static str> {
+        match self {
+            Self::Yaml => Some(concat!(
+                "# No expansions are left; slykey will not start until one is added, e.g.:\n",
+                "# expansions:\n",
+                "#   - trigger: ';example'\n",
+                "#     expansion: example text\n",
+            )),
+            Self::Toml => Some(concat!(
+                "# No expansions are left; slykey will not start until one is added, e.g.:\n",
+                "# [[expansions]]\n",
+                "# trigger = \";example\"\n",
+                "# expansion = \"example text\"\n",
+            )),
+            Self::Json => None,
+        }
+    }
+
+    fn serialize(self, document: &serde_yaml::Value) -> Result<String> {
+        let serialized = match self {
+            Self::Yaml => serde_yaml::to_string(document)?,
+            Self::Toml => toml::to_string_pretty(document)?,
+            Self::Json => serde_json::to_string_pretty(document)? + "\n",
         };
+        Ok(serialized)
+    }
+}
 
-        assert_eq!(cfg.boundary_chars(), " \t\n.,;:!?)]}>'\"");
+/// Removes at most one trailing `\n` or `\r\n`.
+fn strip_trailing_newline(text: &mut String) {
+    if text.ends_with('\n') {
+        text.pop();
+        if text.ends_with('\r') {
+            text.pop();
+        }
     }
+}
 
-    #[test]
-    fn validate_rejects_empty_snippet_title() {
-        let cfg = AppConfig {
-            expansions: vec![sample_rule(";a", "alpha")],
-            snippets: vec![sample_snippet(" ", "hello")],
-            globals: HashMap::new(),
-            notifications: NotificationConfig::default(),
-            match_behavior: MatchBehavior::Immediate,
-            boundary_chars: None,
-            watch: false,
+/// Notes which file each of `rules` came from; a trigger already seen in
+/// another file is an error naming both.
+fn record_trigger_files(
+    rules: &[ExpansionRule],
+    path: &Path,
+    trigger_files: &mut HashMap<String, PathBuf>,
+) -> Result<()> {
+    for rule in rules {
+        if let Some(first) = trigger_files.get(&rule.trigger) {
+            if first != path {
+                bail!(
+                    "duplicate trigger found: {} (in {} and {})",
+                    rule.trigger,
+                    first.display(),
+                    path.display()
+                );
+            }
+        }
+        trigger_files.insert(rule.trigger.clone(), path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// A path from the config, such as `expansions_dir` or `autocorrect_file`,
+/// against `base_dir`, with a leading `~/` meaning `home`.
+fn resolve_config_relative(base_dir: &Path, path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => base_dir.join(path),
+    }
+}
+
+/// The `*.yaml` files directly in `dir`, sorted by name.
+fn expansion_dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read expansions_dir: {}", dir.display()))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("failed to read expansions_dir: {}", dir.display()))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "yaml") && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Reads one `expansions_dir` file: a single rule, a list of rules, or
+/// nothing at all.
+fn read_rule_file(path: &Path) -> Result<Vec<ExpansionRule>> {
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read expansion file: {}", path.display()))?;
+    let value: serde_yaml::Value = serde_yaml::from_str(&raw)
+        .with_context(|| format!("failed to parse expansion file: {}", path.display()))?;
+    let rules = match value {
+        serde_yaml::Value::Null => return Ok(Vec::new()),
+        serde_yaml::Value::Sequence(_) => serde_yaml::from_value(value),
+        _ => serde_yaml::from_value(value).map(|rule| vec![rule]),
+    };
+    rules.with_context(|| format!("failed to parse expansion file: {}", path.display()))
+}
+
+/// Expands `include` entries against `base_dir`. Globs match in sorted
+/// order; a glob matching nothing is fine, while a missing plain path fails
+/// when it is read. The main config itself and repeated matches are skipped.
+fn resolve_includes(
+    base_dir: &Path,
+    patterns: &[String],
+    main_path: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut includes: Vec<PathBuf> = Vec::new();
+    for pattern in patterns {
+        let joined = base_dir.join(pattern);
+        let candidates = if pattern.contains(['*', '?', '[']) {
+            let pattern_str = joined
+                .to_str()
+                .with_context(|| format!("include pattern is not valid UTF-8: {pattern}"))?;
+            let mut matches = glob::glob(pattern_str)
+                .with_context(|| format!("invalid include pattern: {pattern}"))?
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .with_context(|| format!("failed to expand include pattern: {pattern}"))?;
+            matches.sort();
+            matches
+        } else {
+            vec![joined]
         };
+        for candidate in candidates {
+            if candidate != main_path && !includes.contains(&candidate) {
+                includes.push(candidate);
+            }
+        }
+    }
+    Ok(includes)
+}
 
-        let err = cfg.validate().expect_err("empty snippet title should fail");
-        assert!(err.to_string().contains("snippet title cannot be empty"));
+/// Renders `message` followed by the line at `location` (1-based line and
+/// column) with up to two preceding lines and a caret under the column:
+///
+/// ```text
+/// mapping values are not allowed in this context at line 3 column 17
+///   |
+/// 2 |   - trigger: ';a'
+/// 3 |     expansion: b: c
+///   |                 ^
+/// ```
+pub fn format_parse_error(source: &str, location: Option<(usize, usize)>, message: &str) -> String {
+    let Some((line, column)) = location else {
+        return message.to_string();
+    };
+    let lines: Vec<&str> = source.lines().collect();
+    let Some(offending) = line.checked_sub(1).and_then(|index| lines.get(index)) else {
+        return message.to_string();
+    };
+
+    let first = line.saturating_sub(2).max(1);
+    let gutter = line.to_string().len();
+    let mut out = format!("{message}\n{:gutter$} |", "");
+    for number in first..line {
+        out.push_str(&format!("\n{number:>gutter$} | {}", lines[number - 1]));
     }
+    out.push_str(&format!("\n{line:>gutter$} | {offending}"));
 
-    #[test]
-    fn validate_rejects_duplicate_snippet_titles() {
-        let cfg = AppConfig {
-            expansions: vec![sample_rule(";a", "alpha")],
-            snippets: vec![
-                sample_snippet("Email", "a@example.com"),
-                sample_snippet("Email", "b@example.com"),
-            ],
-            globals: HashMap::new(),
-            notifications: NotificationConfig::default(),
-            match_behavior: MatchBehavior::Immediate,
-            boundary_chars: None,
-            watch: false,
+    // Reuse tabs from the source line so the caret lines up however the
+    // terminal renders them.
+    let padding: String = offending
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    out.push_str(&format!("\n{:gutter$} | {padding}^", ""));
+    out
+}
+
+/// Spots lists written as maps, e.g. `expansions:` followed by `;sig: ...`
+/// instead of `- trigger: ;sig`.
+fn config_shape_hint(document: &serde_yaml::Value) -> Option<String> {
+    [
+        ("expansions", "- trigger: ';sig'"),
+        ("snippets", "- title: Email"),
+    ]
+    .into_iter()
+    .find_map(|(key, example)| {
+        document.get(key)?.as_mapping()?;
+        Some(format!(
+            "`{key}` must be a list; start each entry with `- `, e.g. `{example}`"
+        ))
+    })
+}
+
+/// Appends a `trigger` -> `expansion` rule to the config file at `path`.
+pub fn append_expansion(path: &Path, trigger: &str, expansion: &str) -> Result<()> {
+    let rule = ExpansionRule {
+        trigger: trigger.to_string(),
+        expansion: expansion.to_string(),
+        ..ExpansionRule::default()
+    };
+    append_rules(path, &[rule], &[], &BTreeMap::new())
+}
+
+/// Appends a tray snippet to the config file at `path`.
+pub fn append_snippet(path: &Path, title: &str, content: &str) -> Result<()> {
+    let snippet = MenuSnippet {
+        title: title.to_string(),
+        content: content.to_string(),
+        group: None,
+        separator_after: false,
+        mode: SnippetMode::Clipboard,
+        type_delay_ms: None,
+    };
+    append_rules(path, &[], &[snippet], &BTreeMap::new())
+}
+
+/// Appends expansion rules, snippets and globals to the config file at `path`
+/// in one write; an existing global with the same name is replaced.
+pub fn append_rules(
+    path: &Path,
+    expansions: &[ExpansionRule],
+    snippets: &[MenuSnippet],
+    globals: &BTreeMap<String, String>,
+) -> Result<()> {
+    let mut editor = ConfigEditor::open(path)?;
+    for rule in expansions {
+        editor.add_rule(rule)?;
+    }
+    for snippet in snippets {
+        editor.add_snippet(snippet)?;
+    }
+    for (name, value) in globals {
+        editor.set_global(name, value)?;
+    }
+    editor.save_atomic(path)
+}
+
+/// What `slykey remove` deletes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoveTarget {
+    Trigger(String),
+    SnippetTitle(String),
+}
+
+impl RemoveTarget {
+    fn describe(&self) -> String {
+        match self {
+            Self::Trigger(trigger) => format!("expansion with trigger '{trigger}'"),
+            Self::SnippetTitle(title) => format!("snippet titled '{title}'"),
+        }
+    }
+}
+
+/// Removes the expansion rules or snippets matching `target` from the config
+/// file at `path`, top-level and in every profile, and returns them. Only
+/// checks the result with `dry_run`.
+///
+/// A config left without expansions is refused unless `force_empty`, which
+/// writes it anyway (see [`ConfigEditor::allow_no_expansions`]). When nothing
+/// matches, the error names the `include` or `expansions_dir` file defining
+/// `target`, or lists close matches.
+pub fn remove_entry(
+    path: &Path,
+    target: &RemoveTarget,
+    force_empty: bool,
+    dry_run: bool,
+) -> Result<Vec<serde_yaml::Value>> {
+    let mut editor = ConfigEditor::open(path)?;
+    let (removed, candidates) = match target {
+        RemoveTarget::Trigger(trigger) => (editor.remove_rule(trigger)?, editor.triggers()),
+        RemoveTarget::SnippetTitle(title) => {
+            (editor.remove_snippet(title)?, editor.snippet_titles())
+        }
+    };
+    if removed.is_empty() {
+        if let Some(file) = file_defining(path, target) {
+            bail!(
+                "the {} is defined in {}, not in {}; remove it there",
+                target.describe(),
+                file.display(),
+                path.display()
+            );
+        }
+        let wanted = match target {
+            RemoveTarget::Trigger(name) | RemoveTarget::SnippetTitle(name) => name,
         };
+        let mut message = format!("no {} in {}", target.describe(), path.display());
+        let suggestions = close_matches(wanted, &candidates);
+        if !suggestions.is_empty() {
+            message.push_str(&format!("\ndid you mean: {}", suggestions.join(", ")));
+        }
+        bail!(message);
+    }
 
-        let err = cfg
-            .validate()
-            .expect_err("duplicate snippet title should fail");
-        assert!(err.to_string().contains("duplicate snippet title"));
+    if force_empty {
+        editor.allow_no_expansions();
+    } else if !editor.parsed()?.has_expansions() {
+        bail!(
+            "removing the {} would leave {} without expansions, which slykey refuses to load; \
+             pass --force-empty to write it anyway with a commented placeholder",
+            target.describe(),
+            path.display()
+        );
+    }
+    if dry_run {
+        editor
+            .config()
+            .with_context(|| format!("refusing to write invalid config: {}", path.display()))?;
+    } else {
+        editor.save_atomic(path)?;
+    }
+    Ok(removed)
+}
+
+/// The `include` or `expansions_dir` file of the config at `path` that
+/// defines `target`. Files that fail to load are skipped.
+fn file_defining(path: &Path, target: &RemoveTarget) -> Option<PathBuf> {
+    let loaded = AppConfig::load(Some(path.to_path_buf())).ok()?;
+    let defines = |rules: &[ExpansionRule], snippets: &[MenuSnippet]| match target {
+        RemoveTarget::Trigger(trigger) => rules.iter().any(|rule| &rule.trigger == trigger),
+        RemoveTarget::SnippetTitle(title) => snippets.iter().any(|snippet| &snippet.title == title),
+    };
+    let in_include = |include: &&PathBuf| {
+        std::fs::read_to_string(include)
+            .ok()
+            .and_then(|raw| {
+                ConfigFormat::from_path(include)
+                    .parse::<IncludedConfig>(&raw)
+                    .ok()
+            })
+            .is_some_and(|included| defines(&included.expansions, &included.snippets))
+    };
+    let in_rule_file =
+        |file: &&PathBuf| read_rule_file(file).is_ok_and(|rules| defines(&rules, &[]));
+    loaded
+        .includes
+        .iter()
+        .find(in_include)
+        .or_else(|| loaded.expansion_files.iter().find(in_rule_file))
+        .cloned()
+}
+
+/// Up to three of `candidates` that `wanted` looks like a typo or a prefix
+/// of, closest first; compared case-insensitively.
+fn close_matches<'a>(wanted: &str, candidates: &[&'a str]) -> Vec<&'a str> {
+    let wanted = wanted.to_lowercase();
+    // Two edits cover a swapped pair of characters.
+    let max_distance = (wanted.chars().count() / 3).max(2);
+    let mut matches: Vec<(usize, &str)> = candidates
+        .iter()
+        .filter_map(|&candidate| {
+            let lowered = candidate.to_lowercase();
+            let distance = levenshtein(&wanted, &lowered);
+            let prefix = !wanted.is_empty()
+                && (lowered.starts_with(&wanted) || wanted.starts_with(&lowered));
+            (distance <= max_distance || prefix).then_some((distance, candidate))
+        })
+        .collect();
+    matches.sort();
+    matches.dedup_by_key(|(_, candidate)| *candidate);
+    matches
+        .into_iter()
+        .take(3)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Edit distance in characters.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Programmatic edits to a config file.
+///
+/// Edits the untyped document rather than `AppConfig` so keys slykey does not
+/// model survive the round-trip; the result must still validate before it is
+/// written.
+#[derive(Debug, Clone)]
+pub struct ConfigEditor {
+    document: serde_yaml::Mapping,
+    allow_no_expansions: bool,
+}
+
+impl ConfigEditor {
+    /// Reads `path` in the format picked by its extension.
+    pub fn open(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config: {}", path.display()))?;
+        let format = ConfigFormat::from_path(path);
+        let document: serde_yaml::Value = format.parse(&raw).with_context(|| {
+            format!(
+                "failed to parse {} config: {}",
+                format.name(),
+                path.display()
+            )
+        })?;
+        match document {
+            serde_yaml::Value::Mapping(document) => Ok(Self::new(document)),
+            serde_yaml::Value::Null => Ok(Self::new(serde_yaml::Mapping::new())),
+            _ => bail!("config root must be a mapping: {}", path.display()),
+        }
+    }
+
+    /// Starts from an in-memory config, e.g. to write it in another format.
+    pub fn from_config(config: &AppConfig) -> Result<Self> {
+        match serde_yaml::to_value(config).context("failed to serialize config")? {
+            serde_yaml::Value::Mapping(document) => Ok(Self::new(document)),
+            _ => bail!("config did not serialize to a mapping"),
+        }
+    }
+
+    fn new(document: serde_yaml::Mapping) -> Self {
+        Self {
+            document,
+            allow_no_expansions: false,
+        }
+    }
+
+    /// Appends `rule`; its trigger must not be configured yet.
+    pub fn add_rule(&mut self, rule: &ExpansionRule) -> Result<()> {
+        let rules = self.list("expansions")?;
+        let duplicate = rules.iter().any(|existing| {
+            existing.get("trigger").and_then(serde_yaml::Value::as_str) == Some(&rule.trigger)
+        });
+        if duplicate {
+            bail!("duplicate trigger found: {}", rule.trigger);
+        }
+        rules.push(serde_yaml::to_value(rule).context("failed to serialize expansion rule")?);
+        Ok(())
+    }
+
+    pub fn add_snippet(&mut self, snippet: &MenuSnippet) -> Result<()> {
+        let snippet = serde_yaml::to_value(snippet).context("failed to serialize snippet")?;
+        self.list("snippets")?.push(snippet);
+        Ok(())
+    }
+
+    /// Removes the rules for `trigger`, top-level and in every profile,
+    /// returning them.
+    pub fn remove_rule(&mut self, trigger: &str) -> Result<Vec<serde_yaml::Value>> {
+        self.remove_entries("expansions", "trigger", trigger)
+    }
+
+    /// Removes the snippets titled `title`, top-level and in every profile,
+    /// returning them.
+    pub fn remove_snippet(&mut self, title: &str) -> Result<Vec<serde_yaml::Value>> {
+        self.remove_entries("snippets", "title", title)
+    }
+
+    /// Every configured trigger, top-level first, then by profile.
+    pub fn triggers(&self) -> Vec<&str> {
+        self.entry_names("expansions", "trigger")
+    }
+
+    pub fn snippet_titles(&self) -> Vec<&str> {
+        self.entry_names("snippets", "title")
+    }
+
+    /// Lets [`save_atomic`](Self::save_atomic) write a config without any
+    /// expansion. YAML and TOML get a commented example rule appended so the
+    /// file shows how to add one back.
+    pub fn allow_no_expansions(&mut self) {
+        self.allow_no_expansions = true;
+    }
+
+    /// Sets a global, replacing one whose name differs only in case.
+    pub fn set_global(&mut self, name: &str, value: &str) -> Result<()> {
+        let section = self.section("globals", serde_yaml::Mapping::new().into());
+        let Some(globals) = section.as_mapping_mut() else {
+            bail!("config key 'globals' must be a mapping");
+        };
+        globals.retain(|existing, _| {
+            !existing
+                .as_str()
+                .is_some_and(|existing| existing.eq_ignore_ascii_case(name))
+        });
+        globals.insert(name.into(), value.into());
+        Ok(())
+    }
+
+    /// The edited config, checked with [`AppConfig::validate`].
+    pub fn config(&self) -> Result<AppConfig> {
+        let config = self.parsed()?;
+        if self.allow_no_expansions {
+            config.validate_allowing_no_expansions()?;
+        } else {
+            config.validate()?;
+        }
+        Ok(config)
+    }
+
+    fn parsed(&self) -> Result<AppConfig> {
+        serde_yaml::from_value(serde_yaml::Value::Mapping(self.document.clone()))
+            .context("updated config is invalid")
+    }
+
+    /// Validates, then writes the document in the format of `path`'s extension
+    /// to a temporary file next to it and renames it into place, so `path` is
+    /// either fully replaced or left untouched.
+    pub fn save_atomic(&self, path: &Path) -> Result<()> {
+        let config = self
+            .config()
+            .with_context(|| format!("refusing to write invalid config: {}", path.display()))?;
+        let format = ConfigFormat::from_path(path);
+        let mut document = self.document.clone();
+        let placeholder = if config.has_expansions() {
+            None
+        } else {
+            format.expansion_placeholder()
+        };
+        if placeholder.is_some() {
+            // The placeholder shows the whole section; an empty list next to
+            // it would clash once uncommented.
+            document.remove("expansions");
+        }
+        let mut serialized = if placeholder.is_some() && document.is_empty() {
+            String::new()
+        } else {
+            format
+                .serialize(&serde_yaml::Value::Mapping(document))
+                .with_context(|| format!("failed to serialize {} config", format.name()))?
+        };
+        serialized.push_str(placeholder.unwrap_or_default());
+        write_file_atomically(path, &serialized)
+    }
+
+    fn remove_entries(
+        &mut self,
+        section: &str,
+        field: &str,
+        name: &str,
+    ) -> Result<Vec<serde_yaml::Value>> {
+        let mut removed = take_entries(self.list(section)?, field, name);
+        if let Some(profiles) = self
+            .document
+            .get_mut("profiles")
+            .and_then(serde_yaml::Value::as_mapping_mut)
+        {
+            for (profile_name, profile) in profiles.iter_mut() {
+                let Some(entries) = profile.get_mut(section) else {
+                    continue;
+                };
+                let Some(entries) = entries.as_sequence_mut() else {
+                    bail!(
+                        "config key 'profiles.{}.{section}' must be a list",
+                        profile_name.as_str().unwrap_or("?")
+                    );
+                };
+                removed.extend(take_entries(entries, field, name));
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entry_names(&self, section: &str, field: &str) -> Vec<&str> {
+        let profiles = self
+            .document
+            .get("profiles")
+            .and_then(serde_yaml::Value::as_mapping)
+            .into_iter()
+            .flat_map(|profiles| profiles.values());
+        std::iter::once(self.document.get(section))
+            .chain(profiles.map(|profile| profile.get(section)))
+            .flatten()
+            .filter_map(serde_yaml::Value::as_sequence)
+            .flatten()
+            .filter_map(|entry| entry.get(field).and_then(serde_yaml::Value::as_str))
+            .collect()
+    }
+
+    /// The value under `key`, set to `empty` when missing or null.
+    fn section(&mut self, key: &str, empty: serde_yaml::Value) -> &mut serde_yaml::Value {
+        let section = self
+            .document
+            .entry(key.into())
+            .or_insert(serde_yaml::Value::Null);
+        if section.is_null() {
+            *section = empty;
+        }
+        section
+    }
+
+    fn list(&mut self, key: &str) -> Result<&mut Vec<serde_yaml::Value>> {
+        self.section(key, serde_yaml::Value::Sequence(Vec::new()))
+            .as_sequence_mut()
+            .with_context(|| format!("config key '{key}' must be a list"))
+    }
+}
+
+/// Moves the entries whose `field` equals `name` out of `entries`.
+fn take_entries(
+    entries: &mut Vec<serde_yaml::Value>,
+    field: &str,
+    name: &str,
+) -> Vec<serde_yaml::Value> {
+    let (removed, kept) = std::mem::take(entries)
+        .into_iter()
+        .partition(|entry| entry.get(field).and_then(serde_yaml::Value::as_str) == Some(name));
+    *entries = kept;
+    removed
+}
+
+fn write_file_atomically(path: &Path, contents: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("config path has no file name: {}", path.display()))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = std::fs::File::create(&tmp_path).and_then(|mut file| {
+        std::io::Write::write_all(&mut file, contents.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err).with_context(|| format!("failed to write config: {}", tmp_path.display()));
+    }
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(err).with_context(|| format!("failed to replace config: {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Commented example config written by `slykey init`.
+pub const STARTER_CONFIG: &str = include_str!("starter_config.yaml");
+
+/// Writes [`STARTER_CONFIG`] to `path`, creating parent directories. A
+/// `.toml` or `.json` path gets the same settings in that format, without
+/// the comments. An existing file is only replaced with `force`.
+pub fn write_starter_config(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "config already exists: {} (use --force to overwrite)",
+            path.display()
+        );
+    }
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create config directory: {}", parent.display()))?;
+    }
+    match ConfigFormat::from_path(path) {
+        ConfigFormat::Yaml => write_file_atomically(path, STARTER_CONFIG),
+        _ => {
+            let document: serde_yaml::Mapping =
+                serde_yaml::from_str(STARTER_CONFIG).context("failed to parse starter config")?;
+            ConfigEditor::new(document).save_atomic(path)
+        }
+    }
+}
+
+/// `~/.config/slykey/config.yaml`, where `slykey init` writes by default.
+pub fn default_config_path() -> Result<PathBuf> {
+    Ok(default_config_dir()?.join("config.yaml"))
+}
+
+fn default_config_dir() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("unable to resolve config directory from environment")?
+        .join("slykey"))
+}
+
+/// Extensions probed for the default config files, YAML first.
+const CONFIG_EXTENSIONS: [&str; 3] = ["yaml", "toml", "json"];
+
+fn resolve_default_config_path() -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let config_dir = default_config_dir()?;
+    let candidates: Vec<PathBuf> = CONFIG_EXTENSIONS
+        .iter()
+        .map(|ext| cwd.join(format!("slykey.{ext}")))
+        .chain(
+            CONFIG_EXTENSIONS
+                .iter()
+                .map(|ext| config_dir.join(format!("config.{ext}"))),
+        )
+        .collect();
+
+    if let Some(found) = candidates.iter().find(|path| path.exists()) {
+        return Ok(found.clone());
+    }
+
+    let expected: Vec<String> = candidates
+        .iter()
+        .map(|path| format!("- {}", path.display()))
+        .collect();
+    bail!(
+        "no config file found; expected one of:\n{}\nrun `slykey init` to create a starter config",
+        expected.join("\n")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        append_expansion, append_snippet, close_matches, format_parse_error, levenshtein,
+        remove_entry, resolve_config_relative, write_starter_config, AppConfig, BoundaryPosition,
+        ConfigEditor, ConfigFormat, ExpansionConfig, ExpansionRule, Globals, InjectMode,
+        MatchBehavior, MenuSnippet, NotificationConfig, OutputConfig, PasteConfig, PasteShortcut,
+        PickerConfig, ProfileRule, RemoveTarget, SecurityConfig, SnippetMode, TrayConfig,
+        UnicodeFallback, ValidationWarning, MAX_LABEL_CHARS, STARTER_CONFIG,
+    };
+    use std::collections::{BTreeMap, HashMap};
+    use std::path::{Path, PathBuf};
+
+    fn sample_rule(trigger: &str, expansion: &str) -> ExpansionRule {
+        ExpansionRule {
+            trigger: trigger.to_string(),
+            expansion: expansion.to_string(),
+            ..ExpansionRule::default()
+        }
+    }
+
+    fn temp_config(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slykey-test-config-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&dir).expect("create temp config dir");
+        let path = dir.join("slykey.yaml");
+        std::fs::write(&path, contents).expect("write temp config");
+        path
+    }
+
+    fn sample_snippet(title: &str, content: &str) -> MenuSnippet {
+        MenuSnippet {
+            title: title.to_string(),
+            content: content.to_string(),
+            group: None,
+            separator_after: false,
+            mode: SnippetMode::Clipboard,
+            type_delay_ms: None,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_empty_expansions() {
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![],
+            snippets: vec![],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        let err = cfg.validate().expect_err("empty config should fail");
+        assert!(err.to_string().contains("at least one expansion"));
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_triggers() {
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![sample_rule(";a", "alpha"), sample_rule(";a", "again")],
+            snippets: vec![],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        let err = cfg.validate().expect_err("duplicate trigger should fail");
+        assert!(err.to_string().contains("duplicate trigger"));
+    }
+
+    #[test]
+    fn boundary_chars_uses_default_when_unset() {
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![sample_rule(";a", "alpha")],
+            snippets: vec![],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        assert_eq!(cfg.boundary_chars(), " \t\n.,;:!?)]}>'\"");
+    }
+
+    #[test]
+    fn validate_rejects_empty_snippet_title() {
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![sample_rule(";a", "alpha")],
+            snippets: vec![sample_snippet(" ", "hello")],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        let err = cfg.validate().expect_err("empty snippet title should fail");
+        assert!(err.to_string().contains("snippet title cannot be empty"));
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_snippet_titles() {
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![sample_rule(";a", "alpha")],
+            snippets: vec![
+                sample_snippet("Email", "a@example.com"),
+                sample_snippet("Email", "b@example.com"),
+            ],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        let err = cfg
+            .validate()
+            .expect_err("duplicate snippet title should fail");
+        assert!(err.to_string().contains("duplicate snippet title"));
+    }
+
+    #[test]
+    fn allows_app_matches_wm_class_case_insensitively() {
+        let mut rule = sample_rule(";a", "alpha");
+        rule.apps = vec!["alacritty".to_string()];
+        rule.exclude_apps = vec!["Navigator".to_string()];
+
+        assert!(rule.allows_app(&["Alacritty".to_string(), "Alacritty".to_string()]));
+        assert!(!rule.allows_app(&["Navigator".to_string(), "firefox".to_string()]));
+        assert!(!rule.allows_app(&["kitty".to_string(), "kitty".to_string()]));
+    }
+
+    #[test]
+    fn validate_rejects_empty_app_filter() {
+        let mut rule = sample_rule(";a", "alpha");
+        rule.exclude_apps = vec![" ".to_string()];
+        let cfg = AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![rule],
+            snippets: vec![],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior: MatchBehavior::Immediate,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        };
+
+        let err = cfg.validate().expect_err("empty app filter should fail");
+        assert!(err.to_string().contains("app filter cannot be empty"));
+    }
+
+    #[test]
+    fn append_expansion_adds_rule_and_keeps_other_keys() {
+        let path = temp_config(
+            "append",
+            "watch: true\nexpansions:\n  - trigger: ';a'\n    expansion: alpha\n",
+        );
+
+        append_expansion(&path, ";addr", "123 Main St").expect("append should succeed");
+        append_snippet(&path, "Address", "123 Main St").expect("append should succeed");
+
+        let loaded = AppConfig::load(Some(path.clone())).expect("config should still parse");
+        loaded
+            .config
+            .validate()
+            .expect("config should still validate");
+        assert!(loaded.config.watch);
+        let triggers: Vec<&str> = loaded
+            .config
+            .expansions
+            .iter()
+            .map(|rule| rule.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, vec![";a", ";addr"]);
+        assert_eq!(loaded.config.expansions[1].expansion, "123 Main St");
+        assert_eq!(loaded.config.snippets[0].title, "Address");
+
+        let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+    }
+
+    #[test]
+    fn append_expansion_rejects_duplicate_trigger_without_writing() {
+        let original = "expansions:\n  - trigger: ';a'\n    expansion: alpha\n";
+        let path = temp_config("duplicate", original);
+
+        let err = append_expansion(&path, ";a", "again").expect_err("duplicate should fail");
+
+        assert!(err.to_string().contains("duplicate trigger found: ;a"));
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("config should be readable"),
+            original
+        );
+        let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+    }
+
+    #[test]
+    fn parses_inject_mode_and_paste_settings() {
+        let raw = "inject_mode: clipboard\npaste:\n  shortcut: ctrl_shift_v\nexpansions:\n  - trigger: ';a'\n    expansion: alpha\n  - trigger: ';b'\n    expansion: beta\n    inject_mode: type\n";
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+
+        assert_eq!(config.inject_mode, InjectMode::Clipboard);
+        assert_eq!(config.paste.shortcut, PasteShortcut::CtrlShiftV);
+        assert_eq!(config.paste.restore_delay_ms, 150);
+        assert_eq!(config.expansions[0].inject_mode, None);
+        assert_eq!(config.expansions[1].inject_mode, Some(InjectMode::Type));
+    }
+
+    #[test]
+    fn parses_output_pacing_with_defaults() {
+        let defaults: AppConfig =
+            serde_yaml::from_str("expansions: []\n").expect("config should parse");
+        assert_eq!(defaults.output.char_delay_ms, 1);
+        assert_eq!(defaults.output.chunk_size, 0);
+        assert_eq!(defaults.output.unicode_fallback, UnicodeFallback::Skip);
+        assert_eq!(defaults.output.action_delay_ms, 0);
+
+        let raw = "output:\n  chunk_size: 20\n  char_delay_ms: 10\n  unicode_fallback: paste\n  action_delay_ms: 30\nexpansions:\n  - trigger: ';a'\n    expansion: alpha\n    char_delay_ms: 40\n";
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        assert_eq!(config.output.chunk_size, 20);
+        assert_eq!(config.output.char_delay_ms, 10);
+        assert_eq!(config.output.unicode_fallback, UnicodeFallback::Paste);
+        assert_eq!(config.output.action_delay_ms, 30);
+        assert_eq!(config.expansions[0].char_delay_ms, Some(40));
+    }
+
+    #[test]
+    fn parses_notification_throttle_with_defaults() {
+        let defaults: AppConfig =
+            serde_yaml::from_str("expansions: []\n").expect("config should parse");
+        assert_eq!(defaults.notifications.min_interval_ms, 2000);
+        assert!(defaults.notifications.coalesce);
+
+        let raw = "notifications:\n  on_expansion: true\n  min_interval_ms: 500\n  coalesce: false\nexpansions: []\n";
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        assert!(config.notifications.on_expansion);
+        assert_eq!(config.notifications.min_interval_ms, 500);
+        assert!(!config.notifications.coalesce);
+    }
+
+    #[test]
+    fn parses_tray_edit_command() {
+        let defaults: AppConfig =
+            serde_yaml::from_str("expansions: []\n").expect("config should parse");
+        assert_eq!(defaults.tray.edit_command, None);
+
+        let raw = "tray:\n  edit_command: 'alacritty -e nvim {path}'\nexpansions: []\n";
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        assert_eq!(
+            config.tray.edit_command.as_deref(),
+            Some("alacritty -e nvim {path}")
+        );
+    }
+
+    #[test]
+    fn parses_snippet_groups_and_separators() {
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: alpha\nsnippets:\n  - title: Email\n    content: me@example.com\n    separator_after: true\n  - title: Email\n    content: me@work.example.com\n    group: Work\n";
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+
+        assert_eq!(config.snippets[0].group, None);
+        assert!(config.snippets[0].separator_after);
+        assert_eq!(config.snippets[1].group.as_deref(), Some("Work"));
+        assert!(!config.snippets[1].separator_after);
+        config
+            .validate()
+            .expect("same title in different groups should be valid");
+    }
+
+    #[test]
+    fn parses_typed_snippets_and_checks_their_actions() {
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: alpha\nsnippets:\n  - title: Sign\n    content: 'Best,{{KEY:ENTER}}Tyler'\n    mode: type\n    type_delay_ms: 300\n  - title: Email\n    content: me@example.com\n";
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+
+        assert_eq!(config.snippets[0].mode, SnippetMode::Type);
+        assert_eq!(config.snippets[0].type_delay_ms(), 300);
+        assert_eq!(config.snippets[1].mode, SnippetMode::Clipboard);
+        assert_eq!(config.snippets[1].type_delay_ms(), 600);
+        config
+            .validate_deep()
+            .expect("typed snippet should be valid");
+
+        // Clipboard snippets only render template macros, so an action macro
+        // is only an error once the snippet is typed.
+        config.snippets[1].content = "{{KEY:NOPE}}".to_string();
+        config
+            .validate_deep()
+            .expect("clipboard snippet keeps macros as text");
+        config.snippets[0].content = "{{KEY:NOPE}}".to_string();
+        let err = config.validate_deep().expect_err("bad key should fail");
+        assert!(format!("{err:#}").contains("snippet 'Sign'"), "{err:#}");
+
+        config.snippets[0].content = "ok".to_string();
+        config.snippets[1].type_delay_ms = Some(100);
+        let err = config.validate().expect_err("delay without type mode");
+        assert!(err
+            .to_string()
+            .contains("type_delay_ms is set but mode is not type for snippet: Email"));
+
+        assert!(
+            serde_yaml::from_str::<AppConfig>(&raw.replace("mode: type", "mode: paste")).is_err()
+        );
+    }
+
+    #[test]
+    fn validate_rejects_notify_message_on_silenced_rule() {
+        let mut rule = sample_rule(";x", "x");
+        rule.notify = Some(false);
+        rule.notify_message = Some("ran x".to_string());
+        let mut cfg: AppConfig =
+            serde_yaml::from_str("expansions: []\n").expect("config should parse");
+        cfg.expansions = vec![rule];
+
+        let err = cfg.validate().expect_err("validation should fail");
+        assert!(err
+            .to_string()
+            .contains("notify_message is set but notify is false for trigger: ;x"));
+
+        cfg.expansions[0].notify = Some(true);
+        cfg.validate()
+            .expect("notify_message with notify should be valid");
+    }
+
+    #[test]
+    fn validate_rejects_blank_and_overlong_labels() {
+        let mut cfg: AppConfig =
+            serde_yaml::from_str("expansions: []\n").expect("config should parse");
+        cfg.expansions = vec![sample_rule(";x1", "x")];
+        assert_eq!(cfg.expansions[0].display_name(), ";x1");
+
+        cfg.expansions[0].label = Some("é".repeat(MAX_LABEL_CHARS));
+        cfg.validate()
+            .expect("a label at the limit should be valid");
+        assert_eq!(
+            cfg.expansions[0].display_name(),
+            "é".repeat(MAX_LABEL_CHARS)
+        );
+
+        cfg.expansions[0].label = Some("é".repeat(MAX_LABEL_CHARS + 1));
+        let err = cfg.validate().expect_err("validation should fail");
+        assert!(err
+            .to_string()
+            .contains("label is longer than 120 characters for trigger: ;x1"));
+
+        cfg.expansions[0].label = Some(" ".to_string());
+        let err = cfg.validate().expect_err("validation should fail");
+        assert!(err
+            .to_string()
+            .contains("label cannot be empty for trigger: ;x1"));
+    }
+
+    #[test]
+    fn validate_rejects_duplicate_snippet_titles_within_a_group() {
+        let mut first = sample_snippet("Email", "a@example.com");
+        first.group = Some("Work".to_string());
+        let mut second = sample_snippet("Email", "b@example.com");
+        second.group = Some("Work".to_string());
+        let mut config: AppConfig =
+            serde_yaml::from_str("expansions:\n  - trigger: ';a'\n    expansion: alpha\n")
+                .expect("config should parse");
+        config.snippets = vec![first, second];
+
+        let err = config
+            .validate()
+            .expect_err("duplicate title within a group should fail");
+        assert!(err
+            .to_string()
+            .contains("duplicate snippet title found: Email (group Work)"));
+    }
+
+    #[test]
+    fn parses_picker_settings_and_rejects_a_clashing_hotkey() {
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: alpha\npause_hotkey: ctrl+alt+p\npicker_hotkey: ctrl+alt+space\npicker:\n  command: rofi -dmenu -i\n";
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+
+        assert_eq!(config.picker.command.as_deref(), Some("rofi -dmenu -i"));
+        assert_eq!(config.picker.focus_delay_ms, 150);
+        config.validate().expect("picker config should be valid");
+
+        config.picker_hotkey = Some("alt+ctrl+p".to_string());
+        let err = config
+            .validate()
+            .expect_err("picker hotkey equal to the pause hotkey should fail");
+        assert!(err
+            .to_string()
+            .contains("picker_hotkey and pause_hotkey cannot be the same"));
+
+        config.picker_hotkey = Some("ctrl+nope".to_string());
+        let err = config
+            .validate()
+            .expect_err("bad picker hotkey should fail");
+        assert!(err.to_string().contains("invalid picker_hotkey"));
+    }
+
+    #[test]
+    fn validates_profiles_per_effective_rule_set() {
+        let raw = r#"
+expansions:
+  - trigger: ";g"
+    expansion: hello
+globals:
+  ORG: Home
+default_profile: work
+pause_hotkey: ctrl+alt+p
+profiles:
+  work:
+    hotkey: ctrl+alt+1
+    expansions:
+      - trigger: ";s"
+        expansion: "Regards, {{ORG}}"
+    globals:
+      org: ACME
+  personal:
+    expansions:
+      - trigger: ";s"
+        expansion: Cheers
+"#;
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        config
+            .validate_deep()
+            .expect("profiles may reuse a trigger");
+        assert_eq!(config.profile_names(), vec!["personal", "work"]);
+
+        let work = config.effective(Some("work"));
+        assert_eq!(work.expansions.len(), 2);
+        assert_eq!(
+            work.globals,
+            Globals::from([("org".to_string(), "ACME".to_string())])
+        );
+        assert_eq!(config.effective(None).expansions.len(), 1);
+
+        config
+            .profiles
+            .get_mut("personal")
+            .expect("personal profile")
+            .expansions
+            .push(sample_rule(";g", "shadowed"));
+        let err = config
+            .validate()
+            .expect_err("a profile trigger clashing with a top-level one should fail");
+        assert_eq!(
+            format!("{err:#}"),
+            "invalid profile 'personal': duplicate trigger found: ;g"
+        );
+        config
+            .profiles
+            .get_mut("personal")
+            .expect("personal profile")
+            .expansions
+            .pop();
+
+        config
+            .profiles
+            .get_mut("work")
+            .expect("work profile")
+            .hotkey = Some("alt+ctrl+p".to_string());
+        let err = config.validate().expect_err("clashing hotkey should fail");
+        assert_eq!(
+            err.to_string(),
+            "hotkey of profile 'work' is already used by pause_hotkey"
+        );
+        config
+            .profiles
+            .get_mut("work")
+            .expect("work profile")
+            .hotkey = None;
+
+        config.default_profile = Some("home".to_string());
+        let err = config
+            .validate()
+            .expect_err("unknown default profile should fail");
+        assert_eq!(
+            err.to_string(),
+            "default_profile 'home' is not one of the profiles: personal, work"
+        );
+
+        config.default_profile = Some("work".to_string());
+        config.profile_rules = vec![ProfileRule {
+            app: "Slack".to_string(),
+            profile: "casual".to_string(),
+        }];
+        let err = config
+            .validate()
+            .expect_err("unknown rule profile should fail");
+        assert_eq!(
+            err.to_string(),
+            "profile_rules entry for app 'Slack' names unknown profile 'casual'"
+        );
+        config.profile_rules[0].profile = "personal".to_string();
+        config.validate().expect("known rule profile");
+        assert_eq!(
+            config.profile_for_window(&["slack".to_string(), "Slack".to_string()]),
+            Some("personal")
+        );
+        assert_eq!(config.profile_for_window(&["code".to_string()]), None);
+
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: alpha\nprofiles:\n  work: {}\n  work: {}\n";
+        let err = serde_yaml::from_str::<AppConfig>(raw).expect_err("repeated profile should fail");
+        assert!(err
+            .to_string()
+            .contains("duplicate profile name found: work"));
+    }
+
+    #[test]
+    fn validate_deep_lists_every_broken_macro() {
+        let raw = r#"
+expansions:
+  - trigger: ';ok'
+    expansion: 'fine {{CMD:exit 1}}'
+  - trigger: ';key'
+    expansion: '{{KEZ:ENTER}}'
+  - trigger: ';date'
+    expansion: 'today {{DATE'
+  - trigger: ';again'
+    expansion: '{{EXPAND:;ok}} {{EXPAND:;again}}'
+snippets:
+  - title: Party
+    content: '{{EMOJI:not-a-real-emoji}}'
+globals:
+  A: '{{B}}'
+  B: '{{A}}'
+"#;
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        config
+            .validate()
+            .expect("structural validation should pass");
+
+        let err = config
+            .validate_deep()
+            .expect_err("deep validation should fail")
+            .to_string();
+        assert!(err.starts_with("6 invalid macro(s):"), "{err}");
+        assert!(
+            err.contains("expansion ';key': unsupported macro: 'KEZ'"),
+            "{err}"
+        );
+        assert!(err.contains("expansion ';date': unclosed macro"), "{err}");
+        assert!(
+            err.contains(
+                "expansion ';again': expansion cycle detected: EXPAND:;again -> EXPAND:;again"
+            ),
+            "{err}"
+        );
+        assert!(
+            err.contains("snippet 'Party': unknown emoji shortcode"),
+            "{err}"
+        );
+        assert!(
+            err.contains("global 'A': global macro cycle detected"),
+            "{err}"
+        );
+        assert!(
+            err.contains("global 'B': global macro cycle detected"),
+            "{err}"
+        );
+        assert!(!err.contains(";ok"), "{err}");
+    }
+
+    #[test]
+    fn formats_yaml_errors_with_context_and_caret() {
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: b: c\n";
+        let err = ConfigFormat::Yaml
+            .parse::<AppConfig>(raw)
+            .expect_err("stray colon should fail");
+
+        assert_eq!(
+            err.to_string(),
+            "mapping values are not allowed in this context at line 3 column 17\n\
+             \x20 |\n\
+             1 | expansions:\n\
+             2 |   - trigger: ';a'\n\
+             3 |     expansion: b: c\n\
+             \x20 |                 ^"
+        );
+    }
+
+    #[test]
+    fn caret_follows_tabs_and_wide_line_numbers() {
+        let source = format!("{}\tkey: value\n", "\n".repeat(9));
+        let formatted = format_parse_error(&source, Some((10, 2)), "bad tab");
+
+        assert_eq!(
+            formatted,
+            "bad tab\n   |\n 8 | \n 9 | \n10 | \tkey: value\n   | \t^"
+        );
+    }
+
+    #[test]
+    fn yaml_errors_without_a_usable_location_keep_the_message() {
+        assert_eq!(format_parse_error("a: b\n", None, "boom"), "boom");
+        assert_eq!(format_parse_error("a: b\n", Some((7, 1)), "boom"), "boom");
+    }
+
+    #[test]
+    fn hints_when_expansions_is_written_as_a_map() {
+        let err = ConfigFormat::Yaml
+            .parse::<AppConfig>("expansions:\n  ;sig: Best regards\n")
+            .expect_err("map instead of list should fail");
+        let message = err.to_string();
+
+        assert!(message.starts_with("expansions: invalid type: map, expected a sequence"));
+        assert!(message.contains("2 |   ;sig: Best regards"));
+        assert!(message.ends_with("hint: `expansions` must be a list; start each entry with `- `, e.g. `- trigger: ';sig'`"));
+
+        let err = ConfigFormat::Json
+            .parse::<AppConfig>("{\"expansions\": {\";sig\": \"Best regards\"}}")
+            .expect_err("map instead of list should fail");
+        assert!(err
+            .to_string()
+            .contains("hint: `expansions` must be a list"));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slykey-test-include-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("personal")).expect("create temp include dir");
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).expect("write temp config");
+        path
+    }
+
+    #[test]
+    fn repeated_global_names_fail_to_load_and_order_is_kept() {
+        let dir = temp_dir("duplicate-globals");
+        let path = write(
+            &dir,
+            "slykey.yaml",
+            "globals:\n  GREETING: hi\n  NAME: Tyler\n  GREETING: hello\nexpansions:\n  - trigger: ';g'\n    expansion: '{{GREETING}}'\n",
+        );
+        let err = AppConfig::load(Some(path)).expect_err("repeated global should fail");
+        let message = format!("{err:#}");
+        assert!(
+            message.contains("duplicate global found: GREETING at line 2"),
+            "{message}"
+        );
+
+        let profile = "expansions:\n  - trigger: ';a'\n    expansion: a\nprofiles:\n  work:\n    globals: {ORG: a, ORG: b}\n";
+        let err = serde_yaml::from_str::<AppConfig>(profile).expect_err("repeated profile global");
+        assert!(
+            err.to_string().contains("duplicate global found: ORG"),
+            "{err}"
+        );
+
+        let path = write(
+            &dir,
+            "ordered.yaml",
+            "globals:\n  ZED: z\n  ALPHA: a\n  MID: m\nexpansions:\n  - trigger: ';a'\n    expansion: a\n",
+        );
+        let config = AppConfig::load(Some(path.clone())).expect("load").config;
+        assert_eq!(
+            config.globals.keys().collect::<Vec<_>>(),
+            vec!["ZED", "ALPHA", "MID"]
+        );
+        ConfigEditor::from_config(&config)
+            .expect("from config")
+            .save_atomic(&path)
+            .expect("save");
+        assert!(std::fs::read_to_string(&path)
+            .expect("read")
+            .contains("globals:\n  ZED: z\n  ALPHA: a\n  MID: m\n"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn merges_included_files_in_order() {
+        let dir = temp_dir("merge");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "include: [work.yaml, 'personal/*.yaml', 'missing/*.yaml']\nglobals:\n  NAME: Main\n  SIG: main sig\nexpansions:\n  - trigger: ';main'\n    expansion: main\n",
+        );
+        write(
+            &dir,
+            "work.yaml",
+            "globals:\n  name: Work\nexpansions:\n  - trigger: ';work'\n    expansion: work\n",
+        );
+        write(
+            &dir,
+            "personal/b.yaml",
+            "snippets:\n  - title: Home\n    content: home\n",
+        );
+        write(
+            &dir,
+            "personal/a.yaml",
+            "globals:\n  NAME: Personal\nexpansions:\n  - trigger: ';home'\n    expansion: home\n",
+        );
+        write(&dir, "personal/notes.txt", "not yaml: [");
+
+        let loaded = AppConfig::load(Some(main.clone())).expect("config should load");
+
+        assert_eq!(
+            loaded.paths(),
+            vec![
+                main,
+                dir.join("work.yaml"),
+                dir.join("personal/a.yaml"),
+                dir.join("personal/b.yaml"),
+            ]
+        );
+        let triggers: Vec<&str> = loaded
+            .config
+            .expansions
+            .iter()
+            .map(|rule| rule.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, vec![";main", ";work", ";home"]);
+        assert_eq!(loaded.config.snippets[0].title, "Home");
+        assert_eq!(loaded.config.globals.len(), 2);
+        assert_eq!(loaded.config.globals["NAME"], "Personal");
+        assert_eq!(loaded.config.globals["SIG"], "main sig");
+        loaded
+            .config
+            .validate_deep()
+            .expect("merged config should be valid");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn loads_autocorrect_file_skipping_typos_that_are_triggers() {
+        let dir = temp_dir("autocorrect");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "autocorrect_file: typos.tsv\nexpansions:\n  - trigger: Adn\n    expansion: and then\nprofiles:\n  work:\n    expansions:\n      - trigger: wrk\n        expansion: work\n",
+        );
+        write(
+            &dir,
+            "typos.tsv",
+            "teh\tthe\nadn\tand\nwrk\twork\nteh\tten\n",
+        );
+
+        let loaded = AppConfig::load(Some(main.clone())).expect("config should load");
+
+        assert_eq!(loaded.autocorrect_file, Some(dir.join("typos.tsv")));
+        assert_eq!(loaded.paths(), vec![main.clone(), dir.join("typos.tsv")]);
+        let typos: Vec<&str> = loaded
+            .config
+            .autocorrect
+            .iter()
+            .map(|correction| correction.typo.as_str())
+            .collect();
+        assert_eq!(typos, vec!["teh"]);
+        assert_eq!(
+            loaded.warnings,
+            vec![
+                format!(
+                    "{}:4: typo 'teh' repeats line 1, skipped",
+                    dir.join("typos.tsv").display()
+                ),
+                "autocorrect typo 'adn' is also a trigger, skipped".to_string(),
+                "autocorrect typo 'wrk' is also a trigger, skipped".to_string(),
+            ]
+        );
+
+        write(
+            &dir,
+            "slykey.yaml",
+            "autocorrect_file:\n  path: missing.tsv\n  enabled: false\nexpansions:\n  - trigger: ';a'\n    expansion: a\n",
+        );
+        let loaded = AppConfig::load(Some(main.clone())).expect("disabled file is not read");
+        assert_eq!(loaded.autocorrect_file, None);
+        assert!(loaded.config.autocorrect.is_empty());
+        assert_eq!(
+            serde_yaml::to_string(&loaded.config.autocorrect_file).expect("serializes"),
+            "path: missing.tsv\nenabled: false\n"
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn merges_expansions_dir_files_in_name_order_after_inline_rules() {
+        let dir = temp_dir("expansions-dir");
+        std::fs::create_dir_all(dir.join("rules")).expect("create rules dir");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "expansions_dir: rules\ninclude: [work.yaml]\nexpansions:\n  - trigger: ';main'\n    expansion: main\n",
+        );
+        write(
+            &dir,
+            "work.yaml",
+            "expansions:\n  - trigger: ';work'\n    expansion: work\n",
+        );
+        write(
+            &dir,
+            "rules/b.yaml",
+            "- trigger: ';b1'\n  expansion: b1\n- trigger: ';b2'\n  expansion: b2\n",
+        );
+        write(&dir, "rules/a.yaml", "trigger: ';a'\nexpansion: a\n");
+        write(&dir, "rules/c.yaml", "");
+        write(&dir, "rules/notes.txt", "not yaml: [");
+
+        let loaded = AppConfig::load(Some(main.clone())).expect("config should load");
+
+        let triggers: Vec<&str> = loaded
+            .config
+            .expansions
+            .iter()
+            .map(|rule| rule.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, vec![";main", ";a", ";b1", ";b2", ";work"]);
+        assert_eq!(loaded.expansions_dir, Some(dir.join("rules")));
+        assert_eq!(
+            loaded.paths(),
+            vec![
+                main,
+                dir.join("work.yaml"),
+                dir.join("rules/a.yaml"),
+                dir.join("rules/b.yaml"),
+                dir.join("rules/c.yaml"),
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn rejects_duplicate_triggers_across_expansions_dir_files() {
+        let dir = temp_dir("expansions-dir-duplicate");
+        std::fs::create_dir_all(dir.join("rules")).expect("create rules dir");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "expansions_dir: rules\nexpansions:\n  - trigger: ';main'\n    expansion: main\n",
+        );
+        let first = write(&dir, "rules/a.yaml", "trigger: ';sig'\nexpansion: a\n");
+        let second = write(&dir, "rules/b.yaml", "- trigger: ';sig'\n  expansion: b\n");
+
+        let err = AppConfig::load(Some(main.clone())).expect_err("duplicate should fail");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "duplicate trigger found: ;sig (in {} and {})",
+                first.display(),
+                second.display()
+            )
+        );
+
+        std::fs::write(&second, "trigger: ';main'\nexpansion: b\n").expect("rewrite rule");
+        let err = AppConfig::load(Some(main.clone())).expect_err("duplicate should fail");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "duplicate trigger found: ;main (in {} and {})",
+                main.display(),
+                second.display()
+            )
+        );
+
+        std::fs::remove_dir_all(dir.join("rules")).expect("remove rules dir");
+        let err = AppConfig::load(Some(main)).expect_err("missing dir should fail");
+        assert!(err
+            .to_string()
+            .starts_with("failed to read expansions_dir: "));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn strips_one_trailing_newline_from_block_scalars_at_load() {
+        let dir = temp_dir("trailing-newline");
+        let path = write(
+            &dir,
+            "slykey.yaml",
+            r#"expansions:
+  - trigger: ";clip"
+    expansion: |
+      Thanks,
+      Tyler
+  - trigger: ";strip"
+    expansion: |-
+      Thanks,
+      Tyler
+  - trigger: ";keep"
+    expansion: |+
+      Thanks,
+      Tyler
+
+  - trigger: ";crlf"
+    expansion: "Thanks\r\n\r\n"
+  - trigger: ";intended"
+    expansion: |
+      Thanks,
+    strip_trailing_newline: false
+snippets:
+  - title: Sig
+    content: |
+      Tyler
+profiles:
+  work:
+    expansions:
+      - trigger: ";w"
+        expansion: |
+          Work
+"#,
+        );
+
+        let loaded = AppConfig::load(Some(path.clone())).expect("config should load");
+        let expansions: Vec<&str> = loaded
+            .config
+            .expansions
+            .iter()
+            .map(|rule| rule.expansion.as_str())
+            .collect();
+        assert_eq!(
+            expansions,
+            vec![
+                "Thanks,\nTyler",
+                "Thanks,\nTyler",
+                "Thanks,\nTyler\n",
+                "Thanks\r\n",
+                "Thanks,\n",
+            ]
+        );
+        assert_eq!(loaded.config.snippets[0].content, "Tyler");
+        assert_eq!(
+            loaded.config.profiles["work"].expansions[0].expansion,
+            "Work"
+        );
+
+        let raw = std::fs::read_to_string(&path).expect("read config");
+        std::fs::write(&path, format!("strip_trailing_newline: false\n{raw}"))
+            .expect("write config");
+        let loaded = AppConfig::load(Some(path)).expect("config should load");
+        assert_eq!(loaded.config.expansions[0].expansion, "Thanks,\nTyler\n");
+        assert_eq!(loaded.config.expansions[1].expansion, "Thanks,\nTyler");
+        assert_eq!(loaded.config.snippets[0].content, "Tyler\n");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn expansions_dir_is_relative_to_the_config_or_home() {
+        let base = Path::new("/etc/slykey");
+        let home = Path::new("/home/me");
+        assert_eq!(
+            resolve_config_relative(base, "rules", Some(home)),
+            PathBuf::from("/etc/slykey/rules")
+        );
+        assert_eq!(
+            resolve_config_relative(base, "~/.config/slykey/expansions/", Some(home)),
+            PathBuf::from("/home/me/.config/slykey/expansions/")
+        );
+        assert_eq!(
+            resolve_config_relative(base, "/srv/rules", Some(home)),
+            PathBuf::from("/srv/rules")
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_triggers_across_included_files() {
+        let dir = temp_dir("duplicate");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "include: ['personal/*.yaml']\nexpansions:\n  - trigger: ';sig'\n    expansion: main\n",
+        );
+        let included = write(
+            &dir,
+            "personal/sig.yaml",
+            "expansions:\n  - trigger: ';sig'\n    expansion: personal\n",
+        );
+
+        let err = AppConfig::load(Some(main.clone())).expect_err("duplicate should fail");
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "duplicate trigger found: ;sig (in {} and {})",
+                main.display(),
+                included.display()
+            )
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn included_files_only_accept_rules_snippets_and_globals() {
+        let dir = temp_dir("schema");
+        let main = write(
+            &dir,
+            "slykey.yaml",
+            "include: [extra.yaml]\nexpansions:\n  - trigger: ';a'\n    expansion: a\n",
+        );
+        write(&dir, "extra.yaml", "watch: true\n");
+
+        let err = AppConfig::load(Some(main)).expect_err("unknown key should fail");
+
+        assert!(
+            format!("{err:#}").contains("unknown field `watch`"),
+            "{err:#}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    const ROUND_TRIP_YAML: &str = r#"
+watch: true
+match_behavior: boundary
+pause_hotkey: ctrl+alt+p
+paste:
+  shortcut: shift_insert
+  restore_delay_ms: 90
+notifications:
+  on_expansion: true
+  min_interval_ms: 500
+globals:
+  SIG: "Thanks,{{KEY:ENTER}}Tyler"
+expansions:
+  - trigger: ";sig"
+    expansion: "{{SIG}}"
+    apps: [Alacritty]
+    char_delay_ms: 5
+  - trigger: ";mail"
+    expansion: me@example.com
+    notify: false
+snippets:
+  - title: Email
+    content: me@example.com
+    group: Work
+"#;
+
+    const ROUND_TRIP_TOML: &str = r#"
+watch = true
+match_behavior = "boundary"
+pause_hotkey = "ctrl+alt+p"
+
+[paste]
+shortcut = "shift_insert"
+restore_delay_ms = 90
+
+[notifications]
+on_expansion = true
+min_interval_ms = 500
+
+[globals]
+SIG = "Thanks,{{KEY:ENTER}}Tyler"
+
+[[expansions]]
+trigger = ";sig"
+expansion = "{{SIG}}"
+apps = ["Alacritty"]
+char_delay_ms = 5
+
+[[expansions]]
+trigger = ";mail"
+expansion = "me@example.com"
+notify = false
+
+[[snippets]]
+title = "Email"
+content = "me@example.com"
+group = "Work"
+"#;
+
+    const ROUND_TRIP_JSON: &str = r#"{
+  "watch": true,
+  "match_behavior": "boundary",
+  "pause_hotkey": "ctrl+alt+p",
+  "paste": { "shortcut": "shift_insert", "restore_delay_ms": 90 },
+  "notifications": { "on_expansion": true, "min_interval_ms": 500 },
+  "globals": { "SIG": "Thanks,{{KEY:ENTER}}Tyler" },
+  "expansions": [
+    { "trigger": ";sig", "expansion": "{{SIG}}", "apps": ["Alacritty"], "char_delay_ms": 5 },
+    { "trigger": ";mail", "expansion": "me@example.com", "notify": false }
+  ],
+  "snippets": [{ "title": "Email", "content": "me@example.com", "group": "Work" }]
+}"#;
+
+    #[test]
+    fn loads_equivalent_yaml_toml_and_json_configs() {
+        let dir = temp_dir("formats");
+        let load = |name: &str, contents: &str| {
+            let path = write(&dir, name, contents);
+            let loaded = AppConfig::load(Some(path.clone())).expect("config should load");
+            assert_eq!(loaded.path, path);
+            loaded.config
+        };
+
+        let yaml = load("slykey.yaml", ROUND_TRIP_YAML);
+        let toml = load("slykey.toml", ROUND_TRIP_TOML);
+        let json = load("slykey.json", ROUND_TRIP_JSON);
+
+        assert_eq!(yaml, toml);
+        assert_eq!(yaml, json);
+        assert_eq!(yaml.paste.shortcut, PasteShortcut::ShiftInsert);
+        assert_eq!(yaml.expansions[1].notify, Some(false));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn parse_errors_name_the_attempted_format() {
+        let dir = temp_dir("format-errors");
+        for (name, contents, format) in [
+            ("bad.yaml", "expansions: [", "YAML"),
+            ("bad.toml", "expansions = [", "TOML"),
+            ("bad.json", "{\"expansions\": [", "JSON"),
+        ] {
+            let path = write(&dir, name, contents);
+            let err = AppConfig::load(Some(path.clone())).expect_err("bad config should fail");
+            assert_eq!(
+                err.to_string(),
+                format!("failed to parse {format} config: {}", path.display())
+            );
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn appends_expansions_in_the_config_format() {
+        let dir = temp_dir("append-formats");
+        for (name, contents) in [
+            ("slykey.toml", ROUND_TRIP_TOML),
+            ("slykey.json", ROUND_TRIP_JSON),
+        ] {
+            let path = write(&dir, name, contents);
+            append_expansion(&path, ";addr", "123 Main St").expect("append should succeed");
+
+            let loaded = AppConfig::load(Some(path)).expect("config should still load");
+            let added = loaded.config.expansions.last().expect("appended rule");
+            assert_eq!(added.trigger, ";addr");
+            assert_eq!(added.expansion, "123 Main St");
+            assert_eq!(loaded.config.paste.restore_delay_ms, 90);
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn picks_the_format_from_the_extension() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a/config.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("slykey.JSON")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("slykey.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("/nix/store/abc-slykey-config")),
+            ConfigFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn warns_about_fixed_commands_outside_the_allowlist() {
+        let raw = r#"
+security:
+  command_allowlist: [date, git]
+  confirm_commands: true
+globals:
+  WEATHER: "{{CMD:cache=300:curl wttr.in}}"
+expansions:
+  - trigger: ";d"
+    expansion: "{{CMD:LC_ALL=C date}} {{CMD:git log -1}}"
+  - trigger: ";x"
+    expansion: "{{CMD:rm -rf ~/tmp}} {{CMD:{{WEATHER}}}}"
+snippets:
+  - title: Branch
+    content: "{{CMD:git branch | head -1}}"
+"#;
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+
+        assert!(config.security.allow_commands);
+        assert!(config.security.confirm_commands);
+        assert_eq!(
+            config.command_allowlist_warnings(),
+            vec![
+                "expansion ';x': CMD macro command 'rm' is not in security.command_allowlist (rm -rf ~/tmp)",
+                "snippet 'Branch': CMD macro command 'git branch | head -1' uses '|', which security.command_allowlist does not allow",
+                "global 'WEATHER': CMD macro command 'curl' is not in security.command_allowlist (curl wttr.in)",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_expansion_limits() {
+        let raw = "expansions:\n  - trigger: ';a'\n    expansion: a\nexpansion:\n  max_depth: 4\n";
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        assert_eq!(
+            config.expansion,
+            ExpansionConfig {
+                max_depth: 4,
+                max_output_bytes: 1024 * 1024,
+            }
+        );
+        config.validate().expect("limits are valid");
+
+        config.expansion.max_output_bytes = 0;
+        let err = config.validate().expect_err("zero bytes should fail");
+        assert_eq!(
+            err.to_string(),
+            "expansion.max_output_bytes must be at least 1"
+        );
+    }
+
+    #[test]
+    fn rejects_totp_secrets_that_are_not_base32() {
+        let raw = "expansions:\n  - trigger: ';otp'\n    expansion: '{{TOTP:github}}'\ntotp:\n  github: 'JBSW Y3DP EHPK 3PXP'\n";
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        config.validate().expect("spaced base32 is fine");
+
+        config
+            .totp
+            .insert("gitlab".to_string(), "s3cr3t!".to_string());
+        let err = config.validate().expect_err("invalid base32 should fail");
+        assert_eq!(
+            format!("{err:#}"),
+            "invalid totp secret for account: gitlab: not valid base32"
+        );
+    }
+
+    #[test]
+    fn expands_environment_variables_when_enabled() {
+        let raw = r#"
+expand_env: true
+boundary_chars: " ${SEP}"
+globals:
+  EMAIL: "${WORK_EMAIL}"
+expansions:
+  - trigger: ";p"
+    expansion: "~/projects"
+    notify_message: "sent as ${WORK_EMAIL}"
+  - trigger: ";lit"
+    expansion: "$${WORK_EMAIL} ${MISSING}"
+snippets:
+  - title: Shell
+    content: "${NOPE} ${{DATE}}"
+"#;
+        let mut config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        let lookup = |name: &str| match name {
+            "WORK_EMAIL" => Some("me@work.example.com".to_string()),
+            "SEP" => Some("|".to_string()),
+            _ => None,
+        };
+
+        let missing = config.expand_env_values(&lookup, Some(Path::new("/home/me")));
+
+        assert_eq!(missing, vec!["MISSING", "NOPE"]);
+        assert_eq!(config.boundary_chars.as_deref(), Some(" |"));
+        assert_eq!(config.globals["EMAIL"], "me@work.example.com");
+        assert_eq!(config.expansions[0].expansion, "/home/me/projects");
+        assert_eq!(
+            config.expansions[0].notify_message.as_deref(),
+            Some("sent as me@work.example.com")
+        );
+        assert_eq!(config.expansions[1].expansion, "${WORK_EMAIL} ${MISSING}");
+        assert_eq!(config.snippets[0].content, "${NOPE} ${{DATE}}");
+    }
+
+    #[test]
+    fn leaves_values_alone_without_expand_env() {
+        let dir = temp_dir("no-expand-env");
+        let path = write(
+            &dir,
+            "slykey.yaml",
+            "expansions:\n  - trigger: ';h'\n    expansion: '${HOME} ~/x'\n",
+        );
+
+        let loaded = AppConfig::load(Some(path)).expect("config should load");
+
+        assert_eq!(loaded.config.expansions[0].expansion, "${HOME} ~/x");
+        assert!(loaded.warnings.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn starter_config_parses_and_validates() {
+        let config: AppConfig =
+            serde_yaml::from_str(STARTER_CONFIG).expect("starter config should parse");
+
+        config
+            .validate_deep()
+            .expect("starter config should validate");
+        assert_eq!(config.match_behavior, MatchBehavior::Immediate);
+        assert!(!config.globals.is_empty());
+        assert!(!config.snippets.is_empty());
+        assert_eq!(config.lint(), Vec::new());
+    }
+
+    fn lint(yaml: &str) -> Vec<ValidationWarning> {
+        let config: AppConfig = serde_yaml::from_str(yaml).expect("config");
+        config.validate().expect("lint findings are not errors");
+        config.lint()
+    }
+
+    #[test]
+    fn lint_warns_when_a_shorter_trigger_fires_first_in_immediate_mode() {
+        let warnings = lint(
+            "expansions:\n  - {trigger: 'xab', expansion: a}\n  - {trigger: ab, expansion: b}\n  - {trigger: ';s', expansion: c}\n  - {trigger: ';sig', expansion: d}\n",
+        );
+        assert_eq!(
+            warnings,
+            Vec::new(),
+            "a suffix loses to the longer trigger and a prefix is held back"
+        );
+
+        let warnings = lint(
+            "expansions:\n  - {trigger: 'xabc', expansion: a}\n  - {trigger: ab, expansion: b}\n",
+        );
+        assert_eq!(
+            warnings,
+            vec![ValidationWarning::FiresFirst {
+                trigger: "xabc".to_string(),
+                shadowed_by: "ab".to_string(),
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "trigger 'xabc' never fires: in immediate mode typing it completes 'ab' first, \
+             which expands before 'xabc' is finished"
+        );
+
+        let warnings = lint(
+            "prefer_longest: false\nexpansions:\n  - {trigger: ';s', expansion: a}\n  - {trigger: ';sig', expansion: b}\n  - {trigger: ';x', expansion: c, enabled: false}\n  - {trigger: ';xy', expansion: d}\n",
+        );
+        assert_eq!(
+            warnings,
+            vec![ValidationWarning::FiresFirst {
+                trigger: ";sig".to_string(),
+                shadowed_by: ";s".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn lint_warns_about_a_suffix_listed_first_without_prefer_longest() {
+        let yaml =
+            "expansions:\n  - {trigger: sig, expansion: a}\n  - {trigger: ';sig', expansion: b}\n";
+        assert_eq!(lint(yaml), Vec::new());
+
+        let warnings = lint(&format!("prefer_longest: false\n{yaml}"));
+        assert_eq!(
+            warnings,
+            vec![ValidationWarning::SuffixListedFirst {
+                trigger: ";sig".to_string(),
+                shadowed_by: "sig".to_string(),
+            }]
+        );
+        assert_eq!(warnings[0].trigger(), ";sig");
+    }
+
+    #[test]
+    fn lint_warns_about_boundary_characters_inside_boundary_mode_triggers() {
+        let warnings = lint(
+            "match_behavior: boundary\nexpansions:\n  - {trigger: ';sig', expansion: a}\n  - {trigger: 'e.g.', expansion: b}\n  - {trigger: 'x.y', expansion: c}\n  - {trigger: 'ab.cd', expansion: d}\n  - {trigger: ab, expansion: e}\n",
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                ValidationWarning::BoundaryInTrigger {
+                    trigger: "e.g.".to_string(),
+                    boundary: '.',
+                    shadowed_by: None,
+                },
+                ValidationWarning::BoundaryInTrigger {
+                    trigger: "ab.cd".to_string(),
+                    boundary: '.',
+                    shadowed_by: Some("ab".to_string()),
+                },
+            ]
+        );
+        assert!(warnings[0]
+            .to_string()
+            .contains("second boundary character"));
+        assert!(warnings[1]
+            .to_string()
+            .contains("typing it expands 'ab' before 'ab.cd' is finished"));
+    }
+
+    #[test]
+    fn lint_warns_about_whitespace_only_triggers_in_profiles_once() {
+        let warnings = lint(
+            "expansions:\n  - {trigger: '  ', expansion: a}\nprofiles:\n  work:\n    expansions:\n      - {trigger: \"\\t\", expansion: b}\n  home:\n    expansions:\n      - {trigger: ';h', expansion: c}\n",
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                ValidationWarning::WhitespaceOnly {
+                    trigger: "  ".to_string(),
+                },
+                ValidationWarning::WhitespaceOnly {
+                    trigger: "\t".to_string(),
+                },
+            ]
+        );
+        assert!(warnings[1]
+            .to_string()
+            .starts_with("trigger \"\\t\" consists only"));
+    }
+
+    #[test]
+    fn lint_warns_about_duplicate_labels_and_leads_warnings_with_labels() {
+        let config: AppConfig = serde_yaml::from_str(
+            "expansions:\n  - {trigger: ';x1', expansion: a, label: Support reply}\n  - {trigger: ';x2', expansion: b, label: Support reply}\n  - {trigger: '  ', expansion: c, label: Blank}\n",
+        )
+        .expect("config should parse");
+        let warnings = config.lint();
+        assert_eq!(
+            warnings,
+            vec![
+                ValidationWarning::DuplicateLabel {
+                    trigger: ";x2".to_string(),
+                    label: "Support reply".to_string(),
+                    shared_with: ";x1".to_string(),
+                },
+                ValidationWarning::WhitespaceOnly {
+                    trigger: "  ".to_string(),
+                },
+            ]
+        );
+        assert_eq!(
+            config.describe_warning(&warnings[0]),
+            "trigger ';x2' has the label 'Support reply', like ';x1': notifications and \
+             lists cannot tell them apart"
+        );
+        assert!(config
+            .describe_warning(&warnings[1])
+            .starts_with("Blank: trigger \"  \" consists only"));
+    }
+
+    #[test]
+    fn warns_about_disabled_groups_no_rule_belongs_to() {
+        let dir = temp_dir("disabled-groups");
+        let path = write(
+            &dir,
+            "slykey.yaml",
+            "disabled_groups: [xmas, typo]\nexpansions:\n  - trigger: ';tree'\n    expansion: tree\n    group: xmas\n  - trigger: ';old'\n    expansion: old\n    enabled: false\n",
+        );
+
+        let loaded = AppConfig::load(Some(path)).expect("config should load");
+
+        assert_eq!(
+            loaded.warnings,
+            vec!["disabled_groups lists 'typo', but no expansion is in that group"]
+        );
+        let disabled = loaded.config.disabled_groups.iter().cloned().collect();
+        assert!(!loaded.config.expansions[0].is_active(&disabled));
+        assert!(!loaded.config.expansions[1].is_active(&Default::default()));
+        assert!(loaded.config.expansions[0].is_active(&Default::default()));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn writes_starter_config_without_clobbering() {
+        let dir = temp_dir("init");
+        let path = dir.join("nested/slykey/config.yaml");
+
+        write_starter_config(&path, false).expect("init should create parent dirs");
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read config"),
+            STARTER_CONFIG
+        );
+
+        std::fs::write(&path, "expansions: []\n").expect("edit config");
+        let err = write_starter_config(&path, false).expect_err("existing file should be kept");
+        assert!(err.to_string().contains("use --force to overwrite"));
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read config"),
+            "expansions: []\n"
+        );
+
+        write_starter_config(&path, true).expect("--force should overwrite");
+        let starter = AppConfig::load(Some(path))
+            .expect("written config should load")
+            .config;
+        starter
+            .validate_deep()
+            .expect("written config should validate");
+
+        for name in ["config.toml", "config.json"] {
+            let path = dir.join(name);
+            write_starter_config(&path, false).expect("init should write other formats");
+            let config = AppConfig::load(Some(path))
+                .expect("written config should load")
+                .config;
+            assert_eq!(config, starter, "{name}");
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    const FULL_CONFIG: &str = r#"
+expand_env: false
+watch: true
+match_behavior: boundary
+boundary_chars: " .,"
+prefer_longest: false
+pause_hotkey: ctrl+alt+p
+history_size: 5
+inject_mode: clipboard
+notifications:
+  on_expansion: true
+  min_interval_ms: 10
+paste:
+  shortcut: ctrl_shift_v
+output:
+  char_delay_ms: 3
+  chunk_size: 8
+tray:
+  edit_command: code {path}
+globals:
+  NAME: Tyler
+expansions:
+  - trigger: ";a"
+    expansion: "line one\nline \"two\""
+    apps: [firefox]
+    inject_mode: type
+    char_delay_ms: 2
+    notify: true
+    notify_message: sent {{NAME}}
+  - trigger: ";b"
+    expansion: plain
+    exclude_apps: [code]
+snippets:
+  - title: Email
+    content: me@example.com
+    group: Work
+    separator_after: true
+"#;
+
+    #[test]
+    fn saving_an_unchanged_config_reloads_equal_in_every_format() {
+        let dir = temp_dir("editor-round-trip");
+        let yaml = write(&dir, "slykey.yaml", FULL_CONFIG);
+        let original = AppConfig::load(Some(yaml.clone()))
+            .expect("config should load")
+            .config;
+
+        ConfigEditor::open(&yaml)
+            .expect("open")
+            .save_atomic(&yaml)
+            .expect("save unchanged");
+        let reloaded = AppConfig::load(Some(yaml)).expect("reload").config;
+        assert_eq!(reloaded, original);
+
+        for name in ["converted.toml", "converted.json", "converted.yaml"] {
+            let path = dir.join(name);
+            ConfigEditor::from_config(&original)
+                .expect("from config")
+                .save_atomic(&path)
+                .unwrap_or_else(|err| panic!("save {name}: {err:#}"));
+            ConfigEditor::open(&path)
+                .expect("open converted")
+                .save_atomic(&path)
+                .expect("save converted unchanged");
+            let converted = AppConfig::load(Some(path)).expect("load converted").config;
+            assert_eq!(converted, original, "{name}");
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn serializing_skips_defaulted_fields() {
+        let config: AppConfig = serde_yaml::from_str(
+            "expansions:\n  - trigger: ';a'\n    expansion: alpha\nprefer_longest: true\n",
+        )
+        .expect("config");
+
+        assert_eq!(
+            serde_yaml::to_string(&config).expect("serialize"),
+            "expansions:\n- trigger: ;a\n  expansion: alpha\n"
+        );
+    }
+
+    #[test]
+    fn config_editor_adds_removes_and_sets_globals() {
+        let dir = temp_dir("editor-edits");
+        let path = write(
+            &dir,
+            "slykey.json",
+            r#"{"x-note": "kept", "globals": {"name": "old"}, "expansions": [{"trigger": ";a", "expansion": "alpha"}]}"#,
+        );
+        let mut editor = ConfigEditor::open(&path).expect("open");
+
+        editor
+            .add_rule(&sample_rule(";b", "beta"))
+            .expect("add new rule");
+        let err = editor
+            .add_rule(&sample_rule(";b", "again"))
+            .expect_err("duplicate trigger");
+        assert!(err.to_string().contains("duplicate trigger found: ;b"));
+        assert_eq!(editor.remove_rule(";a").expect("remove").len(), 1);
+        assert!(editor
+            .remove_rule(";missing")
+            .expect("remove missing")
+            .is_empty());
+        editor.set_global("NAME", "new").expect("set global");
+        editor.save_atomic(&path).expect("save");
+
+        let config = AppConfig::load(Some(path.clone())).expect("reload").config;
+        assert_eq!(config.expansions, vec![sample_rule(";b", "beta")]);
+        assert_eq!(
+            config.globals,
+            Globals::from([("NAME".to_string(), "new".to_string())])
+        );
+        let raw = std::fs::read_to_string(&path).expect("read saved");
+        assert!(raw.contains("\"x-note\": \"kept\""), "{raw}");
+
+        assert_eq!(editor.remove_rule(";b").expect("remove last").len(), 1);
+        let err = editor
+            .save_atomic(&path)
+            .expect_err("a config without expansions is invalid");
+        assert!(format!("{err:#}").contains("at least one expansion"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    const REMOVE_CONFIG: &str = r#"
+expansions:
+  - trigger: ';old'
+    expansion: old text
+  - trigger: ';keep'
+    expansion: kept
+snippets:
+  - title: Email
+    content: me@example.com
+profiles:
+  work:
+    expansions:
+      - trigger: ';old'
+        expansion: work old text
+"#;
+
+    #[test]
+    fn remove_entry_removes_triggers_and_snippets_everywhere() {
+        let dir = temp_dir("remove");
+        let path = write(&dir, "slykey.yaml", REMOVE_CONFIG);
+
+        let removed = remove_entry(
+            &path,
+            &RemoveTarget::Trigger(";old".to_string()),
+            false,
+            true,
+        )
+        .expect("dry run");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read"),
+            REMOVE_CONFIG,
+            "a dry run leaves the file alone"
+        );
+
+        remove_entry(
+            &path,
+            &RemoveTarget::Trigger(";old".to_string()),
+            false,
+            false,
+        )
+        .expect("remove trigger");
+        remove_entry(
+            &path,
+            &RemoveTarget::SnippetTitle("Email".to_string()),
+            false,
+            false,
+        )
+        .expect("remove snippet");
+
+        let config = AppConfig::load(Some(path)).expect("reload").config;
+        assert_eq!(config.expansions, vec![sample_rule(";keep", "kept")]);
+        assert!(config.snippets.is_empty());
+        assert!(config.profiles["work"].expansions.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn remove_entry_names_the_included_file_defining_the_rule() {
+        let dir = temp_dir("remove-included");
+        let path = write(
+            &dir,
+            "slykey.yaml",
+            "include: [extra.yaml]\nexpansions_dir: rules\nexpansions:\n  - trigger: ';keep'\n    expansion: kept\n",
+        );
+        let extra = write(
+            &dir,
+            "extra.yaml",
+            "snippets:\n  - title: Email\n    content: me@example.com\n",
+        );
+        std::fs::create_dir_all(dir.join("rules")).expect("create rules dir");
+        let rules = write(
+            &dir.join("rules"),
+            "addr.yaml",
+            "trigger: ';addr'\nexpansion: 1 Main St\n",
+        );
+
+        let err = remove_entry(
+            &path,
+            &RemoveTarget::Trigger(";addr".to_string()),
+            false,
+            false,
+        )
+        .expect_err("rule lives in expansions_dir");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "the expansion with trigger ';addr' is defined in {}, not in {}; remove it there",
+                rules.display(),
+                path.display()
+            )
+        );
+        let err = remove_entry(
+            &path,
+            &RemoveTarget::SnippetTitle("Email".to_string()),
+            false,
+            false,
+        )
+        .expect_err("snippet lives in an include");
+        assert!(
+            err.to_string()
+                .contains(&format!("is defined in {}", extra.display())),
+            "{err}"
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn remove_entry_suggests_close_matches() {
+        let dir = temp_dir("remove-missing");
+        let path = write(&dir, "slykey.yaml", REMOVE_CONFIG);
+
+        let err = remove_entry(
+            &path,
+            &RemoveTarget::Trigger(";odl".to_string()),
+            false,
+            false,
+        )
+        .expect_err("unknown trigger");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "no expansion with trigger ';odl' in {}\ndid you mean: ;old",
+                path.display()
+            )
+        );
+        let err = remove_entry(
+            &path,
+            &RemoveTarget::SnippetTitle("em".to_string()),
+            false,
+            false,
+        )
+        .expect_err("unknown title");
+        assert!(err.to_string().ends_with("did you mean: Email"), "{err}");
+        let err = remove_entry(
+            &path,
+            &RemoveTarget::Trigger(";zzzzzz".to_string()),
+            false,
+            false,
+        )
+        .expect_err("unknown trigger");
+        assert!(!err.to_string().contains("did you mean"), "{err}");
+        assert_eq!(std::fs::read_to_string(&path).expect("read"), REMOVE_CONFIG);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn remove_entry_writes_an_empty_config_only_with_force_empty() {
+        let dir = temp_dir("remove-last");
+        let source = write(
+            &dir,
+            "source.yaml",
+            "expansions:\n  - trigger: ';a'\n    expansion: alpha\n",
+        );
+        for name in ["slykey.yaml", "slykey.toml", "slykey.json"] {
+            let path = dir.join(name);
+            ConfigEditor::open(&source)
+                .expect("open")
+                .save_atomic(&path)
+                .expect("convert");
+            let before = std::fs::read_to_string(&path).expect("read");
+            let target = RemoveTarget::Trigger(";a".to_string());
+
+            let err = remove_entry(&path, &target, false, false).expect_err("last expansion");
+            assert!(err.to_string().contains("--force-empty"), "{err}");
+            assert_eq!(std::fs::read_to_string(&path).expect("read"), before);
+
+            remove_entry(&path, &target, true, false).expect("forced");
+            let raw = std::fs::read_to_string(&path).expect("read forced");
+            assert!(!raw.contains(";a"), "{name}: {raw}");
+            assert_eq!(
+                raw.contains("# No expansions are left"),
+                name != "slykey.json"
+            );
+            let config = ConfigEditor::open(&path)
+                .expect("reopen")
+                .parsed()
+                .expect("parse");
+            assert!(!config.has_expansions(), "{name}");
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn close_matches_prefers_typos_and_prefixes() {
+        let candidates = [";addr", ";adr", ";sig", ";signature"];
+        assert_eq!(close_matches(";adrr", &candidates), vec![";addr", ";adr"]);
+        assert_eq!(
+            close_matches(";SIG", &candidates),
+            vec![";sig", ";signature"]
+        );
+        assert!(close_matches(";xyz", &candidates).is_empty());
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_atomic_leaves_the_original_when_the_write_fails() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("editor-readonly");
+        let original = "expansions:\n  - trigger: ';a'\n    expansion: alpha\n";
+        let path = write(&dir, "slykey.yaml", original);
+        let mut editor = ConfigEditor::open(&path).expect("open");
+        editor
+            .add_rule(&sample_rule(";b", "beta"))
+            .expect("add rule");
+
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555))
+            .expect("make dir read-only");
+        let probe = dir.join("probe");
+        if std::fs::write(&probe, "").is_ok() {
+            // Permissions are not enforced (e.g. running as root); block the
+            // temp file with a directory instead.
+            let _ = std::fs::remove_file(&probe);
+            std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))
+                .expect("restore permissions");
+            std::fs::create_dir(dir.join(".slykey.yaml.tmp")).expect("block temp file");
+        }
+
+        editor
+            .save_atomic(&path)
+            .expect_err("write into read-only dir should fail");
+
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("read original"),
+            original
+        );
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755))
+            .expect("restore permissions");
+        let _ = std::fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+
+/// One `typo<TAB>correction` line of an `autocorrect_file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    /// Lowercased; typed words match it case-insensitively.
+    pub typo: String,
+    pub replacement: String,
+}
+
+/// Corrections read from an `autocorrect_file`, plus warnings about the
+/// lines that were skipped.
+#[derive(Debug, Default)]
+pub struct Dictionary {
+    pub corrections: Vec<Correction>,
+    pub warnings: Vec<String>,
+}
+
+/// Reads `path` line by line, see [`parse_dictionary`].
+pub fn read_dictionary(path: &Path) -> Result<Dictionary> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to read autocorrect_file: {}", path.display()))?;
+    parse_dictionary(BufReader::new(file), &path.display().to_string())
+}
+
+/// Parses `typo<TAB>correction` lines; everything after the first tab is the
+/// correction, further tabs included. Blank lines and lines starting with `#`
+/// are skipped, as are repeated typos (compared case-insensitively), with a
+/// warning. Errors and warnings name `source` and the line number.
+pub fn parse_dictionary(reader: impl BufRead, source: &str) -> Result<Dictionary> {
+    let mut dictionary = Dictionary::default();
+    let mut first_lines: HashMap<String, usize> = HashMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let number = index + 1;
+        let line = line.with_context(|| format!("failed to read {source}:{number}"))?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((typo, replacement)) = line.split_once('\t') else {
+            bail!("{source}:{number}: expected a typo and its correction separated by a tab");
+        };
+        if typo.is_empty() || typo.contains(char::is_whitespace) {
+            bail!("{source}:{number}: typo must be a single word, got {typo:?}");
+        }
+        if replacement.is_empty() {
+            bail!("{source}:{number}: correction for '{typo}' is empty");
+        }
+        let typo = typo.to_lowercase();
+        if let Some(first) = first_lines.get(&typo) {
+            dictionary.warnings.push(format!(
+                "{source}:{number}: typo '{typo}' repeats line {first}, skipped"
+            ));
+            continue;
+        }
+        first_lines.insert(typo.clone(), number);
+        dictionary.corrections.push(Correction {
+            typo,
+            replacement: replacement.to_string(),
+        });
+    }
+    Ok(dictionary)
+}
+
+/// `replacement` in the case `typed` was written in: all caps for a typed
+/// word of several capitals, a leading capital for a capitalized one, and
+/// as written otherwise.
+pub fn propagate_case(typed: &str, replacement: &str) -> String {
+    let mut letters = typed.chars().filter(|c| c.is_alphabetic());
+    let Some(first) = letters.next() else {
+        return replacement.to_string();
+    };
+    let rest: Vec<char> = letters.collect();
+    if !first.is_uppercase() {
+        return replacement.to_string();
+    }
+    if !rest.is_empty() && rest.iter().all(|c| c.is_uppercase()) {
+        return replacement.to_uppercase();
+    }
+    let mut chars = replacement.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_dictionary, propagate_case, Correction};
+
+    fn correction(typo: &str, replacement: &str) -> Correction {
+        Correction {
+            typo: typo.to_string(),
+            replacement: replacement.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_tab_separated_lines_skipping_comments_and_repeats() {
+        let raw =
+            "# common typos\r\nteh\tthe\r\n\r\n  \nTEH\tthe again\nadn\tand\t&\nrecieve\treceive\n";
+
+        let dictionary = parse_dictionary(raw.as_bytes(), "typos.tsv").expect("should parse");
+
+        assert_eq!(
+            dictionary.corrections,
+            vec![
+                correction("teh", "the"),
+                correction("adn", "and\t&"),
+                correction("recieve", "receive"),
+            ]
+        );
+        assert_eq!(
+            dictionary.warnings,
+            vec!["typos.tsv:5: typo 'teh' repeats line 2, skipped"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_lines_with_their_number() {
+        for (raw, message) in [
+            (
+                "teh the\n",
+                "typos.tsv:1: expected a typo and its correction",
+            ),
+            (
+                "teh\tthe\n\tthe\n",
+                "typos.tsv:2: typo must be a single word",
+            ),
+            (
+                "a b\tab\n",
+                "typos.tsv:1: typo must be a single word, got \"a b\"",
+            ),
+            ("teh\t\r\n", "typos.tsv:1: correction for 'teh' is empty"),
+        ] {
+            let err = parse_dictionary(raw.as_bytes(), "typos.tsv").expect_err(raw);
+            assert!(err.to_string().starts_with(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn corrections_follow_the_case_of_the_typed_word() {
+        assert_eq!(propagate_case("teh", "the"), "the");
+        assert_eq!(propagate_case("Teh", "the"), "The");
+        assert_eq!(propagate_case("TEH", "the"), "THE");
+        assert_eq!(propagate_case("i", "I"), "I");
+        assert_eq!(propagate_case("I", "I'm"), "I'm");
+        assert_eq!(propagate_case("Dont", "don't"), "Don't");
+        assert_eq!(propagate_case("2nd", "second"), "second");
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::config::{AppConfig, ExpansionRule};
+use crate::core::engine::Engine;
+use crate::io::events::{KeyEvent, KeyEventKind};
+use crate::io::output::NullSink;
+
+/// Share of keystrokes that start typing a whole configured trigger, so the
+/// stream fires expansions instead of only missing.
+const TRIGGER_PERCENT: u64 = 5;
+const SEED: u64 = 0x5eed;
+
+/// Result of `slykey bench`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchReport {
+    pub rules: usize,
+    pub keystrokes: usize,
+    pub expansions: u64,
+    pub elapsed_ms: f64,
+    pub keystrokes_per_sec: f64,
+    pub p50_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+/// Small deterministic generator so runs are comparable between builds; the
+/// export tests use it too.
+pub(crate) struct Lcg(pub(crate) u64);
+
+impl Lcg {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    pub(crate) fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn letter(&mut self) -> char {
+        char::from(b'a' + self.below(26) as u8)
+    }
+}
+
+/// `rules` distinct triggers: `;`, a few random letters and the rule number.
+fn synthetic_rules(rules: usize, rng: &mut Lcg) -> Vec<ExpansionRule> {
+    (0..rules)
+        .map(|index| {
+            let letters: String = (0..2 + rng.below(5)).map(|_| rng.letter()).collect();
+            ExpansionRule {
+                trigger: format!(";{letters}{index}"),
+                expansion: format!("expansion {index}"),
+                ..ExpansionRule::default()
+            }
+        })
+        .collect()
+}
+
+/// `keystrokes` characters of random words, spaces and complete triggers.
+fn synthetic_keystrokes(keystrokes: usize, rules: &[ExpansionRule], rng: &mut Lcg) -> Vec<char> {
+    let mut chars = Vec::with_capacity(keystrokes);
+    while chars.len() < keystrokes {
+        if !rules.is_empty() && rng.next() % 100 < TRIGGER_PERCENT {
+            chars.extend(rules[rng.below(rules.len())].trigger.chars());
+        } else if rng.below(6) == 0 {
+            chars.push(' ');
+        } else {
+            chars.push(rng.letter());
+        }
+    }
+    chars.truncate(keystrokes);
+    chars
+}
+
+/// Streams `keystrokes` synthetic key presses through an engine with
+/// `rules` synthetic rules and output going nowhere, timing each
+/// [`Engine::handle_event`] call.
+pub fn run_bench(rules: usize, keystrokes: usize) -> Result<BenchReport> {
+    let mut rng = Lcg(SEED);
+    let mut config: AppConfig =
+        serde_yaml::from_str("expansions: []").context("failed to build bench config")?;
+    config.expansions = synthetic_rules(rules, &mut rng);
+    let keys = synthetic_keystrokes(keystrokes, &config.expansions, &mut rng);
+
+    let mut engine = Engine::new(config);
+    engine.set_output(Arc::new(NullSink));
+    let mut latencies = Vec::with_capacity(keys.len());
+    let started = Instant::now();
+    for c in keys {
+        let event = KeyEvent {
+            kind: KeyEventKind::Press,
+            text: Some(c.to_string()),
+            special: None,
+            is_injected: false,
+        };
+        let event_started = Instant::now();
+        engine.handle_event(event)?;
+        latencies.push(event_started.elapsed());
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    Ok(BenchReport {
+        rules,
+        keystrokes,
+        expansions: engine.stats().totals().count,
+        elapsed_ms: elapsed.as_secs_f64() * 1000.0,
+        keystrokes_per_sec: if elapsed.is_zero() {
+            0.0
+        } else {
+            keystrokes as f64 / elapsed.as_secs_f64()
+        },
+        p50_us: percentile_us(&latencies, 50),
+        p99_us: percentile_us(&latencies, 99),
+        max_us: latencies.last().map_or(0.0, |max| micros(*max)),
+    })
+}
+
+/// Nearest-rank percentile of sorted `latencies`, in microseconds.
+fn percentile_us(latencies: &[Duration], percent: usize) -> f64 {
+    if latencies.is_empty() {
+        return 0.0;
+    }
+    let rank = (latencies.len() * percent).div_ceil(100).max(1);
+    micros(latencies[rank - 1])
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_nanos() as f64 / 1000.0
+}
+
+pub fn format_report(report: &BenchReport) -> String {
+    format!(
+        "rules:       {}\n\
+         keystrokes:  {} ({} expansions) in {:.1}ms\n\
+         throughput:  {:.0} keystrokes/s\n\
+         latency:     p50 {:.1}µs, p99 {:.1}µs, max {:.1}µs\n",
+        report.rules,
+        report.keystrokes,
+        report.expansions,
+        report.elapsed_ms,
+        report.keystrokes_per_sec,
+        report.p50_us,
+        report.p99_us,
+        report.max_us
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{format_report, percentile_us, run_bench};
+
+    #[test]
+    fn bench_runs_with_tiny_parameters() {
+        let report = run_bench(20, 2_000).expect("bench");
+
+        assert_eq!(report.rules, 20);
+        assert_eq!(report.keystrokes, 2_000);
+        assert!(report.expansions > 0, "{report:?}");
+        assert!(report.keystrokes_per_sec > 0.0, "{report:?}");
+        assert!(report.p50_us <= report.p99_us && report.p99_us <= report.max_us);
+        assert!(format_report(&report).contains("keystrokes/s"));
+
+        let empty = run_bench(0, 0).expect("empty bench");
+        assert_eq!(empty.expansions, 0);
+        assert_eq!(empty.max_us, 0.0);
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_micros).collect();
+        assert_eq!(percentile_us(&latencies, 50), 50.0);
+        assert_eq!(percentile_us(&latencies, 99), 99.0);
+        assert_eq!(percentile_us(&latencies[..1], 99), 1.0);
+        assert_eq!(percentile_us(&[], 99), 0.0);
+    }
+}
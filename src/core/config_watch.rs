@@ -0,0 +1,596 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::warn;
+
+use crate::config::LoadedConfig;
+
+/// Quiet period after the last filesystem event before a reload fires.
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Something that may require the config to be reloaded.
+#[derive(Debug)]
+pub enum WatchInput {
+    /// A filesystem event from a watched config directory.
+    Fs(Event),
+    /// An explicit reload request (SIGHUP).
+    Signal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadReason {
+    FileChanged,
+    Signal,
+}
+
+/// Turns watcher inputs into reload decisions: bursts of events touching a
+/// config file collapse into one reload once `debounce` passes without another
+/// event, while a signal reloads on the next poll.
+pub struct ReloadScheduler {
+    /// Config files as events from their watched directory name them.
+    files: HashSet<PathBuf>,
+    debounce: Duration,
+    last_file_event: Option<Instant>,
+    signaled: bool,
+}
+
+impl ReloadScheduler {
+    pub fn new(config_path: &Path, debounce: Duration) -> Self {
+        let mut scheduler = Self {
+            files: HashSet::new(),
+            debounce,
+            last_file_event: None,
+            signaled: false,
+        };
+        scheduler.set_config_paths(&[config_path.to_path_buf()]);
+        scheduler
+    }
+
+    /// Replaces the files whose changes trigger a reload, e.g. after the
+    /// config's `include` list changed.
+    pub fn set_config_paths(&mut self, paths: &[PathBuf]) {
+        self.files = paths
+            .iter()
+            .filter_map(|path| Some(config_dir(path).join(path.file_name()?)))
+            .collect();
+    }
+
+    pub fn push(&mut self, input: WatchInput, now: Instant) {
+        match input {
+            WatchInput::Fs(event) => {
+                if self.touches_config(&event) {
+                    self.last_file_event = Some(now);
+                }
+            }
+            WatchInput::Signal => self.signaled = true,
+        }
+    }
+
+    /// How long to wait for more input before polling again; `None` when idle.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        if self.signaled {
+            return Some(Duration::ZERO);
+        }
+        self.last_file_event.map(|last| {
+            self.debounce
+                .saturating_sub(now.saturating_duration_since(last))
+        })
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<ReloadReason> {
+        if self.signaled {
+            // A forced reload also covers any file change still settling.
+            self.signaled = false;
+            self.last_file_event = None;
+            return Some(ReloadReason::Signal);
+        }
+
+        let last = self.last_file_event?;
+        if now.saturating_duration_since(last) < self.debounce {
+            return None;
+        }
+        self.last_file_event = None;
+        Some(ReloadReason::FileChanged)
+    }
+
+    /// Editors often save by writing a temp file and renaming it over the
+    /// config, so any create/modify/rename/remove naming a config file counts,
+    /// as does one naming a `*.yaml` file in a watched `expansions_dir`.
+    /// Plain reads (including slykey's own) are ignored.
+    fn touches_config(&self, event: &Event) -> bool {
+        let is_change = match event.kind {
+            EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+            EventKind::Access(_) => false,
+            _ => true,
+        };
+        is_change
+            && event.paths.iter().any(|path| {
+                self.files.contains(path)
+                    || (path.extension().is_some_and(|ext| ext == "yaml")
+                        && path.parent().is_some_and(|dir| self.files.contains(dir)))
+            })
+    }
+}
+
+/// The files a reload depends on: the main config, every include, every
+/// `expansions_dir` file and the `autocorrect_file`, followed by the
+/// `expansions_dir` itself so added and removed rule files are noticed.
+pub fn watched_paths(loaded: &LoadedConfig) -> Vec<PathBuf> {
+    let mut paths = loaded.paths();
+    paths.extend(loaded.expansions_dir.clone());
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+    paths
+}
+
+/// Decides when the running config needs reloading. Requests sent through
+/// [`sender`](Self::sender) (SIGHUP) always reload; with file watching on,
+/// so do changes to any watched file, unless the files read back exactly as
+/// they were after the last reload.
+pub struct ConfigWatcher {
+    sender: Sender<WatchInput>,
+    receiver: Receiver<WatchInput>,
+    scheduler: ReloadScheduler,
+    dir_watcher: Option<ConfigDirWatcher>,
+    paths: Vec<PathBuf>,
+    last_seen_contents: Vec<String>,
+}
+
+impl ConfigWatcher {
+    /// `paths` starts with the main config, as from [`watched_paths`].
+    pub fn new(paths: Vec<PathBuf>, watch_files: bool) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let dir_watcher = if watch_files {
+            Some(ConfigDirWatcher::new(&paths, sender.clone())?)
+        } else {
+            None
+        };
+        let mut scheduler = ReloadScheduler::new(
+            paths.first().map_or(Path::new(""), PathBuf::as_path),
+            DEFAULT_DEBOUNCE,
+        );
+        scheduler.set_config_paths(&paths);
+        Ok(Self {
+            sender,
+            receiver,
+            scheduler,
+            dir_watcher,
+            last_seen_contents: read_files(&paths),
+            paths,
+        })
+    }
+
+    pub fn sender(&self) -> Sender<WatchInput> {
+        self.sender.clone()
+    }
+
+    /// Follows `paths` from now on; call after every reload attempt, as a
+    /// reload can change the includes. Their current contents become the
+    /// baseline for the next file change.
+    pub fn set_paths(&mut self, paths: Vec<PathBuf>) {
+        self.scheduler.set_config_paths(&paths);
+        if let Some(dir_watcher) = &mut self.dir_watcher {
+            if let Err(err) = dir_watcher.watch_paths(&paths) {
+                warn!("{err:#}");
+            }
+        }
+        self.last_seen_contents = read_files(&paths);
+        self.paths = paths;
+    }
+
+    /// Blocks until a reload is due.
+    pub fn next_reload(&mut self) -> ReloadReason {
+        loop {
+            if let Some(reason) = self.wait_for_reload(None) {
+                return reason;
+            }
+        }
+    }
+
+    /// Like [`next_reload`](Self::next_reload), but gives up at `deadline`.
+    fn wait_for_reload(&mut self, deadline: Option<Instant>) -> Option<ReloadReason> {
+        loop {
+            let now = Instant::now();
+            let until_deadline = deadline.map(|deadline| deadline.saturating_duration_since(now));
+            let wait = match (self.scheduler.timeout(now), until_deadline) {
+                (Some(timeout), Some(until_deadline)) => Some(timeout.min(until_deadline)),
+                (timeout, until_deadline) => timeout.or(until_deadline),
+            };
+            // `self.sender` keeps the channel open, so these only stop early
+            // on a timeout.
+            let input = match wait {
+                Some(wait) => self.receiver.recv_timeout(wait).ok(),
+                None => self.receiver.recv().ok(),
+            };
+            if let Some(input) = input {
+                self.scheduler.push(input, Instant::now());
+            }
+
+            if let Some(reason) = self.scheduler.poll(Instant::now()) {
+                if reason == ReloadReason::Signal
+                    || read_files(&self.paths) != self.last_seen_contents
+                {
+                    return Some(reason);
+                }
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return None;
+            }
+        }
+    }
+}
+
+/// What each path reads as; a directory reads as its sorted `*.yaml` file
+/// names, whose contents are compared through their own entries.
+fn read_files(paths: &[PathBuf]) -> Vec<String> {
+    paths
+        .iter()
+        .map(|path| {
+            if path.is_dir() {
+                yaml_file_names(path)
+            } else {
+                std::fs::read_to_string(path).unwrap_or_default()
+            }
+        })
+        .collect()
+}
+
+fn yaml_file_names(dir: &Path) -> String {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return String::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| name.ends_with(".yaml"))
+        .collect();
+    names.sort();
+    names.join("\n")
+}
+
+/// Watches the directories containing the config files (not the files
+/// themselves, so replaced inodes keep being seen), and any watched
+/// directory itself, and forwards events to the sender.
+pub struct ConfigDirWatcher {
+    watcher: RecommendedWatcher,
+    dirs: HashSet<PathBuf>,
+}
+
+impl ConfigDirWatcher {
+    pub fn new(config_paths: &[PathBuf], sender: Sender<WatchInput>) -> Result<Self> {
+        let watcher =
+            notify::recommended_watcher(move |result: notify::Result<Event>| match result {
+                Ok(event) => {
+                    let _ = sender.send(WatchInput::Fs(event));
+                }
+                Err(err) => warn!("config watch error: {err}"),
+            })
+            .context("failed to create config watcher")?;
+        let mut dir_watcher = Self {
+            watcher,
+            dirs: HashSet::new(),
+        };
+        dir_watcher.watch_paths(config_paths)?;
+        Ok(dir_watcher)
+    }
+
+    /// Starts watching the directories of `config_paths` (or a path itself
+    /// when it is a directory) not watched yet.
+    pub fn watch_paths(&mut self, config_paths: &[PathBuf]) -> Result<()> {
+        for path in config_paths {
+            let dir = if path.is_dir() {
+                path.clone()
+            } else {
+                config_dir(path)
+            };
+            if self.dirs.contains(&dir) {
+                continue;
+            }
+            self.watcher
+                .watch(&dir, RecursiveMode::NonRecursive)
+                .with_context(|| format!("failed to watch config directory: {}", dir.display()))?;
+            self.dirs.insert(dir);
+        }
+        Ok(())
+    }
+}
+
+fn config_dir(config_path: &Path) -> PathBuf {
+    match config_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{watched_paths, ConfigWatcher, ReloadReason, ReloadScheduler, WatchInput};
+    use crate::config::AppConfig;
+    use notify::event::{AccessKind, AccessMode, CreateKind, DataChange, ModifyKind, RenameMode};
+    use notify::{Event, EventKind};
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    const DEBOUNCE: Duration = Duration::from_millis(250);
+
+    fn fs_event(kind: EventKind, paths: &[&str]) -> WatchInput {
+        let mut event = Event::new(kind);
+        for path in paths {
+            event = event.add_path(path.into());
+        }
+        WatchInput::Fs(event)
+    }
+
+    fn scheduler() -> ReloadScheduler {
+        ReloadScheduler::new(Path::new("/home/me/.config/slykey/config.yaml"), DEBOUNCE)
+    }
+
+    #[test]
+    fn merges_event_burst_into_one_reload_after_quiet_period() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+
+        scheduler.push(
+            fs_event(modify, &["/home/me/.config/slykey/config.yaml"]),
+            start,
+        );
+        scheduler.push(
+            fs_event(modify, &["/home/me/.config/slykey/config.yaml"]),
+            start + Duration::from_millis(200),
+        );
+
+        assert_eq!(scheduler.poll(start + Duration::from_millis(300)), None);
+        assert_eq!(
+            scheduler.timeout(start + Duration::from_millis(300)),
+            Some(Duration::from_millis(150))
+        );
+        assert_eq!(
+            scheduler.poll(start + Duration::from_millis(450)),
+            Some(ReloadReason::FileChanged)
+        );
+        assert_eq!(scheduler.poll(start + Duration::from_secs(5)), None);
+        assert_eq!(scheduler.timeout(start + Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn detects_editor_rename_over_config() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+
+        scheduler.push(
+            fs_event(
+                EventKind::Create(CreateKind::File),
+                &["/home/me/.config/slykey/.config.yaml.swp"],
+            ),
+            start,
+        );
+        assert_eq!(scheduler.timeout(start), None);
+
+        scheduler.push(
+            fs_event(
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)),
+                &[
+                    "/home/me/.config/slykey/config.yaml.tmp",
+                    "/home/me/.config/slykey/config.yaml",
+                ],
+            ),
+            start,
+        );
+
+        assert_eq!(
+            scheduler.poll(start + DEBOUNCE),
+            Some(ReloadReason::FileChanged)
+        );
+    }
+
+    #[test]
+    fn ignores_reads_of_the_config() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+
+        scheduler.push(
+            fs_event(
+                EventKind::Access(AccessKind::Close(AccessMode::Read)),
+                &["/home/me/.config/slykey/config.yaml"],
+            ),
+            start,
+        );
+        assert_eq!(scheduler.poll(start + DEBOUNCE), None);
+
+        scheduler.push(
+            fs_event(
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                &["/home/me/.config/slykey/config.yaml"],
+            ),
+            start,
+        );
+        assert_eq!(
+            scheduler.poll(start + DEBOUNCE),
+            Some(ReloadReason::FileChanged)
+        );
+    }
+
+    #[test]
+    fn reloads_when_an_included_file_changes() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+        let work = fs_event(modify, &["/home/me/.config/slykey/work.yaml"]);
+
+        scheduler.push(work, start);
+        assert_eq!(scheduler.poll(start + DEBOUNCE), None);
+
+        scheduler.set_config_paths(&[
+            PathBuf::from("/home/me/.config/slykey/config.yaml"),
+            PathBuf::from("/home/me/.config/slykey/work.yaml"),
+        ]);
+        scheduler.push(
+            fs_event(modify, &["/home/me/.config/slykey/work.yaml"]),
+            start,
+        );
+        assert_eq!(
+            scheduler.poll(start + DEBOUNCE),
+            Some(ReloadReason::FileChanged)
+        );
+    }
+
+    #[test]
+    fn signal_reloads_immediately_and_absorbs_pending_change() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+
+        scheduler.push(
+            fs_event(
+                EventKind::Modify(ModifyKind::Any),
+                &["/home/me/.config/slykey/config.yaml"],
+            ),
+            start,
+        );
+        scheduler.push(WatchInput::Signal, start);
+
+        assert_eq!(scheduler.timeout(start), Some(Duration::ZERO));
+        assert_eq!(scheduler.poll(start), Some(ReloadReason::Signal));
+        assert_eq!(scheduler.poll(start + DEBOUNCE), None);
+    }
+
+    #[test]
+    fn ignores_same_named_files_in_other_directories() {
+        let mut scheduler = scheduler();
+        let start = Instant::now();
+
+        scheduler.push(
+            fs_event(
+                EventKind::Modify(ModifyKind::Any),
+                &["/home/me/projects/slykey/config.yaml"],
+            ),
+            start,
+        );
+        assert_eq!(scheduler.poll(start + DEBOUNCE), None);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slykey-test-config-watch-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("parts")).expect("create temp dir");
+        dir
+    }
+
+    #[test]
+    fn watches_the_config_and_its_includes() {
+        let dir = temp_dir("paths");
+        let config_path = dir.join("config.yaml");
+        std::fs::write(
+            &config_path,
+            "include: [\"parts/*.yaml\", \"parts/work.yaml\"]\nexpansions: []\n",
+        )
+        .expect("write config");
+        for name in ["work.yaml", "home.yaml"] {
+            std::fs::write(dir.join("parts").join(name), "expansions: []\n")
+                .expect("write include");
+        }
+
+        let loaded = AppConfig::load(Some(config_path.clone())).expect("load");
+
+        assert_eq!(
+            watched_paths(&loaded),
+            vec![
+                config_path,
+                dir.join("parts").join("home.yaml"),
+                dir.join("parts").join("work.yaml"),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn watches_expansions_dir_files_and_the_dir_itself() {
+        let dir = temp_dir("expansions-dir");
+        let config_path = dir.join("config.yaml");
+        std::fs::write(&config_path, "expansions_dir: parts\n").expect("write config");
+        std::fs::write(
+            dir.join("parts").join("a.yaml"),
+            "trigger: ';a'\nexpansion: a\n",
+        )
+        .expect("write rule file");
+        let loaded = AppConfig::load(Some(config_path.clone())).expect("load");
+        let paths = watched_paths(&loaded);
+        assert_eq!(
+            paths,
+            vec![
+                config_path,
+                dir.join("parts").join("a.yaml"),
+                dir.join("parts"),
+            ]
+        );
+
+        let mut scheduler = ReloadScheduler::new(&paths[0], DEBOUNCE);
+        scheduler.set_config_paths(&paths);
+        let start = Instant::now();
+        let new_file = dir.join("parts").join("b.yaml");
+        scheduler.push(
+            WatchInput::Fs(
+                Event::new(EventKind::Create(CreateKind::File)).add_path(new_file.clone()),
+            ),
+            start,
+        );
+        assert_eq!(
+            scheduler.poll(start + DEBOUNCE),
+            Some(ReloadReason::FileChanged)
+        );
+        scheduler.push(
+            WatchInput::Fs(
+                Event::new(EventKind::Create(CreateKind::File))
+                    .add_path(dir.join("parts").join("notes.txt")),
+            ),
+            start,
+        );
+        assert_eq!(scheduler.poll(start + DEBOUNCE), None);
+
+        let before = super::read_files(&paths);
+        std::fs::write(&new_file, "trigger: ';b'\nexpansion: b\n").expect("add rule file");
+        assert_ne!(super::read_files(&paths), before);
+        std::fs::remove_dir_all(&dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn reloads_on_real_changes_to_watched_files_and_on_signals() {
+        let dir = temp_dir("watcher");
+        let config_path = dir.join("config.yaml");
+        let include_path = dir.join("parts").join("work.yaml");
+        std::fs::write(&config_path, "expansions: []\n").expect("write config");
+        std::fs::write(&include_path, "expansions: []\n").expect("write include");
+        let mut watcher = ConfigWatcher::new(vec![config_path.clone()], false).expect("watcher");
+        watcher.scheduler = ReloadScheduler::new(&config_path, Duration::from_millis(10));
+        let sender = watcher.sender();
+        let modify = |path: &Path| {
+            WatchInput::Fs(Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.into()))
+        };
+        let wait = |watcher: &mut ConfigWatcher| {
+            watcher.wait_for_reload(Some(Instant::now() + Duration::from_millis(100)))
+        };
+
+        sender.send(modify(&config_path)).expect("send");
+        assert_eq!(wait(&mut watcher), None, "contents did not change");
+
+        std::fs::write(&include_path, "expansions: [] # edited\n").expect("edit include");
+        sender.send(modify(&include_path)).expect("send");
+        assert_eq!(wait(&mut watcher), None, "not an include yet");
+
+        watcher.set_paths(vec![config_path.clone(), include_path.clone()]);
+        std::fs::write(&include_path, "expansions: [] # edited again\n").expect("edit include");
+        sender.send(modify(&include_path)).expect("send");
+        assert_eq!(wait(&mut watcher), Some(ReloadReason::FileChanged));
+
+        sender.send(WatchInput::Signal).expect("send");
+        assert_eq!(wait(&mut watcher), Some(ReloadReason::Signal));
+        std::fs::remove_dir_all(&dir).expect("remove temp dir");
+    }
+}
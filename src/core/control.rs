@@ -0,0 +1,461 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::core::engine::Engine;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request sent over the instance control socket, one per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlCommand {
+    Status,
+    /// Like `Status`, answered with a [`DaemonStatus`] as JSON.
+    StatusJson,
+    Pause,
+    Resume,
+    Reload,
+    Stop,
+    /// Asks for the holder's PID, version and config path; answered by the
+    /// instance lock itself.
+    Whoami,
+    EnableGroup(String),
+    DisableGroup(String),
+    /// Switches to the named profile; without a name, reports the active
+    /// profile and lists the others.
+    Profile(Option<String>),
+}
+
+impl ControlCommand {
+    /// Command names are case-insensitive; a group or profile name follows
+    /// after a space.
+    pub fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        let (name, argument) = match line.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, Some(argument.trim())),
+            None => (line, None),
+        };
+        let name = name.to_ascii_lowercase();
+        let command = match (name.as_str(), argument) {
+            ("status", None) => Self::Status,
+            ("status-json", None) => Self::StatusJson,
+            ("pause", None) => Self::Pause,
+            ("resume", None) => Self::Resume,
+            ("reload", None) => Self::Reload,
+            ("stop", None) => Self::Stop,
+            ("whoami", None) => Self::Whoami,
+            ("enable-group", Some(group)) => Self::EnableGroup(group.to_string()),
+            ("disable-group", Some(group)) => Self::DisableGroup(group.to_string()),
+            ("profile", profile) => Self::Profile(profile.map(str::to_string)),
+            ("enable-group" | "disable-group", None) => bail!("{name} needs a group name"),
+            (
+                "status" | "status-json" | "pause" | "resume" | "reload" | "stop" | "whoami",
+                Some(_),
+            ) => {
+                bail!("{name} takes no arguments")
+            }
+            _ => bail!("unknown control command: '{name}'"),
+        };
+        Ok(command)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::StatusJson => "status-json",
+            Self::Pause => "pause",
+            Self::Resume => "resume",
+            Self::Reload => "reload",
+            Self::Stop => "stop",
+            Self::Whoami => "whoami",
+            Self::EnableGroup(_) => "enable-group",
+            Self::DisableGroup(_) => "disable-group",
+            Self::Profile(_) => "profile",
+        }
+    }
+
+    /// The line sent over the socket, without the trailing newline.
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::EnableGroup(group) | Self::DisableGroup(group) => {
+                format!("{} {group}", self.as_str())
+            }
+            Self::Profile(Some(profile)) => format!("{} {profile}", self.as_str()),
+            _ => self.as_str().to_string(),
+        }
+    }
+}
+
+/// Reply to a control command, written back as a single JSON line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControlReply {
+    pub ok: bool,
+    pub message: String,
+}
+
+/// What `slykey status --json` prints for a running daemon. The field
+/// names are relied on by status bar scripts; keep them stable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub running: bool,
+    pub paused: bool,
+    /// Path of the main config file.
+    pub config: String,
+    /// Expansion rules in the active config, profile rules included.
+    pub rules: usize,
+    pub profile: Option<String>,
+    /// Expansions fired today since the daemon started.
+    pub expansions_today: u64,
+    pub version: String,
+}
+
+/// What `slykey status --json` prints when no daemon answers.
+pub const NOT_RUNNING_STATUS_JSON: &str = r#"{"running":false}"#;
+
+impl DaemonStatus {
+    /// Copies the reported values out of `engine`, so the caller can release
+    /// the engine lock before serializing.
+    pub fn from_engine(engine: &Engine, config_path: &Path) -> Self {
+        Self {
+            running: true,
+            paused: engine.is_paused(),
+            config: config_path.display().to_string(),
+            rules: engine.config().expansions.len(),
+            profile: engine.active_profile().map(str::to_string),
+            expansions_today: engine.expansions_today(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// Reads the status out of the reply to [`ControlCommand::StatusJson`].
+    pub fn from_reply(reply: &ControlReply) -> Result<Self> {
+        if !reply.ok {
+            bail!("{}", reply.message);
+        }
+        serde_json::from_str(&reply.message).context("failed to parse status from slykey")
+    }
+}
+
+/// Accepts control connections until a `stop` command has been acknowledged.
+///
+/// Each connection is served on its own thread, so a client that stays
+/// silent does not hold up the others, and may send several newline-delimited
+/// commands; every command gets one reply line. Errors from `handler` are
+/// reported to the client.
+pub fn serve<H>(listener: UnixListener, handler: H)
+where
+    H: Fn(ControlCommand) -> Result<String> + Sync,
+{
+    let stopped = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("control socket accept failed: {err}");
+                    continue;
+                }
+            };
+
+            let (listener, handler, stopped) = (&listener, &handler, &stopped);
+            scope.spawn(move || match handle_connection(stream, handler) {
+                Ok(true) => {
+                    stopped.store(true, Ordering::SeqCst);
+                    wake_accept_loop(listener);
+                }
+                Ok(false) => {}
+                Err(err) => warn!("control connection failed: {err}"),
+            });
+        }
+    });
+}
+
+/// Connects to `listener` so its accept loop notices it should stop.
+fn wake_accept_loop(listener: &UnixListener) {
+    let woken = listener
+        .local_addr()
+        .ok()
+        .and_then(|addr| addr.as_pathname().map(UnixStream::connect));
+    if !matches!(woken, Some(Ok(_))) {
+        warn!("failed to wake the control socket after stop");
+    }
+}
+
+/// Returns true once a successful `stop` reply has been written.
+fn handle_connection<H>(stream: UnixStream, handler: &H) -> Result<bool>
+where
+    H: Fn(ControlCommand) -> Result<String>,
+{
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let command = ControlCommand::parse(&line);
+        let reply = match &command {
+            Ok(command) => match handler(command.clone()) {
+                Ok(message) => ControlReply { ok: true, message },
+                Err(err) => ControlReply {
+                    ok: false,
+                    message: format!("{err:#}"),
+                },
+            },
+            Err(err) => ControlReply {
+                ok: false,
+                message: err.to_string(),
+            },
+        };
+
+        let mut encoded = serde_json::to_string(&reply)?;
+        encoded.push('\n');
+        writer.write_all(encoded.as_bytes())?;
+        writer.flush()?;
+
+        if reply.ok && matches!(command, Ok(ControlCommand::Stop)) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Sends one command to the daemon listening on `socket_path` and waits for its reply.
+pub fn send_command(socket_path: &Path, command: ControlCommand) -> Result<ControlReply> {
+    send_command_with_timeout(socket_path, command, CLIENT_TIMEOUT)
+}
+
+/// Like [`send_command`], giving up when no reply arrives within `timeout`.
+pub fn send_command_with_timeout(
+    socket_path: &Path,
+    command: ControlCommand,
+    timeout: Duration,
+) -> Result<ControlReply> {
+    let stream = UnixStream::connect(socket_path).with_context(|| {
+        format!(
+            "slykey is not running (no control socket at {})",
+            socket_path.display()
+        )
+    })?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    let mut writer = stream.try_clone()?;
+    writer.write_all(format!("{}\n", command.to_line()).as_bytes())?;
+    writer.flush()?;
+
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .context("failed to read reply from slykey")?;
+    if line.trim().is_empty() {
+        bail!("slykey closed the control connection without replying");
+    }
+    serde_json::from_str(&line).context("failed to parse reply from slykey")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send_command, serve, ControlCommand, ControlReply, DaemonStatus};
+    use crate::config::AppConfig;
+    use crate::core::engine::Engine;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    fn test_socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "slykey-test-control-{}-{}.sock",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[test]
+    fn parses_commands_case_insensitively() {
+        assert_eq!(
+            ControlCommand::parse(" Status\n").expect("status should parse"),
+            ControlCommand::Status
+        );
+        assert_eq!(
+            ControlCommand::parse("STOP").expect("stop should parse"),
+            ControlCommand::Stop
+        );
+        for command in [
+            ControlCommand::StatusJson,
+            ControlCommand::Pause,
+            ControlCommand::Resume,
+            ControlCommand::Reload,
+            ControlCommand::Whoami,
+            ControlCommand::EnableGroup("Work Stuff".to_string()),
+            ControlCommand::DisableGroup("xmas".to_string()),
+            ControlCommand::Profile(None),
+            ControlCommand::Profile(Some("work".to_string())),
+        ] {
+            assert_eq!(
+                ControlCommand::parse(&command.to_line()).expect("command should round-trip"),
+                command
+            );
+        }
+        assert_eq!(
+            ControlCommand::parse("DISABLE-GROUP  Xmas ").expect("group name keeps its case"),
+            ControlCommand::DisableGroup("Xmas".to_string())
+        );
+
+        let err = ControlCommand::parse("explode").expect_err("unknown command should fail");
+        assert!(err.to_string().contains("unknown control command"));
+        let err = ControlCommand::parse("enable-group").expect_err("group name is required");
+        assert!(err.to_string().contains("enable-group needs a group name"));
+        let err = ControlCommand::parse("pause now").expect_err("pause takes no arguments");
+        assert!(err.to_string().contains("pause takes no arguments"));
+    }
+
+    #[test]
+    fn serves_commands_until_stop() {
+        let path = test_socket_path("loopback");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("bind control socket");
+
+        let server = std::thread::spawn(move || {
+            let handled = Mutex::new(Vec::new());
+            serve(listener, |command| {
+                handled
+                    .lock()
+                    .expect("mutex poisoned")
+                    .push(command.clone());
+                match command {
+                    ControlCommand::Reload => anyhow::bail!("config is broken"),
+                    _ => Ok(format!("did {}", command.as_str())),
+                }
+            });
+            handled.into_inner().expect("mutex poisoned")
+        });
+
+        assert_eq!(
+            send_command(&path, ControlCommand::Status).expect("status should reply"),
+            ControlReply {
+                ok: true,
+                message: "did status".to_string(),
+            }
+        );
+        let reply = send_command(&path, ControlCommand::Reload).expect("reload should reply");
+        assert!(!reply.ok);
+        assert_eq!(reply.message, "config is broken");
+
+        let mut raw = UnixStream::connect(&path).expect("connect raw client");
+        raw.write_all(b"bogus\n").expect("send bogus command");
+        let mut line = String::new();
+        BufReader::new(raw)
+            .read_line(&mut line)
+            .expect("read bogus reply");
+        assert!(line.contains("unknown control command"));
+
+        send_command(&path, ControlCommand::Stop).expect("stop should reply");
+        let handled = server.join().expect("server thread should exit after stop");
+
+        assert_eq!(
+            handled,
+            vec![
+                ControlCommand::Status,
+                ControlCommand::Reload,
+                ControlCommand::Stop
+            ]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn silent_clients_do_not_hold_up_others() {
+        let path = test_socket_path("silent");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("bind control socket");
+        let server = std::thread::spawn(move || {
+            serve(listener, |command| Ok(format!("did {}", command.as_str())));
+        });
+
+        let silent = UnixStream::connect(&path).expect("connect silent client");
+        let started = std::time::Instant::now();
+        let reply = send_command(&path, ControlCommand::Status).expect("status should reply");
+        assert_eq!(reply.message, "did status");
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        send_command(&path, ControlCommand::Stop).expect("stop should reply");
+        drop(silent);
+        server.join().expect("server thread should exit after stop");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn reports_missing_daemon() {
+        let path = test_socket_path("missing");
+        let _ = std::fs::remove_file(&path);
+
+        let err = send_command(&path, ControlCommand::Status).expect_err("no daemon should fail");
+        assert!(err.to_string().contains("slykey is not running"));
+    }
+
+    #[test]
+    fn status_serializes_with_stable_field_names() {
+        let config: AppConfig = serde_yaml::from_str(
+            "expansions:\n  - trigger: ';a'\n    expansion: A\nprofiles:\n  work:\n    expansions:\n      - trigger: ';w'\n        expansion: W\ndefault_profile: work\n",
+        )
+        .expect("config");
+        let mut engine = Engine::new(config);
+        engine.set_paused(true);
+
+        let status = DaemonStatus::from_engine(&engine, Path::new("/home/me/slykey.yaml"));
+        let json = serde_json::to_string(&status).expect("serialize");
+
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"running":true,"paused":true,"config":"/home/me/slykey.yaml","rules":2,"profile":"work","expansions_today":0,"version":"{}"}}"#,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn parses_status_replies() {
+        let reply = ControlReply {
+            ok: true,
+            message: r#"{"running":true,"paused":false,"config":"/c.yaml","rules":42,"profile":null,"expansions_today":17,"version":"0.3.0"}"#.to_string(),
+        };
+        assert_eq!(
+            DaemonStatus::from_reply(&reply).expect("status"),
+            DaemonStatus {
+                running: true,
+                paused: false,
+                config: "/c.yaml".to_string(),
+                rules: 42,
+                profile: None,
+                expansions_today: 17,
+                version: "0.3.0".to_string(),
+            }
+        );
+
+        let err = DaemonStatus::from_reply(&ControlReply {
+            ok: false,
+            message: "unknown control command: 'status-json'".to_string(),
+        })
+        .expect_err("failed reply");
+        assert!(err.to_string().contains("unknown control command"));
+        assert!(DaemonStatus::from_reply(&ControlReply {
+            ok: true,
+            message: "slykey v0.3.0".to_string(),
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{bail, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, TimeZone};
+
+/// Day and month names for `{{DATE_LOCALE:...}}`; everything else in the
+/// format is left to chrono.
+struct DateNames {
+    code: &'static str,
+    weekdays: [&'static str; 7],
+    weekdays_short: [&'static str; 7],
+    months: [&'static str; 12],
+    months_short: [&'static str; 12],
+}
+
+const ENGLISH_WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+const ENGLISH_WEEKDAYS_SHORT: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const ENGLISH_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const ENGLISH_MONTHS_SHORT: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+const LOCALES: [DateNames; 8] = [
+    DateNames {
+        code: "en-US",
+        weekdays: ENGLISH_WEEKDAYS,
+        weekdays_short: ENGLISH_WEEKDAYS_SHORT,
+        months: ENGLISH_MONTHS,
+        months_short: ENGLISH_MONTHS_SHORT,
+    },
+    DateNames {
+        code: "en-GB",
+        weekdays: ENGLISH_WEEKDAYS,
+        weekdays_short: ENGLISH_WEEKDAYS_SHORT,
+        months: ENGLISH_MONTHS,
+        months_short: ENGLISH_MONTHS_SHORT,
+    },
+    DateNames {
+        code: "de-DE",
+        weekdays: [
+            "Montag",
+            "Dienstag",
+            "Mittwoch",
+            "Donnerstag",
+            "Freitag",
+            "Samstag",
+            "Sonntag",
+        ],
+        weekdays_short: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+        months: [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ],
+        months_short: [
+            "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+        ],
+    },
+    DateNames {
+        code: "fr-FR",
+        weekdays: [
+            "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+        ],
+        weekdays_short: ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+        months: [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ],
+        months_short: [
+            "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+            "nov.", "déc.",
+        ],
+    },
+    DateNames {
+        code: "es-ES",
+        weekdays: [
+            "lunes",
+            "martes",
+            "miércoles",
+            "jueves",
+            "viernes",
+            "sábado",
+            "domingo",
+        ],
+        weekdays_short: ["lun", "mar", "mié", "jue", "vie", "sáb", "dom"],
+        months: [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ],
+        months_short: [
+            "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sept", "oct", "nov", "dic",
+        ],
+    },
+    DateNames {
+        code: "it-IT",
+        weekdays: [
+            "lunedì",
+            "martedì",
+            "mercoledì",
+            "giovedì",
+            "venerdì",
+            "sabato",
+            "domenica",
+        ],
+        weekdays_short: ["lun", "mar", "mer", "gio", "ven", "sab", "dom"],
+        months: [
+            "gennaio",
+            "febbraio",
+            "marzo",
+            "aprile",
+            "maggio",
+            "giugno",
+            "luglio",
+            "agosto",
+            "settembre",
+            "ottobre",
+            "novembre",
+            "dicembre",
+        ],
+        months_short: [
+            "gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic",
+        ],
+    },
+    DateNames {
+        code: "nl-NL",
+        weekdays: [
+            "maandag",
+            "dinsdag",
+            "woensdag",
+            "donderdag",
+            "vrijdag",
+            "zaterdag",
+            "zondag",
+        ],
+        weekdays_short: ["ma", "di", "wo", "do", "vr", "za", "zo"],
+        months: [
+            "januari",
+            "februari",
+            "maart",
+            "april",
+            "mei",
+            "juni",
+            "juli",
+            "augustus",
+            "september",
+            "oktober",
+            "november",
+            "december",
+        ],
+        months_short: [
+            "jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec",
+        ],
+    },
+    DateNames {
+        code: "pt-BR",
+        weekdays: [
+            "segunda-feira",
+            "terça-feira",
+            "quarta-feira",
+            "quinta-feira",
+            "sexta-feira",
+            "sábado",
+            "domingo",
+        ],
+        weekdays_short: ["seg", "ter", "qua", "qui", "sex", "sáb", "dom"],
+        months: [
+            "janeiro",
+            "fevereiro",
+            "março",
+            "abril",
+            "maio",
+            "junho",
+            "julho",
+            "agosto",
+            "setembro",
+            "outubro",
+            "novembro",
+            "dezembro",
+        ],
+        months_short: [
+            "jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez",
+        ],
+    },
+];
+
+/// Renders `{{DATE_LOCALE:<locale>:<format>}}`: `%A`, `%a`, `%B` and `%b`
+/// use the locale's names, every other strftime specifier works as in chrono.
+/// Locale codes ignore case and accept `_` for `-`.
+pub fn format_localized<Tz: TimeZone>(now: &DateTime<Tz>, value: &str) -> Result<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let Some((locale, format)) = value.split_once(':') else {
+        bail!("DATE_LOCALE macro needs a locale and a format, e.g. {{{{DATE_LOCALE:de-DE:%A, %d. %B %Y}}}}");
+    };
+    let locale = locale.trim();
+    let Some(names) = LOCALES
+        .iter()
+        .find(|names| names.code.eq_ignore_ascii_case(&locale.replace('_', "-")))
+    else {
+        bail!(
+            "unsupported locale '{locale}' (supported: {})",
+            LOCALES
+                .iter()
+                .map(|names| names.code)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    };
+
+    let weekday = now.weekday().num_days_from_monday() as usize;
+    let month = now.month0() as usize;
+    let mut translated = String::with_capacity(format.len());
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            translated.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('A') => translated.push_str(names.weekdays[weekday]),
+            Some('a') => translated.push_str(names.weekdays_short[weekday]),
+            Some('B') => translated.push_str(names.months[month]),
+            Some('b') => translated.push_str(names.months_short[month]),
+            Some(other) => {
+                translated.push('%');
+                translated.push(other);
+            }
+            None => translated.push('%'),
+        }
+    }
+    format_checked(now, &translated)
+}
+
+/// Formats `now` with a user-written strftime `format`, failing instead of
+/// panicking on invalid specifiers.
+pub fn format_checked<Tz: TimeZone>(now: &DateTime<Tz>, format: &str) -> Result<String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        bail!("invalid date format '{format}'");
+    }
+    Ok(now.format_with_items(items.into_iter()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_localized;
+    use chrono::{FixedOffset, TimeZone};
+
+    #[test]
+    fn translates_day_and_month_names() {
+        let offset = FixedOffset::east_opt(3600).expect("valid offset");
+        let date = offset
+            .with_ymd_and_hms(2024, 3, 4, 9, 5, 0)
+            .single()
+            .expect("valid date");
+
+        for (value, expected) in [
+            ("de-DE:%A, %d. %B %Y", "Montag, 04. März 2024"),
+            ("fr_fr:%a %d %b %Y %H:%M", "lun. 04 mars 2024 09:05"),
+            ("en-US:%A %B %-d", "Monday March 4"),
+            ("pt-BR:%A, %d de %B", "segunda-feira, 04 de março"),
+            ("de-DE:100%% %a", "100% Mo"),
+        ] {
+            assert_eq!(
+                format_localized(&date, value).expect(value),
+                expected,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_locales_and_formats() {
+        let date = FixedOffset::east_opt(0)
+            .expect("valid offset")
+            .with_ymd_and_hms(2024, 3, 4, 9, 5, 0)
+            .single()
+            .expect("valid date");
+
+        let err = format_localized(&date, "xx-XX:%A").expect_err("unknown locale");
+        assert_eq!(
+            err.to_string(),
+            "unsupported locale 'xx-XX' (supported: en-US, en-GB, de-DE, fr-FR, es-ES, it-IT, nl-NL, pt-BR)"
+        );
+        let err = format_localized(&date, "de-DE:%Q").expect_err("invalid format");
+        assert_eq!(err.to_string(), "invalid date format '%Q'");
+        assert!(format_localized(&date, "de-DE").is_err());
+    }
+}
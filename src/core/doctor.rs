@@ -0,0 +1,558 @@
+use std::fmt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::config::{AppConfig, LoadedConfig};
+use crate::core::expansion::{parse_expansion_actions_with_context, ExpansionContext};
+use crate::core::shell_command::DenyAllRunner;
+
+/// Outcome of one `slykey doctor` check, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Severity {
+    /// Process exit code for a report whose worst check has this severity.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Pass => 0,
+            Self::Warn => 1,
+            Self::Fail => 2,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Pass => "PASS",
+            Self::Warn => "WARN",
+            Self::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub severity: Severity,
+    pub detail: String,
+    /// What to do about a warning or failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    pub fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self::new(name, Severity::Pass, detail.into(), None)
+    }
+
+    pub fn warn(name: &str, detail: impl Into<String>, hint: &str) -> Self {
+        Self::new(name, Severity::Warn, detail.into(), Some(hint))
+    }
+
+    pub fn fail(name: &str, detail: impl Into<String>, hint: &str) -> Self {
+        Self::new(name, Severity::Fail, detail.into(), Some(hint))
+    }
+
+    fn new(name: &str, severity: Severity, detail: String, hint: Option<&str>) -> Self {
+        Self {
+            name: name.to_string(),
+            severity,
+            detail,
+            hint: hint.map(str::to_string),
+        }
+    }
+}
+
+/// Everything `slykey doctor --json` prints.
+#[derive(Debug, Clone, Serialize)]
+pub struct DoctorReport {
+    pub version: &'static str,
+    pub worst: Severity,
+    pub checks: Vec<CheckResult>,
+}
+
+impl DoctorReport {
+    pub fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            worst: worst_severity(&checks),
+            checks,
+        }
+    }
+}
+
+pub fn worst_severity(checks: &[CheckResult]) -> Severity {
+    checks
+        .iter()
+        .map(|check| check.severity)
+        .max()
+        .unwrap_or(Severity::Pass)
+}
+
+/// One line per check, with an indented hint under warnings and failures.
+pub fn format_report(checks: &[CheckResult]) -> String {
+    let mut out = String::new();
+    for check in checks {
+        out.push_str(&format!(
+            "[{}] {}: {}\n",
+            check.severity, check.name, check.detail
+        ));
+        if let Some(hint) = &check.hint {
+            out.push_str(&format!("       hint: {hint}\n"));
+        }
+    }
+    out
+}
+
+/// Config discovery, parsing and validation.
+pub fn check_config(loaded: &Result<LoadedConfig>) -> CheckResult {
+    const NAME: &str = "config";
+    let loaded = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            return CheckResult::fail(
+                NAME,
+                format!("{err:#}"),
+                "create ~/.config/slykey/slykey.yaml or pass --config <PATH>",
+            )
+        }
+    };
+    match loaded.config.validate_deep() {
+        Ok(()) if !loaded.warnings.is_empty() => CheckResult::warn(
+            NAME,
+            format!("{}: {}", loaded.path.display(), loaded.warnings.join("; ")),
+            "export the missing variables in the daemon's environment or escape them as $${VAR}",
+        ),
+        Ok(()) => CheckResult::pass(
+            NAME,
+            format!(
+                "{} ({} expansions)",
+                loaded.path.display(),
+                loaded.config.expansions.len()
+            ),
+        ),
+        Err(err) => CheckResult::fail(
+            NAME,
+            format!("{}: {err:#}", loaded.path.display()),
+            "fix the reported entry, then re-run `slykey validate-config`",
+        ),
+    }
+}
+
+/// Dry-renders every expansion, so broken macros show up before they are
+/// triggered. Nothing runs: `{{CMD:...}}`, secrets and TOTP codes render as
+/// placeholders.
+pub fn check_macros(config: &AppConfig) -> CheckResult {
+    const NAME: &str = "macros";
+    let ctx = ExpansionContext {
+        rules: &config.expansions,
+        dry_run: true,
+        runner: &DenyAllRunner,
+        limits: config.expansion.limits(),
+        ..ExpansionContext::new(&config.globals)
+    };
+    let failures: Vec<String> = config
+        .expansions
+        .iter()
+        .filter_map(|rule| {
+            parse_expansion_actions_with_context(&rule.expansion, &ctx)
+                .err()
+                .map(|err| format!("{}: {err:#}", rule.trigger))
+        })
+        .collect();
+    if failures.is_empty() {
+        return CheckResult::pass(
+            NAME,
+            format!("rendered {} expansions", config.expansions.len()),
+        );
+    }
+    CheckResult::fail(
+        NAME,
+        failures.join("; "),
+        "inspect a rule with `slykey test --trigger <TRIGGER>`",
+    )
+}
+
+/// The kind of graphical session slykey runs in, which decides what its X11
+/// backend can see.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplaySession {
+    /// An X11 session on this display.
+    X11(String),
+    /// A Wayland session whose `DISPLAY` is XWayland: only XWayland windows
+    /// see expansions.
+    XWayland(String),
+    /// A Wayland session with no X display at all.
+    WaylandOnly,
+    /// No display, e.g. a service started outside the graphical session.
+    NoDisplay,
+}
+
+impl DisplaySession {
+    /// Classifies the session from `DISPLAY`, `WAYLAND_DISPLAY` and
+    /// `XDG_SESSION_TYPE`; empty values count as unset. With both displays
+    /// set, `DISPLAY` is XWayland.
+    pub fn classify(
+        display: Option<&str>,
+        wayland_display: Option<&str>,
+        session_type: Option<&str>,
+    ) -> Self {
+        let display = display.filter(|value| !value.is_empty());
+        let wayland = wayland_display.is_some_and(|value| !value.is_empty())
+            || session_type.is_some_and(|value| value.eq_ignore_ascii_case("wayland"));
+        match (display, wayland) {
+            (None, true) => Self::WaylandOnly,
+            (None, false) => Self::NoDisplay,
+            (Some(display), true) => Self::XWayland(display.to_string()),
+            (Some(display), false) => Self::X11(display.to_string()),
+        }
+    }
+
+    /// Why expansions will not work everywhere in this session, if they
+    /// will not: a warning under XWayland, fatal otherwise.
+    pub fn limitation(&self) -> Option<String> {
+        match self {
+            Self::X11(_) => None,
+            Self::XWayland(display) => Some(format!(
+                "this is a Wayland session.\n\
+                 slykey only sees keys typed into XWayland windows (through {display}) and only\n\
+                 types into them; native Wayland windows ignore it, so triggers there do nothing.\n\
+                 Run `slykey doctor` to check your setup, or log into an X11 session."
+            )),
+            Self::WaylandOnly => Some(
+                "this is a Wayland session without DISPLAY.\n\
+                 slykey needs X11: enable XWayland or log into an X11 session, then run\n\
+                 `slykey doctor` to check your setup."
+                    .to_string(),
+            ),
+            Self::NoDisplay => Some(
+                "DISPLAY is not set.\n\
+                 Run slykey inside your graphical session (or import DISPLAY into the service\n\
+                 environment), then run `slykey doctor` to check your setup."
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Whether an X display is available, given `DISPLAY`, `WAYLAND_DISPLAY`
+/// and `XDG_SESSION_TYPE`.
+pub fn check_display(
+    display: Option<&str>,
+    wayland_display: Option<&str>,
+    session_type: Option<&str>,
+) -> CheckResult {
+    const NAME: &str = "display";
+    match DisplaySession::classify(display, wayland_display, session_type) {
+        DisplaySession::WaylandOnly => CheckResult::fail(
+            NAME,
+            "Wayland session without DISPLAY",
+            "slykey needs X11; enable XWayland or log into an X11 session",
+        ),
+        DisplaySession::NoDisplay => CheckResult::fail(
+            NAME,
+            "DISPLAY is not set",
+            "run slykey inside your graphical session (or import DISPLAY into the service environment)",
+        ),
+        DisplaySession::XWayland(display) => CheckResult::warn(
+            NAME,
+            format!("Wayland session, XWayland on {display}"),
+            "only XWayland windows will see expansions; an X11 session works best",
+        ),
+        DisplaySession::X11(display) => CheckResult::pass(NAME, format!("X11 on {display}")),
+    }
+}
+
+/// Reports whether a daemon already answers on the instance lock socket.
+pub fn check_instance_lock(path: &Path) -> CheckResult {
+    const NAME: &str = "instance lock";
+    if !path.exists() {
+        return CheckResult::pass(NAME, "no other instance is running");
+    }
+    if UnixStream::connect(path).is_ok() {
+        return CheckResult::warn(
+            NAME,
+            format!("another instance holds {}", path.display()),
+            "use `slykey status` or `slykey stop` before starting a new one",
+        );
+    }
+    CheckResult::pass(
+        NAME,
+        format!("stale lock {} will be replaced on start", path.display()),
+    )
+}
+
+/// Turns the result of a platform probe into a check: its error becomes
+/// the detail at `severity`.
+pub fn check_probe<T>(
+    name: &str,
+    probe: Result<T>,
+    detail: impl FnOnce(T) -> String,
+    severity: Severity,
+    hint: &str,
+) -> CheckResult {
+    match probe {
+        Ok(value) => CheckResult::pass(name, detail(value)),
+        Err(err) => CheckResult::new(name, severity, format!("{err:#}"), Some(hint)),
+    }
+}
+
+/// Notification server presence from a `GetCapabilities` probe.
+pub fn check_notification_server(capabilities: Result<Vec<String>>) -> CheckResult {
+    const NAME: &str = "notifications";
+    match capabilities {
+        Ok(capabilities) if capabilities.iter().any(|cap| cap == "actions") => {
+            CheckResult::pass(NAME, "org.freedesktop.Notifications supports actions")
+        }
+        Ok(_) => CheckResult::warn(
+            NAME,
+            "org.freedesktop.Notifications does not support actions",
+            "the Undo button needs a server with actions (e.g. dunst, mako, GNOME Shell)",
+        ),
+        Err(err) => CheckResult::warn(
+            NAME,
+            format!("{err:#}"),
+            "install or start a notification daemon (e.g. dunst or mako)",
+        ),
+    }
+}
+
+/// Tray prerequisites: the `tray` feature and a StatusNotifier host.
+pub fn check_tray(compiled: bool, watcher: Result<bool>) -> CheckResult {
+    const NAME: &str = "tray";
+    if !compiled {
+        return CheckResult::warn(
+            NAME,
+            "built without the tray feature",
+            "rebuild with `--features tray` for the tray icon",
+        );
+    }
+    match watcher {
+        Ok(true) => CheckResult::pass(NAME, "StatusNotifierWatcher is running"),
+        Ok(false) => CheckResult::warn(
+            NAME,
+            "no StatusNotifierWatcher on the session bus",
+            "the tray icon needs a StatusNotifier host (e.g. the AppIndicator extension on GNOME)",
+        ),
+        Err(err) => CheckResult::warn(
+            NAME,
+            format!("{err:#}"),
+            "the tray icon needs a reachable D-Bus session",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_config, check_display, check_instance_lock, check_macros, check_notification_server,
+        check_tray, format_report, worst_severity, CheckResult, DisplaySession, DoctorReport,
+        Severity,
+    };
+    use crate::config::{AppConfig, LoadedConfig};
+    use std::path::PathBuf;
+
+    fn config(raw: &str) -> AppConfig {
+        serde_yaml::from_str(raw).expect("config should parse")
+    }
+
+    #[test]
+    fn worst_severity_drives_the_exit_code() {
+        let checks = vec![
+            CheckResult::pass("a", "ok"),
+            CheckResult::warn("b", "meh", "fix b"),
+        ];
+        assert_eq!(worst_severity(&checks), Severity::Warn);
+        assert_eq!(worst_severity(&checks).exit_code(), 1);
+        assert_eq!(worst_severity(&[]).exit_code(), 0);
+
+        let report = DoctorReport::new(vec![CheckResult::fail("c", "broken", "fix c")]);
+        assert_eq!(report.worst.exit_code(), 2);
+    }
+
+    #[test]
+    fn formats_checks_with_hints() {
+        let checks = vec![
+            CheckResult::pass("config", "/tmp/slykey.yaml (2 expansions)"),
+            CheckResult::fail("display", "DISPLAY is not set", "run inside X"),
+        ];
+        assert_eq!(
+            format_report(&checks),
+            "[PASS] config: /tmp/slykey.yaml (2 expansions)\n\
+             [FAIL] display: DISPLAY is not set\n       hint: run inside X\n"
+        );
+    }
+
+    #[test]
+    fn serializes_report_as_json() {
+        let report = DoctorReport::new(vec![
+            CheckResult::pass("config", "ok"),
+            CheckResult::warn("tray", "no watcher", "install one"),
+        ]);
+        let json: serde_json::Value =
+            serde_json::to_value(&report).expect("report should serialize");
+
+        assert_eq!(json["worst"], "warn");
+        assert_eq!(json["checks"][0]["severity"], "pass");
+        assert!(json["checks"][0].get("hint").is_none());
+        assert_eq!(json["checks"][1]["hint"], "install one");
+    }
+
+    #[test]
+    fn config_check_reports_load_and_validation_errors() {
+        let loaded = |raw: &str| {
+            Ok(LoadedConfig {
+                path: PathBuf::from("/tmp/slykey.yaml"),
+                includes: Vec::new(),
+                expansions_dir: None,
+                expansion_files: Vec::new(),
+                autocorrect_file: None,
+                warnings: Vec::new(),
+                config: config(raw),
+            })
+        };
+
+        let valid = check_config(&loaded(
+            "expansions:\n  - trigger: ';a'\n    expansion: a\n",
+        ));
+        assert_eq!(valid.severity, Severity::Pass);
+        assert_eq!(valid.detail, "/tmp/slykey.yaml (1 expansions)");
+
+        assert_eq!(
+            check_config(&loaded("expansions: []\n")).severity,
+            Severity::Fail
+        );
+        assert_eq!(
+            check_config(&Err(anyhow::anyhow!("no config found"))).severity,
+            Severity::Fail
+        );
+    }
+
+    #[test]
+    fn macro_check_names_the_broken_triggers() {
+        let check = check_macros(
+            &config(
+                "expansions:\n  - trigger: ';ok'\n    expansion: fine\n  - trigger: ';cmd'\n    expansion: '{{CMD:false}} {{SECRET:pw}}'\n  - trigger: ';bad'\n    expansion: '{{NOPE}}'\n",
+            ),
+        );
+
+        assert_eq!(check.severity, Severity::Fail);
+        assert!(check.detail.starts_with(";bad: "), "{}", check.detail);
+        assert!(!check.detail.contains(";ok"));
+        assert!(!check.detail.contains(";cmd"), "{}", check.detail);
+    }
+
+    #[test]
+    fn display_check_covers_x11_and_wayland_sessions() {
+        assert_eq!(
+            check_display(Some(":0"), None, Some("x11")).severity,
+            Severity::Pass
+        );
+        assert_eq!(
+            check_display(Some(":0"), Some("wayland-0"), Some("wayland")).severity,
+            Severity::Warn
+        );
+        assert_eq!(
+            check_display(None, Some("wayland-0"), None).severity,
+            Severity::Fail
+        );
+        assert_eq!(check_display(Some(""), None, None).severity, Severity::Fail);
+    }
+
+    #[test]
+    fn classifies_sessions_from_the_display_variables() {
+        let x11 = DisplaySession::X11(":0".to_string());
+        let xwayland = DisplaySession::XWayland(":0".to_string());
+        let cases = [
+            ((Some(":0"), None, None), x11.clone()),
+            ((Some(":0"), None, Some("x11")), x11.clone()),
+            ((Some(":0"), Some(""), Some("tty")), x11),
+            ((Some(":0"), Some("wayland-0"), None), xwayland.clone()),
+            ((Some(":0"), None, Some("Wayland")), xwayland.clone()),
+            ((Some(":0"), Some("wayland-0"), Some("wayland")), xwayland),
+            (
+                (None, Some("wayland-0"), Some("wayland")),
+                DisplaySession::WaylandOnly,
+            ),
+            (
+                (Some(""), None, Some("wayland")),
+                DisplaySession::WaylandOnly,
+            ),
+            ((None, None, None), DisplaySession::NoDisplay),
+            ((Some(""), Some(""), Some("x11")), DisplaySession::NoDisplay),
+        ];
+
+        for ((display, wayland_display, session_type), expected) in cases {
+            assert_eq!(
+                DisplaySession::classify(display, wayland_display, session_type),
+                expected,
+                "{display:?} {wayland_display:?} {session_type:?}"
+            );
+        }
+
+        assert_eq!(DisplaySession::X11(":0".to_string()).limitation(), None);
+        let warning = DisplaySession::XWayland(":1".to_string())
+            .limitation()
+            .expect("XWayland warns");
+        assert!(
+            warning.contains("XWayland windows (through :1)"),
+            "{warning}"
+        );
+        assert!(warning.contains("slykey doctor"), "{warning}");
+        assert!(DisplaySession::WaylandOnly
+            .limitation()
+            .expect("Wayland without DISPLAY fails")
+            .contains("enable XWayland"));
+    }
+
+    #[test]
+    fn lock_check_distinguishes_running_and_stale_instances() {
+        let path = std::env::temp_dir().join(format!(
+            "slykey-test-doctor-lock-{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(check_instance_lock(&path).severity, Severity::Pass);
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).expect("bind lock socket");
+        assert_eq!(check_instance_lock(&path).severity, Severity::Warn);
+
+        drop(listener);
+        let stale = check_instance_lock(&path);
+        assert_eq!(stale.severity, Severity::Pass);
+        assert!(stale.detail.starts_with("stale lock"));
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn notification_and_tray_checks_warn_instead_of_failing() {
+        let caps = |caps: &[&str]| Ok(caps.iter().map(|cap| cap.to_string()).collect());
+
+        assert_eq!(
+            check_notification_server(caps(&["body", "actions"])).severity,
+            Severity::Pass
+        );
+        assert_eq!(
+            check_notification_server(caps(&["body"])).severity,
+            Severity::Warn
+        );
+        assert_eq!(
+            check_notification_server(Err(anyhow::anyhow!("no server"))).severity,
+            Severity::Warn
+        );
+
+        assert_eq!(check_tray(true, Ok(true)).severity, Severity::Pass);
+        assert_eq!(check_tray(true, Ok(false)).severity, Severity::Warn);
+        assert_eq!(check_tray(false, Ok(true)).severity, Severity::Warn);
+    }
+}
@@ -1,73 +1,677 @@
-use std::sync::Arc;
+use std::any::Any;
+use std::collections::{HashSet, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Local};
+use tracing::{debug, debug_span, error, info, warn};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::config::{AppConfig, MatchBehavior};
-use crate::core::expansion::{parse_expansion_actions, OutputAction};
+use crate::config::{
+    AppConfig, BoundaryPosition, ExpansionRule, InjectMode, KeepBoundary, MatchBehavior,
+    ValidationWarning, COMMAND_CONFIRM_WINDOW,
+};
+use crate::core::autocorrect::propagate_case;
+use crate::core::expansion::{
+    apply_newline_mode, parse_expansion_actions_with_context, render_template_macros_with_context,
+    ExpansionContext, OutputAction,
+};
+use crate::core::host_info::SystemInterfaces;
+use crate::core::hotkey::{Hotkey, HotkeyModifiers};
+use crate::core::notification_throttle::NotificationThrottle;
+use crate::core::output_worker::{
+    send_expansion_notification, ExpansionNotice, OutputJob, OutputWorker,
+};
+use crate::core::picker::{self, picker_entries, PickerEntry};
+use crate::core::shell_command::{
+    CommandCache, CommandConfirmation, CommandRunner, ConfirmationRequired, ConfirmingRunner,
+    DenyAllRunner, ShellRunner,
+};
+use crate::core::stats::{DailyCount, UsageStats};
+use crate::core::trigger_index::TriggerIndex;
 use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
-use crate::io::output::{OutputSink, SpecialKey};
-#[cfg(target_os = "linux")]
-use crate::platform::dbus_notification;
+use crate::io::notification::Notifier;
+use crate::io::output::{OutputSink, SinkCapabilities, SpecialKey};
+use crate::io::secrets::SecretStore;
+use crate::io::window::ActiveWindowProvider;
 
+/// Trigger matcher: feed it [`KeyEvent`]s and it emits expansions through the
+/// configured [`OutputSink`].
 pub struct Engine {
+    /// The config as loaded; `config` is its view for the active profile.
+    base_config: AppConfig,
     config: AppConfig,
+    /// Profile `config` was built for: `window_profile` if set, otherwise
+    /// `selected_profile`.
+    active_profile: Option<String>,
+    /// Chosen by `default_profile`, the tray, IPC or a profile hotkey.
+    selected_profile: Option<String>,
+    /// Picked by `profile_rules` for the focused window.
+    window_profile: Option<String>,
+    window_profile_checked: Option<Instant>,
+    /// Last chars of the triggers of every profile; the focused window is
+    /// only checked when the typed text ends with one of them.
+    profile_trigger_ends: HashSet<char>,
+    profile_hotkeys: Vec<(Hotkey, String)>,
+    profile_callback: Option<ProfileCallback>,
     output: Option<Arc<dyn OutputSink>>,
+    output_worker: Option<OutputWorker>,
+    notifier: Option<Arc<dyn Notifier>>,
+    active_window: Option<Arc<dyn ActiveWindowProvider>>,
     typed_buffer: String,
+    trigger_index: TriggerIndex,
     max_trigger_chars: usize,
+    /// Typos of `config.autocorrect`, indexed by their position there.
+    autocorrect_index: TriggerIndex,
+    max_typo_chars: usize,
+    /// Groups switched off, starting from the config's `disabled_groups`.
+    disabled_groups: HashSet<String>,
     active_modifiers: ActiveModifiers,
     pending_expansion: Option<PendingExpansion>,
-    debug: bool,
+    /// Enter was the last key pressed, so the next letter starts a sentence
+    /// for `auto_capitalize`.
+    line_start: bool,
+    /// Source of the times `pending_expansion` is aged by; swapped in tests.
+    clock: Arc<dyn Fn() -> Instant + Send + Sync>,
+    deferred_match: Option<DeferredMatch>,
+    /// Rule last announced by `notifications.on_preview`, until the typed
+    /// text could lead to another trigger.
+    previewed_rule: Option<usize>,
+    pause_hotkey: Option<Hotkey>,
+    paused: Arc<AtomicBool>,
+    picker_hotkey: Option<Hotkey>,
+    picker_callback: Option<PickerCallback>,
+    /// Set from the picker hotkey until [`finish_picker`](Engine::finish_picker);
+    /// keys typed into the picker must not fire expansions.
+    picker_open: bool,
+    command_cache: CommandCache,
+    command_runner: Arc<dyn CommandRunner>,
+    /// `command_runner` behind the config's `security` settings.
+    guarded_runner: Arc<dyn CommandRunner>,
+    /// Set with `security.confirm_commands`.
+    command_confirmation: Option<CommandConfirmation>,
+    secrets: Option<Arc<dyn SecretStore>>,
+    /// Set in dry runs, where what would be typed ends up in the log.
+    mask_secrets: bool,
+    history: VecDeque<ExpansionRecord>,
+    last_undo: Option<UndoRecord>,
+    notification_throttle: NotificationThrottle,
+    stats: UsageStats,
+    stats_dirty: bool,
+    expansions_today: DailyCount,
+    /// Rules the output sink cannot fully handle, checked whenever the
+    /// config or the sink changes.
+    capability_warnings: Vec<ValidationWarning>,
+}
+
+/// Called when the picker hotkey is pressed, with the engine still locked by
+/// the caller of [`Engine::handle_event`]; it should hand off to another
+/// thread that shows the picker and then calls [`Engine::finish_picker`].
+pub type PickerCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Called after the active profile changes, with the new effective config and
+/// profile name, while the engine is still locked.
+pub type ProfileCallback = Arc<dyn Fn(&AppConfig, Option<&str>) + Send + Sync>;
+
+/// How long the focused window's `profile_rules` match is reused before the
+/// window is queried again.
+const WINDOW_PROFILE_TTL: Duration = Duration::from_millis(200);
+
+/// Action key of the "Undo" button on expansion notifications.
+pub const UNDO_ACTION: &str = "undo";
+
+/// A fired expansion, kept in the engine's bounded history.
+#[derive(Debug, Clone)]
+pub struct ExpansionRecord {
+    pub trigger: String,
+    /// Typed text of the expansion (action macros such as keys and sleeps omitted).
+    pub text: String,
+    pub timestamp: DateTime<Local>,
 }
 
 impl Engine {
-    pub fn new(config: AppConfig) -> Self {
-        let max_trigger_chars = config
-            .expansions
-            .iter()
-            .map(|r| r.trigger.chars().count())
-            .max()
-            .unwrap_or(0);
+    /// Starts with the config's `default_profile` active, if any.
+    pub fn new(base_config: AppConfig) -> Self {
+        let selected_profile = base_config.default_profile.clone();
+        let active_profile = selected_profile.clone();
+        let config = base_config.effective(active_profile.as_deref());
+        let profile_hotkeys = parse_profile_hotkeys(&base_config);
+        let profile_trigger_ends = profile_trigger_ends(&base_config);
+        let disabled_groups: HashSet<String> = config.disabled_groups.iter().cloned().collect();
+        let (trigger_index, max_trigger_chars) = index_active_rules(&config, &disabled_groups);
+        let (autocorrect_index, max_typo_chars) = index_typos(&config);
+        let pause_hotkey = parse_hotkey("pause", config.pause_hotkey.as_deref());
+        let picker_hotkey = parse_hotkey("picker", config.picker_hotkey.as_deref());
+        let notification_throttle = NotificationThrottle::new(&config.notifications);
+        let command_runner: Arc<dyn CommandRunner> = Arc::new(ShellRunner);
+        let guarded_runner = config.security.guard(Arc::clone(&command_runner));
+        let command_confirmation = command_confirmation(&config);
 
         Self {
+            base_config,
             config,
+            active_profile,
+            selected_profile,
+            window_profile: None,
+            window_profile_checked: None,
+            profile_trigger_ends,
+            profile_hotkeys,
+            profile_callback: None,
             output: None,
+            output_worker: None,
+            notifier: None,
+            active_window: None,
             typed_buffer: String::new(),
+            trigger_index,
             max_trigger_chars,
+            autocorrect_index,
+            max_typo_chars,
+            disabled_groups,
             active_modifiers: ActiveModifiers::default(),
             pending_expansion: None,
-            debug: false,
+            line_start: false,
+            clock: Arc::new(Instant::now),
+            deferred_match: None,
+            previewed_rule: None,
+            pause_hotkey,
+            paused: Arc::new(AtomicBool::new(false)),
+            picker_hotkey,
+            picker_callback: None,
+            picker_open: false,
+            command_cache: CommandCache::default(),
+            command_runner,
+            guarded_runner,
+            command_confirmation,
+            secrets: None,
+            mask_secrets: false,
+            history: VecDeque::new(),
+            last_undo: None,
+            notification_throttle,
+            stats: UsageStats::default(),
+            stats_dirty: false,
+            expansions_today: DailyCount::default(),
+            capability_warnings: Vec::new(),
         }
     }
 
-    pub fn set_debug(&mut self, debug: bool) {
-        self.debug = debug;
-    }
-
+    /// Sets where expansions are sent; without a sink matches are tracked but not emitted.
     pub fn set_output(&mut self, output: Arc<dyn OutputSink>) {
+        self.output_worker = None;
         self.output = Some(output);
+        self.check_sink_capabilities();
     }
 
-    pub fn reload_config(&mut self, config: AppConfig) {
-        self.max_trigger_chars = config
-            .expansions
-            .iter()
-            .map(|r| r.trigger.chars().count())
-            .max()
-            .unwrap_or(0);
+    /// Sends expansions through `worker` instead of on the calling thread,
+    /// so [`handle_event`](Self::handle_event) returns without waiting for
+    /// the output. Replaces any sink set with [`set_output`](Self::set_output).
+    pub fn set_output_worker(&mut self, worker: OutputWorker) {
+        self.output = None;
+        self.output_worker = Some(worker);
+        self.check_sink_capabilities();
+    }
+
+    /// Rules the output sink cannot fully handle, as of the last reload or
+    /// sink change. Clipboard rules are typed instead and key chords fail
+    /// when they fire.
+    pub fn capability_warnings(&self) -> &[ValidationWarning] {
+        &self.capability_warnings
+    }
+
+    /// What the sink supports; everything when there is none, as nothing is
+    /// sent then.
+    fn sink_capabilities(&self) -> SinkCapabilities {
+        match (&self.output_worker, &self.output) {
+            (Some(worker), _) => worker.capabilities(),
+            (None, Some(output)) => output.capabilities(),
+            (None, None) => SinkCapabilities::FULL,
+        }
+    }
+
+    fn check_sink_capabilities(&mut self) {
+        self.capability_warnings = self
+            .base_config
+            .capability_warnings(&self.sink_capabilities());
+        for warning in &self.capability_warnings {
+            warn!("{}", self.base_config.describe_warning(warning));
+        }
+    }
+
+    /// Detaches the output worker, e.g. to [`shut it down`](OutputWorker::shutdown)
+    /// before exiting.
+    pub fn take_output_worker(&mut self) -> Option<OutputWorker> {
+        self.output_worker.take()
+    }
+
+    /// Blocks until the output worker has sent every queued expansion.
+    pub fn wait_for_output(&self) {
+        if let Some(worker) = &self.output_worker {
+            worker.wait_idle();
+        }
+    }
+
+    /// Sets what the picker hotkey opens; without a callback it does nothing.
+    pub fn set_picker_callback(&mut self, callback: PickerCallback) {
+        self.picker_callback = Some(callback);
+    }
+
+    /// Sets what runs `{{CMD:...}}` commands, [`ShellRunner`] by default.
+    /// The config's `security` settings still apply on top of it.
+    pub fn set_command_runner(&mut self, runner: Arc<dyn CommandRunner>) {
+        self.guarded_runner = self.config.security.guard(Arc::clone(&runner));
+        self.command_runner = runner;
+    }
+
+    /// Sets where `{{SECRET:...}}` values are looked up; without a store the
+    /// macro fails.
+    pub fn set_secret_store(&mut self, secrets: Arc<dyn SecretStore>) {
+        self.secrets = Some(secrets);
+    }
+
+    /// Renders `{{SECRET:...}}` and `{{TOTP:...}}` as a mask instead of their
+    /// values; missing secrets still fail.
+    pub fn set_mask_secrets(&mut self, mask: bool) {
+        self.mask_secrets = mask;
+    }
+
+    /// Sets where desktop notifications go; without one none are shown.
+    pub fn set_notifier(&mut self, notifier: Arc<dyn Notifier>) {
+        self.notifier = Some(notifier);
+    }
+
+    pub fn set_active_window_provider(&mut self, provider: Arc<dyn ActiveWindowProvider>) {
+        self.active_window = Some(provider);
+    }
+
+    /// The rules in force: the top-level ones plus the active profile's.
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    /// The config as loaded, before the active profile is merged in.
+    pub fn base_config(&self) -> &AppConfig {
+        &self.base_config
+    }
+
+    /// Swaps in a new config, resetting any partially typed trigger and
+    /// cached `{{CMD:...}}` outputs. Groups
+    /// toggled at runtime go back to the config's `disabled_groups`. The
+    /// selected profile stays selected if the new config still has it,
+    /// otherwise its `default_profile` takes over; `profile_rules` are
+    /// checked again on the next trigger.
+    pub fn reload_config(&mut self, base_config: AppConfig) {
+        let selected_profile = self
+            .selected_profile
+            .take()
+            .filter(|name| base_config.profiles.contains_key(name))
+            .or_else(|| base_config.default_profile.clone());
+        let config = base_config.effective(selected_profile.as_deref());
+        self.profile_hotkeys = parse_profile_hotkeys(&base_config);
+        self.profile_trigger_ends = profile_trigger_ends(&base_config);
+        self.base_config = base_config;
+        self.active_profile = selected_profile.clone();
+        self.selected_profile = selected_profile;
+        self.window_profile = None;
+        self.window_profile_checked = None;
+        let disabled_groups: HashSet<String> = config.disabled_groups.iter().cloned().collect();
+        let (trigger_index, max_trigger_chars) = index_active_rules(&config, &disabled_groups);
+        let (autocorrect_index, max_typo_chars) = index_typos(&config);
+        let pause_hotkey = parse_hotkey("pause", config.pause_hotkey.as_deref());
+        let picker_hotkey = parse_hotkey("picker", config.picker_hotkey.as_deref());
+
+        self.max_trigger_chars = max_trigger_chars;
+        self.trigger_index = trigger_index;
+        self.autocorrect_index = autocorrect_index;
+        self.max_typo_chars = max_typo_chars;
+        self.disabled_groups = disabled_groups;
+        self.pause_hotkey = pause_hotkey;
+        self.picker_hotkey = picker_hotkey;
+        self.command_cache.clear();
+        self.guarded_runner = config.security.guard(Arc::clone(&self.command_runner));
+        self.command_confirmation = command_confirmation(&config);
+        self.notification_throttle.configure(&config.notifications);
         self.config = config;
+        self.trim_history();
         self.typed_buffer.clear();
         self.pending_expansion = None;
+        self.deferred_match = None;
+        self.check_sink_capabilities();
+    }
+
+    /// Switches every rule in `group` on or off until the next reload.
+    pub fn set_group_enabled(&mut self, group: &str, enabled: bool) -> Result<()> {
+        let known = self
+            .config
+            .expansions
+            .iter()
+            .any(|rule| rule.group.as_deref() == Some(group));
+        if !known {
+            bail!("no expansion is in group '{group}'");
+        }
+
+        let changed = if enabled {
+            self.disabled_groups.remove(group)
+        } else {
+            self.disabled_groups.insert(group.to_string())
+        };
+        if changed {
+            let (trigger_index, max_trigger_chars) =
+                index_active_rules(&self.config, &self.disabled_groups);
+            self.trigger_index = trigger_index;
+            self.max_trigger_chars = max_trigger_chars;
+            self.invalidate_context();
+        }
+        Ok(())
+    }
+
+    /// Selects `profile`, or with `None` only the top-level rules. A
+    /// `profile_rules` match for the focused window still takes precedence.
+    /// Drops any partially typed trigger; groups toggled at runtime stay as
+    /// they are.
+    pub fn set_active_profile(&mut self, profile: Option<&str>) -> Result<()> {
+        if let Some(name) = profile {
+            if !self.base_config.profiles.contains_key(name) {
+                match self.base_config.profile_names().as_slice() {
+                    [] => bail!("unknown profile '{name}': the config defines no profiles"),
+                    names => bail!("unknown profile '{name}' (profiles: {})", names.join(", ")),
+                }
+            }
+        }
+        if self.selected_profile.as_deref() == profile {
+            return Ok(());
+        }
+
+        self.selected_profile = profile.map(str::to_string);
+        if self.apply_active_profile() {
+            self.invalidate_context();
+        } else {
+            // Overridden by a profile rule; listeners such as the tray still
+            // need to show the profile that stays in force.
+            self.notify_profile_callback();
+        }
+        Ok(())
+    }
+
+    /// The profile whose rules are in force.
+    pub fn active_profile(&self) -> Option<&str> {
+        self.active_profile.as_deref()
+    }
+
+    /// Rebuilds the effective rules if the window's or the selected profile
+    /// differs from the one in force. Returns whether it did.
+    fn apply_active_profile(&mut self) -> bool {
+        let profile = self
+            .window_profile
+            .clone()
+            .or_else(|| self.selected_profile.clone());
+        if profile == self.active_profile {
+            return false;
+        }
+
+        self.config = self.base_config.effective(profile.as_deref());
+        self.active_profile = profile;
+        let (trigger_index, max_trigger_chars) =
+            index_active_rules(&self.config, &self.disabled_groups);
+        self.trigger_index = trigger_index;
+        self.max_trigger_chars = max_trigger_chars;
+        // The typed text stays, so a trigger that switched to the window's
+        // profile can still fire; matches against the old rules go.
+        self.pending_expansion = None;
+        self.deferred_match = None;
+        info!(
+            profile = self.active_profile.as_deref().unwrap_or("none"),
+            from_window = self.window_profile.is_some(),
+            "switched profile"
+        );
+        self.notify_profile_callback();
+        true
+    }
+
+    fn notify_profile_callback(&self) {
+        if let Some(callback) = &self.profile_callback {
+            callback(&self.config, self.active_profile.as_deref());
+        }
+    }
+
+    /// Checks `profile_rules` before text ending in `last` is matched, if a
+    /// trigger of some profile ends with it.
+    fn refresh_window_profile_before_match(&mut self, last: Option<char>) {
+        if last.is_some_and(|c| self.profile_trigger_ends.contains(&c)) {
+            self.refresh_window_profile(Instant::now());
+        }
+    }
+
+    /// Matches `profile_rules` against the focused window, reusing the last
+    /// answer for [`WINDOW_PROFILE_TTL`] so a burst of triggers does not
+    /// query the window each time. A failed query matches no rule.
+    fn refresh_window_profile(&mut self, now: Instant) {
+        if self.base_config.profile_rules.is_empty() {
+            return;
+        }
+        if self
+            .window_profile_checked
+            .is_some_and(|checked| now.saturating_duration_since(checked) < WINDOW_PROFILE_TTL)
+        {
+            return;
+        }
+
+        self.window_profile_checked = Some(now);
+        self.window_profile = self.active_window_classes().and_then(|classes| {
+            self.base_config
+                .profile_for_window(&classes)
+                .map(str::to_string)
+        });
+        self.apply_active_profile();
+    }
+
+    /// Sets what runs after every profile switch, such as a tray refresh.
+    pub fn set_profile_callback(&mut self, callback: ProfileCallback) {
+        self.profile_callback = Some(callback);
+    }
+
+    /// Currently disabled groups, sorted.
+    pub fn disabled_groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self.disabled_groups.iter().map(String::as_str).collect();
+        groups.sort_unstable();
+        groups
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        if paused {
+            self.invalidate_context();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Shared pause switch. Other threads (such as the tray) may read it to
+    /// show the current state or store to it to pause and resume expansion.
+    pub fn pause_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.paused)
     }
 
+    /// Graphemes currently remembered as typed context, at most the longest
+    /// trigger plus `buffer_slack`.
+    pub fn buffer_len(&self) -> usize {
+        grapheme_len(&self.typed_buffer)
+    }
+
+    /// Recently fired expansions, oldest first, capped at `history_size`.
+    pub fn history(&self) -> &VecDeque<ExpansionRecord> {
+        &self.history
+    }
+
+    /// Reverts the most recent expansion by erasing its output and retyping
+    /// the input it replaced. Only text-only expansions can be undone, and
+    /// only until the next key press. Returns whether anything was undone.
+    pub fn undo_last(&mut self) -> Result<bool> {
+        let Some(undo) = self.last_undo.take() else {
+            return Ok(false);
+        };
+        self.send_output(OutputJob {
+            backspaces: undo.erase,
+            actions: vec![OutputAction::Text(undo.restore)],
+            inject_mode: InjectMode::Type,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })?;
+        self.invalidate_context();
+        Ok(true)
+    }
+
+    /// What the picker lists: snippets, then the rules that can currently fire.
+    pub fn picker_entries(&self) -> Vec<PickerEntry> {
+        picker_entries(&self.config, &self.disabled_groups)
+    }
+
+    /// Closes the picker opened by the picker hotkey, typing the picked
+    /// entry (macros and `newline` applied as for an expansion) after
+    /// `picker.focus_delay_ms`, or nothing when the picker was cancelled.
+    pub fn finish_picker(&mut self, pick: Option<&PickerEntry>) -> Result<()> {
+        self.picker_open = false;
+        self.invalidate_context();
+        let Some(pick) = pick else {
+            return Ok(());
+        };
+
+        let mut actions = vec![OutputAction::SleepMs(self.config.picker.focus_delay_ms)];
+        let rendered = self.render_confirmed(&pick.content, &pick.content)?;
+        actions.extend(apply_newline_mode(rendered, pick.newline));
+        self.send_output(OutputJob {
+            backspaces: 0,
+            actions,
+            inject_mode: self.config.inject_mode,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })
+    }
+
+    /// Types `actions` that did not come from a trigger, such as a tray
+    /// snippet, through the output worker with the config's `inject_mode`
+    /// and delays, as an expansion would be.
+    pub fn type_actions(&mut self, actions: Vec<OutputAction>) -> Result<()> {
+        self.invalidate_context();
+        self.send_output(OutputJob {
+            backspaces: 0,
+            actions,
+            inject_mode: self.config.inject_mode,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })
+    }
+
+    /// Sends the summary of expansion notifications held back by
+    /// `notifications.min_interval_ms` once the interval has passed. Call
+    /// this periodically.
+    pub fn flush_notifications(&mut self) {
+        self.drop_expired_pending_expansion();
+        let Some(summary) = self.notification_throttle.flush(Instant::now()) else {
+            return;
+        };
+        if let Some(notifier) = &self.notifier {
+            send_expansion_notification(notifier.as_ref(), &summary.body(), false);
+        }
+    }
+
+    /// Replaces the usage counters, e.g. with stats loaded from disk at startup.
+    pub fn set_stats(&mut self, stats: UsageStats) {
+        self.stats = stats;
+        self.stats_dirty = false;
+    }
+
+    pub fn stats(&self) -> &UsageStats {
+        &self.stats
+    }
+
+    /// Expansions fired today (local time) since the engine started.
+    pub fn expansions_today(&self) -> u64 {
+        self.expansions_today.on(Local::now().date_naive())
+    }
+
+    /// Returns a snapshot of the usage counters if they changed since the last call.
+    pub fn take_dirty_stats(&mut self) -> Option<UsageStats> {
+        if !self.stats_dirty {
+            return None;
+        }
+        self.stats_dirty = false;
+        Some(self.stats.clone())
+    }
+
+    /// Processes one keyboard event, firing an expansion when a trigger completes.
     pub fn handle_event(&mut self, event: KeyEvent) -> Result<()> {
+        let _span = debug_span!(
+            "key_event",
+            kind = ?event.kind,
+            special = ?event.special,
+            injected = event.is_injected
+        )
+        .entered();
+        self.drop_expired_pending_expansion();
         if event.is_injected {
+            // Our own key chords press and release modifiers too; releasing
+            // one the user still holds must not mark it as up.
+            return Ok(());
+        }
+
+        if event.kind == KeyEventKind::Press {
+            // Undo erases by count, which is only safe while the caret still
+            // sits right after the expansion.
+            self.last_undo = None;
+        }
+
+        if event.kind == KeyEventKind::Press && self.is_pause_hotkey(&event) {
+            self.toggle_paused();
+            return Ok(());
+        }
+
+        if event.kind == KeyEventKind::Press && self.is_picker_hotkey(&event) {
+            self.open_picker();
+            return Ok(());
+        }
+
+        if event.kind == KeyEventKind::Press {
+            if let Some(profile) = self.profile_for_hotkey(&event) {
+                return self.set_active_profile(Some(&profile));
+            }
+        }
+
+        if self.is_paused() || self.picker_open {
+            if let Some(key) = event.special {
+                self.active_modifiers
+                    .update(key, event.kind == KeyEventKind::Press);
+            }
+            // The flag may have been set from another thread without
+            // going through `set_paused`.
+            self.invalidate_context();
             return Ok(());
         }
 
         match event.kind {
             KeyEventKind::Press => {
-                if let Some(c) = event.printable {
-                    self.on_printable_char(c)?;
+                // X11 also reports text for Enter, Tab, Backspace and Ctrl
+                // shortcuts, as control characters; those keys are handled
+                // by what they do, not what they type.
+                if let Some(text) = event
+                    .text
+                    .as_deref()
+                    .filter(|text| !text.chars().any(char::is_control))
+                {
+                    // An empty text is a dead key: the buffer stays as it is
+                    // and the composed character arrives with the next key.
+                    for c in text.chars() {
+                        self.on_printable_char(c)?;
+                    }
                     return Ok(());
                 }
 
@@ -85,230 +689,1053 @@ impl Engine {
         Ok(())
     }
 
+    fn is_pause_hotkey(&self, event: &KeyEvent) -> bool {
+        self.pause_hotkey.is_some_and(|hotkey| {
+            hotkey.matches(self.active_modifiers.as_hotkey_modifiers(), event)
+        })
+    }
+
+    fn is_picker_hotkey(&self, event: &KeyEvent) -> bool {
+        self.picker_hotkey.is_some_and(|hotkey| {
+            hotkey.matches(self.active_modifiers.as_hotkey_modifiers(), event)
+        })
+    }
+
+    fn profile_for_hotkey(&self, event: &KeyEvent) -> Option<String> {
+        let modifiers = self.active_modifiers.as_hotkey_modifiers();
+        self.profile_hotkeys
+            .iter()
+            .find(|(hotkey, _)| hotkey.matches(modifiers, event))
+            .map(|(_, profile)| profile.clone())
+    }
+
+    fn open_picker(&mut self) {
+        if self.picker_open {
+            return;
+        }
+        let Some(callback) = self.picker_callback.clone() else {
+            return;
+        };
+        self.picker_open = true;
+        self.invalidate_context();
+        callback();
+    }
+
+    fn toggle_paused(&mut self) {
+        self.set_paused(!self.is_paused());
+        let summary = if self.is_paused() {
+            "slykey paused"
+        } else {
+            "slykey resumed"
+        };
+        info!("{summary}");
+
+        if self.config.notifications.on_expansion {
+            let body = self.config.pause_hotkey.as_deref().unwrap_or_default();
+            if let Some(notifier) = &self.notifier {
+                if let Err(err) = notifier.notify(summary, body) {
+                    warn!("failed to send pause notification: {err}");
+                }
+            }
+        }
+    }
+
     fn on_printable_char(&mut self, c: char) -> Result<()> {
+        let line_start = std::mem::take(&mut self.line_start);
         self.typed_buffer.push(c);
         self.truncate_buffer_if_needed();
-        self.log_possible_match_buffer();
+        if self
+            .pending_expansion
+            .as_ref()
+            .is_some_and(|pending| pending.expected_buffer != self.typed_buffer)
+        {
+            debug!("typed text changed: dropping expansion deferred by held modifier");
+            self.pending_expansion = None;
+        }
+        if self.config.notifications.on_preview || tracing::enabled!(tracing::Level::DEBUG) {
+            self.preview_trigger_candidates();
+        }
 
         match self.config.match_behavior {
-            MatchBehavior::Immediate => self.try_expand_immediate()?,
+            MatchBehavior::Immediate => self.try_expand_immediate(c)?,
             MatchBehavior::Boundary => {
                 if self.is_boundary_char(c) {
                     self.try_expand_boundary(Some(c), None)?;
                 }
             }
         }
+        if self.is_boundary_char(c) {
+            self.try_autocorrect(Some(c), None)?;
+        }
 
+        if self.config.auto_capitalize {
+            self.capitalize_sentence_start(c, line_start)?;
+        }
         Ok(())
     }
 
-    fn log_possible_match_buffer(&self) {
-        if !self.debug {
-            return;
+    /// `auto_capitalize`: replaces the lowercase `c` just typed with its
+    /// uppercase form when it starts a sentence. Left alone when an
+    /// expansion fired or is waiting, when a modifier is held and while the
+    /// typed text could still become a trigger.
+    fn capitalize_sentence_start(&mut self, c: char, line_start: bool) -> Result<()> {
+        if !c.is_lowercase()
+            || self.active_modifiers.any_active()
+            || self.pending_expansion.is_some()
+            || self.deferred_match.is_some()
+            || !self.typed_buffer.ends_with(c)
+            || self.trigger_index.possible_match_len(&self.typed_buffer) > 0
+        {
+            return Ok(());
         }
-
-        if self.find_possible_trigger_suffix().is_some() {
-            eprintln!("possible match buffer: {:?}", self.typed_buffer);
+        let before = &self.typed_buffer[..self.typed_buffer.len() - c.len_utf8()];
+        if !line_start && !ends_sentence(before, &self.config.auto_capitalize_exceptions) {
+            return Ok(());
         }
+
+        let upper: String = c.to_uppercase().collect();
+        debug!(%upper, "capitalizing the start of a sentence");
+        self.typed_buffer.pop();
+        self.typed_buffer.push_str(&upper);
+        self.send_output(OutputJob {
+            backspaces: 1,
+            actions: vec![OutputAction::Text(upper)],
+            inject_mode: InjectMode::Type,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })
     }
 
-    fn find_possible_trigger_suffix(&self) -> Option<&str> {
-        for (start, _) in self.typed_buffer.char_indices() {
-            let suffix = &self.typed_buffer[start..];
-            for rule in &self.config.expansions {
-                if rule.trigger.starts_with(suffix) {
-                    return Some(suffix);
+    /// Logs the triggers the typed text could still complete and, once only
+    /// one is left, what it would expand to; `notifications.on_preview`
+    /// also announces that rule once.
+    fn preview_trigger_candidates(&mut self) {
+        let candidates = self.trigger_index.candidates(&self.typed_buffer);
+        let [candidate] = candidates[..] else {
+            if !candidates.is_empty() {
+                debug!(buffer = ?self.typed_buffer, candidates = candidates.len(), "possible match");
+            }
+            self.previewed_rule = None;
+            return;
+        };
+
+        let rule = &self.config.expansions[candidate.rule];
+        let remaining = rule.trigger.chars().count() - candidate.typed_chars;
+        let preview = trigger_preview(&rule.trigger, remaining, &rule.expansion);
+        debug!(buffer = ?self.typed_buffer, "possible match: {preview}");
+        if self.config.notifications.on_preview && self.previewed_rule != Some(candidate.rule) {
+            if let Some(notifier) = &self.notifier {
+                if let Err(err) = notifier.notify("Possible expansion", &preview) {
+                    warn!("failed to send preview notification: {err}");
                 }
             }
         }
-        None
+        self.previewed_rule = Some(candidate.rule);
     }
 
     fn on_special_key_press(&mut self, key: SpecialInputKey) -> Result<()> {
+        if !key.is_modifier() && key != SpecialInputKey::CapsLock {
+            self.line_start = key == SpecialInputKey::Enter;
+        }
         match key {
             SpecialInputKey::Backspace => {
-                self.typed_buffer.pop();
+                pop_grapheme(&mut self.typed_buffer);
+                self.pop_deferred_match_grapheme();
+            }
+            key if key.is_modifier() => {
+                self.active_modifiers.update(key, true);
             }
-            SpecialInputKey::Shift => self.active_modifiers.shift = true,
-            SpecialInputKey::Ctrl => self.active_modifiers.ctrl = true,
-            SpecialInputKey::Alt => self.active_modifiers.alt = true,
-            SpecialInputKey::Meta => self.active_modifiers.meta = true,
             SpecialInputKey::CapsLock => {}
             SpecialInputKey::Enter | SpecialInputKey::Tab => {
                 if self.config.match_behavior == MatchBehavior::Boundary {
                     self.try_expand_boundary(None, Some(key))?;
+                    self.try_autocorrect(None, Some(key))?;
+                } else if let Some(deferred) = self.deferred_match.take() {
+                    self.fire_immediate(deferred.rule_index, &deferred.trailing, Some(key))?;
                 } else {
+                    self.try_autocorrect(None, Some(key))?;
                     self.typed_buffer.clear();
                 }
             }
+            SpecialInputKey::Escape => {
+                if let Some(worker) = self.output_worker.as_ref().filter(|w| w.is_busy()) {
+                    debug!("escape pressed: cancelling expansion in progress");
+                    worker.cancel();
+                }
+                if self.pending_expansion.is_some() || self.deferred_match.is_some() {
+                    debug!("escape pressed: cancelling deferred expansion");
+                }
+                self.invalidate_context();
+            }
+            SpecialInputKey::Left
+            | SpecialInputKey::Right
+            | SpecialInputKey::Up
+            | SpecialInputKey::Down
+            | SpecialInputKey::Home
+            | SpecialInputKey::End
+            | SpecialInputKey::PageUp
+            | SpecialInputKey::PageDown
+            | SpecialInputKey::Delete
+            | SpecialInputKey::Insert => self.invalidate_context(),
+            // A bare unknown key (keypad operator without text, media or
+            // lock key) leaves the text alone, but as a shortcut such as
+            // Ctrl+V it may well have changed it.
+            SpecialInputKey::Unknown(_) if !self.active_modifiers.any_active() => {}
             _ => {
                 self.typed_buffer.clear();
+                self.deferred_match = None;
             }
         }
         Ok(())
     }
 
+    /// Forgets everything tied to the current caret position: the typed buffer
+    /// and any expansion waiting on modifier release or a longer trigger.
+    fn invalidate_context(&mut self) {
+        self.typed_buffer.clear();
+        self.line_start = false;
+        self.pending_expansion = None;
+        self.deferred_match = None;
+    }
+
+    /// Forgets everything tied to the keys typed so far: the typed context,
+    /// a pending or deferred expansion and the undo record. Used after a
+    /// panic left that state half-updated.
+    pub fn reset_transient_state(&mut self) {
+        self.invalidate_context();
+        self.previewed_rule = None;
+        self.last_undo = None;
+    }
+
     fn on_special_key_release(&mut self, key: SpecialInputKey) -> Result<()> {
-        match key {
-            SpecialInputKey::Shift => self.active_modifiers.shift = false,
-            SpecialInputKey::Ctrl => self.active_modifiers.ctrl = false,
-            SpecialInputKey::Alt => self.active_modifiers.alt = false,
-            SpecialInputKey::Meta => self.active_modifiers.meta = false,
-            _ => return Ok(()),
+        if !self.active_modifiers.update(key, false) {
+            return Ok(());
         }
 
         self.flush_pending_expansion_if_ready()
     }
 
-    fn try_expand_immediate(&mut self) -> Result<()> {
-        for rule in &self.config.expansions {
-            if self.typed_buffer.ends_with(&rule.trigger) {
-                eprintln!(
-                    "trigger detected (immediate): '{}' -> expansion fired",
-                    rule.trigger
-                );
-                let actions = parse_expansion_actions(&rule.expansion, &self.config.globals)?;
-                self.dispatch_or_defer_expansion(
-                    self.typed_buffer.clone(),
-                    rule.trigger.chars().count(),
-                    actions,
-                    Some(rule.trigger.clone()),
-                )?;
-                break;
+    fn try_expand_immediate(&mut self, c: char) -> Result<()> {
+        self.refresh_window_profile_before_match(self.typed_buffer.chars().next_back());
+        if let Some(rule_index) = self.find_suffix_match(&self.typed_buffer) {
+            let trigger = &self.config.expansions[rule_index].trigger;
+            if self.config.prefer_longest && self.is_prefix_of_longer_trigger(trigger) {
+                debug!(%trigger, "deferring trigger: a longer trigger may still match");
+                self.deferred_match = Some(DeferredMatch {
+                    rule_index,
+                    trailing: String::new(),
+                });
+                return Ok(());
             }
-        }
-        Ok(())
-    }
 
-    fn try_expand_boundary(
-        &mut self,
-        typed_boundary_char: Option<char>,
-        typed_boundary_key: Option<SpecialInputKey>,
-    ) -> Result<()> {
-        let mut candidate = self.typed_buffer.clone();
-        if typed_boundary_char.is_some() {
-            candidate.pop();
+            self.deferred_match = None;
+            return self.fire_immediate(rule_index, "", None);
         }
 
-        for rule in &self.config.expansions {
-            if candidate.ends_with(&rule.trigger) {
-                let boundary = if let Some(c) = typed_boundary_char {
-                    format!("char '{}'", c)
-                } else if let Some(key) = typed_boundary_key {
-                    format!("key {:?}", key)
-                } else {
-                    "none".to_string()
-                };
-                eprintln!(
-                    "trigger detected (boundary): '{}' at {} -> expansion fired",
-                    rule.trigger, boundary
-                );
-                let mut actions = parse_expansion_actions(&rule.expansion, &self.config.globals)?;
-                if let Some(c) = typed_boundary_char {
-                    actions.push(OutputAction::Text(c.to_string()));
-                }
-                if let Some(key) = typed_boundary_key {
-                    if let Some(mapped) = map_input_key_to_output_key(key) {
-                        actions.push(OutputAction::Key(mapped));
-                    }
-                }
+        let Some(mut deferred) = self.deferred_match.take() else {
+            return Ok(());
+        };
 
-                let delete_count = rule.trigger.chars().count()
-                    + usize::from(typed_boundary_char.is_some() || typed_boundary_key.is_some());
-                self.dispatch_or_defer_expansion(
-                    self.typed_buffer.clone(),
-                    delete_count,
-                    actions,
-                    Some(rule.trigger.clone()),
-                )?;
-                break;
-            }
+        deferred.trailing.push(c);
+        let typed_since_trigger = format!(
+            "{}{}",
+            self.config.expansions[deferred.rule_index].trigger, deferred.trailing
+        );
+        if self.is_prefix_of_longer_trigger(&typed_since_trigger) {
+            self.deferred_match = Some(deferred);
+            return Ok(());
         }
 
-        Ok(())
+        self.fire_immediate(deferred.rule_index, &deferred.trailing, None)
     }
 
-    fn dispatch_or_defer_expansion(
+    fn fire_immediate(
         &mut self,
-        expected_buffer: String,
-        backspaces: usize,
-        mut actions: Vec<OutputAction>,
-        notification_body: Option<String>,
+        rule_index: usize,
+        trailing: &str,
+        trailing_key: Option<SpecialInputKey>,
     ) -> Result<()> {
-        if self.active_modifiers.any_active() {
-            self.pending_expansion = Some(PendingExpansion {
-                expected_buffer,
-                backspaces,
-                actions,
-                notification_body,
-            });
-            return Ok(());
+        let rule = &self.config.expansions[rule_index];
+        info!(trigger = %rule.trigger, "trigger detected (immediate), expansion fired");
+        let mut typed_after = Vec::new();
+        if !trailing.is_empty() {
+            typed_after.push(OutputAction::Text(trailing.to_string()));
         }
-
-        self.pending_expansion = None;
-        self.execute_expansion(backspaces, &mut actions, notification_body.as_deref())
-    }
-
-    fn flush_pending_expansion_if_ready(&mut self) -> Result<()> {
-        if self.active_modifiers.any_active() {
-            return Ok(());
+        let mut backspaces = grapheme_len(&rule.trigger) + grapheme_len(trailing);
+        if let Some(key) = trailing_key {
+            if let Some(mapped) = map_input_key_to_output_key(key) {
+                typed_after.push(OutputAction::Key(mapped));
+            }
+            backspaces += 1;
         }
-
-        let Some(mut pending) = self.pending_expansion.take() else {
-            return Ok(());
+        let mut actions = match self.parse_rule_actions(rule_index) {
+            Err(err) if err.is::<ConfirmationRequired>() => {
+                self.erase_unconfirmed_trigger(backspaces, typed_after)?;
+                return Err(err);
+            }
+            result => result?,
         };
+        let text = typed_text(&actions);
+        actions.extend(typed_after);
 
-        if pending.expected_buffer != self.typed_buffer {
-            return Ok(());
-        }
+        let rule = &self.config.expansions[rule_index];
+        let trigger = rule.trigger.clone();
+        let inject_mode = rule.inject_mode.unwrap_or(self.config.inject_mode);
+        let char_delay_ms = rule.char_delay_ms;
+        let notify = rule.notify;
+        let notify_message = rule.notify_message.clone();
+        let label = rule.label.clone();
+        self.dispatch_or_defer_expansion(PendingExpansion {
+            expected_buffer: self.typed_buffer.clone(),
+            created_at: (self.clock)(),
+            backspaces,
+            actions,
+            inject_mode,
+            char_delay_ms,
+            trigger,
+            text,
+            notify,
+            notify_message,
+            label,
+        })
+    }
 
-        self.execute_expansion(
-            pending.backspaces,
-            &mut pending.actions,
-            pending.notification_body.as_deref(),
-        )
+    fn find_suffix_match(&self, candidate: &str) -> Option<usize> {
+        let mut window_classes = None;
+        self.trigger_index
+            .find_suffix_match(candidate, self.config.prefer_longest, |rule_index| {
+                self.rule_allowed_in_active_window(rule_index, &mut window_classes)
+            })
     }
 
-    fn execute_expansion(
-        &mut self,
-        backspaces: usize,
-        actions: &mut [OutputAction],
-        notification_body: Option<&str>,
-    ) -> Result<()> {
-        if let Some(output) = &self.output {
-            output.send_backspaces(backspaces)?;
-            output.send_actions(actions)?;
+    /// Checks the rule's app filters against the focused window, which is
+    /// queried at most once per `window_classes`.
+    fn rule_allowed_in_active_window(
+        &self,
+        rule_index: usize,
+        window_classes: &mut Option<Option<Vec<String>>>,
+    ) -> bool {
+        let rule = &self.config.expansions[rule_index];
+        if !rule.has_app_filter() {
+            return true;
         }
 
-        #[cfg(target_os = "linux")]
-        if self.config.notifications.on_expansion {
-            if let Some(body) = notification_body {
-                if let Err(err) = dbus_notification::send_notification("Text Expanded", body) {
-                    eprintln!("failed to send expansion notification: {err}");
-                }
+        let classes = window_classes.get_or_insert_with(|| self.active_window_classes());
+        match classes {
+            Some(classes) => rule.allows_app(classes),
+            None => {
+                debug!(trigger = %rule.trigger, "skipping trigger: focused window is unknown");
+                false
             }
         }
-
-        self.typed_buffer.clear();
-        Ok(())
     }
 
-    fn truncate_buffer_if_needed(&mut self) {
-        let max_len = self.max_trigger_chars.saturating_add(8);
-        if self.typed_buffer.chars().count() <= max_len {
-            return;
+    fn active_window_classes(&self) -> Option<Vec<String>> {
+        let provider = self.active_window.as_ref()?;
+        match provider.active_window_classes() {
+            Ok(classes) => Some(classes),
+            Err(err) => {
+                debug!("failed to query focused window: {err}");
+                None
+            }
         }
+    }
 
-        let keep_from = self.typed_buffer.chars().count().saturating_sub(max_len);
-        self.typed_buffer = self.typed_buffer.chars().skip(keep_from).collect();
+    fn is_prefix_of_longer_trigger(&self, prefix: &str) -> bool {
+        let mut window_classes = None;
+        self.trigger_index
+            .has_longer_trigger_with_prefix(prefix, |rule_index| {
+                self.rule_allowed_in_active_window(rule_index, &mut window_classes)
+            })
     }
 
-    fn is_boundary_char(&self, c: char) -> bool {
-        self.config.boundary_chars().contains(c)
+    fn pop_deferred_match_grapheme(&mut self) {
+        let Some(deferred) = &mut self.deferred_match else {
+            return;
+        };
+        if !pop_grapheme(&mut deferred.trailing) {
+            self.deferred_match = None;
+        }
     }
-}
 
-fn map_input_key_to_output_key(key: SpecialInputKey) -> Option<SpecialKey> {
+    fn try_expand_boundary(
+        &mut self,
+        typed_boundary_char: Option<char>,
+        typed_boundary_key: Option<SpecialInputKey>,
+    ) -> Result<()> {
+        let mut candidate = self.typed_buffer.clone();
+        if typed_boundary_char.is_some() {
+            candidate.pop();
+        }
+
+        self.refresh_window_profile_before_match(candidate.chars().next_back());
+        let Some(rule_index) = self.find_suffix_match(&candidate) else {
+            return Ok(());
+        };
+
+        let rule = &self.config.expansions[rule_index];
+        let boundary = if let Some(c) = typed_boundary_char {
+            format!("char '{}'", c)
+        } else if let Some(key) = typed_boundary_key {
+            format!("key {:?}", key)
+        } else {
+            "none".to_string()
+        };
+        info!(
+            trigger = %rule.trigger,
+            %boundary,
+            "trigger detected (boundary), expansion fired"
+        );
+        let boundary_action = if let Some(c) = typed_boundary_char {
+            Some(OutputAction::Text(c.to_string()))
+        } else {
+            typed_boundary_key
+                .and_then(map_input_key_to_output_key)
+                .map(OutputAction::Key)
+        };
+        let delete_count = grapheme_len(&rule.trigger)
+            + usize::from(typed_boundary_char.is_some() || typed_boundary_key.is_some());
+        let mut actions = match self.parse_rule_actions(rule_index) {
+            Err(err) if err.is::<ConfirmationRequired>() => {
+                self.erase_unconfirmed_trigger(
+                    delete_count,
+                    boundary_action.into_iter().collect(),
+                )?;
+                return Err(err);
+            }
+            result => result?,
+        };
+        let text = typed_text(&actions);
+        let rule = &self.config.expansions[rule_index];
+        let retype_boundary = match rule.keep_boundary.unwrap_or_default() {
+            KeepBoundary::Keep => true,
+            KeepBoundary::Swallow => false,
+            KeepBoundary::Smart => !ends_with_whitespace(&actions),
+        };
+        if let Some(boundary_action) = boundary_action.filter(|_| retype_boundary) {
+            let position = rule
+                .boundary_position
+                .unwrap_or(self.config.boundary_position);
+            insert_boundary(&mut actions, boundary_action, position);
+        }
+
+        let trigger = rule.trigger.clone();
+        let inject_mode = rule.inject_mode.unwrap_or(self.config.inject_mode);
+        let char_delay_ms = rule.char_delay_ms;
+        let notify = rule.notify;
+        let notify_message = rule.notify_message.clone();
+        let label = rule.label.clone();
+        self.dispatch_or_defer_expansion(PendingExpansion {
+            expected_buffer: self.typed_buffer.clone(),
+            created_at: (self.clock)(),
+            backspaces: delete_count,
+            actions,
+            inject_mode,
+            char_delay_ms,
+            trigger,
+            text,
+            notify,
+            notify_message,
+            label,
+        })
+    }
+
+    /// `autocorrect_file`: replaces the dictionary typo the boundary just
+    /// ended, matched as a whole word regardless of case and retyped in the
+    /// case it was written in. Works like `boundary` matching whatever
+    /// `match_behavior` says, and stays out of the way of expansions.
+    fn try_autocorrect(
+        &mut self,
+        typed_boundary_char: Option<char>,
+        typed_boundary_key: Option<SpecialInputKey>,
+    ) -> Result<()> {
+        if self.config.autocorrect.is_empty()
+            || self.active_modifiers.any_active()
+            || self.pending_expansion.is_some()
+            || self.deferred_match.is_some()
+        {
+            return Ok(());
+        }
+        // An expansion that fired on this boundary has cleared the buffer.
+        let typed = match typed_boundary_char {
+            Some(c) => match self.typed_buffer.strip_suffix(c) {
+                Some(typed) => typed,
+                None => return Ok(()),
+            },
+            None => self.typed_buffer.as_str(),
+        };
+        let chars: Vec<char> = typed.chars().collect();
+        let lowered: String = chars.iter().map(|&c| lowercase_char(c)).collect();
+        let corrections = &self.config.autocorrect;
+        let Some(index) = self
+            .autocorrect_index
+            .find_suffix_match(&lowered, true, |index| {
+                let len = corrections[index].typo.chars().count();
+                chars.len() == len || !chars[chars.len() - len - 1].is_alphanumeric()
+            })
+        else {
+            return Ok(());
+        };
+
+        let word_start = chars.len() - corrections[index].typo.chars().count();
+        let word: String = chars[word_start..].iter().collect();
+        let replacement = propagate_case(&word, &corrections[index].replacement);
+        if replacement == word {
+            return Ok(());
+        }
+        debug!(typo = %word, %replacement, "autocorrecting typo");
+
+        let mut actions = vec![OutputAction::Text(replacement.clone())];
+        if let Some(c) = typed_boundary_char {
+            actions.push(OutputAction::Text(c.to_string()));
+        } else if let Some(key) = typed_boundary_key.and_then(map_input_key_to_output_key) {
+            actions.push(OutputAction::Key(key));
+        }
+        let backspaces = grapheme_len(&word)
+            + usize::from(typed_boundary_char.is_some() || typed_boundary_key.is_some());
+        let byte_start = typed.len() - word.len();
+        self.typed_buffer.truncate(byte_start);
+        self.typed_buffer.push_str(&replacement);
+        self.typed_buffer.extend(typed_boundary_char);
+        self.send_output(OutputJob {
+            backspaces,
+            actions,
+            inject_mode: InjectMode::Type,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })
+    }
+
+    fn parse_rule_actions(&mut self, rule_index: usize) -> Result<Vec<OutputAction>> {
+        let rule = &self.config.expansions[rule_index];
+        let (trigger, expansion, newline) =
+            (rule.trigger.clone(), rule.expansion.clone(), rule.newline);
+        let actions = self.render_confirmed(&trigger, &expansion)?;
+        Ok(apply_newline_mode(actions, newline))
+    }
+
+    /// Renders `content` typed by way of `key` (its trigger, or the picked
+    /// entry). With `security.confirm_commands` only commands armed by the
+    /// previous attempt at `key` run; if the render asked for any others,
+    /// all of them are armed and nothing is typed.
+    fn render_confirmed(&mut self, key: &str, content: &str) -> Result<Vec<OutputAction>> {
+        let Some(confirmation) = self.command_confirmation.as_mut() else {
+            return parse_expansion_actions_with_context(content, &self.expansion_context());
+        };
+        let runner =
+            ConfirmingRunner::new(self.guarded_runner.as_ref(), confirmation.take_armed(key));
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..self.expansion_context()
+        };
+        let actions = parse_expansion_actions_with_context(content, &ctx);
+        let unconfirmed = runner.unconfirmed();
+        if unconfirmed.is_empty() {
+            return actions;
+        }
+        // The stand-in outputs of unconfirmed commands may have been cached.
+        self.command_cache.clear();
+        let confirmation = self.command_confirmation.as_mut().expect("checked above");
+        Err(confirmation.arm(key, unconfirmed).into())
+    }
+
+    /// Takes back a trigger whose commands wait for confirmation, retyping
+    /// what was typed after it, so typing it again starts afresh. Left alone
+    /// while a modifier is held, which would turn the backspaces into
+    /// shortcuts.
+    fn erase_unconfirmed_trigger(
+        &mut self,
+        backspaces: usize,
+        typed_after: Vec<OutputAction>,
+    ) -> Result<()> {
+        self.typed_buffer.clear();
+        self.deferred_match = None;
+        if self.active_modifiers.any_active() {
+            return Ok(());
+        }
+        self.send_output(OutputJob {
+            backspaces,
+            actions: typed_after,
+            inject_mode: InjectMode::Type,
+            char_delay_ms: None,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification: None,
+        })
+    }
+
+    fn expansion_context(&self) -> ExpansionContext<'_> {
+        ExpansionContext {
+            globals: &self.config.globals,
+            rules: &self.config.expansions,
+            last_expansion: self.history.back().map(|record| record.text.as_str()),
+            dry_run: false,
+            cache: Some(&self.command_cache),
+            runner: self.guarded_runner.as_ref(),
+            secrets: self.secrets.as_deref(),
+            totp_secrets: Some(&self.config.totp),
+            mask_secrets: self.mask_secrets,
+            clock: &Local::now,
+            interfaces: &SystemInterfaces,
+            limits: self.config.expansion.limits(),
+        }
+    }
+
+    fn dispatch_or_defer_expansion(&mut self, expansion: PendingExpansion) -> Result<()> {
+        if self.active_modifiers.any_active() {
+            self.pending_expansion = Some(expansion);
+            return Ok(());
+        }
+
+        self.pending_expansion = None;
+        self.execute_expansion(expansion)
+    }
+
+    fn flush_pending_expansion_if_ready(&mut self) -> Result<()> {
+        self.drop_expired_pending_expansion();
+        if self.active_modifiers.any_active() {
+            return Ok(());
+        }
+
+        let Some(pending) = self.pending_expansion.take() else {
+            return Ok(());
+        };
+
+        if pending.expected_buffer != self.typed_buffer {
+            return Ok(());
+        }
+
+        self.execute_expansion(pending)
+    }
+
+    /// Forgets an expansion that has waited longer than
+    /// `pending_expansion_timeout_ms` for the modifiers to be released, so it
+    /// cannot fire long after the caret has moved on.
+    fn drop_expired_pending_expansion(&mut self) {
+        let timeout = self.config.pending_expansion_timeout_ms;
+        if timeout == 0 {
+            return;
+        }
+        let now = (self.clock)();
+        if self.pending_expansion.as_ref().is_some_and(|pending| {
+            now.saturating_duration_since(pending.created_at) >= Duration::from_millis(timeout)
+        }) {
+            debug!(
+                timeout_ms = timeout,
+                "dropping expansion deferred by held modifier: timed out"
+            );
+            self.pending_expansion = None;
+        }
+    }
+
+    fn execute_expansion(&mut self, expansion: PendingExpansion) -> Result<()> {
+        let undo = undo_record(&expansion);
+        let notification = self.expansion_notice(&expansion, undo.is_some());
+        self.send_output(OutputJob {
+            backspaces: expansion.backspaces,
+            actions: expansion.actions,
+            inject_mode: expansion.inject_mode,
+            char_delay_ms: expansion.char_delay_ms,
+            action_delay_ms: self.config.output.action_delay_ms,
+            notifier: self.notifier.clone(),
+            notification,
+        })?;
+
+        let chars_saved =
+            grapheme_len(&expansion.text) as i64 - grapheme_len(&expansion.trigger) as i64;
+        self.stats.record(&expansion.trigger, chars_saved);
+        self.stats_dirty = true;
+        self.expansions_today.record(Local::now().date_naive());
+        self.record_history(expansion.trigger, expansion.text);
+        self.last_undo = undo;
+        self.typed_buffer.clear();
+        Ok(())
+    }
+
+    /// Hands `job` to the output worker, or sends it right away when there is none.
+    fn send_output(&self, mut job: OutputJob) -> Result<()> {
+        adapt_to_sink(&mut job, &self.sink_capabilities())?;
+        match &self.output_worker {
+            Some(worker) => worker.submit(job),
+            None => job.run(self.output.as_deref()),
+        }
+    }
+
+    /// A rule's own `notify` setting always applies and skips the rate limit;
+    /// otherwise `notifications.on_expansion` decides, throttled.
+    fn expansion_notice(
+        &mut self,
+        expansion: &PendingExpansion,
+        undoable: bool,
+    ) -> Option<ExpansionNotice> {
+        self.notifier.as_ref()?;
+        let (body, undoable) = match expansion.notify {
+            Some(false) => return None,
+            Some(true) => (self.expansion_notification_body(expansion), undoable),
+            None if self.config.notifications.on_expansion => {
+                let summary = self
+                    .notification_throttle
+                    .record(expansion.display_name(), Instant::now())?;
+                // Undo is only offered when the notification is about the
+                // expansion it would revert, not a summary of several.
+                if summary.triggers.len() == 1 {
+                    (self.expansion_notification_body(expansion), undoable)
+                } else {
+                    (summary.body(), false)
+                }
+            }
+            None => return None,
+        };
+        Some(ExpansionNotice { body, undoable })
+    }
+
+    fn expansion_notification_body(&self, expansion: &PendingExpansion) -> String {
+        let Some(message) = &expansion.notify_message else {
+            return expansion.display_name().to_string();
+        };
+        // Secrets are masked: notifications are for people, not the target
+        // window. Commands here were never confirmed, so with
+        // `confirm_commands` they do not run.
+        let runner = match self.command_confirmation {
+            Some(_) => &DenyAllRunner as &dyn CommandRunner,
+            None => self.guarded_runner.as_ref(),
+        };
+        let ctx = ExpansionContext {
+            mask_secrets: true,
+            runner,
+            ..self.expansion_context()
+        };
+        match render_template_macros_with_context(message, &ctx) {
+            Ok(rendered) => rendered,
+            Err(err) => {
+                warn!(
+                    trigger = %expansion.trigger,
+                    "failed to render notify_message: {err}"
+                );
+                message.clone()
+            }
+        }
+    }
+
+    fn record_history(&mut self, trigger: String, text: String) {
+        self.history.push_back(ExpansionRecord {
+            trigger,
+            text,
+            timestamp: Local::now(),
+        });
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        while self.history.len() > self.config.history_size {
+            self.history.pop_front();
+        }
+    }
+
+    /// Drops the oldest graphemes in place once the buffer holds more than
+    /// the longest trigger (or autocorrect typo) plus `buffer_slack`.
+    fn truncate_buffer_if_needed(&mut self) {
+        let max_len = self
+            .max_trigger_chars
+            .max(self.max_typo_chars)
+            .saturating_add(self.config.buffer_slack);
+        let len = grapheme_len(&self.typed_buffer);
+        if len <= max_len {
+            return;
+        }
+
+        let keep_from = self
+            .typed_buffer
+            .grapheme_indices(true)
+            .nth(len - max_len)
+            .map_or(self.typed_buffer.len(), |(index, _)| index);
+        self.typed_buffer.drain(..keep_from);
+    }
+
+    fn is_boundary_char(&self, c: char) -> bool {
+        self.config.boundary_chars().contains(c)
+    }
+}
+
+/// Runs [`Engine::handle_event`] on the shared engine. A panic while
+/// handling the event, say from a macro bug or an output sink, comes back as
+/// an error and drops the typed context instead of poisoning the mutex; an
+/// engine poisoned anyway is recovered the same way, so one bad expansion
+/// never stops later keys from being handled.
+pub fn handle_shared_event(engine: &Mutex<Engine>, event: KeyEvent) -> Result<()> {
+    let mut engine = match engine.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            error!("engine mutex poisoned by an earlier panic; clearing typed context");
+            let mut guard = poisoned.into_inner();
+            engine.clear_poison();
+            guard.reset_transient_state();
+            guard
+        }
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| engine.handle_event(event))) {
+        Ok(result) => result,
+        Err(payload) => {
+            engine.reset_transient_state();
+            Err(anyhow!(
+                "expansion panicked: {}",
+                panic_message(payload.as_ref())
+            ))
+        }
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+/// Fits `job` to what the sink supports: pastes become typing, text is
+/// split into chunks the sink accepts, and key chords it cannot press fail
+/// the job before anything is sent.
+fn adapt_to_sink(job: &mut OutputJob, capabilities: &SinkCapabilities) -> Result<()> {
+    if !capabilities.key_combos && job.actions.iter().any(OutputAction::presses_chord) {
+        bail!("the output backend cannot press key chords");
+    }
+    if job.inject_mode == InjectMode::Clipboard && !capabilities.paste {
+        warn!("the output backend cannot paste; typing the expansion instead");
+        job.inject_mode = InjectMode::Type;
+    }
+    if let Some(max_chars) = capabilities.max_text_chunk.filter(|max| *max > 0) {
+        job.actions = std::mem::take(&mut job.actions)
+            .into_iter()
+            .flat_map(|action| match action {
+                OutputAction::Text(text) if grapheme_len(&text) > max_chars => {
+                    let graphemes: Vec<&str> = text.graphemes(true).collect();
+                    graphemes
+                        .chunks(max_chars)
+                        .map(|chunk| OutputAction::Text(chunk.concat()))
+                        .collect()
+                }
+                action => vec![action],
+            })
+            .collect();
+    }
+    Ok(())
+}
+
+fn undo_record(expansion: &PendingExpansion) -> Option<UndoRecord> {
+    let mut erase = 0;
+    for action in &expansion.actions {
+        let OutputAction::Text(text) = action else {
+            return None;
+        };
+        erase += grapheme_len(text);
+    }
+
+    let typed: Vec<&str> = expansion.expected_buffer.graphemes(true).collect();
+    let start = typed.len().checked_sub(expansion.backspaces)?;
+    Some(UndoRecord {
+        erase,
+        restore: typed[start..].concat(),
+    })
+}
+
+/// Concatenates the text chunks of an action list, skipping keys, sleeps, and caret moves.
+fn typed_text(actions: &[OutputAction]) -> String {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            OutputAction::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Counts user-perceived characters; one Backspace removes a whole grapheme
+/// cluster in most applications, not a single codepoint.
+fn grapheme_len(text: &str) -> usize {
+    text.graphemes(true).count()
+}
+
+/// Whether `typed` ends a sentence: `.`, `!` or `?` and a space, unless
+/// the sentence ends in one of the `exceptions`.
+fn ends_sentence(typed: &str, exceptions: &[String]) -> bool {
+    let Some(sentence) = typed.strip_suffix(' ') else {
+        return false;
+    };
+    if !sentence.ends_with(['.', '!', '?']) {
+        return false;
+    }
+    let sentence = sentence.to_lowercase();
+    !exceptions.iter().any(|exception| {
+        let exception = exception.trim().to_lowercase();
+        !exception.is_empty()
+            && sentence
+                .strip_suffix(&exception)
+                .is_some_and(|rest| !rest.chars().next_back().is_some_and(char::is_alphanumeric))
+    })
+}
+
+/// Removes the last grapheme cluster, returning false when `text` was empty.
+fn pop_grapheme(text: &mut String) -> bool {
+    match text.grapheme_indices(true).next_back() {
+        Some((index, _)) => {
+            text.truncate(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether the last visible output is whitespace or a whitespace key such as
+/// `{{KEY:ENTER}}`; sleeps are skipped.
+fn ends_with_whitespace(actions: &[OutputAction]) -> bool {
+    let last = actions.iter().rev().find(|action| !action.is_sleep());
+    match last {
+        Some(OutputAction::Text(text)) => text.chars().last().is_some_and(char::is_whitespace),
+        Some(OutputAction::Key(key)) => {
+            matches!(key, SpecialKey::Enter | SpecialKey::Tab | SpecialKey::Space)
+        }
+        _ => false,
+    }
+}
+
+/// Adds the retyped boundary to `actions`. `Before` puts it ahead of the
+/// trailing caret moves (and any sleeps among them), so the caret still
+/// lands where the expansion placed it; otherwise it is typed last. A
+/// trailing selection always gets the boundary first, as typing would
+/// replace it.
+fn insert_boundary(
+    actions: &mut Vec<OutputAction>,
+    boundary: OutputAction,
+    position: BoundaryPosition,
+) {
+    let tail_start = actions
+        .iter()
+        .rposition(|action| {
+            !matches!(
+                action,
+                OutputAction::MoveCaret { .. } | OutputAction::Select { .. }
+            ) && !action.is_sleep()
+        })
+        .map_or(0, |index| index + 1);
+    let tail = &actions[tail_start..];
+    let moves_caret = tail
+        .iter()
+        .any(|action| matches!(action, OutputAction::MoveCaret { .. }));
+    // Typing after a selection would replace it, so the boundary goes first.
+    let selects = tail
+        .iter()
+        .any(|action| matches!(action, OutputAction::Select { .. }));
+    if selects || (position == BoundaryPosition::Before && moves_caret) {
+        actions.insert(tail_start, boundary);
+    } else {
+        actions.push(boundary);
+    }
+}
+
+/// "1 more char until ';sigg' → 'Best regards…'", with the expansion as
+/// written (macros unrendered) cut like a picker preview.
+fn trigger_preview(trigger: &str, remaining_chars: usize, expansion: &str) -> String {
+    let chars = if remaining_chars == 1 {
+        "char"
+    } else {
+        "chars"
+    };
+    format!(
+        "{remaining_chars} more {chars} until '{trigger}' → '{}'",
+        picker::preview(expansion)
+    )
+}
+
+/// Last chars of the top-level and profile triggers, or none without
+/// `profile_rules`.
+fn profile_trigger_ends(base_config: &AppConfig) -> HashSet<char> {
+    if base_config.profile_rules.is_empty() {
+        return HashSet::new();
+    }
+    base_config
+        .profiles
+        .values()
+        .flat_map(|profile| &profile.expansions)
+        .chain(&base_config.expansions)
+        .filter_map(|rule| rule.trigger.chars().next_back())
+        .collect()
+}
+
+/// Trigger index and longest trigger length over the rules that may fire.
+fn index_active_rules(
+    config: &AppConfig,
+    disabled_groups: &HashSet<String>,
+) -> (TriggerIndex, usize) {
+    let active = |rule: &ExpansionRule| rule.is_active(disabled_groups);
+    let max_trigger_chars = config
+        .expansions
+        .iter()
+        .filter(|rule| active(rule))
+        .map(|rule| grapheme_len(&rule.trigger))
+        .max()
+        .unwrap_or(0);
+    (
+        TriggerIndex::with_filter(&config.expansions, active),
+        max_trigger_chars,
+    )
+}
+
+fn command_confirmation(config: &AppConfig) -> Option<CommandConfirmation> {
+    config
+        .security
+        .confirm_commands
+        .then(|| CommandConfirmation::new(COMMAND_CONFIRM_WINDOW))
+}
+
+/// Trigger index over the `autocorrect` typos and the longest one's length.
+fn index_typos(config: &AppConfig) -> (TriggerIndex, usize) {
+    let typos = config
+        .autocorrect
+        .iter()
+        .map(|correction| correction.typo.as_str());
+    (
+        TriggerIndex::from_triggers(typos.clone().enumerate()),
+        typos.map(grapheme_len).max().unwrap_or(0),
+    )
+}
+
+/// `c` lowercased, or as it is when its lowercase form is several chars, so
+/// the text keeps its length in chars.
+fn lowercase_char(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
+    }
+}
+
+fn parse_profile_hotkeys(config: &AppConfig) -> Vec<(Hotkey, String)> {
+    config
+        .profiles
+        .iter()
+        .filter_map(|(name, profile)| {
+            let hotkey = parse_hotkey(&format!("profile '{name}'"), profile.hotkey.as_deref())?;
+            Some((hotkey, name.clone()))
+        })
+        .collect()
+}
+
+fn parse_hotkey(name: &str, hotkey: Option<&str>) -> Option<Hotkey> {
+    match Hotkey::parse(hotkey?) {
+        Ok(hotkey) => Some(hotkey),
+        Err(err) => {
+            warn!("ignoring {name} hotkey: {err}");
+            None
+        }
+    }
+}
+
+fn map_input_key_to_output_key(key: SpecialInputKey) -> Option<SpecialKey> {
     match key {
         SpecialInputKey::Enter => Some(SpecialKey::Enter),
         SpecialInputKey::Tab => Some(SpecialKey::Tab),
@@ -316,49 +1743,143 @@ fn map_input_key_to_output_key(key: SpecialInputKey) -> Option<SpecialKey> {
     }
 }
 
+/// Modifier keys currently held, each side tracked on its own so releasing
+/// one Shift while the other is still down keeps Shift active.
 #[derive(Default)]
 struct ActiveModifiers {
-    shift: bool,
-    ctrl: bool,
-    alt: bool,
-    meta: bool,
+    shift_left: bool,
+    shift_right: bool,
+    ctrl_left: bool,
+    ctrl_right: bool,
+    alt_left: bool,
+    alt_gr: bool,
+    meta_left: bool,
+    meta_right: bool,
 }
 
 impl ActiveModifiers {
+    /// Whether an expansion should wait for modifiers to be released. AltGr
+    /// does not count: layouts need it to type trigger characters like `@`.
     fn any_active(&self) -> bool {
-        self.shift || self.ctrl || self.alt || self.meta
+        self.shift() || self.ctrl() || self.alt_left || self.meta()
+    }
+
+    fn shift(&self) -> bool {
+        self.shift_left || self.shift_right
+    }
+
+    fn ctrl(&self) -> bool {
+        self.ctrl_left || self.ctrl_right
+    }
+
+    fn meta(&self) -> bool {
+        self.meta_left || self.meta_right
+    }
+
+    /// Records a modifier press/release; returns false when `key` is not a modifier.
+    fn update(&mut self, key: SpecialInputKey, pressed: bool) -> bool {
+        let held = match key {
+            SpecialInputKey::ShiftLeft => &mut self.shift_left,
+            SpecialInputKey::ShiftRight => &mut self.shift_right,
+            SpecialInputKey::CtrlLeft => &mut self.ctrl_left,
+            SpecialInputKey::CtrlRight => &mut self.ctrl_right,
+            SpecialInputKey::AltLeft => &mut self.alt_left,
+            SpecialInputKey::AltGr => &mut self.alt_gr,
+            SpecialInputKey::MetaLeft => &mut self.meta_left,
+            SpecialInputKey::MetaRight => &mut self.meta_right,
+            _ => return false,
+        };
+        *held = pressed;
+        true
     }
+
+    /// Hotkeys do not care about sides; either Alt key counts as `alt`.
+    fn as_hotkey_modifiers(&self) -> HotkeyModifiers {
+        HotkeyModifiers {
+            shift: self.shift(),
+            ctrl: self.ctrl(),
+            alt: self.alt_left || self.alt_gr,
+            meta: self.meta(),
+        }
+    }
+}
+
+struct DeferredMatch {
+    rule_index: usize,
+    trailing: String,
+}
+
+/// How to revert the last expansion, see [`Engine::undo_last`].
+struct UndoRecord {
+    erase: usize,
+    restore: String,
 }
 
 struct PendingExpansion {
     expected_buffer: String,
+    /// When the expansion was matched, for `pending_expansion_timeout_ms`.
+    created_at: Instant,
     backspaces: usize,
     actions: Vec<OutputAction>,
-    notification_body: Option<String>,
+    inject_mode: InjectMode,
+    char_delay_ms: Option<u64>,
+    trigger: String,
+    /// Typed text of the expansion itself, excluding re-emitted boundary input.
+    text: String,
+    notify: Option<bool>,
+    notify_message: Option<String>,
+    label: Option<String>,
+}
+
+impl PendingExpansion {
+    /// The rule's label, or else its trigger.
+    fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.trigger)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     use anyhow::Result;
 
-    use super::Engine;
-    use crate::config::{AppConfig, ExpansionRule, MatchBehavior, NotificationConfig};
-    use crate::core::expansion::OutputAction;
+    use super::{handle_shared_event, trigger_preview, Engine};
+    use crate::config::{
+        AppConfig, BoundaryPosition, ExpansionConfig, ExpansionRule, Globals, InjectMode,
+        KeepBoundary, MatchBehavior, NewlineMode, NotificationConfig, OutputConfig, OutputFeature,
+        PasteConfig, PickerConfig, Profile, ProfileRule, SecurityConfig, TrayConfig,
+        ValidationWarning,
+    };
+    use crate::core::autocorrect::Correction;
+    use crate::core::expansion::{format_actions, OutputAction};
+    use crate::core::output_worker::OutputWorker;
+    use crate::core::picker::PickerEntry;
+    use crate::core::shell_command::{CommandOutput, ConfirmationRequired, MockRunner};
     use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
-    use crate::io::output::OutputSink;
+    use crate::io::notification::RecordingNotifier;
+    use crate::io::output::{OutputCancelled, OutputSink, SinkCapabilities, SpecialKey};
+    use crate::io::secrets::{MemorySecretStore, SECRET_MASK};
+    use crate::io::window::ActiveWindowProvider;
 
     #[derive(Default)]
     struct RecordingSink {
         backspaces: Mutex<Vec<usize>>,
         actions: Mutex<Vec<Vec<OutputAction>>>,
+        /// Every sink call in order, e.g. `backspace 3`, `type hi[KEY Enter]`, `paste hi`.
+        calls: Mutex<Vec<String>>,
     }
 
     impl OutputSink for RecordingSink {
         fn send_backspaces(&self, count: usize) -> Result<()> {
             self.backspaces.lock().expect("mutex poisoned").push(count);
+            self.calls
+                .lock()
+                .expect("mutex poisoned")
+                .push(format!("backspace {count}"));
             Ok(())
         }
 
@@ -367,14 +1888,84 @@ mod tests {
                 .lock()
                 .expect("mutex poisoned")
                 .push(actions.to_vec());
+            self.calls
+                .lock()
+                .expect("mutex poisoned")
+                .push(format!("type {}", format_actions(actions)));
+            Ok(())
+        }
+
+        fn send_actions_with_char_delay(
+            &self,
+            actions: &[OutputAction],
+            char_delay_ms: u64,
+        ) -> Result<()> {
+            self.calls.lock().expect("mutex poisoned").push(format!(
+                "type({char_delay_ms}ms) {}",
+                format_actions(actions)
+            ));
+            Ok(())
+        }
+
+        fn send_paste(&self, text: &str) -> Result<()> {
+            self.calls
+                .lock()
+                .expect("mutex poisoned")
+                .push(format!("paste {text}"));
             Ok(())
         }
+
+        fn capabilities(&self) -> SinkCapabilities {
+            SinkCapabilities::FULL
+        }
+    }
+
+    struct StubWindow {
+        classes: Option<Vec<String>>,
+    }
+
+    impl ActiveWindowProvider for StubWindow {
+        fn active_window_classes(&self) -> Result<Vec<String>> {
+            self.classes
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no focused window"))
+        }
+    }
+
+    fn stub_window(classes: Option<&[&str]>) -> Arc<StubWindow> {
+        Arc::new(StubWindow {
+            classes: classes.map(|classes| classes.iter().map(|c| c.to_string()).collect()),
+        })
+    }
+
+    fn app_filtered_config() -> AppConfig {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions = vec![
+            ExpansionRule {
+                trigger: ";t".to_string(),
+                expansion: "terminal only".to_string(),
+                apps: vec!["alacritty".to_string()],
+                ..ExpansionRule::default()
+            },
+            ExpansionRule {
+                trigger: ";p".to_string(),
+                expansion: "hunter2".to_string(),
+                exclude_apps: vec!["firefox".to_string()],
+                ..ExpansionRule::default()
+            },
+            ExpansionRule {
+                trigger: ";g".to_string(),
+                expansion: "hello".to_string(),
+                ..ExpansionRule::default()
+            },
+        ];
+        config
     }
 
     fn press_char(c: char) -> KeyEvent {
         KeyEvent {
             kind: KeyEventKind::Press,
-            printable: Some(c),
+            text: Some(c.to_string()),
             special: None,
             is_injected: false,
         }
@@ -383,7 +1974,7 @@ mod tests {
     fn press_special(key: SpecialInputKey) -> KeyEvent {
         KeyEvent {
             kind: KeyEventKind::Press,
-            printable: None,
+            text: None,
             special: Some(key),
             is_injected: false,
         }
@@ -392,7 +1983,7 @@ mod tests {
     fn release_special(key: SpecialInputKey) -> KeyEvent {
         KeyEvent {
             kind: KeyEventKind::Release,
-            printable: None,
+            text: None,
             special: Some(key),
             is_injected: false,
         }
@@ -400,19 +1991,122 @@ mod tests {
 
     fn test_config(match_behavior: MatchBehavior) -> AppConfig {
         AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
             expansions: vec![ExpansionRule {
                 trigger: ";g".to_string(),
                 expansion: "hello".to_string(),
+                ..ExpansionRule::default()
             }],
             snippets: vec![],
-            globals: HashMap::new(),
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
+            notifications: NotificationConfig::default(),
+            match_behavior,
+            boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
+            watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
+        }
+    }
+
+    fn overlapping_config(match_behavior: MatchBehavior, prefer_longest: bool) -> AppConfig {
+        AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
+            expansions: vec![
+                ExpansionRule {
+                    trigger: ";a".to_string(),
+                    expansion: "alpha".to_string(),
+                    ..ExpansionRule::default()
+                },
+                ExpansionRule {
+                    trigger: ";ab".to_string(),
+                    expansion: "alphabet".to_string(),
+                    ..ExpansionRule::default()
+                },
+            ],
+            snippets: vec![],
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
             notifications: NotificationConfig::default(),
             match_behavior,
             boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
             watch: false,
+            prefer_longest,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
         }
     }
 
+    fn type_text(engine: &mut Engine, text: &str) {
+        for c in text.chars() {
+            engine
+                .handle_event(press_char(c))
+                .expect("event should work");
+        }
+    }
+
+    fn emitted_texts(sink: &RecordingSink) -> Vec<Vec<String>> {
+        sink.actions
+            .lock()
+            .expect("mutex poisoned")
+            .iter()
+            .map(|actions| {
+                actions
+                    .iter()
+                    .map(|action| match action {
+                        OutputAction::Text(text) => text.clone(),
+                        other => panic!("expected text output action, got {other:?}"),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
     #[test]
     fn immediate_mode_expands_trigger_and_emits_actions() {
         let sink = Arc::new(RecordingSink::default());
@@ -442,16 +2136,44 @@ mod tests {
     fn immediate_mode_keeps_buffer_through_modifier_keys() {
         let sink = Arc::new(RecordingSink::default());
         let mut engine = Engine::new(AppConfig {
+            include: Vec::new(),
+            expansions_dir: None,
+            expand_env: false,
+            strip_trailing_newline: true,
+            disabled_groups: Vec::new(),
             expansions: vec![ExpansionRule {
                 trigger: "tg@".to_string(),
                 expansion: "tylergetsay@gmail.com".to_string(),
+                ..ExpansionRule::default()
             }],
             snippets: vec![],
-            globals: HashMap::new(),
+            globals: Globals::new(),
+            totp: HashMap::new(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            profile_rules: Vec::new(),
+            expansion: ExpansionConfig::default(),
             notifications: NotificationConfig::default(),
             match_behavior: MatchBehavior::Immediate,
             boundary_chars: None,
+            boundary_position: BoundaryPosition::After,
             watch: false,
+            prefer_longest: true,
+            pause_hotkey: None,
+            picker_hotkey: None,
+            history_size: 20,
+            buffer_slack: 8,
+            pending_expansion_timeout_ms: 2000,
+            auto_capitalize: false,
+            auto_capitalize_exceptions: Vec::new(),
+            autocorrect_file: None,
+            autocorrect: Default::default(),
+            inject_mode: InjectMode::Type,
+            paste: PasteConfig::default(),
+            output: OutputConfig::default(),
+            tray: TrayConfig::default(),
+            picker: PickerConfig::default(),
+            security: SecurityConfig::default(),
         });
         engine.set_output(sink.clone());
 
@@ -462,7 +2184,7 @@ mod tests {
             .handle_event(press_char('g'))
             .expect("event should work");
         engine
-            .handle_event(press_special(SpecialInputKey::Shift))
+            .handle_event(press_special(SpecialInputKey::ShiftLeft))
             .expect("event should work");
         engine
             .handle_event(press_char('@'))
@@ -478,7 +2200,7 @@ mod tests {
         }
 
         engine
-            .handle_event(release_special(SpecialInputKey::Shift))
+            .handle_event(release_special(SpecialInputKey::ShiftLeft))
             .expect("event should work");
 
         let backspaces = sink.backspaces.lock().expect("mutex poisoned");
@@ -492,4 +2214,2303 @@ mod tests {
             _ => panic!("expected text output action"),
         }
     }
+
+    #[test]
+    fn immediate_mode_prefers_longest_overlapping_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(overlapping_config(MatchBehavior::Immediate, true));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";a");
+        assert!(sink.actions.lock().expect("mutex poisoned").is_empty());
+
+        type_text(&mut engine, "b");
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[3]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["alphabet".to_string()]]);
+    }
+
+    #[test]
+    fn immediate_mode_fires_deferred_trigger_when_next_char_diverges() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(overlapping_config(MatchBehavior::Immediate, true));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";ax");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[3]);
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["alpha".to_string(), "x".to_string()]]
+        );
+    }
+
+    #[test]
+    fn immediate_mode_without_prefer_longest_keeps_config_order() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(overlapping_config(MatchBehavior::Immediate, false));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";ab");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[2]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["alpha".to_string()]]);
+    }
+
+    #[test]
+    fn boundary_mode_prefers_longest_overlapping_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(overlapping_config(MatchBehavior::Boundary, true));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, "x;ab ");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[4]);
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["alphabet".to_string(), " ".to_string()]]
+        );
+    }
+
+    #[test]
+    fn boundary_mode_without_prefer_longest_keeps_config_order() {
+        let mut config = overlapping_config(MatchBehavior::Boundary, false);
+        config.expansions.reverse();
+        config.expansions.push(ExpansionRule {
+            trigger: "b".to_string(),
+            expansion: "bravo".to_string(),
+            ..ExpansionRule::default()
+        });
+        config.expansions.rotate_right(1);
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";ab ");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[2]);
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["bravo".to_string(), " ".to_string()]]
+        );
+    }
+
+    #[test]
+    fn matches_within_budget_with_thousands_of_rules() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions = (0..5000)
+            .map(|i| ExpansionRule {
+                trigger: format!(";w{i};"),
+                expansion: format!("word {i}"),
+                ..ExpansionRule::default()
+            })
+            .collect();
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        // Debug logging does the extra possible-match lookup on every key;
+        // the lines themselves are thrown away.
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(std::io::sink)
+            .finish();
+        let started = Instant::now();
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..2000 {
+                type_text(&mut engine, "lorem ipsum ");
+            }
+            type_text(&mut engine, ";w4321;");
+        });
+        let elapsed = started.elapsed();
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[7]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["word 4321".to_string()]]);
+        assert!(
+            elapsed < Duration::from_secs(2),
+            "typing 24k chars against 5000 rules took {elapsed:?}"
+        );
+    }
+
+    /// Log output written by [`capturing_subscriber`].
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn contents(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().expect("mutex poisoned")).into_owned()
+        }
+    }
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .lock()
+                .expect("mutex poisoned")
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capturing_subscriber(
+        level: tracing::Level,
+        logs: CapturedLogs,
+    ) -> impl tracing::Subscriber + Send + Sync {
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_ansi(false)
+            .with_writer(move || logs.clone())
+            .finish()
+    }
+
+    #[test]
+    fn logs_matching_internals_at_debug_level() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        let logs = CapturedLogs::default();
+        let subscriber = capturing_subscriber(tracing::Level::DEBUG, logs.clone());
+        tracing::subscriber::with_default(subscriber, || type_text(&mut engine, "x;g"));
+
+        let lines = logs.contents();
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2, "{lines:#?}");
+        assert!(
+            lines[0].contains("DEBUG key_event{kind=Press"),
+            "{}",
+            lines[0]
+        );
+        assert!(
+            lines[0].ends_with(r#"possible match: 1 more char until ';g' → 'hello' buffer="x;""#),
+            "{}",
+            lines[0]
+        );
+        assert!(lines[1].contains(" INFO key_event{"), "{}", lines[1]);
+        assert!(
+            lines[1].ends_with("trigger detected (immediate), expansion fired trigger=;g"),
+            "{}",
+            lines[1]
+        );
+
+        let logs = CapturedLogs::default();
+        let subscriber = capturing_subscriber(tracing::Level::INFO, logs.clone());
+        tracing::subscriber::with_default(subscriber, || type_text(&mut engine, ";g"));
+        let contents = logs.contents();
+        assert_eq!(contents.lines().count(), 1, "{contents}");
+        assert!(!contents.contains("possible match"), "{contents}");
+        assert_eq!(sink.backspaces.lock().expect("mutex poisoned").len(), 2);
+    }
+
+    #[test]
+    fn app_filters_follow_focused_window_class() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(app_filtered_config());
+        engine.set_output(sink.clone());
+        engine.set_active_window_provider(stub_window(Some(&["Navigator", "firefox"])));
+
+        type_text(&mut engine, ";t;p;g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+
+        engine.set_active_window_provider(stub_window(Some(&["Alacritty", "Alacritty"])));
+        type_text(&mut engine, ";t;p");
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![
+                vec!["hello".to_string()],
+                vec!["terminal only".to_string()],
+                vec!["hunter2".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn longer_triggers_for_other_apps_do_not_hold_back_shorter_ones() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = overlapping_config(MatchBehavior::Immediate, true);
+        config.expansions[1].apps = vec!["alacritty".to_string()];
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        engine.set_active_window_provider(stub_window(Some(&["Navigator", "firefox"])));
+
+        type_text(&mut engine, ";a");
+        assert_eq!(emitted_texts(&sink), vec![vec!["alpha".to_string()]]);
+
+        engine.set_active_window_provider(stub_window(Some(&["Alacritty", "Alacritty"])));
+        type_text(&mut engine, " ;a");
+        assert_eq!(emitted_texts(&sink), vec![vec!["alpha".to_string()]]);
+        type_text(&mut engine, "b");
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["alpha".to_string()], vec!["alphabet".to_string()]]
+        );
+    }
+
+    #[test]
+    fn filtered_rules_are_skipped_when_window_query_fails() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(app_filtered_config());
+        engine.set_output(sink.clone());
+        engine.set_active_window_provider(stub_window(None));
+
+        type_text(&mut engine, ";t;p;g");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[2]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    fn pause_config() -> AppConfig {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.pause_hotkey = Some("ctrl+alt+p".to_string());
+        config
+    }
+
+    fn press_pause_hotkey(engine: &mut Engine) {
+        press_ctrl_alt(engine, '\u{10}');
+    }
+
+    /// Ctrl+Alt plus the key that types `control_char` while Ctrl is held.
+    fn press_ctrl_alt(engine: &mut Engine, control_char: char) {
+        for key in [SpecialInputKey::CtrlLeft, SpecialInputKey::AltLeft] {
+            engine
+                .handle_event(press_special(key))
+                .expect("event should work");
+        }
+        engine
+            .handle_event(press_char(control_char))
+            .expect("event should work");
+        for key in [SpecialInputKey::AltLeft, SpecialInputKey::CtrlLeft] {
+            engine
+                .handle_event(release_special(key))
+                .expect("event should work");
+        }
+    }
+
+    #[test]
+    fn pause_hotkey_toggles_paused_state() {
+        let mut engine = Engine::new(pause_config());
+        assert!(!engine.is_paused());
+
+        press_pause_hotkey(&mut engine);
+        assert!(engine.is_paused());
+
+        press_pause_hotkey(&mut engine);
+        assert!(!engine.is_paused());
+    }
+
+    #[test]
+    fn no_expansion_fires_while_paused() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(pause_config());
+        engine.set_output(sink.clone());
+
+        press_pause_hotkey(&mut engine);
+        type_text(&mut engine, ";g");
+        assert!(sink.actions.lock().expect("mutex poisoned").is_empty());
+
+        engine.set_paused(false);
+        type_text(&mut engine, ";g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    #[test]
+    fn shared_pause_flag_controls_the_engine() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(pause_config());
+        engine.set_output(sink.clone());
+        let flag = engine.pause_flag();
+
+        type_text(&mut engine, ";");
+        flag.store(true, Ordering::Relaxed);
+        assert!(engine.is_paused());
+        type_text(&mut engine, "g");
+        flag.store(false, Ordering::Relaxed);
+        type_text(&mut engine, "g");
+        assert!(sink.actions.lock().expect("mutex poisoned").is_empty());
+
+        press_pause_hotkey(&mut engine);
+        assert!(flag.load(Ordering::Relaxed));
+        engine.set_paused(false);
+        assert!(!flag.load(Ordering::Relaxed));
+        type_text(&mut engine, ";g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    fn press_picker_hotkey(engine: &mut Engine) {
+        for key in [SpecialInputKey::CtrlLeft, SpecialInputKey::AltLeft] {
+            engine
+                .handle_event(press_special(key))
+                .expect("event should work");
+        }
+        engine
+            .handle_event(press_char(' '))
+            .expect("event should work");
+        for key in [SpecialInputKey::AltLeft, SpecialInputKey::CtrlLeft] {
+            engine
+                .handle_event(release_special(key))
+                .expect("event should work");
+        }
+    }
+
+    fn picker_engine(sink: &Arc<RecordingSink>, opened: &Arc<AtomicUsize>) -> Engine {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.picker_hotkey = Some("ctrl+alt+space".to_string());
+        config.picker.focus_delay_ms = 75;
+        config
+            .globals
+            .insert("NAME".to_string(), "Tyler".to_string());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        let opened = Arc::clone(opened);
+        engine.set_picker_callback(Arc::new(move || {
+            opened.fetch_add(1, Ordering::Relaxed);
+        }));
+        engine
+    }
+
+    #[test]
+    fn picker_hotkey_opens_the_picker_once_and_mutes_matching() {
+        let sink = Arc::new(RecordingSink::default());
+        let opened = Arc::new(AtomicUsize::new(0));
+        let mut engine = picker_engine(&sink, &opened);
+
+        type_text(&mut engine, ";");
+        press_picker_hotkey(&mut engine);
+        press_picker_hotkey(&mut engine);
+        assert_eq!(opened.load(Ordering::Relaxed), 1);
+
+        // Typed into the picker's search box, not the target window.
+        type_text(&mut engine, ";g");
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+
+        engine.finish_picker(None).expect("cancel should work");
+        type_text(&mut engine, "g");
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+        type_text(&mut engine, ";g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    #[test]
+    fn picked_entries_are_typed_with_their_newline_mode_after_the_focus_delay() {
+        let sink = Arc::new(RecordingSink::default());
+        let opened = Arc::new(AtomicUsize::new(0));
+        let mut engine = picker_engine(&sink, &opened);
+
+        press_picker_hotkey(&mut engine);
+        engine
+            .finish_picker(Some(&PickerEntry {
+                label: "greeting".to_string(),
+                content: "Hi {{NAME}}{{KEY:ENTER}}".to_string(),
+                newline: NewlineMode::Text,
+            }))
+            .expect("pick should be typed");
+        press_picker_hotkey(&mut engine);
+        engine
+            .finish_picker(Some(&PickerEntry {
+                label: ";sig  signature".to_string(),
+                content: "Best,\n{{NAME}}".to_string(),
+                newline: NewlineMode::Strip,
+            }))
+            .expect("pick should be typed");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 0".to_string(),
+                "type [SLEEP 75ms]Hi Tyler[KEY Enter]".to_string(),
+                "backspace 0".to_string(),
+                "type [SLEEP 75ms]Best, Tyler".to_string()
+            ]
+        );
+        let labels: Vec<String> = engine
+            .picker_entries()
+            .into_iter()
+            .map(|entry| entry.label)
+            .collect();
+        assert!(labels.contains(&";g  hello".to_string()));
+    }
+
+    #[test]
+    fn typed_actions_follow_the_inject_mode() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.inject_mode = InjectMode::Clipboard;
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        engine
+            .type_actions(vec![
+                OutputAction::SleepMs(150),
+                OutputAction::Text("hi".to_string()),
+                OutputAction::Key(SpecialKey::Enter),
+            ])
+            .expect("actions should be sent");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 0".to_string(),
+                "type [SLEEP 150ms]".to_string(),
+                "paste hi".to_string(),
+                "type [KEY Enter]".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn picker_hotkey_without_a_callback_is_ignored() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.picker_hotkey = Some("ctrl+alt+space".to_string());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        press_picker_hotkey(&mut engine);
+        type_text(&mut engine, ";g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    fn profile_config() -> AppConfig {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.profiles = BTreeMap::from([
+            (
+                "work".to_string(),
+                Profile {
+                    expansions: vec![
+                        ExpansionRule {
+                            trigger: ";s".to_string(),
+                            expansion: "Regards, {{ORG}}".to_string(),
+                            ..ExpansionRule::default()
+                        },
+                        ExpansionRule {
+                            trigger: ";w".to_string(),
+                            expansion: "work only".to_string(),
+                            ..ExpansionRule::default()
+                        },
+                    ],
+                    globals: Globals::from([("ORG".to_string(), "ACME".to_string())]),
+                    hotkey: Some("ctrl+alt+w".to_string()),
+                    ..Profile::default()
+                },
+            ),
+            (
+                "personal".to_string(),
+                Profile {
+                    expansions: vec![
+                        ExpansionRule {
+                            trigger: ";s".to_string(),
+                            expansion: "Cheers".to_string(),
+                            ..ExpansionRule::default()
+                        },
+                        ExpansionRule {
+                            trigger: ";personal-signature".to_string(),
+                            expansion: "Sam".to_string(),
+                            ..ExpansionRule::default()
+                        },
+                    ],
+                    ..Profile::default()
+                },
+            ),
+        ]);
+        config.default_profile = Some("work".to_string());
+        config
+    }
+
+    #[test]
+    fn switching_profiles_swaps_the_effective_rules() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(profile_config());
+        engine.set_output(sink.clone());
+        assert_eq!(engine.active_profile(), Some("work"));
+
+        type_text(&mut engine, ";s ;w ;g ");
+        engine
+            .set_active_profile(Some("personal"))
+            .expect("known profile");
+        // The longest trigger now comes from the profile, so the buffer must
+        // grow to fit it.
+        type_text(&mut engine, ";s ;w ;personal-signature ");
+        engine.set_active_profile(None).expect("no profile");
+        type_text(&mut engine, ";s ;g ");
+
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![
+                vec!["Regards, ACME".to_string()],
+                vec!["work only".to_string()],
+                vec!["hello".to_string()],
+                vec!["Cheers".to_string()],
+                vec!["Sam".to_string()],
+                vec!["hello".to_string()],
+            ]
+        );
+
+        let err = engine
+            .set_active_profile(Some("home"))
+            .expect_err("unknown profile");
+        assert_eq!(
+            err.to_string(),
+            "unknown profile 'home' (profiles: personal, work)"
+        );
+        assert_eq!(engine.active_profile(), None);
+    }
+
+    #[test]
+    fn profile_hotkey_switches_and_reload_keeps_the_active_profile() {
+        let switches = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new(profile_config());
+        let recorded = Arc::clone(&switches);
+        engine.set_profile_callback(Arc::new(move |config, profile| {
+            recorded
+                .lock()
+                .expect("mutex poisoned")
+                .push((profile.map(str::to_string), config.expansions.len()));
+        }));
+
+        engine.set_active_profile(Some("personal")).expect("switch");
+        press_ctrl_alt(&mut engine, '\u{17}');
+        press_ctrl_alt(&mut engine, '\u{17}');
+        assert_eq!(engine.active_profile(), Some("work"));
+        assert_eq!(
+            *switches.lock().expect("mutex poisoned"),
+            vec![
+                (Some("personal".to_string()), 3),
+                (Some("work".to_string()), 3),
+            ]
+        );
+
+        engine.set_active_profile(Some("personal")).expect("switch");
+        engine.reload_config(profile_config());
+        assert_eq!(engine.active_profile(), Some("personal"));
+
+        let mut config = profile_config();
+        config.profiles.remove("personal");
+        engine.reload_config(config);
+        assert_eq!(engine.active_profile(), Some("work"));
+        assert_eq!(engine.config().expansions.len(), 3);
+    }
+
+    /// A focused window that tests can switch, counting queries.
+    #[derive(Default)]
+    struct FocusedWindow {
+        classes: Mutex<Option<Vec<String>>>,
+        queries: AtomicUsize,
+    }
+
+    impl FocusedWindow {
+        fn focus(&self, classes: Option<&[&str]>) {
+            *self.classes.lock().expect("mutex poisoned") =
+                classes.map(|classes| classes.iter().map(|c| c.to_string()).collect());
+        }
+    }
+
+    impl ActiveWindowProvider for FocusedWindow {
+        fn active_window_classes(&self) -> Result<Vec<String>> {
+            self.queries.fetch_add(1, Ordering::Relaxed);
+            self.classes
+                .lock()
+                .expect("mutex poisoned")
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no focused window"))
+        }
+    }
+
+    #[test]
+    fn profile_rules_follow_the_focused_window() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = profile_config();
+        config.profile_rules = vec![ProfileRule {
+            app: "slack".to_string(),
+            profile: "personal".to_string(),
+        }];
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        let window = Arc::new(FocusedWindow::default());
+        engine.set_active_window_provider(window.clone());
+
+        let start = Instant::now();
+        window.focus(Some(&["slack", "Slack"]));
+        engine.refresh_window_profile(start);
+        assert_eq!(engine.active_profile(), Some("personal"));
+
+        // Within the TTL the cached match is used, even across a focus change.
+        window.focus(Some(&["code", "Code"]));
+        engine.refresh_window_profile(start + Duration::from_millis(100));
+        assert_eq!(engine.active_profile(), Some("personal"));
+        assert_eq!(window.queries.load(Ordering::Relaxed), 1);
+
+        engine.refresh_window_profile(start + Duration::from_millis(250));
+        assert_eq!(engine.active_profile(), Some("work"));
+
+        // A matching rule wins over a manual selection, and a failed query
+        // falls back to the selected profile.
+        window.focus(Some(&["Slack"]));
+        engine.refresh_window_profile(start + Duration::from_millis(500));
+        engine.set_active_profile(None).expect("select no profile");
+        assert_eq!(engine.active_profile(), Some("personal"));
+        window.focus(None);
+        engine.refresh_window_profile(start + Duration::from_millis(750));
+        assert_eq!(engine.active_profile(), None);
+
+        // Typing checks the window only once a trigger could be complete,
+        // and keeps the trigger typed before the switch.
+        window.focus(Some(&["Slack"]));
+        engine.window_profile_checked = None;
+        type_text(&mut engine, "xy;");
+        assert_eq!(window.queries.load(Ordering::Relaxed), 4);
+        type_text(&mut engine, "s ");
+        assert_eq!(emitted_texts(&sink), vec![vec!["Cheers".to_string()]]);
+        assert_eq!(window.queries.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn navigation_key_drops_expansion_deferred_by_held_modifier() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        for cancel_key in [SpecialInputKey::Left, SpecialInputKey::Escape] {
+            engine
+                .handle_event(press_special(SpecialInputKey::ShiftLeft))
+                .expect("event should work");
+            type_text(&mut engine, ";g");
+            assert!(engine.pending_expansion.is_some());
+
+            engine
+                .handle_event(press_special(cancel_key))
+                .expect("event should work");
+            assert!(engine.pending_expansion.is_none());
+            assert!(engine.typed_buffer.is_empty());
+
+            engine
+                .handle_event(release_special(SpecialInputKey::ShiftLeft))
+                .expect("event should work");
+        }
+
+        assert!(sink.backspaces.lock().expect("mutex poisoned").is_empty());
+        assert!(sink.actions.lock().expect("mutex poisoned").is_empty());
+    }
+
+    /// Replaces the engine's clock with one that only moves when the returned
+    /// handle is advanced.
+    fn manual_clock(engine: &mut Engine) -> Arc<Mutex<Instant>> {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = Arc::clone(&now);
+        engine.clock = Arc::new(move || *clock.lock().expect("mutex poisoned"));
+        now
+    }
+
+    #[test]
+    fn expansion_deferred_by_held_modifier_expires() {
+        for (timeout_ms, waited_ms, fires) in [
+            (2000, 1500, true),
+            (2000, 2000, false),
+            (2000, 60_000, false),
+            (0, 60_000, true),
+        ] {
+            let mut config = test_config(MatchBehavior::Immediate);
+            config.pending_expansion_timeout_ms = timeout_ms;
+            let sink = Arc::new(RecordingSink::default());
+            let mut engine = Engine::new(config);
+            engine.set_output(sink.clone());
+            let now = manual_clock(&mut engine);
+
+            engine
+                .handle_event(press_special(SpecialInputKey::CtrlLeft))
+                .expect("event should work");
+            type_text(&mut engine, ";g");
+            assert!(engine.pending_expansion.is_some());
+
+            *now.lock().expect("mutex poisoned") += Duration::from_millis(waited_ms);
+            engine.flush_notifications();
+            assert_eq!(
+                engine.pending_expansion.is_some(),
+                fires,
+                "{timeout_ms} {waited_ms}"
+            );
+            engine
+                .handle_event(release_special(SpecialInputKey::CtrlLeft))
+                .expect("event should work");
+
+            let expected = if fires {
+                vec!["backspace 2".to_string(), "type hello".to_string()]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(
+                *sink.calls.lock().expect("mutex poisoned"),
+                expected,
+                "{timeout_ms} {waited_ms}"
+            );
+        }
+    }
+
+    #[test]
+    fn typing_on_drops_expansion_deferred_by_held_modifier() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+        manual_clock(&mut engine);
+
+        engine
+            .handle_event(press_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        type_text(&mut engine, ";g");
+        assert!(engine.pending_expansion.is_some());
+
+        type_text(&mut engine, "x");
+        assert!(engine.pending_expansion.is_none());
+
+        // Back at the text the trigger matched on, the dropped expansion
+        // stays dropped.
+        engine
+            .handle_event(press_special(SpecialInputKey::Backspace))
+            .expect("event should work");
+        assert_eq!(engine.typed_buffer, ";g");
+        engine
+            .handle_event(release_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+    }
+
+    fn auto_capitalize_config(match_behavior: MatchBehavior) -> AppConfig {
+        let mut config = test_config(match_behavior);
+        config.auto_capitalize = true;
+        config.auto_capitalize_exceptions = vec!["e.g.".to_string(), "i.e.".to_string()];
+        config.expansions.push(ExpansionRule {
+            trigger: "btw".to_string(),
+            expansion: "by the way".to_string(),
+            ..ExpansionRule::default()
+        });
+        config
+    }
+
+    #[test]
+    fn auto_capitalize_fixes_the_first_letter_of_a_sentence() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(auto_capitalize_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, "done. now? yes! ok");
+        engine
+            .handle_event(press_special(SpecialInputKey::Enter))
+            .expect("event should work");
+        type_text(&mut engine, "next");
+        engine
+            .handle_event(press_special(SpecialInputKey::Left))
+            .expect("event should work");
+        type_text(&mut engine, "x");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 1",
+                "type N",
+                "backspace 1",
+                "type Y",
+                "backspace 1",
+                "type O",
+                "backspace 1",
+                "type N",
+            ]
+        );
+        assert_eq!(engine.typed_buffer, "x");
+    }
+
+    #[test]
+    fn auto_capitalize_skips_exceptions_held_modifiers_and_other_text() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(auto_capitalize_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        type_text(
+            &mut engine,
+            "cats, e.g. lions. I.E. tigers. 3.5 km. Fine.no",
+        );
+        engine
+            .handle_event(press_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        type_text(&mut engine, ". c");
+        engine
+            .handle_event(release_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+
+        let mut config = auto_capitalize_config(MatchBehavior::Immediate);
+        config.auto_capitalize = false;
+        engine.reload_config(config);
+        type_text(&mut engine, "done. now");
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+    }
+
+    #[test]
+    fn auto_capitalize_leaves_possible_triggers_alone() {
+        for match_behavior in [MatchBehavior::Immediate, MatchBehavior::Boundary] {
+            let sink = Arc::new(RecordingSink::default());
+            let mut engine = Engine::new(auto_capitalize_config(match_behavior));
+            engine.set_output(sink.clone());
+
+            type_text(&mut engine, "ok. btw ok. ;g ");
+
+            let calls = sink.calls.lock().expect("mutex poisoned").clone();
+            assert!(
+                calls.iter().any(|call| call.starts_with("type by the way")),
+                "{calls:?}"
+            );
+            assert!(!calls.iter().any(|call| call == "type B"), "{calls:?}");
+            assert!(
+                calls.iter().any(|call| call.starts_with("type hello")),
+                "{calls:?}"
+            );
+        }
+    }
+
+    fn autocorrect_config(match_behavior: MatchBehavior) -> AppConfig {
+        let mut config = test_config(match_behavior);
+        config.autocorrect = Arc::new(
+            [("teh", "the"), ("adn", "and"), ("i", "I")]
+                .into_iter()
+                .map(|(typo, replacement)| Correction {
+                    typo: typo.to_string(),
+                    replacement: replacement.to_string(),
+                })
+                .collect(),
+        );
+        config
+    }
+
+    #[test]
+    fn autocorrect_fixes_typos_ended_by_a_boundary() {
+        for match_behavior in [MatchBehavior::Immediate, MatchBehavior::Boundary] {
+            let sink = Arc::new(RecordingSink::default());
+            let mut engine = Engine::new(autocorrect_config(match_behavior));
+            engine.set_output(sink.clone());
+
+            type_text(&mut engine, "teh ");
+            assert_eq!(
+                *sink.calls.lock().expect("mutex poisoned"),
+                vec!["backspace 4", "type the "],
+                "{match_behavior:?}"
+            );
+            assert!(engine.typed_buffer.ends_with("the "));
+
+            type_text(&mut engine, "Teh TEH, i adn");
+            engine
+                .handle_event(press_special(SpecialInputKey::Enter))
+                .expect("event should work");
+            assert_eq!(
+                sink.calls.lock().expect("mutex poisoned")[2..],
+                [
+                    "backspace 4",
+                    "type The ",
+                    "backspace 4",
+                    "type THE,",
+                    "backspace 2",
+                    "type I ",
+                    "backspace 4",
+                    "type and[KEY Enter]",
+                ],
+                "{match_behavior:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn autocorrect_only_matches_whole_words_and_yields_to_triggers() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(autocorrect_config(MatchBehavior::Boundary));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, "steh tehs I teh");
+        engine
+            .handle_event(press_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        type_text(&mut engine, " ");
+        engine
+            .handle_event(release_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+
+        type_text(&mut engine, ";g ");
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["hello".to_string(), " ".to_string()]]
+        );
+
+        let mut config = autocorrect_config(MatchBehavior::Boundary);
+        config.autocorrect = Arc::default();
+        engine.reload_config(config);
+        type_text(&mut engine, "teh ");
+        assert_eq!(emitted_texts(&sink).len(), 1);
+    }
+
+    #[test]
+    fn backspace_count_uses_graphemes_for_combining_characters() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions = vec![ExpansionRule {
+            trigger: ";cafe\u{301}".to_string(),
+            expansion: "coffee".to_string(),
+            ..ExpansionRule::default()
+        }];
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";cafe\u{301}");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[5]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["coffee".to_string()]]);
+    }
+
+    #[test]
+    fn backspace_key_removes_whole_grapheme_from_buffer() {
+        let mut engine = Engine::new(test_config(MatchBehavior::Boundary));
+
+        type_text(&mut engine, "x👍🏽");
+        engine
+            .handle_event(press_special(SpecialInputKey::Backspace))
+            .expect("event should work");
+
+        assert_eq!(engine.typed_buffer, "x");
+    }
+
+    #[test]
+    fn truncation_keeps_grapheme_clusters_intact() {
+        let mut engine = Engine::new(test_config(MatchBehavior::Boundary));
+
+        for _ in 0..20 {
+            type_text(&mut engine, "e\u{301}");
+        }
+
+        assert_eq!(engine.typed_buffer, "e\u{301}".repeat(10));
+    }
+
+    #[test]
+    fn history_records_expansions_and_feeds_last_expansion_macro() {
+        let mut config = test_config(MatchBehavior::Boundary);
+        config.expansions.push(ExpansionRule {
+            trigger: ";again".to_string(),
+            expansion: "<{{LAST_EXPANSION}}>".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g ;again ");
+
+        let history: Vec<(&str, &str)> = engine
+            .history()
+            .iter()
+            .map(|record| (record.trigger.as_str(), record.text.as_str()))
+            .collect();
+        assert_eq!(history, vec![(";g", "hello"), (";again", "<hello>")]);
+        assert_eq!(
+            emitted_texts(&sink)[1],
+            vec!["<hello>".to_string(), " ".to_string()]
+        );
+    }
+
+    #[test]
+    fn history_is_capped_and_survives_reload() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.history_size = 2;
+        let mut engine = Engine::new(config.clone());
+
+        type_text(&mut engine, ";g;g;g");
+        assert_eq!(engine.history().len(), 2);
+
+        config.history_size = 1;
+        engine.reload_config(config);
+        assert_eq!(engine.history().len(), 1);
+        assert_eq!(engine.history()[0].text, "hello");
+    }
+
+    #[test]
+    fn notifies_on_expansion_only_when_enabled() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_notifier(notifier.clone());
+
+        type_text(&mut engine, ";g ");
+        assert!(notifier.sent().is_empty());
+
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.notifications.on_expansion = true;
+        config.notifications.min_interval_ms = 0;
+        engine.reload_config(config);
+        type_text(&mut engine, ";g");
+        assert_eq!(notifier.sent(), vec!["Text Expanded: ;g [undo]"]);
+    }
+
+    #[test]
+    fn previews_the_only_remaining_trigger_once_when_enabled() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions.push(ExpansionRule {
+            trigger: ";addr".to_string(),
+            expansion: "{{UPPER:123 Main St}}, Springfield, 12345 United States of America"
+                .to_string(),
+            ..ExpansionRule::default()
+        });
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut engine = Engine::new(config.clone());
+        engine.set_notifier(notifier.clone());
+        type_text(&mut engine, "x;ad");
+        assert!(notifier.sent().is_empty());
+
+        config.notifications.on_preview = true;
+        engine.reload_config(config);
+        type_text(&mut engine, " ;");
+        assert!(notifier.sent().is_empty(), "';' could still become ;g");
+        type_text(&mut engine, "ad");
+        type_text(&mut engine, " ;a");
+        assert_eq!(
+            notifier.sent(),
+            vec![
+                "Possible expansion: 3 more chars until ';addr' → '{{UPPER:123 Main St}}, Springfield, 1234…'",
+                "Possible expansion: 3 more chars until ';addr' → '{{UPPER:123 Main St}}, Springfield, 1234…'",
+            ]
+        );
+    }
+
+    #[test]
+    fn trigger_preview_counts_chars_and_keeps_macros() {
+        assert_eq!(
+            trigger_preview(";sigg", 1, "Best regards,\n{{NAME}}"),
+            "1 more char until ';sigg' → 'Best regards,…'"
+        );
+        assert_eq!(
+            trigger_preview(";d", 2, "{{DATE}}"),
+            "2 more chars until ';d' → '{{DATE}}'"
+        );
+    }
+
+    #[test]
+    fn pause_toggle_notifies_when_expansion_notifications_are_enabled() {
+        let mut config = pause_config();
+        config.notifications.on_expansion = true;
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut engine = Engine::new(config);
+        engine.set_notifier(notifier.clone());
+
+        press_pause_hotkey(&mut engine);
+        press_pause_hotkey(&mut engine);
+
+        assert_eq!(
+            notifier.sent(),
+            vec!["slykey paused: ctrl+alt+p", "slykey resumed: ctrl+alt+p"]
+        );
+    }
+
+    #[test]
+    fn per_rule_notify_overrides_the_global_setting() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.globals.insert("ENV".to_string(), "prod".to_string());
+        config.expansions.push(ExpansionRule {
+            trigger: ";deploy".to_string(),
+            expansion: "deploy{{KEY:ENTER}}".to_string(),
+            notify: Some(true),
+            notify_message: Some("Deployed to {{ENV}}".to_string()),
+            ..ExpansionRule::default()
+        });
+        config.expansions.push(ExpansionRule {
+            trigger: ";quiet".to_string(),
+            expansion: "shh".to_string(),
+            notify: Some(false),
+            ..ExpansionRule::default()
+        });
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut engine = Engine::new(config.clone());
+        engine.set_notifier(notifier.clone());
+
+        type_text(&mut engine, ";g ;deploy ;quiet ");
+        assert_eq!(notifier.sent(), vec!["Text Expanded: Deployed to prod"]);
+
+        config.notifications.on_expansion = true;
+        config.notifications.min_interval_ms = 0;
+        engine.reload_config(config);
+        type_text(&mut engine, ";g ;quiet ");
+        assert_eq!(notifier.sent()[1..], ["Text Expanded: ;g [undo]"]);
+    }
+
+    #[test]
+    fn expansion_notifications_show_the_rule_label() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.notifications.on_expansion = true;
+        config.notifications.min_interval_ms = 0;
+        config.expansions[0].label = Some("Greeting".to_string());
+        config.expansions.push(ExpansionRule {
+            trigger: ";x1".to_string(),
+            expansion: "shipping".to_string(),
+            label: Some("Support reply".to_string()),
+            notify_message: Some("Sent the shipping reply".to_string()),
+            ..ExpansionRule::default()
+        });
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut engine = Engine::new(config);
+        engine.set_notifier(notifier.clone());
+
+        type_text(&mut engine, ";g ;x1");
+
+        assert_eq!(
+            notifier.sent(),
+            vec![
+                "Text Expanded: Greeting [undo]",
+                "Text Expanded: Sent the shipping reply [undo]"
+            ]
+        );
+    }
+
+    #[test]
+    fn undo_last_erases_expansion_and_retypes_the_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Boundary));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g ");
+        assert!(engine.undo_last().expect("undo should succeed"));
+        assert!(!engine.undo_last().expect("second undo is a no-op"));
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 3", "type hello ", "backspace 6", "type ;g "]
+        );
+    }
+
+    #[test]
+    fn undo_is_unavailable_after_typing_or_for_key_macros() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions.push(ExpansionRule {
+            trigger: ";k".to_string(),
+            expansion: "hi{{KEY:ENTER}}".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";gx");
+        assert!(!engine.undo_last().expect("undo should not fail"));
+
+        type_text(&mut engine, ";k");
+        assert!(!engine.undo_last().expect("undo should not fail"));
+        assert_eq!(sink.backspaces.lock().expect("mutex poisoned").len(), 2);
+    }
+
+    #[test]
+    fn execute_expansion_counts_fires_and_chars_saved() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions.push(ExpansionRule {
+            trigger: ";longer".to_string(),
+            expansion: "hi".to_string(),
+            ..ExpansionRule::default()
+        });
+        let mut engine = Engine::new(config);
+
+        type_text(&mut engine, ";g;g;longer");
+
+        let stats = engine.stats();
+        assert_eq!(stats.triggers[";g"].count, 2);
+        assert_eq!(stats.triggers[";g"].chars_saved, 6);
+        assert_eq!(stats.triggers[";longer"].count, 1);
+        assert_eq!(stats.triggers[";longer"].chars_saved, -5);
+
+        let snapshot = engine.take_dirty_stats().expect("stats should be dirty");
+        assert_eq!(snapshot.totals().count, 3);
+        assert!(engine.take_dirty_stats().is_none());
+    }
+
+    #[test]
+    fn clipboard_mode_pastes_text_runs_between_actions_in_order() {
+        let mut config = test_config(MatchBehavior::Boundary);
+        config.inject_mode = InjectMode::Clipboard;
+        config.expansions[0].expansion = "Best,{{KEY:ENTER}}Tyler".to_string();
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g ");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 3".to_string(),
+                "paste Best,".to_string(),
+                "type [KEY Enter]".to_string(),
+                "paste Tyler ".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn rule_inject_mode_overrides_global_default() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.inject_mode = InjectMode::Clipboard;
+        config.expansions[0].inject_mode = Some(InjectMode::Type);
+        config.expansions.push(ExpansionRule {
+            trigger: ";p".to_string(),
+            expansion: "pasted".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g;p");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 2".to_string(),
+                "type hello".to_string(),
+                "backspace 2".to_string(),
+                "paste pasted".to_string(),
+            ]
+        );
+    }
+
+    /// A [`RecordingSink`] that only supports `capabilities`.
+    struct LimitedSink {
+        capabilities: SinkCapabilities,
+        inner: RecordingSink,
+    }
+
+    impl LimitedSink {
+        fn new(capabilities: SinkCapabilities) -> Self {
+            Self {
+                capabilities,
+                inner: RecordingSink::default(),
+            }
+        }
+    }
+
+    impl OutputSink for LimitedSink {
+        fn send_backspaces(&self, count: usize) -> Result<()> {
+            self.inner.send_backspaces(count)
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            self.inner.send_actions(actions)
+        }
+
+        fn send_paste(&self, text: &str) -> Result<()> {
+            self.inner.send_paste(text)
+        }
+
+        fn capabilities(&self) -> SinkCapabilities {
+            self.capabilities
+        }
+    }
+
+    #[test]
+    fn clipboard_rules_are_typed_with_a_warning_when_the_sink_cannot_paste() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].inject_mode = Some(InjectMode::Clipboard);
+        let sink = Arc::new(LimitedSink::new(SinkCapabilities::NONE));
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        assert_eq!(
+            engine.capability_warnings(),
+            [ValidationWarning::Unsupported {
+                trigger: ";g".to_string(),
+                feature: OutputFeature::Paste,
+            }]
+        );
+        type_text(&mut engine, ";g");
+        assert_eq!(
+            *sink.inner.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 2", "type hello"]
+        );
+
+        engine.set_output(Arc::new(RecordingSink::default()));
+        assert!(engine.capability_warnings().is_empty());
+    }
+
+    #[test]
+    fn sink_capabilities_reject_key_chords_and_chunk_text() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "héllo{{KEY:CTRL+S}}".to_string();
+        config.expansions.push(ExpansionRule {
+            trigger: ";l".to_string(),
+            expansion: "abcdefg".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(LimitedSink::new(SinkCapabilities {
+            max_text_chunk: Some(3),
+            ..SinkCapabilities::NONE
+        }));
+        let mut engine = Engine::new(config.clone());
+        engine.set_output(sink.clone());
+
+        let warnings: Vec<String> = engine
+            .capability_warnings()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "trigger ';g' presses a key chord, which the output backend cannot send, so it \
+                 fails when it fires",
+                "trigger ';g' types non-ASCII text, which the output backend may not be able to \
+                 type",
+            ]
+        );
+
+        engine
+            .handle_event(press_char(';'))
+            .expect("event should work");
+        let err = engine
+            .handle_event(press_char('g'))
+            .expect_err("key chords are unsupported");
+        assert_eq!(
+            err.to_string(),
+            "the output backend cannot press key chords"
+        );
+        type_text(&mut engine, ";l");
+        assert_eq!(
+            *sink.inner.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 2", "type abcdefg"]
+        );
+        assert_eq!(
+            emitted_texts(&sink.inner),
+            vec![vec!["abc".to_string(), "def".to_string(), "g".to_string()]]
+        );
+    }
+
+    #[test]
+    fn rule_char_delay_is_passed_to_output() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].char_delay_ms = Some(20);
+        config.expansions.push(ExpansionRule {
+            trigger: ";f".to_string(),
+            expansion: "fast".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g;f");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 2".to_string(),
+                "type(20ms) hello".to_string(),
+                "backspace 2".to_string(),
+                "type fast".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn injected_modifier_release_leaves_the_held_modifier_down() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        engine
+            .handle_event(press_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        engine
+            .handle_event(KeyEvent {
+                is_injected: true,
+                ..release_special(SpecialInputKey::CtrlLeft)
+            })
+            .expect("event should work");
+        type_text(&mut engine, ";g");
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+
+        engine
+            .handle_event(release_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 2".to_string(), "type hello".to_string()]
+        );
+    }
+
+    #[test]
+    fn key_chords_are_sent_in_order() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "{{KEY:SHIFT+HOME}}{{KEY:CTRL+x}}cut".to_string();
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 2".to_string(),
+                "type [KEY Shift+Home][KEY Ctrl+x]cut".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_keys_reach_the_sink_individually() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "fn(){{KEY:BACKSPACE*3}}".to_string();
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g");
+
+        let actions = sink.actions.lock().expect("mutex poisoned");
+        let backspace_keys = actions[0]
+            .iter()
+            .filter(|action| matches!(action, OutputAction::Key(SpecialKey::Backspace)))
+            .count();
+        assert_eq!(backspace_keys, 3);
+    }
+
+    #[test]
+    fn disabled_rules_never_fire() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].enabled = Some(false);
+        config.expansions.push(ExpansionRule {
+            trigger: ";gx".to_string(),
+            expansion: "longer".to_string(),
+            ..ExpansionRule::default()
+        });
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        // With ";g" switched off there is nothing to defer for, so ";gx" fires.
+        type_text(&mut engine, ";g ;gx");
+
+        assert_eq!(emitted_texts(&sink), vec![vec!["longer".to_string()]]);
+        assert_eq!(engine.max_trigger_chars, 3);
+    }
+
+    #[test]
+    fn enabling_a_group_at_runtime_makes_its_rules_fire() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].group = Some("xmas".to_string());
+        config.expansions.push(ExpansionRule {
+            trigger: ";long-xmas".to_string(),
+            expansion: "ho ho ho".to_string(),
+            group: Some("xmas".to_string()),
+            ..ExpansionRule::default()
+        });
+        config.disabled_groups = vec!["xmas".to_string()];
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config.clone());
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g ");
+        assert!(sink.actions.lock().expect("mutex poisoned").is_empty());
+        assert_eq!(engine.disabled_groups(), vec!["xmas"]);
+        assert_eq!(engine.max_trigger_chars, 0);
+
+        engine
+            .set_group_enabled("xmas", true)
+            .expect("group should exist");
+        type_text(&mut engine, ";g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+        assert_eq!(engine.max_trigger_chars, 10);
+
+        engine
+            .set_group_enabled("xmas", false)
+            .expect("group should exist");
+        type_text(&mut engine, " ;g");
+        assert_eq!(emitted_texts(&sink).len(), 1);
+
+        let err = engine
+            .set_group_enabled("easter", true)
+            .expect_err("unknown group should fail");
+        assert!(err
+            .to_string()
+            .contains("no expansion is in group 'easter'"));
+
+        engine
+            .set_group_enabled("xmas", true)
+            .expect("group should exist");
+        engine.reload_config(config);
+        assert_eq!(
+            engine.disabled_groups(),
+            vec!["xmas"],
+            "reload restores the file's groups"
+        );
+    }
+
+    fn boundary_expansion_calls(
+        keep_boundary: Option<KeepBoundary>,
+        expansion: &str,
+        boundary: KeyEvent,
+    ) -> Vec<String> {
+        let mut config = test_config(MatchBehavior::Boundary);
+        config.expansions[0].expansion = expansion.to_string();
+        config.expansions[0].keep_boundary = keep_boundary;
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g");
+        engine.handle_event(boundary).expect("event should work");
+
+        let calls = sink.calls.lock().expect("mutex poisoned").clone();
+        calls
+    }
+
+    #[test]
+    fn keep_boundary_retypes_or_swallows_the_boundary() {
+        let cases = [
+            (None, press_char(' '), "type hello "),
+            (
+                None,
+                press_special(SpecialInputKey::Tab),
+                "type hello[KEY Tab]",
+            ),
+            (
+                Some(KeepBoundary::Keep),
+                press_special(SpecialInputKey::Enter),
+                "type hello[KEY Enter]",
+            ),
+            (Some(KeepBoundary::Swallow), press_char('.'), "type hello"),
+            (
+                Some(KeepBoundary::Swallow),
+                press_special(SpecialInputKey::Enter),
+                "type hello",
+            ),
+            (
+                Some(KeepBoundary::Swallow),
+                press_special(SpecialInputKey::Tab),
+                "type hello",
+            ),
+        ];
+
+        for (keep_boundary, boundary, typed) in cases {
+            assert_eq!(
+                boundary_expansion_calls(keep_boundary, "hello", boundary),
+                vec!["backspace 3", typed],
+                "keep_boundary: {keep_boundary:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn smart_keep_boundary_swallows_only_after_trailing_whitespace() {
+        let cases = [
+            ("hello", press_char(' '), "type hello "),
+            (
+                "hello",
+                press_special(SpecialInputKey::Tab),
+                "type hello[KEY Tab]",
+            ),
+            ("hello ", press_char(','), "type hello "),
+            ("Hi,\n", press_special(SpecialInputKey::Enter), "type Hi,\n"),
+            (
+                "Hi,{{KEY:ENTER}}",
+                press_special(SpecialInputKey::Enter),
+                "type Hi,[KEY Enter]",
+            ),
+            (
+                "Hi,{{KEY:ENTER}}{{SLEEP_MS:5}}",
+                press_char(' '),
+                "type Hi,[KEY Enter][SLEEP 5ms]",
+            ),
+        ];
+
+        for (expansion, boundary, typed) in cases {
+            assert_eq!(
+                boundary_expansion_calls(Some(KeepBoundary::Smart), expansion, boundary),
+                vec!["backspace 3", typed],
+                "expansion: {expansion:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn newline_mode_decides_how_a_multi_line_expansion_is_typed() {
+        let cases = [
+            (NewlineMode::Text, "type Hi,\nBest,[KEY Enter]Tyler\n"),
+            (
+                NewlineMode::Enter,
+                "type Hi,[KEY Enter]Best,[KEY Enter]Tyler[KEY Enter]",
+            ),
+            (
+                NewlineMode::ShiftEnter,
+                "type Hi,[KEY Shift+Enter]Best,[KEY Enter]Tyler[KEY Shift+Enter]",
+            ),
+            (NewlineMode::Strip, "type Hi, Best,[KEY Enter]Tyler "),
+        ];
+
+        for (mode, typed) in cases {
+            let mut config = test_config(MatchBehavior::Immediate);
+            config.expansions[0].expansion = "Hi,\nBest,{{KEY:ENTER}}{{NAME}}\n".to_string();
+            config.expansions[0].newline = mode;
+            config
+                .globals
+                .insert("NAME".to_string(), "Tyler".to_string());
+            let sink = Arc::new(RecordingSink::default());
+            let mut engine = Engine::new(config);
+            engine.set_output(sink.clone());
+
+            type_text(&mut engine, ";g");
+
+            assert_eq!(
+                *sink.calls.lock().expect("mutex poisoned"),
+                vec!["backspace 2", typed],
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn boundary_position_orders_the_boundary_around_trailing_caret_moves() {
+        let cases = [
+            (None, "f(){{MOVE_CARET:-1}}", "type f()[CARET -1] "),
+            (
+                Some(BoundaryPosition::Before),
+                "f(){{MOVE_CARET:-1}}",
+                "type f() [CARET -1]",
+            ),
+            (
+                Some(BoundaryPosition::Before),
+                "<b></b>{{MOVE_CARET:-4}}{{SLEEP_MS:5}}",
+                "type <b></b> [CARET -4][SLEEP 5ms]",
+            ),
+            (
+                Some(BoundaryPosition::Before),
+                "{{MOVE_CARET:-1}}x",
+                "type [CARET -1]x ",
+            ),
+            (Some(BoundaryPosition::Before), "hello", "type hello "),
+            (None, "Dear NAME{{SELECT:-4}}", "type Dear NAME [SELECT -4]"),
+        ];
+
+        for (position, expansion, typed) in cases {
+            let mut config = test_config(MatchBehavior::Boundary);
+            config.expansions[0].expansion = expansion.to_string();
+            config.expansions[0].boundary_position = position;
+            let sink = Arc::new(RecordingSink::default());
+            let mut engine = Engine::new(config);
+            engine.set_output(sink.clone());
+
+            type_text(&mut engine, ";g ");
+
+            assert_eq!(
+                *sink.calls.lock().expect("mutex poisoned"),
+                vec!["backspace 3", typed],
+                "{position:?} {expansion:?}"
+            );
+        }
+
+        let mut config = test_config(MatchBehavior::Boundary);
+        config.expansions[0].expansion = "f(){{MOVE_CARET:-1}}".to_string();
+        config.boundary_position = BoundaryPosition::Before;
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        type_text(&mut engine, ";g");
+        engine
+            .handle_event(press_special(SpecialInputKey::Tab))
+            .expect("event should work");
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 3", "type f()[KEY Tab][CARET -1]"]
+        );
+    }
+
+    #[test]
+    fn keep_boundary_accepts_booleans_and_smart() {
+        let config: AppConfig = serde_yaml::from_str(
+            "expansions:\n  - {trigger: ';a', expansion: a, keep_boundary: false}\n  - {trigger: ';b', expansion: b, keep_boundary: smart}\n  - {trigger: ';c', expansion: c, keep_boundary: true}\n",
+        )
+        .expect("config should parse");
+
+        let keep: Vec<_> = config
+            .expansions
+            .iter()
+            .map(|rule| rule.keep_boundary)
+            .collect();
+        assert_eq!(
+            keep,
+            vec![
+                Some(KeepBoundary::Swallow),
+                Some(KeepBoundary::Smart),
+                Some(KeepBoundary::Keep)
+            ]
+        );
+        let err = serde_yaml::from_str::<AppConfig>(
+            "expansions:\n  - {trigger: ';a', expansion: a, keep_boundary: sometimes}\n",
+        )
+        .expect_err("unknown value should fail");
+        assert!(err.to_string().contains("true, false or \"smart\""));
+        assert_eq!(
+            serde_yaml::to_string(&config.expansions[1]).expect("serialize"),
+            "trigger: ;b\nexpansion: b\nkeep_boundary: smart\n"
+        );
+    }
+
+    /// Forwards to a [`RecordingSink`] one action at a time, sleeping before
+    /// each like a long expansion, and stops early once cancelled.
+    #[derive(Default)]
+    struct SlowSink {
+        delay: Duration,
+        cancelled: AtomicBool,
+        inner: RecordingSink,
+    }
+
+    impl OutputSink for SlowSink {
+        fn send_backspaces(&self, count: usize) -> Result<()> {
+            self.cancelled.store(false, Ordering::SeqCst);
+            self.inner.send_backspaces(count)
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            for action in actions {
+                std::thread::sleep(self.delay);
+                if self.cancelled.load(Ordering::SeqCst) {
+                    return Err(OutputCancelled.into());
+                }
+                self.inner.send_actions(std::slice::from_ref(action))?;
+            }
+            Ok(())
+        }
+
+        fn cancel(&self) {
+            self.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn output_worker_lets_handle_event_return_before_a_slow_expansion_finishes() {
+        let sink = Arc::new(SlowSink {
+            delay: Duration::from_millis(300),
+            ..SlowSink::default()
+        });
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output_worker(
+            OutputWorker::spawn(sink.clone(), OutputWorker::DEFAULT_CAPACITY)
+                .expect("worker should start"),
+        );
+
+        let started = Instant::now();
+        type_text(&mut engine, ";g");
+        assert!(started.elapsed() < Duration::from_millis(150));
+        assert!(sink.inner.calls.lock().expect("mutex poisoned").len() < 2);
+
+        engine.wait_for_output();
+        assert_eq!(
+            *sink.inner.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 2", "type hello"]
+        );
+    }
+
+    #[test]
+    fn output_worker_runs_expansions_in_order_while_typing_continues() {
+        let sink = Arc::new(SlowSink {
+            delay: Duration::from_millis(50),
+            ..SlowSink::default()
+        });
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions.push(ExpansionRule {
+            trigger: ";b".to_string(),
+            expansion: "bye".to_string(),
+            ..ExpansionRule::default()
+        });
+        let mut engine = Engine::new(config);
+        engine.set_output_worker(
+            OutputWorker::spawn(sink.clone(), OutputWorker::DEFAULT_CAPACITY)
+                .expect("worker should start"),
+        );
+
+        // The second trigger is typed while the first expansion is still
+        // being sent and must match against the buffer as typed.
+        type_text(&mut engine, ";g x ;b");
+        engine.wait_for_output();
+
+        assert_eq!(
+            *sink.inner.calls.lock().expect("mutex poisoned"),
+            vec!["backspace 2", "type hello", "backspace 2", "type bye"]
+        );
+        let triggers: Vec<&str> = engine
+            .history()
+            .iter()
+            .map(|record| record.trigger.as_str())
+            .collect();
+        assert_eq!(triggers, vec![";g", ";b"]);
+        engine
+            .take_output_worker()
+            .expect("worker should be set")
+            .shutdown();
+    }
+
+    #[test]
+    fn escape_cancels_the_expansion_being_typed() {
+        let sink = Arc::new(SlowSink {
+            delay: Duration::from_millis(40),
+            ..SlowSink::default()
+        });
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion =
+            "one{{KEY:Tab}}two{{KEY:Tab}}three{{KEY:Tab}}four".to_string();
+        config.expansions[0].notify = Some(true);
+        let mut engine = Engine::new(config);
+        engine.set_notifier(notifier.clone());
+        engine.set_output_worker(
+            OutputWorker::spawn(sink.clone(), OutputWorker::DEFAULT_CAPACITY)
+                .expect("worker should start"),
+        );
+
+        type_text(&mut engine, ";g");
+        std::thread::sleep(Duration::from_millis(100));
+        engine
+            .handle_event(press_special(SpecialInputKey::Escape))
+            .expect("escape should be handled");
+        engine.wait_for_output();
+
+        let all = [
+            "type one",
+            "type [KEY Tab]",
+            "type two",
+            "type [KEY Tab]",
+            "type three",
+            "type [KEY Tab]",
+            "type four",
+        ];
+        let calls = sink.inner.calls.lock().expect("mutex poisoned").clone();
+        let typed = &calls[1..];
+        assert_eq!(calls[0], "backspace 2");
+        assert!(!typed.is_empty() && typed.len() < all.len(), "{calls:?}");
+        assert_eq!(typed, &all[..typed.len()]);
+        assert!(notifier.sent().is_empty());
+    }
+
+    #[test]
+    fn altgr_typed_trigger_character_fires_without_waiting_for_release() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].trigger = "tg@".to_string();
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, "tg");
+        engine
+            .handle_event(press_special(SpecialInputKey::AltGr))
+            .expect("event should work");
+        type_text(&mut engine, "@");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[3]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+        engine
+            .handle_event(release_special(SpecialInputKey::AltGr))
+            .expect("event should work");
+        assert_eq!(sink.backspaces.lock().expect("mutex poisoned").len(), 1);
+    }
+
+    #[test]
+    fn expansion_waits_until_both_shift_keys_are_released() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        for key in [SpecialInputKey::ShiftLeft, SpecialInputKey::ShiftRight] {
+            engine
+                .handle_event(press_special(key))
+                .expect("event should work");
+        }
+        type_text(&mut engine, ";g");
+        engine
+            .handle_event(release_special(SpecialInputKey::ShiftLeft))
+            .expect("event should work");
+        assert!(sink.backspaces.lock().expect("mutex poisoned").is_empty());
+
+        engine
+            .handle_event(release_special(SpecialInputKey::ShiftRight))
+            .expect("event should work");
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[2]);
+    }
+
+    fn press_text(text: &str) -> KeyEvent {
+        KeyEvent {
+            kind: KeyEventKind::Press,
+            text: Some(text.to_string()),
+            special: Some(SpecialInputKey::Unknown(0)),
+            is_injected: false,
+        }
+    }
+
+    #[test]
+    fn dead_key_composed_character_completes_a_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].trigger = ";café".to_string();
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";caf");
+        // The dead acute commits nothing; the following `e` commits "é".
+        engine
+            .handle_event(press_text(""))
+            .expect("dead key should be handled");
+        engine
+            .handle_event(press_text("é"))
+            .expect("composed key should be handled");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[5]);
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
+
+    #[test]
+    fn multi_character_commit_is_added_to_the_buffer_in_full() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Boundary));
+        engine.set_output(sink.clone());
+
+        engine
+            .handle_event(press_text(";g"))
+            .expect("compose sequence should be handled");
+        type_text(&mut engine, " ");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[3]);
+    }
+
+    fn key_event(kind: KeyEventKind, text: Option<&str>, key: SpecialInputKey) -> KeyEvent {
+        KeyEvent {
+            kind,
+            text: text.map(String::from),
+            special: Some(key),
+            is_injected: false,
+        }
+    }
+
+    #[test]
+    fn numpad_digit_types_into_a_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Boundary);
+        config.expansions[0].trigger = ";n1".to_string();
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";n");
+        // Keypad 1 with NumLock on: the backend names the key End, but it
+        // commits "1", and releasing it must not touch the buffer.
+        for kind in [KeyEventKind::Press, KeyEventKind::Release] {
+            let text = (kind == KeyEventKind::Press).then_some("1");
+            engine
+                .handle_event(key_event(kind, text, SpecialInputKey::End))
+                .expect("keypad event should work");
+        }
+        type_text(&mut engine, " ");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[4]);
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["hello".to_string(), " ".to_string()]]
+        );
+    }
+
+    #[test]
+    fn keypad_navigation_without_text_clears_the_buffer() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";");
+        engine
+            .handle_event(key_event(KeyEventKind::Press, None, SpecialInputKey::Home))
+            .expect("keypad event should work");
+        type_text(&mut engine, "g");
+
+        assert!(sink.calls.lock().expect("mutex poisoned").is_empty());
+    }
+
+    #[test]
+    fn unknown_key_keeps_the_buffer_unless_it_is_a_shortcut() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";");
+        engine
+            .handle_event(press_special(SpecialInputKey::Unknown(77)))
+            .expect("unknown key should work");
+        type_text(&mut engine, "g");
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+
+        // Ctrl+V arrives as a control character on an unnamed key.
+        type_text(&mut engine, ";");
+        engine
+            .handle_event(press_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        engine
+            .handle_event(key_event(
+                KeyEventKind::Press,
+                Some("\u{16}"),
+                SpecialInputKey::Unknown(0),
+            ))
+            .expect("shortcut should work");
+        engine
+            .handle_event(release_special(SpecialInputKey::CtrlLeft))
+            .expect("event should work");
+        type_text(&mut engine, "g");
+        assert_eq!(emitted_texts(&sink).len(), 1);
+    }
+
+    #[test]
+    fn enter_reported_with_text_still_completes_a_boundary_trigger() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Boundary));
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, ";g");
+        engine
+            .handle_event(key_event(
+                KeyEventKind::Press,
+                Some("\r"),
+                SpecialInputKey::Enter,
+            ))
+            .expect("enter should work");
+
+        assert_eq!(
+            *sink.calls.lock().expect("mutex poisoned"),
+            vec![
+                "backspace 3".to_string(),
+                "type hello[KEY Enter]".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn buffer_keeps_the_longest_trigger_plus_slack_across_multibyte_text() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].trigger = ";ünï".to_string();
+        config.buffer_slack = 2;
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+
+        type_text(&mut engine, "ßßßßéé🙂e\u{301};ün");
+        assert_eq!(engine.buffer_len(), 6);
+        type_text(&mut engine, "ï");
+
+        assert_eq!(&*sink.backspaces.lock().expect("mutex poisoned"), &[4]);
+    }
+
+    #[test]
+    fn typing_a_long_stream_stays_linear() {
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].trigger = format!(";{}", "x".repeat(200));
+        let mut engine = Engine::new(config);
+        let text: String = "lorem ipsum dolor sit amet ".repeat(800);
+
+        let started = Instant::now();
+        for c in text.chars() {
+            engine
+                .handle_event(press_char(c))
+                .expect("event should work");
+            assert!(engine.buffer_len() <= 209);
+        }
+
+        // Generous for debug builds; quadratic work on 20k keys takes far longer.
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn cached_cmd_output_is_reused_until_the_config_is_reloaded() {
+        let sink = Arc::new(RecordingSink::default());
+        let runner = Arc::new(
+            MockRunner::default().with_output("curl wttr.in", CommandOutput::success("sunny\n")),
+        );
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "{{CMD:cache=300:curl wttr.in}}".to_string();
+        let mut engine = Engine::new(config.clone());
+        engine.set_output(sink.clone());
+        engine.set_command_runner(runner.clone());
+
+        type_text(&mut engine, ";g;g");
+        assert_eq!(runner.calls().len(), 1);
+        engine.reload_config(config);
+        type_text(&mut engine, ";g");
+
+        assert_eq!(runner.calls().len(), 2);
+        assert_eq!(
+            emitted_texts(&sink),
+            vec![vec!["sunny"], vec!["sunny"], vec!["sunny"]]
+        );
+    }
+
+    #[test]
+    fn disallowed_commands_do_not_reach_the_runner() {
+        let sink = Arc::new(RecordingSink::default());
+        let runner =
+            Arc::new(MockRunner::default().with_output("whoami", CommandOutput::success("tyler")));
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "{{CMD:whoami}}".to_string();
+        config.security.allow_commands = false;
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        engine.set_command_runner(runner.clone());
+
+        engine
+            .handle_event(press_char(';'))
+            .expect("event should work");
+        let err = engine
+            .handle_event(press_char('g'))
+            .expect_err("the command should be refused");
+
+        assert!(err.to_string().contains("CMD macros are disabled"), "{err}");
+        assert!(runner.calls().is_empty());
+        assert!(emitted_texts(&sink).is_empty());
+    }
+
+    #[test]
+    fn confirmed_commands_run_when_the_trigger_is_typed_again() {
+        for match_behavior in [MatchBehavior::Immediate, MatchBehavior::Boundary] {
+            let sink = Arc::new(RecordingSink::default());
+            let notifier = Arc::new(RecordingNotifier::default());
+            let runner = Arc::new(
+                MockRunner::default()
+                    .with_output("pass show email", CommandOutput::success("me@example.com"))
+                    .with_output("date", CommandOutput::success("today"))
+                    .with_output("whoami", CommandOutput::success("me")),
+            );
+            let mut config = test_config(match_behavior);
+            config.expansions[0].expansion = "{{CMD:pass show email}} {{CMD:date}}".to_string();
+            config.expansions[0].notify = Some(true);
+            config.expansions[0].notify_message = Some("{{CMD:whoami}}".to_string());
+            config.security.confirm_commands = true;
+            let mut engine = Engine::new(config);
+            engine.set_output(sink.clone());
+            engine.set_notifier(notifier.clone());
+            engine.set_command_runner(runner.clone());
+
+            type_text(&mut engine, "x;");
+            let err = engine
+                .handle_event(press_char('g'))
+                .and_then(|()| engine.handle_event(press_char(' ')))
+                .expect_err("the first trigger should ask for confirmation");
+            let required = err
+                .downcast_ref::<ConfirmationRequired>()
+                .expect("confirmation error");
+            assert_eq!(required.commands, vec!["pass show email", "date"]);
+            assert!(runner.calls().is_empty());
+            // The trigger is taken back so it can simply be typed again.
+            let erased = match match_behavior {
+                MatchBehavior::Immediate => vec!["backspace 2", "type "],
+                MatchBehavior::Boundary => vec!["backspace 3", "type  "],
+            };
+            assert_eq!(*sink.calls.lock().expect("mutex poisoned"), erased);
+
+            type_text(&mut engine, ";g ");
+
+            assert_eq!(runner.calls(), vec!["pass show email", "date"]);
+            let texts = emitted_texts(&sink);
+            assert!(
+                texts.last().expect("expansion")[0].starts_with("me@example.com today"),
+                "{texts:?}"
+            );
+            // notify_message commands are never confirmed, so never run.
+            let sent = notifier.sent();
+            assert!(sent[0].contains("{{CMD:whoami}}"), "{sent:?}");
+        }
+    }
+
+    #[test]
+    fn secrets_are_typed_but_masked_in_notifications() {
+        let sink = Arc::new(RecordingSink::default());
+        let notifier = Arc::new(RecordingNotifier::default());
+        let mut config = test_config(MatchBehavior::Immediate);
+        config.expansions[0].expansion = "{{SECRET:api-key}}".to_string();
+        config.expansions[0].notify = Some(true);
+        config.expansions[0].notify_message = Some("typed {{SECRET:api-key}}".to_string());
+        let mut engine = Engine::new(config);
+        engine.set_output(sink.clone());
+        engine.set_notifier(notifier.clone());
+        engine.set_secret_store(Arc::new(
+            MemorySecretStore::default().with_secret("api-key", "sk-live-42"),
+        ));
+
+        type_text(&mut engine, ";g");
+
+        assert_eq!(emitted_texts(&sink), vec![vec!["sk-live-42"]]);
+        let sent = notifier.sent();
+        assert_eq!(sent.len(), 1);
+        assert!(
+            sent[0].contains(&format!("typed {SECRET_MASK}")),
+            "{sent:?}"
+        );
+        assert!(!sent[0].contains("sk-live-42"));
+    }
+
+    /// Panics on the first expansion it is asked to type, then records.
+    #[derive(Default)]
+    struct PanickingSink {
+        panicked: AtomicBool,
+        inner: RecordingSink,
+    }
+
+    impl OutputSink for PanickingSink {
+        fn send_backspaces(&self, count: usize) -> Result<()> {
+            self.inner.send_backspaces(count)
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            if !self.panicked.swap(true, Ordering::SeqCst) {
+                panic!("sink bug");
+            }
+            self.inner.send_actions(actions)
+        }
+    }
+
+    #[test]
+    fn a_panicking_sink_does_not_stop_later_events() {
+        let sink = Arc::new(PanickingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+        let engine = Mutex::new(engine);
+
+        handle_shared_event(&engine, press_char(';')).expect("event should work");
+        let err = handle_shared_event(&engine, press_char('g')).expect_err("sink panicked");
+        assert_eq!(err.to_string(), "expansion panicked: sink bug");
+        assert!(!engine.is_poisoned());
+        assert_eq!(engine.lock().expect("engine mutex").buffer_len(), 0);
+
+        for c in ";g".chars() {
+            handle_shared_event(&engine, press_char(c)).expect("event should work");
+        }
+        assert_eq!(emitted_texts(&sink.inner), vec![vec!["hello".to_string()]]);
+    }
+
+    #[test]
+    fn a_poisoned_engine_is_recovered_with_its_typed_context_cleared() {
+        let sink = Arc::new(RecordingSink::default());
+        let mut engine = Engine::new(test_config(MatchBehavior::Immediate));
+        engine.set_output(sink.clone());
+        let engine = Mutex::new(engine);
+        handle_shared_event(&engine, press_char(';')).expect("event should work");
+
+        std::thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _guard = engine.lock().expect("engine mutex");
+                panic!("poison the engine");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        assert!(engine.is_poisoned());
+
+        // The `;` typed before the panic is gone, so `g` alone fires nothing.
+        handle_shared_event(&engine, press_char('g')).expect("event should work");
+        assert!(!engine.is_poisoned());
+        assert!(emitted_texts(&sink).is_empty());
+        for c in ";g".chars() {
+            handle_shared_event(&engine, press_char(c)).expect("event should work");
+        }
+        assert_eq!(emitted_texts(&sink), vec![vec!["hello".to_string()]]);
+    }
 }
@@ -0,0 +1,141 @@
+use std::path::Path;
+
+/// Result of [`expand_env`]: the substituted text plus the names of
+/// variables that were not set and so were left as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvExpansion {
+    pub text: String,
+    pub missing: Vec<String>,
+}
+
+/// Replaces `${VAR}` with `lookup(VAR)` and a leading `~/` with `home`.
+///
+/// Only `${NAME}` with a plain identifier counts, so `${{DATE}}` (a dollar
+/// sign before a template macro) is left alone. `$${VAR}` is an escape for
+/// a literal `${VAR}`, and unset variables stay as written.
+pub fn expand_env(
+    input: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    home: Option<&Path>,
+) -> EnvExpansion {
+    let mut text = String::with_capacity(input.len());
+    let mut missing = Vec::new();
+    let mut rest = input;
+
+    if let (Some(after), Some(home)) = (rest.strip_prefix("~/"), home) {
+        text.push_str(&home.to_string_lossy());
+        text.push('/');
+        rest = after;
+    }
+
+    while let Some(dollar) = rest.find('$') {
+        text.push_str(&rest[..dollar]);
+        let after = &rest[dollar..];
+
+        if let Some(escaped) = after.strip_prefix("$${") {
+            text.push_str("${");
+            rest = escaped;
+            continue;
+        }
+        match variable_name(after) {
+            Some(name) => {
+                let reference = &after[..name.len() + 3];
+                match lookup(name) {
+                    Some(value) => text.push_str(&value),
+                    None => {
+                        text.push_str(reference);
+                        if !missing.iter().any(|known| known == name) {
+                            missing.push(name.to_string());
+                        }
+                    }
+                }
+                rest = &after[reference.len()..];
+            }
+            None => {
+                text.push('$');
+                rest = &after[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+
+    EnvExpansion { text, missing }
+}
+
+/// The `NAME` of a `${NAME}` at the start of `input`.
+fn variable_name(input: &str) -> Option<&str> {
+    let body = input.strip_prefix("${")?;
+    let end = body.find('}')?;
+    let name = &body[..end];
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_env, EnvExpansion};
+    use std::path::Path;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "WORK_EMAIL" => Some("me@work.example.com".to_string()),
+            "HOME" => Some("/home/me".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    fn expand(input: &str) -> EnvExpansion {
+        expand_env(input, &lookup, Some(Path::new("/home/me")))
+    }
+
+    #[test]
+    fn substitutes_variables_and_leading_tilde() {
+        assert_eq!(expand("${WORK_EMAIL}").text, "me@work.example.com");
+        assert_eq!(expand("$HOME stays").text, "$HOME stays");
+        assert_eq!(expand("${HOME}/projects").text, "/home/me/projects");
+        assert_eq!(
+            expand("~/notes ~/not-this").text,
+            "/home/me/notes ~/not-this"
+        );
+        assert_eq!(expand("a${EMPTY}b").text, "ab");
+        assert_eq!(expand("~user/x").text, "~user/x");
+        assert_eq!(
+            expand_env("~/x", &lookup, None).text,
+            "~/x",
+            "without a home dir the tilde stays"
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_variables_and_reports_them_once() {
+        let expanded = expand("${NOPE} and ${NOPE} and ${ALSO_MISSING}");
+
+        assert_eq!(expanded.text, "${NOPE} and ${NOPE} and ${ALSO_MISSING}");
+        assert_eq!(expanded.missing, vec!["NOPE", "ALSO_MISSING"]);
+    }
+
+    #[test]
+    fn escaped_references_stay_literal() {
+        let expanded = expand("$${WORK_EMAIL} vs ${WORK_EMAIL}");
+
+        assert_eq!(expanded.text, "${WORK_EMAIL} vs me@work.example.com");
+        assert!(expanded.missing.is_empty());
+        assert_eq!(expand("costs $$5").text, "costs $$5");
+    }
+
+    #[test]
+    fn ignores_braces_that_are_not_variable_references() {
+        assert_eq!(expand("${{DATE}}").text, "${{DATE}}");
+        assert_eq!(expand("${A${WORK_EMAIL}}").text, "${Ame@work.example.com}");
+        assert_eq!(
+            expand("${} ${1X} ${unterminated").text,
+            "${} ${1X} ${unterminated"
+        );
+        assert_eq!(expand("trailing $").text, "trailing $");
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::config::ExpansionRule;
+
+/// Espanso's "put the cursor here" marker.
+const CURSOR_MARKER: &str = "$|$";
+
+/// The parts of an espanso match file slykey understands.
+#[derive(Debug, Default, Deserialize)]
+struct MatchFile {
+    #[serde(default)]
+    matches: Vec<EspansoMatch>,
+    #[serde(default)]
+    global_vars: Vec<EspansoVar>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EspansoMatch {
+    trigger: Option<String>,
+    #[serde(default)]
+    triggers: Vec<String>,
+    regex: Option<String>,
+    replace: Option<String>,
+    markdown: Option<String>,
+    html: Option<String>,
+    image_path: Option<String>,
+    form: Option<String>,
+    #[serde(default)]
+    vars: Vec<EspansoVar>,
+    #[serde(default)]
+    word: bool,
+    #[serde(default)]
+    left_word: bool,
+    #[serde(default)]
+    right_word: bool,
+    #[serde(default)]
+    propagate_case: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EspansoVar {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    params: serde_yaml::Mapping,
+}
+
+/// A feature of an espanso match that did not carry over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportIssue {
+    pub file: PathBuf,
+    /// The match's first trigger, or its regex.
+    pub trigger: String,
+    pub message: String,
+    /// The match was left out rather than converted approximately.
+    pub skipped: bool,
+}
+
+impl fmt::Display for ImportIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.file.display(),
+            self.trigger,
+            self.message
+        )?;
+        if self.skipped {
+            f.write_str(" (skipped)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts espanso match files into slykey expansion rules, collecting
+/// what could not be converted along the way.
+#[derive(Debug, Default)]
+pub struct EspansoImport {
+    pub rules: Vec<ExpansionRule>,
+    pub issues: Vec<ImportIssue>,
+    existing: HashSet<String>,
+    imported: HashSet<String>,
+}
+
+impl EspansoImport {
+    /// `existing_triggers` are already configured; matches reusing one are
+    /// skipped and reported.
+    pub fn new(existing_triggers: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            existing: existing_triggers.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Converts every match in the espanso YAML `raw`, read from `file`.
+    pub fn add_file(&mut self, file: &Path, raw: &str) -> Result<()> {
+        let parsed: MatchFile = serde_yaml::from_str(raw)
+            .with_context(|| format!("failed to parse espanso file: {}", file.display()))?;
+        for espanso_match in &parsed.matches {
+            self.add_match(file, espanso_match, &parsed.global_vars);
+        }
+        Ok(())
+    }
+
+    fn add_match(&mut self, file: &Path, espanso_match: &EspansoMatch, globals: &[EspansoVar]) {
+        let triggers: Vec<&String> = espanso_match
+            .trigger
+            .iter()
+            .chain(&espanso_match.triggers)
+            .collect();
+        let label = triggers
+            .first()
+            .copied()
+            .or(espanso_match.regex.as_ref())
+            .cloned()
+            .unwrap_or_else(|| "<no trigger>".to_string());
+        let mut issue = |message: String, skipped: bool| {
+            self.issues.push(ImportIssue {
+                file: file.to_path_buf(),
+                trigger: label.clone(),
+                message,
+                skipped,
+            })
+        };
+
+        if espanso_match.regex.is_some() {
+            return issue("regex triggers are not supported".to_string(), true);
+        }
+        if triggers.is_empty() {
+            return issue("match has no trigger".to_string(), true);
+        }
+        let Some(replace) = &espanso_match.replace else {
+            let kind = [
+                ("markdown", espanso_match.markdown.is_some()),
+                ("html", espanso_match.html.is_some()),
+                ("image_path", espanso_match.image_path.is_some()),
+                ("form", espanso_match.form.is_some()),
+            ]
+            .into_iter()
+            .find_map(|(kind, present)| present.then_some(kind));
+            let message = match kind {
+                Some(kind) => format!("{kind} replacements are not supported"),
+                None => "match has no replace text".to_string(),
+            };
+            return issue(message, true);
+        };
+
+        let (expansion, notes) = match convert_replacement(replace, &espanso_match.vars, globals) {
+            Ok(converted) => converted,
+            Err(message) => return issue(message, true),
+        };
+        for note in notes {
+            issue(note, false);
+        }
+        if espanso_match.word || espanso_match.left_word || espanso_match.right_word {
+            issue(
+                "word matching only applies with the global `match_behavior: boundary`".to_string(),
+                false,
+            );
+        }
+        if espanso_match.propagate_case {
+            issue(
+                "propagate_case is not supported; only the exact trigger expands".to_string(),
+                false,
+            );
+        }
+
+        for trigger in triggers {
+            let duplicate = if self.existing.contains(trigger) {
+                Some("trigger already exists in the config")
+            } else if !self.imported.insert(trigger.clone()) {
+                Some("trigger appears earlier in the import")
+            } else {
+                None
+            };
+            if let Some(message) = duplicate {
+                self.issues.push(ImportIssue {
+                    file: file.to_path_buf(),
+                    trigger: trigger.clone(),
+                    message: message.to_string(),
+                    skipped: true,
+                });
+                continue;
+            }
+            self.rules.push(ExpansionRule {
+                trigger: trigger.clone(),
+                expansion: expansion.clone(),
+                ..ExpansionRule::default()
+            });
+        }
+    }
+}
+
+/// `path` itself, or every `.yml`/`.yaml` file below it in sorted order.
+pub fn espanso_match_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    collect_yaml_files(path, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_yaml_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_yaml_files(&path, files)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == "yml" || ext == "yaml")
+        {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Converts an espanso `replace` text: variables are inlined as slykey
+/// macros and a `$|$` cursor marker becomes a trailing `{{MOVE_CARET:-N}}`.
+/// Returns the expansion plus notes about approximations, or why the match
+/// cannot be converted.
+fn convert_replacement(
+    replace: &str,
+    vars: &[EspansoVar],
+    globals: &[EspansoVar],
+) -> std::result::Result<(String, Vec<String>), String> {
+    let mut notes = Vec::new();
+    let (before, after) = match replace.split_once(CURSOR_MARKER) {
+        Some((before, after)) => (before, Some(after)),
+        None => (replace, None),
+    };
+
+    let mut expansion = substitute_vars(before, vars, globals, &mut notes)?;
+    if let Some(after) = after {
+        if after.contains(CURSOR_MARKER) {
+            return Err("more than one $|$ cursor marker".to_string());
+        }
+        let tail = substitute_vars(after, vars, globals, &mut notes)?;
+        expansion.push_str(&tail);
+        if tail.contains("{{") {
+            notes.push(
+                "a variable follows the $|$ cursor marker, so the cursor stays at the end"
+                    .to_string(),
+            );
+        } else if !tail.is_empty() {
+            expansion.push_str(&format!("{{{{MOVE_CARET:-{}}}}}", tail.chars().count()));
+        }
+    }
+    Ok((expansion, notes))
+}
+
+/// Replaces each `{{name}}` with the converted match-local or global var.
+fn substitute_vars(
+    text: &str,
+    vars: &[EspansoVar],
+    globals: &[EspansoVar],
+    notes: &mut Vec<String>,
+) -> std::result::Result<String, String> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unclosed {{ in replace text".to_string())?;
+        let name = after[..end].trim();
+        let var = vars
+            .iter()
+            .find(|var| var.name == name)
+            .or_else(|| globals.iter().find(|var| var.name == name));
+        match var {
+            Some(var) => out.push_str(&convert_var(var, notes)?),
+            None if name.contains('.') => {
+                return Err(format!("form field {{{{{name}}}}} is not supported"))
+            }
+            None => return Err(format!("unknown variable {{{{{name}}}}}")),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn convert_var(var: &EspansoVar, notes: &mut Vec<String>) -> std::result::Result<String, String> {
+    let param = |key: &str| var.params.get(key).and_then(serde_yaml::Value::as_str);
+    let missing = |key: &str| format!("{} variable '{}' has no {key} param", var.kind, var.name);
+
+    match var.kind.as_str() {
+        "echo" => param("echo")
+            .map(str::to_string)
+            .ok_or_else(|| missing("echo")),
+        "shell" => {
+            let cmd = param("cmd").ok_or_else(|| missing("cmd"))?;
+            if cmd.contains("{{") || cmd.contains("}}") {
+                return Err(format!(
+                    "shell variable '{}' uses braces or other variables",
+                    var.name
+                ));
+            }
+            if let Some(shell) = param("shell").filter(|shell| *shell != "sh") {
+                notes.push(format!(
+                    "shell variable '{}' runs with sh instead of {shell}",
+                    var.name
+                ));
+            }
+            Ok(format!("{{{{CMD:{cmd}}}}}"))
+        }
+        "date" => {
+            let format = param("format").ok_or_else(|| missing("format"))?;
+            let offset = match var.params.get("offset") {
+                None => 0,
+                Some(offset) => offset.as_i64().ok_or_else(|| {
+                    format!(
+                        "date variable '{}' has an offset that is not a number of seconds",
+                        var.name
+                    )
+                })?,
+            };
+            for ignored in ["locale", "tz"] {
+                if var.params.contains_key(ignored) {
+                    notes.push(format!(
+                        "date variable '{}': {ignored} is not supported and was dropped",
+                        var.name
+                    ));
+                }
+            }
+            date_macro(&var.name, format, offset)
+        }
+        other => Err(format!(
+            "variable '{}' of type {other} is not supported",
+            var.name
+        )),
+    }
+}
+
+/// Uses `{{DATE}}`, `{{TIME}}` or `{{DATETIME}}` for their formats and
+/// `{{DATE:...}}` for any other strftime format or an `offset` in seconds.
+fn date_macro(name: &str, format: &str, offset: i64) -> std::result::Result<String, String> {
+    let builtin = match format {
+        "%Y-%m-%d" => Some("DATE"),
+        "%H:%M:%S" => Some("TIME"),
+        "%Y-%m-%d %H:%M:%S" => Some("DATETIME"),
+        _ => None,
+    };
+    if let Some(builtin) = builtin.filter(|_| offset == 0) {
+        return Ok(format!("{{{{{builtin}}}}}"));
+    }
+    if format.contains("{{") || format.contains("}}") {
+        return Err(format!("date variable '{name}' has a format with braces"));
+    }
+    if offset == 0 {
+        return Ok(format!("{{{{DATE:{format}}}}}"));
+    }
+    Ok(format!("{{{{DATE:offset={offset}:{format}}}}}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{espanso_match_files, EspansoImport, ImportIssue};
+    use crate::config::ExpansionRule;
+    use crate::core::export::RuleSet;
+    use std::path::Path;
+
+    fn import(raw: &str) -> EspansoImport {
+        let mut import = EspansoImport::new([";taken".to_string()]);
+        import
+            .add_file(Path::new("base.yml"), raw)
+            .expect("espanso file should parse");
+        import
+    }
+
+    fn rule(trigger: &str, expansion: &str) -> ExpansionRule {
+        ExpansionRule {
+            trigger: trigger.to_string(),
+            expansion: expansion.to_string(),
+            ..ExpansionRule::default()
+        }
+    }
+
+    fn messages(import: &EspansoImport) -> Vec<String> {
+        import.issues.iter().map(ImportIssue::to_string).collect()
+    }
+
+    #[test]
+    fn converts_plain_matches_and_trigger_lists() {
+        let import = import(
+            r#"
+matches:
+  - trigger: ":sig"
+    replace: "Best regards,\nTyler"
+  - triggers: [":hi", ":hello"]
+    replace: "Hello!"
+    label: greeting
+"#,
+        );
+
+        assert_eq!(
+            import.rules,
+            vec![
+                rule(":sig", "Best regards,\nTyler"),
+                rule(":hi", "Hello!"),
+                rule(":hello", "Hello!"),
+            ]
+        );
+        assert!(import.issues.is_empty());
+    }
+
+    #[test]
+    fn turns_the_cursor_marker_into_a_caret_move() {
+        let import = import(
+            r#"
+matches:
+  - trigger: ":div"
+    replace: "<div>$|$</div>"
+  - trigger: ":end"
+    replace: "done$|$"
+  - trigger: ":two"
+    replace: "$|$ and $|$"
+"#,
+        );
+
+        assert_eq!(
+            import.rules,
+            vec![
+                rule(":div", "<div></div>{{MOVE_CARET:-6}}"),
+                rule(":end", "done")
+            ]
+        );
+        assert_eq!(
+            messages(&import),
+            vec!["base.yml: :two: more than one $|$ cursor marker (skipped)"]
+        );
+    }
+
+    #[test]
+    fn inlines_date_shell_and_echo_variables() {
+        let import = import(
+            r#"
+global_vars:
+  - name: myname
+    type: echo
+    params:
+      echo: Tyler
+matches:
+  - trigger: ":now"
+    replace: "{{today}} at {{clock}}"
+    vars:
+      - name: today
+        type: date
+        params:
+          format: "%Y-%m-%d"
+      - name: clock
+        type: date
+        params:
+          format: "%H:%M"
+  - trigger: ":ip"
+    replace: "ip: {{ip}} ({{myname}})"
+    vars:
+      - name: ip
+        type: shell
+        params:
+          cmd: "curl -s ifconfig.me"
+          shell: bash
+"#,
+        );
+
+        assert_eq!(
+            import.rules,
+            vec![
+                rule(":now", "{{DATE}} at {{DATE:%H:%M}}"),
+                rule(":ip", "ip: {{CMD:curl -s ifconfig.me}} (Tyler)"),
+            ]
+        );
+        assert_eq!(
+            messages(&import),
+            vec!["base.yml: :ip: shell variable 'ip' runs with sh instead of bash"]
+        );
+    }
+
+    #[test]
+    fn reports_unconvertible_matches() {
+        let import = import(
+            r#"
+matches:
+  - regex: ":date\\((?P<offset>.*)\\)"
+    replace: "x"
+  - trigger: ":md"
+    markdown: "**bold**"
+  - trigger: ":clip"
+    replace: "{{clipboard}}"
+    vars:
+      - name: clipboard
+        type: clipboard
+  - trigger: ":form"
+    replace: "Hi {{form1.name}}"
+  - trigger: ":missing"
+    replace: "{{nope}}"
+  - trigger: ":word"
+    replace: "kept"
+    word: true
+    propagate_case: true
+"#,
+        );
+
+        assert_eq!(import.rules, vec![rule(":word", "kept")]);
+        assert_eq!(
+            messages(&import),
+            vec![
+                "base.yml: :date\\((?P<offset>.*)\\): regex triggers are not supported (skipped)",
+                "base.yml: :md: markdown replacements are not supported (skipped)",
+                "base.yml: :clip: variable 'clipboard' of type clipboard is not supported (skipped)",
+                "base.yml: :form: form field {{form1.name}} is not supported (skipped)",
+                "base.yml: :missing: unknown variable {{nope}} (skipped)",
+                "base.yml: :word: word matching only applies with the global `match_behavior: boundary`",
+                "base.yml: :word: propagate_case is not supported; only the exact trigger expands",
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_duplicates_against_the_config_and_the_import() {
+        let mut import = import(
+            r#"
+matches:
+  - trigger: ";taken"
+    replace: "clash"
+  - trigger: ":a"
+    replace: "first"
+"#,
+        );
+        import
+            .add_file(
+                Path::new("packages/extra.yml"),
+                "matches:\n  - trigger: ':a'\n    replace: second\n",
+            )
+            .expect("second file should parse");
+
+        assert_eq!(import.rules, vec![rule(":a", "first")]);
+        assert_eq!(
+            messages(&import),
+            vec![
+                "base.yml: ;taken: trigger already exists in the config (skipped)",
+                "packages/extra.yml: :a: trigger appears earlier in the import (skipped)",
+            ]
+        );
+    }
+
+    #[test]
+    fn converted_rules_load_as_slykey_config() {
+        let import = import(
+            r#"
+matches:
+  - trigger: ":div"
+    replace: "<div>$|$</div>"
+  - trigger: ":now"
+    replace: "{{t}}"
+    vars:
+      - name: t
+        type: date
+        params:
+          format: "%d.%m.%Y"
+          offset: -86400
+"#,
+        );
+
+        let yaml = RuleSet {
+            expansions: import.rules,
+            ..RuleSet::default()
+        }
+        .to_yaml()
+        .expect("rules should serialize");
+        let config: crate::config::AppConfig =
+            serde_yaml::from_str(&yaml).expect("output should be a valid config");
+        config
+            .validate_deep()
+            .expect("converted macros should validate");
+        assert_eq!(
+            config.expansions[1].expansion,
+            "{{DATE:offset=-86400:%d.%m.%Y}}"
+        );
+    }
+
+    #[test]
+    fn finds_match_files_recursively() {
+        let dir = std::env::temp_dir().join(format!("slykey-test-espanso-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("packages/emoji")).expect("create match dirs");
+        for name in [
+            "base.yml",
+            "packages/emoji/package.yml",
+            "notes.txt",
+            "b.yaml",
+        ] {
+            std::fs::write(dir.join(name), "matches: []\n").expect("write match file");
+        }
+
+        let files = espanso_match_files(&dir).expect("match files");
+
+        assert_eq!(
+            files,
+            vec![
+                dir.join("b.yaml"),
+                dir.join("base.yml"),
+                dir.join("packages/emoji/package.yml"),
+            ]
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,40 +1,410 @@
-use anyhow::{bail, Result};
-use chrono::Local;
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Local, SecondsFormat, TimeDelta, Utc};
 use std::collections::HashMap;
-use std::process::Command;
 
-use crate::io::output::SpecialKey;
+use crate::config::{ExpansionRule, Globals, NewlineMode};
+use crate::core::date_locale::{format_checked, format_localized};
+use crate::core::host_info::{self, select_ipv4, InterfaceSource, SystemInterfaces};
+use crate::core::math::render_math_macro;
+use crate::core::shell_command::{
+    split_command_options, CommandCache, CommandOptions, CommandRunner, ShellRunner,
+};
+use crate::core::totp::{decode_base32, parse_totp_macro, totp};
+use crate::io::output::{ComboKey, KeyModifier, SpecialKey};
+use crate::io::secrets::{SecretStore, SECRET_MASK};
 
+/// Upper bound for `{{KEY:...*N}}` repeats.
+const MAX_KEY_REPEAT: usize = 100;
+/// Typed as a literal `{{` or `}}` instead of starting or ending a macro.
+const ESCAPED_OPEN: &[u8] = b"\\{{";
+const ESCAPED_CLOSE: &[u8] = b"\\}}";
+/// Appended to "unsupported macro" errors.
+const ESCAPE_HINT: &str = "write \\{{ and \\}} for literal braces";
+
+/// A step of a parsed expansion.
 #[derive(Debug, Clone)]
 pub enum OutputAction {
     Text(String),
     Key(SpecialKey),
+    /// Holds `modifiers` in order, taps `key`, then releases them in reverse.
+    KeyCombo {
+        modifiers: Vec<KeyModifier>,
+        key: ComboKey,
+    },
     SleepMs(u64),
-    MoveCaret(i64),
+    /// Sleeps a random number of milliseconds in `min..=max`, picked each
+    /// time it is sent.
+    SleepRange(u64, u64),
+    /// Moves the caret `amount` units, left/up when negative.
+    MoveCaret {
+        amount: i64,
+        unit: CaretUnit,
+    },
+    /// Extends a selection from the caret by `amount` units with Shift held,
+    /// to the left when negative.
+    Select {
+        amount: i64,
+        unit: CaretUnit,
+    },
+}
+
+/// What `{{MOVE_CARET:...}}` and `{{SELECT:...}}` count in: no suffix or `c` for characters,
+/// `w` for words, `l` for lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaretUnit {
+    Chars,
+    Words,
+    Lines,
+}
+
+impl CaretUnit {
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Chars => "",
+            Self::Words => "w",
+            Self::Lines => "l",
+        }
+    }
+}
+
+/// Action macros that take no argument, kept for the action pass.
+const BARE_ACTION_MACROS: &[&str] = &["SELECT_WORD"];
+
+impl OutputAction {
+    /// Whether this is a `{{SLEEP_MS:...}}` pause, fixed or ranged.
+    pub fn is_sleep(&self) -> bool {
+        matches!(self, Self::SleepMs(_) | Self::SleepRange(..))
+    }
+
+    /// Whether sending this holds a modifier, as chords, selections and
+    /// word-wise caret moves (Ctrl+Left/Right) do.
+    pub fn presses_chord(&self) -> bool {
+        matches!(
+            self,
+            Self::KeyCombo { .. }
+                | Self::Select { .. }
+                | Self::MoveCaret {
+                    unit: CaretUnit::Words,
+                    ..
+                }
+        )
+    }
+}
+
+/// Longest random pause `{{SLEEP_MS:min-max}}` may ask for.
+pub const MAX_SLEEP_RANGE_MS: u64 = 10_000;
+
+/// A piece of rendered template text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rendered {
+    /// Text from the config itself, globals and `{{IF:...}}` branches included:
+    /// the action pass still parses its `{{KEY:...}}` macros and brace escapes.
+    Template(String),
+    /// Produced while expanding, e.g. command output or a secret: typed as is,
+    /// so a `{{KEY:ENTER}}` in it never presses Enter.
+    Literal(String),
+}
+
+/// Bounds on template rendering, so runaway globals fail instead of hanging
+/// the keyboard thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderLimits {
+    /// Longest chain of globals and `{{EXPAND:...}}` rules resolved inside
+    /// one another; the next one fails instead of recursing further.
+    pub max_depth: usize,
+    /// Largest text a macro or the whole expansion may render to.
+    pub max_output_bytes: usize,
+}
+
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+impl Default for RenderLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
 }
 
-pub fn render_template_macros(input: &str, globals: &HashMap<String, String>) -> Result<String> {
-    render_template_macros_internal(input, globals, &mut Vec::new())
+/// Inputs available to template macros while rendering.
+#[derive(Clone, Copy)]
+pub struct ExpansionContext<'a> {
+    pub globals: &'a Globals,
+    /// Rules `{{EXPAND:trigger}}` looks triggers up in; empty unless a rule
+    /// set is being expanded.
+    pub rules: &'a [ExpansionRule],
+    /// Rendered text of the most recent expansion, used by `{{LAST_EXPANSION}}`.
+    pub last_expansion: Option<&'a str>,
+    /// Checks macros without side effects: `{{CMD:...}}` is rendered but not
+    /// run, and secrets and host details (`{{HOSTNAME}}`, `{{LOCAL_IP}}`, ...)
+    /// are left empty instead of being looked up.
+    pub dry_run: bool,
+    /// Where `{{CMD:cache=...:...}}` outputs are kept; without one every
+    /// command runs each time.
+    pub cache: Option<&'a CommandCache>,
+    /// What runs `{{CMD:...}}` commands; [`ShellRunner`] unless sandboxed.
+    pub runner: &'a dyn CommandRunner,
+    /// Where `{{SECRET:...}}` values come from; without one the macro fails.
+    pub secrets: Option<&'a dyn SecretStore>,
+    /// Base32 secrets from the config's `totp:` map, tried before the
+    /// `totp/<account>` entries of `secrets`.
+    pub totp_secrets: Option<&'a HashMap<String, String>>,
+    /// Renders [`SECRET_MASK`] in place of `{{SECRET:...}}` values and
+    /// `{{TOTP:...}}` codes, for text shown to people; secrets are still
+    /// looked up, so missing ones fail.
+    pub mask_secrets: bool,
+    /// The current time for date macros and `{{TOTP:...}}`; [`Local::now`]
+    /// except in tests.
+    pub clock: &'a dyn Fn() -> DateTime<Local>,
+    /// Where `{{LOCAL_IP}}` finds addresses; [`SystemInterfaces`] except in tests.
+    pub interfaces: &'a dyn InterfaceSource,
+    pub limits: RenderLimits,
 }
 
-pub fn parse_expansion_actions(
+impl std::fmt::Debug for ExpansionContext<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExpansionContext")
+            .field("globals", &self.globals)
+            .field("last_expansion", &self.last_expansion)
+            .field("dry_run", &self.dry_run)
+            .field("mask_secrets", &self.mask_secrets)
+            .field("cache", &self.cache)
+            .field("limits", &self.limits)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> ExpansionContext<'a> {
+    pub fn new(globals: &'a Globals) -> Self {
+        Self {
+            globals,
+            rules: &[],
+            last_expansion: None,
+            dry_run: false,
+            cache: None,
+            runner: &ShellRunner,
+            secrets: None,
+            totp_secrets: None,
+            mask_secrets: false,
+            clock: &Local::now,
+            interfaces: &SystemInterfaces,
+            limits: RenderLimits::default(),
+        }
+    }
+}
+
+pub fn render_template_macros(input: &str, globals: &Globals) -> Result<String> {
+    render_template_macros_with_context(input, &ExpansionContext::new(globals))
+}
+
+pub fn render_template_macros_with_context(input: &str, ctx: &ExpansionContext) -> Result<String> {
+    render_template_macros_internal(input, ctx, &mut Vec::new())
+}
+
+pub fn parse_expansion_actions(input: &str, globals: &Globals) -> Result<Vec<OutputAction>> {
+    parse_expansion_actions_with_context(input, &ExpansionContext::new(globals))
+}
+
+pub fn parse_expansion_actions_with_context(
     input: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
 ) -> Result<Vec<OutputAction>> {
-    let templated = render_template_macros(input, globals)?;
-    parse_action_macros_only(&templated)
+    let mut actions = Vec::new();
+    for segment in render_template_segments(input, ctx, &mut Vec::new())? {
+        // Brace escapes survive the template pass and are resolved here.
+        let parsed = match segment {
+            Rendered::Template(text) => parse_action_macros_only(&text)?,
+            Rendered::Literal(text) => vec![OutputAction::Text(text)],
+        };
+        for action in parsed {
+            match (actions.last_mut(), action) {
+                (Some(OutputAction::Text(previous)), OutputAction::Text(text)) => {
+                    previous.push_str(&text);
+                }
+                (_, action) => actions.push(action),
+            }
+        }
+    }
+    Ok(actions)
+}
+
+/// Applies a rule's `newline` mode to the `\n`s (or `\r\n`s) in the text
+/// of parsed `actions`. Keys from macros such as `{{KEY:ENTER}}` are left
+/// alone.
+pub fn apply_newline_mode(actions: Vec<OutputAction>, mode: NewlineMode) -> Vec<OutputAction> {
+    if mode == NewlineMode::Text {
+        return actions;
+    }
+    let mut converted = Vec::with_capacity(actions.len());
+    for action in actions {
+        let OutputAction::Text(text) = action else {
+            converted.push(action);
+            continue;
+        };
+        if mode == NewlineMode::Strip {
+            let joined: Vec<&str> = text
+                .split('\n')
+                .map(|line| line.strip_suffix('\r').unwrap_or(line))
+                .collect();
+            converted.push(OutputAction::Text(joined.join(" ")));
+            continue;
+        }
+        for (index, line) in text.split('\n').enumerate() {
+            if index > 0 {
+                converted.push(match mode {
+                    NewlineMode::ShiftEnter => OutputAction::KeyCombo {
+                        modifiers: vec![KeyModifier::Shift],
+                        key: ComboKey::Special(SpecialKey::Enter),
+                    },
+                    _ => OutputAction::Key(SpecialKey::Enter),
+                });
+            }
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if !line.is_empty() {
+                converted.push(OutputAction::Text(line.to_string()));
+            }
+        }
+    }
+    converted
+}
+
+/// Commands of the `{{CMD:...}}` macros in `input` that contain no other
+/// macros, so their text is known before rendering. Options such as
+/// `timeout=2s:` are left on.
+pub fn static_command_macros(input: &str) -> Vec<&str> {
+    let mut commands = Vec::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(end) = find_macro_end(rest, start + 2) else {
+            break;
+        };
+        if let Some((name, value)) = rest[start + 2..end].trim().split_once(':') {
+            let name = name.trim().to_ascii_uppercase();
+            if matches!(name.as_str(), "CMD" | "COMMAND") && !value.contains("{{") {
+                commands.push(value.trim());
+            }
+        }
+        rest = &rest[end + 2..];
+    }
+    commands
 }
 
+/// Renders actions for humans: text verbatim, other actions as `[KEY Enter]`,
+/// `[SLEEP 50ms]` or `[CARET -3]`.
+pub fn format_actions(actions: &[OutputAction]) -> String {
+    let mut formatted = String::new();
+    for action in actions {
+        match action {
+            OutputAction::Text(text) => formatted.push_str(text),
+            OutputAction::Key(key) => formatted.push_str(&format!("[KEY {key:?}]")),
+            OutputAction::KeyCombo { modifiers, key } => {
+                formatted.push_str("[KEY ");
+                for modifier in modifiers {
+                    formatted.push_str(&format!("{modifier:?}+"));
+                }
+                match key {
+                    ComboKey::Special(key) => formatted.push_str(&format!("{key:?}]")),
+                    ComboKey::Char(c) => formatted.push_str(&format!("{c}]")),
+                }
+            }
+            OutputAction::SleepMs(ms) => formatted.push_str(&format!("[SLEEP {ms}ms]")),
+            OutputAction::SleepRange(min, max) => {
+                formatted.push_str(&format!("[SLEEP {min}-{max}ms]"))
+            }
+            OutputAction::MoveCaret { amount, unit } => {
+                formatted.push_str(&format!("[CARET {amount}{}]", unit.suffix()))
+            }
+            OutputAction::Select { amount, unit } => {
+                formatted.push_str(&format!("[SELECT {amount}{}]", unit.suffix()))
+            }
+        }
+    }
+    formatted
+}
+
+/// Renders `input` to the text it finally stands for: escapes resolved and
+/// action macros left as written. Used for macro arguments and snippets.
 fn render_template_macros_internal(
     input: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
 ) -> Result<String> {
-    let mut rendered = String::with_capacity(input.len());
+    let segments = render_template_segments(input, ctx, resolving_stack)?;
+    Ok(plain_text(&segments))
+}
+
+fn plain_text(segments: &[Rendered]) -> String {
+    segments
+        .iter()
+        .map(|segment| match segment {
+            Rendered::Template(text) => unescape_braces(text),
+            Rendered::Literal(text) => text.clone(),
+        })
+        .collect()
+}
+
+/// Fails once `segments` hold more than `expansion.max_output_bytes`, naming
+/// the globals being rendered.
+fn check_output_size(
+    segments: &[Rendered],
+    ctx: &ExpansionContext,
+    resolving_stack: &[String],
+) -> Result<()> {
+    let size: usize = segments
+        .iter()
+        .map(|segment| match segment {
+            Rendered::Template(text) | Rendered::Literal(text) => text.len(),
+        })
+        .sum();
+    if size > ctx.limits.max_output_bytes {
+        let location = match resolving_stack {
+            [] => "the expansion".to_string(),
+            chain => chain.join(" -> "),
+        };
+        bail!(
+            "{location} renders to more than {} bytes (expansion.max_output_bytes)",
+            ctx.limits.max_output_bytes
+        );
+    }
+    Ok(())
+}
+
+/// Appends `segment`, merging it into the last one of the same kind.
+fn push_segment(segments: &mut Vec<Rendered>, segment: Rendered) {
+    match (segments.last_mut(), segment) {
+        (_, Rendered::Template(text) | Rendered::Literal(text)) if text.is_empty() => {}
+        (Some(Rendered::Template(previous)), Rendered::Template(text))
+        | (Some(Rendered::Literal(previous)), Rendered::Literal(text)) => previous.push_str(&text),
+        (_, segment) => segments.push(segment),
+    }
+}
+
+fn push_template(segments: &mut Vec<Rendered>, text: &str) {
+    push_segment(segments, Rendered::Template(text.to_string()));
+}
+
+fn render_template_segments(
+    input: &str,
+    ctx: &ExpansionContext,
+    resolving_stack: &mut Vec<String>,
+) -> Result<Vec<Rendered>> {
+    let mut rendered = Vec::new();
     let mut i = 0usize;
     let bytes = input.as_bytes();
 
     while i < bytes.len() {
+        if starts_with_at(bytes, i, ESCAPED_OPEN) || starts_with_at(bytes, i, ESCAPED_CLOSE) {
+            push_template(&mut rendered, &input[i..i + 3]);
+            i += 3;
+            continue;
+        }
         if starts_with_at(bytes, i, b"{{") {
             let end = find_macro_end(input, i + 2)
                 .ok_or_else(|| anyhow::anyhow!("unclosed macro starting at byte {}", i))?;
@@ -42,25 +412,33 @@ fn render_template_macros_internal(
 
             if let Some((name, value)) = body.split_once(':') {
                 if is_template_macro_with_argument(name) {
-                    rendered.push_str(&render_template_macro_with_argument(
+                    let segments = render_template_macro_with_argument(
                         name.trim(),
                         value.trim(),
-                        globals,
+                        ctx,
                         resolving_stack,
-                    )?);
+                    )?;
+                    for segment in segments {
+                        push_segment(&mut rendered, segment);
+                    }
                 } else {
-                    rendered.push_str(&input[i..end + 2]);
+                    push_template(&mut rendered, &input[i..end + 2]);
                 }
+            } else if is_bare_action_macro(body) {
+                push_template(&mut rendered, &input[i..end + 2]);
             } else {
-                rendered.push_str(&render_template_macro(body, globals, resolving_stack)?);
+                for segment in render_template_macro(body, ctx, resolving_stack)? {
+                    push_segment(&mut rendered, segment);
+                }
             }
+            check_output_size(&rendered, ctx, resolving_stack)?;
 
             i = end + 2;
             continue;
         }
 
         let ch = input[i..].chars().next().expect("char exists");
-        rendered.push(ch);
+        push_template(&mut rendered, ch.encode_utf8(&mut [0; 4]));
         i += ch.len_utf8();
     }
 
@@ -74,6 +452,11 @@ fn parse_action_macros_only(input: &str) -> Result<Vec<OutputAction>> {
     let bytes = input.as_bytes();
 
     while i < bytes.len() {
+        if starts_with_at(bytes, i, ESCAPED_OPEN) || starts_with_at(bytes, i, ESCAPED_CLOSE) {
+            text_buf.push_str(&input[i + 1..i + 3]);
+            i += 3;
+            continue;
+        }
         if starts_with_at(bytes, i, b"{{") {
             if !text_buf.is_empty() {
                 actions.push(OutputAction::Text(std::mem::take(&mut text_buf)));
@@ -82,8 +465,8 @@ fn parse_action_macros_only(input: &str) -> Result<Vec<OutputAction>> {
             let end = find_macro_end(input, i + 2)
                 .ok_or_else(|| anyhow::anyhow!("unclosed macro starting at byte {}", i))?;
             let body = &input[i + 2..end];
-            if body.contains(':') {
-                actions.push(parse_action_macro(body.trim())?);
+            if body.contains(':') || is_bare_action_macro(body) {
+                actions.extend(parse_action_macro(body.trim())?);
             } else {
                 text_buf.push_str(&input[i..end + 2]);
             }
@@ -102,59 +485,146 @@ fn parse_action_macros_only(input: &str) -> Result<Vec<OutputAction>> {
     Ok(actions)
 }
 
+/// Resolves `\\{{` and `\\}}` to the braces they stand for.
+fn unescape_braces(text: &str) -> String {
+    text.replace("\\{{", "{{").replace("\\}}", "}}")
+}
+
 fn starts_with_at(haystack: &[u8], index: usize, needle: &[u8]) -> bool {
     haystack.get(index..index + needle.len()) == Some(needle)
 }
 
+/// Index of the `}}` closing the macro whose body starts at `start`, skipping
+/// over nested macros such as `{{UPPER:{{DATE}}}}`.
 fn find_macro_end(input: &str, start: usize) -> Option<usize> {
-    input[start..].find("}}").map(|offset| start + offset)
+    let bytes = input.as_bytes();
+    let mut depth = 0usize;
+    let mut i = start;
+    while i < bytes.len() {
+        if starts_with_at(bytes, i, ESCAPED_OPEN) || starts_with_at(bytes, i, ESCAPED_CLOSE) {
+            i += 3;
+        } else if starts_with_at(bytes, i, b"{{") {
+            depth += 1;
+            i += 2;
+        } else if starts_with_at(bytes, i, b"}}") {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    None
 }
 
-fn parse_action_macro(body: &str) -> Result<OutputAction> {
+fn parse_action_macro(body: &str) -> Result<Vec<OutputAction>> {
     if let Some((name, value)) = body.split_once(':') {
         let name = name.trim().to_ascii_uppercase();
         let value = value.trim();
 
         return match name.as_str() {
-            "KEY" => Ok(OutputAction::Key(parse_special_key(value)?)),
-            "SLEEP_MS" => {
-                let ms: u64 = value.parse()?;
-                Ok(OutputAction::SleepMs(ms))
+            "KEY" => {
+                let (chord, count) = split_key_repeat(value)?;
+                Ok(vec![parse_key_chord(chord)?; count])
             }
+            "SLEEP_MS" => Ok(vec![parse_sleep(value)?]),
             "MOVE_CARET" | "CARET_MOVE" => {
-                let amount: i64 = value.parse()?;
-                Ok(OutputAction::MoveCaret(amount))
+                let (amount, unit) = parse_caret_count(value)
+                    .map_err(|err| anyhow::anyhow!("invalid caret move '{value}': {err}"))?;
+                Ok(vec![OutputAction::MoveCaret { amount, unit }])
+            }
+            "SELECT" => {
+                let (amount, unit) = parse_caret_count(value)
+                    .map_err(|err| anyhow::anyhow!("invalid selection '{value}': {err}"))?;
+                Ok(vec![OutputAction::Select { amount, unit }])
             }
-            _ => bail!("unsupported macro: '{name}'"),
+            _ => bail!("unsupported macro: '{name}' ({ESCAPE_HINT})"),
         };
     }
 
-    bail!("unsupported macro: '{body}'")
+    if body.eq_ignore_ascii_case("SELECT_WORD") {
+        return Ok(vec![OutputAction::Select {
+            amount: -1,
+            unit: CaretUnit::Words,
+        }]);
+    }
+
+    bail!("unsupported macro: '{body}' ({ESCAPE_HINT})")
+}
+
+fn is_bare_action_macro(body: &str) -> bool {
+    BARE_ACTION_MACROS
+        .iter()
+        .any(|name| body.trim().eq_ignore_ascii_case(name))
+}
+
+/// `-3`, `-2w` or `+1l`: a signed count with an optional unit suffix, as
+/// taken by `{{MOVE_CARET:...}}` and `{{SELECT:...}}`.
+fn parse_caret_count(value: &str) -> Result<(i64, CaretUnit), std::num::ParseIntError> {
+    let (count, unit) = match value.char_indices().next_back() {
+        Some((index, 'c' | 'C')) => (&value[..index], CaretUnit::Chars),
+        Some((index, 'w' | 'W')) => (&value[..index], CaretUnit::Words),
+        Some((index, 'l' | 'L')) => (&value[..index], CaretUnit::Lines),
+        _ => (value, CaretUnit::Chars),
+    };
+    Ok((count.trim().parse()?, unit))
+}
+
+/// `{{SLEEP_MS:100}}` sleeps a fixed time, `{{SLEEP_MS:100-300}}` a random
+/// one in the range.
+fn parse_sleep(value: &str) -> Result<OutputAction> {
+    let Some((min, max)) = value.split_once('-') else {
+        return Ok(OutputAction::SleepMs(value.parse()?));
+    };
+    let (min, max): (u64, u64) = (min.trim().parse()?, max.trim().parse()?);
+    if min > max {
+        bail!("invalid sleep range '{value}': {min} is more than {max}");
+    }
+    if max > MAX_SLEEP_RANGE_MS {
+        bail!("invalid sleep range '{value}': sleep ranges go up to {MAX_SLEEP_RANGE_MS} ms");
+    }
+    Ok(OutputAction::SleepRange(min, max))
 }
 
 fn render_template_macro(
     name: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
-) -> Result<String> {
-    let now = Local::now();
+) -> Result<Vec<Rendered>> {
+    let now = (ctx.clock)();
     let normalized_name = name.trim().to_ascii_uppercase();
     let rendered = match normalized_name.as_str() {
         "DATETIME" => now.format("%Y-%m-%d %H:%M:%S").to_string(),
         "DATE" => now.format("%Y-%m-%d").to_string(),
         "TIME" => now.format("%H:%M:%S").to_string(),
-        _ => resolve_global_template_macro(&normalized_name, globals, resolving_stack)?,
+        "TIME_HOUR" => now.format("%H").to_string(),
+        "TIME_MINUTE" => now.format("%M").to_string(),
+        "WEEKDAY" => now.format("%A").to_string(),
+        "WEEK" => now.format("%V").to_string(),
+        "MONTH_NAME" => now.format("%B").to_string(),
+        "EPOCH" => now.timestamp().to_string(),
+        "EPOCH_MS" => now.timestamp_millis().to_string(),
+        "ISO8601" => now.to_rfc3339_opts(SecondsFormat::Secs, false),
+        "HOSTNAME" | "USERNAME" | "OS_RELEASE" | "LOCAL_IP" if ctx.dry_run => String::new(),
+        "HOSTNAME" => host_info::hostname()?,
+        "USERNAME" => host_info::username()?,
+        "OS_RELEASE" => host_info::os_release()?,
+        "LOCAL_IP" => select_ipv4(&ctx.interfaces.ipv4_interfaces()?, None)?.to_string(),
+        "LAST_EXPANSION" => ctx.last_expansion.unwrap_or_default().to_string(),
+        _ => return resolve_global_template_macro(&normalized_name, ctx, resolving_stack),
     };
-    Ok(rendered)
+    Ok(vec![Rendered::Literal(rendered)])
 }
 
 fn resolve_global_template_macro(
     name: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
-) -> Result<String> {
-    let Some(value) = lookup_global_macro_case_insensitive(globals, name) else {
-        bail!("unsupported macro: '{name}'");
+) -> Result<Vec<Rendered>> {
+    let Some(value) = lookup_global_macro_case_insensitive(ctx.globals, name) else {
+        bail!("unsupported macro: '{name}' ({ESCAPE_HINT})");
     };
 
     if resolving_stack.iter().any(|existing| existing == name) {
@@ -162,17 +632,60 @@ fn resolve_global_template_macro(
         chain.push(name.to_string());
         bail!("global macro cycle detected: {}", chain.join(" -> "));
     }
+    if resolving_stack.len() >= ctx.limits.max_depth {
+        let mut chain = resolving_stack.clone();
+        chain.push(name.to_string());
+        bail!(
+            "global macros nest more than {} deep (expansion.max_depth): {}",
+            ctx.limits.max_depth,
+            chain.join(" -> ")
+        );
+    }
 
     resolving_stack.push(name.to_string());
-    let rendered = render_template_macros_internal(value, globals, resolving_stack)?;
+    let rendered = render_template_segments(value, ctx, resolving_stack)?;
     resolving_stack.pop();
     Ok(rendered)
 }
 
-fn lookup_global_macro_case_insensitive<'a>(
-    globals: &'a HashMap<String, String>,
-    name: &str,
-) -> Option<&'a str> {
+/// `{{EXPAND:trigger}}`: the expansion of the rule with that trigger, still
+/// a template so its action macros fire where the macro stands. Rules share
+/// `resolving_stack` with globals, as `EXPAND:trigger`, so loops through
+/// either are caught.
+fn render_expand_macro(
+    trigger: &str,
+    ctx: &ExpansionContext,
+    resolving_stack: &mut Vec<String>,
+) -> Result<Vec<Rendered>> {
+    let key = format!("EXPAND:{trigger}");
+    let mut chain = resolving_stack.clone();
+    chain.push(key.clone());
+    let Some(rule) = ctx.rules.iter().find(|rule| rule.trigger == trigger) else {
+        bail!(
+            "no rule has the trigger '{trigger}': {}",
+            chain.join(" -> ")
+        );
+    };
+    if resolving_stack.contains(&key) {
+        bail!("expansion cycle detected: {}", chain.join(" -> "));
+    }
+    if resolving_stack.len() >= ctx.limits.max_depth {
+        bail!(
+            "expansions nest more than {} deep (expansion.max_depth): {}",
+            ctx.limits.max_depth,
+            chain.join(" -> ")
+        );
+    }
+
+    resolving_stack.push(key);
+    let rendered = render_template_segments(&rule.expansion, ctx, resolving_stack)?;
+    resolving_stack.pop();
+    Ok(rendered)
+}
+
+/// The first global, in declaration order, whose name matches `name`
+/// case-insensitively.
+fn lookup_global_macro_case_insensitive<'a>(globals: &'a Globals, name: &str) -> Option<&'a str> {
     for (global_name, value) in globals {
         if global_name.eq_ignore_ascii_case(name) {
             return Some(value);
@@ -184,30 +697,142 @@ fn lookup_global_macro_case_insensitive<'a>(
 fn is_template_macro_with_argument(name: &str) -> bool {
     matches!(
         name.trim().to_ascii_uppercase().as_str(),
-        "CMD" | "COMMAND" | "EMOJI"
+        "CMD"
+            | "COMMAND"
+            | "EMOJI"
+            | "SECRET"
+            | "TOTP"
+            | "UPPER"
+            | "LOWER"
+            | "TITLE"
+            | "TRIM"
+            | "URLENCODE"
+            | "BASE64"
+            | "MATH"
+            | "IF"
+            | "DATE"
+            | "DATE_LOCALE"
+            | "ISO8601"
+            | "LOCAL_IP"
+            | "EXPAND"
     )
 }
 
 fn render_template_macro_with_argument(
     name: &str,
     value: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
-) -> Result<String> {
+) -> Result<Vec<Rendered>> {
     let normalized = name.to_ascii_uppercase();
-    match normalized.as_str() {
-        "CMD" | "COMMAND" => run_linux_command_macro(value, globals, resolving_stack),
-        "EMOJI" => render_emoji_macro(value, globals, resolving_stack),
+    let rendered = match normalized.as_str() {
+        "CMD" | "COMMAND" => run_linux_command_macro(value, ctx, resolving_stack),
+        "EMOJI" => render_emoji_macro(value, ctx, resolving_stack),
+        "SECRET" => render_secret_macro(value, ctx),
+        "TOTP" => render_totp_macro(value, ctx),
+        "UPPER" | "LOWER" | "TITLE" | "TRIM" => {
+            // Config text stays a template, so `{{UPPER:hi{{KEY:ENTER}}}}` still
+            // presses Enter; anything produced while expanding stays literal.
+            let argument = render_template_segments(value, ctx, resolving_stack)?;
+            return Ok(vec![match &argument[..] {
+                [] => Rendered::Literal(String::new()),
+                [Rendered::Template(text)] => Rendered::Template(transform_text(&normalized, text)),
+                _ => Rendered::Literal(transform_text(&normalized, &plain_text(&argument))),
+            }]);
+        }
+        "URLENCODE" => {
+            let text = render_template_macros_internal(value, ctx, resolving_stack)?;
+            Ok(percent_encode(&text))
+        }
+        "BASE64" => render_base64_macro(value, ctx, resolving_stack),
+        "IF" => return render_if_macro(value, ctx, resolving_stack),
+        "EXPAND" => return render_expand_macro(value, ctx, resolving_stack),
+        "DATE" => render_date_macro(value, ctx),
+        "DATE_LOCALE" => format_localized(&(ctx.clock)(), value),
+        "LOCAL_IP" if ctx.dry_run => Ok(String::new()),
+        "LOCAL_IP" => {
+            let interfaces = ctx.interfaces.ipv4_interfaces()?;
+            Ok(select_ipv4(&interfaces, Some(value))?.to_string())
+        }
+        "ISO8601" => {
+            let now = (ctx.clock)();
+            match value.to_ascii_lowercase().as_str() {
+                "utc" => Ok(now
+                    .with_timezone(&Utc)
+                    .to_rfc3339_opts(SecondsFormat::Secs, true)),
+                "local" => Ok(now.to_rfc3339_opts(SecondsFormat::Secs, false)),
+                _ => bail!("invalid ISO8601 zone '{value}' (expected utc or local)"),
+            }
+        }
+        "MATH" => {
+            let expression = render_template_macros_internal(value, ctx, resolving_stack)?;
+            render_math_macro(&expression)
+        }
         _ => bail!("unsupported macro: '{normalized}'"),
+    };
+    Ok(vec![Rendered::Literal(rendered?)])
+}
+
+fn transform_text(transform: &str, text: &str) -> String {
+    match transform {
+        "UPPER" => text.to_uppercase(),
+        "LOWER" => text.to_lowercase(),
+        "TITLE" => title_case(text),
+        _ => text.trim().to_string(),
+    }
+}
+
+/// Uppercases the first letter of each whitespace-separated word and leaves
+/// the rest, and the whitespace itself, as it is.
+fn title_case(text: &str) -> String {
+    let mut titled = String::with_capacity(text.len());
+    let mut at_word_start = true;
+    for ch in text.chars() {
+        if at_word_start && !ch.is_whitespace() {
+            titled.extend(ch.to_uppercase());
+        } else {
+            titled.push(ch);
+        }
+        at_word_start = ch.is_whitespace();
+    }
+    titled
+}
+
+/// Percent-encodes every UTF-8 byte outside RFC 3986's unreserved set.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
     }
+    encoded
+}
+
+/// `{{BASE64:text}}`, or `{{BASE64:url:text}}` / `{{BASE64:std:text}}` to pick
+/// the alphabet. Anything else before a colon is part of the text.
+fn render_base64_macro(
+    value: &str,
+    ctx: &ExpansionContext,
+    resolving_stack: &mut Vec<String>,
+) -> Result<String> {
+    let (engine, text) = match value.split_once(':') {
+        Some(("url", text)) => (&base64::engine::general_purpose::URL_SAFE, text),
+        Some(("std", text)) => (&base64::engine::general_purpose::STANDARD, text),
+        _ => (&base64::engine::general_purpose::STANDARD, value),
+    };
+    let text = render_template_macros_internal(text, ctx, resolving_stack)?;
+    Ok(engine.encode(text))
 }
 
 fn render_emoji_macro(
     shortcode: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
 ) -> Result<String> {
-    let rendered_shortcode = render_template_macros_internal(shortcode, globals, resolving_stack)?;
+    let rendered_shortcode = render_template_macros_internal(shortcode, ctx, resolving_stack)?;
     let normalized_shortcode = rendered_shortcode.trim().trim_matches(':').to_ascii_lowercase();
     let lookup_candidates = [
         normalized_shortcode.clone(),
@@ -224,39 +849,253 @@ fn render_emoji_macro(
     Ok(emoji.as_str().to_string())
 }
 
+/// `{{IF:condition?then:else}}`. Only the chosen branch is rendered, so a
+/// `{{CMD:...}}` in the other one never runs.
+fn render_if_macro(
+    value: &str,
+    ctx: &ExpansionContext,
+    resolving_stack: &mut Vec<String>,
+) -> Result<Vec<Rendered>> {
+    let (condition, branches) = split_unescaped(value, '?');
+    let Some(branches) = branches else {
+        bail!("IF macro needs a '?', e.g. {{{{IF:{{{{TIME_HOUR}}}}<12?morning:afternoon}}}}");
+    };
+    let (then_branch, else_branch) = split_unescaped(branches, ':');
+
+    let branch = if evaluate_condition(condition, ctx, resolving_stack)? {
+        then_branch
+    } else {
+        else_branch.unwrap_or_default()
+    };
+    render_template_segments(&unescape_if_delimiters(branch), ctx, resolving_stack)
+}
+
+/// Splits at the first `delimiter` that is neither backslash-escaped nor
+/// inside a nested `{{...}}`.
+fn split_unescaped(value: &str, delimiter: char) -> (&str, Option<&str>) {
+    let bytes = value.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0usize;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+        } else if starts_with_at(bytes, i, b"{{") {
+            depth += 1;
+            i += 2;
+        } else if depth > 0 && starts_with_at(bytes, i, b"}}") {
+            depth -= 1;
+            i += 2;
+        } else if depth == 0 && bytes[i] == delimiter as u8 {
+            return (&value[..i], Some(&value[i + 1..]));
+        } else {
+            i += 1;
+        }
+    }
+    (value, None)
+}
+
+/// Drops the backslash from `\?`, `\:` and `\\` outside nested macros; other
+/// backslashes stay as they are.
+fn unescape_if_delimiters(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut depth = 0usize;
+    let mut chars = value.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if depth == 0 && matches!(chars.peek(), Some('?' | ':' | '\\')) => {
+                unescaped.push(chars.next().expect("peeked char exists"));
+                continue;
+            }
+            '{' if chars.peek() == Some(&'{') => {
+                depth += 1;
+                unescaped.push(ch);
+                unescaped.push(chars.next().expect("peeked char exists"));
+                continue;
+            }
+            '}' if depth > 0 && chars.peek() == Some(&'}') => {
+                depth -= 1;
+                unescaped.push(ch);
+                unescaped.push(chars.next().expect("peeked char exists"));
+                continue;
+            }
+            _ => unescaped.push(ch),
+        }
+    }
+    unescaped
+}
+
+const IF_OPERATORS: [&str; 6] = ["<=", ">=", "==", "!=", "<", ">"];
+
+/// Compares both rendered sides as numbers when they both parse as one, and
+/// as strings otherwise, which only `==` and `!=` allow.
+fn evaluate_condition(
+    condition: &str,
+    ctx: &ExpansionContext,
+    resolving_stack: &mut Vec<String>,
+) -> Result<bool> {
+    let Some((lhs, operator, rhs)) = split_condition(condition) else {
+        bail!(
+            "IF condition '{}' needs one of {}",
+            condition.trim(),
+            IF_OPERATORS.join(" ")
+        );
+    };
+    let lhs = render_template_macros_internal(&unescape_if_delimiters(lhs), ctx, resolving_stack)?;
+    let rhs = render_template_macros_internal(&unescape_if_delimiters(rhs), ctx, resolving_stack)?;
+    let (lhs, rhs) = (lhs.trim(), rhs.trim());
+
+    if let (Ok(left), Ok(right)) = (lhs.parse::<f64>(), rhs.parse::<f64>()) {
+        return Ok(match operator {
+            "<=" => left <= right,
+            ">=" => left >= right,
+            "==" => left == right,
+            "!=" => left != right,
+            "<" => left < right,
+            _ => left > right,
+        });
+    }
+    match operator {
+        "==" => Ok(lhs == rhs),
+        "!=" => Ok(lhs != rhs),
+        _ => bail!("IF condition '{lhs} {operator} {rhs}' compares numbers, but got text"),
+    }
+}
+
+/// The first comparison operator outside nested macros, with both sides.
+fn split_condition(condition: &str) -> Option<(&str, &'static str, &str)> {
+    let bytes = condition.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0usize;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            i += 2;
+            continue;
+        }
+        if starts_with_at(bytes, i, b"{{") {
+            depth += 1;
+            i += 2;
+            continue;
+        }
+        if depth > 0 && starts_with_at(bytes, i, b"}}") {
+            depth -= 1;
+            i += 2;
+            continue;
+        }
+        if depth == 0 {
+            if let Some(operator) = IF_OPERATORS
+                .iter()
+                .find(|operator| starts_with_at(bytes, i, operator.as_bytes()))
+            {
+                return Some((&condition[..i], operator, &condition[i + operator.len()..]));
+            }
+        }
+        i += 1;
+    }
+    None
+}
+
+fn render_secret_macro(name: &str, ctx: &ExpansionContext) -> Result<String> {
+    if name.is_empty() {
+        bail!("SECRET macro needs a name, e.g. {{{{SECRET:github-token}}}}");
+    }
+    if ctx.dry_run {
+        return Ok(String::new());
+    }
+    let Some(secrets) = ctx.secrets else {
+        bail!("SECRET macro is not available here (no secret store)");
+    };
+    // The error names the secret, never its value.
+    let secret = secrets
+        .get(name)
+        .map_err(|err| err.context(format!("failed to read secret '{name}'")))?;
+    if ctx.mask_secrets {
+        return Ok(SECRET_MASK.to_string());
+    }
+    Ok(secret)
+}
+
+fn render_totp_macro(value: &str, ctx: &ExpansionContext) -> Result<String> {
+    let (account, params) = parse_totp_macro(value)?;
+    if ctx.dry_run {
+        return Ok(String::new());
+    }
+    let secret = match ctx.totp_secrets.and_then(|secrets| secrets.get(account)) {
+        Some(secret) => secret.clone(),
+        None => {
+            let Some(secrets) = ctx.secrets else {
+                bail!("no TOTP secret for '{account}': add it to `totp:` in the config");
+            };
+            secrets
+                .get(&format!("totp/{account}"))
+                .map_err(|err| err.context(format!("failed to read TOTP secret for '{account}'")))?
+        }
+    };
+    if ctx.mask_secrets {
+        return Ok(SECRET_MASK.to_string());
+    }
+    let key = decode_base32(&secret)
+        .with_context(|| format!("TOTP secret for '{account}' is invalid"))?;
+    let Ok(now) = u64::try_from((ctx.clock)().timestamp()) else {
+        bail!("system clock is before 1970");
+    };
+    Ok(totp(&key, now, params))
+}
+
+/// `{{DATE:%d.%m.%Y}}` formats the current time, `{{DATE:offset=86400:...}}`
+/// the time that many seconds later (earlier when negative).
+fn render_date_macro(value: &str, ctx: &ExpansionContext) -> Result<String> {
+    let now = (ctx.clock)();
+    let Some((offset, format)) = value
+        .strip_prefix("offset=")
+        .and_then(|rest| rest.split_once(':'))
+    else {
+        return format_checked(&now, value);
+    };
+    let seconds: i64 = offset
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid DATE offset '{offset}' (expected seconds)"))?;
+    let Some(then) =
+        TimeDelta::try_seconds(seconds).and_then(|delta| now.checked_add_signed(delta))
+    else {
+        bail!("DATE offset '{offset}' is out of range");
+    };
+    format_checked(&then, format)
+}
+
 fn run_linux_command_macro(
     command: &str,
-    globals: &HashMap<String, String>,
+    ctx: &ExpansionContext,
     resolving_stack: &mut Vec<String>,
 ) -> Result<String> {
+    let (options, command) = split_command_options(command)?;
+    if ctx.dry_run {
+        render_template_macros_internal(command, ctx, resolving_stack)?;
+        return Ok(String::new());
+    }
+
     #[cfg(not(target_os = "linux"))]
     {
-        let _ = (command, globals, resolving_stack);
+        let _ = (command, options, ctx, resolving_stack);
         bail!("CMD macro is only supported on Linux");
     }
 
     #[cfg(target_os = "linux")]
     {
-        let rendered_command = render_template_macros_internal(command, globals, resolving_stack)?;
-        let output = Command::new("sh")
-            .arg("-c")
-            .arg(&rendered_command)
-            .output()?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "CMD macro command failed (status: {}): {}",
-                output
-                    .status
-                    .code()
-                    .map_or_else(|| "terminated by signal".to_string(), |code| code.to_string()),
-                stderr.trim()
-            );
+        let rendered_command = render_template_macros_internal(command, ctx, resolving_stack)?;
+        let options = CommandOptions {
+            output_limit: Some(ctx.limits.max_output_bytes),
+            ..options
+        };
+        let run = || {
+            ctx.runner
+                .run(&rendered_command, &options)?
+                .text(options.trim)
+        };
+        match ctx.cache {
+            Some(cache) => cache.get_or_run(&rendered_command, &options, run),
+            None => run(),
         }
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
     }
 }
 
@@ -267,6 +1106,61 @@ fn is_valid_for_format(value: &str, format: &str) -> bool {
         || chrono::NaiveTime::parse_from_str(value, format).is_ok()
 }
 
+/// Splits an optional `*N` repeat suffix off a `{{KEY:...}}` value, so
+/// `LEFT*5` taps Left five times. A lone `*` is still the asterisk key.
+fn split_key_repeat(value: &str) -> Result<(&str, usize)> {
+    let Some((chord, count)) = value.rsplit_once('*') else {
+        return Ok((value, 1));
+    };
+    let (chord, count) = (chord.trim(), count.trim());
+    if chord.is_empty() || chord.ends_with('+') || count.is_empty() {
+        return Ok((value, 1));
+    }
+
+    let count: usize = count
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid key repeat count in macro: KEY:{value}"))?;
+    if !(1..=MAX_KEY_REPEAT).contains(&count) {
+        bail!("key repeat count must be between 1 and {MAX_KEY_REPEAT}, got {count}");
+    }
+    Ok((chord, count))
+}
+
+/// Parses the value of `{{KEY:...}}`: optional `+`-separated modifiers followed
+/// by a special key name or a single character, e.g. `ENTER` or `CTRL+SHIFT+V`.
+fn parse_key_chord(value: &str) -> Result<OutputAction> {
+    let tokens: Vec<&str> = value.split('+').map(str::trim).collect();
+    let (key_token, modifier_tokens) = tokens.split_last().expect("split yields a token");
+
+    let mut modifiers = Vec::new();
+    for token in modifier_tokens {
+        let modifier = match token.to_ascii_uppercase().as_str() {
+            "CTRL" | "CONTROL" => KeyModifier::Ctrl,
+            "SHIFT" => KeyModifier::Shift,
+            "ALT" => KeyModifier::Alt,
+            "META" | "SUPER" | "WIN" => KeyModifier::Meta,
+            other => bail!("unknown key modifier in macro: {other}"),
+        };
+        if !modifiers.contains(&modifier) {
+            modifiers.push(modifier);
+        }
+    }
+
+    let mut chars = key_token.chars();
+    let key = match (chars.next(), chars.next()) {
+        (None, _) => bail!("missing key in macro: KEY:{value}"),
+        // Shift is explicit in a chord, so letters are the unshifted key.
+        (Some(c), None) if !modifiers.is_empty() => ComboKey::Char(c.to_ascii_lowercase()),
+        (Some(c), None) => ComboKey::Char(c),
+        _ => ComboKey::Special(parse_special_key(key_token)?),
+    };
+
+    match key {
+        ComboKey::Special(key) if modifiers.is_empty() => Ok(OutputAction::Key(key)),
+        key => Ok(OutputAction::KeyCombo { modifiers, key }),
+    }
+}
+
 fn parse_special_key(name: &str) -> Result<SpecialKey> {
     let key = match name.to_ascii_uppercase().as_str() {
         "ENTER" | "RETURN" => SpecialKey::Enter,
@@ -303,13 +1197,22 @@ fn parse_special_key(name: &str) -> Result<SpecialKey> {
 #[cfg(test)]
 mod tests {
     use super::{
-        is_valid_for_format, parse_expansion_actions, render_template_macros, OutputAction,
+        apply_newline_mode, format_actions, is_valid_for_format, parse_expansion_actions,
+        parse_expansion_actions_with_context, render_template_macros,
+        render_template_macros_with_context, static_command_macros, CaretUnit, ExpansionContext,
+        OutputAction, RenderLimits,
     };
-    use crate::io::output::SpecialKey;
+    use crate::config::{ExpansionRule, Globals, NewlineMode};
+    use crate::core::host_info::{InterfaceSource, Ipv4Interface, SystemInterfaces};
+    use crate::core::shell_command::{CommandOutput, DenyAllRunner, MockRunner};
+    use crate::io::output::{ComboKey, KeyModifier, SpecialKey};
+    use crate::io::secrets::{MemorySecretStore, SecretError, SECRET_MASK};
+    use chrono::{DateTime, Local, TimeZone};
     use std::collections::HashMap;
+    use std::net::Ipv4Addr;
 
-    fn no_globals() -> HashMap<String, String> {
-        HashMap::new()
+    fn no_globals() -> Globals {
+        Globals::new()
     }
 
     #[test]
@@ -348,6 +1251,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_sleep_ranges_and_rejects_bad_ones() {
+        let actions =
+            parse_expansion_actions("{{SLEEP_MS:100-300}}{{SLEEP_MS: 5 - 5 }}", &no_globals())
+                .expect("ranges should parse");
+        assert_eq!(format_actions(&actions), "[SLEEP 100-300ms][SLEEP 5-5ms]");
+
+        for (input, message) in [
+            (
+                "{{SLEEP_MS:300-100}}",
+                "invalid sleep range '300-100': 300 is more than 100",
+            ),
+            (
+                "{{SLEEP_MS:0-20000}}",
+                "invalid sleep range '0-20000': sleep ranges go up to 10000 ms",
+            ),
+            (
+                "{{SLEEP_MS:-100}}",
+                "cannot parse integer from empty string",
+            ),
+        ] {
+            let err = parse_expansion_actions(input, &no_globals()).expect_err(input);
+            assert!(format!("{err:#}").contains(message), "{input}: {err:#}");
+        }
+    }
+
     #[test]
     fn parses_move_caret_macro() {
         let actions = parse_expansion_actions("x{{MOVE_CARET:-3}}y", &no_globals())
@@ -359,7 +1288,10 @@ mod tests {
             _ => panic!("expected first text action"),
         }
         match actions[1] {
-            OutputAction::MoveCaret(-3) => {}
+            OutputAction::MoveCaret {
+                amount: -3,
+                unit: CaretUnit::Chars,
+            } => {}
             _ => panic!("expected move caret action"),
         }
         match &actions[2] {
@@ -375,11 +1307,56 @@ mod tests {
 
         assert_eq!(actions.len(), 1);
         match actions[0] {
-            OutputAction::MoveCaret(2) => {}
+            OutputAction::MoveCaret {
+                amount: 2,
+                unit: CaretUnit::Chars,
+            } => {}
             _ => panic!("expected move caret action"),
         }
     }
 
+    #[test]
+    fn parses_caret_moves_by_words_and_lines() {
+        let actions = parse_expansion_actions(
+            "{{MOVE_CARET:-2w}}{{MOVE_CARET:+1l}}{{MOVE_CARET:3c}}{{MOVE_CARET:+4}}{{MOVE_CARET:-1L}}{{MOVE_CARET: 5W }}",
+            &no_globals(),
+        )
+        .expect("parsing should succeed");
+
+        assert_eq!(
+            format_actions(&actions),
+            "[CARET -2w][CARET 1l][CARET 3][CARET 4][CARET -1l][CARET 5w]"
+        );
+        for input in [
+            "{{MOVE_CARET:w}}",
+            "{{MOVE_CARET:2x}}",
+            "{{MOVE_CARET:--1l}}",
+        ] {
+            let err = parse_expansion_actions(input, &no_globals()).expect_err(input);
+            assert!(
+                format!("{err:#}").contains("invalid caret move"),
+                "{input}: {err:#}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_select_and_select_word_macros() {
+        let actions = parse_expansion_actions(
+            "Dear NAME{{SELECT:-4}}{{SELECT:+2w}}{{select_word}}",
+            &no_globals(),
+        )
+        .expect("parsing should succeed");
+
+        assert_eq!(
+            format_actions(&actions),
+            "Dear NAME[SELECT -4][SELECT 2w][SELECT -1w]"
+        );
+        let err = parse_expansion_actions("{{SELECT:left}}", &no_globals())
+            .expect_err("a count is required");
+        assert!(format!("{err:#}").contains("invalid selection 'left'"));
+    }
+
     #[test]
     fn parses_datetime_macro_in_expansion() {
         let actions = parse_expansion_actions("Today: {{DATE}} {{TIME}}", &no_globals())
@@ -428,7 +1405,7 @@ mod tests {
 
     #[test]
     fn renders_global_template_macro_with_nested_macros() {
-        let mut globals = HashMap::new();
+        let mut globals = Globals::new();
         globals.insert("GREETING".to_string(), "Hello".to_string());
         globals.insert(
             "SIGNOFF".to_string(),
@@ -440,9 +1417,30 @@ mod tests {
         assert!(rendered.starts_with("Msg: Hello, Tyler on "));
     }
 
+    #[test]
+    fn global_lookup_takes_the_first_case_insensitive_match_in_declaration_order() {
+        let globals = Globals::from([
+            ("name".to_string(), "first".to_string()),
+            ("NAME".to_string(), "second".to_string()),
+        ]);
+        assert_eq!(
+            render_template_macros("{{Name}}", &globals).expect("render"),
+            "first"
+        );
+
+        let globals = Globals::from([
+            ("NAME".to_string(), "second".to_string()),
+            ("name".to_string(), "first".to_string()),
+        ]);
+        assert_eq!(
+            render_template_macros("{{Name}}", &globals).expect("render"),
+            "second"
+        );
+    }
+
     #[test]
     fn parses_actions_from_global_template_expansion() {
-        let mut globals = HashMap::new();
+        let mut globals = Globals::new();
         globals.insert("SIGNATURE".to_string(), "Thanks{{KEY:ENTER}}".to_string());
 
         let actions = parse_expansion_actions("{{SIGNATURE}}", &globals)
@@ -460,9 +1458,569 @@ mod tests {
 
     #[test]
     fn renders_cmd_macro_output() {
-        let rendered = render_template_macros("{{CMD:printf hello}}", &no_globals())
-            .expect("command macro should render");
-        assert_eq!(rendered, "hello");
+        let globals = no_globals();
+        let runner = MockRunner::default().with_output(
+            "git branch --show-current",
+            CommandOutput::success("main\n"),
+        );
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered =
+            render_template_macros_with_context("[{{CMD:git branch --show-current}}]", &ctx)
+                .expect("command macro should render");
+        assert_eq!(rendered, "[main]");
+        assert_eq!(runner.calls(), vec!["git branch --show-current"]);
+    }
+
+    #[test]
+    fn cmd_macro_reports_failed_commands() {
+        let globals = no_globals();
+        let runner = MockRunner::default().with_output(
+            "false",
+            CommandOutput {
+                exit_code: Some(2),
+                stdout: String::new(),
+                stderr: "no such thing\n".to_string(),
+            },
+        );
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let err = render_template_macros_with_context("{{CMD:false}}", &ctx)
+            .expect_err("failed command should be an error");
+        assert!(err.to_string().contains("status: 2"), "{err}");
+        assert!(err.to_string().contains("no such thing"), "{err}");
+    }
+
+    #[test]
+    fn finds_static_command_macros() {
+        assert_eq!(
+            static_command_macros(
+                "{{CMD:date}} {{DATE}} {{cmd:timeout=2s:git log}} {{CMD:echo {{DATE}}}} {{EMOJI:x}}"
+            ),
+            vec!["date", "timeout=2s:git log"]
+        );
+    }
+
+    #[test]
+    fn renders_secret_macro_from_the_store() {
+        let globals = no_globals();
+        let secrets = MemorySecretStore::default().with_secret("gh-token", "ghp_123");
+        let ctx = ExpansionContext {
+            secrets: Some(&secrets),
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered = render_template_macros_with_context("token={{SECRET:gh-token}}", &ctx)
+            .expect("secret should render");
+        assert_eq!(rendered, "token=ghp_123");
+
+        let err = render_template_macros_with_context("{{SECRET:missing}}", &ctx)
+            .expect_err("unknown secret should fail");
+        assert_eq!(
+            err.downcast_ref::<SecretError>(),
+            Some(&SecretError::NotFound("missing".to_string()))
+        );
+        assert!(err.to_string().contains("'missing'"), "{err}");
+    }
+
+    #[test]
+    fn secret_macro_needs_a_store_except_in_dry_runs() {
+        let globals = no_globals();
+        let err = render_template_macros("{{SECRET:gh-token}}", &globals)
+            .expect_err("no store should fail");
+        assert!(err.to_string().contains("no secret store"), "{err}");
+
+        let ctx = ExpansionContext {
+            dry_run: true,
+            ..ExpansionContext::new(&globals)
+        };
+        let rendered = render_template_macros_with_context("[{{SECRET:gh-token}}]", &ctx)
+            .expect("dry run should not look the secret up");
+        assert_eq!(rendered, "[]");
+        assert!(render_template_macros_with_context("{{SECRET: }}", &ctx).is_err());
+    }
+
+    #[test]
+    fn renders_totp_codes_from_the_store_or_the_config() {
+        let globals = no_globals();
+        let secrets = MemorySecretStore::default()
+            .with_secret("totp/github", "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ")
+            .with_secret("totp/broken", "hunter2!");
+        let totp_secrets = HashMap::from([("aws".to_string(), "gezdgnbvgy3tqojq".to_string())]);
+        let ctx = ExpansionContext {
+            secrets: Some(&secrets),
+            totp_secrets: Some(&totp_secrets),
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered = render_template_macros_with_context(
+            "{{TOTP:github}} {{TOTP:aws:digits=8,period=60}}",
+            &ctx,
+        )
+        .expect("codes should render");
+        let (github, aws) = rendered.split_once(' ').expect("two codes");
+        assert!(github.len() == 6 && github.chars().all(|c| c.is_ascii_digit()));
+        assert!(aws.len() == 8 && aws.chars().all(|c| c.is_ascii_digit()));
+
+        let err = render_template_macros_with_context("{{TOTP:broken}}", &ctx)
+            .expect_err("invalid base32 should fail");
+        assert!(format!("{err:#}").contains("not valid base32"), "{err:#}");
+        assert!(!format!("{err:#}").contains("hunter2"));
+
+        let err = render_template_macros_with_context("{{TOTP:gitlab}}", &ctx)
+            .expect_err("missing secret should fail");
+        assert!(err.to_string().contains("'gitlab'"), "{err}");
+    }
+
+    #[test]
+    fn masked_contexts_hide_secrets_and_totp_codes() {
+        let globals = no_globals();
+        let secrets = MemorySecretStore::default()
+            .with_secret("gh-token", "ghp_123")
+            .with_secret("plain", SECRET_MASK);
+        let totp_secrets = HashMap::from([("aws".to_string(), "gezdgnbvgy3tqojq".to_string())]);
+        let ctx = ExpansionContext {
+            secrets: Some(&secrets),
+            totp_secrets: Some(&totp_secrets),
+            mask_secrets: true,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered =
+            render_template_macros_with_context("{{SECRET:gh-token}} {{TOTP:aws}}", &ctx)
+                .expect("masked macros should render");
+        assert_eq!(rendered, format!("{SECRET_MASK} {SECRET_MASK}"));
+        let err = render_template_macros_with_context("{{SECRET:missing}}", &ctx)
+            .expect_err("missing secrets still fail");
+        assert!(err.to_string().contains("'missing'"), "{err}");
+
+        // A secret that happens to equal the mask is still a secret.
+        let ctx = ExpansionContext {
+            mask_secrets: false,
+            ..ctx
+        };
+        let rendered = render_template_macros_with_context("{{SECRET:plain}}", &ctx)
+            .expect("secret should render");
+        assert_eq!(rendered, SECRET_MASK);
+    }
+
+    #[test]
+    fn deny_all_runner_refuses_cmd_macros() {
+        let globals = no_globals();
+        let ctx = ExpansionContext {
+            runner: &DenyAllRunner,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let err = render_template_macros_with_context("{{CMD:echo hi}}", &ctx)
+            .expect_err("commands should be refused");
+        assert!(err.to_string().contains("CMD macros are disabled"), "{err}");
+    }
+
+    #[test]
+    fn renders_text_transform_macros() {
+        let mut globals = Globals::new();
+        globals.insert("NAME".to_string(), "  ada lovelace ".to_string());
+        globals.insert("SHOUT".to_string(), "{{UPPER:{{NAME}}}}!".to_string());
+
+        let rendered = render_template_macros(
+            "Dear {{TITLE:{{TRIM:{{NAME}}}}}}, {{SHOUT}} {{lower:ÉCOLE}}",
+            &globals,
+        )
+        .expect("transforms should render");
+        assert_eq!(rendered, "Dear Ada Lovelace,   ADA LOVELACE ! école");
+
+        let rendered = render_template_macros("{{UPPER:straße}} {{TITLE:élan vital}}", &globals)
+            .expect("multi-byte text should render");
+        assert_eq!(rendered, "STRASSE Élan Vital");
+
+        let rendered = render_template_macros("[{{UPPER:}}][{{TRIM:   }}][{{TITLE:}}]", &globals)
+            .expect("empty arguments should render");
+        assert_eq!(rendered, "[][][]");
+    }
+
+    #[test]
+    fn transforms_compose_with_action_macros() {
+        let actions = parse_expansion_actions("{{UPPER:hi}}{{KEY:ENTER}}", &no_globals())
+            .expect("parsing should succeed");
+        assert!(matches!(&actions[..], [
+            OutputAction::Text(text),
+            OutputAction::Key(SpecialKey::Enter)
+        ] if text == "HI"));
+    }
+
+    #[test]
+    fn renders_urlencode_macro() {
+        let mut globals = Globals::new();
+        globals.insert("QUERY".to_string(), "a&b=c d".to_string());
+
+        let rendered = render_template_macros(
+            "https://example.com/?q={{URLENCODE:{{QUERY}}}}&r={{URLENCODE:-._~/?#[]@!$'()*+,;:%}}",
+            &globals,
+        )
+        .expect("urlencode should render");
+        assert_eq!(
+            rendered,
+            "https://example.com/?q=a%26b%3Dc%20d&r=-._~%2F%3F%23%5B%5D%40%21%24%27%28%29%2A%2B%2C%3B%3A%25"
+        );
+
+        let rendered = render_template_macros("{{URLENCODE:café ☕}}", &globals)
+            .expect("unicode should render");
+        assert_eq!(rendered, "caf%C3%A9%20%E2%98%95");
+    }
+
+    #[test]
+    fn renders_base64_macro_flavors() {
+        let mut globals = Globals::new();
+        globals.insert("CREDS".to_string(), "user:pa>ss?".to_string());
+
+        for (input, expected) in [
+            ("{{BASE64:{{CREDS}}}}", "dXNlcjpwYT5zcz8="),
+            ("{{BASE64:std:{{CREDS}}}}", "dXNlcjpwYT5zcz8="),
+            ("{{BASE64:url:{{CREDS}}}}", "dXNlcjpwYT5zcz8="),
+            ("{{BASE64:std:>>>?}}", "Pj4+Pw=="),
+            ("{{BASE64:url:>>>?}}", "Pj4-Pw=="),
+            ("{{BASE64:user:pa>ss?}}", "dXNlcjpwYT5zcz8="),
+            ("{{BASE64:héllo}}", "aMOpbGxv"),
+            ("{{BASE64:}}", ""),
+        ] {
+            let rendered = render_template_macros(input, &globals).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn renders_math_macro_with_globals() {
+        let mut globals = Globals::new();
+        globals.insert("PRICE".to_string(), "19.99".to_string());
+
+        let rendered = render_template_macros(
+            "Total: {{MATH:2:{{PRICE}}*1.19}} ({{MATH:{{PRICE}}*3}})",
+            &globals,
+        )
+        .expect("math should render");
+        assert_eq!(rendered, "Total: 23.79 (59.97)");
+
+        let err = render_template_macros("{{MATH:{{PRICE}}/0}}", &globals)
+            .expect_err("division by zero should fail");
+        assert!(err.to_string().contains("'19.99/0': division by zero"));
+    }
+
+    #[test]
+    fn renders_if_macro_branches() {
+        let mut globals = Globals::new();
+        globals.insert("HOUR".to_string(), "09".to_string());
+        globals.insert("NAME".to_string(), "Ada".to_string());
+
+        for (input, expected) in [
+            (
+                "{{IF:{{HOUR}}<12?Good morning:Good afternoon}}",
+                "Good morning",
+            ),
+            (
+                "{{IF:{{HOUR}} >= 12 ?Good morning:Good afternoon}}",
+                "Good afternoon",
+            ),
+            ("{{IF:9.0==09?same:different}}", "same"),
+            ("{{IF:{{NAME}}==Ada?Hi {{NAME}}:Hello}}", "Hi Ada"),
+            ("{{IF:{{NAME}}!=Ada?Hi:}}", ""),
+            ("{{IF:{{NAME}}==Bob?Hi}}", ""),
+            (
+                "[{{IF:1<2?{{UPPER:{{NAME}}}}:{{LOWER:{{NAME}}}}}}]",
+                "[ADA]",
+            ),
+        ] {
+            let rendered = render_template_macros(input, &globals).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn if_macro_honors_escaped_delimiters() {
+        let globals = no_globals();
+        for (input, expected) in [
+            (r"{{IF:1<2?Ready\? Go\: now:Wait}}", "Ready? Go: now"),
+            (r"{{IF:1>2?Ready:Time\: later\?}}", "Time: later?"),
+            (r"{{IF:a\:b==a\:b?match:no}}", "match"),
+            (r"{{IF:1<2?back\\slash:no}}", r"back\slash"),
+            (r"{{IF:1<2?C:\temp:no}}", r"C"),
+            (r"{{IF:1<2?{{UPPER:a:b}}:no}}", "A:B"),
+        ] {
+            let rendered = render_template_macros(input, &globals).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn if_macro_only_runs_the_chosen_branch() {
+        let globals = no_globals();
+        let runner = MockRunner::default().with_output("echo yes", CommandOutput::success("yes\n"));
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered = render_template_macros_with_context(
+            "{{IF:1<2?{{CMD:echo yes}}:{{CMD:echo no}}}}",
+            &ctx,
+        )
+        .expect("if should render");
+        assert_eq!(rendered, "yes");
+        assert_eq!(runner.calls(), vec!["echo yes"]);
+    }
+
+    #[test]
+    fn if_macro_reports_malformed_conditions() {
+        let globals = no_globals();
+        for (input, expected) in [
+            ("{{IF:1<2}}", "IF macro needs a '?'"),
+            ("{{IF:yes?a:b}}", "IF condition 'yes' needs one of"),
+            (
+                "{{IF:abc<2?a:b}}",
+                "IF condition 'abc < 2' compares numbers",
+            ),
+        ] {
+            let err = render_template_macros(input, &globals).expect_err(input);
+            assert!(err.to_string().contains(expected), "{input}: {err}");
+        }
+    }
+
+    #[test]
+    fn renders_date_macros_from_the_clock() {
+        let globals = no_globals();
+        let clock = || {
+            Local
+                .with_ymd_and_hms(2024, 3, 4, 9, 5, 7)
+                .single()
+                .expect("unambiguous local time")
+        };
+        let ctx = ExpansionContext {
+            clock: &clock,
+            ..ExpansionContext::new(&globals)
+        };
+
+        for (input, expected) in [
+            ("{{DATETIME}}", "2024-03-04 09:05:07"),
+            (
+                "{{WEEKDAY}}, week {{WEEK}} of {{MONTH_NAME}}",
+                "Monday, week 10 of March",
+            ),
+            ("{{TIME_HOUR}}:{{TIME_MINUTE}}", "09:05"),
+            (
+                "{{DATE_LOCALE:de-DE:%A, %d. %B %Y}}",
+                "Montag, 04. März 2024",
+            ),
+            (
+                "{{DATE_LOCALE:fr-FR:%A %-d %B, %H:%M}}",
+                "lundi 4 mars, 09:05",
+            ),
+            ("{{DATE_LOCALE:es_es:%a %d %b}}", "lun 04 mar"),
+            ("{{DATE:%d.%m.%Y %H:%M}}", "04.03.2024 09:05"),
+            ("{{DATE:offset=86400:%A %d}}", "Tuesday 05"),
+            ("{{DATE:offset=-3600:%H:%M}}", "08:05"),
+        ] {
+            let rendered = render_template_macros_with_context(input, &ctx).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+        }
+
+        let err = render_template_macros_with_context("{{DATE_LOCALE:tlh:%A}}", &ctx)
+            .expect_err("unknown locale");
+        assert!(err.to_string().contains("supported: en-US, en-GB, de-DE"));
+        let err = render_template_macros_with_context("{{DATE:offset=1d:%d}}", &ctx)
+            .expect_err("offsets are seconds");
+        assert!(
+            err.to_string().contains("invalid DATE offset '1d'"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn renders_timestamp_macros_from_the_clock() {
+        let globals = no_globals();
+        let now = Local
+            .timestamp_millis_opt(1_709_543_107_123)
+            .single()
+            .expect("valid timestamp");
+        let clock = || now;
+        let ctx = ExpansionContext {
+            clock: &clock,
+            ..ExpansionContext::new(&globals)
+        };
+
+        for (input, expected) in [
+            ("{{EPOCH}}", "1709543107"),
+            ("{{EPOCH_MS}}", "1709543107123"),
+            ("{{ISO8601:utc}}", "2024-03-04T09:05:07Z"),
+            ("{{ISO8601:UTC}}", "2024-03-04T09:05:07Z"),
+        ] {
+            let rendered = render_template_macros_with_context(input, &ctx).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+        }
+
+        for input in ["{{ISO8601}}", "{{ISO8601:local}}"] {
+            let rendered = render_template_macros_with_context(input, &ctx).expect(input);
+            let offset = now.format("%:z").to_string();
+            assert_eq!(
+                rendered,
+                format!("{}{offset}", now.format("%Y-%m-%dT%H:%M:%S")),
+                "{input}"
+            );
+            let parsed = DateTime::parse_from_rfc3339(&rendered).expect("valid RFC 3339");
+            assert_eq!(parsed.timestamp(), 1_709_543_107);
+        }
+
+        assert!(render_template_macros_with_context("{{ISO8601:mars}}", &ctx).is_err());
+    }
+
+    #[test]
+    fn renders_local_ip_from_the_interface_source() {
+        struct FakeInterfaces;
+
+        impl InterfaceSource for FakeInterfaces {
+            fn ipv4_interfaces(&self) -> anyhow::Result<Vec<Ipv4Interface>> {
+                Ok(vec![
+                    Ipv4Interface {
+                        name: "lo".to_string(),
+                        address: Ipv4Addr::LOCALHOST,
+                        loopback: true,
+                    },
+                    Ipv4Interface {
+                        name: "wlan0".to_string(),
+                        address: Ipv4Addr::new(192, 168, 1, 20),
+                        loopback: false,
+                    },
+                ])
+            }
+        }
+
+        let globals = no_globals();
+        let ctx = ExpansionContext {
+            interfaces: &FakeInterfaces,
+            ..ExpansionContext::new(&globals)
+        };
+        let rendered = render_template_macros_with_context("{{LOCAL_IP}} {{LOCAL_IP:lo}}", &ctx)
+            .expect("addresses should render");
+        assert_eq!(rendered, "192.168.1.20 127.0.0.1");
+        let err = render_template_macros_with_context("{{LOCAL_IP:eth0}}", &ctx)
+            .expect_err("unknown interface");
+        assert!(err
+            .to_string()
+            .contains("no IPv4 address on interface 'eth0'"));
+
+        let ctx = ExpansionContext {
+            dry_run: true,
+            ..ctx
+        };
+        let rendered = render_template_macros_with_context(
+            "[{{LOCAL_IP}}{{LOCAL_IP:eth0}}{{HOSTNAME}}{{USERNAME}}{{OS_RELEASE}}]",
+            &ctx,
+        )
+        .expect("dry runs look nothing up");
+        assert_eq!(rendered, "[]");
+    }
+
+    #[test]
+    fn escaped_braces_are_typed_literally() {
+        let mut globals = Globals::new();
+        globals.insert(
+            "TEMPLATE".to_string(),
+            "\\{{DATE\\}} is {{NAME}}".to_string(),
+        );
+        globals.insert("NAME".to_string(), "a macro".to_string());
+
+        for (input, expected) in [
+            ("\\{{DATE}}", "{{DATE}}"),
+            ("Use \\{{NAME\\}}", "Use {{NAME}}"),
+            ("{{NAME}}\\{{NAME}}{{NAME}}", "a macro{{NAME}}a macro"),
+            ("{{TEMPLATE}}", "{{DATE}} is a macro"),
+            ("{{UPPER:\\{{x\\}}}}", "{{X}}"),
+            ("ends with \\}}", "ends with }}"),
+        ] {
+            let rendered = render_template_macros(input, &globals).expect(input);
+            assert_eq!(rendered, expected, "{input}");
+
+            let actions = parse_expansion_actions(input, &globals).expect(input);
+            assert!(
+                matches!(&actions[..], [OutputAction::Text(text)] if text == expected),
+                "{input}: {actions:?}"
+            );
+        }
+
+        let actions =
+            parse_expansion_actions("\\{{KEY:ENTER}}{{KEY:ENTER}}", &globals).expect("parse");
+        assert!(matches!(&actions[..], [
+            OutputAction::Text(text),
+            OutputAction::Key(SpecialKey::Enter)
+        ] if text == "{{KEY:ENTER}}"));
+    }
+
+    #[test]
+    fn command_output_is_typed_literally() {
+        let mut globals = Globals::new();
+        globals.insert("CONFIG".to_string(), "{{CMD:cat config}}".to_string());
+        let runner = MockRunner::default().with_output(
+            "cat config",
+            CommandOutput::success("{{KEY:ENTER}} \\{{x}}\n"),
+        );
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..ExpansionContext::new(&globals)
+        };
+
+        for input in [
+            "[{{CMD:cat config}}]{{KEY:TAB}}",
+            "[{{CONFIG}}]{{KEY:TAB}}",
+            "[{{TRIM:{{CMD:cat config}}}}]{{KEY:TAB}}",
+            "[{{IF:1<2?{{CONFIG}}:no}}]{{KEY:TAB}}",
+        ] {
+            let actions = parse_expansion_actions_with_context(input, &ctx).expect(input);
+            assert!(
+                matches!(&actions[..], [
+                    OutputAction::Text(text),
+                    OutputAction::Key(SpecialKey::Tab)
+                ] if text == "[{{KEY:ENTER}} \\{{x}}]"),
+                "{input}: {actions:?}"
+            );
+        }
+
+        let rendered = render_template_macros_with_context("{{CONFIG}}", &ctx).expect("render");
+        assert_eq!(rendered, "{{KEY:ENTER}} \\{{x}}");
+    }
+
+    #[test]
+    fn secrets_and_last_expansion_are_typed_literally() {
+        let globals = no_globals();
+        let secrets = MemorySecretStore::default().with_secret("odd", "p{{KEY:ENTER}}w");
+        let ctx = ExpansionContext {
+            secrets: Some(&secrets),
+            last_expansion: Some("{{KEY:BACKSPACE}}"),
+            ..ExpansionContext::new(&globals)
+        };
+
+        let actions = parse_expansion_actions_with_context(
+            "{{SECRET:odd}}{{UPPER:{{LAST_EXPANSION}}}}",
+            &ctx,
+        )
+        .expect("parse");
+        assert!(
+            matches!(&actions[..], [OutputAction::Text(text)] if text == "p{{KEY:ENTER}}w{{KEY:BACKSPACE}}"),
+            "{actions:?}"
+        );
+    }
+
+    #[test]
+    fn unsupported_macro_errors_suggest_the_escape() {
+        let err = render_template_macros("{{NOPE}}", &no_globals()).expect_err("unknown macro");
+        assert_eq!(
+            err.to_string(),
+            "unsupported macro: 'NOPE' (write \\{{ and \\}} for literal braces)"
+        );
     }
 
     #[test]
@@ -486,13 +2044,315 @@ mod tests {
         assert!(err.to_string().contains("unknown emoji shortcode"));
     }
 
+    #[test]
+    fn rejects_globals_nested_past_max_depth() {
+        let globals: Globals = (0..20)
+            .map(|level| (format!("G{level}"), format!("{{{{G{}}}}}", level + 1)))
+            .chain([("G20".to_string(), "bottom".to_string())])
+            .collect();
+
+        let err = render_template_macros("{{G0}}", &globals).expect_err("too deep");
+        let chain = (0..=16)
+            .map(|level| format!("G{level}"))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        assert_eq!(
+            err.to_string(),
+            format!("global macros nest more than 16 deep (expansion.max_depth): {chain}")
+        );
+        assert_eq!(
+            render_template_macros("{{G5}}", &globals).expect("16 levels are fine"),
+            "bottom"
+        );
+    }
+
+    #[test]
+    fn rejects_output_past_max_output_bytes() {
+        // Each level doubles its child: E0 would be 2^40 bytes.
+        let globals: Globals = (0..40)
+            .map(|level| {
+                (
+                    format!("E{level}"),
+                    format!("{{{{E{0}}}}}{{{{E{0}}}}}", level + 1),
+                )
+            })
+            .chain([("E40".to_string(), "x".to_string())])
+            .collect();
+        let ctx = ExpansionContext {
+            limits: RenderLimits {
+                max_depth: 64,
+                max_output_bytes: 1000,
+            },
+            ..ExpansionContext::new(&globals)
+        };
+
+        let err = render_template_macros_with_context("{{E0}}", &ctx).expect_err("too large");
+        let chain = (0..=30)
+            .map(|level| format!("E{level}"))
+            .collect::<Vec<_>>()
+            .join(" -> ");
+        assert_eq!(
+            err.to_string(),
+            format!("{chain} renders to more than 1000 bytes (expansion.max_output_bytes)")
+        );
+
+        let runner = MockRunner::default()
+            .with_output("yes | head", CommandOutput::success("y\n".repeat(600)));
+        let ctx = ExpansionContext {
+            runner: &runner,
+            ..ctx
+        };
+        let err = parse_expansion_actions_with_context("{{CMD:yes | head}}", &ctx)
+            .expect_err("command output counts too");
+        assert_eq!(
+            err.to_string(),
+            "the expansion renders to more than 1000 bytes (expansion.max_output_bytes)"
+        );
+    }
+
     #[test]
     fn rejects_global_macro_cycles() {
-        let mut globals = HashMap::new();
+        let mut globals = Globals::new();
         globals.insert("A".to_string(), "{{B}}".to_string());
         globals.insert("B".to_string(), "{{A}}".to_string());
 
         let err = render_template_macros("{{A}}", &globals).expect_err("cycle should fail");
         assert!(err.to_string().contains("cycle"));
     }
+
+    fn chained_rules() -> Vec<ExpansionRule> {
+        [
+            (";sig", "Thanks,{{KEY:ENTER}}Tyler"),
+            (";reply", "Got it.{{KEY:ENTER}}{{EXPAND:;sig}}"),
+            (";a", "a{{EXPAND:;b}}"),
+            (";b", "b{{EXPAND: ;a }}"),
+            (";lost", "{{EXPAND:;nope}}"),
+        ]
+        .into_iter()
+        .map(|(trigger, expansion)| ExpansionRule {
+            trigger: trigger.to_string(),
+            expansion: expansion.to_string(),
+            ..ExpansionRule::default()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn expand_splices_in_another_rule() {
+        let mut globals = no_globals();
+        globals.insert("SIGNOFF".to_string(), "--{{EXPAND:;sig}}".to_string());
+        let rules = chained_rules();
+        let ctx = ExpansionContext {
+            rules: &rules,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let actions = parse_expansion_actions_with_context("{{EXPAND:;reply}}!", &ctx)
+            .expect("chain should expand");
+        assert_eq!(
+            format_actions(&actions),
+            "Got it.[KEY Enter]Thanks,[KEY Enter]Tyler!"
+        );
+        let actions = parse_expansion_actions_with_context("{{UPPER:{{SIGNOFF}}}}", &ctx)
+            .expect("globals can expand rules too");
+        assert_eq!(format_actions(&actions), "--THANKS,[KEY Enter]TYLER");
+    }
+
+    #[test]
+    fn expand_names_the_chain_of_unknown_triggers_and_cycles() {
+        let globals = no_globals();
+        let rules = chained_rules();
+        let ctx = ExpansionContext {
+            rules: &rules,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let err = parse_expansion_actions_with_context("{{EXPAND:;a}}", &ctx)
+            .expect_err("cycle should fail");
+        assert_eq!(
+            err.to_string(),
+            "expansion cycle detected: EXPAND:;a -> EXPAND:;b -> EXPAND:;a"
+        );
+        let err = parse_expansion_actions_with_context("{{EXPAND:;lost}}", &ctx)
+            .expect_err("unknown trigger should fail");
+        assert_eq!(
+            err.to_string(),
+            "no rule has the trigger ';nope': EXPAND:;lost -> EXPAND:;nope"
+        );
+        let err = parse_expansion_actions("{{EXPAND:;sig}}", &globals)
+            .expect_err("no rules to expand without a rule set");
+        assert_eq!(
+            err.to_string(),
+            "no rule has the trigger ';sig': EXPAND:;sig"
+        );
+    }
+
+    #[test]
+    fn dry_run_does_not_run_commands() {
+        let globals = no_globals();
+        let ctx = ExpansionContext {
+            dry_run: true,
+            ..ExpansionContext::new(&globals)
+        };
+
+        let rendered = render_template_macros_with_context("[{{CMD:exit 1}}]", &ctx)
+            .expect("dry run should not run the command");
+        assert_eq!(rendered, "[]");
+    }
+
+    #[test]
+    fn renders_last_expansion_macro_from_context() {
+        let globals = no_globals();
+        let ctx = ExpansionContext {
+            globals: &globals,
+            rules: &[],
+            last_expansion: Some("previous text"),
+            dry_run: false,
+            cache: None,
+            runner: &DenyAllRunner,
+            secrets: None,
+            totp_secrets: None,
+            mask_secrets: false,
+            clock: &Local::now,
+            interfaces: &SystemInterfaces,
+            limits: RenderLimits::default(),
+        };
+
+        let rendered = render_template_macros_with_context("again: {{LAST_EXPANSION}}", &ctx)
+            .expect("render should succeed");
+        assert_eq!(rendered, "again: previous text");
+
+        let rendered = render_template_macros("[{{LAST_EXPANSION}}]", &globals)
+            .expect("render should succeed");
+        assert_eq!(rendered, "[]");
+    }
+
+    #[test]
+    fn formats_actions_readably() {
+        let actions = parse_expansion_actions(
+            "Best,{{KEY:ENTER}}{{SLEEP_MS:50}}Tyler{{MOVE_CARET:-3}}",
+            &no_globals(),
+        )
+        .expect("parsing should succeed");
+
+        assert_eq!(
+            format_actions(&actions),
+            "Best,[KEY Enter][SLEEP 50ms]Tyler[CARET -3]"
+        );
+    }
+
+    #[test]
+    fn formats_text_verbatim() {
+        let actions = vec![OutputAction::Text("line one\n  [not a key]".to_string())];
+
+        assert_eq!(format_actions(&actions), "line one\n  [not a key]");
+    }
+
+    #[test]
+    fn newline_modes_rewrite_line_breaks_in_text_only() {
+        let actions =
+            parse_expansion_actions("Hi,\nthanks{{KEY:ENTER}}\r\n\nTyler\n", &no_globals())
+                .expect("parsing should succeed");
+        let formatted = |mode| format_actions(&apply_newline_mode(actions.clone(), mode));
+
+        assert_eq!(
+            formatted(NewlineMode::Text),
+            "Hi,\nthanks[KEY Enter]\r\n\nTyler\n"
+        );
+        assert_eq!(
+            formatted(NewlineMode::Enter),
+            "Hi,[KEY Enter]thanks[KEY Enter][KEY Enter][KEY Enter]Tyler[KEY Enter]"
+        );
+        assert_eq!(
+            formatted(NewlineMode::Strip),
+            "Hi, thanks[KEY Enter]  Tyler "
+        );
+        let shift_enter = apply_newline_mode(actions.clone(), NewlineMode::ShiftEnter);
+        assert_eq!(
+            format_actions(&shift_enter),
+            "Hi,[KEY Shift+Enter]thanks[KEY Enter][KEY Shift+Enter][KEY Shift+Enter]Tyler[KEY Shift+Enter]"
+        );
+        assert!(matches!(
+            &shift_enter[1],
+            OutputAction::KeyCombo { modifiers, key: ComboKey::Special(SpecialKey::Enter) }
+                if modifiers == &[KeyModifier::Shift]
+        ));
+    }
+
+    #[test]
+    fn parses_key_chords() {
+        let actions = parse_expansion_actions(
+            "{{KEY:CTRL+SHIFT+V}}{{KEY:shift + end}}{{KEY:TAB}}",
+            &no_globals(),
+        )
+        .expect("parsing should succeed");
+
+        match &actions[0] {
+            OutputAction::KeyCombo { modifiers, key } => {
+                assert_eq!(modifiers, &vec![KeyModifier::Ctrl, KeyModifier::Shift]);
+                assert_eq!(*key, ComboKey::Char('v'));
+            }
+            other => panic!("expected key combo, got {other:?}"),
+        }
+        match &actions[1] {
+            OutputAction::KeyCombo { modifiers, key } => {
+                assert_eq!(modifiers, &vec![KeyModifier::Shift]);
+                assert_eq!(*key, ComboKey::Special(SpecialKey::End));
+            }
+            other => panic!("expected key combo, got {other:?}"),
+        }
+        assert!(matches!(actions[2], OutputAction::Key(SpecialKey::Tab)));
+        assert_eq!(
+            format_actions(&actions),
+            "[KEY Ctrl+Shift+v][KEY Shift+End][KEY Tab]"
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_chord_modifiers_and_missing_keys() {
+        let err = parse_expansion_actions("{{KEY:HYPER+A}}", &no_globals())
+            .expect_err("unknown modifier should fail");
+        assert!(err.to_string().contains("unknown key modifier"));
+
+        let err = parse_expansion_actions("{{KEY:CTRL+}}", &no_globals())
+            .expect_err("missing key should fail");
+        assert!(err.to_string().contains("missing key"));
+
+        let err = parse_expansion_actions("{{KEY:CTRL+NOPE}}", &no_globals())
+            .expect_err("unknown key should fail");
+        assert!(err.to_string().contains("unknown special key"));
+    }
+
+    #[test]
+    fn repeats_key_actions() {
+        let actions = parse_expansion_actions(
+            "{{KEY:LEFT*5}}{{KEY:CTRL+Z * 2}}{{KEY:*}}{{KEY:SHIFT+**3}}",
+            &no_globals(),
+        )
+        .expect("parsing should succeed");
+
+        assert_eq!(actions.len(), 11);
+        assert!(actions[..5]
+            .iter()
+            .all(|action| matches!(action, OutputAction::Key(SpecialKey::Left))));
+        assert_eq!(
+            format_actions(&actions[5..]),
+            "[KEY Ctrl+z][KEY Ctrl+z][KEY *][KEY Shift+*][KEY Shift+*][KEY Shift+*]"
+        );
+    }
+
+    #[test]
+    fn rejects_key_repeat_counts_out_of_range() {
+        for value in ["TAB*0", "TAB*101"] {
+            let err = parse_expansion_actions(&format!("{{{{KEY:{value}}}}}"), &no_globals())
+                .expect_err("out of range count should fail");
+            assert!(err
+                .to_string()
+                .contains("key repeat count must be between 1 and 100"));
+        }
+
+        let err = parse_expansion_actions("{{KEY:TAB*x}}", &no_globals())
+            .expect_err("non-numeric count should fail");
+        assert!(err.to_string().contains("invalid key repeat count"));
+    }
 }
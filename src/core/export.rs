@@ -0,0 +1,485 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{
+    AppConfig, BoundaryPosition, ExpansionRule, InjectMode, KeepBoundary, MenuSnippet, NewlineMode,
+    SnippetMode,
+};
+
+/// Column names of [`RuleSet::to_csv`].
+pub const CSV_HEADER: [&str; 19] = [
+    "kind",
+    "name",
+    "text",
+    "apps",
+    "exclude_apps",
+    "inject_mode",
+    "char_delay_ms",
+    "notify",
+    "notify_message",
+    "enabled",
+    "keep_boundary",
+    "boundary_position",
+    "strip_trailing_newline",
+    "newline",
+    "group",
+    "separator_after",
+    "mode",
+    "type_delay_ms",
+    "label",
+];
+
+/// The rules of a config without its settings: what `slykey export` writes
+/// and `slykey import --format slykey-json` reads back. Globals are sorted so
+/// the output is stable.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expansions: Vec<ExpansionRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snippets: Vec<MenuSnippet>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub globals: BTreeMap<String, String>,
+}
+
+impl RuleSet {
+    pub fn from_config(config: &AppConfig) -> Self {
+        Self {
+            expansions: config.expansions.clone(),
+            snippets: config.snippets.clone(),
+            globals: config
+                .globals
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn from_json(raw: &str) -> Result<Self> {
+        serde_json::from_str(raw).context("failed to parse slykey JSON export")
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let mut json = serde_json::to_string_pretty(self).context("failed to serialize rules")?;
+        json.push('\n');
+        Ok(json)
+    }
+
+    /// A YAML document that works as a config file or an `include`.
+    pub fn to_yaml(&self) -> Result<String> {
+        serde_yaml::to_string(self).context("failed to serialize rules")
+    }
+
+    /// One row per expansion, snippet and global under [`CSV_HEADER`]. App
+    /// lists are joined with `;`; fields with commas, quotes or line breaks
+    /// are quoted as in RFC 4180.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        push_csv_row(&mut out, &CSV_HEADER.map(str::to_string));
+        for rule in &self.expansions {
+            push_csv_row(
+                &mut out,
+                &[
+                    "expansion".to_string(),
+                    rule.trigger.clone(),
+                    rule.expansion.clone(),
+                    rule.apps.join(";"),
+                    rule.exclude_apps.join(";"),
+                    rule.inject_mode
+                        .map(inject_mode_name)
+                        .unwrap_or_default()
+                        .to_string(),
+                    rule.char_delay_ms
+                        .map(|ms| ms.to_string())
+                        .unwrap_or_default(),
+                    rule.notify
+                        .map(|notify| notify.to_string())
+                        .unwrap_or_default(),
+                    rule.notify_message.clone().unwrap_or_default(),
+                    rule.enabled
+                        .map(|enabled| enabled.to_string())
+                        .unwrap_or_default(),
+                    rule.keep_boundary
+                        .map(keep_boundary_name)
+                        .unwrap_or_default()
+                        .to_string(),
+                    rule.boundary_position
+                        .map(boundary_position_name)
+                        .unwrap_or_default()
+                        .to_string(),
+                    rule.strip_trailing_newline
+                        .map(|strip| strip.to_string())
+                        .unwrap_or_default(),
+                    newline_mode_name(rule.newline).to_string(),
+                    rule.group.clone().unwrap_or_default(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    rule.label.clone().unwrap_or_default(),
+                ],
+            );
+        }
+        for snippet in &self.snippets {
+            push_csv_row(
+                &mut out,
+                &[
+                    "snippet".to_string(),
+                    snippet.title.clone(),
+                    snippet.content.clone(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    snippet.group.clone().unwrap_or_default(),
+                    snippet.separator_after.to_string(),
+                    snippet_mode_name(snippet.mode).to_string(),
+                    snippet
+                        .type_delay_ms
+                        .map(|ms| ms.to_string())
+                        .unwrap_or_default(),
+                    String::new(),
+                ],
+            );
+        }
+        for (name, value) in &self.globals {
+            let mut row = vec!["global".to_string(), name.clone(), value.clone()];
+            row.resize(CSV_HEADER.len(), String::new());
+            push_csv_row(&mut out, &row);
+        }
+        out
+    }
+
+    /// Drops entries `config` already has: expansions with a configured
+    /// trigger, snippets with a configured title and content, and globals it
+    /// already defines (case-insensitively, like includes). Returns a
+    /// description of each dropped entry whose value differed.
+    pub fn remove_existing(&mut self, config: &AppConfig) -> Vec<String> {
+        let mut dropped = Vec::new();
+        self.expansions.retain(|rule| {
+            let exists = config
+                .expansions
+                .iter()
+                .any(|existing| existing.trigger == rule.trigger);
+            if exists {
+                dropped.push(format!(
+                    "{}: trigger already exists in the config (skipped)",
+                    rule.trigger
+                ));
+            }
+            !exists
+        });
+        self.snippets.retain(|snippet| {
+            let exists = config.snippets.iter().any(|existing| {
+                existing.title == snippet.title && existing.content == snippet.content
+            });
+            if exists {
+                dropped.push(format!(
+                    "snippet '{}': already exists in the config (skipped)",
+                    snippet.title
+                ));
+            }
+            !exists
+        });
+        self.globals.retain(|name, value| {
+            let existing = config
+                .globals
+                .iter()
+                .find(|(existing, _)| existing.eq_ignore_ascii_case(name));
+            match existing {
+                Some((_, existing)) if existing == value => false,
+                Some(_) => {
+                    dropped.push(format!(
+                        "global '{name}': already defined in the config with another value (skipped)"
+                    ));
+                    false
+                }
+                None => true,
+            }
+        });
+        dropped
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expansions.is_empty() && self.snippets.is_empty() && self.globals.is_empty()
+    }
+}
+
+fn inject_mode_name(mode: InjectMode) -> &'static str {
+    match mode {
+        InjectMode::Type => "type",
+        InjectMode::Clipboard => "clipboard",
+    }
+}
+
+fn keep_boundary_name(keep: KeepBoundary) -> &'static str {
+    match keep {
+        KeepBoundary::Keep => "true",
+        KeepBoundary::Swallow => "false",
+        KeepBoundary::Smart => "smart",
+    }
+}
+
+fn boundary_position_name(position: BoundaryPosition) -> &'static str {
+    match position {
+        BoundaryPosition::Before => "before",
+        BoundaryPosition::After => "after",
+    }
+}
+
+fn newline_mode_name(mode: NewlineMode) -> &'static str {
+    match mode {
+        NewlineMode::Text => "text",
+        NewlineMode::Enter => "enter",
+        NewlineMode::ShiftEnter => "shift_enter",
+        NewlineMode::Strip => "strip",
+    }
+}
+
+fn snippet_mode_name(mode: SnippetMode) -> &'static str {
+    match mode {
+        SnippetMode::Clipboard => "clipboard",
+        SnippetMode::Type => "type",
+    }
+}
+
+fn push_csv_row(out: &mut String, fields: &[String]) {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuleSet;
+    use crate::config::{
+        AppConfig, BoundaryPosition, ExpansionRule, InjectMode, KeepBoundary, MenuSnippet,
+        NewlineMode, SnippetMode,
+    };
+    use crate::core::bench::Lcg;
+
+    // The generator lets the round-trip test cover many shapes without a
+    // property-testing dependency.
+    fn text(rng: &mut Lcg) -> String {
+        const PIECES: [&str; 10] = [
+            "a", " ", ",", "\"", "\n", "\r\n", "é", "{{DATE}}", "\\", "🙂",
+        ];
+        (0..rng.below(8))
+            .map(|_| PIECES[rng.below(PIECES.len())])
+            .collect()
+    }
+
+    fn option<T>(rng: &mut Lcg, value: impl FnOnce(&mut Lcg) -> T) -> Option<T> {
+        (rng.below(2) == 0).then(|| value(rng))
+    }
+
+    fn random_rules(rng: &mut Lcg) -> RuleSet {
+        let expansions = (0..rng.below(4))
+            .map(|index| ExpansionRule {
+                trigger: format!(";t{index}{}", text(rng)),
+                expansion: text(rng),
+                label: option(rng, text),
+                apps: (0..rng.below(3)).map(|_| text(rng)).collect(),
+                exclude_apps: (0..rng.below(2)).map(|_| text(rng)).collect(),
+                inject_mode: option(rng, |rng| {
+                    [InjectMode::Type, InjectMode::Clipboard][rng.below(2)]
+                }),
+                char_delay_ms: option(rng, |rng| rng.next() % 100),
+                notify: option(rng, |rng| rng.below(2) == 0),
+                notify_message: option(rng, text),
+                enabled: option(rng, |rng| rng.below(2) == 0),
+                group: option(rng, text),
+                keep_boundary: option(rng, |rng| {
+                    [
+                        KeepBoundary::Keep,
+                        KeepBoundary::Swallow,
+                        KeepBoundary::Smart,
+                    ][rng.below(3)]
+                }),
+                boundary_position: option(rng, |rng| {
+                    [BoundaryPosition::Before, BoundaryPosition::After][rng.below(2)]
+                }),
+                strip_trailing_newline: option(rng, |rng| rng.below(2) == 0),
+                newline: [
+                    NewlineMode::Text,
+                    NewlineMode::Enter,
+                    NewlineMode::ShiftEnter,
+                    NewlineMode::Strip,
+                ][rng.below(4)],
+            })
+            .collect();
+        let snippets = (0..rng.below(3))
+            .map(|_| MenuSnippet {
+                title: text(rng),
+                content: text(rng),
+                group: option(rng, text),
+                separator_after: rng.below(2) == 0,
+                mode: [SnippetMode::Clipboard, SnippetMode::Type][rng.below(2)],
+                type_delay_ms: option(rng, |rng| rng.next() % 1000),
+            })
+            .collect();
+        let globals = (0..rng.below(3))
+            .map(|index| (format!("G{index}"), text(rng)))
+            .collect();
+        RuleSet {
+            expansions,
+            snippets,
+            globals,
+        }
+    }
+
+    #[test]
+    fn json_export_round_trips_losslessly() {
+        let mut rng = Lcg(0x5eed);
+        for _ in 0..500 {
+            let rules = random_rules(&mut rng);
+
+            let exported = rules.to_json().expect("export");
+            let imported = RuleSet::from_json(&exported).expect("import");
+            assert_eq!(imported, rules);
+            assert_eq!(imported.to_json().expect("re-export"), exported);
+
+            let yaml = rules.to_yaml().expect("yaml");
+            let from_yaml: RuleSet = serde_yaml::from_str(&yaml).expect("yaml parses");
+            assert_eq!(from_yaml, rules, "yaml output: {yaml}");
+        }
+    }
+
+    #[test]
+    fn json_export_is_stable_keyed_and_skips_defaults() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+expansions:
+  - trigger: ";b"
+    expansion: "two"
+    inject_mode: clipboard
+globals:
+  ZED: z
+  ALPHA: a
+"#,
+        )
+        .expect("config");
+
+        let json = RuleSet::from_config(&config).to_json().expect("export");
+
+        assert_eq!(
+            json,
+            r#"{
+  "expansions": [
+    {
+      "trigger": ";b",
+      "expansion": "two",
+      "inject_mode": "clipboard"
+    }
+  ],
+  "globals": {
+    "ALPHA": "a",
+    "ZED": "z"
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn csv_quotes_newlines_commas_and_quotes() {
+        let rules = RuleSet {
+            expansions: vec![ExpansionRule {
+                trigger: ";sig".to_string(),
+                expansion: "Thanks,\n\"Tyler\"".to_string(),
+                apps: vec!["firefox".to_string(), "code".to_string()],
+                char_delay_ms: Some(5),
+                enabled: Some(false),
+                group: Some("seasonal".to_string()),
+                keep_boundary: Some(KeepBoundary::Smart),
+                boundary_position: Some(BoundaryPosition::Before),
+                strip_trailing_newline: Some(false),
+                newline: NewlineMode::ShiftEnter,
+                label: Some("Sign-off".to_string()),
+                ..ExpansionRule::default()
+            }],
+            snippets: vec![MenuSnippet {
+                title: "Email".to_string(),
+                content: "me@example.com".to_string(),
+                group: Some("Work".to_string()),
+                separator_after: true,
+                mode: SnippetMode::Type,
+                type_delay_ms: Some(250),
+            }],
+            globals: [("NAME".to_string(), "Tyler".to_string())].into(),
+        };
+
+        assert_eq!(
+            rules.to_csv(),
+            "kind,name,text,apps,exclude_apps,inject_mode,char_delay_ms,notify,notify_message,enabled,keep_boundary,boundary_position,strip_trailing_newline,newline,group,separator_after,mode,type_delay_ms,label\r\n\
+             expansion,;sig,\"Thanks,\n\"\"Tyler\"\"\",firefox;code,,,5,,,false,smart,before,false,shift_enter,seasonal,,,,Sign-off\r\n\
+             snippet,Email,me@example.com,,,,,,,,,,,,Work,true,type,250,\r\n\
+             global,NAME,Tyler,,,,,,,,,,,,,,,,\r\n"
+        );
+    }
+
+    #[test]
+    fn remove_existing_drops_what_the_config_already_has() {
+        let config: AppConfig = serde_yaml::from_str(
+            r#"
+expansions:
+  - trigger: ";a"
+    expansion: "old"
+snippets:
+  - title: Email
+    content: me@example.com
+globals:
+  name: Tyler
+  TEAM: core
+"#,
+        )
+        .expect("config");
+        let mut rules = RuleSet::from_json(
+            r#"{
+  "expansions": [
+    {"trigger": ";a", "expansion": "new"},
+    {"trigger": ";b", "expansion": "kept"}
+  ],
+  "snippets": [
+    {"title": "Email", "content": "me@example.com"},
+    {"title": "Email", "content": "other@example.com"}
+  ],
+  "globals": {"NAME": "Tyler", "TEAM": "infra", "NEW": "x"}
+}"#,
+        )
+        .expect("json");
+
+        let dropped = rules.remove_existing(&config);
+
+        assert_eq!(
+            dropped,
+            vec![
+                ";a: trigger already exists in the config (skipped)",
+                "snippet 'Email': already exists in the config (skipped)",
+                "global 'TEAM': already defined in the config with another value (skipped)",
+            ]
+        );
+        assert_eq!(rules.expansions.len(), 1);
+        assert_eq!(rules.snippets[0].content, "other@example.com");
+        assert_eq!(rules.globals.keys().collect::<Vec<_>>(), vec!["NEW"]);
+    }
+}
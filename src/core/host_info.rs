@@ -0,0 +1,233 @@
+use std::net::Ipv4Addr;
+
+use anyhow::{bail, Context, Result};
+
+/// Files checked, in order, for `{{OS_RELEASE}}`, as os-release(5) describes.
+const OS_RELEASE_PATHS: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+/// An IPv4 address assigned to a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ipv4Interface {
+    pub name: String,
+    pub address: Ipv4Addr,
+    pub loopback: bool,
+}
+
+/// Lists interface addresses for `{{LOCAL_IP}}`; [`SystemInterfaces`] except
+/// in tests.
+pub trait InterfaceSource: Send + Sync {
+    fn ipv4_interfaces(&self) -> Result<Vec<Ipv4Interface>>;
+}
+
+/// Reads interfaces with `getifaddrs(3)`, so no process is started.
+pub struct SystemInterfaces;
+
+impl InterfaceSource for SystemInterfaces {
+    #[cfg(unix)]
+    fn ipv4_interfaces(&self) -> Result<Vec<Ipv4Interface>> {
+        let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+        // SAFETY: `addrs` is only read after getifaddrs succeeded, and freed once.
+        if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context("failed to list network interfaces");
+        }
+
+        let mut interfaces = Vec::new();
+        let mut current = addrs;
+        while !current.is_null() {
+            // SAFETY: getifaddrs returned a valid linked list, alive until freeifaddrs.
+            let entry = unsafe { &*current };
+            current = entry.ifa_next;
+            if entry.ifa_addr.is_null()
+                // SAFETY: checked for null above.
+                || i32::from(unsafe { (*entry.ifa_addr).sa_family }) != libc::AF_INET
+            {
+                continue;
+            }
+            // SAFETY: AF_INET entries carry a sockaddr_in.
+            let addr = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+            // SAFETY: ifa_name is a NUL-terminated string owned by the list.
+            let name = unsafe { std::ffi::CStr::from_ptr(entry.ifa_name) };
+            interfaces.push(Ipv4Interface {
+                name: name.to_string_lossy().into_owned(),
+                address: Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                loopback: entry.ifa_flags & libc::IFF_LOOPBACK as u32 != 0,
+            });
+        }
+        // SAFETY: `addrs` came from getifaddrs and is not used afterwards.
+        unsafe { libc::freeifaddrs(addrs) };
+        Ok(interfaces)
+    }
+
+    #[cfg(not(unix))]
+    fn ipv4_interfaces(&self) -> Result<Vec<Ipv4Interface>> {
+        bail!("LOCAL_IP macro is only supported on Unix")
+    }
+}
+
+/// The address `{{LOCAL_IP}}` types: the named interface's first IPv4
+/// address, or without a name the first one not on a loopback interface.
+pub fn select_ipv4(interfaces: &[Ipv4Interface], name: Option<&str>) -> Result<Ipv4Addr> {
+    match name {
+        Some(name) => {
+            if let Some(interface) = interfaces.iter().find(|interface| interface.name == name) {
+                return Ok(interface.address);
+            }
+            let mut names: Vec<&str> = interfaces
+                .iter()
+                .map(|interface| interface.name.as_str())
+                .collect();
+            names.dedup();
+            bail!(
+                "no IPv4 address on interface '{name}' (interfaces with one: {})",
+                names.join(", ")
+            )
+        }
+        None => interfaces
+            .iter()
+            .find(|interface| !interface.loopback && !interface.address.is_loopback())
+            .map(|interface| interface.address)
+            .context("no network interface with a non-loopback IPv4 address"),
+    }
+}
+
+pub fn hostname() -> Result<String> {
+    let mut buffer = [0u8; 256];
+    // SAFETY: the buffer outlives the call and its length is passed along.
+    if unsafe { libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("failed to read the hostname");
+    }
+    let end = buffer
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(buffer.len());
+    let name = String::from_utf8_lossy(&buffer[..end]).into_owned();
+    if name.is_empty() {
+        bail!("the hostname is not set");
+    }
+    Ok(name)
+}
+
+pub fn username() -> Result<String> {
+    ["USER", "LOGNAME"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok().filter(|name| !name.is_empty()))
+        .context("cannot tell the user name: neither $USER nor $LOGNAME is set")
+}
+
+/// `PRETTY_NAME` from the first os-release file that exists.
+pub fn os_release() -> Result<String> {
+    for path in OS_RELEASE_PATHS {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                return parse_os_release_pretty_name(&contents)
+                    .with_context(|| format!("no PRETTY_NAME in {path}"));
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to read {path}")),
+        }
+    }
+    bail!("no os-release file found ({})", OS_RELEASE_PATHS.join(", "))
+}
+
+/// The unquoted `PRETTY_NAME` of os-release(5) contents, if set and not empty.
+pub fn parse_os_release_pretty_name(contents: &str) -> Option<String> {
+    let value = contents.lines().rev().find_map(|line| {
+        line.trim()
+            .strip_prefix("PRETTY_NAME=")
+            .map(|value| value.trim())
+    })?;
+    let unquoted = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(quote) => {
+            let inner = &value[1..value.len() - 1];
+            if quote == '"' {
+                unescape_double_quoted(inner)
+            } else {
+                inner.to_string()
+            }
+        }
+        _ => value.to_string(),
+    };
+    Some(unquoted).filter(|name| !name.is_empty())
+}
+
+/// Shell-style escapes allowed inside double quotes: `\"`, `\\`, `\$`, `` \` ``.
+fn unescape_double_quoted(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' && matches!(chars.peek(), Some('"' | '\\' | '$' | '`')) {
+            unescaped.push(chars.next().expect("peeked char exists"));
+        } else {
+            unescaped.push(ch);
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{parse_os_release_pretty_name, select_ipv4, Ipv4Interface};
+
+    fn interface(name: &str, address: [u8; 4], loopback: bool) -> Ipv4Interface {
+        Ipv4Interface {
+            name: name.to_string(),
+            address: Ipv4Addr::from(address),
+            loopback,
+        }
+    }
+
+    #[test]
+    fn parses_pretty_name_from_os_release() {
+        let contents = "NAME=\"Fedora Linux\"\nVERSION_ID=40\nPRETTY_NAME=\"Fedora Linux 40 (Workstation Edition)\"\n";
+        assert_eq!(
+            parse_os_release_pretty_name(contents).as_deref(),
+            Some("Fedora Linux 40 (Workstation Edition)")
+        );
+        assert_eq!(
+            parse_os_release_pretty_name("PRETTY_NAME='Arch Linux'").as_deref(),
+            Some("Arch Linux")
+        );
+        assert_eq!(
+            parse_os_release_pretty_name("PRETTY_NAME=NixOS").as_deref(),
+            Some("NixOS")
+        );
+        assert_eq!(
+            parse_os_release_pretty_name(r#"PRETTY_NAME="Say \"hi\" \$HOME \\o/""#).as_deref(),
+            Some(r#"Say "hi" $HOME \o/"#)
+        );
+        assert_eq!(parse_os_release_pretty_name("NAME=Debian\n"), None);
+        assert_eq!(parse_os_release_pretty_name("PRETTY_NAME=\"\""), None);
+    }
+
+    #[test]
+    fn selects_interfaces() {
+        let interfaces = [
+            interface("lo", [127, 0, 0, 1], true),
+            interface("docker0", [172, 17, 0, 1], false),
+            interface("wlan0", [192, 168, 1, 20], false),
+        ];
+
+        assert_eq!(
+            select_ipv4(&interfaces, None).expect("first non-loopback"),
+            Ipv4Addr::new(172, 17, 0, 1)
+        );
+        assert_eq!(
+            select_ipv4(&interfaces, Some("wlan0")).expect("named interface"),
+            Ipv4Addr::new(192, 168, 1, 20)
+        );
+        let err = select_ipv4(&interfaces, Some("eth0")).expect_err("unknown interface");
+        assert_eq!(
+            err.to_string(),
+            "no IPv4 address on interface 'eth0' (interfaces with one: lo, docker0, wlan0)"
+        );
+
+        let err = select_ipv4(&interfaces[..1], None).expect_err("only loopback");
+        assert_eq!(
+            err.to_string(),
+            "no network interface with a non-loopback IPv4 address"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{bail, Result};
+
+use crate::io::events::{KeyEvent, SpecialInputKey};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HotkeyModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyKey {
+    Char(char),
+    Special(SpecialInputKey),
+}
+
+/// A global shortcut such as `ctrl+alt+p`: a set of required modifiers plus one key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: HotkeyModifiers,
+    pub key: HotkeyKey,
+}
+
+impl Hotkey {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut modifiers = HotkeyModifiers::default();
+        let mut key = None;
+
+        let tokens: Vec<&str> = input.split('+').map(str::trim).collect();
+        for (position, token) in tokens.iter().enumerate() {
+            if token.is_empty() {
+                bail!("invalid hotkey '{input}': empty key name");
+            }
+
+            let is_last = position + 1 == tokens.len();
+            match token.to_ascii_uppercase().as_str() {
+                "CTRL" | "CONTROL" if !is_last => modifiers.ctrl = true,
+                "SHIFT" if !is_last => modifiers.shift = true,
+                "ALT" if !is_last => modifiers.alt = true,
+                "META" | "SUPER" | "WIN" if !is_last => modifiers.meta = true,
+                _ if is_last => key = Some(parse_hotkey_key(token, input)?),
+                other => bail!("invalid hotkey '{input}': unknown modifier '{other}'"),
+            }
+        }
+
+        let Some(key) = key else {
+            bail!("invalid hotkey '{input}': missing key");
+        };
+        if matches!(key, HotkeyKey::Char(_)) && modifiers == HotkeyModifiers::default() {
+            bail!("invalid hotkey '{input}': printable keys need at least one modifier");
+        }
+
+        Ok(Self { modifiers, key })
+    }
+
+    /// Returns true when `event` presses this hotkey's key while exactly its modifiers are held.
+    pub fn matches(&self, held: HotkeyModifiers, event: &KeyEvent) -> bool {
+        if held != self.modifiers {
+            return false;
+        }
+
+        match self.key {
+            HotkeyKey::Char(expected) => {
+                let mut chars = event.text.as_deref().unwrap_or_default().chars();
+                matches!(
+                    (chars.next(), chars.next()),
+                    (Some(c), None) if normalize_hotkey_char(c) == expected
+                )
+            }
+            HotkeyKey::Special(expected) => event.special == Some(expected),
+        }
+    }
+}
+
+fn parse_hotkey_key(token: &str, input: &str) -> Result<HotkeyKey> {
+    let mut chars = token.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(HotkeyKey::Char(normalize_hotkey_char(c)));
+    }
+
+    let key = match token.to_ascii_uppercase().as_str() {
+        "SPACE" => return Ok(HotkeyKey::Char(' ')),
+        "ENTER" | "RETURN" => SpecialInputKey::Enter,
+        "TAB" => SpecialInputKey::Tab,
+        "ESC" | "ESCAPE" => SpecialInputKey::Escape,
+        "BACKSPACE" => SpecialInputKey::Backspace,
+        "LEFT" => SpecialInputKey::Left,
+        "RIGHT" => SpecialInputKey::Right,
+        "UP" => SpecialInputKey::Up,
+        "DOWN" => SpecialInputKey::Down,
+        "HOME" => SpecialInputKey::Home,
+        "END" => SpecialInputKey::End,
+        "DELETE" => SpecialInputKey::Delete,
+        "PAGEUP" => SpecialInputKey::PageUp,
+        "PAGEDOWN" => SpecialInputKey::PageDown,
+        "F1" => SpecialInputKey::F1,
+        "F2" => SpecialInputKey::F2,
+        "F3" => SpecialInputKey::F3,
+        "F4" => SpecialInputKey::F4,
+        "F5" => SpecialInputKey::F5,
+        "F6" => SpecialInputKey::F6,
+        "F7" => SpecialInputKey::F7,
+        "F8" => SpecialInputKey::F8,
+        "F9" => SpecialInputKey::F9,
+        "F10" => SpecialInputKey::F10,
+        "F11" => SpecialInputKey::F11,
+        "F12" => SpecialInputKey::F12,
+        other => bail!("invalid hotkey '{input}': unknown key '{other}'"),
+    };
+    Ok(HotkeyKey::Special(key))
+}
+
+/// X11 reports Ctrl+letter as an ASCII control character and Shift+letter in
+/// uppercase, so fold both back to the lowercase letter.
+fn normalize_hotkey_char(c: char) -> char {
+    match c {
+        '\u{1}'..='\u{1a}' => char::from(b'a' + (c as u8) - 1),
+        _ => c.to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Hotkey, HotkeyKey, HotkeyModifiers};
+    use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
+
+    fn press(printable: Option<char>, special: Option<SpecialInputKey>) -> KeyEvent {
+        KeyEvent {
+            kind: KeyEventKind::Press,
+            text: printable.map(String::from),
+            special,
+            is_injected: false,
+        }
+    }
+
+    #[test]
+    fn parses_modifiers_and_key() {
+        let hotkey = Hotkey::parse("Ctrl + Alt + P").expect("hotkey should parse");
+
+        assert_eq!(
+            hotkey.modifiers,
+            HotkeyModifiers {
+                ctrl: true,
+                alt: true,
+                ..HotkeyModifiers::default()
+            }
+        );
+        assert_eq!(hotkey.key, HotkeyKey::Char('p'));
+    }
+
+    #[test]
+    fn parses_special_key_without_modifiers() {
+        let hotkey = Hotkey::parse("F9").expect("hotkey should parse");
+        assert_eq!(hotkey.key, HotkeyKey::Special(SpecialInputKey::F9));
+    }
+
+    #[test]
+    fn rejects_unknown_modifier_and_bare_printable_key() {
+        let err = Hotkey::parse("hyper+p").expect_err("unknown modifier should fail");
+        assert!(err.to_string().contains("unknown modifier"));
+
+        let err = Hotkey::parse("p").expect_err("bare printable key should fail");
+        assert!(err.to_string().contains("at least one modifier"));
+    }
+
+    #[test]
+    fn matches_control_character_reported_for_ctrl_letter() {
+        let hotkey = Hotkey::parse("ctrl+alt+p").expect("hotkey should parse");
+        let held = HotkeyModifiers {
+            ctrl: true,
+            alt: true,
+            ..HotkeyModifiers::default()
+        };
+
+        assert!(hotkey.matches(held, &press(Some('\u{10}'), None)));
+        assert!(hotkey.matches(held, &press(Some('P'), None)));
+        assert!(!hotkey.matches(HotkeyModifiers::default(), &press(Some('p'), None)));
+    }
+}
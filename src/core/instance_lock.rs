@@ -1,18 +1,123 @@
 use std::fs;
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::core::control::{self, ControlCommand};
+
+/// How long `--replace` waits for the running instance to answer `stop` and
+/// release the lock.
+pub const REPLACE_TIMEOUT: Duration = Duration::from_secs(3);
+const RELEASE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long a conflicting acquire waits for the holder to answer `whoami`.
+const WHOAMI_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// What [`InstanceLock::acquire_with`] does when another instance holds the lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Takeover {
+    /// Fail with "another slykey instance is already running".
+    #[default]
+    Refuse,
+    /// Send the running instance `stop` and wait for it to release the lock;
+    /// with `force`, take the lock even if it does not.
+    Replace { force: bool },
+}
 
 pub struct InstanceLock {
     path: PathBuf,
-    _listener: UnixListener,
+    listener: UnixListener,
+    info: InstanceInfo,
+}
+
+/// What the lock holder answers to `whoami`, so a second instance can say
+/// which process is in the way.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceInfo {
+    pub pid: u32,
+    pub version: String,
+    pub config_path: Option<PathBuf>,
+    /// Unix time in seconds.
+    pub started_at: u64,
+}
+
+impl InstanceInfo {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config_path: None,
+            started_at: unix_now(),
+        }
+    }
+
+    /// E.g. `PID 12345 (v0.2.5) using ~/.config/slykey/config.yaml, started 2h ago`.
+    pub fn describe(&self, now: u64) -> String {
+        let mut description = format!("PID {} (v{})", self.pid, self.version);
+        if let Some(path) = &self.config_path {
+            description.push_str(&format!(" using {}", display_with_home(path)));
+        }
+        description.push_str(&format!(
+            ", started {} ago",
+            format_age(now.saturating_sub(self.started_at))
+        ));
+        description
+    }
+}
+
+/// The lock socket, serving control commands; see [`InstanceLock::control_server`].
+pub struct ControlServer {
+    listener: UnixListener,
+    info: InstanceInfo,
+}
+
+impl ControlServer {
+    /// Serves until `stop` is acknowledged: `whoami` is answered with the
+    /// holder's [`InstanceInfo`], every other command goes to `handler`.
+    pub fn serve<H>(self, handler: H)
+    where
+        H: Fn(ControlCommand) -> Result<String> + Sync,
+    {
+        let info = self.info;
+        control::serve(self.listener, |command| match command {
+            ControlCommand::Whoami => Ok(serde_json::to_string(&info)?),
+            command => handler(command),
+        });
+    }
 }
 
 impl InstanceLock {
     pub fn acquire() -> Result<Self> {
+        Self::acquire_with(Takeover::Refuse)
+    }
+
+    pub fn acquire_with(takeover: Takeover) -> Result<Self> {
         let lock_path = default_lock_path();
-        acquire_from_path(lock_path)
+        acquire_from_path(lock_path, takeover, REPLACE_TIMEOUT)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Records the config file the instance runs with, for `whoami`.
+    pub fn set_config_path(&mut self, path: PathBuf) {
+        self.info.config_path = Some(path);
+    }
+
+    /// Returns a handle to the lock socket for serving control commands.
+    pub fn control_server(&self) -> Result<ControlServer> {
+        let listener = self
+            .listener
+            .try_clone()
+            .context("failed to clone slykey instance lock socket")?;
+        Ok(ControlServer {
+            listener,
+            info: self.info.clone(),
+        })
     }
 }
 
@@ -22,15 +127,24 @@ impl Drop for InstanceLock {
     }
 }
 
-fn acquire_from_path(path: PathBuf) -> Result<InstanceLock> {
-    if path.exists() {
-        if UnixStream::connect(&path).is_ok() {
-            bail!(
-                "another slykey instance is already running (lock: {})",
-                path.display()
-            );
+fn acquire_from_path(path: PathBuf, takeover: Takeover, timeout: Duration) -> Result<InstanceLock> {
+    if path.exists() && UnixStream::connect(&path).is_ok() {
+        match takeover {
+            Takeover::Refuse => {
+                let holder = query_holder(&path)
+                    .map(|info| format!(" as {}", info.describe(unix_now())))
+                    .unwrap_or_default();
+                bail!(
+                    "another slykey instance is already running{holder} (lock: {}); use `slykey run --replace` to take over",
+                    path.display()
+                )
+            }
+            Takeover::Replace { force } => replace_running_instance(&path, force, timeout)?,
         }
+    }
 
+    // Stale after a crash, or still there after a forced takeover.
+    if path.exists() {
         fs::remove_file(&path).with_context(|| {
             format!(
                 "failed to remove stale slykey instance lock file: {}",
@@ -48,11 +162,87 @@ fn acquire_from_path(path: PathBuf) -> Result<InstanceLock> {
 
     Ok(InstanceLock {
         path,
-        _listener: listener,
+        listener,
+        info: InstanceInfo::current(),
     })
 }
 
-fn default_lock_path() -> PathBuf {
+/// Asks the instance holding `path` who it is; `None` if it does not answer
+/// in time or predates `whoami`.
+pub fn query_holder(path: &Path) -> Option<InstanceInfo> {
+    let reply = control::send_command_with_timeout(path, ControlCommand::Whoami, WHOAMI_TIMEOUT)
+        .ok()
+        .filter(|reply| reply.ok)?;
+    serde_json::from_str(&reply.message).ok()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// `~/...` for paths under the home directory.
+fn display_with_home(path: &Path) -> String {
+    match dirs::home_dir().and_then(|home| path.strip_prefix(home).ok().map(Path::to_path_buf)) {
+        Some(relative) => format!("~/{}", relative.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// The largest whole unit of `seconds`: `45s`, `12m`, `2h`, `3d`.
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+/// Asks the instance holding `path` to stop and waits for it to let go of the
+/// lock; fails if it does not, unless `force` is set.
+fn replace_running_instance(path: &Path, force: bool, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let problem = match control::send_command_with_timeout(path, ControlCommand::Stop, timeout) {
+        Ok(reply) if reply.ok => {
+            if wait_for_release(path, deadline) {
+                return Ok(());
+            }
+            format!(
+                "it acknowledged stop but still holds the lock after {timeout:?}, so it looks hung"
+            )
+        }
+        Ok(reply) => format!("it refused to stop: {}", reply.message),
+        Err(err) => format!("it did not answer stop ({err:#}), so it looks hung"),
+    };
+
+    if !force {
+        bail!(
+            "cannot replace the running slykey instance (lock: {}): {problem}; add --force to take over the lock anyway",
+            path.display()
+        );
+    }
+    warn!("taking over the instance lock: {problem}; the old process may still be running");
+    Ok(())
+}
+
+/// Whether the lock at `path` is gone, or no longer accepts connections,
+/// before `deadline`.
+fn wait_for_release(path: &Path, deadline: Instant) -> bool {
+    loop {
+        if !path.exists() || UnixStream::connect(path).is_err() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(RELEASE_POLL_INTERVAL);
+    }
+}
+
+pub fn default_lock_path() -> PathBuf {
     let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
         .map(PathBuf::from)
         .unwrap_or_else(std::env::temp_dir);
@@ -68,8 +258,12 @@ fn user_hint() -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::acquire_from_path;
-    use std::path::PathBuf;
+    use super::{acquire_from_path, unix_now, InstanceInfo, Takeover, REPLACE_TIMEOUT};
+    use anyhow::bail;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     fn test_lock_path(name: &str) -> PathBuf {
         std::env::temp_dir().join(format!("slykey-test-lock-{}-{}.sock", std::process::id(), name))
@@ -78,8 +272,9 @@ mod tests {
     #[test]
     fn rejects_second_lock_holder() {
         let path = test_lock_path("second-holder");
-        let first = acquire_from_path(path.clone()).expect("first lock should succeed");
-        let second = acquire_from_path(path.clone());
+        let first = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT)
+            .expect("first lock should succeed");
+        let second = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT);
 
         assert!(second.is_err(), "second lock should fail");
 
@@ -87,15 +282,155 @@ mod tests {
         let _ = std::fs::remove_file(path);
     }
 
+    #[test]
+    fn conflict_error_names_the_running_instance() {
+        let path = test_lock_path("whoami");
+        let mut holder = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT)
+            .expect("first lock should succeed");
+        holder.set_config_path(PathBuf::from("/etc/slykey/config.yaml"));
+        // Two hours back, so the age reads the same however long the test takes.
+        holder.info.started_at = unix_now() - 2 * 3600;
+        let server = holder.control_server().expect("control server");
+        std::thread::spawn(move || server.serve(|command| bail!("unexpected {command:?}")));
+
+        let err = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT)
+            .err()
+            .expect("second lock should fail");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another slykey instance is already running as PID {} (v{}) using /etc/slykey/config.yaml, started 2h ago (lock: {}); use `slykey run --replace` to take over",
+                std::process::id(),
+                env!("CARGO_PKG_VERSION"),
+                path.display()
+            )
+        );
+
+        drop(holder);
+    }
+
+    #[test]
+    fn conflict_error_falls_back_when_holder_does_not_answer() {
+        let path = test_lock_path("whoami-silent");
+        // Never accepted, so `whoami` times out.
+        let silent = UnixListener::bind(&path).expect("bind silent holder");
+
+        let err = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT)
+            .err()
+            .expect("second lock should fail");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another slykey instance is already running (lock: {}); use `slykey run --replace` to take over",
+                path.display()
+            )
+        );
+
+        drop(silent);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn describes_instance_age_in_the_largest_unit() {
+        let info = InstanceInfo {
+            pid: 12345,
+            version: "0.2.5".to_string(),
+            config_path: None,
+            started_at: 1_000,
+        };
+        assert_eq!(info.describe(1_045), "PID 12345 (v0.2.5), started 45s ago");
+        assert_eq!(
+            info.describe(1_000 + 7_500),
+            "PID 12345 (v0.2.5), started 2h ago"
+        );
+        assert_eq!(
+            info.describe(1_000 + 3 * 86_400 + 5),
+            "PID 12345 (v0.2.5), started 3d ago"
+        );
+        assert_eq!(info.describe(10), "PID 12345 (v0.2.5), started 0s ago");
+    }
+
     #[test]
     fn recovers_from_stale_socket_file() {
         let path = test_lock_path("stale-socket");
         let stale = std::os::unix::net::UnixListener::bind(&path).expect("create stale listener");
         drop(stale);
 
-        let lock = acquire_from_path(path.clone()).expect("lock should recover from stale path");
+        let lock = acquire_from_path(path.clone(), Takeover::Refuse, REPLACE_TIMEOUT)
+            .expect("lock should recover from stale path");
+        drop(lock);
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    /// Stands in for a running instance: answers one `stop`, then releases
+    /// the lock like a clean shutdown does.
+    fn spawn_instance_honoring_stop(path: &Path) -> std::thread::JoinHandle<String> {
+        let listener = UnixListener::bind(path).expect("bind old instance socket");
+        let path = path.to_path_buf();
+        std::thread::spawn(move || {
+            // The liveness probe connects without sending anything.
+            for stream in listener.incoming() {
+                let stream = stream.expect("accept connection");
+                let mut line = String::new();
+                BufReader::new(stream.try_clone().expect("clone stream"))
+                    .read_line(&mut line)
+                    .expect("read command");
+                if line.is_empty() {
+                    continue;
+                }
+                (&stream)
+                    .write_all(b"{\"ok\":true,\"message\":\"Stopping slykey\"}\n")
+                    .expect("write reply");
+                std::thread::sleep(Duration::from_millis(100));
+                std::fs::remove_file(&path).expect("remove lock");
+                return line;
+            }
+            unreachable!("listener.incoming() never ends")
+        })
+    }
+
+    #[test]
+    fn replace_stops_the_running_instance() {
+        let path = test_lock_path("replace-honored");
+        let old = spawn_instance_honoring_stop(&path);
+
+        let lock = acquire_from_path(
+            path.clone(),
+            Takeover::Replace { force: false },
+            REPLACE_TIMEOUT,
+        )
+        .expect("replace should take over after stop");
+        assert_eq!(old.join().expect("old instance thread"), "stop\n");
+        assert!(path.exists());
+
         drop(lock);
+        assert!(!path.exists());
+    }
 
+    #[test]
+    fn replace_refuses_a_hung_instance_unless_forced() {
+        let path = test_lock_path("replace-hung");
+        // Never accepted, so `stop` goes unanswered.
+        let hung = UnixListener::bind(&path).expect("bind hung instance socket");
+        let timeout = Duration::from_millis(200);
+
+        let err = acquire_from_path(path.clone(), Takeover::Replace { force: false }, timeout)
+            .err()
+            .expect("hung instance should not be replaced without --force");
+        let message = format!("{err:#}");
+        assert!(message.contains("looks hung"), "{message}");
+        assert!(message.contains("--force"), "{message}");
+
+        let err = acquire_from_path(path.clone(), Takeover::Refuse, timeout)
+            .err()
+            .expect("refuse should not touch a live lock");
+        assert!(format!("{err:#}").contains("--replace"), "{err:#}");
+
+        let lock = acquire_from_path(path.clone(), Takeover::Replace { force: true }, timeout)
+            .expect("--force should take over the lock");
+        drop(lock);
+        drop(hung);
         let _ = std::fs::remove_file(path);
     }
 }
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+
+use crate::config::AppConfig;
+use crate::core::picker::preview;
+
+/// Each trigger of `config` once, in config order.
+fn unique_triggers(config: &AppConfig) -> Vec<&str> {
+    let mut seen = HashSet::new();
+    config
+        .expansions
+        .iter()
+        .map(|rule| rule.trigger.as_str())
+        .filter(|trigger| seen.insert(*trigger))
+        .collect()
+}
+
+/// `slykey list --format plain-triggers`: one trigger per line and nothing
+/// else, for shell completion scripts.
+pub fn format_plain_triggers(config: &AppConfig) -> String {
+    unique_triggers(config)
+        .into_iter()
+        .map(|trigger| format!("{trigger}\n"))
+        .collect()
+}
+
+/// `slykey list`: every rule's trigger next to a one-line expansion preview,
+/// with a label column once any rule has a label. Rules that cannot fire are
+/// marked `(off)`.
+pub fn format_rule_table(config: &AppConfig) -> String {
+    let disabled_groups: HashSet<String> = config.disabled_groups.iter().cloned().collect();
+    let width = column_width(
+        "TRIGGER",
+        config.expansions.iter().map(|rule| rule.trigger.as_str()),
+    );
+    let labels = config.expansions.iter().any(|rule| rule.label.is_some());
+    let label_width = column_width(
+        "LABEL",
+        config
+            .expansions
+            .iter()
+            .filter_map(|rule| rule.label.as_deref()),
+    );
+
+    let mut table = format!("{:<width$}  ", "TRIGGER");
+    if labels {
+        table.push_str(&format!("{:<label_width$}  ", "LABEL"));
+    }
+    table.push_str("EXPANSION\n");
+    for rule in &config.expansions {
+        let state = if rule.is_active(&disabled_groups) {
+            ""
+        } else {
+            " (off)"
+        };
+        table.push_str(&format!("{:<width$}  ", rule.trigger));
+        if labels {
+            let label = rule.label.as_deref().unwrap_or_default();
+            table.push_str(&format!("{label:<label_width$}  "));
+        }
+        table.push_str(&format!("{}{state}\n", preview(&rule.expansion)));
+    }
+    table
+}
+
+/// `slykey list --autocorrect --format plain-triggers`: one typo per line.
+pub fn format_plain_typos(config: &AppConfig) -> String {
+    config
+        .autocorrect
+        .iter()
+        .map(|correction| format!("{}\n", correction.typo))
+        .collect()
+}
+
+/// `slykey list --autocorrect`: every `autocorrect_file` typo next to a
+/// one-line preview of its correction, in file order.
+pub fn format_autocorrect_table(config: &AppConfig) -> String {
+    let width = column_width(
+        "TYPO",
+        config
+            .autocorrect
+            .iter()
+            .map(|correction| correction.typo.as_str()),
+    );
+    let mut table = format!("{:<width$}  CORRECTION\n", "TYPO");
+    for correction in config.autocorrect.iter() {
+        table.push_str(&format!(
+            "{:<width$}  {}\n",
+            correction.typo,
+            preview(&correction.replacement)
+        ));
+    }
+    table
+}
+
+/// Characters needed for a column headed `header` holding `values`.
+fn column_width<'a>(header: &str, values: impl Iterator<Item = &'a str>) -> usize {
+    values
+        .map(|value| value.chars().count())
+        .chain([header.len()])
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{
+        format_autocorrect_table, format_plain_triggers, format_plain_typos, format_rule_table,
+    };
+    use crate::config::AppConfig;
+    use crate::core::autocorrect::parse_dictionary;
+
+    fn config() -> AppConfig {
+        serde_yaml::from_str(
+            r#"
+disabled_groups: [xmas]
+expansions:
+  - trigger: ";sig"
+    expansion: "Thanks,\nTyler"
+  - trigger: ";ho"
+    expansion: "Ho ho ho"
+    group: xmas
+  - trigger: ";sig"
+    expansion: "Cheers"
+    apps: ["Slack"]
+"#,
+        )
+        .expect("config")
+    }
+
+    #[test]
+    fn plain_triggers_are_one_per_line_without_decoration() {
+        assert_eq!(format_plain_triggers(&config()), ";sig\n;ho\n");
+        let empty: AppConfig = serde_yaml::from_str("expansions: []").expect("config");
+        assert_eq!(format_plain_triggers(&empty), "");
+    }
+
+    #[test]
+    fn rule_table_previews_each_rule() {
+        assert_eq!(
+            format_rule_table(&config()),
+            "TRIGGER  EXPANSION\n\
+             ;sig     Thanks,…\n\
+             ;ho      Ho ho ho (off)\n\
+             ;sig     Cheers\n"
+        );
+    }
+
+    #[test]
+    fn rule_table_adds_a_label_column_when_any_rule_has_one() {
+        let mut config = config();
+        config.expansions[0].label = Some("Signature — formal".to_string());
+
+        assert_eq!(
+            format_rule_table(&config),
+            "TRIGGER  LABEL               EXPANSION\n\
+             ;sig     Signature — formal  Thanks,…\n\
+             ;ho                          Ho ho ho (off)\n\
+             ;sig                         Cheers\n"
+        );
+    }
+
+    #[test]
+    fn autocorrect_listing_shows_typos_in_file_order() {
+        let mut config = config();
+        assert_eq!(format_autocorrect_table(&config), "TYPO  CORRECTION\n");
+
+        let dictionary =
+            parse_dictionary("recieve\treceive\nteh\tthe\tend\n".as_bytes(), "typos.tsv")
+                .expect("dictionary");
+        config.autocorrect = Arc::new(dictionary.corrections);
+
+        assert_eq!(
+            format_autocorrect_table(&config),
+            "TYPO     CORRECTION\n\
+             recieve  receive\n\
+             teh      the\tend\n"
+        );
+        assert_eq!(format_plain_typos(&config), "recieve\nteh\n");
+    }
+}
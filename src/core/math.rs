@@ -0,0 +1,272 @@
+use anyhow::{bail, Result};
+
+/// Most decimals `{{MATH:<precision>:...}}` accepts.
+const MAX_PRECISION: usize = 15;
+/// Decimals shown without an explicit precision, to hide float noise such as
+/// `0.1+0.2 = 0.30000000000000004`.
+const DEFAULT_DECIMALS: usize = 10;
+
+/// Renders `{{MATH:expression}}` or `{{MATH:precision:expression}}`, where
+/// `value` has already been template-rendered.
+pub fn render_math_macro(value: &str) -> Result<String> {
+    let (precision, expression) = match value.split_once(':') {
+        Some((precision, expression)) => {
+            let precision = precision.trim();
+            let Ok(precision) = precision.parse::<usize>() else {
+                bail!("invalid MATH precision '{precision}' (expected {{{{MATH:2:1/3}}}})");
+            };
+            if precision > MAX_PRECISION {
+                bail!("invalid MATH precision {precision} (at most {MAX_PRECISION})");
+            }
+            (Some(precision), expression)
+        }
+        None => (None, value),
+    };
+
+    let result = evaluate(expression)?;
+    Ok(format_number(result, precision))
+}
+
+/// Evaluates `+ - * / %`, parentheses and unary minus over integer and float
+/// literals, with the usual precedence.
+pub fn evaluate(expression: &str) -> Result<f64> {
+    let trimmed = expression.trim();
+    let mut parser = Parser {
+        expression: trimmed,
+        bytes: trimmed.as_bytes(),
+        pos: 0,
+    };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.bytes.len() {
+        return Err(parser.unexpected());
+    }
+    if !value.is_finite() {
+        bail!("cannot evaluate '{trimmed}': result is too large");
+    }
+    Ok(value)
+}
+
+fn format_number(value: f64, precision: Option<usize>) -> String {
+    let formatted = match precision {
+        Some(precision) => format!("{value:.precision$}"),
+        None => {
+            let formatted = format!("{value:.DEFAULT_DECIMALS$}");
+            formatted
+                .trim_end_matches('0')
+                .trim_end_matches('.')
+                .to_string()
+        }
+    };
+    // Rounding can leave `-0` or `-0.00` behind.
+    if formatted.starts_with('-') && formatted[1..].chars().all(|c| c == '0' || c == '.') {
+        return formatted[1..].to_string();
+    }
+    formatted
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// `term (('+' | '-') term)*`
+    fn expression(&mut self) -> Result<f64> {
+        let mut value = self.term()?;
+        loop {
+            match self.peek() {
+                Some(b'+') => {
+                    self.pos += 1;
+                    value += self.term()?;
+                }
+                Some(b'-') => {
+                    self.pos += 1;
+                    value -= self.term()?;
+                }
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    /// `factor (('*' | '/' | '%') factor)*`
+    fn term(&mut self) -> Result<f64> {
+        let mut value = self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some(op @ (b'*' | b'/' | b'%')) => op,
+                _ => return Ok(value),
+            };
+            self.pos += 1;
+            let rhs = self.factor()?;
+            if rhs == 0.0 && op != b'*' {
+                bail!("cannot evaluate '{}': division by zero", self.expression);
+            }
+            match op {
+                b'*' => value *= rhs,
+                b'/' => value /= rhs,
+                _ => value %= rhs,
+            }
+        }
+    }
+
+    /// `'-' factor | '+' factor | '(' expression ')' | number`
+    fn factor(&mut self) -> Result<f64> {
+        match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                Ok(-self.factor()?)
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                self.factor()
+            }
+            Some(b'(') => {
+                self.pos += 1;
+                let value = self.expression()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.unexpected());
+                }
+                self.pos += 1;
+                Ok(value)
+            }
+            Some(b'0'..=b'9' | b'.') => self.number(),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_digit() || *byte == b'.')
+        {
+            self.pos += 1;
+        }
+        let literal = &self.expression[start..self.pos];
+        match literal.parse() {
+            Ok(value) => Ok(value),
+            Err(_) => bail!(
+                "cannot evaluate '{}': invalid number '{literal}'",
+                self.expression
+            ),
+        }
+    }
+
+    /// The next non-whitespace byte, without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn unexpected(&self) -> anyhow::Error {
+        match self.expression[self.pos..].chars().next() {
+            Some(ch) => anyhow::anyhow!(
+                "cannot evaluate '{}': unexpected '{ch}' at position {}",
+                self.expression,
+                self.pos + 1
+            ),
+            None => anyhow::anyhow!(
+                "cannot evaluate '{}': unexpected end of expression",
+                self.expression
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, render_math_macro};
+
+    #[test]
+    fn follows_operator_precedence() {
+        for (expression, expected) in [
+            ("2*(3+4)", 14.0),
+            ("2*3+4", 10.0),
+            ("2+3*4", 14.0),
+            ("10-4-3", 3.0),
+            ("100/10/5", 2.0),
+            ("7%4*2", 6.0),
+            ("((1+2))*(3)", 9.0),
+            (" 1.5 * 4 ", 6.0),
+            (".5+.25", 0.75),
+        ] {
+            assert_eq!(
+                evaluate(expression).expect(expression),
+                expected,
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn handles_unary_minus() {
+        for (expression, expected) in [
+            ("-3", -3.0),
+            ("--3", 3.0),
+            ("2*-3", -6.0),
+            ("-(2+3)*2", -10.0),
+            ("4 - -1", 5.0),
+            ("+2", 2.0),
+        ] {
+            assert_eq!(
+                evaluate(expression).expect(expression),
+                expected,
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_results() {
+        for (value, expected) in [
+            ("2*(3+4)", "14"),
+            ("10/4", "2.5"),
+            ("0.1+0.2", "0.3"),
+            ("19.99*1.19", "23.7881"),
+            ("2:1/3", "0.33"),
+            ("2:5/2", "2.50"),
+            ("0:2/3", "1"),
+            ("2:-0.001", "0.00"),
+            ("-0", "0"),
+        ] {
+            assert_eq!(render_math_macro(value).expect(value), expected, "{value}");
+        }
+    }
+
+    #[test]
+    fn reports_readable_errors() {
+        for (value, expected) in [
+            ("1/0", "cannot evaluate '1/0': division by zero"),
+            ("5 % (2-2)", "cannot evaluate '5 % (2-2)': division by zero"),
+            ("2*x", "cannot evaluate '2*x': unexpected 'x' at position 3"),
+            (
+                "(1+2",
+                "cannot evaluate '(1+2': unexpected end of expression",
+            ),
+            (
+                "1+2)",
+                "cannot evaluate '1+2)': unexpected ')' at position 4",
+            ),
+            ("1..2", "cannot evaluate '1..2': invalid number '1..2'"),
+            ("", "cannot evaluate '': unexpected end of expression"),
+            ("x:1/3", "invalid MATH precision 'x'"),
+            ("99:1/3", "invalid MATH precision 99"),
+        ] {
+            let err = render_math_macro(value).expect_err(value);
+            assert!(err.to_string().starts_with(expected), "{value}: {err}");
+        }
+    }
+}
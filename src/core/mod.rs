@@ -1,3 +1,26 @@
+pub mod autocorrect;
+pub mod bench;
+pub mod config_watch;
+pub mod control;
+pub mod date_locale;
+pub mod doctor;
 pub mod engine;
+pub mod env_expand;
+pub mod espanso_import;
 pub mod expansion;
+pub mod export;
+pub mod host_info;
+pub mod hotkey;
 pub mod instance_lock;
+pub mod list;
+pub mod math;
+pub mod notification_throttle;
+pub mod output_worker;
+pub mod picker;
+pub mod recording;
+pub mod reload;
+pub mod shell_command;
+pub mod shutdown;
+pub mod stats;
+pub mod totp;
+pub mod trigger_index;
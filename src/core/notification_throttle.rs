@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+
+use crate::config::NotificationConfig;
+
+/// Limits expansion notifications to one per `min_interval`. Expansions that
+/// fire in between are either dropped or, with `coalesce`, held back and
+/// summarized in the next notification.
+#[derive(Debug)]
+pub struct NotificationThrottle {
+    min_interval: Duration,
+    coalesce: bool,
+    last_sent: Option<Instant>,
+    pending: Vec<String>,
+}
+
+/// Expansions to report in one notification, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpansionSummary {
+    pub triggers: Vec<String>,
+}
+
+impl ExpansionSummary {
+    /// The trigger for a single expansion, otherwise e.g.
+    /// `3 expansions (;sig, ;addr, ;brb)`.
+    pub fn body(&self) -> String {
+        match self.triggers.as_slice() {
+            [trigger] => trigger.clone(),
+            triggers => format!("{} expansions ({})", triggers.len(), triggers.join(", ")),
+        }
+    }
+}
+
+impl NotificationThrottle {
+    pub fn new(config: &NotificationConfig) -> Self {
+        Self {
+            min_interval: Duration::from_millis(config.min_interval_ms),
+            coalesce: config.coalesce,
+            last_sent: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Applies reloaded settings while keeping the send history.
+    pub fn configure(&mut self, config: &NotificationConfig) {
+        self.min_interval = Duration::from_millis(config.min_interval_ms);
+        self.coalesce = config.coalesce;
+        if !self.coalesce {
+            self.pending.clear();
+        }
+    }
+
+    /// Records a fired expansion and returns what to show now, if anything.
+    pub fn record(&mut self, trigger: &str, now: Instant) -> Option<ExpansionSummary> {
+        if self.is_open(now) {
+            self.pending.push(trigger.to_string());
+            return Some(self.take_pending(now));
+        }
+        if self.coalesce {
+            self.pending.push(trigger.to_string());
+        }
+        None
+    }
+
+    /// Returns the held-back summary once the interval has passed.
+    pub fn flush(&mut self, now: Instant) -> Option<ExpansionSummary> {
+        if self.pending.is_empty() || !self.is_open(now) {
+            return None;
+        }
+        Some(self.take_pending(now))
+    }
+
+    fn is_open(&self, now: Instant) -> bool {
+        self.last_sent
+            .is_none_or(|last| now.saturating_duration_since(last) >= self.min_interval)
+    }
+
+    fn take_pending(&mut self, now: Instant) -> ExpansionSummary {
+        self.last_sent = Some(now);
+        ExpansionSummary {
+            triggers: std::mem::take(&mut self.pending),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExpansionSummary, NotificationThrottle};
+    use crate::config::NotificationConfig;
+    use std::time::{Duration, Instant};
+
+    fn throttle(min_interval_ms: u64, coalesce: bool) -> NotificationThrottle {
+        NotificationThrottle::new(&NotificationConfig {
+            min_interval_ms,
+            coalesce,
+            ..NotificationConfig::default()
+        })
+    }
+
+    fn summary(triggers: &[&str]) -> Option<ExpansionSummary> {
+        Some(ExpansionSummary {
+            triggers: triggers.iter().map(|t| t.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn coalesces_expansions_within_the_interval() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = throttle(2000, true);
+
+        assert_eq!(throttle.record(";sig", at(0)), summary(&[";sig"]));
+        assert_eq!(throttle.record(";sig", at(100)), None);
+        assert_eq!(throttle.record(";addr", at(500)), None);
+        assert_eq!(throttle.record(";brb", at(900)), None);
+        assert_eq!(throttle.flush(at(1999)), None);
+
+        let held = throttle.flush(at(2000)).expect("summary after interval");
+        assert_eq!(held.body(), "3 expansions (;sig, ;addr, ;brb)");
+        assert_eq!(throttle.flush(at(5000)), None);
+    }
+
+    #[test]
+    fn next_expansion_after_the_interval_includes_held_ones() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = throttle(2000, true);
+
+        throttle.record(";a", at(0));
+        throttle.record(";b", at(1000));
+
+        assert_eq!(throttle.record(";c", at(2500)), summary(&[";b", ";c"]));
+        assert_eq!(throttle.record(";d", at(3000)), None);
+    }
+
+    #[test]
+    fn drops_held_expansions_without_coalescing() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = throttle(2000, false);
+
+        assert_eq!(throttle.record(";a", at(0)), summary(&[";a"]));
+        assert_eq!(throttle.record(";b", at(1000)), None);
+        assert_eq!(throttle.flush(at(3000)), None);
+        assert_eq!(throttle.record(";c", at(3000)), summary(&[";c"]));
+    }
+
+    #[test]
+    fn zero_interval_notifies_every_expansion() {
+        let now = Instant::now();
+        let mut throttle = throttle(0, true);
+
+        assert_eq!(throttle.record(";a", now), summary(&[";a"]));
+        assert_eq!(throttle.record(";b", now), summary(&[";b"]));
+    }
+}
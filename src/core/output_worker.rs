@@ -0,0 +1,641 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail, Context, Result};
+use tracing::{error, info, info_span, warn};
+
+use crate::config::InjectMode;
+use crate::core::engine::{panic_message, UNDO_ACTION};
+use crate::core::expansion::OutputAction;
+use crate::io::notification::Notifier;
+use crate::io::output::{
+    actions_before_delays, insert_action_delays, OutputCancelled, OutputSink, PartialOutput,
+    SinkCapabilities,
+};
+
+/// One expansion's worth of output: erase the trigger, send the actions, then
+/// show the notification.
+pub struct OutputJob {
+    pub backspaces: usize,
+    pub actions: Vec<OutputAction>,
+    pub inject_mode: InjectMode,
+    pub char_delay_ms: Option<u64>,
+    /// `output.action_delay_ms`: pause between consecutive actions, pastes
+    /// included.
+    pub action_delay_ms: u64,
+    /// Where the notification goes, and where the [`OutputWorker`] reports
+    /// a failed job.
+    pub notifier: Option<Arc<dyn Notifier>>,
+    /// Shown once the output has been sent; skipped when sending fails.
+    pub notification: Option<ExpansionNotice>,
+}
+
+/// Expansion notification attached to an [`OutputJob`].
+pub struct ExpansionNotice {
+    pub body: String,
+    /// Adds an "Undo" button.
+    pub undoable: bool,
+}
+
+impl OutputJob {
+    /// Sends the job to `output` on the calling thread. Without a sink only
+    /// the notification is shown.
+    pub fn run(self, output: Option<&dyn OutputSink>) -> Result<()> {
+        let _span = info_span!(
+            "expansion",
+            backspaces = self.backspaces,
+            actions = self.actions.len(),
+            inject_mode = ?self.inject_mode
+        )
+        .entered();
+        if let Some(output) = output {
+            output.send_backspaces(self.backspaces)?;
+            send_expansion_actions(
+                output,
+                &self.actions,
+                self.inject_mode,
+                self.char_delay_ms,
+                self.action_delay_ms,
+            )?;
+        }
+        if let (Some(notifier), Some(notice)) = (&self.notifier, self.notification) {
+            send_expansion_notification(notifier.as_ref(), &notice.body, notice.undoable);
+        }
+        Ok(())
+    }
+}
+
+/// Runs [`OutputJob`]s one at a time, in submission order, on a dedicated
+/// thread that owns the [`OutputSink`], so slow expansions never hold up
+/// the key listener.
+///
+/// Dropping the worker waits for queued jobs, like [`shutdown`](Self::shutdown).
+pub struct OutputWorker {
+    sender: Option<SyncSender<(u64, OutputJob)>>,
+    thread: Option<JoinHandle<()>>,
+    output: Arc<dyn OutputSink>,
+    capacity: usize,
+    pending: Arc<PendingJobs>,
+    abort: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct PendingJobs {
+    count: Mutex<usize>,
+    idle: Condvar,
+    /// Sequence number of the next submitted job.
+    next: AtomicU64,
+    /// Jobs numbered below this were cancelled.
+    cancelled_before: AtomicU64,
+}
+
+impl PendingJobs {
+    fn add(&self) {
+        *self.count.lock().expect("output worker mutex poisoned") += 1;
+    }
+
+    fn finish(&self) {
+        let mut count = self.count.lock().expect("output worker mutex poisoned");
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            self.idle.notify_all();
+        }
+    }
+}
+
+impl OutputWorker {
+    /// Jobs that can wait behind the running one before new ones are refused.
+    pub const DEFAULT_CAPACITY: usize = 32;
+
+    pub fn spawn(output: Arc<dyn OutputSink>, capacity: usize) -> Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let pending = Arc::new(PendingJobs::default());
+        let abort = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::Builder::new()
+            .name("slykey-output".to_string())
+            .spawn({
+                let output = Arc::clone(&output);
+                let pending = Arc::clone(&pending);
+                let abort = Arc::clone(&abort);
+                move || run_jobs(receiver, output.as_ref(), &pending, &abort)
+            })
+            .context("failed to start the output thread")?;
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+            output,
+            capacity,
+            pending,
+            abort,
+        })
+    }
+
+    /// What the worker's sink supports.
+    pub fn capabilities(&self) -> SinkCapabilities {
+        self.output.capabilities()
+    }
+
+    /// Queues `job` behind any running one. Never blocks: fails instead when
+    /// the queue is full or the worker has stopped.
+    pub fn submit(&self, job: OutputJob) -> Result<()> {
+        let Some(sender) = &self.sender else {
+            bail!("output thread has stopped");
+        };
+        self.pending.add();
+        let seq = self.pending.next.fetch_add(1, Ordering::SeqCst);
+        match sender.try_send((seq, job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.pending.finish();
+                bail!(
+                    "output queue is full ({} expansions waiting); expansion dropped",
+                    self.capacity
+                )
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.pending.finish();
+                bail!("output thread has stopped")
+            }
+        }
+    }
+
+    /// Whether a submitted job has not finished yet.
+    pub fn is_busy(&self) -> bool {
+        *self
+            .pending
+            .count
+            .lock()
+            .expect("output worker mutex poisoned")
+            > 0
+    }
+
+    /// Stops the expansion being sent (see [`OutputSink::cancel`]) and drops
+    /// the queued ones, without their notifications.
+    pub fn cancel(&self) {
+        let next = self.pending.next.load(Ordering::SeqCst);
+        self.pending.cancelled_before.store(next, Ordering::SeqCst);
+        self.output.cancel();
+    }
+
+    /// Blocks until every submitted job has finished.
+    pub fn wait_idle(&self) {
+        let mut count = self
+            .pending
+            .count
+            .lock()
+            .expect("output worker mutex poisoned");
+        while *count > 0 {
+            count = self
+                .pending
+                .idle
+                .wait(count)
+                .expect("output worker mutex poisoned");
+        }
+    }
+
+    /// Stops accepting jobs, finishes the queued ones and joins the thread.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    /// Like [`shutdown`](Self::shutdown), but drops queued jobs; the one
+    /// already being sent still completes.
+    pub fn abort(mut self) {
+        self.abort.store(true, Ordering::Relaxed);
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("output thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for OutputWorker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn run_jobs(
+    receiver: Receiver<(u64, OutputJob)>,
+    output: &dyn OutputSink,
+    pending: &PendingJobs,
+    abort: &AtomicBool,
+) {
+    for (seq, job) in receiver {
+        let cancelled = seq < pending.cancelled_before.load(Ordering::SeqCst);
+        if !abort.load(Ordering::Relaxed) && !cancelled {
+            let notifier = job.notifier.clone();
+            // A panicking sink fails only its own job; the thread keeps
+            // serving the ones queued behind it.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| job.run(Some(output))))
+                .unwrap_or_else(|payload| {
+                    Err(anyhow!(
+                        "output panicked: {}",
+                        panic_message(payload.as_ref())
+                    ))
+                });
+            match result {
+                Ok(()) => {}
+                Err(err) if err.is::<OutputCancelled>() => info!("{err}"),
+                Err(err) => {
+                    error!("failed to send expansion: {err}");
+                    if let Some(notifier) = notifier {
+                        if let Err(notify_err) =
+                            notifier.notify("Expansion Error", &err.to_string())
+                        {
+                            warn!("failed to send expansion error notification: {notify_err}");
+                        }
+                    }
+                }
+            }
+        }
+        pending.finish();
+    }
+}
+
+/// Puts `action_delay_ms` between consecutive actions, then sends them as
+/// [`send_batches`] does. A failure is reported as a [`PartialOutput`]
+/// counting `actions`, not the pauses put between them.
+fn send_expansion_actions(
+    output: &dyn OutputSink,
+    actions: &[OutputAction],
+    inject_mode: InjectMode,
+    char_delay_ms: Option<u64>,
+    action_delay_ms: u64,
+) -> Result<()> {
+    if action_delay_ms == 0 {
+        return send_batches(output, actions, inject_mode, char_delay_ms);
+    }
+    let delayed = insert_action_delays(actions, action_delay_ms);
+    send_batches(output, &delayed, inject_mode, char_delay_ms).map_err(|err| {
+        match err.downcast::<PartialOutput>() {
+            Ok(partial) => PartialOutput {
+                emitted: actions_before_delays(actions, action_delay_ms, partial.emitted),
+                total: actions.len(),
+                source: partial.source,
+            }
+            .into(),
+            Err(err) => err,
+        }
+    })
+}
+
+/// In clipboard mode each run of consecutive text actions becomes one paste,
+/// while keys, sleeps and caret moves are still sent in order between them.
+/// A batch that fails is reported as a [`PartialOutput`] counting the actions
+/// of the batches before it.
+fn send_batches(
+    output: &dyn OutputSink,
+    actions: &[OutputAction],
+    inject_mode: InjectMode,
+    char_delay_ms: Option<u64>,
+) -> Result<()> {
+    if inject_mode == InjectMode::Type {
+        return match char_delay_ms {
+            Some(delay_ms) => output.send_actions_with_char_delay(actions, delay_ms),
+            None => output.send_actions(actions),
+        };
+    }
+
+    let mut sent = 0;
+    let mut paste_text = String::new();
+    let mut pasted_actions = 0;
+    let mut other_actions = Vec::new();
+    for action in actions {
+        match action {
+            OutputAction::Text(text) => {
+                if !other_actions.is_empty() {
+                    let batch = std::mem::take(&mut other_actions);
+                    output
+                        .send_actions(&batch)
+                        .map_err(|err| partial_output(err, sent, actions.len()))?;
+                    sent += batch.len();
+                }
+                paste_text.push_str(text);
+                pasted_actions += 1;
+            }
+            other => {
+                if !paste_text.is_empty() {
+                    output
+                        .send_paste(&std::mem::take(&mut paste_text))
+                        .map_err(|err| partial_output(err, sent, actions.len()))?;
+                }
+                sent += std::mem::take(&mut pasted_actions);
+                other_actions.push(other.clone());
+            }
+        }
+    }
+
+    if !paste_text.is_empty() {
+        output
+            .send_paste(&paste_text)
+            .map_err(|err| partial_output(err, sent, actions.len()))?;
+        sent += pasted_actions;
+    }
+    if !other_actions.is_empty() {
+        output
+            .send_actions(&other_actions)
+            .map_err(|err| partial_output(err, sent, actions.len()))?;
+    }
+    Ok(())
+}
+
+/// Counts `sent` earlier actions into an error from a later batch.
+fn partial_output(err: anyhow::Error, sent: usize, total: usize) -> anyhow::Error {
+    if err.is::<OutputCancelled>() {
+        return err;
+    }
+    match err.downcast::<PartialOutput>() {
+        Ok(partial) => PartialOutput {
+            emitted: sent + partial.emitted,
+            total,
+            source: partial.source,
+        }
+        .into(),
+        Err(source) => PartialOutput {
+            emitted: sent,
+            total,
+            source,
+        }
+        .into(),
+    }
+}
+
+pub(crate) fn send_expansion_notification(notifier: &dyn Notifier, body: &str, undoable: bool) {
+    let sent = if undoable {
+        notifier.notify_with_actions("Text Expanded", body, &[(UNDO_ACTION, "Undo")])
+    } else {
+        notifier.notify("Text Expanded", body)
+    };
+    if let Err(err) = sent {
+        warn!("failed to send expansion notification: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::core::expansion::format_actions;
+    use crate::io::notification::RecordingNotifier;
+    use crate::io::output::SpecialKey;
+
+    #[derive(Default)]
+    struct SlowSink {
+        started: AtomicBool,
+        texts: Mutex<Vec<String>>,
+    }
+
+    impl OutputSink for SlowSink {
+        fn send_backspaces(&self, _count: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            self.started.store(true, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(20));
+            for action in actions {
+                if let OutputAction::Text(text) = action {
+                    self.texts
+                        .lock()
+                        .expect("texts lock poisoned")
+                        .push(text.clone());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn text_job(text: &str) -> OutputJob {
+        OutputJob {
+            backspaces: 0,
+            actions: vec![OutputAction::Text(text.to_string())],
+            inject_mode: InjectMode::Type,
+            char_delay_ms: None,
+            action_delay_ms: 0,
+            notifier: None,
+            notification: None,
+        }
+    }
+
+    #[test]
+    fn shutdown_drains_queued_jobs_in_order() {
+        let sink = Arc::new(SlowSink::default());
+        let worker = OutputWorker::spawn(sink.clone(), 8).expect("spawn worker");
+        for text in ["a", "b", "c"] {
+            worker.submit(text_job(text)).expect("queue has room");
+        }
+        worker.shutdown();
+
+        assert_eq!(
+            *sink.texts.lock().expect("texts lock poisoned"),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn abort_drops_jobs_that_have_not_started() {
+        let sink = Arc::new(SlowSink::default());
+        let worker = OutputWorker::spawn(sink.clone(), 8).expect("spawn worker");
+        worker.submit(text_job("first")).expect("queue has room");
+        while !sink.started.load(Ordering::SeqCst) {
+            std::thread::yield_now();
+        }
+        for _ in 0..4 {
+            worker.submit(text_job("queued")).expect("queue has room");
+        }
+        worker.abort();
+
+        assert_eq!(
+            *sink.texts.lock().expect("texts lock poisoned"),
+            vec!["first"]
+        );
+    }
+
+    #[test]
+    fn full_queue_refuses_jobs_without_blocking() {
+        let sink = Arc::new(SlowSink::default());
+        let worker = OutputWorker::spawn(sink.clone(), 1).expect("spawn worker");
+        let results: Vec<bool> = (0..4)
+            .map(|_| worker.submit(text_job("x")).is_ok())
+            .collect();
+
+        assert!(results.contains(&false));
+        worker.wait_idle();
+        let sent = sink.texts.lock().expect("texts lock poisoned").len();
+        assert_eq!(sent, results.iter().filter(|ok| **ok).count());
+    }
+
+    /// Pastes fine until asked to paste `fail_on`.
+    struct FailingPasteSink {
+        fail_on: &'static str,
+    }
+
+    impl OutputSink for FailingPasteSink {
+        fn send_backspaces(&self, _count: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn send_actions(&self, _actions: &[OutputAction]) -> Result<()> {
+            Ok(())
+        }
+
+        fn send_paste(&self, text: &str) -> Result<()> {
+            if text == self.fail_on {
+                bail!("clipboard unavailable");
+            }
+            Ok(())
+        }
+    }
+
+    /// Panics when asked to type `panic_on`.
+    struct PanickingSink {
+        panic_on: &'static str,
+        texts: Mutex<Vec<String>>,
+    }
+
+    impl OutputSink for PanickingSink {
+        fn send_backspaces(&self, _count: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            for action in actions {
+                if let OutputAction::Text(text) = action {
+                    assert_ne!(text, self.panic_on, "sink bug");
+                    self.texts
+                        .lock()
+                        .expect("mutex poisoned")
+                        .push(text.clone());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_panicking_job_is_reported_and_later_jobs_still_run() {
+        let sink = Arc::new(PanickingSink {
+            panic_on: "boom",
+            texts: Mutex::default(),
+        });
+        let notifier = Arc::new(RecordingNotifier::default());
+        let worker = OutputWorker::spawn(sink.clone(), 8).expect("spawn");
+        worker
+            .submit(OutputJob {
+                notifier: Some(notifier.clone()),
+                ..text_job("boom")
+            })
+            .expect("submit");
+        worker.submit(text_job("after")).expect("submit");
+        worker.wait_idle();
+        assert!(!worker.is_busy());
+        worker.shutdown();
+
+        assert_eq!(*sink.texts.lock().expect("mutex poisoned"), vec!["after"]);
+        let sent = notifier.sent();
+        assert_eq!(sent.len(), 1, "{sent:?}");
+        assert!(
+            sent[0].starts_with("Expansion Error: output panicked: assertion"),
+            "{sent:?}"
+        );
+    }
+
+    #[test]
+    fn failed_jobs_are_reported_with_the_actions_already_sent() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let worker = OutputWorker::spawn(Arc::new(FailingPasteSink { fail_on: "b" }), 8)
+            .expect("spawn worker");
+        worker
+            .submit(OutputJob {
+                backspaces: 2,
+                actions: vec![
+                    OutputAction::Text("a".to_string()),
+                    OutputAction::Key(SpecialKey::Tab),
+                    OutputAction::Text("b".to_string()),
+                ],
+                inject_mode: InjectMode::Clipboard,
+                char_delay_ms: None,
+                action_delay_ms: 0,
+                notifier: Some(notifier.clone()),
+                notification: Some(ExpansionNotice {
+                    body: ";g".to_string(),
+                    undoable: false,
+                }),
+            })
+            .expect("queue has room");
+        worker.shutdown();
+
+        assert_eq!(
+            notifier.sent(),
+            vec!["Expansion Error: sent 2 of 3 actions before failing: clipboard unavailable"]
+        );
+    }
+
+    /// Records pastes and actions in order, failing to paste `fail_on`.
+    #[derive(Default)]
+    struct RecordingPasteSink {
+        fail_on: &'static str,
+        sent: Mutex<Vec<String>>,
+    }
+
+    impl OutputSink for RecordingPasteSink {
+        fn send_backspaces(&self, _count: usize) -> Result<()> {
+            Ok(())
+        }
+
+        fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+            let mut sent = self.sent.lock().expect("sent lock poisoned");
+            sent.push(format_actions(actions));
+            Ok(())
+        }
+
+        fn send_paste(&self, text: &str) -> Result<()> {
+            if text == self.fail_on {
+                bail!("clipboard unavailable");
+            }
+            let mut sent = self.sent.lock().expect("sent lock poisoned");
+            sent.push(format!("paste {text}"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn action_delays_go_around_pastes_and_are_not_counted_as_sent() {
+        let actions = [
+            OutputAction::Text("a".to_string()),
+            OutputAction::Key(SpecialKey::Tab),
+            OutputAction::Text("b".to_string()),
+        ];
+        let sink = RecordingPasteSink::default();
+        send_expansion_actions(&sink, &actions, InjectMode::Clipboard, None, 20)
+            .expect("sending should succeed");
+        assert_eq!(
+            *sink.sent.lock().expect("sent lock poisoned"),
+            vec!["paste a", "[SLEEP 20ms][KEY Tab][SLEEP 20ms]", "paste b"]
+        );
+
+        let sink = RecordingPasteSink {
+            fail_on: "b",
+            ..RecordingPasteSink::default()
+        };
+        let err = send_expansion_actions(&sink, &actions, InjectMode::Clipboard, None, 20)
+            .expect_err("pasting b should fail");
+        let partial = err
+            .downcast_ref::<PartialOutput>()
+            .expect("failure should be partial");
+        assert_eq!((partial.emitted, partial.total), (2, 3));
+    }
+}
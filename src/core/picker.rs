@@ -0,0 +1,294 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::{AppConfig, NewlineMode};
+
+/// Longest expansion preview shown next to a trigger in the picker.
+const PREVIEW_CHARS: usize = 40;
+
+/// One row of the snippet picker: a tray snippet or an expansion rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickerEntry {
+    /// Single-line, unique text shown in the list and echoed back by dmenu.
+    pub label: String,
+    /// Snippet content or rule expansion, macros not yet rendered.
+    pub content: String,
+    /// The rule's `newline`; snippets type line breaks as text.
+    pub newline: NewlineMode,
+}
+
+/// Snippets first, then the rules that can currently fire (not `enabled:
+/// false`, not in `disabled_groups`), each labelled `trigger  label`, or
+/// `trigger  preview` when the rule has no label.
+pub fn picker_entries(config: &AppConfig, disabled_groups: &HashSet<String>) -> Vec<PickerEntry> {
+    let snippets = config.snippets.iter().map(|snippet| {
+        (
+            single_line(&snippet.title),
+            snippet.content.clone(),
+            NewlineMode::Text,
+        )
+    });
+    let rules = config
+        .expansions
+        .iter()
+        .filter(|rule| rule.is_active(disabled_groups))
+        .map(|rule| {
+            let label = match &rule.label {
+                Some(label) => format!("{}  {label}", rule.trigger),
+                None => format!("{}  {}", rule.trigger, preview(&rule.expansion)),
+            };
+            (single_line(&label), rule.expansion.clone(), rule.newline)
+        });
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    snippets
+        .chain(rules)
+        .map(|(label, content, newline)| {
+            let count = seen.entry(label.clone()).or_insert(0);
+            *count += 1;
+            let label = if *count == 1 {
+                label
+            } else {
+                format!("{label} ({count})")
+            };
+            PickerEntry {
+                label,
+                content,
+                newline,
+            }
+        })
+        .collect()
+}
+
+/// Entries matching `query`, best first: labels starting with it, then labels
+/// containing it, then labels containing its characters in order, then
+/// entries whose content contains it. Case-insensitive; an empty query keeps
+/// every entry in order.
+pub fn filter_entries<'a>(entries: &'a [PickerEntry], query: &str) -> Vec<&'a PickerEntry> {
+    let query = query.trim().to_lowercase();
+    let mut ranked: Vec<(u8, &PickerEntry)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let label = entry.label.to_lowercase();
+            let rank = if label.starts_with(&query) {
+                0
+            } else if label.contains(&query) {
+                1
+            } else if is_subsequence(&query, &label) {
+                2
+            } else if entry.content.to_lowercase().contains(&query) {
+                3
+            } else {
+                return None;
+            };
+            Some((rank, entry))
+        })
+        .collect();
+    // Stable, so entries of the same rank keep their config order.
+    ranked.sort_by_key(|(rank, _)| *rank);
+    ranked.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// The dmenu protocol's input: one label per line.
+pub fn dmenu_input(entries: &[PickerEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("{}\n", entry.label))
+        .collect()
+}
+
+/// The entry whose label dmenu printed, or `None` for an empty or unknown
+/// line (e.g. free text typed into rofi).
+pub fn dmenu_selection<'a>(entries: &'a [PickerEntry], stdout: &str) -> Option<&'a PickerEntry> {
+    let selected = stdout.lines().next()?;
+    entries.iter().find(|entry| entry.label == selected)
+}
+
+/// Runs a dmenu-style `command` through `sh -c`, writing the labels to its
+/// stdin and reading the chosen one from stdout. Exit status 1, which dmenu
+/// and rofi use for Escape, means nothing was picked.
+pub fn run_picker_command(command: &str, entries: &[PickerEntry]) -> Result<Option<PickerEntry>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to start picker command '{command}'"))?;
+
+    let input = dmenu_input(entries);
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Written from another thread so a picker that answers before reading
+    // everything cannot deadlock against a full pipe.
+    let writer = std::thread::spawn(move || {
+        // The picker may exit without reading its input; that is not an error.
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("failed to run picker command '{command}'"))?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        if output.status.code() == Some(1) {
+            return Ok(None);
+        }
+        bail!(
+            "picker command '{command}' failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(dmenu_selection(entries, &String::from_utf8_lossy(&output.stdout)).cloned())
+}
+
+/// First line of `expansion`, cut to [`PREVIEW_CHARS`], with `…` when anything was left out.
+pub(crate) fn preview(expansion: &str) -> String {
+    let line = expansion.lines().next().unwrap_or_default();
+    let mut preview: String = line.chars().take(PREVIEW_CHARS).collect();
+    if line.chars().count() > PREVIEW_CHARS || expansion.lines().nth(1).is_some() {
+        preview.push('…');
+    }
+    preview
+}
+
+fn single_line(text: &str) -> String {
+    text.split(['\n', '\r']).collect::<Vec<_>>().join(" ")
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ExpansionRule, MenuSnippet, SnippetMode};
+
+    fn entry(label: &str) -> PickerEntry {
+        PickerEntry {
+            label: label.to_string(),
+            content: format!("content of {label}"),
+            newline: NewlineMode::Text,
+        }
+    }
+
+    fn labels<'a>(entries: &[&'a PickerEntry]) -> Vec<&'a str> {
+        entries.iter().map(|entry| entry.label.as_str()).collect()
+    }
+
+    #[test]
+    fn entries_list_snippets_then_active_rules_with_unique_labels() {
+        let config = AppConfig {
+            snippets: vec![
+                MenuSnippet {
+                    title: "Address".to_string(),
+                    content: "1 Main St".to_string(),
+                    group: None,
+                    separator_after: false,
+                    mode: SnippetMode::Clipboard,
+                    type_delay_ms: None,
+                },
+                MenuSnippet {
+                    title: "Address".to_string(),
+                    content: "2 Side St".to_string(),
+                    group: None,
+                    separator_after: false,
+                    mode: SnippetMode::Clipboard,
+                    type_delay_ms: None,
+                },
+            ],
+            expansions: vec![
+                ExpansionRule {
+                    trigger: ";sig".to_string(),
+                    expansion: "Best regards,\nTyler".to_string(),
+                    ..ExpansionRule::default()
+                },
+                ExpansionRule {
+                    trigger: ";off".to_string(),
+                    expansion: "hidden".to_string(),
+                    enabled: Some(false),
+                    ..ExpansionRule::default()
+                },
+                ExpansionRule {
+                    trigger: ";work".to_string(),
+                    expansion: "work only".to_string(),
+                    group: Some("work".to_string()),
+                    ..ExpansionRule::default()
+                },
+                ExpansionRule {
+                    trigger: ";x1".to_string(),
+                    expansion: "Sorry, your parcel is late".to_string(),
+                    label: Some("Support reply".to_string()),
+                    ..ExpansionRule::default()
+                },
+            ],
+            ..serde_yaml::from_str("{}").expect("empty config should parse")
+        };
+        let disabled = HashSet::from(["work".to_string()]);
+
+        let entries = picker_entries(&config, &disabled);
+
+        let labels: Vec<&str> = entries.iter().map(|entry| entry.label.as_str()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "Address",
+                "Address (2)",
+                ";sig  Best regards,…",
+                ";x1  Support reply"
+            ]
+        );
+        assert_eq!(entries[2].content, "Best regards,\nTyler");
+    }
+
+    #[test]
+    fn filter_ranks_prefix_then_substring_then_fuzzy_then_content() {
+        let entries = vec![
+            entry("Home address"),
+            entry(";addr  1 Main St"),
+            entry("Address"),
+            entry("a-d-d-r"),
+            entry("Phone"),
+        ];
+
+        assert_eq!(
+            labels(&filter_entries(&entries, "ADDR")),
+            vec!["Address", "Home address", ";addr  1 Main St", "a-d-d-r"]
+        );
+        assert_eq!(labels(&filter_entries(&entries, "of phone")), vec!["Phone"]);
+        assert_eq!(filter_entries(&entries, "").len(), entries.len());
+        assert!(filter_entries(&entries, "zzz").is_empty());
+    }
+
+    #[test]
+    fn dmenu_protocol_round_trips_labels() {
+        let entries = vec![entry("one"), entry("two")];
+
+        assert_eq!(dmenu_input(&entries), "one\ntwo\n");
+        assert_eq!(dmenu_selection(&entries, "two\n"), Some(&entries[1]));
+        assert_eq!(dmenu_selection(&entries, "two"), Some(&entries[1]));
+        assert_eq!(dmenu_selection(&entries, ""), None);
+        assert_eq!(dmenu_selection(&entries, "typed by hand\n"), None);
+    }
+
+    #[test]
+    fn picker_command_reads_labels_and_returns_the_choice() {
+        let entries = vec![entry("one"), entry("two"), entry("three")];
+
+        let picked = run_picker_command("sed -n 2p", &entries).expect("command should run");
+        assert_eq!(picked, Some(entries[1].clone()));
+
+        let cancelled = run_picker_command("exit 1", &entries).expect("escape is not an error");
+        assert_eq!(cancelled, None);
+
+        let err = run_picker_command("echo broken >&2; exit 3", &entries)
+            .expect_err("other failures should be reported");
+        assert!(err.to_string().contains("broken"));
+    }
+}
@@ -0,0 +1,340 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+use crate::core::engine::Engine;
+use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
+use crate::io::output::{LoggingSink, NullSink, SinkCapabilities};
+
+/// Minimum length of a word [`EventRecorder`] redacts as a likely password.
+const PASSWORD_MIN_CHARS: usize = 8;
+
+/// One line of a `slykey run --record` file: the event as the engine saw
+/// it, `t_ms` milliseconds after recording started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub t_ms: u64,
+    #[serde(flatten)]
+    pub event: KeyEvent,
+}
+
+/// Appends key events to a JSON lines file. With redaction on, the typed
+/// characters of a word that looks like a password are replaced by `*`; as
+/// that is only known once the word ends, its events are held back until
+/// then (or until [`flush`](Self::flush)).
+pub struct EventRecorder<W: Write> {
+    writer: W,
+    started: Instant,
+    redact: bool,
+    word: Vec<RecordedEvent>,
+    word_text: String,
+}
+
+impl EventRecorder<File> {
+    /// Appends to `path`, creating it if needed, readable only by the owner:
+    /// without redaction it holds every keystroke, passwords included.
+    pub fn create(path: &Path, redact: bool) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("failed to open recording file {}", path.display()))?;
+        Ok(Self::new(file, redact))
+    }
+}
+
+impl<W: Write> EventRecorder<W> {
+    pub fn new(writer: W, redact: bool) -> Self {
+        Self {
+            writer,
+            started: Instant::now(),
+            redact,
+            word: Vec::new(),
+            word_text: String::new(),
+        }
+    }
+
+    pub fn record(&mut self, event: &KeyEvent) -> Result<()> {
+        let recorded = RecordedEvent {
+            t_ms: self.started.elapsed().as_millis() as u64,
+            event: event.clone(),
+        };
+        if !self.redact {
+            return self.write(&recorded);
+        }
+
+        if event.kind == KeyEventKind::Press && !event.is_injected {
+            if let Some(key) = event.special.filter(|key| !key.is_modifier()) {
+                if key == SpecialInputKey::Backspace {
+                    self.word_text.pop();
+                } else {
+                    self.flush()?;
+                    return self.write(&recorded);
+                }
+            } else if let Some(text) = &event.text {
+                if text.chars().any(char::is_whitespace) {
+                    self.flush()?;
+                    return self.write(&recorded);
+                }
+                self.word_text.push_str(text);
+            }
+        }
+        self.word.push(recorded);
+        Ok(())
+    }
+
+    /// Writes the events held back for the current word.
+    pub fn flush(&mut self) -> Result<()> {
+        let redact = looks_like_password(&self.word_text);
+        self.word_text.clear();
+        for mut recorded in std::mem::take(&mut self.word) {
+            if redact && !recorded.event.is_injected {
+                if let Some(text) = &mut recorded.event.text {
+                    *text = "*".repeat(text.chars().count());
+                }
+            }
+            self.write(&recorded)?;
+        }
+        self.writer
+            .flush()
+            .context("failed to flush recording file")
+    }
+
+    /// Flushes and returns the writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, recorded: &RecordedEvent) -> Result<()> {
+        let mut line = serde_json::to_string(recorded).context("failed to serialize key event")?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .context("failed to write recording file")
+    }
+}
+
+/// At least [`PASSWORD_MIN_CHARS`] characters mixing three of lowercase,
+/// uppercase, digits and symbols.
+fn looks_like_password(word: &str) -> bool {
+    if word.chars().count() < PASSWORD_MIN_CHARS {
+        return false;
+    }
+    let classes = [
+        word.chars().any(char::is_lowercase),
+        word.chars().any(char::is_uppercase),
+        word.chars().any(|c| c.is_ascii_digit()),
+        word.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    classes.into_iter().filter(|present| *present).count() >= 3
+}
+
+/// Parses a recording; `line N` in errors counts from 1, blank lines are skipped.
+pub fn parse_recording(raw: &str) -> Result<Vec<RecordedEvent>> {
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid event on line {}", index + 1))
+        })
+        .collect()
+}
+
+/// An expansion fired while replaying a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayedExpansion {
+    /// Index of the event that fired it.
+    pub event: usize,
+    pub t_ms: u64,
+    pub trigger: Option<String>,
+    /// What would have been sent, as `slykey run --dry-run` logs it.
+    pub output: Vec<String>,
+}
+
+/// Feeds `events` to a fresh engine for `config` that sends nothing, and
+/// returns each event's output. Errors from an event are reported as its
+/// output instead of ending the replay, like the daemon logs and moves on.
+pub fn replay(config: AppConfig, events: &[RecordedEvent]) -> Vec<ReplayedExpansion> {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let logged = Arc::clone(&lines);
+    let sink = LoggingSink::new(Arc::new(NullSink), move |line| {
+        logged
+            .lock()
+            .expect("replay output mutex poisoned")
+            .push(line.to_string())
+    })
+    .with_capabilities(SinkCapabilities::FULL);
+    let mut engine = Engine::new(config);
+    engine.set_output(Arc::new(sink));
+
+    let mut fired = Vec::new();
+    for (index, recorded) in events.iter().enumerate() {
+        let count = engine.stats().totals().count;
+        let result = engine.handle_event(recorded.event.clone());
+        let mut output = std::mem::take(&mut *lines.lock().expect("replay output mutex poisoned"));
+        if let Err(err) = result {
+            output.push(format!("error: {err:#}"));
+        }
+        if output.is_empty() {
+            continue;
+        }
+        let trigger = (engine.stats().totals().count > count)
+            .then(|| engine.history().back().map(|record| record.trigger.clone()))
+            .flatten();
+        fired.push(ReplayedExpansion {
+            event: index,
+            t_ms: recorded.t_ms,
+            trigger,
+            output,
+        });
+    }
+    fired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_recording, replay, EventRecorder, RecordedEvent};
+    use crate::config::AppConfig;
+    use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
+
+    fn press_text(text: &str) -> KeyEvent {
+        KeyEvent {
+            kind: KeyEventKind::Press,
+            text: Some(text.to_string()),
+            special: None,
+            is_injected: false,
+        }
+    }
+
+    fn press_special(key: SpecialInputKey) -> KeyEvent {
+        KeyEvent {
+            kind: KeyEventKind::Press,
+            text: None,
+            special: Some(key),
+            is_injected: false,
+        }
+    }
+
+    fn record(redact: bool, events: &[KeyEvent]) -> String {
+        let mut recorder = EventRecorder::new(Vec::new(), redact);
+        for event in events {
+            recorder.record(event).expect("record");
+        }
+        String::from_utf8(recorder.into_inner().expect("flush")).expect("utf-8")
+    }
+
+    fn typed(text: &str) -> Vec<KeyEvent> {
+        text.chars().map(|c| press_text(&c.to_string())).collect()
+    }
+
+    #[test]
+    fn recordings_round_trip_as_json_lines() {
+        let events = vec![
+            press_text("ü"),
+            KeyEvent {
+                kind: KeyEventKind::Release,
+                text: None,
+                special: Some(SpecialInputKey::Unknown(94)),
+                is_injected: true,
+            },
+            press_special(SpecialInputKey::AltGr),
+        ];
+
+        let raw = record(false, &events);
+        let lines: Vec<&str> = raw.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[0].ends_with(r#""kind":"press","text":"ü","special":null,"is_injected":false}"#),
+            "{raw}"
+        );
+        assert!(lines[1].contains(r#""special":{"unknown":94}"#), "{raw}");
+        assert!(lines[2].contains(r#""special":"alt_gr""#), "{raw}");
+
+        let parsed = parse_recording(&format!("{raw}\n")).expect("parse");
+        assert_eq!(
+            parsed
+                .iter()
+                .map(|recorded| recorded.event.clone())
+                .collect::<Vec<_>>(),
+            events
+        );
+        assert!(parsed.windows(2).all(|pair| pair[0].t_ms <= pair[1].t_ms));
+
+        let err = parse_recording("{\"t_ms\":1}\n").expect_err("missing fields");
+        assert!(format!("{err:#}").contains("line 1"), "{err:#}");
+    }
+
+    #[test]
+    fn recording_file_is_created_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!(
+            "slykey-recording-{}-{}.jsonl",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("time")
+                .as_nanos()
+        ));
+        EventRecorder::create(&path, false).expect("create");
+
+        let mode = std::fs::metadata(&path)
+            .expect("metadata")
+            .permissions()
+            .mode();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn redaction_masks_only_password_looking_words() {
+        let mut events = typed("hi Tr0ub4dor&3");
+        events.push(press_special(SpecialInputKey::Enter));
+        events.extend(typed("plainwords"));
+
+        let raw = record(true, &events);
+        let texts: String = parse_recording(&raw)
+            .expect("parse")
+            .into_iter()
+            .filter_map(|recorded| recorded.event.text)
+            .collect();
+        assert_eq!(texts, "hi ***********plainwords");
+
+        let raw = record(false, &events);
+        assert!(raw.contains(r#""text":"&""#), "{raw}");
+    }
+
+    #[test]
+    fn replay_reports_the_expansions_that_fire() {
+        let config: AppConfig = serde_yaml::from_str(
+            "expansions:\n  - trigger: ';sig'\n    expansion: 'Thanks{{KEY:ENTER}}'\n",
+        )
+        .expect("config");
+        let events: Vec<RecordedEvent> = typed("x ;sig")
+            .into_iter()
+            .enumerate()
+            .map(|(index, event)| RecordedEvent {
+                t_ms: index as u64 * 10,
+                event,
+            })
+            .collect();
+
+        let fired = replay(config, &events);
+
+        assert_eq!(fired.len(), 1, "{fired:?}");
+        assert_eq!(fired[0].event, 5);
+        assert_eq!(fired[0].t_ms, 50);
+        assert_eq!(fired[0].trigger.as_deref(), Some(";sig"));
+        assert_eq!(fired[0].output, vec!["[BACKSPACE x4]", "Thanks[KEY Enter]"]);
+    }
+}
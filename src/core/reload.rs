@@ -0,0 +1,437 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use tracing::warn;
+
+use crate::config::{AppConfig, ExpansionRule, MenuSnippet};
+use crate::core::config_watch::watched_paths;
+use crate::core::engine::Engine;
+
+/// Loads and validates the config at `path`, then swaps it into `engine`.
+/// Shared by the file watcher, `slykey reload` and the tray menu; on error
+/// the engine keeps its previous config. Returns the files the new config
+/// depends on, so the watcher can follow changed includes.
+pub fn reload_from_path(path: &Path, engine: &Mutex<Engine>) -> Result<Vec<PathBuf>> {
+    let loaded = AppConfig::load(Some(path.to_path_buf()))?;
+    loaded.config.validate_deep()?;
+    for warning in &loaded.warnings {
+        warn!("{warning}");
+    }
+    let paths = watched_paths(&loaded);
+    engine
+        .lock()
+        .expect("engine mutex poisoned")
+        .reload_config(loaded.config);
+    Ok(paths)
+}
+
+/// How many entries of one kind a reload added, removed or changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChangeCounts {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+impl ChangeCounts {
+    /// Entries are matched by key; a kept key with a different value counts
+    /// as changed.
+    fn between<K: Ord, V: PartialEq>(old: &BTreeMap<K, V>, new: &BTreeMap<K, V>) -> Self {
+        let mut counts = Self::default();
+        for (key, value) in old {
+            match new.get(key) {
+                Some(new_value) if new_value != value => counts.changed += 1,
+                Some(_) => {}
+                None => counts.removed += 1,
+            }
+        }
+        counts.added = new.keys().filter(|key| !old.contains_key(key)).count();
+        counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0 && self.changed == 0
+    }
+
+    fn describe(&self, kind: &str) -> Option<String> {
+        let parts: Vec<String> = [
+            (self.added, "added"),
+            (self.removed, "removed"),
+            (self.changed, "changed"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{count} {what}"))
+        .collect();
+        (!parts.is_empty()).then(|| format!("{kind}: {}", parts.join(", ")))
+    }
+}
+
+/// What a reload changed, plus what the new config is warned about: its
+/// [`lint`](AppConfig::lint) findings and the rules the output sink cannot
+/// fully handle. Shown in the reload notification and `slykey reload`'s
+/// reply, not only logged.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    pub diff: ConfigDiff,
+    pub warnings: Vec<String>,
+}
+
+impl ReloadReport {
+    /// Compares `previous` with the config `engine` runs since the reload.
+    pub fn new(previous: &AppConfig, engine: &Engine) -> Self {
+        let config = engine.base_config();
+        let warnings = config
+            .lint()
+            .iter()
+            .chain(engine.capability_warnings())
+            .map(|warning| config.describe_warning(warning))
+            .collect();
+        Self {
+            diff: ConfigDiff::between(previous, config),
+            warnings,
+        }
+    }
+
+    /// The diff's [`summary`](ConfigDiff::summary), then one line per warning.
+    pub fn describe(&self) -> String {
+        let mut text = self.diff.summary();
+        for warning in &self.warnings {
+            text.push_str("\nwarning: ");
+            text.push_str(warning);
+        }
+        text
+    }
+}
+
+/// What changed between two configs, for the reload notification.
+/// Profile entries count alongside the top-level ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// Rules keyed by trigger; a trigger listed twice (e.g. for different
+    /// apps) is matched by its position among the rules sharing it.
+    pub rules: ChangeCounts,
+    pub globals: ChangeCounts,
+    /// Snippets keyed by group and title.
+    pub snippets: ChangeCounts,
+    /// Whether anything else, such as `match_behavior`, changed.
+    pub settings_changed: bool,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &AppConfig, new: &AppConfig) -> Self {
+        Self {
+            rules: ChangeCounts::between(&rules_by_key(old), &rules_by_key(new)),
+            globals: ChangeCounts::between(&globals_by_key(old), &globals_by_key(new)),
+            snippets: ChangeCounts::between(&snippets_by_key(old), &snippets_by_key(new)),
+            settings_changed: settings_only(old) != settings_only(new),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+            && self.globals.is_empty()
+            && self.snippets.is_empty()
+            && !self.settings_changed
+    }
+
+    /// One line such as `rules: 2 added, 1 changed; globals: 1 removed`.
+    pub fn summary(&self) -> String {
+        let mut parts: Vec<String> = [
+            self.rules.describe("rules"),
+            self.globals.describe("globals"),
+            self.snippets.describe("snippets"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if self.settings_changed {
+            parts.push("settings changed".to_string());
+        }
+        if parts.is_empty() {
+            return "no changes".to_string();
+        }
+        parts.join("; ")
+    }
+}
+
+/// Every rule, top-level and per profile, keyed by profile, trigger and
+/// occurrence of that trigger.
+fn rules_by_key(config: &AppConfig) -> BTreeMap<(Option<&str>, &str, usize), &ExpansionRule> {
+    let mut keyed = BTreeMap::new();
+    let lists = std::iter::once((None, &config.expansions)).chain(
+        config
+            .profiles
+            .iter()
+            .map(|(name, profile)| (Some(name.as_str()), &profile.expansions)),
+    );
+    for (profile, rules) in lists {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for rule in rules {
+            let occurrence = seen.entry(rule.trigger.as_str()).or_default();
+            keyed.insert((profile, rule.trigger.as_str(), *occurrence), rule);
+            *occurrence += 1;
+        }
+    }
+    keyed
+}
+
+fn globals_by_key(config: &AppConfig) -> BTreeMap<(Option<&str>, &str), &str> {
+    let top_level = config
+        .globals
+        .iter()
+        .map(|(name, value)| ((None, name.as_str()), value.as_str()));
+    let profiles = config.profiles.iter().flat_map(|(profile, contents)| {
+        contents
+            .globals
+            .iter()
+            .map(move |(name, value)| ((Some(profile.as_str()), name.as_str()), value.as_str()))
+    });
+    top_level.chain(profiles).collect()
+}
+
+type SnippetKey<'a> = (Option<&'a str>, Option<&'a str>, &'a str);
+
+fn snippets_by_key(config: &AppConfig) -> BTreeMap<SnippetKey<'_>, &MenuSnippet> {
+    let top_level = config
+        .snippets
+        .iter()
+        .map(|snippet| (snippet_key(None, snippet), snippet));
+    let profiles = config.profiles.iter().flat_map(|(profile, contents)| {
+        contents
+            .snippets
+            .iter()
+            .map(move |snippet| (snippet_key(Some(profile.as_str()), snippet), snippet))
+    });
+    top_level.chain(profiles).collect()
+}
+
+fn snippet_key<'a>(profile: Option<&'a str>, snippet: &'a MenuSnippet) -> SnippetKey<'a> {
+    (profile, snippet.group.as_deref(), snippet.title.as_str())
+}
+
+/// `config` without the rules, globals and snippets counted on their own.
+fn settings_only(config: &AppConfig) -> AppConfig {
+    let mut settings = config.clone();
+    settings.expansions.clear();
+    settings.globals.clear();
+    settings.snippets.clear();
+    for profile in settings.profiles.values_mut() {
+        profile.expansions.clear();
+        profile.globals.clear();
+        profile.snippets.clear();
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reload_from_path, ChangeCounts, ConfigDiff, ReloadReport};
+    use crate::config::AppConfig;
+    use crate::core::engine::Engine;
+    use crate::io::output::NullSink;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    fn temp_config(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "slykey-test-reload-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::create_dir_all(&dir).expect("create temp config dir");
+        let path = dir.join("slykey.yaml");
+        std::fs::write(&path, contents).expect("write temp config");
+        path
+    }
+
+    fn engine_with(raw: &str) -> Mutex<Engine> {
+        let config: AppConfig = serde_yaml::from_str(raw).expect("config should parse");
+        Mutex::new(Engine::new(config))
+    }
+
+    fn triggers(engine: &Mutex<Engine>) -> Vec<String> {
+        engine
+            .lock()
+            .expect("engine mutex")
+            .config()
+            .expansions
+            .iter()
+            .map(|rule| rule.trigger.clone())
+            .collect()
+    }
+
+    #[test]
+    fn swaps_in_the_new_config() {
+        let engine = engine_with("expansions:\n  - trigger: ';old'\n    expansion: old\n");
+        let path = temp_config(
+            "valid",
+            "expansions:\n  - trigger: ';new'\n    expansion: new\n",
+        );
+
+        let paths = reload_from_path(&path, &engine).expect("reload should succeed");
+
+        assert_eq!(paths, vec![path.clone()]);
+        assert_eq!(triggers(&engine), vec![";new"]);
+        let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+    }
+
+    #[test]
+    fn base_configs_count_an_edited_profile_rule_once() {
+        let engine = engine_with(
+            "default_profile: work\nexpansions:\n  - trigger: ';a'\n    expansion: a\nprofiles:\n  work:\n    expansions:\n      - trigger: ';w'\n        expansion: old\n",
+        );
+        let path = temp_config(
+            "profile-edit",
+            "default_profile: work\nexpansions:\n  - trigger: ';a'\n    expansion: a\nprofiles:\n  work:\n    expansions:\n      - trigger: ';w'\n        expansion: new\n",
+        );
+        let previous = engine.lock().expect("engine mutex").base_config().clone();
+
+        reload_from_path(&path, &engine).expect("reload should succeed");
+
+        let diff = ConfigDiff::between(
+            &previous,
+            engine.lock().expect("engine mutex").base_config(),
+        );
+        assert_eq!(diff.summary(), "rules: 1 changed");
+        let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+    }
+
+    #[test]
+    fn report_lists_lint_and_capability_warnings() {
+        let engine = engine_with("expansions:\n  - trigger: ';old'\n    expansion: old\n");
+        engine
+            .lock()
+            .expect("engine mutex")
+            .set_output(Arc::new(NullSink));
+        let path = temp_config(
+            "warnings",
+            "prefer_longest: false\nexpansions:\n  - trigger: ';a'\n    expansion: a\n  - trigger: ';ab'\n    expansion: ab\n  - trigger: ';p'\n    expansion: pasted\n    inject_mode: clipboard\n",
+        );
+        let previous = engine.lock().expect("engine mutex").base_config().clone();
+
+        reload_from_path(&path, &engine).expect("reload should succeed");
+
+        let report = ReloadReport::new(&previous, &engine.lock().expect("engine mutex"));
+        assert_eq!(
+            report.describe(),
+            "rules: 3 added, 1 removed; settings changed\n\
+             warning: trigger ';ab' never fires: in immediate mode typing it completes ';a' first, \
+             which expands before ';ab' is finished\n\
+             warning: trigger ';p' uses inject_mode: clipboard, but the output backend cannot \
+             paste, so it is typed instead"
+        );
+        let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+    }
+
+    #[test]
+    fn keeps_previous_config_when_the_file_is_invalid() {
+        let engine = engine_with("expansions:\n  - trigger: ';old'\n    expansion: old\n");
+        let unparsable = temp_config("unparsable", "expansions: [");
+        let invalid = temp_config("invalid", "expansions: []\n");
+
+        assert!(reload_from_path(&unparsable, &engine).is_err());
+        assert!(reload_from_path(&invalid, &engine).is_err());
+
+        assert_eq!(triggers(&engine), vec![";old"]);
+        for path in [unparsable, invalid] {
+            let _ = std::fs::remove_dir_all(path.parent().expect("config dir"));
+        }
+    }
+
+    #[test]
+    fn diff_counts_added_removed_and_changed_entries() {
+        let old: AppConfig = serde_yaml::from_str(
+            r#"
+expansions:
+  - trigger: ";sig"
+    expansion: "Thanks"
+  - trigger: ";addr"
+    expansion: "1 Main St"
+  - trigger: ";old"
+    expansion: "gone"
+globals:
+  NAME: Tyler
+snippets:
+  - title: Email
+    content: tyler@example.com
+profiles:
+  work:
+    expansions:
+      - trigger: ";sig"
+        expansion: "Regards"
+"#,
+        )
+        .expect("old config");
+        let new: AppConfig = serde_yaml::from_str(
+            r#"
+expansions:
+  - trigger: ";sig"
+    expansion: "Thanks!"
+  - trigger: ";addr"
+    expansion: "1 Main St"
+  - trigger: ";new"
+    expansion: "fresh"
+  - trigger: ";new"
+    expansion: "fresh in slack"
+    apps: ["Slack"]
+globals:
+  NAME: Tyler
+  TEAM: Platform
+snippets:
+  - title: Email
+    content: tyler@example.com
+    group: Work
+profiles:
+  work:
+    expansions:
+      - trigger: ";sig"
+        expansion: "Regards"
+"#,
+        )
+        .expect("new config");
+
+        let diff = ConfigDiff::between(&old, &new);
+
+        assert_eq!(
+            diff.rules,
+            ChangeCounts {
+                added: 2,
+                removed: 1,
+                changed: 1
+            }
+        );
+        assert_eq!(
+            diff.globals,
+            ChangeCounts {
+                added: 1,
+                ..ChangeCounts::default()
+            }
+        );
+        assert_eq!(
+            diff.snippets,
+            ChangeCounts {
+                added: 1,
+                removed: 1,
+                changed: 0
+            }
+        );
+        assert!(!diff.settings_changed);
+        assert_eq!(
+            diff.summary(),
+            "rules: 2 added, 1 removed, 1 changed; globals: 1 added; snippets: 1 added, 1 removed"
+        );
+
+        let mut strict = old.clone();
+        strict.prefer_longest = !strict.prefer_longest;
+        assert_eq!(
+            ConfigDiff::between(&old, &strict).summary(),
+            "settings changed"
+        );
+        assert!(ConfigDiff::between(&old, &old).is_empty());
+        assert_eq!(ConfigDiff::between(&old, &old).summary(), "no changes");
+    }
+}
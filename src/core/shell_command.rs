@@ -0,0 +1,1090 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+/// How often a command with a timeout is checked for having exited.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Settings from the extended `{{CMD:options:command}}` form.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CommandOptions {
+    /// Kill the command (and everything it started) after this long.
+    pub timeout: Option<Duration>,
+    /// Reuse the output of an earlier run for this long; see [`CommandCache`].
+    pub cache: Option<Duration>,
+    /// Working directory; a leading `~` is the home directory.
+    pub cwd: Option<String>,
+    pub trim: TrimMode,
+    pub shell: CommandShell,
+    /// Kill the command once it prints more than this many bytes. Set from
+    /// `expansion.max_output_bytes`, not from the macro.
+    pub output_limit: Option<usize>,
+}
+
+/// What is stripped from the command's output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TrimMode {
+    None,
+    /// Trailing newlines only.
+    #[default]
+    End,
+    /// Whitespace at both ends.
+    All,
+}
+
+/// What runs the command text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum CommandShell {
+    #[default]
+    Sh,
+    Bash,
+    /// Split into words (honoring quotes) and run directly.
+    None,
+}
+
+/// Splits `options:command` into its parts. The text is only read as
+/// options when everything before the first `:` is a comma-separated list of
+/// known `key=value` pairs, so plain commands containing `:`, `=` or `,`
+/// keep working unchanged.
+pub fn split_command_options(value: &str) -> Result<(CommandOptions, &str)> {
+    let Some((prefix, command)) = value.split_once(':') else {
+        return Ok((CommandOptions::default(), value));
+    };
+    let pairs: Option<Vec<(&str, &str)>> = prefix
+        .split(',')
+        .map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = key.trim();
+            is_option_key(key).then_some((key, value.trim()))
+        })
+        .collect();
+    let Some(pairs) = pairs else {
+        return Ok((CommandOptions::default(), value));
+    };
+
+    let mut options = CommandOptions::default();
+    for (key, value) in pairs {
+        match key {
+            "timeout" => options.timeout = Some(parse_duration(key, value)?),
+            "cache" => options.cache = Some(parse_duration(key, value)?),
+            "cwd" => {
+                if value.is_empty() {
+                    bail!("CMD option cwd cannot be empty");
+                }
+                options.cwd = Some(value.to_string());
+            }
+            "trim" => {
+                options.trim = match value {
+                    "none" => TrimMode::None,
+                    "end" => TrimMode::End,
+                    "all" => TrimMode::All,
+                    other => bail!("invalid CMD option trim={other} (expected none, end or all)"),
+                }
+            }
+            "shell" => {
+                options.shell = match value {
+                    "sh" => CommandShell::Sh,
+                    "bash" => CommandShell::Bash,
+                    "none" => CommandShell::None,
+                    other => bail!("invalid CMD option shell={other} (expected sh, bash or none)"),
+                }
+            }
+            _ => unreachable!("option keys are checked by is_option_key"),
+        }
+    }
+    Ok((options, command))
+}
+
+/// Runs the commands of `{{CMD:...}}` macros. Swapped out to sandbox them
+/// ([`DenyAllRunner`]) or to test without spawning processes ([`MockRunner`]).
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, command: &str, options: &CommandOptions) -> Result<CommandOutput>;
+}
+
+/// What a finished command left behind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// `None` when the command was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    /// A successful run that printed `stdout`.
+    pub fn success(stdout: impl Into<String>) -> Self {
+        Self {
+            exit_code: Some(0),
+            stdout: stdout.into(),
+            stderr: String::new(),
+        }
+    }
+
+    /// Stdout trimmed as `trim` says. A non-zero exit is an error carrying
+    /// stderr.
+    pub fn text(&self, trim: TrimMode) -> Result<String> {
+        if self.exit_code != Some(0) {
+            bail!(
+                "CMD macro command failed (status: {}): {}",
+                self.exit_code.map_or_else(
+                    || "terminated by signal".to_string(),
+                    |code| code.to_string()
+                ),
+                self.stderr.trim()
+            );
+        }
+        Ok(match trim {
+            TrimMode::None => self.stdout.clone(),
+            TrimMode::End => self.stdout.trim_end_matches(['\r', '\n']).to_string(),
+            TrimMode::All => self.stdout.trim().to_string(),
+        })
+    }
+}
+
+/// Runs commands as child processes, through the shell `options.shell` names.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellRunner;
+
+/// Refuses every command. Used when `security.allow_commands` is false and
+/// wherever config content is rendered without being typed (validation, tray
+/// menus).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DenyAllRunner;
+
+/// Answers commands from canned outputs and records what was asked, so tests
+/// can exercise CMD macros without spawning processes. Commands without an
+/// output fail.
+#[derive(Debug, Default)]
+pub struct MockRunner {
+    outputs: HashMap<String, CommandOutput>,
+    calls: Mutex<Vec<String>>,
+}
+
+impl MockRunner {
+    pub fn with_output(mut self, command: &str, output: CommandOutput) -> Self {
+        self.outputs.insert(command.to_string(), output);
+        self
+    }
+
+    /// Commands run so far, oldest first.
+    pub fn calls(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .expect("mock runner mutex poisoned")
+            .clone()
+    }
+}
+
+impl CommandRunner for ShellRunner {
+    fn run(&self, command: &str, options: &CommandOptions) -> Result<CommandOutput> {
+        let mut process = match options.shell {
+            CommandShell::Sh | CommandShell::Bash => {
+                let shell = if options.shell == CommandShell::Bash {
+                    "bash"
+                } else {
+                    "sh"
+                };
+                let mut process = Command::new(shell);
+                process.arg("-c").arg(command);
+                process
+            }
+            CommandShell::None => {
+                let words = split_words(command)?;
+                // Without a shell, leading `NAME=value` words are still
+                // assignments, so the program run is the one allowlisted.
+                let (assignments, words) = split_env_assignments(&words);
+                let Some((program, args)) = words.split_first() else {
+                    bail!("CMD macro command is empty");
+                };
+                let mut process = Command::new(program);
+                process.args(args).envs(assignments);
+                process
+            }
+        };
+        if let Some(cwd) = &options.cwd {
+            process.current_dir(expand_home(cwd));
+        }
+        // Its own process group, so a timeout can kill whatever the command
+        // started along with it.
+        process
+            .process_group(0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = process
+            .spawn()
+            .with_context(|| format!("failed to start CMD macro command '{command}'"))?;
+        let group = child.id() as libc::pid_t;
+        let stdout = read_in_background(child.stdout.take(), options.output_limit, group);
+        let stderr = read_in_background(child.stderr.take(), options.output_limit, group);
+        let Some(status) = wait_with_timeout(&mut child, options.timeout)? else {
+            bail!(
+                "CMD macro command '{command}' timed out after {:?} and was killed",
+                options.timeout.unwrap_or_default()
+            );
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if let Some(limit) = options.output_limit.filter(|limit| stdout.len() > *limit) {
+            bail!(
+                "CMD macro command '{command}' printed more than {limit} bytes \
+                 (expansion.max_output_bytes) and was killed"
+            );
+        }
+
+        Ok(CommandOutput {
+            exit_code: status.code(),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+}
+
+impl CommandRunner for DenyAllRunner {
+    fn run(&self, command: &str, _options: &CommandOptions) -> Result<CommandOutput> {
+        bail!("CMD macros are disabled by config (security.allow_commands); refusing to run '{command}'")
+    }
+}
+
+impl CommandRunner for MockRunner {
+    fn run(&self, command: &str, _options: &CommandOptions) -> Result<CommandOutput> {
+        self.calls
+            .lock()
+            .expect("mock runner mutex poisoned")
+            .push(command.to_string());
+        match self.outputs.get(command) {
+            Some(output) => Ok(output.clone()),
+            None => bail!("no mock output for CMD macro command '{command}'"),
+        }
+    }
+}
+
+/// A clock for [`CommandCache`] and [`CommandConfirmation`], replaceable in tests.
+pub type Clock = Box<dyn Fn() -> Instant + Send + Sync>;
+
+/// Outputs of `{{CMD:cache=...:...}}` macros, keyed by the rendered command
+/// and its options, so expensive commands referenced by several triggers run
+/// once per TTL. Failed runs are not cached.
+pub struct CommandCache {
+    entries: Mutex<HashMap<(CommandOptions, String), CachedOutput>>,
+    clock: Clock,
+}
+
+struct CachedOutput {
+    output: String,
+    expires_at: Instant,
+}
+
+impl std::fmt::Debug for CommandCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandCache")
+            .field("entries", &self.lock().len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for CommandCache {
+    fn default() -> Self {
+        Self::with_clock(Box::new(Instant::now))
+    }
+}
+
+impl CommandCache {
+    pub fn with_clock(clock: Clock) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// The cached output of `command` if it has not expired, otherwise the
+    /// result of `run`, which is remembered for `options.cache` on success.
+    /// Without a `cache` option `run` is always called.
+    pub fn get_or_run(
+        &self,
+        command: &str,
+        options: &CommandOptions,
+        run: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let Some(ttl) = options.cache else {
+            return run();
+        };
+        let key = (options.clone(), command.to_string());
+        let now = (self.clock)();
+        if let Some(cached) = self.lock().get(&key) {
+            if now < cached.expires_at {
+                return Ok(cached.output.clone());
+            }
+        }
+
+        // Not held while the command runs, so other macros are not blocked.
+        let output = run()?;
+        self.lock().insert(
+            key,
+            CachedOutput {
+                output: output.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(output)
+    }
+
+    /// Forgets every cached output, e.g. after a config reload.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(CommandOptions, String), CachedOutput>> {
+        self.entries.lock().expect("command cache mutex poisoned")
+    }
+}
+
+/// Returned instead of an expansion whose commands still need confirming.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfirmationRequired {
+    /// Every command the expansion asked for, in order.
+    pub commands: Vec<String>,
+    /// How long the user has to type the trigger again.
+    pub window: Duration,
+}
+
+impl std::fmt::Display for ConfirmationRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let commands: Vec<String> = self
+            .commands
+            .iter()
+            .map(|command| format!("'{command}'"))
+            .collect();
+        write!(
+            f,
+            "type the trigger again within {}s to run {}",
+            self.window.as_secs(),
+            commands.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ConfirmationRequired {}
+
+/// Enforces `security.command_allowlist` in front of another runner.
+pub struct GuardedRunner {
+    inner: Arc<dyn CommandRunner>,
+    allowlist: Vec<String>,
+}
+
+impl GuardedRunner {
+    /// Only runs commands whose program is in `allowlist`.
+    pub fn new(inner: Arc<dyn CommandRunner>, allowlist: Vec<String>) -> Self {
+        Self { inner, allowlist }
+    }
+}
+
+impl std::fmt::Debug for GuardedRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GuardedRunner")
+            .field("allowlist", &self.allowlist)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandRunner for GuardedRunner {
+    fn run(&self, command: &str, options: &CommandOptions) -> Result<CommandOutput> {
+        check_allowlist(command, options, &self.allowlist)?;
+        self.inner.run(command, options)
+    }
+}
+
+/// `security.confirm_commands`: the commands an expansion asks for only run
+/// once it is typed again within `window`. The first attempt arms all of
+/// them at once, keyed by its trigger, so an expansion with several
+/// commands needs a single confirmation.
+pub struct CommandConfirmation {
+    window: Duration,
+    armed: Option<ArmedCommands>,
+    clock: Clock,
+}
+
+struct ArmedCommands {
+    key: String,
+    commands: Vec<String>,
+    deadline: Instant,
+}
+
+impl CommandConfirmation {
+    pub fn new(window: Duration) -> Self {
+        Self::with_clock(window, Box::new(Instant::now))
+    }
+
+    pub fn with_clock(window: Duration, clock: Clock) -> Self {
+        Self {
+            window,
+            armed: None,
+            clock,
+        }
+    }
+
+    /// The commands armed for `key` if the window has not passed; either
+    /// way nothing stays armed.
+    pub fn take_armed(&mut self, key: &str) -> Vec<String> {
+        let now = (self.clock)();
+        match self.armed.take() {
+            Some(armed) if armed.key == key && now <= armed.deadline => armed.commands,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Arms `commands` for `key`, returning the error that asks for them.
+    pub fn arm(&mut self, key: &str, commands: Vec<String>) -> ConfirmationRequired {
+        self.armed = Some(ArmedCommands {
+            key: key.to_string(),
+            commands: commands.clone(),
+            deadline: (self.clock)() + self.window,
+        });
+        ConfirmationRequired {
+            commands,
+            window: self.window,
+        }
+    }
+}
+
+impl std::fmt::Debug for CommandConfirmation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandConfirmation")
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Runs the `confirmed` commands through `inner`. Any other command is
+/// noted in [`unconfirmed`](Self::unconfirmed) and stands in as empty
+/// output, so one render finds every command an expansion needs.
+pub struct ConfirmingRunner<'a> {
+    inner: &'a dyn CommandRunner,
+    confirmed: Vec<String>,
+    unconfirmed: Mutex<Vec<String>>,
+}
+
+impl<'a> ConfirmingRunner<'a> {
+    pub fn new(inner: &'a dyn CommandRunner, confirmed: Vec<String>) -> Self {
+        Self {
+            inner,
+            confirmed,
+            unconfirmed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The commands asked for that were not confirmed, in order.
+    pub fn unconfirmed(self) -> Vec<String> {
+        self.unconfirmed
+            .into_inner()
+            .expect("runner mutex poisoned")
+    }
+}
+
+impl std::fmt::Debug for ConfirmingRunner<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConfirmingRunner")
+            .field("confirmed", &self.confirmed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommandRunner for ConfirmingRunner<'_> {
+    fn run(&self, command: &str, options: &CommandOptions) -> Result<CommandOutput> {
+        if self.confirmed.iter().any(|confirmed| confirmed == command) {
+            return self.inner.run(command, options);
+        }
+        let mut unconfirmed = self.unconfirmed.lock().expect("runner mutex poisoned");
+        if !unconfirmed.iter().any(|pending| pending == command) {
+            unconfirmed.push(command.to_string());
+        }
+        Ok(CommandOutput::success(""))
+    }
+}
+
+/// Environment variables a command may set in front of an allowlisted
+/// program; anything else (`PATH`, `LD_PRELOAD`, ...) could change what runs.
+const ALLOWLISTED_ENV_NAMES: [&str; 3] = ["LANG", "LANGUAGE", "TZ"];
+
+/// Fails unless `command`'s program is in `allowlist` and it sets no variables
+/// besides the locale and time zone. Commands run through a shell must be a
+/// single simple command: a `;`, `&&`, pipe, redirect or command substitution
+/// could run programs the list does not name.
+pub fn check_allowlist(
+    command: &str,
+    options: &CommandOptions,
+    allowlist: &[String],
+) -> Result<()> {
+    let words = split_words(command)?;
+    let (assignments, words) = split_env_assignments(&words);
+    let Some(program) = words.first() else {
+        bail!("CMD macro command is empty");
+    };
+    if !allowlist.contains(program) {
+        bail!("CMD macro command '{program}' is not in security.command_allowlist ({command})");
+    }
+    if let Some((name, _)) = assignments
+        .iter()
+        .find(|(name, _)| !ALLOWLISTED_ENV_NAMES.contains(name) && !name.starts_with("LC_"))
+    {
+        bail!(
+            "CMD macro command '{command}' sets {name}, which security.command_allowlist does not allow"
+        );
+    }
+    if options.shell != CommandShell::None {
+        if let Some(operator) = shell_operator(command) {
+            bail!(
+                "CMD macro command '{command}' uses '{operator}', which security.command_allowlist does not allow"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The program `command` runs: its first word after shell-style splitting,
+/// skipping `NAME=value` environment assignments.
+pub fn command_program(command: &str) -> Result<Option<String>> {
+    let words = split_words(command)?;
+    let (_, words) = split_env_assignments(&words);
+    Ok(words.first().cloned())
+}
+
+/// Splits the leading `NAME=value` words off `words`, returning them as
+/// name/value pairs along with the words that follow.
+fn split_env_assignments(words: &[String]) -> (Vec<(&str, &str)>, &[String]) {
+    let count = words
+        .iter()
+        .take_while(|word| is_env_assignment(word))
+        .count();
+    let assignments = words[..count]
+        .iter()
+        .filter_map(|word| word.split_once('='))
+        .collect();
+    (assignments, &words[count..])
+}
+
+fn is_env_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The first shell operator in `command` outside quotes, if any.
+fn shell_operator(command: &str) -> Option<&'static str> {
+    let mut chars = command.chars().peekable();
+    let mut in_double = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' if !in_double => {
+                for c in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                }
+            }
+            '"' => in_double = !in_double,
+            '`' => return Some("`"),
+            '$' if chars.peek() == Some(&'(') => return Some("$("),
+            ';' if !in_double => return Some(";"),
+            '&' if !in_double => return Some("&"),
+            '|' if !in_double => return Some("|"),
+            '<' if !in_double => return Some("<"),
+            '>' if !in_double => return Some(">"),
+            '\n' if !in_double => return Some("newline"),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_option_key(key: &str) -> bool {
+    matches!(key, "timeout" | "cache" | "cwd" | "trim" | "shell")
+}
+
+/// `500ms`, `2s`, `1m`, or a bare number of seconds.
+fn parse_duration(key: &str, value: &str) -> Result<Duration> {
+    let (number, unit_ms) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1_000)
+    } else if let Some(m) = value.strip_suffix('m') {
+        (m, 60_000)
+    } else {
+        (value, 1_000)
+    };
+    let amount: u64 = number
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid CMD option {key}={value} (e.g. 500ms, 2s, 1m)"))?;
+    if amount == 0 {
+        bail!("invalid CMD option {key}={value}: must be greater than zero");
+    }
+    Ok(Duration::from_millis(amount.saturating_mul(unit_ms)))
+}
+
+/// Splits `input` into words like a shell would, without expanding
+/// anything: single quotes are literal, double quotes allow `\"` and `\\`,
+/// and a backslash outside quotes escapes the next character.
+fn split_words(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = input.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated ' in CMD macro command"),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated \" in CMD macro command"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated \" in CMD macro command"),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    let home = dirs::home_dir();
+    match (path, path.strip_prefix("~/"), home) {
+        ("~", _, Some(home)) => home,
+        (_, Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Reads `pipe` to the end, or until it has more than `limit` bytes; then
+/// the command's process `group` is killed so it stops writing.
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    limit: Option<usize>,
+    group: libc::pid_t,
+) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(pipe) = pipe {
+            let cap = limit.map_or(u64::MAX, |limit| limit as u64 + 1);
+            let _ = pipe.take(cap).read_to_end(&mut buf);
+            if limit.is_some_and(|limit| buf.len() > limit) {
+                // SAFETY: killpg only sends a signal; it has no memory-safety preconditions.
+                unsafe { libc::killpg(group, libc::SIGKILL) };
+            }
+        }
+        buf
+    })
+}
+
+/// The child's exit status, or `None` when it ran past `timeout` and its
+/// process group was killed.
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(child.wait()?));
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill_process_group(child);
+            return Ok(None);
+        }
+        std::thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+fn kill_process_group(child: &mut Child) {
+    // The child leads its own group (see `process_group(0)`), so its pid is
+    // the group id.
+    let group = child.id() as libc::pid_t;
+    // SAFETY: killpg only sends a signal; it has no memory-safety preconditions.
+    if unsafe { libc::killpg(group, libc::SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{
+        check_allowlist, command_program, split_command_options, split_words, CommandCache,
+        CommandConfirmation, CommandOptions, CommandOutput, CommandRunner, CommandShell,
+        ConfirmationRequired, ConfirmingRunner, GuardedRunner, MockRunner, ShellRunner, TrimMode,
+    };
+
+    fn run_command(command: &str, options: &CommandOptions) -> String {
+        ShellRunner
+            .run(command, options)
+            .and_then(|output| output.text(options.trim))
+            .expect("command should run")
+    }
+
+    #[test]
+    fn parses_options_before_the_command() {
+        let (options, command) =
+            split_command_options("timeout=2s, cwd=~/notes,trim=all,shell=bash:git log -1")
+                .expect("options should parse");
+
+        assert_eq!(
+            options,
+            CommandOptions {
+                timeout: Some(Duration::from_secs(2)),
+                cache: None,
+                cwd: Some("~/notes".to_string()),
+                trim: TrimMode::All,
+                shell: CommandShell::Bash,
+                output_limit: None,
+            }
+        );
+        assert_eq!(command, "git log -1");
+
+        let (options, _) =
+            split_command_options("timeout=250ms,cache=300:true").expect("options should parse");
+        assert_eq!(options.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(options.cache, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn commands_without_options_are_left_alone() {
+        for command in [
+            "date +%H:%M",
+            "FOO=1 env | grep FOO",
+            "printf 'a,b=c:d'",
+            "echo x=1,y=2:z",
+            "timeout 2 curl wttr.in",
+        ] {
+            let (options, rest) = split_command_options(command).expect("plain command");
+            assert_eq!(options, CommandOptions::default(), "{command}");
+            assert_eq!(rest, command);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_option_values() {
+        for (value, expected) in [
+            ("timeout=soon:true", "invalid CMD option timeout=soon"),
+            ("timeout=0s:true", "must be greater than zero"),
+            ("trim=both:true", "invalid CMD option trim=both"),
+            ("shell=zsh:true", "invalid CMD option shell=zsh"),
+            ("cwd=:true", "cwd cannot be empty"),
+        ] {
+            let err = split_command_options(value).expect_err(value);
+            assert!(err.to_string().contains(expected), "{value}: {err}");
+        }
+    }
+
+    #[test]
+    fn splits_words_like_a_shell() {
+        let words =
+            split_words(r#"printf '%s|' "a b" c\ d 'e"f' "g\"h""#).expect("words should split");
+        assert_eq!(words, vec!["printf", "%s|", "a b", "c d", "e\"f", "g\"h"]);
+        assert!(split_words("echo 'open").is_err());
+    }
+
+    #[test]
+    fn commands_printing_past_the_output_limit_are_killed() {
+        let options = CommandOptions {
+            output_limit: Some(1000),
+            ..CommandOptions::default()
+        };
+
+        let started = Instant::now();
+        let err = ShellRunner
+            .run("yes", &options)
+            .expect_err("yes never stops printing");
+
+        assert!(
+            err.to_string()
+                .contains("printed more than 1000 bytes (expansion.max_output_bytes)"),
+            "{err}"
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(run_command("printf abc", &options), "abc");
+    }
+
+    #[test]
+    fn runs_with_trim_shell_and_cwd_options() {
+        let raw = CommandOptions {
+            trim: TrimMode::None,
+            ..CommandOptions::default()
+        };
+        assert_eq!(run_command("printf ' hi \\n'", &raw), " hi \n");
+
+        let all = CommandOptions {
+            trim: TrimMode::All,
+            ..CommandOptions::default()
+        };
+        assert_eq!(run_command("printf ' hi \\n'", &all), "hi");
+
+        let direct = CommandOptions {
+            shell: CommandShell::None,
+            cwd: Some("/".to_string()),
+            ..CommandOptions::default()
+        };
+        assert_eq!(run_command("sh -c 'pwd; echo $0' 'a;b'", &direct), "/\na;b");
+    }
+
+    #[test]
+    fn cached_output_is_reused_until_the_ttl_passes() {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock_now = Arc::clone(&now);
+        let cache = CommandCache::with_clock(Box::new(move || {
+            *clock_now.lock().expect("clock mutex poisoned")
+        }));
+        let runs = AtomicUsize::new(0);
+        let run = || {
+            let count = runs.fetch_add(1, Ordering::Relaxed) + 1;
+            Ok(format!("run {count}"))
+        };
+        let options = CommandOptions {
+            cache: Some(Duration::from_secs(300)),
+            ..CommandOptions::default()
+        };
+
+        let render = |command: &str, options: &CommandOptions| {
+            cache
+                .get_or_run(command, options, run)
+                .expect("run should succeed")
+        };
+        assert_eq!(render("curl wttr.in", &options), "run 1");
+        *now.lock().expect("clock mutex poisoned") += Duration::from_secs(299);
+        assert_eq!(render("curl wttr.in", &options), "run 1");
+        // Another command, or the same one without caching, runs on its own.
+        assert_eq!(render("date", &options), "run 2");
+        assert_eq!(render("curl wttr.in", &CommandOptions::default()), "run 3");
+
+        *now.lock().expect("clock mutex poisoned") += Duration::from_secs(1);
+        assert_eq!(render("curl wttr.in", &options), "run 4");
+
+        cache.clear();
+        assert_eq!(render("curl wttr.in", &options), "run 5");
+    }
+
+    #[test]
+    fn failed_runs_are_not_cached() {
+        let cache = CommandCache::default();
+        let options = CommandOptions {
+            cache: Some(Duration::from_secs(60)),
+            ..CommandOptions::default()
+        };
+
+        cache
+            .get_or_run("flaky", &options, || anyhow::bail!("offline"))
+            .expect_err("the failure should be returned");
+        let output = cache
+            .get_or_run("flaky", &options, || Ok("online".to_string()))
+            .expect("the retry should run");
+        assert_eq!(output, "online");
+    }
+
+    #[test]
+    fn command_program_skips_quotes_and_env_assignments() {
+        for (command, expected) in [
+            ("date +%H:%M", Some("date")),
+            ("FOO=1 BAR='a b' git status", Some("git")),
+            ("'pass' show email", Some("pass")),
+            ("\"/usr/bin/git\" log", Some("/usr/bin/git")),
+            ("  ", None),
+            ("FOO=1", None),
+            ("1X=2 env", Some("1X=2")),
+        ] {
+            assert_eq!(
+                command_program(command)
+                    .expect("command should split")
+                    .as_deref(),
+                expected,
+                "{command}"
+            );
+        }
+    }
+
+    #[test]
+    fn allowlist_blocks_other_programs_and_shell_operators() {
+        let allowlist = vec!["date".to_string(), "git".to_string()];
+        let shell = CommandOptions::default();
+        let direct = CommandOptions {
+            shell: CommandShell::None,
+            ..CommandOptions::default()
+        };
+
+        for command in [
+            "date +%H:%M",
+            "LC_ALL=C TZ=UTC date",
+            "git log --format='%h|%s'",
+        ] {
+            check_allowlist(command, &shell, &allowlist).expect(command);
+        }
+        check_allowlist("git log ; x", &direct, &allowlist).expect("no shell, no operators");
+
+        for (command, expected) in [
+            (
+                "curl wttr.in",
+                "'curl' is not in security.command_allowlist",
+            ),
+            ("/bin/date", "'/bin/date' is not in"),
+            ("date; rm -rf ~", "'date;' is not in"),
+            ("date && rm -rf ~", "uses '&'"),
+            ("git log | sh", "uses '|'"),
+            ("git log > /etc/x", "uses '>'"),
+            ("git \"$(rm -rf ~)\"", "uses '$('"),
+        ] {
+            let err = check_allowlist(command, &shell, &allowlist).expect_err(command);
+            assert!(err.to_string().contains(expected), "{command}: {err}");
+        }
+
+        for (command, expected) in [
+            ("LD_PRELOAD=/tmp/x.so date", "sets LD_PRELOAD"),
+            ("LANG=C PATH=/tmp/evil date", "sets PATH"),
+        ] {
+            for options in [&shell, &direct] {
+                let err = check_allowlist(command, options, &allowlist).expect_err(command);
+                assert!(err.to_string().contains(expected), "{command}: {err}");
+            }
+        }
+    }
+
+    #[test]
+    fn env_assignments_without_a_shell_set_variables_for_the_program() {
+        let direct = CommandOptions {
+            shell: CommandShell::None,
+            ..CommandOptions::default()
+        };
+        assert_eq!(
+            run_command(
+                "TZ=UTC GREETING='hi there' sh -c 'echo $TZ $GREETING'",
+                &direct
+            ),
+            "UTC hi there"
+        );
+    }
+
+    #[test]
+    fn guarded_runner_runs_allowlisted_commands_only() {
+        let inner =
+            Arc::new(MockRunner::default().with_output("date", CommandOutput::success("today")));
+        let runner = GuardedRunner::new(inner.clone(), vec!["date".to_string()]);
+        let options = CommandOptions::default();
+
+        assert_eq!(
+            runner.run("date", &options).expect("allowed").stdout,
+            "today"
+        );
+        let err = runner.run("curl x", &options).expect_err("not allowlisted");
+        assert!(err.to_string().contains("'curl' is not in"), "{err}");
+        assert_eq!(inner.calls(), vec!["date"]);
+    }
+
+    #[test]
+    fn confirmation_arms_every_command_of_an_expansion_at_once() {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock_now = Arc::clone(&now);
+        let mut confirmation = CommandConfirmation::with_clock(
+            Duration::from_secs(5),
+            Box::new(move || *clock_now.lock().expect("clock mutex poisoned")),
+        );
+        let inner = MockRunner::default()
+            .with_output("date", CommandOutput::success("today"))
+            .with_output("git log", CommandOutput::success("abc"));
+        let options = CommandOptions::default();
+
+        let first = ConfirmingRunner::new(&inner, confirmation.take_armed(";d"));
+        assert_eq!(first.run("date", &options).expect("stand-in").stdout, "");
+        assert_eq!(first.run("git log", &options).expect("stand-in").stdout, "");
+        assert_eq!(first.run("date", &options).expect("stand-in").stdout, "");
+        let err = confirmation.arm(";d", first.unconfirmed());
+        assert_eq!(
+            err,
+            ConfirmationRequired {
+                commands: vec!["date".to_string(), "git log".to_string()],
+                window: Duration::from_secs(5),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "type the trigger again within 5s to run 'date', 'git log'"
+        );
+        assert!(inner.calls().is_empty());
+
+        *now.lock().expect("clock mutex poisoned") += Duration::from_secs(4);
+        let second = ConfirmingRunner::new(&inner, confirmation.take_armed(";d"));
+        assert_eq!(
+            second.run("date", &options).expect("confirmed").stdout,
+            "today"
+        );
+        assert_eq!(
+            second.run("git log", &options).expect("confirmed").stdout,
+            "abc"
+        );
+        assert!(second.unconfirmed().is_empty());
+        assert_eq!(inner.calls(), vec!["date", "git log"]);
+
+        // Used up, tied to its trigger and gone once the window has passed.
+        assert!(confirmation.take_armed(";d").is_empty());
+        confirmation.arm(";d", vec!["date".to_string()]);
+        assert!(confirmation.take_armed(";other").is_empty());
+        confirmation.arm(";d", vec!["date".to_string()]);
+        *now.lock().expect("clock mutex poisoned") += Duration::from_secs(6);
+        assert!(confirmation.take_armed(";d").is_empty());
+    }
+}
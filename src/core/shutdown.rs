@@ -0,0 +1,104 @@
+use std::sync::{Condvar, Mutex};
+
+type Cleanup = Box<dyn FnOnce() + Send>;
+
+/// Coordinates a clean exit requested from any thread (signal handler,
+/// control socket, tray menu): [`request`](Self::request) wakes the thread
+/// blocked in [`wait`](Self::wait), which then runs the registered cleanups.
+#[derive(Default)]
+pub struct Shutdown {
+    requested: Mutex<Option<String>>,
+    wake: Condvar,
+    cleanups: Mutex<Vec<Cleanup>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks for shutdown, giving `reason` for the log. Returns `false` if
+    /// shutdown was already requested, keeping the first reason.
+    pub fn request(&self, reason: &str) -> bool {
+        let mut requested = self.requested.lock().expect("shutdown mutex poisoned");
+        if requested.is_some() {
+            return false;
+        }
+        *requested = Some(reason.to_string());
+        self.wake.notify_all();
+        true
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested
+            .lock()
+            .expect("shutdown mutex poisoned")
+            .is_some()
+    }
+
+    /// Registers work to do on shutdown, such as flushing state or removing
+    /// the instance lock. Cleanups run in registration order.
+    pub fn on_shutdown(&self, cleanup: impl FnOnce() + Send + 'static) {
+        self.cleanups
+            .lock()
+            .expect("shutdown mutex poisoned")
+            .push(Box::new(cleanup));
+    }
+
+    /// Blocks until shutdown is requested and returns the reason.
+    pub fn wait(&self) -> String {
+        let requested = self.requested.lock().expect("shutdown mutex poisoned");
+        let requested = self
+            .wake
+            .wait_while(requested, |requested| requested.is_none())
+            .expect("shutdown mutex poisoned");
+        requested.clone().unwrap_or_default()
+    }
+
+    /// Runs the registered cleanups; each runs at most once, even if this is
+    /// called again.
+    pub fn run_cleanups(&self) {
+        let cleanups = std::mem::take(&mut *self.cleanups.lock().expect("shutdown mutex poisoned"));
+        for cleanup in cleanups {
+            cleanup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::Shutdown;
+
+    #[test]
+    fn request_wakes_waiter_and_cleanups_run_once_in_order() {
+        let shutdown = Arc::new(Shutdown::new());
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        for name in ["flush stats", "remove lock"] {
+            let ran = Arc::clone(&ran);
+            shutdown.on_shutdown(move || ran.lock().expect("mutex poisoned").push(name));
+        }
+        assert!(!shutdown.is_requested());
+
+        let requester = Arc::clone(&shutdown);
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            assert!(requester.request("SIGTERM"));
+        });
+        assert_eq!(shutdown.wait(), "SIGTERM");
+        handle.join().expect("requester thread");
+
+        assert!(shutdown.is_requested());
+        assert!(!shutdown.request("SIGINT"));
+        assert_eq!(shutdown.wait(), "SIGTERM");
+
+        shutdown.run_cleanups();
+        shutdown.run_cleanups();
+        assert_eq!(
+            *ran.lock().expect("mutex poisoned"),
+            vec!["flush stats", "remove lock"]
+        );
+    }
+}
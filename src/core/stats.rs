@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+const STATS_FORMAT_VERSION: u32 = 1;
+
+/// Per-trigger usage counters persisted to `$XDG_STATE_HOME/slykey/stats.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageStats {
+    pub version: u32,
+    #[serde(default)]
+    pub triggers: BTreeMap<String, TriggerStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TriggerStats {
+    pub count: u64,
+    /// Expansion length minus trigger length, summed over every firing.
+    pub chars_saved: i64,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self {
+            version: STATS_FORMAT_VERSION,
+            triggers: BTreeMap::new(),
+        }
+    }
+}
+
+impl UsageStats {
+    pub fn record(&mut self, trigger: &str, chars_saved: i64) {
+        let entry = self.triggers.entry(trigger.to_string()).or_default();
+        entry.count += 1;
+        entry.chars_saved += chars_saved;
+    }
+
+    pub fn totals(&self) -> TriggerStats {
+        self.triggers
+            .values()
+            .fold(TriggerStats::default(), |total, stats| TriggerStats {
+                count: total.count + stats.count,
+                chars_saved: total.chars_saved + stats.chars_saved,
+            })
+    }
+
+    /// Loads stats from `path`, falling back to empty stats when the file is
+    /// missing, unreadable, corrupt, or written by an unknown format version.
+    pub fn load(path: &Path) -> Self {
+        let raw = match fs::read_to_string(path) {
+            Ok(raw) => raw,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                warn!("failed to read stats file {}: {err}", path.display());
+                return Self::default();
+            }
+        };
+
+        match serde_json::from_str::<UsageStats>(&raw) {
+            Ok(stats) if stats.version == STATS_FORMAT_VERSION => stats,
+            Ok(stats) => {
+                warn!(
+                    "ignoring stats file {} with unsupported version {}",
+                    path.display(),
+                    stats.version
+                );
+                Self::default()
+            }
+            Err(err) => {
+                warn!("ignoring corrupt stats file {}: {err}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    /// Writes stats via a temp file + rename so readers never see a partial file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create stats directory: {}", parent.display())
+            })?;
+        }
+
+        let json = serde_json::to_string_pretty(self).context("failed to serialize stats")?;
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("failed to write stats file: {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to replace stats file: {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Expansions fired on the current day, starting over when the day changes.
+/// Kept in memory only, so it counts from daemon start.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DailyCount {
+    day: Option<NaiveDate>,
+    count: u64,
+}
+
+impl DailyCount {
+    pub fn record(&mut self, day: NaiveDate) {
+        if self.day != Some(day) {
+            self.day = Some(day);
+            self.count = 0;
+        }
+        self.count += 1;
+    }
+
+    /// The count for `day`; zero when nothing was recorded that day.
+    pub fn on(&self, day: NaiveDate) -> u64 {
+        if self.day == Some(day) {
+            self.count
+        } else {
+            0
+        }
+    }
+}
+
+pub fn default_stats_path() -> Option<PathBuf> {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(dirs::state_dir)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".local/state")))?;
+    Some(state_dir.join("slykey").join("stats.json"))
+}
+
+/// Renders stats as a table sorted by expansion count (descending), then trigger.
+pub fn format_stats_table(stats: &UsageStats) -> String {
+    let mut rows: Vec<(&String, &TriggerStats)> = stats.triggers.iter().collect();
+    rows.sort_by(|(a_trigger, a), (b_trigger, b)| {
+        b.count.cmp(&a.count).then_with(|| a_trigger.cmp(b_trigger))
+    });
+
+    let width = rows
+        .iter()
+        .map(|(trigger, _)| trigger.chars().count())
+        .chain(["TRIGGER".len(), "TOTAL".len()])
+        .max()
+        .unwrap_or(0);
+
+    let mut table = format!(
+        "{:<width$}  {:>8}  {:>11}\n",
+        "TRIGGER", "COUNT", "CHARS SAVED"
+    );
+    for (trigger, trigger_stats) in rows {
+        table.push_str(&format!(
+            "{:<width$}  {:>8}  {:>11}\n",
+            trigger, trigger_stats.count, trigger_stats.chars_saved
+        ));
+    }
+    let totals = stats.totals();
+    table.push_str(&format!(
+        "{:<width$}  {:>8}  {:>11}\n",
+        "TOTAL", totals.count, totals.chars_saved
+    ));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_stats_table, DailyCount, UsageStats};
+    use chrono::NaiveDate;
+    use std::path::PathBuf;
+
+    fn test_stats_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("slykey-test-stats-{}-{}", std::process::id(), name))
+            .join("stats.json")
+    }
+
+    #[test]
+    fn round_trips_stats_through_json_file() {
+        let path = test_stats_path("round-trip");
+        let mut stats = UsageStats::default();
+        stats.record(";sig", 12);
+        stats.record(";sig", 12);
+        stats.record("tg@", 18);
+
+        stats.save(&path).expect("save should succeed");
+        let loaded = UsageStats::load(&path);
+
+        assert_eq!(loaded, stats);
+        let _ = std::fs::remove_dir_all(path.parent().expect("stats dir"));
+    }
+
+    #[test]
+    fn tolerates_missing_corrupt_and_unknown_version_files() {
+        let path = test_stats_path("corrupt");
+        assert_eq!(UsageStats::load(&path), UsageStats::default());
+
+        std::fs::create_dir_all(path.parent().expect("stats dir")).expect("create dir");
+        std::fs::write(&path, "{ not json").expect("write corrupt file");
+        assert_eq!(UsageStats::load(&path), UsageStats::default());
+
+        std::fs::write(&path, r#"{"version": 99, "triggers": {}}"#).expect("write file");
+        assert_eq!(UsageStats::load(&path), UsageStats::default());
+
+        let _ = std::fs::remove_dir_all(path.parent().expect("stats dir"));
+    }
+
+    #[test]
+    fn formats_sorted_table_with_totals() {
+        let mut stats = UsageStats::default();
+        stats.record("tg@", 18);
+        stats.record(";sig", 12);
+        stats.record(";sig", 12);
+
+        let table = format_stats_table(&stats);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("TRIGGER"));
+        assert!(lines[1].starts_with(";sig"));
+        assert!(lines[1].ends_with("2           24"));
+        assert!(lines[2].starts_with("tg@"));
+        assert!(lines[3].starts_with("TOTAL"));
+        assert!(lines[3].ends_with("3           42"));
+    }
+
+    #[test]
+    fn daily_count_starts_over_each_day() {
+        let monday = NaiveDate::from_ymd_opt(2026, 10, 12).expect("date");
+        let tuesday = monday.succ_opt().expect("date");
+        let mut daily = DailyCount::default();
+        assert_eq!(daily.on(monday), 0);
+
+        daily.record(monday);
+        daily.record(monday);
+        assert_eq!(daily.on(monday), 2);
+        assert_eq!(daily.on(tuesday), 0);
+
+        daily.record(tuesday);
+        assert_eq!(daily.on(tuesday), 1);
+        assert_eq!(daily.on(monday), 0);
+    }
+}
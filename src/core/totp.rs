@@ -0,0 +1,222 @@
+use anyhow::{bail, Context, Result};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+/// Hash behind the HMAC, as in RFC 6238's `T0`-based variants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Settings from `{{TOTP:account:digits=8,period=60,algorithm=sha256}}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TotpParams {
+    pub digits: u32,
+    /// Seconds each code is valid for.
+    pub period: u64,
+    pub algorithm: TotpAlgorithm,
+}
+
+impl Default for TotpParams {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            period: 30,
+            algorithm: TotpAlgorithm::Sha1,
+        }
+    }
+}
+
+/// Splits a `{{TOTP:...}}` value into the account name and its parameters.
+pub fn parse_totp_macro(value: &str) -> Result<(&str, TotpParams)> {
+    let (account, options) = match value.split_once(':') {
+        Some((account, options)) => (account.trim(), Some(options)),
+        None => (value.trim(), None),
+    };
+    if account.is_empty() {
+        bail!("TOTP macro needs an account name, e.g. {{{{TOTP:github}}}}");
+    }
+
+    let mut params = TotpParams::default();
+    for pair in options.into_iter().flat_map(|options| options.split(',')) {
+        let Some((key, value)) = pair.split_once('=') else {
+            bail!("invalid TOTP option '{}' (expected key=value)", pair.trim());
+        };
+        let value = value.trim();
+        match key.trim() {
+            "digits" => {
+                params.digits = value
+                    .parse()
+                    .ok()
+                    .filter(|digits| (6..=10).contains(digits))
+                    .with_context(|| format!("invalid TOTP digits={value} (expected 6 to 10)"))?;
+            }
+            "period" => {
+                params.period = value
+                    .parse()
+                    .ok()
+                    .filter(|period| *period > 0)
+                    .with_context(|| format!("invalid TOTP period={value} (expected seconds)"))?;
+            }
+            "algorithm" => {
+                params.algorithm = match value.to_ascii_lowercase().as_str() {
+                    "sha1" => TotpAlgorithm::Sha1,
+                    "sha256" => TotpAlgorithm::Sha256,
+                    "sha512" => TotpAlgorithm::Sha512,
+                    _ => bail!("invalid TOTP algorithm={value} (expected sha1, sha256 or sha512)"),
+                };
+            }
+            other => bail!("unknown TOTP option '{other}'"),
+        }
+    }
+    Ok((account, params))
+}
+
+/// The RFC 6238 code for `key` at `unix_time`, zero-padded to `params.digits`.
+pub fn totp(key: &[u8], unix_time: u64, params: TotpParams) -> String {
+    let counter = unix_time / params.period;
+    let code = hotp(key, counter, params.algorithm) % 10u64.pow(params.digits);
+    format!("{code:0width$}", width = params.digits as usize)
+}
+
+/// RFC 4226 dynamic truncation of the HMAC of `counter`, before the modulo.
+fn hotp(key: &[u8], counter: u64, algorithm: TotpAlgorithm) -> u64 {
+    let message = counter.to_be_bytes();
+    let digest = match algorithm {
+        TotpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(key, &message),
+        TotpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(key, &message),
+        TotpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(key, &message),
+    };
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let bytes: [u8; 4] = digest[offset..offset + 4]
+        .try_into()
+        .expect("digest has 4 bytes past any offset");
+    u64::from(u32::from_be_bytes(bytes) & 0x7fff_ffff)
+}
+
+fn hmac<M: Mac + KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Decodes an RFC 4648 base32 secret as authenticator apps show them:
+/// case, spaces, dashes and `=` padding are ignored. The error never
+/// includes the secret.
+pub fn decode_base32(secret: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(secret.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+    for c in secret.chars() {
+        if c.is_whitespace() || c == '-' || c == '=' {
+            continue;
+        }
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => bail!("not valid base32"),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.is_empty() {
+        bail!("empty secret");
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_base32, parse_totp_macro, totp, TotpAlgorithm, TotpParams};
+
+    const SHA1_SEED: &[u8] = b"12345678901234567890";
+    const SHA256_SEED: &[u8] = b"12345678901234567890123456789012";
+    const SHA512_SEED: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    #[test]
+    fn matches_rfc_6238_test_vectors() {
+        let vectors: [(u64, &str, &str, &str); 6] = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        let params = |algorithm| TotpParams {
+            digits: 8,
+            period: 30,
+            algorithm,
+        };
+
+        for (time, sha1, sha256, sha512) in vectors {
+            assert_eq!(totp(SHA1_SEED, time, params(TotpAlgorithm::Sha1)), sha1);
+            assert_eq!(
+                totp(SHA256_SEED, time, params(TotpAlgorithm::Sha256)),
+                sha256
+            );
+            assert_eq!(
+                totp(SHA512_SEED, time, params(TotpAlgorithm::Sha512)),
+                sha512
+            );
+        }
+        assert_eq!(totp(SHA1_SEED, 59, TotpParams::default()), "287082");
+    }
+
+    #[test]
+    fn decodes_base32_secrets_as_apps_display_them() {
+        assert_eq!(
+            decode_base32("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").expect("valid base32"),
+            SHA1_SEED
+        );
+        assert_eq!(
+            decode_base32("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").expect("valid base32"),
+            SHA1_SEED
+        );
+        assert_eq!(decode_base32("MY======").expect("padded"), b"f");
+
+        let err = decode_base32("not base32!").expect_err("invalid characters");
+        assert_eq!(err.to_string(), "not valid base32");
+        assert!(decode_base32("").is_err());
+    }
+
+    #[test]
+    fn parses_account_and_options() {
+        assert_eq!(
+            parse_totp_macro("github").expect("plain account"),
+            ("github", TotpParams::default())
+        );
+        assert_eq!(
+            parse_totp_macro("aws:digits=8, period=60,algorithm=SHA256").expect("options"),
+            (
+                "aws",
+                TotpParams {
+                    digits: 8,
+                    period: 60,
+                    algorithm: TotpAlgorithm::Sha256,
+                }
+            )
+        );
+        for (value, expected) in [
+            (":digits=8", "needs an account name"),
+            ("github:digits=4", "invalid TOTP digits=4"),
+            ("github:period=0", "invalid TOTP period=0"),
+            ("github:algorithm=md5", "invalid TOTP algorithm=md5"),
+            ("github:skew=1", "unknown TOTP option 'skew'"),
+            ("github:digits", "expected key=value"),
+        ] {
+            let err = parse_totp_macro(value).expect_err(value);
+            assert!(err.to_string().contains(expected), "{value}: {err}");
+        }
+    }
+}
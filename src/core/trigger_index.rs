@@ -0,0 +1,309 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::config::ExpansionRule;
+
+const ROOT: usize = 0;
+
+/// Aho–Corasick automaton over all configured triggers.
+///
+/// Feeding the typed buffer through the automaton yields, in a single pass over
+/// the buffer, both the longest buffer suffix that is still a trigger prefix and
+/// every trigger that is a suffix of the buffer, so per-keystroke work depends on
+/// the buffer length rather than on the number of rules.
+pub struct TriggerIndex {
+    nodes: Vec<Node>,
+}
+
+/// A rule [`TriggerIndex::candidates`] found under way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriggerCandidate {
+    pub rule: usize,
+    /// Leading chars of the trigger already at the end of the text.
+    pub typed_chars: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    children: HashMap<char, usize>,
+    fail: usize,
+    /// Nearest node on the failure chain (excluding this one) that ends a trigger.
+    output: Option<usize>,
+    /// Rules whose trigger ends at this node, in config order.
+    rules: Vec<usize>,
+    depth: usize,
+}
+
+impl TriggerIndex {
+    pub fn new(rules: &[ExpansionRule]) -> Self {
+        Self::with_filter(rules, |_| true)
+    }
+
+    /// Indexes only the rules `include` accepts; matches still report indices
+    /// into the full `rules` slice.
+    pub fn with_filter(rules: &[ExpansionRule], include: impl Fn(&ExpansionRule) -> bool) -> Self {
+        Self::from_triggers(
+            rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| include(rule))
+                .map(|(rule_index, rule)| (rule_index, rule.trigger.as_str())),
+        )
+    }
+
+    /// Indexes `(index, trigger)` pairs from any source; matches report the
+    /// given indices.
+    pub fn from_triggers<'a>(triggers: impl IntoIterator<Item = (usize, &'a str)>) -> Self {
+        let mut nodes = vec![Node::default()];
+
+        for (rule_index, trigger) in triggers {
+            let mut current = ROOT;
+            for c in trigger.chars() {
+                current = match nodes[current].children.get(&c) {
+                    Some(&next) => next,
+                    None => {
+                        let next = nodes.len();
+                        let depth = nodes[current].depth + 1;
+                        nodes.push(Node {
+                            depth,
+                            ..Node::default()
+                        });
+                        nodes[current].children.insert(c, next);
+                        next
+                    }
+                };
+            }
+            if current != ROOT {
+                nodes[current].rules.push(rule_index);
+            }
+        }
+
+        let mut queue: VecDeque<usize> = nodes[ROOT].children.values().copied().collect();
+        while let Some(current) = queue.pop_front() {
+            let children: Vec<(char, usize)> = nodes[current]
+                .children
+                .iter()
+                .map(|(&c, &child)| (c, child))
+                .collect();
+
+            for (c, child) in children {
+                let mut fallback = nodes[current].fail;
+                let fail = loop {
+                    if let Some(&next) = nodes[fallback].children.get(&c) {
+                        break next;
+                    }
+                    if fallback == ROOT {
+                        break ROOT;
+                    }
+                    fallback = nodes[fallback].fail;
+                };
+
+                nodes[child].fail = fail;
+                nodes[child].output = if nodes[fail].rules.is_empty() {
+                    nodes[fail].output
+                } else {
+                    Some(fail)
+                };
+                queue.push_back(child);
+            }
+        }
+
+        Self { nodes }
+    }
+
+    /// Returns the number of trailing chars of `text` that form the longest
+    /// suffix which is also a prefix of some trigger (0 when there is none).
+    pub fn possible_match_len(&self, text: &str) -> usize {
+        self.nodes[self.run(text)].depth
+    }
+
+    /// Every rule whose trigger is longer than some suffix of `text` and starts
+    /// with it, i.e. could still be completed by typing on, in config order.
+    pub fn candidates(&self, text: &str) -> Vec<TriggerCandidate> {
+        let mut candidates = Vec::new();
+        let mut state = self.run(text);
+        // Each suffix still inside the automaton is a node on the failure
+        // chain; the triggers continuing it are the rules below that node.
+        while state != ROOT {
+            let typed_chars = self.nodes[state].depth;
+            let mut below: Vec<usize> = self.nodes[state].children.values().copied().collect();
+            while let Some(node) = below.pop() {
+                let node = &self.nodes[node];
+                candidates.extend(
+                    node.rules
+                        .iter()
+                        .map(|&rule| TriggerCandidate { rule, typed_chars }),
+                );
+                below.extend(node.children.values());
+            }
+            state = self.nodes[state].fail;
+        }
+        candidates.sort_unstable_by_key(|candidate| candidate.rule);
+        candidates
+    }
+
+    /// Returns the index of the rule whose trigger is a suffix of `text`, skipping
+    /// rules rejected by `accept`.
+    ///
+    /// With `prefer_longest` the longest trigger wins (ties keep config order);
+    /// otherwise the first matching rule in config order wins.
+    pub fn find_suffix_match(
+        &self,
+        text: &str,
+        prefer_longest: bool,
+        mut accept: impl FnMut(usize) -> bool,
+    ) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        let mut current = Some(self.run(text));
+
+        while let Some(node_index) = current {
+            let node = &self.nodes[node_index];
+            if let Some(first) = node.rules.iter().copied().find(|&rule| accept(rule)) {
+                if prefer_longest {
+                    return Some(first);
+                }
+                best = Some(best.map_or(first, |existing| existing.min(first)));
+            }
+            current = node.output;
+        }
+
+        best
+    }
+
+    /// Returns true when some trigger starts with `prefix` and is longer than it,
+    /// skipping rules rejected by `accept`.
+    pub fn has_longer_trigger_with_prefix(
+        &self,
+        prefix: &str,
+        mut accept: impl FnMut(usize) -> bool,
+    ) -> bool {
+        let mut current = ROOT;
+        for c in prefix.chars() {
+            match self.nodes[current].children.get(&c) {
+                Some(&next) => current = next,
+                None => return false,
+            }
+        }
+        let mut below: Vec<usize> = self.nodes[current].children.values().copied().collect();
+        while let Some(node) = below.pop() {
+            let node = &self.nodes[node];
+            if node.rules.iter().any(|&rule| accept(rule)) {
+                return true;
+            }
+            below.extend(node.children.values());
+        }
+        false
+    }
+
+    fn run(&self, text: &str) -> usize {
+        let mut current = ROOT;
+        for c in text.chars() {
+            current = self.step(current, c);
+        }
+        current
+    }
+
+    fn step(&self, mut current: usize, c: char) -> usize {
+        loop {
+            if let Some(&next) = self.nodes[current].children.get(&c) {
+                return next;
+            }
+            if current == ROOT {
+                return ROOT;
+            }
+            current = self.nodes[current].fail;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TriggerIndex;
+    use crate::config::ExpansionRule;
+
+    fn rules(triggers: &[&str]) -> Vec<ExpansionRule> {
+        triggers
+            .iter()
+            .map(|trigger| ExpansionRule {
+                trigger: trigger.to_string(),
+                expansion: format!("expanded {trigger}"),
+                ..ExpansionRule::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_longest_suffix_match_or_config_order() {
+        let index = TriggerIndex::new(&rules(&["b", ";a", ";ab"]));
+
+        assert_eq!(index.find_suffix_match("x;ab", true, |_| true), Some(2));
+        assert_eq!(index.find_suffix_match("x;ab", false, |_| true), Some(0));
+        assert_eq!(index.find_suffix_match("x;a", true, |_| true), Some(1));
+        assert_eq!(index.find_suffix_match("x;", true, |_| true), None);
+    }
+
+    #[test]
+    fn finds_suffix_match_through_failure_links() {
+        let index = TriggerIndex::new(&rules(&["abcd", "bce"]));
+
+        assert_eq!(index.find_suffix_match("abce", true, |_| true), Some(1));
+        assert_eq!(index.find_suffix_match("abcd", true, |_| true), Some(0));
+    }
+
+    #[test]
+    fn candidates_cover_every_suffix_still_under_way() {
+        let index = TriggerIndex::new(&rules(&[";sig", ";sigg", ";sx", "abcd", "bce"]));
+        let candidates = |text: &str| {
+            index
+                .candidates(text)
+                .into_iter()
+                .map(|candidate| (candidate.rule, candidate.typed_chars))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(candidates("hello ;s"), vec![(0, 2), (1, 2), (2, 2)]);
+        assert_eq!(candidates("hello ;si"), vec![(0, 3), (1, 3)]);
+        assert_eq!(candidates("hello ;sig"), vec![(1, 4)]);
+        assert_eq!(candidates("hello ;sigg"), vec![]);
+        assert_eq!(candidates("hello"), vec![]);
+        // "abc" continues into "abcd" and its suffix "bc" into "bce".
+        assert_eq!(candidates("abc"), vec![(3, 3), (4, 2)]);
+        assert_eq!(candidates("xbc"), vec![(4, 2)]);
+    }
+
+    #[test]
+    fn skips_rejected_rules_and_falls_back_to_shorter_match() {
+        let index = TriggerIndex::new(&rules(&[";a", ";ab"]));
+
+        assert_eq!(index.find_suffix_match(";ab", true, |rule| rule != 1), None);
+        assert_eq!(
+            index.find_suffix_match(";a", true, |rule| rule != 1),
+            Some(0)
+        );
+
+        let index = TriggerIndex::new(&rules(&["b", ";ab"]));
+        assert_eq!(
+            index.find_suffix_match(";ab", true, |rule| rule != 1),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn reports_longest_possible_match_suffix() {
+        let index = TriggerIndex::new(&rules(&["tg@", "sig;"]));
+
+        assert_eq!(index.possible_match_len("hello t"), 1);
+        assert_eq!(index.possible_match_len("hello si"), 2);
+        assert_eq!(index.possible_match_len("hello"), 0);
+        assert_eq!(index.possible_match_len(""), 0);
+    }
+
+    #[test]
+    fn detects_longer_triggers_sharing_a_prefix() {
+        let index = TriggerIndex::new(&rules(&[";a", ";ab"]));
+
+        assert!(index.has_longer_trigger_with_prefix(";a", |_| true));
+        assert!(!index.has_longer_trigger_with_prefix(";ab", |_| true));
+        assert!(!index.has_longer_trigger_with_prefix(";x", |_| true));
+        assert!(!index.has_longer_trigger_with_prefix(";a", |rule| rule != 1));
+    }
+}
@@ -1,18 +1,31 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use serde::{Deserialize, Serialize};
+
+/// Whether a key went down or up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum KeyEventKind {
     Press,
     Release,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Non-printable keys the engine reacts to; anything else maps to `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SpecialInputKey {
     Enter,
     Tab,
     Backspace,
-    Shift,
-    Ctrl,
-    Alt,
-    Meta,
+    ShiftLeft,
+    ShiftRight,
+    CtrlLeft,
+    CtrlRight,
+    /// Left Alt.
+    AltLeft,
+    /// Right Alt, which X11 reports as AltGr (ISO Level 3 shift) on most
+    /// layouts. European layouts need it to type characters such as `@`.
+    AltGr,
+    MetaLeft,
+    MetaRight,
     CapsLock,
     Escape,
     Left,
@@ -22,6 +35,7 @@ pub enum SpecialInputKey {
     Home,
     End,
     Delete,
+    Insert,
     PageUp,
     PageDown,
     F1,
@@ -36,13 +50,44 @@ pub enum SpecialInputKey {
     F10,
     F11,
     F12,
-    Unknown,
+    /// Any other key, with the backend's keycode when it reports one (0
+    /// otherwise). Such keys neither type text nor move the caret, so they
+    /// leave a partly typed trigger alone.
+    Unknown(u32),
+}
+
+impl SpecialInputKey {
+    pub fn is_shift(self) -> bool {
+        matches!(self, Self::ShiftLeft | Self::ShiftRight)
+    }
+
+    pub fn is_ctrl(self) -> bool {
+        matches!(self, Self::CtrlLeft | Self::CtrlRight)
+    }
+
+    /// Either Alt key, including AltGr.
+    pub fn is_alt(self) -> bool {
+        matches!(self, Self::AltLeft | Self::AltGr)
+    }
+
+    pub fn is_meta(self) -> bool {
+        matches!(self, Self::MetaLeft | Self::MetaRight)
+    }
+
+    pub fn is_modifier(self) -> bool {
+        self.is_shift() || self.is_ctrl() || self.is_alt() || self.is_meta()
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// A backend-neutral keyboard event fed to [`crate::core::engine::Engine::handle_event`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct KeyEvent {
     pub kind: KeyEventKind,
-    pub printable: Option<char>,
+    /// Text the key press committed, if any (already shifted by the backend):
+    /// usually one character, several for some compose sequences, and empty
+    /// for a dead key whose character only arrives with the next key.
+    pub text: Option<String>,
     pub special: Option<SpecialInputKey>,
+    /// Set for events caused by slykey's own output so they are not re-matched.
     pub is_injected: bool,
 }
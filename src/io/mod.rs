@@ -1,2 +1,5 @@
 pub mod events;
+pub mod notification;
 pub mod output;
+pub mod secrets;
+pub mod window;
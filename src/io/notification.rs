@@ -0,0 +1,66 @@
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+/// Destination for desktop notifications, usually the session's
+/// notification daemon.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, summary: &str, body: &str) -> Result<()>;
+
+    /// Shows a notification with `(key, label)` action buttons. Notifiers
+    /// without action support show it without them.
+    fn notify_with_actions(
+        &self,
+        summary: &str,
+        body: &str,
+        _actions: &[(&str, &str)],
+    ) -> Result<()> {
+        self.notify(summary, body)
+    }
+}
+
+/// Discards every notification, for platforms without a notification daemon.
+pub struct NullNotifier;
+
+impl Notifier for NullNotifier {
+    fn notify(&self, _summary: &str, _body: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps notifications in memory as `summary: body`, with the action keys
+/// appended in brackets (`summary: body [undo]`). Meant for tests.
+#[derive(Default)]
+pub struct RecordingNotifier {
+    sent: Mutex<Vec<String>>,
+}
+
+impl RecordingNotifier {
+    pub fn sent(&self) -> Vec<String> {
+        self.sent.lock().expect("notifier mutex poisoned").clone()
+    }
+}
+
+impl Notifier for RecordingNotifier {
+    fn notify(&self, summary: &str, body: &str) -> Result<()> {
+        self.sent
+            .lock()
+            .expect("notifier mutex poisoned")
+            .push(format!("{summary}: {body}"));
+        Ok(())
+    }
+
+    fn notify_with_actions(
+        &self,
+        summary: &str,
+        body: &str,
+        actions: &[(&str, &str)],
+    ) -> Result<()> {
+        let keys: Vec<&str> = actions.iter().map(|(key, _)| *key).collect();
+        self.sent
+            .lock()
+            .expect("notifier mutex poisoned")
+            .push(format!("{summary}: {body} [{}]", keys.join(", ")));
+        Ok(())
+    }
+}
@@ -1,8 +1,20 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
+use tracing::{info, warn};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::core::expansion::OutputAction;
+use crate::config::UnicodeFallback;
+use crate::core::expansion::{format_actions, OutputAction};
+use crate::io::notification::Notifier;
 
-#[derive(Debug, Clone, Copy)]
+/// Keys an [`OutputSink`] can press on behalf of `{{KEY:...}}` macros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpecialKey {
     Enter,
     Tab,
@@ -32,7 +44,601 @@ pub enum SpecialKey {
     F12,
 }
 
+/// Modifier held down while a key chord such as `{{KEY:CTRL+SHIFT+V}}` is tapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyModifier {
+    Ctrl,
+    Shift,
+    Alt,
+    Meta,
+}
+
+/// Final key of a chord: a named key or a single character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComboKey {
+    Special(SpecialKey),
+    Char(char),
+}
+
+/// What an [`OutputSink`] can do, so the engine can adapt expansions to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SinkCapabilities {
+    /// [`send_paste`](OutputSink::send_paste) goes through the clipboard.
+    pub paste: bool,
+    /// [`OutputAction::KeyCombo`] chords can be pressed.
+    pub key_combos: bool,
+    /// Text beyond ASCII can be typed.
+    pub unicode_text: bool,
+    /// Longest text, in grapheme clusters, one [`OutputAction::Text`] may
+    /// hold; the engine splits longer text.
+    pub max_text_chunk: Option<usize>,
+}
+
+impl SinkCapabilities {
+    /// Supports nothing beyond typing ASCII text and single keys.
+    pub const NONE: Self = Self {
+        paste: false,
+        key_combos: false,
+        unicode_text: false,
+        max_text_chunk: None,
+    };
+    pub const FULL: Self = Self {
+        paste: true,
+        key_combos: true,
+        unicode_text: true,
+        max_text_chunk: None,
+    };
+}
+
+impl Default for SinkCapabilities {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Destination for expansion output, usually a keyboard injector.
 pub trait OutputSink: Send + Sync {
+    /// Deletes `count` characters before the caret (the typed trigger).
     fn send_backspaces(&self, count: usize) -> Result<()>;
+    /// Types text, presses keys and sleeps in order.
     fn send_actions(&self, actions: &[OutputAction]) -> Result<()>;
+    /// Like [`send_actions`](Self::send_actions), but pauses `char_delay_ms`
+    /// between typed chunks instead of the sink's configured delay.
+    fn send_actions_with_char_delay(
+        &self,
+        actions: &[OutputAction],
+        _char_delay_ms: u64,
+    ) -> Result<()> {
+        self.send_actions(actions)
+    }
+    /// Inserts `text` through the clipboard; sinks without clipboard support type it instead.
+    fn send_paste(&self, text: &str) -> Result<()> {
+        self.send_actions(&[OutputAction::Text(text.to_string())])
+    }
+    /// What this sink supports; nothing beyond plain typing unless it says so.
+    fn capabilities(&self) -> SinkCapabilities {
+        SinkCapabilities::default()
+    }
+    /// Asks the expansion being sent from another thread to stop at the next
+    /// action or text chunk; the interrupted call fails with [`OutputCancelled`].
+    /// The request is cleared by the next [`send_backspaces`](Self::send_backspaces),
+    /// which starts every expansion.
+    fn cancel(&self) {}
+}
+
+/// Sink that accepts everything and sends nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl OutputSink for NullSink {
+    fn send_backspaces(&self, _count: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn send_actions(&self, _actions: &[OutputAction]) -> Result<()> {
+        Ok(())
+    }
+}
+
+type LogLine = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Describes every call on one line, rendered like `slykey test` output,
+/// before passing it on to the inner sink. Over a [`NullSink`] this is
+/// `slykey run --dry-run`: triggers fire, nothing is typed.
+pub struct LoggingSink {
+    inner: Arc<dyn OutputSink>,
+    log: LogLine,
+    capabilities: SinkCapabilities,
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl LoggingSink {
+    /// Logs at info level and sends nothing, claiming full support so the
+    /// log shows expansions as a real backend would get them.
+    pub fn dry_run() -> Self {
+        Self::new(Arc::new(NullSink), |line| {
+            info!("dry run: would send {line}")
+        })
+        .with_capabilities(SinkCapabilities::FULL)
+    }
+
+    /// Reports the capabilities of `inner`.
+    pub fn new(inner: Arc<dyn OutputSink>, log: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            capabilities: inner.capabilities(),
+            inner,
+            log: Box::new(log),
+            notifier: None,
+        }
+    }
+
+    /// Reports `capabilities` instead of the inner sink's.
+    pub fn with_capabilities(mut self, capabilities: SinkCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Also shows what would be typed or pasted as a notification;
+    /// backspaces are only logged.
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    fn log(&self, line: &str) {
+        (self.log)(line);
+        if let Some(notifier) = &self.notifier {
+            if let Err(err) = notifier.notify("slykey dry run", &format!("Would send {line}")) {
+                warn!("failed to send dry run notification: {err}");
+            }
+        }
+    }
+}
+
+impl OutputSink for LoggingSink {
+    fn send_backspaces(&self, count: usize) -> Result<()> {
+        (self.log)(&format!("[BACKSPACE x{count}]"));
+        self.inner.send_backspaces(count)
+    }
+
+    fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+        self.log(&format_actions(actions));
+        self.inner.send_actions(actions)
+    }
+
+    fn send_actions_with_char_delay(
+        &self,
+        actions: &[OutputAction],
+        char_delay_ms: u64,
+    ) -> Result<()> {
+        self.log(&format!(
+            "{} (char delay {char_delay_ms}ms)",
+            format_actions(actions)
+        ));
+        self.inner
+            .send_actions_with_char_delay(actions, char_delay_ms)
+    }
+
+    fn send_paste(&self, text: &str) -> Result<()> {
+        self.log(&format!("[PASTE] {text}"));
+        self.inner.send_paste(text)
+    }
+
+    fn capabilities(&self) -> SinkCapabilities {
+        self.capabilities
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+}
+
+/// Error returned by an [`OutputSink`] call that stopped because of
+/// [`OutputSink::cancel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputCancelled;
+
+impl fmt::Display for OutputCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expansion cancelled")
+    }
+}
+
+impl std::error::Error for OutputCancelled {}
+
+/// Error from an [`OutputSink`] call that failed part-way through its actions,
+/// e.g. after the trigger was already erased.
+#[derive(Debug)]
+pub struct PartialOutput {
+    /// Actions fully sent before the failure.
+    pub emitted: usize,
+    pub total: usize,
+    pub source: anyhow::Error,
+}
+
+impl fmt::Display for PartialOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} of {} actions before failing: {:#}",
+            self.emitted, self.total, self.source
+        )
+    }
+}
+
+impl std::error::Error for PartialOutput {}
+
+/// Marks keys sent while it is alive as our own: sets the backend's
+/// "injecting" flag, which its listener stamps on events, and clears it on
+/// drop, including when an error or panic leaves the sending code early.
+pub struct InjectGuard<'a>(&'a AtomicBool);
+
+impl<'a> InjectGuard<'a> {
+    pub fn new(flag: &'a AtomicBool) -> Self {
+        flag.store(true, Ordering::Relaxed);
+        Self(flag)
+    }
+}
+
+impl Drop for InjectGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Calls `f` until it succeeds, at most `attempts` times (at least once),
+/// sleeping `backoff`, then twice that and so on between tries. Returns the
+/// last error when every attempt fails.
+pub fn with_retries<T, E>(
+    attempts: u32,
+    backoff: Duration,
+    mut f: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Ok(value) => return Ok(value),
+            Err(err) if attempt >= attempts => return Err(err),
+            Err(_) => {
+                std::thread::sleep(backoff * attempt);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Splits `text` into chunks of at most `chunk_size` characters (0 keeps it
+/// whole), each paired with the pause to take after typing it.
+///
+/// Chunks never split a grapheme cluster.
+pub fn plan_typed_text(
+    text: &str,
+    chunk_size: usize,
+    char_delay_ms: u64,
+) -> Vec<(String, Duration)> {
+    let delay = Duration::from_millis(char_delay_ms);
+    if text.is_empty() {
+        return Vec::new();
+    }
+    if chunk_size == 0 {
+        return vec![(text.to_string(), delay)];
+    }
+
+    let graphemes: Vec<&str> = text.graphemes(true).collect();
+    graphemes
+        .chunks(chunk_size)
+        .map(|chunk| (chunk.concat(), delay))
+        .collect()
+}
+
+/// Puts a `delay_ms` pause between consecutive actions, except next to an
+/// explicit sleep, which already separates them.
+pub fn insert_action_delays(actions: &[OutputAction], delay_ms: u64) -> Vec<OutputAction> {
+    let mut delayed = Vec::with_capacity(actions.len() * 2);
+    for (index, action) in actions.iter().enumerate() {
+        if needs_action_delay(actions, index, delay_ms) {
+            delayed.push(OutputAction::SleepMs(delay_ms));
+        }
+        delayed.push(action.clone());
+    }
+    delayed
+}
+
+/// How many of `actions` are among the first `emitted` actions of
+/// [`insert_action_delays`]`(actions, delay_ms)`, so a failure can be
+/// reported without counting the inserted pauses.
+pub fn actions_before_delays(actions: &[OutputAction], delay_ms: u64, emitted: usize) -> usize {
+    let mut position = 0;
+    for index in 0..actions.len() {
+        position += usize::from(needs_action_delay(actions, index, delay_ms));
+        if position >= emitted {
+            return index;
+        }
+        position += 1;
+    }
+    actions.len()
+}
+
+fn needs_action_delay(actions: &[OutputAction], index: usize, delay_ms: u64) -> bool {
+    delay_ms > 0 && index > 0 && !actions[index - 1].is_sleep() && !actions[index].is_sleep()
+}
+
+/// Picks how long a `{{SLEEP_MS:min-max}}` pause lasts this time. Not
+/// cryptographically random, just different from run to run.
+pub fn random_sleep_ms(min: u64, max: u64) -> u64 {
+    let span = max.saturating_sub(min).saturating_add(1);
+    min + RandomState::new().hash_one(Instant::now()) % span
+}
+
+/// What to do with a grapheme the backend failed to type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackStep {
+    /// Leave it out and carry on typing.
+    Skip,
+    /// Type this in its place.
+    Type(String),
+    /// Paste this instead of typing the rest of the text run.
+    Paste(String),
+}
+
+/// Decides how to handle the grapheme at byte `failed_at` of the text run
+/// `text` after typing it failed.
+pub fn plan_unicode_fallback(mode: UnicodeFallback, text: &str, failed_at: usize) -> FallbackStep {
+    match mode {
+        UnicodeFallback::Skip => FallbackStep::Skip,
+        UnicodeFallback::Replace => FallbackStep::Type("?".to_string()),
+        UnicodeFallback::Paste => FallbackStep::Paste(text[failed_at..].to_string()),
+    }
+}
+
+/// Lists untypable graphemes with their code points, e.g. `'😀' (U+1F600)`,
+/// so the expansion can be fixed.
+pub fn describe_untypable(graphemes: &[String]) -> String {
+    graphemes
+        .iter()
+        .map(|grapheme| {
+            let code_points = grapheme
+                .chars()
+                .map(|c| format!("U+{:04X}", c as u32))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("'{grapheme}' ({code_points})")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        actions_before_delays, describe_untypable, insert_action_delays, plan_typed_text,
+        plan_unicode_fallback, random_sleep_ms, with_retries, ComboKey, FallbackStep, InjectGuard,
+        KeyModifier, LoggingSink, NullSink, OutputSink, SinkCapabilities, SpecialKey,
+    };
+    use crate::config::UnicodeFallback;
+    use crate::core::expansion::{format_actions, CaretUnit, OutputAction};
+    use crate::io::notification::RecordingNotifier;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn unchunked_text_is_typed_in_one_step() {
+        assert_eq!(
+            plan_typed_text("hello world", 0, 1),
+            vec![("hello world".to_string(), Duration::from_millis(1))]
+        );
+        assert!(plan_typed_text("", 0, 1).is_empty());
+    }
+
+    #[test]
+    fn splits_text_into_paced_chunks() {
+        let delay = Duration::from_millis(15);
+        assert_eq!(
+            plan_typed_text("abcdefg", 3, 15),
+            vec![
+                ("abc".to_string(), delay),
+                ("def".to_string(), delay),
+                ("g".to_string(), delay),
+            ]
+        );
+    }
+
+    #[test]
+    fn chunks_keep_grapheme_clusters_together() {
+        let steps = plan_typed_text("e\u{301}a👍🏽", 1, 0);
+        let chunks: Vec<&str> = steps.iter().map(|(text, _)| text.as_str()).collect();
+
+        assert_eq!(chunks, vec!["e\u{301}", "a", "👍🏽"]);
+        assert!(steps.iter().all(|(_, sleep)| sleep.is_zero()));
+    }
+
+    #[test]
+    fn retries_until_a_call_succeeds() {
+        let mut calls = 0;
+        let result: Result<u32, &str> = with_retries(3, Duration::ZERO, || {
+            calls += 1;
+            if calls < 3 {
+                Err("busy")
+            } else {
+                Ok(calls)
+            }
+        });
+
+        assert_eq!(result, Ok(3));
+    }
+
+    #[test]
+    fn gives_up_with_the_last_error_after_all_attempts() {
+        let mut calls = 0;
+        let result: Result<(), String> = with_retries(3, Duration::ZERO, || {
+            calls += 1;
+            Err(format!("failure {calls}"))
+        });
+
+        assert_eq!(result, Err("failure 3".to_string()));
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let _: Result<(), ()> = with_retries(0, Duration::ZERO, || {
+            calls += 1;
+            Err(())
+        });
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn inject_guard_clears_the_flag_on_every_exit() {
+        let flag = AtomicBool::new(false);
+        let send = |fail: bool| -> anyhow::Result<()> {
+            let _guard = InjectGuard::new(&flag);
+            assert!(flag.load(Ordering::Relaxed));
+            if fail {
+                anyhow::bail!("key press simulation failed");
+            }
+            Ok(())
+        };
+
+        send(false).expect("send should succeed");
+        assert!(!flag.load(Ordering::Relaxed));
+        send(true).expect_err("send should fail");
+        assert!(!flag.load(Ordering::Relaxed));
+
+        let panicked = std::panic::catch_unwind(|| {
+            let _guard = InjectGuard::new(&flag);
+            panic!("enigo panicked");
+        });
+        assert!(panicked.is_err());
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn logging_sink_describes_each_call_without_an_output_backend() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logged = Arc::clone(&lines);
+        let sink = LoggingSink::new(Arc::new(NullSink), move |line| {
+            logged
+                .lock()
+                .expect("mutex poisoned")
+                .push(line.to_string())
+        });
+
+        sink.send_backspaces(3).expect("backspaces");
+        sink.send_actions(&[
+            OutputAction::Text("Best,".to_string()),
+            OutputAction::Key(SpecialKey::Enter),
+            OutputAction::SleepMs(50),
+            OutputAction::Text("Tyler".to_string()),
+            OutputAction::MoveCaret {
+                amount: -3,
+                unit: CaretUnit::Chars,
+            },
+            OutputAction::KeyCombo {
+                modifiers: vec![KeyModifier::Ctrl, KeyModifier::Shift],
+                key: ComboKey::Char('v'),
+            },
+        ])
+        .expect("actions");
+        sink.send_actions_with_char_delay(&[OutputAction::Text("slow".to_string())], 20)
+            .expect("delayed actions");
+        sink.send_paste("pasted").expect("paste");
+
+        assert_eq!(
+            *lines.lock().expect("mutex poisoned"),
+            vec![
+                "[BACKSPACE x3]",
+                "Best,[KEY Enter][SLEEP 50ms]Tyler[CARET -3][KEY Ctrl+Shift+v]",
+                "slow (char delay 20ms)",
+                "[PASTE] pasted",
+            ]
+        );
+    }
+
+    #[test]
+    fn logging_sink_notifies_what_would_be_typed() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let sink = LoggingSink::new(Arc::new(NullSink), |_| {}).with_notifier(notifier.clone());
+
+        sink.send_backspaces(2).expect("backspaces");
+        sink.send_actions(&[OutputAction::Text("hello".to_string())])
+            .expect("actions");
+        sink.send_paste("pasted").expect("paste");
+
+        assert_eq!(
+            notifier.sent(),
+            vec![
+                "slykey dry run: Would send hello",
+                "slykey dry run: Would send [PASTE] pasted",
+            ]
+        );
+    }
+
+    #[test]
+    fn logging_sink_reports_its_inner_capabilities_unless_dry_running() {
+        assert_eq!(NullSink.capabilities(), SinkCapabilities::NONE);
+        let sink = LoggingSink::new(Arc::new(NullSink), |_| {});
+        assert_eq!(sink.capabilities(), SinkCapabilities::NONE);
+        assert_eq!(
+            LoggingSink::dry_run().capabilities(),
+            SinkCapabilities::FULL
+        );
+    }
+
+    #[test]
+    fn unicode_fallback_skips_replaces_or_pastes_the_rest() {
+        assert_eq!(
+            plan_unicode_fallback(UnicodeFallback::Skip, "hi 😀 done", 3),
+            FallbackStep::Skip
+        );
+        assert_eq!(
+            plan_unicode_fallback(UnicodeFallback::Replace, "hi 😀 done", 3),
+            FallbackStep::Type("?".to_string())
+        );
+        assert_eq!(
+            plan_unicode_fallback(UnicodeFallback::Paste, "hi 😀 done", 3),
+            FallbackStep::Paste("😀 done".to_string())
+        );
+        assert_eq!(
+            describe_untypable(&["😀".to_string(), "e\u{301}".to_string()]),
+            "'😀' (U+1F600), 'e\u{301}' (U+0065 U+0301)"
+        );
+    }
+
+    #[test]
+    fn action_delays_go_between_actions_but_not_next_to_sleeps() {
+        let actions = [
+            OutputAction::Text("user".to_string()),
+            OutputAction::Key(SpecialKey::Tab),
+            OutputAction::Text("pass".to_string()),
+            OutputAction::SleepRange(100, 300),
+            OutputAction::SleepMs(50),
+            OutputAction::Key(SpecialKey::Enter),
+        ];
+
+        assert_eq!(
+            format_actions(&insert_action_delays(&actions, 20)),
+            "user[SLEEP 20ms][KEY Tab][SLEEP 20ms]pass[SLEEP 100-300ms][SLEEP 50ms][KEY Enter]"
+        );
+        assert_eq!(
+            format_actions(&insert_action_delays(&actions, 0)),
+            format_actions(&actions)
+        );
+
+        // user, delay, Tab, delay, pass, ...: failing on the second delay
+        // leaves two of the actions sent.
+        assert_eq!(actions_before_delays(&actions, 20, 0), 0);
+        assert_eq!(actions_before_delays(&actions, 20, 3), 2);
+        assert_eq!(actions_before_delays(&actions, 20, 4), 2);
+        assert_eq!(actions_before_delays(&actions, 20, 5), 3);
+        assert_eq!(actions_before_delays(&actions, 20, 8), 6);
+        assert_eq!(actions_before_delays(&actions, 0, 4), 4);
+    }
+
+    #[test]
+    fn random_sleeps_stay_within_their_range() {
+        for _ in 0..100 {
+            assert!((100..=300).contains(&random_sleep_ms(100, 300)));
+        }
+        assert_eq!(random_sleep_ms(5, 5), 5);
+    }
 }
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use anyhow::Result;
+
+/// What `{{SECRET:...}}` renders to wherever people rather than the focused
+/// window read the text, e.g. notifications and `slykey test`.
+pub const SECRET_MASK: &str = "••••••";
+
+/// Where `{{SECRET:name}}` values live, usually the desktop keyring.
+/// Failures should be [`SecretError`]s so callers can tell them apart.
+pub trait SecretStore: Send + Sync {
+    fn get(&self, name: &str) -> Result<String>;
+    /// Stores `value` under `name`, replacing any previous value.
+    fn set(&self, name: &str, value: &str) -> Result<()>;
+    fn remove(&self, name: &str) -> Result<()>;
+}
+
+/// Why a secret could not be read or written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretError {
+    /// The keyring holding the secret is locked.
+    Locked,
+    NotFound(String),
+    /// No secret service answered; carries the reason.
+    Unavailable(String),
+}
+
+impl std::fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Locked => write!(f, "keyring is locked; unlock it and try again"),
+            Self::NotFound(name) => write!(
+                f,
+                "secret '{name}' not found; add it with `slykey secret set {name}`"
+            ),
+            Self::Unavailable(reason) => write!(f, "secret service unavailable: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+/// For platforms without a secret service: every lookup fails.
+pub struct NullSecretStore;
+
+impl SecretStore for NullSecretStore {
+    fn get(&self, _name: &str) -> Result<String> {
+        Err(unsupported())
+    }
+
+    fn set(&self, _name: &str, _value: &str) -> Result<()> {
+        Err(unsupported())
+    }
+
+    fn remove(&self, _name: &str) -> Result<()> {
+        Err(unsupported())
+    }
+}
+
+fn unsupported() -> anyhow::Error {
+    SecretError::Unavailable("not supported on this platform".to_string()).into()
+}
+
+/// Keeps secrets in memory. Meant for tests.
+#[derive(Default)]
+pub struct MemorySecretStore {
+    secrets: Mutex<HashMap<String, String>>,
+}
+
+impl MemorySecretStore {
+    pub fn with_secret(self, name: &str, value: &str) -> Self {
+        self.lock().insert(name.to_string(), value.to_string());
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, String>> {
+        self.secrets.lock().expect("secret store mutex poisoned")
+    }
+}
+
+impl SecretStore for MemorySecretStore {
+    fn get(&self, name: &str) -> Result<String> {
+        match self.lock().get(name) {
+            Some(value) => Ok(value.clone()),
+            None => Err(SecretError::NotFound(name.to_string()).into()),
+        }
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<()> {
+        self.lock().insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        match self.lock().remove(name) {
+            Some(_) => Ok(()),
+            None => Err(SecretError::NotFound(name.to_string()).into()),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use anyhow::Result;
+
+/// Reports the focused window so rules can be filtered per application.
+pub trait ActiveWindowProvider: Send + Sync {
+    /// Returns the WM_CLASS names (instance and class) of the focused window.
+    fn active_window_classes(&self) -> Result<Vec<String>>;
+}
@@ -0,0 +1,63 @@
+//! Text expansion engine behind the `slykey` binary.
+//!
+//! The [`core::engine::Engine`] consumes [`io::events::KeyEvent`]s, matches
+//! configured triggers, and emits backspaces plus [`core::expansion::OutputAction`]s
+//! through an [`io::output::OutputSink`]. Everything outside [`platform`] is
+//! free of X11/GTK dependencies, so the engine can be driven by any input
+//! backend:
+//!
+//! ```
+//! use std::sync::{Arc, Mutex};
+//!
+//! use slykey::config::AppConfig;
+//! use slykey::core::engine::Engine;
+//! use slykey::core::expansion::OutputAction;
+//! use slykey::io::events::{KeyEvent, KeyEventKind};
+//! use slykey::io::output::OutputSink;
+//!
+//! #[derive(Default)]
+//! struct Recorder(Mutex<Vec<String>>);
+//!
+//! impl OutputSink for Recorder {
+//!     fn send_backspaces(&self, count: usize) -> anyhow::Result<()> {
+//!         self.0.lock().unwrap().push(format!("<bs x{count}>"));
+//!         Ok(())
+//!     }
+//!
+//!     fn send_actions(&self, actions: &[OutputAction]) -> anyhow::Result<()> {
+//!         for action in actions {
+//!             if let OutputAction::Text(text) = action {
+//!                 self.0.lock().unwrap().push(text.clone());
+//!             }
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let config: AppConfig =
+//!     serde_yaml::from_str("expansions:\n  - trigger: ';hi'\n    expansion: hello\n")?;
+//! let recorder = Arc::new(Recorder::default());
+//! let mut engine = Engine::new(config);
+//! engine.set_output(recorder.clone());
+//!
+//! for c in ";hi".chars() {
+//!     engine.handle_event(KeyEvent {
+//!         kind: KeyEventKind::Press,
+//!         text: Some(c.to_string()),
+//!         special: None,
+//!         is_injected: false,
+//!     })?;
+//! }
+//!
+//! assert_eq!(*recorder.0.lock().unwrap(), vec!["<bs x3>", "hello"]);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The X11 input backend and focused-window lookup are behind the `x11`
+//! feature; the system tray icon is behind the `tray` feature. Both are on by
+//! default.
+
+pub mod config;
+pub mod core;
+pub mod io;
+pub mod platform;
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::EnvFilter;
+
+use crate::cli::LogLevel;
+
+/// Installs the global subscriber: `level` (or `RUST_LOG`) to stderr, or to
+/// a daily-rotated `log_file`. Keep the returned guard alive until exit so
+/// buffered file lines are flushed.
+pub fn init(level: LogLevel, log_file: Option<&Path>) -> Result<Option<WorkerGuard>> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(&directives)
+            .with_context(|| format!("invalid RUST_LOG '{directives}'"))?,
+        _ => EnvFilter::new(level_directive(level)),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false);
+
+    let Some(path) = log_file else {
+        builder.with_writer(std::io::stderr).init();
+        return Ok(None);
+    };
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("--log-file needs a file name: {}", path.display()))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create log directory {}", dir.display()))?;
+    let (writer, guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::daily(dir, file_name));
+    builder.with_writer(writer).with_ansi(false).init();
+    Ok(Some(guard))
+}
+
+fn level_directive(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "error",
+        LogLevel::Warn => "warn",
+        LogLevel::Info => "info",
+        LogLevel::Debug => "debug",
+        LogLevel::Trace => "trace",
+    }
+}
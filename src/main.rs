@@ -1,129 +1,1404 @@
 mod cli;
-mod config;
-mod core;
-mod io;
-mod platform;
+mod completions;
+mod logging;
 
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+#[cfg(all(target_os = "linux", feature = "tray"))]
+use std::sync::OnceLock;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use tracing::{debug, error, info, warn};
 
-use crate::cli::{Cli, Commands};
-use crate::config::AppConfig;
-use crate::core::engine::Engine;
-use crate::core::instance_lock::InstanceLock;
+use slykey::config::{self, AppConfig, NewlineMode, RemoveTarget};
+use slykey::core::bench;
+use slykey::core::config_watch::{watched_paths, ConfigWatcher, WatchInput};
+use slykey::core::control::{self, ControlCommand, DaemonStatus, NOT_RUNNING_STATUS_JSON};
+use slykey::core::doctor::{self, CheckResult, DisplaySession, DoctorReport, Severity};
+use slykey::core::engine::{handle_shared_event, Engine, PickerCallback, UNDO_ACTION};
+use slykey::core::espanso_import::{self, EspansoImport};
+use slykey::core::expansion::{
+    apply_newline_mode, format_actions, parse_expansion_actions_with_context, ExpansionContext,
+};
+use slykey::core::export::RuleSet;
+use slykey::core::instance_lock::{self, InstanceLock, Takeover};
+use slykey::core::list;
+use slykey::core::output_worker::OutputWorker;
+use slykey::core::picker::{self, PickerEntry};
+use slykey::core::recording::{self, EventRecorder};
+use slykey::core::reload::{reload_from_path, ReloadReport};
+use slykey::core::shell_command::ConfirmationRequired;
+use slykey::core::shutdown::Shutdown;
+use slykey::core::stats::{self, UsageStats};
+use slykey::io::events::KeyEvent;
+use slykey::io::notification::Notifier;
+use slykey::io::output::{LoggingSink, OutputSink};
+use slykey::io::secrets::SecretStore;
+use slykey::platform::active_window::X11ActiveWindow;
+#[cfg(all(target_os = "linux", feature = "tray"))]
+use slykey::platform::app_indicator;
 #[cfg(target_os = "linux")]
-use crate::platform::app_indicator;
+use slykey::platform::dbus_notification;
 #[cfg(target_os = "linux")]
-use crate::platform::dbus_notification;
-use crate::platform::x11_rdev::X11RdevBackend;
+use slykey::platform::secret_service;
+#[cfg(target_os = "linux")]
+use slykey::platform::systemd;
+use slykey::platform::x11_rdev::X11RdevBackend;
+
+use crate::cli::{Cli, Commands, ExportFormat, ImportFormat, ListFormat, SecretCommand};
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    let _log_guard = logging::init(cli.effective_log_level(), cli.log_file.as_deref())?;
 
-    match cli.command.unwrap_or(Commands::Run) {
-        Commands::Run => run(cli.config, cli.debug),
+    let command = cli.command.unwrap_or(Commands::Run {
+        replace: false,
+        force: false,
+        dry_run: false,
+        notify: false,
+        record: None,
+        record_redact: false,
+    });
+    match command {
+        Commands::Run {
+            replace,
+            force,
+            dry_run,
+            notify,
+            record,
+            record_redact,
+        } => {
+            let takeover = if replace {
+                Takeover::Replace { force }
+            } else {
+                Takeover::Refuse
+            };
+            run(
+                cli.config,
+                RunOptions {
+                    takeover,
+                    dry_run,
+                    notify,
+                    record,
+                    record_redact,
+                },
+            )
+        }
         Commands::ValidateConfig => validate_config(cli.config),
+        Commands::Init { force } => init_config(cli.config, force),
+        Commands::InstallService { print, force } => install_service(cli.config, print, force),
+        Commands::Stats => print_stats(),
+        Commands::Doctor { json } => doctor(cli.config, json),
+        Commands::Status { json: false } => send_control_command(ControlCommand::Status),
+        Commands::Status { json: true } => print_status_json(),
+        Commands::Pause => send_control_command(ControlCommand::Pause),
+        Commands::Resume => send_control_command(ControlCommand::Resume),
+        Commands::Reload => send_control_command(ControlCommand::Reload),
+        Commands::Stop => send_control_command(ControlCommand::Stop),
+        Commands::EnableGroup { group } => send_control_command(ControlCommand::EnableGroup(group)),
+        Commands::DisableGroup { group } => {
+            send_control_command(ControlCommand::DisableGroup(group))
+        }
+        Commands::Profile { name } => send_control_command(ControlCommand::Profile(name)),
+        Commands::Test { trigger, text } => test_expansion(cli.config, trigger, text),
+        Commands::Replay { path } => replay_recording(cli.config, &path),
+        Commands::Bench {
+            rules,
+            keystrokes,
+            json,
+        } => run_bench(rules, keystrokes, json),
+        Commands::Add {
+            trigger,
+            expansion,
+            snippet,
+            title,
+            content,
+        } => add_entry(cli.config, trigger, expansion, snippet, title, content),
+        Commands::Remove {
+            trigger,
+            snippet_title,
+            force_empty,
+            dry_run,
+        } => remove_entry(cli.config, trigger, snippet_title, force_empty, dry_run),
+        Commands::List {
+            format,
+            autocorrect,
+        } => list_rules(cli.config, format, autocorrect),
+        Commands::Completions { shell } => {
+            print!("{}", completions::completion_script(shell));
+            Ok(())
+        }
+        Commands::Export { format, output } => export_rules(cli.config, format, output),
+        Commands::Import {
+            format,
+            path,
+            write,
+        } => import_rules(cli.config, format, path, write),
+        Commands::Secret { command } => manage_secret(command),
     }
 }
 
-fn run(config_path_override: Option<std::path::PathBuf>, debug: bool) -> Result<()> {
+type SharedRecorder = Arc<Mutex<EventRecorder<File>>>;
+
+/// How `slykey run` was asked to behave.
+struct RunOptions {
+    takeover: Takeover,
+    /// Log expansions instead of typing them.
+    dry_run: bool,
+    /// With `dry_run`, also show them as notifications.
+    notify: bool,
+    /// File to append key events to.
+    record: Option<PathBuf>,
+    record_redact: bool,
+}
+
+fn run(config_path_override: Option<std::path::PathBuf>, options: RunOptions) -> Result<()> {
     println!("slykey v{}", env!("CARGO_PKG_VERSION"));
-    let _instance_lock = InstanceLock::acquire()?;
+    let mut instance_lock = InstanceLock::acquire_with(options.takeover)?;
 
     let loaded = AppConfig::load(config_path_override)?;
+    print_config_warnings(&loaded.warnings);
     let config_path = loaded.path.clone();
+    instance_lock.set_config_path(config_path.clone());
+    let config_files = watched_paths(&loaded);
     let watch = loaded.config.watch;
     let config = loaded.config;
-    config.validate()?;
+    config.validate_deep()?;
+    print_lint_warnings(&config);
+
+    let session = display_session();
+    if let Some(limitation) = session.limitation() {
+        if !matches!(session, DisplaySession::XWayland(_)) {
+            anyhow::bail!(limitation);
+        }
+        eprintln!("WARNING: {limitation}");
+    }
 
     println!("Loaded config from {}", config_path.display());
+    if options.dry_run {
+        println!("Dry run: expansions are logged, not typed");
+    }
+    let recorder = match &options.record {
+        Some(path) => {
+            println!("Recording key events to {}", path.display());
+            if !options.record_redact {
+                eprintln!(
+                    "WARNING: the recording holds every key typed, passwords included; \
+                     add --record-redact to mask them"
+                );
+            }
+            Some(Arc::new(Mutex::new(EventRecorder::create(
+                path,
+                options.record_redact,
+            )?)))
+        }
+        None => None,
+    };
+
+    let backend = Arc::new(X11RdevBackend::new()?);
+    backend.apply_config(&config);
     println!("Listening on X11 backend (rdev)...");
+    let notifier = platform_notifier();
+    if matches!(session, DisplaySession::XWayland(_))
+        && (config.notifications.on_reload || config.notifications.on_expansion)
+    {
+        if let Err(err) = notifier.notify(
+            "slykey under Wayland",
+            "Only XWayland windows see expansions. Run `slykey doctor` for details.",
+        ) {
+            warn!("failed to send Wayland notification: {err}");
+        }
+    }
+    let mut engine = Engine::new(config);
+    let output: Arc<dyn OutputSink> = match (options.dry_run, options.notify) {
+        (true, true) => Arc::new(LoggingSink::dry_run().with_notifier(Arc::clone(&notifier))),
+        (true, false) => Arc::new(LoggingSink::dry_run()),
+        (false, _) => backend.clone(),
+    };
+    engine.set_output_worker(OutputWorker::spawn(output, OutputWorker::DEFAULT_CAPACITY)?);
+    engine.set_notifier(Arc::clone(&notifier));
+    engine.set_secret_store(platform_secrets());
+    // What would be typed ends up in the log, so secrets are masked there as
+    // in `slykey test`.
+    engine.set_mask_secrets(options.dry_run);
+    match X11ActiveWindow::new() {
+        Ok(active_window) => engine.set_active_window_provider(Arc::new(active_window)),
+        Err(err) => warn!("focused window detection unavailable: {err}"),
+    }
+    let stats_path = stats::default_stats_path();
+    if let Some(path) = &stats_path {
+        engine.set_stats(UsageStats::load(path));
+    }
 
+    let daemon = Arc::new(Daemon {
+        config_path,
+        config_files: Mutex::new(config_files),
+        stats_path,
+        engine: Mutex::new(engine),
+        backend: Arc::clone(&backend),
+        notifier,
+        #[cfg(all(target_os = "linux", feature = "tray"))]
+        tray: OnceLock::new(),
+    });
+
+    let shutdown = Arc::new(Shutdown::new());
+    start_shutdown_signals(&shutdown)?;
+
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    start_tray(&daemon, &shutdown);
     #[cfg(target_os = "linux")]
-    let _app_indicator = app_indicator::start(
-        config.snippets.clone(),
-        config.globals.clone(),
-        config.notifications.clone(),
-    );
+    start_notification_actions(&daemon);
+    start_picker(&daemon);
 
-    let backend = Arc::new(X11RdevBackend::new()?);
-    let mut engine = Engine::new(config);
-    engine.set_debug(debug);
-    engine.set_output(backend.clone());
-    let engine = Arc::new(Mutex::new(engine));
+    if daemon.stats_path.is_some() {
+        start_stats_flusher(Arc::clone(&daemon));
+    }
+    start_notification_flusher(Arc::clone(&daemon));
+    start_control_server(&instance_lock, Arc::clone(&daemon), Arc::clone(&shutdown))?;
 
     if watch {
         println!(
             "Watching config for changes: {}",
-            config_path.display()
+            daemon.config_path.display()
         );
-        start_config_watcher(config_path, Arc::clone(&engine));
     }
+    start_config_watcher(watch, Arc::clone(&daemon))?;
+    start_systemd_watchdog(Arc::clone(&daemon));
 
-    backend.listen(move |event| {
-        let mut guard = engine.lock().expect("engine mutex poisoned");
-        if let Err(err) = guard.handle_event(event) {
-            eprintln!("event handling error: {err}");
-            #[cfg(target_os = "linux")]
-            if let Err(notification_err) =
-                dbus_notification::send_notification("Expansion Error", &err.to_string())
-            {
-                eprintln!("failed to send expansion error notification: {notification_err}");
+    let cleanup_daemon = Arc::clone(&daemon);
+    shutdown.on_shutdown(move || {
+        cleanup_daemon.finish_output();
+        cleanup_daemon.flush_stats();
+    });
+    if let Some(recorder) = recorder.clone() {
+        shutdown.on_shutdown(move || {
+            if let Err(err) = recorder.lock().expect("recorder mutex poisoned").flush() {
+                warn!("failed to flush key event recording: {err:#}");
             }
+        });
+    }
+    shutdown.on_shutdown(move || drop(instance_lock));
+
+    let (listen_result_tx, listen_result_rx) = mpsc::channel();
+    start_listener(&backend, &daemon, &shutdown, recorder, listen_result_tx);
+    notify_systemd(&[("READY", "1"), ("STATUS", "Listening for triggers")]);
+
+    let reason = shutdown.wait();
+    info!("shutting down: {reason}");
+    notify_systemd(&[("STOPPING", "1")]);
+    shutdown.run_cleanups();
+    // The listener thread may still be blocked in rdev; returning ends it
+    // along with the process.
+    listen_result_rx.try_recv().unwrap_or(Ok(()))
+}
+
+/// Runs the key listener on its own thread, as rdev's listener cannot be
+/// stopped; shutdown is requested if it ever returns.
+fn start_listener(
+    backend: &Arc<X11RdevBackend>,
+    daemon: &Arc<Daemon>,
+    shutdown: &Arc<Shutdown>,
+    recorder: Option<SharedRecorder>,
+    result: mpsc::Sender<Result<()>>,
+) {
+    let backend = Arc::clone(backend);
+    let listener_daemon = Arc::clone(daemon);
+    let listener_shutdown = Arc::clone(shutdown);
+    let shutdown = Arc::clone(shutdown);
+    std::thread::spawn(move || {
+        let listen_result = backend.listen(move |event| {
+            // Keys typed while cleaning up must not start new expansions.
+            if listener_shutdown.is_requested() {
+                return;
+            }
+            if let Some(recorder) = &recorder {
+                if let Err(err) = recorder
+                    .lock()
+                    .expect("recorder mutex poisoned")
+                    .record(&event)
+                {
+                    warn!("failed to record key event: {err:#}");
+                }
+            }
+            handle_key_event(&listener_daemon, event);
+        });
+        let reason = match &listen_result {
+            Ok(()) => "key listener stopped".to_string(),
+            Err(err) => format!("key listener failed: {err:#}"),
+        };
+        let _ = result.send(listen_result);
+        shutdown.request(&reason);
+    });
+}
+
+fn handle_key_event(daemon: &Daemon, event: KeyEvent) {
+    if let Err(err) = handle_shared_event(&daemon.engine, event) {
+        error!("event handling error: {err}");
+        // security.confirm_commands asks through the same notification.
+        let summary = if err.downcast_ref::<ConfirmationRequired>().is_some() {
+            "Confirm Command"
+        } else {
+            "Expansion Error"
+        };
+        if let Err(notification_err) = daemon.notifier.notify(summary, &err.to_string()) {
+            warn!("failed to send expansion error notification: {notification_err}");
         }
-    })?;
+    }
+}
 
+/// SIGTERM and SIGINT request a clean shutdown; a second one exits right
+/// away in case cleanup hangs.
+fn start_shutdown_signals(shutdown: &Arc<Shutdown>) -> Result<()> {
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT]).context("failed to install SIGTERM/SIGINT handlers")?;
+    let shutdown = Arc::clone(shutdown);
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let name = if signal == SIGINT {
+                "SIGINT"
+            } else {
+                "SIGTERM"
+            };
+            if !shutdown.request(&format!("received {name}")) {
+                warn!("received {name} again, exiting without cleanup");
+                std::process::exit(1);
+            }
+        }
+    });
     Ok(())
 }
 
 fn validate_config(config_path_override: Option<std::path::PathBuf>) -> Result<()> {
     let loaded = AppConfig::load(config_path_override)?;
-    loaded.config.validate()?;
+    loaded.config.validate_deep()?;
+    print_config_warnings(&loaded.warnings);
+    print_lint_warnings(&loaded.config);
     println!("Config is valid: {}", loaded.path.display());
     Ok(())
 }
 
-fn start_config_watcher(config_path: PathBuf, engine: Arc<Mutex<Engine>>) {
-    std::thread::spawn(move || {
-        let mut last_seen_contents = std::fs::read_to_string(&config_path).unwrap_or_default();
+fn init_config(config_path_override: Option<PathBuf>, force: bool) -> Result<()> {
+    let (path, run_hint) = match config_path_override {
+        Some(path) => {
+            let hint = format!("slykey -c {}", path.display());
+            (path, hint)
+        }
+        None => (config::default_config_path()?, "slykey".to_string()),
+    };
+    config::write_starter_config(&path, force)?;
+    println!("Wrote starter config to {}", path.display());
+    println!("Now run: {run_hint}");
+    Ok(())
+}
 
-        loop {
-            std::thread::sleep(Duration::from_secs(1));
+#[cfg(target_os = "linux")]
+fn install_service(config_path_override: Option<PathBuf>, print: bool, force: bool) -> Result<()> {
+    let binary = std::env::current_exe().context("failed to resolve the slykey binary path")?;
+    let config_path = config::resolve_config_path(config_path_override)?;
+    let config_path = std::path::absolute(&config_path)
+        .with_context(|| format!("failed to resolve config path: {}", config_path.display()))?;
+    let unit = systemd::service_unit(&binary, &config_path)?;
+    if print {
+        print!("{unit}");
+        return Ok(());
+    }
 
-            let current_contents = match std::fs::read_to_string(&config_path) {
-                Ok(contents) => contents,
-                Err(err) => {
-                    eprintln!("failed to read config while watching: {err}");
-                    continue;
-                }
+    let path = systemd::default_service_path()?;
+    systemd::write_service_file(&path, &unit, force)?;
+    println!("Wrote systemd user service to {}", path.display());
+    println!(
+        "Now run: systemctl --user daemon-reload && systemctl --user enable --now {}",
+        systemd::SERVICE_NAME
+    );
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn install_service(
+    _config_path_override: Option<PathBuf>,
+    _print: bool,
+    _force: bool,
+) -> Result<()> {
+    anyhow::bail!("install-service needs systemd, which is only available on Linux")
+}
+
+fn print_config_warnings(warnings: &[String]) {
+    for warning in warnings {
+        warn!("{warning}");
+    }
+}
+
+/// Warns about rules [`AppConfig::lint`] expects to misbehave.
+fn print_lint_warnings(config: &AppConfig) {
+    for warning in config.lint() {
+        warn!("{}", config.describe_warning(&warning));
+    }
+}
+
+fn test_expansion(
+    config_path_override: Option<PathBuf>,
+    trigger: Option<String>,
+    text: Option<String>,
+) -> Result<()> {
+    let loaded = AppConfig::load(config_path_override)?;
+    loaded.config.validate()?;
+    // What the daemon uses at startup: top-level rules plus the default profile.
+    let config = loaded
+        .config
+        .effective(loaded.config.default_profile.as_deref());
+
+    let (input, newline) = match (trigger, text) {
+        (Some(trigger), _) => config
+            .expansions
+            .iter()
+            .find(|rule| rule.trigger == trigger)
+            .map(|rule| (rule.expansion.clone(), rule.newline))
+            .with_context(|| format!("no expansion configured for trigger: {trigger}"))?,
+        (None, Some(text)) => (text, NewlineMode::Text),
+        (None, None) => anyhow::bail!("either --trigger or --text is required"),
+    };
+
+    let runner = config.security.command_runner();
+    let secrets = platform_secrets();
+    let ctx = ExpansionContext {
+        rules: &config.expansions,
+        runner: runner.as_ref(),
+        secrets: Some(secrets.as_ref()),
+        totp_secrets: Some(&config.totp),
+        mask_secrets: true,
+        limits: config.expansion.limits(),
+        ..ExpansionContext::new(&config.globals)
+    };
+    let actions = apply_newline_mode(parse_expansion_actions_with_context(&input, &ctx)?, newline);
+    println!("{}", format_actions(&actions));
+    Ok(())
+}
+
+fn run_bench(rules: usize, keystrokes: usize, json: bool) -> Result<()> {
+    let report = bench::run_bench(rules, keystrokes)?;
+    if json {
+        let json =
+            serde_json::to_string_pretty(&report).context("failed to serialize bench report")?;
+        println!("{json}");
+    } else {
+        print!("{}", bench::format_report(&report));
+    }
+    Ok(())
+}
+
+fn replay_recording(config_path_override: Option<PathBuf>, path: &Path) -> Result<()> {
+    let loaded = AppConfig::load(config_path_override)?;
+    loaded.config.validate()?;
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read recording {}", path.display()))?;
+    let events = recording::parse_recording(&raw)?;
+
+    let fired = recording::replay(loaded.config, &events);
+    for expansion in &fired {
+        let trigger = expansion.trigger.as_deref().unwrap_or("-");
+        println!(
+            "event {} at {}ms: {trigger}: {}",
+            expansion.event + 1,
+            expansion.t_ms,
+            expansion.output.join(" ")
+        );
+    }
+    println!(
+        "{} events replayed, {} expansions fired",
+        events.len(),
+        fired
+            .iter()
+            .filter(|expansion| expansion.trigger.is_some())
+            .count()
+    );
+    Ok(())
+}
+
+fn doctor(config_path_override: Option<PathBuf>, json: bool) -> Result<()> {
+    let report = DoctorReport::new(doctor_checks(config_path_override));
+    if json {
+        let json =
+            serde_json::to_string_pretty(&report).context("failed to serialize doctor report")?;
+        println!("{json}");
+    } else {
+        print!("{}", doctor::format_report(&report.checks));
+    }
+    std::process::exit(report.worst.exit_code());
+}
+
+/// The session slykey runs in, from the display environment variables.
+fn display_session() -> DisplaySession {
+    let env = |name| std::env::var(name).ok();
+    DisplaySession::classify(
+        env("DISPLAY").as_deref(),
+        env("WAYLAND_DISPLAY").as_deref(),
+        env("XDG_SESSION_TYPE").as_deref(),
+    )
+}
+
+fn doctor_checks(config_path_override: Option<PathBuf>) -> Vec<CheckResult> {
+    let loaded = AppConfig::load(config_path_override);
+    let mut checks = vec![doctor::check_config(&loaded)];
+    if let Ok(loaded) = &loaded {
+        checks.push(doctor::check_macros(&loaded.config));
+    }
+
+    let env = |name| std::env::var(name).ok();
+    checks.push(doctor::check_display(
+        env("DISPLAY").as_deref(),
+        env("WAYLAND_DISPLAY").as_deref(),
+        env("XDG_SESSION_TYPE").as_deref(),
+    ));
+    checks.push(doctor::check_probe(
+        "x11",
+        X11ActiveWindow::new(),
+        |_| "connected to the X server".to_string(),
+        Severity::Fail,
+        "check DISPLAY and XAUTHORITY for the slykey process",
+    ));
+    checks.push(doctor::check_probe(
+        "enigo",
+        X11RdevBackend::new(),
+        |_| "key injector initialized".to_string(),
+        Severity::Fail,
+        "slykey needs X11 access to inject keys; see the x11 check",
+    ));
+    checks.extend(dbus_checks());
+    checks.push(doctor::check_instance_lock(
+        &instance_lock::default_lock_path(),
+    ));
+    checks
+}
+
+#[cfg(target_os = "linux")]
+fn dbus_checks() -> Vec<CheckResult> {
+    vec![
+        doctor::check_probe(
+            "d-bus session",
+            dbus_notification::probe_session_bus(),
+            |()| "connected to the session bus".to_string(),
+            Severity::Warn,
+            "notifications and the tray need DBUS_SESSION_BUS_ADDRESS from your desktop session",
+        ),
+        doctor::check_notification_server(dbus_notification::probe_notification_server()),
+        doctor::check_tray(
+            cfg!(feature = "tray"),
+            dbus_notification::session_name_has_owner("org.kde.StatusNotifierWatcher"),
+        ),
+    ]
+}
+
+#[cfg(not(target_os = "linux"))]
+fn dbus_checks() -> Vec<CheckResult> {
+    Vec::new()
+}
+
+fn add_entry(
+    config_path_override: Option<PathBuf>,
+    trigger: Option<String>,
+    expansion: Option<String>,
+    snippet: bool,
+    title: Option<String>,
+    content: Option<String>,
+) -> Result<()> {
+    let path = config::resolve_config_path(config_path_override)?;
+
+    if snippet {
+        let (Some(title), Some(content)) = (title, content) else {
+            anyhow::bail!("--snippet requires --title and --content");
+        };
+        config::append_snippet(&path, &title, &content)?;
+        println!("Added snippet '{title}' to {}", path.display());
+    } else {
+        let (Some(trigger), Some(expansion)) = (trigger, expansion) else {
+            anyhow::bail!("--trigger and --expansion are required");
+        };
+        config::append_expansion(&path, &trigger, &expansion)?;
+        println!("Added expansion '{trigger}' to {}", path.display());
+    }
+    Ok(())
+}
+
+fn remove_entry(
+    config_path_override: Option<PathBuf>,
+    trigger: Option<String>,
+    snippet_title: Option<String>,
+    force_empty: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let path = config::resolve_config_path(config_path_override)?;
+    let target = match (trigger, snippet_title) {
+        (Some(trigger), _) => RemoveTarget::Trigger(trigger),
+        (None, Some(title)) => RemoveTarget::SnippetTitle(title),
+        (None, None) => anyhow::bail!("--trigger or --snippet-title is required"),
+    };
+
+    let removed = config::remove_entry(&path, &target, force_empty, dry_run)?;
+    let rendered = serde_yaml::to_string(&removed).context("failed to render removed entries")?;
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    println!("{verb} from {}:\n{}", path.display(), rendered.trim_end());
+    Ok(())
+}
+
+fn list_rules(
+    config_path_override: Option<PathBuf>,
+    format: ListFormat,
+    autocorrect: bool,
+) -> Result<()> {
+    let loaded = AppConfig::load(config_path_override)?;
+    if autocorrect {
+        print_config_warnings(&loaded.warnings);
+        match &loaded.config.autocorrect_file {
+            None => eprintln!("No autocorrect_file is configured."),
+            Some(file) if !file.enabled => eprintln!("autocorrect_file {} is disabled.", file.path),
+            Some(_) => {}
+        }
+        match format {
+            ListFormat::Table => print!("{}", list::format_autocorrect_table(&loaded.config)),
+            ListFormat::PlainTriggers => print!("{}", list::format_plain_typos(&loaded.config)),
+        }
+        return Ok(());
+    }
+    // Same rules as `slykey test --trigger` looks up.
+    let config = loaded
+        .config
+        .effective(loaded.config.default_profile.as_deref());
+    print_config_warnings(&loaded.warnings);
+    match format {
+        ListFormat::Table => print!("{}", list::format_rule_table(&config)),
+        ListFormat::PlainTriggers => print!("{}", list::format_plain_triggers(&config)),
+    }
+    Ok(())
+}
+
+fn export_rules(
+    config_path_override: Option<PathBuf>,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let loaded = AppConfig::load(config_path_override)?;
+    print_config_warnings(&loaded.warnings);
+    let rules = RuleSet::from_config(&loaded.config);
+    let rendered = match format {
+        ExportFormat::Json => rules.to_json()?,
+        ExportFormat::Csv => rules.to_csv(),
+    };
+
+    match output {
+        Some(path) => {
+            std::fs::write(&path, rendered)
+                .with_context(|| format!("failed to write export: {}", path.display()))?;
+            eprintln!(
+                "Exported {} expansion(s), {} snippet(s) and {} global(s) to {}",
+                rules.expansions.len(),
+                rules.snippets.len(),
+                rules.globals.len(),
+                path.display()
+            );
+        }
+        None => print!("{rendered}"),
+    }
+    Ok(())
+}
+
+fn import_rules(
+    config_path_override: Option<PathBuf>,
+    format: ImportFormat,
+    source: PathBuf,
+    write: bool,
+) -> Result<()> {
+    // Duplicate detection is best effort when only printing, but appending to a
+    // config that does not load would just produce a broken file.
+    let existing = match AppConfig::load(config_path_override.clone()) {
+        Ok(loaded) => Some(loaded.config),
+        Err(err) if write => return Err(err),
+        Err(_) => None,
+    };
+
+    let (rules, issues, skipped) = match format {
+        ImportFormat::Espanso => {
+            let triggers = existing
+                .iter()
+                .flat_map(|config| &config.expansions)
+                .map(|rule| rule.trigger.clone());
+            let mut import = EspansoImport::new(triggers);
+            for file in espanso_import::espanso_match_files(&source)? {
+                let raw = std::fs::read_to_string(&file)
+                    .with_context(|| format!("failed to read espanso file: {}", file.display()))?;
+                import.add_file(&file, &raw)?;
+            }
+            let skipped = import.issues.iter().filter(|issue| issue.skipped).count();
+            let rules = RuleSet {
+                expansions: import.rules,
+                ..RuleSet::default()
             };
+            let issues = import.issues.iter().map(ToString::to_string).collect();
+            (rules, issues, skipped)
+        }
+        ImportFormat::SlykeyJson => {
+            let raw = std::fs::read_to_string(&source)
+                .with_context(|| format!("failed to read export: {}", source.display()))?;
+            let mut rules = RuleSet::from_json(&raw)?;
+            let issues = match &existing {
+                Some(config) => rules.remove_existing(config),
+                None => Vec::new(),
+            };
+            let skipped = issues.len();
+            (rules, issues, skipped)
+        }
+    };
+    for issue in &issues {
+        eprintln!("{issue}");
+    }
+    eprintln!(
+        "Converted {} rule(s), {} snippet(s) and {} global(s), skipped {skipped}",
+        rules.expansions.len(),
+        rules.snippets.len(),
+        rules.globals.len()
+    );
+
+    if !write {
+        print!("{}", rules.to_yaml()?);
+        return Ok(());
+    }
+    if rules.is_empty() {
+        return Ok(());
+    }
+    let path = config::resolve_config_path(config_path_override)?;
+    config::append_rules(&path, &rules.expansions, &rules.snippets, &rules.globals)?;
+    println!("Appended the imported rules to {}", path.display());
+    Ok(())
+}
+
+fn print_stats() -> Result<()> {
+    let Some(path) = stats::default_stats_path() else {
+        anyhow::bail!("could not determine state directory for stats file");
+    };
+    let usage = UsageStats::load(&path);
+    if usage.triggers.is_empty() {
+        println!("No expansions recorded yet ({})", path.display());
+        return Ok(());
+    }
+    print!("{}", stats::format_stats_table(&usage));
+    Ok(())
+}
+
+const NOTIFICATION_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
 
-            if current_contents == last_seen_contents {
-                continue;
+/// State shared by the listener, control socket, stats and config watcher threads.
+struct Daemon {
+    config_path: PathBuf,
+    /// The main config file plus its includes, as of the last (re)load.
+    config_files: Mutex<Vec<PathBuf>>,
+    stats_path: Option<PathBuf>,
+    engine: Mutex<Engine>,
+    backend: Arc<X11RdevBackend>,
+    notifier: Arc<dyn Notifier>,
+    #[cfg(all(target_os = "linux", feature = "tray"))]
+    tray: OnceLock<app_indicator::AppIndicator>,
+}
+
+impl Daemon {
+    fn flush_stats(&self) {
+        let Some(path) = &self.stats_path else {
+            return;
+        };
+        let snapshot = self
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .take_dirty_stats();
+        if let Some(snapshot) = snapshot {
+            if let Err(err) = snapshot.save(path) {
+                warn!("failed to save usage stats: {err}");
             }
+        }
+    }
 
-            match AppConfig::load(Some(config_path.clone())) {
-                Ok(loaded) => {
-                    if let Err(err) = loaded.config.validate() {
-                        eprintln!("config changed but validation failed: {err}");
-                        last_seen_contents = current_contents;
-                        continue;
-                    }
+    /// Lets an expansion that is still being typed finish, then stops the
+    /// output thread.
+    fn finish_output(&self) {
+        let worker = self
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .take_output_worker();
+        if let Some(worker) = worker {
+            worker.shutdown();
+        }
+    }
 
-                    let mut guard = engine.lock().expect("engine mutex poisoned");
-                    guard.reload_config(loaded.config);
-                    println!("Reloaded config from {}", config_path.display());
-                }
+    /// Loads and validates the config file, then swaps it into the running
+    /// engine, backend and tray. On error the previous config stays active.
+    fn reload(&self) -> Result<ReloadReport> {
+        // Compared as loaded: with the active profile merged in, its rules
+        // would be counted twice.
+        let previous = self
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .base_config()
+            .clone();
+        let config_files = reload_from_path(&self.config_path, &self.engine)?;
+        *self
+            .config_files
+            .lock()
+            .expect("config files mutex poisoned") = config_files;
+        let (config, report) = {
+            let engine = self.engine.lock().expect("engine mutex poisoned");
+            (
+                engine.config().clone(),
+                ReloadReport::new(&previous, &engine),
+            )
+        };
+        self.backend.apply_config(&config);
+
+        #[cfg(all(target_os = "linux", feature = "tray"))]
+        if let Some(tray) = self.tray.get() {
+            let active_profile = self
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .active_profile()
+                .map(str::to_string);
+            tray.update_config(&config, active_profile.as_deref());
+        }
+
+        self.flush_stats();
+        info!(
+            "reloaded config from {} ({})",
+            self.config_path.display(),
+            report.diff.summary()
+        );
+        Ok(report)
+    }
+
+    /// Shows the snippet picker: `picker.command` when set, otherwise the
+    /// tray's popup. Blocks until the picker closes.
+    fn open_picker(self: &Arc<Self>) {
+        let (entries, command) = {
+            let engine = self.engine.lock().expect("engine mutex poisoned");
+            (
+                engine.picker_entries(),
+                engine.config().picker.command.clone(),
+            )
+        };
+
+        if let Some(command) = command {
+            match picker::run_picker_command(&command, &entries) {
+                Ok(pick) => self.finish_picker(pick),
                 Err(err) => {
-                    eprintln!("config changed but reload failed: {err}");
+                    self.finish_picker(None);
+                    self.notify_picker_error(&format!("{err:#}"));
                 }
             }
+            return;
+        }
 
-            last_seen_contents = current_contents;
+        #[cfg(all(target_os = "linux", feature = "tray"))]
+        if let Some(tray) = self.tray.get() {
+            let daemon = Arc::clone(self);
+            tray.show_picker(entries, Box::new(move |pick| daemon.finish_picker(pick)));
+            return;
         }
+
+        self.finish_picker(None);
+        self.notify_picker_error("set picker.command (e.g. rofi -dmenu -i) or enable the tray");
+    }
+
+    /// Types the picked entry, or just re-enables matching when `pick` is `None`.
+    fn finish_picker(&self, pick: Option<PickerEntry>) {
+        let result = self
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .finish_picker(pick.as_ref());
+        if let Err(err) = result {
+            self.notify_picker_error(&format!("failed to type picked snippet: {err:#}"));
+        }
+    }
+
+    fn notify_picker_error(&self, body: &str) {
+        error!("snippet picker: {body}");
+        if let Err(err) = self.notifier.notify("Snippet Picker Failed", body) {
+            warn!("failed to send picker notification: {err}");
+        }
+    }
+
+    fn config_files(&self) -> Vec<PathBuf> {
+        self.config_files
+            .lock()
+            .expect("config files mutex poisoned")
+            .clone()
+    }
+}
+
+/// Starts the tray icon; its "Reload config" item goes through
+/// `Daemon::reload`, and its profile menu follows every profile switch.
+#[cfg(all(target_os = "linux", feature = "tray"))]
+fn start_tray(daemon: &Arc<Daemon>, shutdown: &Arc<Shutdown>) {
+    let (config, paused) = {
+        let guard = daemon.engine.lock().expect("engine mutex poisoned");
+        (guard.config().clone(), guard.pause_flag())
+    };
+    // Weak, because the daemon owns the tray.
+    let weak_daemon = Arc::downgrade(daemon);
+    let reload: app_indicator::ReloadCallback = Arc::new(move || match weak_daemon.upgrade() {
+        Some(daemon) => daemon.reload().map(|_| ()),
+        None => Ok(()),
+    });
+    let weak_daemon = Arc::downgrade(daemon);
+    let switch_profile: app_indicator::SwitchProfileCallback =
+        Arc::new(move |profile| match weak_daemon.upgrade() {
+            Some(daemon) => daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_active_profile(Some(profile)),
+            None => Ok(()),
+        });
+    let weak_daemon = Arc::downgrade(daemon);
+    let type_snippet: app_indicator::TypeSnippetCallback =
+        Arc::new(move |actions| match weak_daemon.upgrade() {
+            Some(daemon) => daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .type_actions(actions),
+            None => Ok(()),
+        });
+    let quit_shutdown = Arc::clone(shutdown);
+    let quit: app_indicator::QuitCallback = Arc::new(move || {
+        quit_shutdown.request("quit from tray menu");
     });
+
+    if let Some(tray) = app_indicator::start(
+        &config,
+        paused,
+        daemon.config_path.clone(),
+        reload,
+        switch_profile,
+        quit,
+        Arc::clone(&daemon.notifier),
+        type_snippet,
+    ) {
+        let _ = daemon.tray.set(tray);
+        let weak_daemon = Arc::downgrade(daemon);
+        let on_switch: slykey::core::engine::ProfileCallback = Arc::new(move |config, profile| {
+            if let Some(daemon) = weak_daemon.upgrade() {
+                if let Some(tray) = daemon.tray.get() {
+                    tray.update_config(config, profile);
+                }
+            }
+        });
+        daemon
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .set_profile_callback(on_switch);
+    }
+}
+
+/// Opens the snippet picker on its own thread when the picker hotkey is
+/// pressed, since the engine is locked while it handles the key.
+fn start_picker(daemon: &Arc<Daemon>) {
+    // Weak, because the daemon owns the engine holding this callback.
+    let weak_daemon = Arc::downgrade(daemon);
+    let callback: PickerCallback = Arc::new(move || {
+        if let Some(daemon) = weak_daemon.upgrade() {
+            std::thread::spawn(move || daemon.open_picker());
+        }
+    });
+    daemon
+        .engine
+        .lock()
+        .expect("engine mutex poisoned")
+        .set_picker_callback(callback);
+}
+
+/// Lets the "Undo" button on expansion notifications revert the expansion.
+#[cfg(target_os = "linux")]
+fn start_notification_actions(daemon: &Arc<Daemon>) {
+    let weak_daemon = Arc::downgrade(daemon);
+    let result = dbus_notification::listen_for_actions(Box::new(move |_id, action| {
+        if action != UNDO_ACTION {
+            return;
+        }
+        let Some(daemon) = weak_daemon.upgrade() else {
+            return;
+        };
+        let mut engine = daemon.engine.lock().expect("engine mutex poisoned");
+        match engine.undo_last() {
+            Ok(true) => info!("undid last expansion"),
+            Ok(false) => info!("nothing to undo: text was typed after the expansion"),
+            Err(err) => warn!("failed to undo expansion: {err}"),
+        }
+    }));
+    if let Err(err) = result {
+        warn!("notification actions unavailable: {err:#}");
+    }
+}
+
+fn start_stats_flusher(daemon: Arc<Daemon>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(60));
+        daemon.flush_stats();
+    });
+}
+
+/// Sends coalesced expansion notifications once their interval has passed,
+/// even if no further keys are typed.
+fn start_notification_flusher(daemon: Arc<Daemon>) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(NOTIFICATION_FLUSH_INTERVAL);
+        daemon
+            .engine
+            .lock()
+            .expect("engine mutex poisoned")
+            .flush_notifications();
+    });
+}
+
+fn send_control_command(command: ControlCommand) -> Result<()> {
+    let reply = control::send_command(&instance_lock::default_lock_path(), command)?;
+    if !reply.ok {
+        anyhow::bail!(reply.message);
+    }
+    println!("{}", reply.message);
+    Ok(())
+}
+
+/// How long `status --json` waits for the daemon; status bars poll it, so a
+/// hung daemon shows as not running instead of stalling the bar.
+const STATUS_JSON_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn print_status_json() -> Result<()> {
+    let reply = match control::send_command_with_timeout(
+        &instance_lock::default_lock_path(),
+        ControlCommand::StatusJson,
+        STATUS_JSON_TIMEOUT,
+    ) {
+        Ok(reply) => reply,
+        Err(err) => {
+            debug!("{err:#}");
+            println!("{NOT_RUNNING_STATUS_JSON}");
+            std::process::exit(1);
+        }
+    };
+    let status = DaemonStatus::from_reply(&reply)?;
+    println!(
+        "{}",
+        serde_json::to_string(&status).context("failed to serialize status")?
+    );
+    Ok(())
+}
+
+fn start_control_server(
+    instance_lock: &InstanceLock,
+    daemon: Arc<Daemon>,
+    shutdown: Arc<Shutdown>,
+) -> Result<()> {
+    let server = instance_lock.control_server()?;
+
+    std::thread::spawn(move || {
+        server.serve(|command| handle_control_command(command, &daemon));
+
+        // `serve` only returns after acknowledging `stop`.
+        shutdown.request("stopped by control command");
+    });
+    Ok(())
+}
+
+fn handle_control_command(command: ControlCommand, daemon: &Daemon) -> Result<String> {
+    match command {
+        ControlCommand::StatusJson => {
+            let status = DaemonStatus::from_engine(
+                &daemon.engine.lock().expect("engine mutex poisoned"),
+                &daemon.config_path,
+            );
+            serde_json::to_string(&status).context("failed to serialize status")
+        }
+        ControlCommand::Status => {
+            let guard = daemon.engine.lock().expect("engine mutex poisoned");
+            let mut status = format!(
+                "slykey v{}\nconfig: {}\nrules: {}\npaused: {}",
+                env!("CARGO_PKG_VERSION"),
+                daemon.config_path.display(),
+                guard.config().expansions.len(),
+                if guard.is_paused() { "yes" } else { "no" }
+            );
+            if let Some(profile) = guard.active_profile() {
+                status.push_str(&format!("\nprofile: {profile}"));
+            }
+            let disabled_groups = guard.disabled_groups();
+            if !disabled_groups.is_empty() {
+                status.push_str(&format!(
+                    "\ndisabled groups: {}",
+                    disabled_groups.join(", ")
+                ));
+            }
+            Ok(status)
+        }
+        ControlCommand::Pause => {
+            daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_paused(true);
+            Ok("Expansion paused".to_string())
+        }
+        ControlCommand::Resume => {
+            daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_paused(false);
+            Ok("Expansion resumed".to_string())
+        }
+        ControlCommand::Reload => {
+            let report = daemon.reload()?;
+            Ok(format!(
+                "Reloaded config from {}: {}",
+                daemon.config_path.display(),
+                report.describe()
+            ))
+        }
+        ControlCommand::Stop => Ok("Stopping slykey".to_string()),
+        ControlCommand::Whoami => anyhow::bail!("whoami is answered by the instance lock"),
+        ControlCommand::EnableGroup(group) => {
+            daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_group_enabled(&group, true)?;
+            Ok(format!("Enabled group '{group}' until the next reload"))
+        }
+        ControlCommand::DisableGroup(group) => {
+            daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_group_enabled(&group, false)?;
+            Ok(format!("Disabled group '{group}' until the next reload"))
+        }
+        ControlCommand::Profile(Some(profile)) => {
+            daemon
+                .engine
+                .lock()
+                .expect("engine mutex poisoned")
+                .set_active_profile(Some(&profile))?;
+            Ok(format!("Switched to profile '{profile}'"))
+        }
+        ControlCommand::Profile(None) => {
+            let guard = daemon.engine.lock().expect("engine mutex poisoned");
+            let profiles = guard.config().profile_names();
+            if profiles.is_empty() {
+                return Ok("The config defines no profiles".to_string());
+            }
+            Ok(format!(
+                "active profile: {}\nprofiles: {}",
+                guard.active_profile().unwrap_or("none"),
+                profiles.join(", ")
+            ))
+        }
+    }
+}
+
+/// Reloads on SIGHUP always, and on changes to the config or its includes
+/// when `watch` is set.
+fn start_config_watcher(watch: bool, daemon: Arc<Daemon>) -> Result<()> {
+    let mut watcher = ConfigWatcher::new(daemon.config_files(), watch)?;
+
+    let mut signals = Signals::new([SIGHUP]).context("failed to install SIGHUP handler")?;
+    let signal_sender = watcher.sender();
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if signal_sender.send(WatchInput::Signal).is_err() {
+                break;
+            }
+        }
+    });
+
+    std::thread::spawn(move || loop {
+        watcher.next_reload();
+        match daemon.reload() {
+            Ok(report) => notify_reload_success(&daemon, &report),
+            Err(err) => {
+                error!("config reload failed, keeping previous config: {err:#}");
+                notify_reload_failure(&daemon, &err);
+            }
+        }
+        // The include list may have changed; follow the files that are in use now.
+        watcher.set_paths(daemon.config_files());
+    });
+
+    Ok(())
+}
+
+fn notify_reload_success(daemon: &Daemon, report: &ReloadReport) {
+    let enabled = daemon
+        .engine
+        .lock()
+        .expect("engine mutex poisoned")
+        .config()
+        .notifications
+        .on_reload;
+    if !enabled {
+        return;
+    }
+    if let Err(err) = daemon
+        .notifier
+        .notify("Config Reloaded", &report.describe())
+    {
+        warn!("failed to send reload notification: {err}");
+    }
+}
+
+/// Reports the first line of the error; the log has the rest.
+fn notify_reload_failure(daemon: &Daemon, err: &anyhow::Error) {
+    let enabled = {
+        let engine = daemon.engine.lock().expect("engine mutex poisoned");
+        let notifications = &engine.config().notifications;
+        notifications.on_reload || notifications.on_expansion
+    };
+    if !enabled {
+        return;
+    }
+    let message = format!("{err:#}");
+    let first_line = message.lines().next().unwrap_or_default();
+    if let Err(notification_err) = daemon.notifier.notify("Config Reload Failed", first_line) {
+        warn!("failed to send reload failure notification: {notification_err}");
+    }
+}
+
+fn manage_secret(command: SecretCommand) -> Result<()> {
+    let secrets = platform_secrets();
+    match command {
+        SecretCommand::Set { name } => {
+            let value = read_secret_value(&name)?;
+            secrets.set(&name, &value)?;
+            println!("Stored secret '{name}'; use it as {{{{SECRET:{name}}}}}");
+        }
+        SecretCommand::Rm { name } => {
+            secrets.remove(&name)?;
+            println!("Removed secret '{name}'");
+        }
+    }
+    Ok(())
+}
+
+/// The value for `slykey secret set`: typed at a prompt without echo when
+/// stdin is a terminal, otherwise all of stdin minus one trailing newline.
+fn read_secret_value(name: &str) -> Result<String> {
+    let stdin = std::io::stdin();
+    let mut value = String::new();
+    if stdin.is_terminal() {
+        eprint!("Value for secret '{name}': ");
+        let _echo_off = EchoOff::new();
+        stdin
+            .read_line(&mut value)
+            .context("failed to read secret")?;
+        eprintln!();
+    } else {
+        stdin
+            .lock()
+            .read_to_string(&mut value)
+            .context("failed to read secret from stdin")?;
+    }
+    let value = value
+        .strip_suffix('\n')
+        .map(|value| value.strip_suffix('\r').unwrap_or(value))
+        .unwrap_or(&value);
+    if value.is_empty() {
+        anyhow::bail!("refusing to store an empty secret");
+    }
+    Ok(value.to_string())
+}
+
+/// Turns terminal echo off on stdin until dropped.
+struct EchoOff(Option<libc::termios>);
+
+impl EchoOff {
+    fn new() -> Self {
+        // SAFETY: tcgetattr/tcsetattr only read and write the termios struct
+        // passed to them; stdin is a valid descriptor for the whole process.
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Self(None);
+            }
+            let mut silent = termios;
+            silent.c_lflag &= !libc::ECHO;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent);
+            Self(Some(termios))
+        }
+    }
+}
+
+impl Drop for EchoOff {
+    fn drop(&mut self) {
+        if let Some(termios) = &self.0 {
+            // SAFETY: restores the settings read in `new`.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, termios);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn platform_secrets() -> Arc<dyn SecretStore> {
+    Arc::new(secret_service::SessionSecretStore)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn platform_secrets() -> Arc<dyn SecretStore> {
+    Arc::new(slykey::io::secrets::NullSecretStore)
+}
+
+#[cfg(target_os = "linux")]
+fn notify_systemd(fields: &[(&str, &str)]) {
+    if let Err(err) = systemd::notify(fields) {
+        warn!("{err:#}");
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn notify_systemd(_fields: &[(&str, &str)]) {}
+
+/// Pings the systemd watchdog while the engine can still be locked, so a
+/// listener stuck holding it (or one that panicked) gets slykey restarted.
+/// A `{{CMD:...}}` holds the lock while it runs, hence the unit's advice to
+/// give slow commands a `timeout` below `WatchdogSec`.
+#[cfg(target_os = "linux")]
+fn start_systemd_watchdog(daemon: Arc<Daemon>) {
+    let Some(interval) = systemd::watchdog_interval() else {
+        return;
+    };
+    std::thread::spawn(move || loop {
+        if daemon.engine.lock().is_ok() {
+            notify_systemd(&[("WATCHDOG", "1")]);
+        }
+        std::thread::sleep(interval);
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+fn start_systemd_watchdog(_daemon: Arc<Daemon>) {}
+
+#[cfg(target_os = "linux")]
+fn platform_notifier() -> Arc<dyn Notifier> {
+    Arc::new(dbus_notification::DbusNotifier)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn platform_notifier() -> Arc<dyn Notifier> {
+    Arc::new(slykey::io::notification::NullNotifier)
 }
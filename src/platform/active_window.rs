@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{Atom, AtomEnum, ConnectionExt, Window};
+use x11rb::rust_connection::RustConnection;
+
+use crate::io::window::ActiveWindowProvider;
+
+const CACHE_TTL: Duration = Duration::from_millis(200);
+
+pub struct X11ActiveWindow {
+    connection: RustConnection,
+    root: Window,
+    net_active_window: Atom,
+    cache: Mutex<Option<CachedClasses>>,
+}
+
+struct CachedClasses {
+    fetched_at: Instant,
+    classes: Vec<String>,
+}
+
+impl X11ActiveWindow {
+    pub fn new() -> Result<Self> {
+        let (connection, screen_num) =
+            x11rb::connect(None).context("failed to connect to X11 display")?;
+        let root = connection.setup().roots[screen_num].root;
+        let net_active_window = connection
+            .intern_atom(false, b"_NET_ACTIVE_WINDOW")?
+            .reply()
+            .context("failed to intern _NET_ACTIVE_WINDOW atom")?
+            .atom;
+
+        Ok(Self {
+            connection,
+            root,
+            net_active_window,
+            cache: Mutex::new(None),
+        })
+    }
+
+    fn query_active_window_classes(&self) -> Result<Vec<String>> {
+        let active = self
+            .connection
+            .get_property(
+                false,
+                self.root,
+                self.net_active_window,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()
+            .context("failed to read _NET_ACTIVE_WINDOW")?;
+        let window = active
+            .value32()
+            .and_then(|mut values| values.next())
+            .filter(|window| *window != 0)
+            .context("no focused window reported by the window manager")?;
+
+        let wm_class = self
+            .connection
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)?
+            .reply()
+            .context("failed to read WM_CLASS of focused window")?;
+
+        Ok(parse_wm_class(&wm_class.value))
+    }
+}
+
+impl ActiveWindowProvider for X11ActiveWindow {
+    fn active_window_classes(&self) -> Result<Vec<String>> {
+        let mut cache = self
+            .cache
+            .lock()
+            .expect("active window cache mutex poisoned");
+        if let Some(cached) = cache.as_ref() {
+            if cached.fetched_at.elapsed() < CACHE_TTL {
+                return Ok(cached.classes.clone());
+            }
+        }
+
+        let classes = self.query_active_window_classes()?;
+        *cache = Some(CachedClasses {
+            fetched_at: Instant::now(),
+            classes: classes.clone(),
+        });
+        Ok(classes)
+    }
+}
+
+/// WM_CLASS holds two NUL-terminated strings: the instance name and the class name.
+fn parse_wm_class(value: &[u8]) -> Vec<String> {
+    value
+        .split(|byte| *byte == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).into_owned())
+        .collect()
+}
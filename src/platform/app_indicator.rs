@@ -1,60 +1,179 @@
 use std::env;
 use std::fs;
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::process;
-use std::sync::mpsc::{self, Sender};
+use std::cell::{Cell, RefCell};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
 use std::thread::JoinHandle;
 use std::time::Duration;
 
+use chrono::Local;
+use gtk::glib::{self, ControlFlow};
 use gtk::prelude::*;
 use libappindicator::{AppIndicator as LibAppIndicator, AppIndicatorStatus};
+use tracing::{error, warn};
 
-use crate::config::{MenuSnippet, NotificationConfig};
-use crate::core::expansion::render_template_macros;
-use crate::platform::dbus_notification;
+use crate::config::{AppConfig, Globals, MenuSnippet, NotificationConfig, SnippetMode};
+use crate::core::expansion::{render_template_macros_with_context, ExpansionContext, OutputAction};
+use crate::core::picker::{filter_entries, PickerEntry};
+use crate::core::shell_command::DenyAllRunner;
+use crate::io::notification::Notifier;
+use crate::platform::tray_menu::{
+    edit_config_command, tray_menu_layout, typed_snippet_actions, CopiedSnippet, CopyHistory,
+    SnippetMenuEntry, TrayMenuEntry,
+};
+
+/// Called on the GTK thread when "Reload config" is clicked.
+pub type ReloadCallback = Arc<dyn Fn() -> anyhow::Result<()> + Send + Sync>;
+
+/// Called on the GTK thread with the profile picked in the "Profile" submenu.
+pub type SwitchProfileCallback = Arc<dyn Fn(&str) -> anyhow::Result<()> + Send + Sync>;
+
+/// Called on the GTK thread with the actions of a clicked `mode: type`
+/// snippet; types them as an expansion would be.
+pub type TypeSnippetCallback = Arc<dyn Fn(Vec<OutputAction>) -> anyhow::Result<()> + Send + Sync>;
+
+/// Called on the GTK thread when "Quit" is clicked; starts the daemon's
+/// shutdown instead of exiting on the spot.
+pub type QuitCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Called on the GTK thread once the picker popup closes, with the chosen
+/// entry or `None` when it was dismissed.
+pub type PickCallback = Box<dyn FnOnce(Option<PickerEntry>) + Send>;
 
 pub struct AppIndicator {
     _gtk_thread: JoinHandle<()>,
+    updates: Sender<MenuContents>,
+}
+
+impl AppIndicator {
+    /// Rebuilds the menu from `config`, e.g. after a config reload or a
+    /// profile switch.
+    pub fn update_config(&self, config: &AppConfig, active_profile: Option<&str>) {
+        if self
+            .updates
+            .send(MenuContents::from_config(config, active_profile))
+            .is_err()
+        {
+            warn!("tray thread is gone; menu not updated");
+        }
+    }
+
+    /// Opens the searchable snippet picker popup listing `entries`.
+    pub fn show_picker(&self, entries: Vec<PickerEntry>, on_pick: PickCallback) {
+        glib::idle_add_once(move || show_picker_window(entries, on_pick));
+    }
+}
+
+/// Everything the menu is built from.
+struct MenuContents {
+    snippets: Vec<MenuSnippet>,
+    globals: Globals,
+    notifications: NotificationConfig,
+    edit_command: Option<String>,
+    profiles: Vec<String>,
+    active_profile: Option<String>,
+}
+
+impl MenuContents {
+    fn from_config(config: &AppConfig, active_profile: Option<&str>) -> Self {
+        Self {
+            profiles: config.profiles.keys().cloned().collect(),
+            active_profile: active_profile.map(str::to_string),
+            snippets: config.snippets.clone(),
+            globals: config.globals.clone(),
+            notifications: config.notifications.clone(),
+            edit_command: config.tray.edit_command.clone(),
+        }
+    }
+}
+
+/// What menu items act on besides the menu contents.
+struct MenuHandles {
+    config_path: PathBuf,
+    reload: ReloadCallback,
+    switch_profile: SwitchProfileCallback,
+    quit: QuitCallback,
+    notifier: Arc<dyn Notifier>,
+    type_snippet: TypeSnippetCallback,
+}
+
+/// Snippets copied from the menu, kept on the GTK thread. A copy only marks
+/// the menu as stale; it is rebuilt on the next update tick rather than from
+/// inside the activated item's handler.
+#[derive(Default)]
+struct CopyTracker {
+    history: RefCell<CopyHistory>,
+    changed: Cell<bool>,
+}
+
+impl CopyTracker {
+    fn record(&self, copied: CopiedSnippet) {
+        self.history.borrow_mut().record(copied);
+        self.changed.set(true);
+    }
 }
 
 const BUNDLED_TRAY_ICON_NAME: &str = "slykey";
 const BUNDLED_TRAY_ICON_SVG: &[u8] = include_bytes!("slykey.svg");
+/// How often the GTK thread checks for menu updates and pause changes.
+const MENU_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
 
+/// Shows the config's `default_profile` as active, as a new engine starts
+/// with it. Snippets with `mode: type` are typed through `type_snippet`.
+#[allow(clippy::too_many_arguments)]
 pub fn start(
-    snippets: Vec<MenuSnippet>,
-    globals: HashMap<String, String>,
-    notifications: NotificationConfig,
+    config: &AppConfig,
+    paused: Arc<AtomicBool>,
+    config_path: PathBuf,
+    reload: ReloadCallback,
+    switch_profile: SwitchProfileCallback,
+    quit: QuitCallback,
+    notifier: Arc<dyn Notifier>,
+    type_snippet: TypeSnippetCallback,
 ) -> Option<AppIndicator> {
     if env::var_os("DISPLAY").is_none() {
-        eprintln!("warning: DISPLAY is not set; cannot create tray icon");
+        warn!("DISPLAY is not set; cannot create tray icon");
         return None;
     }
     if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
-        eprintln!("warning: DBus session is not set; appindicator may not be visible");
+        warn!("DBus session is not set; appindicator may not be visible");
     }
 
     let (ready_tx, ready_rx) = mpsc::channel();
+    let (updates_tx, updates_rx) = mpsc::channel();
+    let contents = MenuContents::from_config(config, config.default_profile.as_deref());
+    let handles = MenuHandles {
+        config_path,
+        reload,
+        switch_profile,
+        quit,
+        notifier,
+        type_snippet,
+    };
     let gtk_thread = std::thread::spawn(move || {
-        if let Err(err) = run_indicator(ready_tx, snippets, globals, notifications) {
-            eprintln!("tray thread exited: {err}");
+        if let Err(err) = run_indicator(ready_tx, contents, updates_rx, paused, handles) {
+            error!("tray thread exited: {err}");
         }
     });
 
     match ready_rx.recv_timeout(Duration::from_secs(3)) {
         Ok(Ok(())) => Some(AppIndicator {
             _gtk_thread: gtk_thread,
+            updates: updates_tx,
         }),
         Ok(Err(err)) => {
-            eprintln!("failed to start tray icon: {err}");
+            error!("failed to start tray icon: {err}");
             let _ = gtk_thread.join();
             None
         }
         Err(_) => {
-            eprintln!("warning: tray startup timed out; keeping tray thread running");
+            warn!("tray startup timed out; keeping tray thread running");
             Some(AppIndicator {
                 _gtk_thread: gtk_thread,
+                updates: updates_tx,
             })
         }
     }
@@ -62,9 +181,10 @@ pub fn start(
 
 fn run_indicator(
     ready_tx: Sender<Result<(), String>>,
-    snippets: Vec<MenuSnippet>,
-    globals: HashMap<String, String>,
-    notifications: NotificationConfig,
+    contents: MenuContents,
+    updates: Receiver<MenuContents>,
+    paused: Arc<AtomicBool>,
+    handles: MenuHandles,
 ) -> Result<(), String> {
     if let Err(err) = gtk::init() {
         let msg = err.to_string();
@@ -77,67 +197,475 @@ fn run_indicator(
     indicator.set_title("slykey");
     indicator.set_status(AppIndicatorStatus::Active);
 
+    let copies = Rc::new(CopyTracker::default());
+    let mut contents = contents;
     let mut menu = gtk::Menu::new();
-    let running_item = gtk::MenuItem::with_label("Running");
-    running_item.set_sensitive(false);
-    menu.append(&running_item);
-    running_item.show();
-
-    if !snippets.is_empty() {
-        let separator = gtk::SeparatorMenuItem::new();
-        menu.append(&separator);
-        separator.show();
-    }
-    let has_snippets = !snippets.is_empty();
-
-    let globals = Arc::new(globals);
-    let notify_on_snippet_copy = notifications.on_snippet_copy;
-
-    for snippet in snippets {
-        let item = gtk::MenuItem::with_label(&snippet.title);
-        let title = snippet.title;
-        let content = snippet.content;
-        let globals = Arc::clone(&globals);
-        item.connect_activate(move |_| {
-            let text = match render_template_macros(&content, &globals) {
-                Ok(rendered) => rendered,
-                Err(err) => {
-                    eprintln!("failed to render snippet template macros: {err}");
-                    content.clone()
-                }
-            };
-            let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
-            clipboard.set_text(&text);
-            clipboard.store();
+    let mut enabled_item = populate_menu(&menu, &contents, &paused, &handles, &copies);
 
-            if notify_on_snippet_copy {
-                if let Err(err) = dbus_notification::send_notification("Copied Snippet", &title) {
-                    eprintln!("failed to send snippet notification: {err}");
-                }
+    indicator.set_menu(&mut menu);
+    let _ = ready_tx.send(Ok(()));
+
+    let mut shown_paused = false;
+    glib::timeout_add_local(MENU_UPDATE_INTERVAL, move || {
+        // Only the newest config matters if several reloads queued up.
+        if let Some(update) = updates.try_iter().last() {
+            contents = update;
+            copies.changed.set(true);
+        }
+        if copies.changed.replace(false) {
+            enabled_item = populate_menu(&menu, &contents, &paused, &handles, &copies);
+        }
+
+        // Pause can also be toggled by the hotkey or `slykey pause`.
+        let is_paused = paused.load(Ordering::Relaxed);
+        if let Some(item) = &enabled_item {
+            if item.is_active() == is_paused {
+                item.set_active(!is_paused);
             }
-        });
+        }
+        if is_paused != shown_paused {
+            indicator.set_title(if is_paused {
+                "slykey (paused)"
+            } else {
+                "slykey"
+            });
+            shown_paused = is_paused;
+        }
+        ControlFlow::Continue
+    });
+
+    gtk::main();
+    Ok(())
+}
+
+/// Replaces every item in `menu` with the layout for `contents` and returns
+/// the new "Enabled" item.
+fn populate_menu(
+    menu: &gtk::Menu,
+    contents: &MenuContents,
+    paused: &Arc<AtomicBool>,
+    handles: &MenuHandles,
+    copies: &Rc<CopyTracker>,
+) -> Option<gtk::CheckMenuItem> {
+    for child in menu.children() {
+        menu.remove(&child);
+    }
+
+    let globals = Arc::new(contents.globals.clone());
+    let copy_notifier = contents
+        .notifications
+        .on_snippet_copy
+        .then(|| Arc::clone(&handles.notifier));
+    let mut enabled = None;
+
+    let history = copies.history.borrow().clone();
+    let layout = tray_menu_layout(
+        &contents.snippets,
+        &contents.profiles,
+        contents.active_profile.as_deref(),
+        &history,
+    );
+    for entry in layout {
+        let item: gtk::MenuItem = match entry {
+            TrayMenuEntry::Status => {
+                let running_item = gtk::MenuItem::with_label("Running");
+                running_item.set_sensitive(false);
+                running_item
+            }
+            TrayMenuEntry::Enabled => {
+                let enabled_item = gtk::CheckMenuItem::with_label("Enabled");
+                enabled_item.set_active(!paused.load(Ordering::Relaxed));
+                let paused = Arc::clone(paused);
+                enabled_item.connect_toggled(move |item| {
+                    paused.store(!item.is_active(), Ordering::Relaxed);
+                });
+                enabled = Some(enabled_item.clone());
+                enabled_item.upcast()
+            }
+            TrayMenuEntry::Profiles { names, active } => profiles_menu_item(names, active, handles),
+            TrayMenuEntry::Separator => gtk::SeparatorMenuItem::new().upcast(),
+            TrayMenuEntry::Snippet(entry) => {
+                snippet_menu_item(entry, &globals, &copy_notifier, handles, copies)
+            }
+            TrayMenuEntry::RecentlyCopied(history) => {
+                recently_copied_menu_item(history, &globals, &copy_notifier, copies)
+            }
+            TrayMenuEntry::EditConfig => {
+                let edit_item = gtk::MenuItem::with_label("Edit config…");
+                let edit_command = contents.edit_command.clone();
+                let path = handles.config_path.clone();
+                let notifier = Arc::clone(&handles.notifier);
+                edit_item.connect_activate(move |_| {
+                    open_config_editor(edit_command.as_deref(), &path, notifier.as_ref());
+                });
+                edit_item
+            }
+            TrayMenuEntry::ReloadConfig => {
+                let reload_item = gtk::MenuItem::with_label("Reload config");
+                let reload = Arc::clone(&handles.reload);
+                let notifier = Arc::clone(&handles.notifier);
+                reload_item.connect_activate(move |_| {
+                    if let Err(err) = reload() {
+                        error!("config reload failed, keeping previous config: {err:#}");
+                        notify_error(
+                            notifier.as_ref(),
+                            "Config Reload Failed",
+                            &format!("{err:#}"),
+                        );
+                    }
+                });
+                reload_item
+            }
+            TrayMenuEntry::Quit => {
+                let quit_item = gtk::MenuItem::with_label("Quit");
+                let quit = Arc::clone(&handles.quit);
+                quit_item.connect_activate(move |_| quit());
+                quit_item
+            }
+        };
         menu.append(&item);
-        item.show();
     }
 
-    if has_snippets {
-        let separator = gtk::SeparatorMenuItem::new();
-        menu.append(&separator);
-        separator.show();
+    menu.show_all();
+    enabled
+}
+
+/// The "Profile" submenu. Items are check items drawn as radios rather than
+/// a radio group, so none is checked while no profile is active; the menu is
+/// rebuilt after every switch.
+fn profiles_menu_item(
+    names: &[String],
+    active: Option<&str>,
+    handles: &MenuHandles,
+) -> gtk::MenuItem {
+    let submenu = gtk::Menu::new();
+    for name in names {
+        let item = gtk::CheckMenuItem::with_label(name);
+        item.set_draw_as_radio(true);
+        item.set_active(active == Some(name.as_str()));
+        let name = name.clone();
+        let switch_profile = Arc::clone(&handles.switch_profile);
+        let notifier = Arc::clone(&handles.notifier);
+        item.connect_toggled(move |item| {
+            if !item.is_active() {
+                // Clicking the active profile keeps it selected.
+                item.set_active(true);
+                return;
+            }
+            if let Err(err) = switch_profile(&name) {
+                error!("failed to switch profile: {err:#}");
+                notify_error(
+                    notifier.as_ref(),
+                    "Profile Switch Failed",
+                    &format!("{err:#}"),
+                );
+            }
+        });
+        submenu.append(&item);
+    }
+    let item = gtk::MenuItem::with_label("Profile");
+    item.set_submenu(Some(&submenu));
+    item
+}
+
+/// Builds a snippet row, a separator, or a group's submenu. Copies are
+/// announced through `copy_notifier` when set and recorded in `copies`.
+fn snippet_menu_item(
+    entry: SnippetMenuEntry,
+    globals: &Arc<Globals>,
+    copy_notifier: &Option<Arc<dyn Notifier>>,
+    handles: &MenuHandles,
+    copies: &Rc<CopyTracker>,
+) -> gtk::MenuItem {
+    match entry {
+        SnippetMenuEntry::Snippet(snippet) if snippet.mode == SnippetMode::Type => {
+            typed_snippet_item(snippet, Arc::clone(globals), handles)
+        }
+        SnippetMenuEntry::Snippet(snippet) => {
+            let item = snippet_item(snippet, Arc::clone(globals), copy_notifier.clone(), copies);
+            if copies.history.borrow().is_latest(snippet) {
+                set_bold_label(&item, &snippet.title);
+            }
+            item
+        }
+        SnippetMenuEntry::Separator => gtk::SeparatorMenuItem::new().upcast(),
+        SnippetMenuEntry::Group { name, entries } => {
+            let submenu = gtk::Menu::new();
+            for entry in entries {
+                submenu.append(&snippet_menu_item(
+                    entry,
+                    globals,
+                    copy_notifier,
+                    handles,
+                    copies,
+                ));
+            }
+            let item = gtk::MenuItem::with_label(name);
+            item.set_submenu(Some(&submenu));
+            item
+        }
     }
+}
 
-    let quit_item = gtk::MenuItem::with_label("Quit");
-    quit_item.connect_activate(|_| process::exit(0));
-    menu.append(&quit_item);
-    quit_item.show();
+fn snippet_item(
+    snippet: &MenuSnippet,
+    globals: Arc<Globals>,
+    copy_notifier: Option<Arc<dyn Notifier>>,
+    copies: &Rc<CopyTracker>,
+) -> gtk::MenuItem {
+    let item = gtk::MenuItem::with_label(&snippet.title);
+    let snippet = snippet.clone();
+    let copies = Rc::clone(copies);
+    item.connect_activate(move |_| {
+        copy_snippet(&snippet.title, &snippet.content, &globals, &copy_notifier);
+        copies.record(CopiedSnippet::new(&snippet, Local::now()));
+    });
+    item
+}
 
-    menu.show_all();
+/// The "Recently copied" submenu. Entries keep the snippet's raw content, so
+/// re-copying renders it again: `{{DATE}}` gives today's date, not the one
+/// from the first copy.
+fn recently_copied_menu_item(
+    history: &CopyHistory,
+    globals: &Arc<Globals>,
+    copy_notifier: &Option<Arc<dyn Notifier>>,
+    copies: &Rc<CopyTracker>,
+) -> gtk::MenuItem {
+    let submenu = gtk::Menu::new();
+    for (index, copied) in history.entries().enumerate() {
+        let label = copied.label();
+        let item = gtk::MenuItem::with_label(&label);
+        if index == 0 {
+            set_bold_label(&item, &label);
+        }
+        let copied = copied.clone();
+        let globals = Arc::clone(globals);
+        let copy_notifier = copy_notifier.clone();
+        let copies = Rc::clone(copies);
+        item.connect_activate(move |_| {
+            copy_snippet(&copied.title, &copied.content, &globals, &copy_notifier);
+            copies.record(CopiedSnippet {
+                copied_at: Local::now(),
+                ..copied.clone()
+            });
+        });
+        submenu.append(&item);
+    }
+    let item = gtk::MenuItem::with_label("Recently copied");
+    item.set_submenu(Some(&submenu));
+    item
+}
 
-    indicator.set_menu(&mut menu);
-    let _ = ready_tx.send(Ok(()));
+/// Renders `content` the way snippet rows always have and puts it on the
+/// clipboard.
+fn copy_snippet(
+    title: &str,
+    content: &str,
+    globals: &Globals,
+    copy_notifier: &Option<Arc<dyn Notifier>>,
+) {
+    let text = match render_template_macros_with_context(content, &menu_context(globals)) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            warn!("failed to render snippet template macros: {err}");
+            content.to_string()
+        }
+    };
+    let clipboard = gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD);
+    clipboard.set_text(&text);
+    clipboard.store();
 
-    gtk::main();
-    Ok(())
+    if let Some(notifier) = copy_notifier {
+        if let Err(err) = notifier.notify("Copied Snippet", title) {
+            warn!("failed to send snippet notification: {err}");
+        }
+    }
+}
+
+/// Marks the row of the snippet copied last.
+fn set_bold_label(item: &gtk::MenuItem, text: &str) {
+    if let Some(label) = item.child().and_downcast::<gtk::Label>() {
+        label.set_markup(&format!("<b>{}</b>", glib::markup_escape_text(text)));
+    }
+}
+
+/// How menu snippets are rendered: with the config's globals, and a runner
+/// that refuses `{{CMD:...}}`, since clicking a menu item should never run a
+/// command.
+fn menu_context(globals: &Globals) -> ExpansionContext<'_> {
+    ExpansionContext {
+        runner: &DenyAllRunner,
+        ..ExpansionContext::new(globals)
+    }
+}
+
+/// A snippet typed into the window that had focus before the menu opened.
+/// The output worker types it after the snippet's focus delay, so the menu
+/// closes right away.
+fn typed_snippet_item(
+    snippet: &MenuSnippet,
+    globals: Arc<Globals>,
+    handles: &MenuHandles,
+) -> gtk::MenuItem {
+    let item = gtk::MenuItem::with_label(&snippet.title);
+    let snippet = snippet.clone();
+    let type_snippet = Arc::clone(&handles.type_snippet);
+    let notifier = Arc::clone(&handles.notifier);
+    item.connect_activate(move |_| {
+        let sent = typed_snippet_actions(&snippet, &menu_context(&globals))
+            .and_then(|actions| type_snippet(actions));
+        if let Err(err) = sent {
+            error!("failed to type snippet '{}': {err:#}", snippet.title);
+            notify_error(notifier.as_ref(), "Snippet Failed", &format!("{err:#}"));
+        }
+    });
+    item
+}
+
+const PICKER_WIDTH: i32 = 480;
+const PICKER_HEIGHT: i32 = 360;
+
+/// A borderless window with a search box over the filtered entry list. Enter
+/// or a click picks the selected row; Escape or losing the window cancels.
+fn show_picker_window(entries: Vec<PickerEntry>, on_pick: PickCallback) {
+    let window = gtk::Window::new(gtk::WindowType::Toplevel);
+    window.set_title("slykey");
+    window.set_decorated(false);
+    window.set_keep_above(true);
+    window.set_skip_taskbar_hint(true);
+    window.set_position(gtk::WindowPosition::Center);
+    window.set_default_size(PICKER_WIDTH, PICKER_HEIGHT);
+
+    let search = gtk::SearchEntry::new();
+    let list = gtk::ListBox::new();
+    list.set_activate_on_single_click(true);
+    let scrolled = gtk::ScrolledWindow::new(gtk::Adjustment::NONE, gtk::Adjustment::NONE);
+    scrolled.set_vexpand(true);
+    scrolled.add(&list);
+    let layout = gtk::Box::new(gtk::Orientation::Vertical, 4);
+    layout.pack_start(&search, false, false, 0);
+    layout.pack_start(&scrolled, true, true, 0);
+    window.add(&layout);
+
+    let entries = Rc::new(entries);
+    // Rows currently shown, in list order.
+    let shown: Rc<RefCell<Vec<PickerEntry>>> = Rc::default();
+    // Taken by whichever of pick or close happens first.
+    let on_pick = Rc::new(RefCell::new(Some(on_pick)));
+    let finish = {
+        let on_pick = Rc::clone(&on_pick);
+        let window = window.clone();
+        move |entry: Option<PickerEntry>| {
+            // Taken before closing, which re-enters through delete-event.
+            let on_pick = on_pick.borrow_mut().take();
+            if let Some(on_pick) = on_pick {
+                window.close();
+                on_pick(entry);
+            }
+        }
+    };
+
+    let refill = {
+        let list = list.clone();
+        let shown = Rc::clone(&shown);
+        move |query: &str| {
+            for child in list.children() {
+                list.remove(&child);
+            }
+            let matches: Vec<PickerEntry> = filter_entries(&entries, query)
+                .into_iter()
+                .cloned()
+                .collect();
+            for entry in &matches {
+                let label = gtk::Label::new(Some(&entry.label));
+                label.set_xalign(0.0);
+                list.add(&label);
+            }
+            list.show_all();
+            list.select_row(list.row_at_index(0).as_ref());
+            *shown.borrow_mut() = matches;
+        }
+    };
+    refill("");
+    search.connect_search_changed(move |search| refill(&search.text()));
+
+    let pick_row = {
+        let shown = Rc::clone(&shown);
+        let finish = finish.clone();
+        move |row: Option<gtk::ListBoxRow>| {
+            let entry = row.and_then(|row| {
+                let index = usize::try_from(row.index()).ok()?;
+                shown.borrow().get(index).cloned()
+            });
+            if entry.is_some() {
+                finish(entry);
+            }
+        }
+    };
+    {
+        let list = list.clone();
+        let pick_row = pick_row.clone();
+        search.connect_activate(move |_| pick_row(list.selected_row()));
+    }
+    list.connect_row_activated(move |_, row| pick_row(Some(row.clone())));
+
+    {
+        let list = list.clone();
+        let finish = finish.clone();
+        window.connect_key_press_event(move |_, event| {
+            let step = match event.keyval() {
+                gtk::gdk::keys::constants::Escape => {
+                    finish(None);
+                    return glib::Propagation::Stop;
+                }
+                gtk::gdk::keys::constants::Down => 1,
+                gtk::gdk::keys::constants::Up => -1,
+                _ => return glib::Propagation::Proceed,
+            };
+            let current = list.selected_row().map_or(-1, |row| row.index());
+            if let Some(row) = list.row_at_index((current + step).max(0)) {
+                list.select_row(Some(&row));
+            }
+            glib::Propagation::Stop
+        });
+    }
+    {
+        let finish = finish.clone();
+        window.connect_focus_out_event(move |_, _| {
+            finish(None);
+            glib::Propagation::Proceed
+        });
+    }
+    window.connect_delete_event(move |_, _| {
+        finish(None);
+        glib::Propagation::Proceed
+    });
+
+    window.show_all();
+    window.present();
+    search.grab_focus();
+}
+
+fn open_config_editor(edit_command: Option<&str>, path: &Path, notifier: &dyn Notifier) {
+    match edit_config_command(edit_command, path).spawn() {
+        Ok(mut child) => {
+            // Reap the editor once it exits so it doesn't linger as a zombie.
+            std::thread::spawn(move || {
+                let _ = child.wait();
+            });
+        }
+        Err(err) => {
+            warn!("failed to open config editor: {err}");
+            notify_error(notifier, "Edit Config Failed", &err.to_string());
+        }
+    }
+}
+
+fn notify_error(notifier: &dyn Notifier, summary: &str, body: &str) {
+    if let Err(err) = notifier.notify(summary, body) {
+        warn!("failed to send notification: {err}");
+    }
 }
 
 fn install_bundled_icon() -> Option<&'static str> {
@@ -154,13 +682,13 @@ fn install_bundled_icon() -> Option<&'static str> {
 
     if let Some(parent) = icon_path.parent() {
         if let Err(err) = fs::create_dir_all(parent) {
-            eprintln!("warning: failed to create icon directory: {err}");
+            warn!("failed to create icon directory: {err}");
             return None;
         }
     }
 
     if let Err(err) = fs::write(&icon_path, BUNDLED_TRAY_ICON_SVG) {
-        eprintln!("warning: failed to write bundled tray icon: {err}");
+        warn!("failed to write bundled tray icon: {err}");
         return None;
     }
 
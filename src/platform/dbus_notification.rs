@@ -1,37 +1,457 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, OnceLock};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
 use dbus::arg::{RefArg, Variant};
 use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use tracing::warn;
+
+use crate::io::notification::Notifier;
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+const NOTIFICATION_TIMEOUT_MS: i32 = 2000;
+/// Notification ids remembered for action routing; older ones are forgotten.
+const MAX_TRACKED_NOTIFICATIONS: usize = 32;
+
+/// Called with the notification id and the action key the user clicked.
+pub type ActionCallback = Box<dyn Fn(u32, &str) + Send + Sync>;
+
+static ACTION_DISPATCHER: OnceLock<ActionDispatcher> = OnceLock::new();
+static SESSION_NOTIFIER: OnceLock<Mutex<SessionNotifier>> = OnceLock::new();
+
+/// The parts of the notification server slykey talks to, so the action
+/// plumbing can be tested without a session bus.
+pub trait NotificationServer {
+    fn capabilities(&mut self) -> Result<Vec<String>>;
+    /// Shows a notification; `actions` alternates keys and labels as in the spec.
+    fn notify(&mut self, summary: &str, body: &str, actions: &[&str]) -> Result<u32>;
+}
+
+/// Session-bus client for `org.freedesktop.Notifications`. Connects on first
+/// use and keeps the connection, reconnecting once if the bus dropped it.
+#[derive(Default)]
+pub struct SessionNotifier {
+    connection: Option<Connection>,
+    /// Cached `GetCapabilities` reply for the current connection.
+    capabilities: Option<Vec<String>>,
+    /// Last bubble id per summary, so repeated notifications such as
+    /// "Text Expanded" update one bubble instead of stacking.
+    last_ids: HashMap<String, u32>,
+}
+
+impl SessionNotifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn connection(&mut self) -> Result<&Connection> {
+        if self.connection.is_none() {
+            let connection =
+                Connection::new_session().context("failed to connect to D-Bus session")?;
+            self.connection = Some(connection);
+            self.capabilities = None;
+        }
+        Ok(self.connection.as_ref().expect("connection was just set"))
+    }
+
+    /// Runs `call` on the shared connection, retrying once on a fresh
+    /// connection when the old one turns out to be disconnected.
+    fn call<T>(
+        &mut self,
+        call: impl Fn(&Connection) -> std::result::Result<T, dbus::Error>,
+    ) -> Result<T> {
+        match call(self.connection()?) {
+            Err(err) if is_disconnect(&err) => {
+                self.connection = None;
+                Ok(call(self.connection()?)?)
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+impl NotificationServer for SessionNotifier {
+    fn capabilities(&mut self) -> Result<Vec<String>> {
+        if let Some(capabilities) = &self.capabilities {
+            return Ok(capabilities.clone());
+        }
+        let (capabilities,): (Vec<String>,) = self
+            .call(|connection| {
+                notifications_proxy(connection).method_call(
+                    NOTIFICATIONS_NAME,
+                    "GetCapabilities",
+                    (),
+                )
+            })
+            .context("failed to query notification capabilities")?;
+        self.capabilities = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    fn notify(&mut self, summary: &str, body: &str, actions: &[&str]) -> Result<u32> {
+        let replaces_id = self.last_ids.get(summary).copied().unwrap_or(0);
+        let (id,): (u32,) = self
+            .call(|connection| {
+                let hints: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+                notifications_proxy(connection).method_call(
+                    NOTIFICATIONS_NAME,
+                    "Notify",
+                    (
+                        "",
+                        replaces_id,
+                        "",
+                        summary,
+                        body,
+                        actions.to_vec(),
+                        hints,
+                        NOTIFICATION_TIMEOUT_MS,
+                    ),
+                )
+            })
+            .context("failed to send desktop notification")?;
+        self.last_ids.insert(summary.to_string(), id);
+        Ok(id)
+    }
+}
+
+fn notifications_proxy(connection: &Connection) -> dbus::blocking::Proxy<'_, &Connection> {
+    connection.with_proxy(
+        NOTIFICATIONS_NAME,
+        NOTIFICATIONS_PATH,
+        Duration::from_millis(800),
+    )
+}
+
+/// Whether `err` means the connection itself is gone, so a new one may work.
+/// Errors from the notification daemon (missing service, timeouts) are not.
+fn is_disconnect(err: &dbus::Error) -> bool {
+    should_reconnect(err.name())
+}
+
+fn should_reconnect(error_name: Option<&str>) -> bool {
+    matches!(
+        error_name,
+        Some(
+            "org.freedesktop.DBus.Error.Disconnected"
+                | "org.freedesktop.DBus.Error.NoServer"
+                | "org.freedesktop.DBus.Error.IOError"
+        )
+    )
+}
+
+fn session_notifier() -> MutexGuard<'static, SessionNotifier> {
+    SESSION_NOTIFIER
+        .get_or_init(|| Mutex::new(SessionNotifier::new()))
+        .lock()
+        .expect("notifier mutex poisoned")
+}
+
+/// Routes `ActionInvoked` signals for notifications slykey sent to the
+/// registered callback. The signal is broadcast for every application's
+/// notifications, so unknown ids are ignored.
+pub struct ActionDispatcher {
+    sent: Mutex<VecDeque<u32>>,
+    callback: ActionCallback,
+}
+
+impl ActionDispatcher {
+    pub fn new(callback: ActionCallback) -> Self {
+        Self {
+            sent: Mutex::new(VecDeque::new()),
+            callback,
+        }
+    }
+
+    /// Remembers a notification sent with actions. A replaced bubble keeps
+    /// its id, so it is only tracked once.
+    pub fn track(&self, id: u32) {
+        let mut sent = self.sent.lock().expect("notification id mutex poisoned");
+        sent.retain(|sent_id| *sent_id != id);
+        sent.push_back(id);
+        while sent.len() > MAX_TRACKED_NOTIFICATIONS {
+            sent.pop_front();
+        }
+    }
+
+    /// Runs the callback if `id` is one of ours; each notification's actions
+    /// fire at most once. Returns whether the callback ran.
+    pub fn dispatch(&self, id: u32, action: &str) -> bool {
+        {
+            let mut sent = self.sent.lock().expect("notification id mutex poisoned");
+            let Some(position) = sent.iter().position(|sent_id| *sent_id == id) else {
+                return false;
+            };
+            sent.remove(position);
+        }
+        (self.callback)(id, action);
+        true
+    }
+}
+
+/// Whether a `GetCapabilities` reply allows notifications with action buttons.
+pub fn supports_actions(capabilities: &[String]) -> bool {
+    capabilities
+        .iter()
+        .any(|capability| capability == "actions")
+}
 
 pub fn send_notification(summary: &str, body: &str) -> Result<()> {
+    session_notifier().notify(summary, body, &[])?;
+    Ok(())
+}
+
+/// Opens a throwaway session-bus connection, for `slykey doctor`.
+pub fn probe_session_bus() -> Result<()> {
+    Connection::new_session().context("failed to connect to D-Bus session")?;
+    Ok(())
+}
+
+/// Connects to the session bus and asks the notification server for its
+/// capabilities on a fresh connection, for `slykey doctor`.
+pub fn probe_notification_server() -> Result<Vec<String>> {
+    SessionNotifier::new().capabilities()
+}
+
+/// Whether some client currently owns the bus `name` on the session bus.
+pub fn session_name_has_owner(name: &str) -> Result<bool> {
     let connection = Connection::new_session().context("failed to connect to D-Bus session")?;
-    let proxy = connection.with_proxy(
-        "org.freedesktop.Notifications",
-        "/org/freedesktop/Notifications",
-        Duration::from_millis(800),
-    );
-
-    let actions: Vec<&str> = Vec::new();
-    let hints: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
-
-    let _: (u32,) = proxy
-        .method_call(
-            "org.freedesktop.Notifications",
-            "Notify",
-            (
-                "",
-                0u32,
-                "",
-                summary,
-                body,
-                actions,
-                hints,
-                2000i32,
-            ),
+    let (has_owner,): (bool,) = connection
+        .with_proxy(
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            Duration::from_millis(800),
         )
-        .context("failed to send desktop notification")?;
+        .method_call("org.freedesktop.DBus", "NameHasOwner", (name,))
+        .with_context(|| format!("failed to look up D-Bus name {name}"))?;
+    Ok(has_owner)
+}
 
+/// [`Notifier`] backed by the process-wide [`SessionNotifier`].
+pub struct DbusNotifier;
+
+impl Notifier for DbusNotifier {
+    fn notify(&self, summary: &str, body: &str) -> Result<()> {
+        send_notification(summary, body)
+    }
+
+    fn notify_with_actions(
+        &self,
+        summary: &str,
+        body: &str,
+        actions: &[(&str, &str)],
+    ) -> Result<()> {
+        send_notification_with_actions(summary, body, actions)?;
+        Ok(())
+    }
+}
+
+/// Sends a notification with `(key, label)` action buttons and returns its id.
+/// Falls back to a plain notification when no action listener is running or
+/// the notification daemon doesn't support actions.
+pub fn send_notification_with_actions(
+    summary: &str,
+    body: &str,
+    actions: &[(&str, &str)],
+) -> Result<u32> {
+    notify_with_actions(
+        &mut *session_notifier(),
+        ACTION_DISPATCHER.get(),
+        summary,
+        body,
+        actions,
+    )
+}
+
+/// Starts a background thread that hands clicked actions to `callback`.
+/// Only one listener can be registered per process.
+pub fn listen_for_actions(callback: ActionCallback) -> Result<()> {
+    if ACTION_DISPATCHER
+        .set(ActionDispatcher::new(callback))
+        .is_err()
+    {
+        anyhow::bail!("notification action listener is already running");
+    }
+
+    let connection = Connection::new_session().context("failed to connect to D-Bus session")?;
+    let rule = MatchRule::new_signal(NOTIFICATIONS_NAME, "ActionInvoked");
+    connection
+        .add_match(rule, |(id, action): (u32, String), _, _| {
+            if let Some(dispatcher) = ACTION_DISPATCHER.get() {
+                dispatcher.dispatch(id, &action);
+            }
+            true
+        })
+        .context("failed to subscribe to notification actions")?;
+
+    std::thread::spawn(move || loop {
+        if let Err(err) = connection.process(Duration::from_secs(1)) {
+            warn!("notification action listener stopped: {err}");
+            break;
+        }
+    });
     Ok(())
 }
+
+fn notify_with_actions(
+    server: &mut impl NotificationServer,
+    dispatcher: Option<&ActionDispatcher>,
+    summary: &str,
+    body: &str,
+    actions: &[(&str, &str)],
+) -> Result<u32> {
+    let Some(dispatcher) = dispatcher else {
+        return server.notify(summary, body, &[]);
+    };
+    match server.capabilities() {
+        Ok(capabilities) if supports_actions(&capabilities) => {}
+        Ok(_) => return server.notify(summary, body, &[]),
+        Err(err) => {
+            warn!("{err:#}; sending notification without actions");
+            return server.notify(summary, body, &[]);
+        }
+    }
+
+    let flat_actions: Vec<&str> = actions
+        .iter()
+        .flat_map(|(key, label)| [*key, *label])
+        .collect();
+    let id = server.notify(summary, body, &flat_actions)?;
+    dispatcher.track(id);
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_disconnect, notify_with_actions, should_reconnect, supports_actions, ActionDispatcher,
+        NotificationServer,
+    };
+    use anyhow::Result;
+    use std::sync::{Arc, Mutex};
+
+    struct MockServer {
+        capabilities: Vec<String>,
+        sent: Vec<Vec<String>>,
+    }
+
+    impl MockServer {
+        fn with_capabilities(capabilities: &[&str]) -> Self {
+            Self {
+                capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl NotificationServer for MockServer {
+        fn capabilities(&mut self) -> Result<Vec<String>> {
+            Ok(self.capabilities.clone())
+        }
+
+        fn notify(&mut self, _summary: &str, _body: &str, actions: &[&str]) -> Result<u32> {
+            self.sent
+                .push(actions.iter().map(|a| a.to_string()).collect());
+            Ok(self.sent.len() as u32)
+        }
+    }
+
+    type Invocations = Arc<Mutex<Vec<(u32, String)>>>;
+
+    fn recording_dispatcher() -> (ActionDispatcher, Invocations) {
+        let invoked = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&invoked);
+        let dispatcher = ActionDispatcher::new(Box::new(move |id, action| {
+            sink.lock()
+                .expect("invoked mutex")
+                .push((id, action.to_string()));
+        }));
+        (dispatcher, invoked)
+    }
+
+    #[test]
+    fn detects_action_capability() {
+        let caps = |list: &[&str]| list.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        assert!(supports_actions(&caps(&["body", "actions", "persistence"])));
+        assert!(!supports_actions(&caps(&["body", "body-markup"])));
+        assert!(!supports_actions(&[]));
+    }
+
+    #[test]
+    fn sends_actions_only_when_supported_and_listened_for() {
+        let (dispatcher, _) = recording_dispatcher();
+        let actions = [("undo", "Undo")];
+
+        let mut capable = MockServer::with_capabilities(&["body", "actions"]);
+        notify_with_actions(&mut capable, Some(&dispatcher), "s", "b", &actions).expect("notify");
+        notify_with_actions(&mut capable, None, "s", "b", &actions).expect("notify");
+        assert_eq!(
+            capable.sent,
+            vec![vec!["undo".to_string(), "Undo".to_string()], vec![]]
+        );
+
+        let mut plain = MockServer::with_capabilities(&["body"]);
+        notify_with_actions(&mut plain, Some(&dispatcher), "s", "b", &actions).expect("notify");
+        assert_eq!(plain.sent, vec![Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn dispatches_each_tracked_notification_once() {
+        let (dispatcher, invoked) = recording_dispatcher();
+        let mut server = MockServer::with_capabilities(&["actions"]);
+        let id = notify_with_actions(
+            &mut server,
+            Some(&dispatcher),
+            "s",
+            "b",
+            &[("undo", "Undo")],
+        )
+        .expect("notify");
+
+        assert!(!dispatcher.dispatch(id + 100, "undo"));
+        assert!(dispatcher.dispatch(id, "undo"));
+        assert!(!dispatcher.dispatch(id, "undo"));
+        assert_eq!(
+            *invoked.lock().expect("invoked mutex"),
+            vec![(id, "undo".to_string())]
+        );
+    }
+
+    #[test]
+    fn tracks_replaced_notifications_once() {
+        let (dispatcher, invoked) = recording_dispatcher();
+        dispatcher.track(7);
+        dispatcher.track(7);
+
+        assert!(dispatcher.dispatch(7, "undo"));
+        assert!(!dispatcher.dispatch(7, "undo"));
+        assert_eq!(invoked.lock().expect("invoked mutex").len(), 1);
+    }
+
+    #[test]
+    fn reconnects_only_when_the_connection_is_gone() {
+        assert!(should_reconnect(Some(
+            "org.freedesktop.DBus.Error.Disconnected"
+        )));
+        assert!(should_reconnect(Some(
+            "org.freedesktop.DBus.Error.NoServer"
+        )));
+        assert!(should_reconnect(Some("org.freedesktop.DBus.Error.IOError")));
+
+        assert!(!should_reconnect(Some(
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+        )));
+        assert!(!should_reconnect(Some(
+            "org.freedesktop.DBus.Error.NoReply"
+        )));
+        assert!(!should_reconnect(None));
+
+        let dropped = dbus::Error::new_custom("org.freedesktop.DBus.Error.Disconnected", "gone");
+        let timeout = dbus::Error::new_custom("org.freedesktop.DBus.Error.NoReply", "slow");
+        assert!(is_disconnect(&dropped));
+        assert!(!is_disconnect(&timeout));
+    }
+}
@@ -1,5 +1,13 @@
+#[cfg(feature = "x11")]
+pub mod active_window;
+#[cfg(feature = "x11")]
 pub mod x11_rdev;
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "tray"))]
 pub mod app_indicator;
 #[cfg(target_os = "linux")]
 pub mod dbus_notification;
+#[cfg(target_os = "linux")]
+pub mod secret_service;
+#[cfg(target_os = "linux")]
+pub mod systemd;
+pub mod tray_menu;
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::{Connection, Proxy};
+use dbus::Path;
+
+use crate::io::secrets::{SecretError, SecretStore};
+
+const SECRETS_NAME: &str = "org.freedesktop.secrets";
+const SECRETS_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_INTERFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+/// Attribute every slykey item carries, next to `name`.
+const APPLICATION: &str = "slykey";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `(session, parameters, value, content type)` as in the Secret Service spec.
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+/// Secret Service (`org.freedesktop.secrets`, e.g. GNOME Keyring or KWallet)
+/// client for `{{SECRET:...}}`. Items live in the default collection with the
+/// attributes `application=slykey` and `name=<name>`. Connects per call, as
+/// secrets are only read when an expansion asks for one.
+pub struct SessionSecretStore;
+
+impl SecretStore for SessionSecretStore {
+    fn get(&self, name: &str) -> Result<String> {
+        let session = SecretSession::open()?;
+        let item = session.find_item(name)?;
+        let (secret,): (Secret,) = session
+            .proxy(&item)
+            .method_call(ITEM_INTERFACE, "GetSecret", (&session.path,))
+            .map_err(secret_error)?;
+        String::from_utf8(secret.2).with_context(|| format!("secret '{name}' is not UTF-8"))
+    }
+
+    fn set(&self, name: &str, value: &str) -> Result<()> {
+        let session = SecretSession::open()?;
+        let mut properties = PropMap::new();
+        properties.insert(
+            "org.freedesktop.Secret.Item.Label".to_string(),
+            Variant(Box::new(format!("slykey: {name}")) as Box<dyn RefArg>),
+        );
+        properties.insert(
+            "org.freedesktop.Secret.Item.Attributes".to_string(),
+            Variant(Box::new(attributes(name)) as Box<dyn RefArg>),
+        );
+        let secret: Secret = (
+            session.path.clone(),
+            Vec::new(),
+            value.as_bytes().to_vec(),
+            "text/plain".to_string(),
+        );
+        let (_item, prompt): (Path<'static>, Path<'static>) = session
+            .proxy(&session.collection)
+            .method_call(
+                COLLECTION_INTERFACE,
+                "CreateItem",
+                (properties, secret, true),
+            )
+            .map_err(secret_error)?;
+        // A prompt is only returned when the collection has to be unlocked.
+        if !is_empty_path(&prompt) {
+            return Err(SecretError::Locked.into());
+        }
+        Ok(())
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let session = SecretSession::open()?;
+        let item = session.find_item(name)?;
+        let (prompt,): (Path<'static>,) = session
+            .proxy(&item)
+            .method_call(ITEM_INTERFACE, "Delete", ())
+            .map_err(secret_error)?;
+        if !is_empty_path(&prompt) {
+            return Err(SecretError::Locked.into());
+        }
+        Ok(())
+    }
+}
+
+/// An open connection with a `plain` transfer session and the default
+/// collection's path.
+struct SecretSession {
+    connection: Connection,
+    path: Path<'static>,
+    collection: Path<'static>,
+}
+
+impl SecretSession {
+    fn open() -> Result<Self> {
+        let connection = Connection::new_session().map_err(|err| {
+            SecretError::Unavailable(format!("cannot connect to the D-Bus session: {err}"))
+        })?;
+        let service = connection.with_proxy(SECRETS_NAME, SECRETS_PATH, CALL_TIMEOUT);
+        let (_output, path): (Variant<Box<dyn RefArg>>, Path<'static>) = service
+            .method_call(SERVICE_INTERFACE, "OpenSession", ("plain", Variant("")))
+            .map_err(secret_error)?;
+        let (collection,): (Path<'static>,) = service
+            .method_call(SERVICE_INTERFACE, "ReadAlias", ("default",))
+            .map_err(secret_error)?;
+        if is_empty_path(&collection) {
+            return Err(SecretError::Unavailable("no default keyring".to_string()).into());
+        }
+        Ok(Self {
+            connection,
+            path,
+            collection,
+        })
+    }
+
+    fn proxy<'a>(&'a self, path: &'a Path<'static>) -> Proxy<'a, &'a Connection> {
+        self.connection
+            .with_proxy(SECRETS_NAME, path.clone(), CALL_TIMEOUT)
+    }
+
+    /// The unlocked item holding `name` in the default collection.
+    fn find_item(&self, name: &str) -> Result<Path<'static>> {
+        let (unlocked, locked): (Vec<Path<'static>>, Vec<Path<'static>>) = self
+            .connection
+            .with_proxy(SECRETS_NAME, SECRETS_PATH, CALL_TIMEOUT)
+            .method_call(SERVICE_INTERFACE, "SearchItems", (attributes(name),))
+            .map_err(secret_error)?;
+        let in_collection = |item: &Path<'static>| {
+            item.strip_prefix(&*self.collection)
+                .is_some_and(|rest| rest.starts_with('/'))
+        };
+        if let Some(item) = unlocked.into_iter().find(in_collection) {
+            return Ok(item);
+        }
+        if locked.iter().any(in_collection) {
+            return Err(SecretError::Locked.into());
+        }
+        Err(SecretError::NotFound(name.to_string()).into())
+    }
+}
+
+fn attributes(name: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("application".to_string(), APPLICATION.to_string()),
+        ("name".to_string(), name.to_string()),
+    ])
+}
+
+/// `/` is how the spec says "no object", e.g. no prompt needed.
+fn is_empty_path(path: &Path) -> bool {
+    &**path == "/"
+}
+
+fn secret_error(err: dbus::Error) -> anyhow::Error {
+    match classify_error(err.name(), err.message()) {
+        Some(error) => error.into(),
+        None => anyhow::Error::new(err).context("secret service call failed"),
+    }
+}
+
+/// Maps D-Bus errors to the failures `{{SECRET:...}}` reports.
+fn classify_error(error_name: Option<&str>, message: Option<&str>) -> Option<SecretError> {
+    match error_name? {
+        "org.freedesktop.Secret.Error.IsLocked" => Some(SecretError::Locked),
+        "org.freedesktop.DBus.Error.ServiceUnknown"
+        | "org.freedesktop.DBus.Error.NameHasNoOwner"
+        | "org.freedesktop.DBus.Error.NoReply"
+        | "org.freedesktop.DBus.Error.Timeout"
+        | "org.freedesktop.DBus.Error.Disconnected"
+        | "org.freedesktop.DBus.Error.NoServer" => Some(SecretError::Unavailable(
+            message
+                .unwrap_or("no reply from org.freedesktop.secrets")
+                .to_string(),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::classify_error;
+    use crate::io::secrets::SecretError;
+
+    #[test]
+    fn classifies_secret_service_errors() {
+        assert_eq!(
+            classify_error(Some("org.freedesktop.Secret.Error.IsLocked"), None),
+            Some(SecretError::Locked)
+        );
+        assert_eq!(
+            classify_error(
+                Some("org.freedesktop.DBus.Error.ServiceUnknown"),
+                Some("The name org.freedesktop.secrets was not provided")
+            ),
+            Some(SecretError::Unavailable(
+                "The name org.freedesktop.secrets was not provided".to_string()
+            ))
+        );
+        assert_eq!(classify_error(Some("org.example.Other"), None), None);
+        assert_eq!(classify_error(None, None), None);
+    }
+}
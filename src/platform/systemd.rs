@@ -0,0 +1,246 @@
+use std::ffi::OsStr;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+/// File name of the unit `slykey install-service` writes.
+pub const SERVICE_NAME: &str = "slykey.service";
+
+/// Sends sd_notify(3) `KEY=VALUE` assignments to `$NOTIFY_SOCKET`. Returns
+/// `Ok(false)` without doing anything when slykey was not started by a
+/// `Type=notify` unit.
+pub fn notify(fields: &[(&str, &str)]) -> Result<bool> {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    send_notification(&socket, &format_notification(fields)?)?;
+    Ok(true)
+}
+
+/// The datagram for `fields`: one `KEY=VALUE` line each. Newlines in values
+/// become spaces, as they would end the assignment.
+pub fn format_notification(fields: &[(&str, &str)]) -> Result<String> {
+    let mut lines = Vec::with_capacity(fields.len());
+    for (key, value) in fields {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+        {
+            bail!("invalid sd_notify field name '{key}'");
+        }
+        lines.push(format!("{key}={}", value.replace(['\n', '\r'], " ")));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Sends `message` to `socket`: a path, or an abstract socket name after `@`.
+fn send_notification(socket: &OsStr, message: &str) -> Result<()> {
+    let addr = match socket.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(Path::new(socket)),
+    }
+    .with_context(|| format!("invalid NOTIFY_SOCKET '{}'", socket.to_string_lossy()))?;
+    let datagram = UnixDatagram::unbound().context("failed to create sd_notify socket")?;
+    datagram
+        .send_to_addr(message.as_bytes(), &addr)
+        .with_context(|| format!("failed to notify systemd at {}", socket.to_string_lossy()))?;
+    Ok(())
+}
+
+/// How often to send `WATCHDOG=1`: half of the unit's `WatchdogSec`, or
+/// `None` when the watchdog is off or meant for another process.
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog_interval(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn parse_watchdog_interval(
+    usec: Option<&str>,
+    pid: Option<&str>,
+    own_pid: u32,
+) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.trim().parse::<u32>().ok()? != own_pid {
+            return None;
+        }
+    }
+    let usec = usec?.trim().parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// `~/.config/systemd/user/slykey.service`.
+pub fn default_service_path() -> Result<PathBuf> {
+    Ok(dirs::config_dir()
+        .context("unable to resolve config directory from environment")?
+        .join("systemd")
+        .join("user")
+        .join(SERVICE_NAME))
+}
+
+/// A `Type=notify` user unit running `binary --config <config> run` with the
+/// session.
+pub fn service_unit(binary: &Path, config: &Path) -> Result<String> {
+    let exec_start = [
+        exec_arg(binary)?,
+        "--config".to_string(),
+        exec_arg(config)?,
+        "run".to_string(),
+    ]
+    .join(" ");
+    Ok(format!(
+        "[Unit]
+Description=slykey text expansion daemon
+After=graphical-session.target
+PartOf=graphical-session.target
+
+[Service]
+Type=notify
+ExecStart={exec_start}
+Restart=on-failure
+RestartSec=2
+# Key handling waits for {{{{CMD:...}}}}; give commands that may run longer
+# than this a timeout below it, e.g. {{{{CMD:timeout=20s:...}}}}.
+WatchdogSec=30
+
+[Install]
+WantedBy=graphical-session.target
+"
+    ))
+}
+
+/// `path` as one `ExecStart=` word: specifiers (`%`) and variables (`$`) are
+/// escaped, and the word is quoted when it contains spaces or quotes.
+fn exec_arg(path: &Path) -> Result<String> {
+    let Some(path) = path.to_str() else {
+        bail!("path is not valid UTF-8: {}", path.display());
+    };
+    let escaped = path
+        .replace('%', "%%")
+        .replace('$', "$$")
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    if path.is_empty() || path.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+        Ok(format!("\"{escaped}\""))
+    } else {
+        Ok(escaped)
+    }
+}
+
+pub fn write_service_file(path: &Path, unit: &str, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "service file already exists: {} (use --force to overwrite)",
+            path.display()
+        );
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+    }
+    std::fs::write(path, unit)
+        .with_context(|| format!("failed to write service file: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+    use std::path::Path;
+    use std::time::Duration;
+
+    use super::{format_notification, parse_watchdog_interval, send_notification, service_unit};
+
+    #[test]
+    fn formats_notification_datagrams() {
+        assert_eq!(
+            format_notification(&[("READY", "1"), ("STATUS", "Listening\nfor triggers")])
+                .expect("valid fields"),
+            "READY=1\nSTATUS=Listening for triggers"
+        );
+        assert_eq!(
+            format_notification(&[("WATCHDOG", "1")]).expect("valid field"),
+            "WATCHDOG=1"
+        );
+        assert!(format_notification(&[("ready", "1")]).is_err());
+        assert!(format_notification(&[("READY=1\nX", "1")]).is_err());
+    }
+
+    #[test]
+    fn sends_notifications_to_the_socket() {
+        let dir = std::env::temp_dir().join(format!("slykey-sd-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create temp dir");
+        let socket_path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&socket_path);
+        let receiver = UnixDatagram::bind(&socket_path).expect("bind socket");
+
+        send_notification(socket_path.as_os_str(), "READY=1").expect("send");
+        let mut buffer = [0u8; 64];
+        let len = receiver.recv(&mut buffer).expect("receive");
+        assert_eq!(&buffer[..len], b"READY=1");
+
+        let name = format!("slykey-sd-notify-test-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(&name).expect("abstract address");
+        let abstract_receiver = UnixDatagram::bind_addr(&addr).expect("bind abstract socket");
+        send_notification(format!("@{name}").as_ref(), "WATCHDOG=1").expect("send abstract");
+        let len = abstract_receiver.recv(&mut buffer).expect("receive");
+        assert_eq!(&buffer[..len], b"WATCHDOG=1");
+
+        std::fs::remove_dir_all(&dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn derives_watchdog_interval() {
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            parse_watchdog_interval(Some("30000000"), Some("7"), 42),
+            None
+        );
+        assert_eq!(parse_watchdog_interval(None, None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog_interval(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn templates_service_file() {
+        let unit = service_unit(
+            Path::new("/usr/bin/slykey"),
+            Path::new("/home/me/.config/slykey/config.yaml"),
+        )
+        .expect("unit");
+        assert!(unit.contains("\nType=notify\n"), "{unit}");
+        assert!(
+            unit.contains(
+                "\nExecStart=/usr/bin/slykey --config /home/me/.config/slykey/config.yaml run\n"
+            ),
+            "{unit}"
+        );
+        assert!(unit.ends_with("[Install]\nWantedBy=graphical-session.target\n"));
+
+        let unit = service_unit(
+            Path::new("/opt/sly key/slykey"),
+            Path::new("/home/me/100%/$HOME.yaml"),
+        )
+        .expect("unit");
+        assert!(
+            unit.contains(
+                r#"ExecStart="/opt/sly key/slykey" --config /home/me/100%%/$$HOME.yaml run"#
+            ),
+            "{unit}"
+        );
+    }
+}
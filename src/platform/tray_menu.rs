@@ -0,0 +1,576 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+
+use crate::config::MenuSnippet;
+use crate::core::expansion::{
+    parse_expansion_actions_with_context, ExpansionContext, OutputAction,
+};
+
+/// One row of the tray menu, independent of GTK so the layout can be tested.
+#[derive(Debug, Clone)]
+pub enum TrayMenuEntry<'a> {
+    /// Insensitive "Running" label.
+    Status,
+    /// Check item that pauses and resumes expansion.
+    Enabled,
+    /// "Profile" submenu with one radio item per profile, `active` checked.
+    Profiles {
+        names: &'a [String],
+        active: Option<&'a str>,
+    },
+    Separator,
+    Snippet(SnippetMenuEntry<'a>),
+    /// "Recently copied" submenu re-copying the history's snippets.
+    RecentlyCopied(&'a CopyHistory),
+    /// Opens the active config file in an editor.
+    EditConfig,
+    /// Reloads the config file immediately.
+    ReloadConfig,
+    Quit,
+}
+
+/// A row of the snippet section or of a snippet group's submenu.
+#[derive(Debug, Clone)]
+pub enum SnippetMenuEntry<'a> {
+    Snippet(&'a MenuSnippet),
+    Separator,
+    /// Submenu for all snippets sharing `group`, in config order.
+    Group {
+        name: &'a str,
+        entries: Vec<SnippetMenuEntry<'a>>,
+    },
+}
+
+/// Lays out the whole menu for `snippets`, with a profile switcher when the
+/// config has `profiles` and a "Recently copied" submenu once something was
+/// copied. The tray rebuilds from this on every config reload, profile
+/// switch and copy, so snippets removed from the config disappear while the
+/// status, config and Quit rows stay in place.
+pub fn tray_menu_layout<'a>(
+    snippets: &'a [MenuSnippet],
+    profiles: &'a [String],
+    active_profile: Option<&'a str>,
+    history: &'a CopyHistory,
+) -> Vec<TrayMenuEntry<'a>> {
+    let mut entries = vec![TrayMenuEntry::Status, TrayMenuEntry::Enabled];
+    if !profiles.is_empty() {
+        entries.push(TrayMenuEntry::Profiles {
+            names: profiles,
+            active: active_profile,
+        });
+    }
+    entries.push(TrayMenuEntry::Separator);
+    let snippet_entries = snippet_menu_model(snippets);
+    if !snippet_entries.is_empty() || !history.is_empty() {
+        entries.extend(snippet_entries.into_iter().map(TrayMenuEntry::Snippet));
+        if !history.is_empty() {
+            entries.push(TrayMenuEntry::RecentlyCopied(history));
+        }
+        entries.push(TrayMenuEntry::Separator);
+    }
+    entries.extend([
+        TrayMenuEntry::EditConfig,
+        TrayMenuEntry::ReloadConfig,
+        TrayMenuEntry::Separator,
+        TrayMenuEntry::Quit,
+    ]);
+    entries
+}
+
+/// Number of snippets listed under "Recently copied".
+pub const COPY_HISTORY_SIZE: usize = 5;
+
+/// A snippet copied from the tray. `content` is kept unrendered, so copying
+/// it again renders macros such as `{{DATE}}` afresh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopiedSnippet {
+    pub title: String,
+    pub group: Option<String>,
+    pub content: String,
+    pub copied_at: DateTime<Local>,
+}
+
+impl CopiedSnippet {
+    pub fn new(snippet: &MenuSnippet, copied_at: DateTime<Local>) -> Self {
+        Self {
+            title: snippet.title.clone(),
+            group: snippet.group.clone(),
+            content: snippet.content.clone(),
+            copied_at,
+        }
+    }
+
+    /// Menu label: the title and when it was copied.
+    pub fn label(&self) -> String {
+        format!("{} ({})", self.title, self.copied_at.format("%H:%M"))
+    }
+
+    /// Whether this was copied from `snippet` (matched by title and group).
+    pub fn is_from(&self, snippet: &MenuSnippet) -> bool {
+        self.title == snippet.title && self.group == snippet.group
+    }
+}
+
+/// The last few snippets copied from the tray, most recent first. Copying a
+/// snippet again moves it to the front instead of listing it twice. Kept in
+/// memory only.
+#[derive(Debug, Clone)]
+pub struct CopyHistory {
+    entries: VecDeque<CopiedSnippet>,
+    capacity: usize,
+}
+
+impl Default for CopyHistory {
+    fn default() -> Self {
+        Self::new(COPY_HISTORY_SIZE)
+    }
+}
+
+impl CopyHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, copied: CopiedSnippet) {
+        self.entries
+            .retain(|entry| entry.title != copied.title || entry.group != copied.group);
+        self.entries.push_front(copied);
+        self.entries.truncate(self.capacity);
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &CopiedSnippet> {
+        self.entries.iter()
+    }
+
+    pub fn latest(&self) -> Option<&CopiedSnippet> {
+        self.entries.front()
+    }
+
+    /// Whether `snippet` is the one copied last, which the menu highlights.
+    pub fn is_latest(&self, snippet: &MenuSnippet) -> bool {
+        self.latest().is_some_and(|latest| latest.is_from(snippet))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Builds the command behind "Edit config…". Without `tray.edit_command`
+/// the file is handed to `xdg-open`; otherwise the command runs through
+/// `sh -c` with `{path}` replaced by the quoted path (or the path appended).
+pub fn edit_config_command(edit_command: Option<&str>, path: &Path) -> Command {
+    let Some(edit_command) = edit_command.map(str::trim).filter(|cmd| !cmd.is_empty()) else {
+        let mut command = Command::new("xdg-open");
+        command.arg(path);
+        return command;
+    };
+
+    let quoted = shell_quote(&path.to_string_lossy());
+    let script = if edit_command.contains("{path}") {
+        edit_command.replace("{path}", &quoted)
+    } else {
+        format!("{edit_command} {quoted}")
+    };
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+/// What clicking a `mode: type` snippet sends: a pause of its
+/// `type_delay_ms` for the menu to close and focus to return, then the
+/// content's text, keys and caret moves.
+pub fn typed_snippet_actions(
+    snippet: &MenuSnippet,
+    ctx: &ExpansionContext,
+) -> Result<Vec<OutputAction>> {
+    let mut actions = vec![OutputAction::SleepMs(snippet.type_delay_ms())];
+    actions.extend(parse_expansion_actions_with_context(&snippet.content, ctx)?);
+    Ok(actions)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Nests grouped snippets into one submenu per group, placed where the group
+/// first appears; ungrouped snippets stay at the top level. `separator_after`
+/// adds a separator inside the snippet's own menu unless it would be last.
+pub fn snippet_menu_model(snippets: &[MenuSnippet]) -> Vec<SnippetMenuEntry<'_>> {
+    let mut top_level: Vec<SnippetMenuEntry> = Vec::new();
+    for snippet in snippets {
+        let menu = match snippet.group.as_deref() {
+            None => &mut top_level,
+            Some(group) => group_entries(&mut top_level, group),
+        };
+        menu.push(SnippetMenuEntry::Snippet(snippet));
+        if snippet.separator_after {
+            menu.push(SnippetMenuEntry::Separator);
+        }
+    }
+
+    trim_trailing_separators(&mut top_level);
+    top_level
+}
+
+fn group_entries<'m, 'a>(
+    top_level: &'m mut Vec<SnippetMenuEntry<'a>>,
+    group: &'a str,
+) -> &'m mut Vec<SnippetMenuEntry<'a>> {
+    let position = top_level
+        .iter()
+        .position(|entry| matches!(entry, SnippetMenuEntry::Group { name, .. } if *name == group));
+    let index = position.unwrap_or_else(|| {
+        top_level.push(SnippetMenuEntry::Group {
+            name: group,
+            entries: Vec::new(),
+        });
+        top_level.len() - 1
+    });
+    match &mut top_level[index] {
+        SnippetMenuEntry::Group { entries, .. } => entries,
+        _ => unreachable!("index points at a group"),
+    }
+}
+
+fn trim_trailing_separators(entries: &mut Vec<SnippetMenuEntry>) {
+    while matches!(entries.last(), Some(SnippetMenuEntry::Separator)) {
+        entries.pop();
+    }
+    for entry in entries {
+        if let SnippetMenuEntry::Group { entries, .. } = entry {
+            trim_trailing_separators(entries);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        edit_config_command, snippet_menu_model, tray_menu_layout, typed_snippet_actions,
+        CopiedSnippet, CopyHistory, SnippetMenuEntry, TrayMenuEntry,
+    };
+    use crate::config::{Globals, MenuSnippet, SnippetMode};
+    use crate::core::expansion::{format_actions, ExpansionContext};
+    use chrono::{Local, TimeZone};
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use std::process::Command;
+
+    fn snippet(title: &str) -> MenuSnippet {
+        MenuSnippet {
+            title: title.to_string(),
+            content: format!("{title} content"),
+            group: None,
+            separator_after: false,
+            mode: SnippetMode::Clipboard,
+            type_delay_ms: None,
+        }
+    }
+
+    fn grouped(title: &str, group: &str) -> MenuSnippet {
+        MenuSnippet {
+            group: Some(group.to_string()),
+            ..snippet(title)
+        }
+    }
+
+    fn titles(entries: &[TrayMenuEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                TrayMenuEntry::Status => "Running".to_string(),
+                TrayMenuEntry::Enabled => "Enabled".to_string(),
+                TrayMenuEntry::Profiles { names, active } => {
+                    let names: Vec<String> = names
+                        .iter()
+                        .map(|name| {
+                            if Some(name.as_str()) == *active {
+                                format!("({name})")
+                            } else {
+                                name.clone()
+                            }
+                        })
+                        .collect();
+                    format!("Profile[{}]", names.join(", "))
+                }
+                TrayMenuEntry::Separator => "---".to_string(),
+                TrayMenuEntry::Snippet(entry) => snippet_title(entry),
+                TrayMenuEntry::RecentlyCopied(history) => {
+                    let titles: Vec<&str> = history
+                        .entries()
+                        .map(|entry| entry.title.as_str())
+                        .collect();
+                    format!("Recently copied[{}]", titles.join(", "))
+                }
+                TrayMenuEntry::EditConfig => "Edit config…".to_string(),
+                TrayMenuEntry::ReloadConfig => "Reload config".to_string(),
+                TrayMenuEntry::Quit => "Quit".to_string(),
+            })
+            .collect()
+    }
+
+    fn command_line(command: &Command) -> Vec<&OsStr> {
+        std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .collect()
+    }
+
+    fn snippet_titles(entries: &[SnippetMenuEntry]) -> Vec<String> {
+        entries.iter().map(snippet_title).collect()
+    }
+
+    fn snippet_title(entry: &SnippetMenuEntry) -> String {
+        match entry {
+            SnippetMenuEntry::Snippet(snippet) => snippet.title.clone(),
+            SnippetMenuEntry::Separator => "---".to_string(),
+            SnippetMenuEntry::Group { name, entries } => {
+                format!("{name}[{}]", snippet_titles(entries).join(", "))
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_snippets_between_status_and_config_items() {
+        let snippets = vec![snippet("Email"), snippet("Address")];
+
+        assert_eq!(
+            titles(&tray_menu_layout(
+                &snippets,
+                &[],
+                None,
+                &CopyHistory::default()
+            )),
+            vec![
+                "Running",
+                "Enabled",
+                "---",
+                "Email",
+                "Address",
+                "---",
+                "Edit config…",
+                "Reload config",
+                "---",
+                "Quit"
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_profiles_after_the_enabled_item() {
+        let profiles = vec!["personal".to_string(), "work".to_string()];
+
+        assert_eq!(
+            titles(&tray_menu_layout(
+                &[],
+                &profiles,
+                Some("work"),
+                &CopyHistory::default()
+            ))[..4],
+            ["Running", "Enabled", "Profile[personal, (work)]", "---"]
+        );
+        assert_eq!(
+            titles(&tray_menu_layout(
+                &[],
+                &profiles,
+                None,
+                &CopyHistory::default()
+            ))[2],
+            "Profile[personal, work]"
+        );
+    }
+
+    #[test]
+    fn omits_snippet_separator_without_snippets() {
+        assert_eq!(
+            titles(&tray_menu_layout(&[], &[], None, &CopyHistory::default())),
+            vec![
+                "Running",
+                "Enabled",
+                "---",
+                "Edit config…",
+                "Reload config",
+                "---",
+                "Quit"
+            ]
+        );
+    }
+
+    #[test]
+    fn relayout_drops_removed_snippets() {
+        let before = vec![snippet("Email"), snippet("Address"), snippet("Phone")];
+        let after = vec![snippet("Phone"), snippet("Email")];
+
+        assert_eq!(
+            titles(&tray_menu_layout(
+                &before,
+                &[],
+                None,
+                &CopyHistory::default()
+            ))[3..6],
+            ["Email", "Address", "Phone"]
+        );
+        assert_eq!(
+            titles(&tray_menu_layout(
+                &after,
+                &[],
+                None,
+                &CopyHistory::default()
+            ))[3..6],
+            ["Phone", "Email", "---"]
+        );
+    }
+
+    #[test]
+    fn edit_command_defaults_to_xdg_open() {
+        let path = Path::new("/home/me/.config/slykey/slykey.yaml");
+
+        for edit_command in [None, Some("  ")] {
+            assert_eq!(
+                command_line(&edit_config_command(edit_command, path)),
+                vec!["xdg-open", "/home/me/.config/slykey/slykey.yaml"]
+            );
+        }
+    }
+
+    #[test]
+    fn edit_command_substitutes_or_appends_the_quoted_path() {
+        let path = Path::new("/home/me/it's here/slykey.yaml");
+
+        assert_eq!(
+            command_line(&edit_config_command(Some("alacritty -e nvim {path}"), path)),
+            vec![
+                "sh",
+                "-c",
+                "alacritty -e nvim '/home/me/it'\\''s here/slykey.yaml'"
+            ]
+        );
+        assert_eq!(
+            command_line(&edit_config_command(Some("code --wait"), path)),
+            vec![
+                "sh",
+                "-c",
+                "code --wait '/home/me/it'\\''s here/slykey.yaml'"
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_snippets_into_submenus_in_first_appearance_order() {
+        let snippets = vec![
+            grouped("Email", "Work"),
+            snippet("Address"),
+            grouped("Email", "Personal"),
+            grouped("Phone", "Work"),
+            snippet("Signature"),
+        ];
+
+        assert_eq!(
+            snippet_titles(&snippet_menu_model(&snippets)),
+            vec![
+                "Work[Email, Phone]",
+                "Address",
+                "Personal[Email]",
+                "Signature"
+            ]
+        );
+    }
+
+    #[test]
+    fn separator_after_stays_inside_its_menu_and_never_trails() {
+        let mut address = snippet("Address");
+        address.separator_after = true;
+        let mut work_email = grouped("Email", "Work");
+        work_email.separator_after = true;
+        let mut work_phone = grouped("Phone", "Work");
+        work_phone.separator_after = true;
+        let mut last = snippet("Signature");
+        last.separator_after = true;
+        let snippets = vec![address, work_email, work_phone, last];
+
+        assert_eq!(
+            snippet_titles(&snippet_menu_model(&snippets)),
+            vec!["Address", "---", "Work[Email, ---, Phone]", "Signature"]
+        );
+    }
+
+    #[test]
+    fn typed_snippets_wait_for_focus_before_their_actions() {
+        let globals = Globals::from([("NAME".to_string(), "Tyler".to_string())]);
+        let ctx = ExpansionContext::new(&globals);
+        let mut address = snippet("Address");
+        address.mode = SnippetMode::Type;
+        address.content = "Hi {{NAME}}{{KEY:ENTER}}()){{MOVE_CARET:-1}}".to_string();
+
+        let actions = typed_snippet_actions(&address, &ctx).expect("actions");
+        assert_eq!(
+            format_actions(&actions),
+            "[SLEEP 600ms]Hi Tyler[KEY Enter]())[CARET -1]"
+        );
+
+        address.type_delay_ms = Some(150);
+        address.content = "x".to_string();
+        let actions = typed_snippet_actions(&address, &ctx).expect("actions");
+        assert_eq!(format_actions(&actions), "[SLEEP 150ms]x");
+
+        address.content = "{{KEY:NOPE}}".to_string();
+        assert!(typed_snippet_actions(&address, &ctx).is_err());
+    }
+
+    #[test]
+    fn copy_history_keeps_the_latest_copies_once_each() {
+        let at = |minute| {
+            Local
+                .with_ymd_and_hms(2026, 10, 16, 9, minute, 0)
+                .single()
+                .expect("unambiguous local time")
+        };
+        let mut history = CopyHistory::new(3);
+        let email = snippet("Email");
+        let work_email = grouped("Email", "Work");
+        for (minute, copied) in [
+            (1, snippet("Address")),
+            (2, email.clone()),
+            (3, work_email.clone()),
+            (4, snippet("Phone")),
+            (5, email.clone()),
+        ] {
+            history.record(CopiedSnippet::new(&copied, at(minute)));
+        }
+
+        let labels: Vec<String> = history.entries().map(CopiedSnippet::label).collect();
+        assert_eq!(
+            labels,
+            vec!["Email (09:05)", "Phone (09:04)", "Email (09:03)"]
+        );
+        assert!(history.is_latest(&email));
+        assert!(!history.is_latest(&work_email));
+        assert_eq!(
+            history.latest().map(|latest| latest.content.as_str()),
+            Some("Email content")
+        );
+    }
+
+    #[test]
+    fn lists_recently_copied_after_the_snippets() {
+        let snippets = vec![snippet("Email"), snippet("Address")];
+        let mut history = CopyHistory::default();
+        history.record(CopiedSnippet::new(&snippets[1], Local::now()));
+
+        assert_eq!(
+            titles(&tray_menu_layout(&snippets, &[], None, &history))[3..7],
+            ["Email", "Address", "Recently copied[Address]", "---"]
+        );
+        assert_eq!(
+            titles(&tray_menu_layout(&[], &[], None, &history))[2..5],
+            ["---", "Recently copied[Address]", "---"]
+        );
+    }
+}
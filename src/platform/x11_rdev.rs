@@ -3,27 +3,116 @@ use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use enigo::{Direction, Enigo, Key as EnigoKey, Keyboard, Settings};
 use rdev::{Event, EventType, Key};
+use tracing::warn;
+use unicode_segmentation::UnicodeSegmentation;
+use x11_clipboard::Clipboard;
+use x11rb::connection::RequestConnection;
 
-use crate::core::expansion::OutputAction;
+use crate::config::{AppConfig, OutputConfig, PasteConfig, PasteShortcut, UnicodeFallback};
+use crate::core::expansion::{CaretUnit, OutputAction};
 use crate::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
-use crate::io::output::{OutputSink, SpecialKey};
+use crate::io::output::{
+    describe_untypable, plan_typed_text, plan_unicode_fallback, random_sleep_ms, with_retries,
+    ComboKey, FallbackStep, InjectGuard, KeyModifier, OutputCancelled, OutputSink, PartialOutput,
+    SinkCapabilities, SpecialKey,
+};
+
+/// Longest uninterrupted sleep while typing, so a cancel takes effect quickly.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Tries per simulated key or text call; XTest occasionally fails while the compositor is busy.
+const INJECT_ATTEMPTS: u32 = 3;
+/// Pause before the first retry, doubled before the second.
+const INJECT_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// X extensions the backend needs, with what slykey uses each for.
+const REQUIRED_EXTENSIONS: &[(&str, &str)] = &[
+    ("XTEST", "type expansions"),
+    ("RECORD", "see the keys you type"),
+];
+
+/// The key presses and text the backend injects, so the typing logic can be
+/// tested against a fake instead of the X server.
+pub trait KeySimulator: Send {
+    fn key(&mut self, key: EnigoKey, direction: Direction) -> Result<()>;
+    /// Types `text` in order. When part of it went out before a failure,
+    /// the error is a [`TypedPrefix`] saying how much.
+    fn text(&mut self, text: &str) -> Result<()>;
+}
+
+impl KeySimulator for Enigo {
+    fn key(&mut self, key: EnigoKey, direction: Direction) -> Result<()> {
+        Keyboard::key(self, key, direction).map_err(|err| anyhow::anyhow!("{err}"))
+    }
+
+    /// enigo types one key at a time and keeps what it typed when a later
+    /// key fails, so each grapheme gets its own call to know where it stopped.
+    fn text(&mut self, text: &str) -> Result<()> {
+        for (index, grapheme) in text.grapheme_indices(true) {
+            Keyboard::text(self, grapheme).map_err(|err| TypedPrefix {
+                typed: index,
+                source: anyhow::anyhow!("{err}"),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Error from [`KeySimulator::text`] after the first `typed` bytes of the
+/// text were already typed.
+#[derive(Debug)]
+pub struct TypedPrefix {
+    pub typed: usize,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for TypedPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.source)
+    }
+}
+
+impl std::error::Error for TypedPrefix {}
 
 pub struct X11RdevBackend {
     injecting: Arc<AtomicBool>,
-    enigo: Mutex<Enigo>,
+    /// Set by [`OutputSink::cancel`]; checked between actions and text chunks.
+    cancelled: Arc<AtomicBool>,
+    simulator: Mutex<Box<dyn KeySimulator>>,
+    output: Mutex<OutputConfig>,
+    paste: Mutex<PasteConfig>,
+    /// Opened on first paste; must stay alive while we own the selection.
+    clipboard: Mutex<Option<Clipboard>>,
 }
 
 impl X11RdevBackend {
+    /// Connects to the X server, failing with a descriptive error when it
+    /// lacks an extension the backend needs.
     pub fn new() -> Result<Self> {
+        probe_x_server()?;
         let enigo = Enigo::new(&Settings::default())
             .map_err(|err| anyhow::anyhow!("failed to initialize enigo: {err}"))?;
-        Ok(Self {
+        Ok(Self::with_simulator(Box::new(enigo)))
+    }
+
+    /// A backend that injects through `simulator` instead of enigo.
+    pub fn with_simulator(simulator: Box<dyn KeySimulator>) -> Self {
+        Self {
             injecting: Arc::new(AtomicBool::new(false)),
-            enigo: Mutex::new(enigo),
-        })
+            cancelled: Arc::new(AtomicBool::new(false)),
+            simulator: Mutex::new(simulator),
+            output: Mutex::new(OutputConfig::default()),
+            paste: Mutex::new(PasteConfig::default()),
+            clipboard: Mutex::new(None),
+        }
+    }
+
+    /// Applies the output pacing and paste settings from `config`.
+    pub fn apply_config(&self, config: &AppConfig) {
+        *self.output.lock().expect("output config mutex poisoned") = config.output.clone();
+        *self.paste.lock().expect("paste config mutex poisoned") = config.paste.clone();
     }
 
     pub fn listen<F>(&self, mut on_event: F) -> Result<()>
@@ -39,58 +128,424 @@ impl X11RdevBackend {
         })
         .map_err(|err| anyhow::anyhow!("failed to start global X11 listener: {err:?}"))
     }
+
+    fn type_actions(&self, actions: &[OutputAction], char_delay_ms: Option<u64>) -> Result<()> {
+        let output = self
+            .output
+            .lock()
+            .expect("output config mutex poisoned")
+            .clone();
+        let char_delay_ms = char_delay_ms.unwrap_or(output.char_delay_ms);
+        let paste = self
+            .paste
+            .lock()
+            .expect("paste config mutex poisoned")
+            .clone();
+
+        while_injecting(&self.injecting, || {
+            let mut simulator = self.simulator.lock().expect("key simulator mutex poisoned");
+            type_actions_with(
+                &mut **simulator,
+                &self.cancelled,
+                actions,
+                output.chunk_size,
+                char_delay_ms,
+                output.unicode_fallback,
+                &mut |simulator, text| {
+                    let previous = self.set_clipboard(text)?;
+                    send_paste_shortcut(simulator, paste.shortcut)?;
+                    std::thread::sleep(Duration::from_millis(paste.restore_delay_ms));
+                    self.restore_clipboard(previous);
+                    Ok(())
+                },
+            )
+        })
+    }
+
+    /// Puts `text` on the clipboard, returning the text it held before.
+    fn set_clipboard(&self, text: &str) -> Result<Option<Vec<u8>>> {
+        let mut clipboard_slot = self.clipboard.lock().expect("clipboard mutex poisoned");
+        if clipboard_slot.is_none() {
+            let clipboard = Clipboard::new()
+                .map_err(|err| anyhow::anyhow!("failed to open X11 clipboard: {err}"))?;
+            *clipboard_slot = Some(clipboard);
+        }
+        let clipboard = clipboard_slot.as_ref().expect("clipboard initialized");
+        let atoms = &clipboard.getter.atoms;
+
+        let previous = clipboard
+            .load(
+                atoms.clipboard,
+                atoms.utf8_string,
+                atoms.property,
+                Duration::from_millis(100),
+            )
+            .ok()
+            .filter(|contents| !contents.is_empty());
+        clipboard
+            .store(atoms.clipboard, atoms.utf8_string, text.as_bytes())
+            .map_err(|err| anyhow::anyhow!("failed to set clipboard: {err}"))?;
+        Ok(previous)
+    }
+
+    fn restore_clipboard(&self, previous: Option<Vec<u8>>) {
+        let Some(previous) = previous else {
+            return;
+        };
+        let clipboard_slot = self.clipboard.lock().expect("clipboard mutex poisoned");
+        let Some(clipboard) = clipboard_slot.as_ref() else {
+            return;
+        };
+        let atoms = &clipboard.getter.atoms;
+        if let Err(err) = clipboard.store(atoms.clipboard, atoms.utf8_string, previous) {
+            warn!("failed to restore previous clipboard contents: {err}");
+        }
+    }
 }
 
 impl OutputSink for X11RdevBackend {
     fn send_backspaces(&self, count: usize) -> Result<()> {
-        self.injecting.store(true, Ordering::Relaxed);
-        let mut enigo = self.enigo.lock().expect("enigo mutex poisoned");
-        for _ in 0..count {
-            tap_key(&mut enigo, EnigoKey::Backspace)?;
-        }
-        self.injecting.store(false, Ordering::Relaxed);
-        Ok(())
+        self.cancelled.store(false, Ordering::Relaxed);
+        while_injecting(&self.injecting, || {
+            let mut simulator = self.simulator.lock().expect("key simulator mutex poisoned");
+            send_backspaces_with(&mut **simulator, count)
+        })
     }
 
     fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
-        self.injecting.store(true, Ordering::Relaxed);
-        let mut enigo = self.enigo.lock().expect("enigo mutex poisoned");
-        for action in actions {
-            match action {
-                OutputAction::Text(s) => {
-                    enigo
-                        .text(s)
-                        .map_err(|err| anyhow::anyhow!("text simulation failed: {err}"))?;
-                    std::thread::sleep(Duration::from_millis(1));
-                }
-                OutputAction::Key(k) => tap_key(&mut enigo, map_special_key(*k))?,
-                OutputAction::SleepMs(ms) => {
-                    std::thread::sleep(Duration::from_millis(*ms));
+        self.type_actions(actions, None)
+    }
+
+    fn send_actions_with_char_delay(
+        &self,
+        actions: &[OutputAction],
+        char_delay_ms: u64,
+    ) -> Result<()> {
+        self.type_actions(actions, Some(char_delay_ms))
+    }
+
+    fn send_paste(&self, text: &str) -> Result<()> {
+        check_cancelled(&self.cancelled)?;
+        let paste = self
+            .paste
+            .lock()
+            .expect("paste config mutex poisoned")
+            .clone();
+        let previous = self.set_clipboard(text)?;
+
+        while_injecting(&self.injecting, || {
+            let mut simulator = self.simulator.lock().expect("key simulator mutex poisoned");
+            send_paste_shortcut(&mut **simulator, paste.shortcut)
+        })?;
+
+        // The target app reads the clipboard asynchronously after the shortcut.
+        std::thread::sleep(Duration::from_millis(paste.restore_delay_ms));
+        self.restore_clipboard(previous);
+        Ok(())
+    }
+
+    fn capabilities(&self) -> SinkCapabilities {
+        SinkCapabilities::FULL
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Checks that the X server is reachable and has [`REQUIRED_EXTENSIONS`], so
+/// a broken setup fails at startup instead of silently ignoring keys.
+fn probe_x_server() -> Result<()> {
+    let (connection, _) = x11rb::connect(None)
+        .context("failed to connect to the X server; check DISPLAY and XAUTHORITY")?;
+    check_extensions(|name| Ok(connection.extension_information(name)?.is_some()))
+}
+
+fn check_extensions(mut has_extension: impl FnMut(&'static str) -> Result<bool>) -> Result<()> {
+    for (name, purpose) in REQUIRED_EXTENSIONS {
+        if !has_extension(name)? {
+            anyhow::bail!(
+                "the X server lacks the {name} extension, which slykey needs to {purpose}"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Pastes text through the clipboard with the keyboard already held for typing.
+type PasteWith<'a> = dyn FnMut(&mut dyn KeySimulator, &str) -> Result<()> + 'a;
+
+fn send_backspaces_with(simulator: &mut dyn KeySimulator, count: usize) -> Result<()> {
+    for _ in 0..count {
+        tap_key(simulator, EnigoKey::Backspace)?;
+    }
+    Ok(())
+}
+
+fn type_actions_with(
+    simulator: &mut dyn KeySimulator,
+    cancelled: &AtomicBool,
+    actions: &[OutputAction],
+    chunk_size: usize,
+    char_delay_ms: u64,
+    fallback: UnicodeFallback,
+    paste: &mut PasteWith<'_>,
+) -> Result<()> {
+    for (emitted, action) in actions.iter().enumerate() {
+        if let Err(err) = type_action(
+            simulator,
+            cancelled,
+            action,
+            chunk_size,
+            char_delay_ms,
+            fallback,
+            paste,
+        ) {
+            if err.is::<OutputCancelled>() {
+                return Err(err);
+            }
+            return Err(PartialOutput {
+                emitted,
+                total: actions.len(),
+                source: err,
+            }
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Types a text run chunk by chunk. A chunk that fails is retried from where
+/// it stopped, then one grapheme at a time, and graphemes that still fail are
+/// handled as `fallback` says, with a warning naming them.
+fn type_text(
+    simulator: &mut dyn KeySimulator,
+    cancelled: &AtomicBool,
+    text: &str,
+    chunk_size: usize,
+    char_delay_ms: u64,
+    fallback: UnicodeFallback,
+    paste: &mut PasteWith<'_>,
+) -> Result<()> {
+    let mut failed = Vec::new();
+    let mut offset = 0;
+    for (chunk, pause) in plan_typed_text(text, chunk_size, char_delay_ms) {
+        check_cancelled(cancelled)?;
+        let mut typed = 0;
+        let result = retry(|| {
+            simulator.text(&chunk[typed..]).inspect_err(|err| {
+                typed += err
+                    .downcast_ref::<TypedPrefix>()
+                    .map_or(0, |prefix| prefix.typed);
+            })
+        });
+        if result.is_err() {
+            for (index, grapheme) in chunk[typed..].grapheme_indices(true) {
+                if retry(|| simulator.text(grapheme)).is_ok() {
+                    continue;
                 }
-                OutputAction::MoveCaret(amount) => {
-                    let key = if *amount < 0 {
-                        EnigoKey::LeftArrow
-                    } else {
-                        EnigoKey::RightArrow
-                    };
-                    for _ in 0..amount.unsigned_abs() {
-                        tap_key(&mut enigo, key)?;
+                failed.push(grapheme.to_string());
+                match plan_unicode_fallback(fallback, text, offset + typed + index) {
+                    FallbackStep::Skip => {}
+                    FallbackStep::Type(replacement) => retry(|| simulator.text(&replacement))
+                        .map_err(|err| {
+                            anyhow::anyhow!(
+                                "could not type {}, nor '{replacement}' in its place: {err}",
+                                describe_untypable(&failed)
+                            )
+                        })?,
+                    FallbackStep::Paste(rest) => {
+                        paste(simulator, &rest).map_err(|err| {
+                            err.context(format!("could not type {}", describe_untypable(&failed)))
+                        })?;
+                        warn!(
+                            "could not type {}; pasted the rest of the text instead",
+                            describe_untypable(&failed)
+                        );
+                        return Ok(());
                     }
                 }
             }
         }
-        self.injecting.store(false, Ordering::Relaxed);
-        Ok(())
+        offset += chunk.len();
+        sleep_unless_cancelled(cancelled, pause)?;
     }
+    match fallback {
+        _ if failed.is_empty() => {}
+        UnicodeFallback::Replace => warn!(
+            "could not type {}; typed '?' instead",
+            describe_untypable(&failed)
+        ),
+        _ => warn!(
+            "could not type {}; skipped them",
+            describe_untypable(&failed)
+        ),
+    }
+    Ok(())
 }
 
-fn tap_key(enigo: &mut Enigo, key: EnigoKey) -> Result<()> {
-    enigo
-        .key(key, Direction::Press)
+fn type_action(
+    simulator: &mut dyn KeySimulator,
+    cancelled: &AtomicBool,
+    action: &OutputAction,
+    chunk_size: usize,
+    char_delay_ms: u64,
+    fallback: UnicodeFallback,
+    paste: &mut PasteWith<'_>,
+) -> Result<()> {
+    check_cancelled(cancelled)?;
+    match action {
+        OutputAction::Text(s) => type_text(
+            simulator,
+            cancelled,
+            s,
+            chunk_size,
+            char_delay_ms,
+            fallback,
+            paste,
+        )?,
+        OutputAction::Key(k) => tap_key(simulator, map_special_key(*k))?,
+        OutputAction::KeyCombo { modifiers, key } => {
+            let modifiers: Vec<EnigoKey> = modifiers.iter().map(|m| map_modifier(*m)).collect();
+            press_chord(simulator, &modifiers, map_combo_key(*key))?;
+        }
+        OutputAction::SleepMs(ms) => sleep_unless_cancelled(cancelled, Duration::from_millis(*ms))?,
+        OutputAction::SleepRange(min, max) => sleep_unless_cancelled(
+            cancelled,
+            Duration::from_millis(random_sleep_ms(*min, *max)),
+        )?,
+        OutputAction::MoveCaret { amount, unit } => {
+            let (modifiers, key) = caret_move_keys(*amount, *unit);
+            for _ in 0..amount.unsigned_abs() {
+                check_cancelled(cancelled)?;
+                press_chord(simulator, modifiers, key)?;
+            }
+        }
+        OutputAction::Select { amount, unit } => {
+            let (modifiers, key) = caret_move_keys(*amount, *unit);
+            let modifiers = [modifiers, &[EnigoKey::Shift]].concat();
+            hold_modifiers(simulator, &modifiers, |simulator| {
+                for _ in 0..amount.unsigned_abs() {
+                    check_cancelled(cancelled)?;
+                    tap_key(simulator, key)?;
+                }
+                Ok(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The chord tapped once per unit of a caret move: arrows for characters,
+/// Ctrl+arrows for words, Up/Down for lines.
+fn caret_move_keys(amount: i64, unit: CaretUnit) -> (&'static [EnigoKey], EnigoKey) {
+    let backward = amount < 0;
+    match unit {
+        CaretUnit::Chars if backward => (&[], EnigoKey::LeftArrow),
+        CaretUnit::Chars => (&[], EnigoKey::RightArrow),
+        CaretUnit::Words if backward => (&[EnigoKey::Control], EnigoKey::LeftArrow),
+        CaretUnit::Words => (&[EnigoKey::Control], EnigoKey::RightArrow),
+        CaretUnit::Lines if backward => (&[], EnigoKey::UpArrow),
+        CaretUnit::Lines => (&[], EnigoKey::DownArrow),
+    }
+}
+
+fn check_cancelled(cancelled: &AtomicBool) -> Result<()> {
+    if cancelled.load(Ordering::Relaxed) {
+        return Err(OutputCancelled.into());
+    }
+    Ok(())
+}
+
+/// Sleeps for `duration`, waking early with an error once cancelled.
+fn sleep_unless_cancelled(cancelled: &AtomicBool, duration: Duration) -> Result<()> {
+    let mut remaining = duration;
+    while !remaining.is_zero() {
+        check_cancelled(cancelled)?;
+        let step = remaining.min(CANCEL_POLL_INTERVAL);
+        std::thread::sleep(step);
+        remaining -= step;
+    }
+    Ok(())
+}
+
+/// Runs `f` with `flag` set so the listener marks the keys it sends as
+/// injected; an [`InjectGuard`] clears it again however `f` exits.
+fn while_injecting<T>(flag: &AtomicBool, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let result = {
+        let _guard = InjectGuard::new(flag);
+        f()
+    };
+    if let Err(err) = &result {
+        if !err.is::<OutputCancelled>() {
+            warn!("key injection failed, the expansion may be half-typed: {err}");
+        }
+    }
+    result
+}
+
+/// Retries a transiently failing simulator call, see [`INJECT_ATTEMPTS`].
+fn retry<T>(f: impl FnMut() -> Result<T>) -> Result<T> {
+    with_retries(INJECT_ATTEMPTS, INJECT_RETRY_BACKOFF, f)
+}
+
+fn send_paste_shortcut(simulator: &mut dyn KeySimulator, shortcut: PasteShortcut) -> Result<()> {
+    let (modifiers, key): (&[EnigoKey], EnigoKey) = match shortcut {
+        PasteShortcut::CtrlV => (&[EnigoKey::Control], EnigoKey::Unicode('v')),
+        PasteShortcut::ShiftInsert => (&[EnigoKey::Shift], EnigoKey::Insert),
+        PasteShortcut::CtrlShiftV => (
+            &[EnigoKey::Control, EnigoKey::Shift],
+            EnigoKey::Unicode('v'),
+        ),
+    };
+    press_chord(simulator, modifiers, key)
+}
+
+/// Holds `modifiers` in order, taps `key`, then releases the modifiers in
+/// reverse. Every modifier that went down is released, even after an error.
+fn press_chord(
+    simulator: &mut dyn KeySimulator,
+    modifiers: &[EnigoKey],
+    key: EnigoKey,
+) -> Result<()> {
+    hold_modifiers(simulator, modifiers, |simulator| tap_key(simulator, key))
+}
+
+/// Presses `modifiers` in order, runs `f`, then releases the pressed ones in
+/// reverse, even when a press or `f` fails.
+fn hold_modifiers(
+    simulator: &mut dyn KeySimulator,
+    modifiers: &[EnigoKey],
+    f: impl FnOnce(&mut dyn KeySimulator) -> Result<()>,
+) -> Result<()> {
+    let mut pressed = 0;
+    let mut result = Ok(());
+    for modifier in modifiers {
+        if let Err(err) = retry(|| simulator.key(*modifier, Direction::Press)) {
+            result = Err(anyhow::anyhow!("key press simulation failed: {err}"));
+            break;
+        }
+        pressed += 1;
+    }
+    if result.is_ok() {
+        result = f(simulator);
+    }
+
+    for modifier in modifiers[..pressed].iter().rev() {
+        if let Err(err) = retry(|| simulator.key(*modifier, Direction::Release)) {
+            if result.is_ok() {
+                result = Err(anyhow::anyhow!("key release simulation failed: {err}"));
+            }
+        }
+    }
+    result
+}
+
+fn tap_key(simulator: &mut dyn KeySimulator, key: EnigoKey) -> Result<()> {
+    retry(|| simulator.key(key, Direction::Press))
         .map_err(|err| anyhow::anyhow!("key press simulation failed: {err}"))?;
     std::thread::sleep(Duration::from_millis(1));
-    enigo
-        .key(key, Direction::Release)
+    retry(|| simulator.key(key, Direction::Release))
         .map_err(|err| anyhow::anyhow!("key release simulation failed: {err}"))?;
     std::thread::sleep(Duration::from_millis(1));
     Ok(())
@@ -100,13 +555,13 @@ fn map_event(event: &Event, is_injected: bool) -> Option<KeyEvent> {
     match event.event_type {
         EventType::KeyPress(key) => Some(KeyEvent {
             kind: KeyEventKind::Press,
-            printable: event.name.as_deref().and_then(extract_single_char),
+            text: event.name.clone(),
             special: Some(map_input_key(key)),
             is_injected,
         }),
         EventType::KeyRelease(key) => Some(KeyEvent {
             kind: KeyEventKind::Release,
-            printable: None,
+            text: None,
             special: Some(map_input_key(key)),
             is_injected,
         }),
@@ -114,21 +569,16 @@ fn map_event(event: &Event, is_injected: bool) -> Option<KeyEvent> {
     }
 }
 
-fn extract_single_char(s: &str) -> Option<char> {
-    let mut chars = s.chars();
-    let first = chars.next()?;
-    if chars.next().is_some() {
-        return None;
-    }
-    Some(first)
-}
-
 fn map_input_key(key: Key) -> SpecialInputKey {
     match key {
-        Key::ShiftLeft | Key::ShiftRight => SpecialInputKey::Shift,
-        Key::ControlLeft | Key::ControlRight => SpecialInputKey::Ctrl,
-        Key::Alt | Key::AltGr => SpecialInputKey::Alt,
-        Key::MetaLeft | Key::MetaRight => SpecialInputKey::Meta,
+        Key::ShiftLeft => SpecialInputKey::ShiftLeft,
+        Key::ShiftRight => SpecialInputKey::ShiftRight,
+        Key::ControlLeft => SpecialInputKey::CtrlLeft,
+        Key::ControlRight => SpecialInputKey::CtrlRight,
+        Key::Alt => SpecialInputKey::AltLeft,
+        Key::AltGr => SpecialInputKey::AltGr,
+        Key::MetaLeft => SpecialInputKey::MetaLeft,
+        Key::MetaRight => SpecialInputKey::MetaRight,
         Key::CapsLock => SpecialInputKey::CapsLock,
         Key::Return => SpecialInputKey::Enter,
         Key::Tab => SpecialInputKey::Tab,
@@ -141,8 +591,23 @@ fn map_input_key(key: Key) -> SpecialInputKey {
         Key::Home => SpecialInputKey::Home,
         Key::End => SpecialInputKey::End,
         Key::Delete => SpecialInputKey::Delete,
+        Key::Insert => SpecialInputKey::Insert,
         Key::PageUp => SpecialInputKey::PageUp,
         Key::PageDown => SpecialInputKey::PageDown,
+        Key::KpReturn => SpecialInputKey::Enter,
+        Key::KpDelete => SpecialInputKey::Delete,
+        // With NumLock on these commit a digit, which the engine types
+        // instead; with it off they move the caret like the key printed
+        // under the digit.
+        Key::Kp0 => SpecialInputKey::Insert,
+        Key::Kp1 => SpecialInputKey::End,
+        Key::Kp2 => SpecialInputKey::Down,
+        Key::Kp3 => SpecialInputKey::PageDown,
+        Key::Kp4 => SpecialInputKey::Left,
+        Key::Kp6 => SpecialInputKey::Right,
+        Key::Kp7 => SpecialInputKey::Home,
+        Key::Kp8 => SpecialInputKey::Up,
+        Key::Kp9 => SpecialInputKey::PageUp,
         Key::F1 => SpecialInputKey::F1,
         Key::F2 => SpecialInputKey::F2,
         Key::F3 => SpecialInputKey::F3,
@@ -155,7 +620,24 @@ fn map_input_key(key: Key) -> SpecialInputKey {
         Key::F10 => SpecialInputKey::F10,
         Key::F11 => SpecialInputKey::F11,
         Key::F12 => SpecialInputKey::F12,
-        _ => SpecialInputKey::Unknown,
+        Key::Unknown(code) => SpecialInputKey::Unknown(code),
+        _ => SpecialInputKey::Unknown(0),
+    }
+}
+
+fn map_modifier(modifier: KeyModifier) -> EnigoKey {
+    match modifier {
+        KeyModifier::Ctrl => EnigoKey::Control,
+        KeyModifier::Shift => EnigoKey::Shift,
+        KeyModifier::Alt => EnigoKey::Alt,
+        KeyModifier::Meta => EnigoKey::Meta,
+    }
+}
+
+fn map_combo_key(key: ComboKey) -> EnigoKey {
+    match key {
+        ComboKey::Special(key) => map_special_key(key),
+        ComboKey::Char(c) => EnigoKey::Unicode(c),
     }
 }
 
@@ -189,3 +671,463 @@ fn map_special_key(key: SpecialKey) -> EnigoKey {
         SpecialKey::F12 => EnigoKey::F12,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use super::{
+        check_extensions, map_input_key, press_chord, send_backspaces_with, tap_key,
+        type_actions_with, while_injecting, KeySimulator, TypedPrefix, X11RdevBackend,
+    };
+    use crate::config::{AppConfig, UnicodeFallback};
+    use crate::core::expansion::{CaretUnit, OutputAction};
+    use crate::io::events::SpecialInputKey;
+    use crate::io::output::{OutputSink, PartialOutput, SpecialKey};
+    use enigo::{Direction, Key as EnigoKey};
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Call {
+        Key(EnigoKey, Direction),
+        Text(String),
+    }
+
+    /// Records every call instead of touching the X server.
+    #[derive(Default)]
+    struct FakeSimulator {
+        calls: Vec<Call>,
+        fail_on: Option<EnigoKey>,
+        /// Calls that fail before the keyboard starts working.
+        transient_failures: usize,
+        /// Text containing this cannot be typed.
+        untypable: Option<char>,
+    }
+
+    impl FakeSimulator {
+        fn keys(&self) -> Vec<(EnigoKey, Direction)> {
+            self.calls
+                .iter()
+                .filter_map(|call| match call {
+                    Call::Key(key, direction) => Some((*key, *direction)),
+                    Call::Text(_) => None,
+                })
+                .collect()
+        }
+
+        fn typed(&self) -> String {
+            self.calls
+                .iter()
+                .filter_map(|call| match call {
+                    Call::Text(text) => Some(text.as_str()),
+                    Call::Key(..) => None,
+                })
+                .collect()
+        }
+    }
+
+    impl KeySimulator for FakeSimulator {
+        fn key(&mut self, key: EnigoKey, direction: Direction) -> anyhow::Result<()> {
+            if self.transient_failures > 0 {
+                self.transient_failures -= 1;
+                anyhow::bail!("busy");
+            }
+            if self.fail_on == Some(key) && direction == Direction::Press {
+                anyhow::bail!("mock failure");
+            }
+            self.calls.push(Call::Key(key, direction));
+            Ok(())
+        }
+
+        /// Types what comes before an untypable character, like enigo.
+        fn text(&mut self, text: &str) -> anyhow::Result<()> {
+            let Some(typed) = self.untypable.and_then(|c| text.find(c)) else {
+                self.calls.push(Call::Text(text.to_string()));
+                return Ok(());
+            };
+            if typed > 0 {
+                self.calls.push(Call::Text(text[..typed].to_string()));
+            }
+            Err(TypedPrefix {
+                typed,
+                source: anyhow::anyhow!("no keysym"),
+            }
+            .into())
+        }
+    }
+
+    /// Lets a test inspect the fake after handing it to a backend.
+    struct SharedSimulator(Arc<Mutex<FakeSimulator>>);
+
+    impl KeySimulator for SharedSimulator {
+        fn key(&mut self, key: EnigoKey, direction: Direction) -> anyhow::Result<()> {
+            self.0.lock().expect("mutex poisoned").key(key, direction)
+        }
+
+        fn text(&mut self, text: &str) -> anyhow::Result<()> {
+            self.0.lock().expect("mutex poisoned").text(text)
+        }
+    }
+
+    fn press_and_release(key: EnigoKey) -> [Call; 2] {
+        [
+            Call::Key(key, Direction::Press),
+            Call::Key(key, Direction::Release),
+        ]
+    }
+
+    #[test]
+    fn chord_releases_modifiers_in_reverse_order() {
+        let mut keyboard = FakeSimulator::default();
+
+        press_chord(
+            &mut keyboard,
+            &[EnigoKey::Control, EnigoKey::Shift],
+            EnigoKey::Unicode('v'),
+        )
+        .expect("chord should succeed");
+
+        assert_eq!(
+            keyboard.keys(),
+            vec![
+                (EnigoKey::Control, Direction::Press),
+                (EnigoKey::Shift, Direction::Press),
+                (EnigoKey::Unicode('v'), Direction::Press),
+                (EnigoKey::Unicode('v'), Direction::Release),
+                (EnigoKey::Shift, Direction::Release),
+                (EnigoKey::Control, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn chord_releases_held_modifiers_when_a_press_fails() {
+        let mut keyboard = FakeSimulator {
+            fail_on: Some(EnigoKey::End),
+            ..FakeSimulator::default()
+        };
+
+        let err = press_chord(&mut keyboard, &[EnigoKey::Shift], EnigoKey::End)
+            .expect_err("failing key should fail the chord");
+
+        assert!(err.to_string().contains("key press simulation failed"));
+        assert_eq!(
+            keyboard.keys(),
+            vec![
+                (EnigoKey::Shift, Direction::Press),
+                (EnigoKey::Shift, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn transient_key_failures_are_retried() {
+        let mut keyboard = FakeSimulator {
+            transient_failures: 2,
+            ..FakeSimulator::default()
+        };
+
+        tap_key(&mut keyboard, EnigoKey::Backspace).expect("retries should recover");
+
+        assert_eq!(
+            keyboard.keys(),
+            vec![
+                (EnigoKey::Backspace, Direction::Press),
+                (EnigoKey::Backspace, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_actions_report_how_many_were_sent() {
+        let mut keyboard = FakeSimulator {
+            fail_on: Some(EnigoKey::Tab),
+            ..FakeSimulator::default()
+        };
+        let actions = [
+            OutputAction::Text("a".to_string()),
+            OutputAction::Key(SpecialKey::Tab),
+            OutputAction::Text("b".to_string()),
+        ];
+
+        let err = type_actions_with(
+            &mut keyboard,
+            &AtomicBool::new(false),
+            &actions,
+            0,
+            0,
+            UnicodeFallback::Skip,
+            &mut |_, _| Ok(()),
+        )
+        .expect_err("tab should keep failing");
+
+        let partial = err
+            .downcast_ref::<PartialOutput>()
+            .expect("error should be a PartialOutput");
+        assert_eq!((partial.emitted, partial.total), (1, 3));
+        assert!(err.to_string().starts_with("sent 1 of 3 actions"));
+    }
+
+    #[test]
+    fn untypable_characters_are_skipped_replaced_or_pasted() {
+        let actions = [
+            OutputAction::Text("hi 😀 there".to_string()),
+            OutputAction::Key(SpecialKey::Tab),
+        ];
+        let type_with = |fallback| {
+            let mut keyboard = FakeSimulator {
+                untypable: Some('😀'),
+                ..FakeSimulator::default()
+            };
+            let mut pasted = Vec::new();
+            type_actions_with(
+                &mut keyboard,
+                &AtomicBool::new(false),
+                &actions,
+                4,
+                0,
+                fallback,
+                &mut |_, text| {
+                    pasted.push(text.to_string());
+                    Ok(())
+                },
+            )
+            .expect("fallback should keep the expansion going");
+            assert_eq!(keyboard.keys().len(), 2, "the tab is still pressed");
+            (keyboard.typed(), pasted)
+        };
+
+        assert_eq!(
+            type_with(UnicodeFallback::Skip),
+            ("hi  there".to_string(), Vec::new())
+        );
+        assert_eq!(
+            type_with(UnicodeFallback::Replace),
+            ("hi ? there".to_string(), Vec::new())
+        );
+        assert_eq!(
+            type_with(UnicodeFallback::Paste),
+            ("hi ".to_string(), vec!["😀 there".to_string()])
+        );
+    }
+
+    #[test]
+    fn caret_moves_tap_arrows_per_char_ctrl_arrows_per_word_and_up_down_per_line() {
+        let mut keyboard = FakeSimulator::default();
+        let actions = [
+            OutputAction::MoveCaret {
+                amount: -2,
+                unit: CaretUnit::Words,
+            },
+            OutputAction::MoveCaret {
+                amount: 1,
+                unit: CaretUnit::Lines,
+            },
+            OutputAction::MoveCaret {
+                amount: 1,
+                unit: CaretUnit::Chars,
+            },
+        ];
+
+        type_actions_with(
+            &mut keyboard,
+            &AtomicBool::new(false),
+            &actions,
+            0,
+            0,
+            UnicodeFallback::Skip,
+            &mut |_, _| Ok(()),
+        )
+        .expect("caret moves should be sent");
+
+        let ctrl_left = [
+            (EnigoKey::Control, Direction::Press),
+            (EnigoKey::LeftArrow, Direction::Press),
+            (EnigoKey::LeftArrow, Direction::Release),
+            (EnigoKey::Control, Direction::Release),
+        ];
+        let mut expected = [ctrl_left, ctrl_left].concat();
+        expected.extend([
+            (EnigoKey::DownArrow, Direction::Press),
+            (EnigoKey::DownArrow, Direction::Release),
+            (EnigoKey::RightArrow, Direction::Press),
+            (EnigoKey::RightArrow, Direction::Release),
+        ]);
+        assert_eq!(keyboard.keys(), expected);
+    }
+
+    #[test]
+    fn selections_hold_shift_around_every_arrow_tap() {
+        let mut keyboard = FakeSimulator::default();
+        let actions = [
+            OutputAction::Select {
+                amount: -2,
+                unit: CaretUnit::Chars,
+            },
+            OutputAction::Select {
+                amount: -1,
+                unit: CaretUnit::Words,
+            },
+        ];
+
+        type_actions_with(
+            &mut keyboard,
+            &AtomicBool::new(false),
+            &actions,
+            0,
+            0,
+            UnicodeFallback::Skip,
+            &mut |_, _| Ok(()),
+        )
+        .expect("selections should be sent");
+
+        assert_eq!(
+            keyboard.keys(),
+            vec![
+                (EnigoKey::Shift, Direction::Press),
+                (EnigoKey::LeftArrow, Direction::Press),
+                (EnigoKey::LeftArrow, Direction::Release),
+                (EnigoKey::LeftArrow, Direction::Press),
+                (EnigoKey::LeftArrow, Direction::Release),
+                (EnigoKey::Shift, Direction::Release),
+                (EnigoKey::Control, Direction::Press),
+                (EnigoKey::Shift, Direction::Press),
+                (EnigoKey::LeftArrow, Direction::Press),
+                (EnigoKey::LeftArrow, Direction::Release),
+                (EnigoKey::Shift, Direction::Release),
+                (EnigoKey::Control, Direction::Release),
+            ]
+        );
+
+        let mut keyboard = FakeSimulator {
+            fail_on: Some(EnigoKey::RightArrow),
+            ..FakeSimulator::default()
+        };
+        type_actions_with(
+            &mut keyboard,
+            &AtomicBool::new(false),
+            &[OutputAction::Select {
+                amount: 3,
+                unit: CaretUnit::Chars,
+            }],
+            0,
+            0,
+            UnicodeFallback::Skip,
+            &mut |_, _| Ok(()),
+        )
+        .expect_err("the arrow keeps failing");
+        assert_eq!(
+            keyboard.keys(),
+            vec![
+                (EnigoKey::Shift, Direction::Press),
+                (EnigoKey::Shift, Direction::Release),
+            ]
+        );
+    }
+
+    #[test]
+    fn representative_actions_make_exact_simulator_calls() {
+        let mut keyboard = FakeSimulator::default();
+        let actions = [
+            OutputAction::Text("Hello".to_string()),
+            OutputAction::Key(SpecialKey::Enter),
+            OutputAction::SleepMs(1),
+            OutputAction::Text("x".to_string()),
+            OutputAction::MoveCaret {
+                amount: -2,
+                unit: CaretUnit::Chars,
+            },
+        ];
+
+        send_backspaces_with(&mut keyboard, 2).expect("backspaces");
+        type_actions_with(
+            &mut keyboard,
+            &AtomicBool::new(false),
+            &actions,
+            2,
+            0,
+            UnicodeFallback::Skip,
+            &mut |_, _| Ok(()),
+        )
+        .expect("actions should be sent");
+
+        let expected = [
+            press_and_release(EnigoKey::Backspace).to_vec(),
+            press_and_release(EnigoKey::Backspace).to_vec(),
+            vec![
+                Call::Text("He".to_string()),
+                Call::Text("ll".to_string()),
+                Call::Text("o".to_string()),
+            ],
+            press_and_release(EnigoKey::Return).to_vec(),
+            vec![Call::Text("x".to_string())],
+            press_and_release(EnigoKey::LeftArrow).to_vec(),
+            press_and_release(EnigoKey::LeftArrow).to_vec(),
+        ]
+        .concat();
+        assert_eq!(keyboard.calls, expected);
+    }
+
+    #[test]
+    fn backend_types_through_its_simulator_with_the_configured_chunking() {
+        let fake = Arc::new(Mutex::new(FakeSimulator::default()));
+        let backend = X11RdevBackend::with_simulator(Box::new(SharedSimulator(Arc::clone(&fake))));
+        let config: AppConfig =
+            serde_yaml::from_str("output:\n  chunk_size: 3\n  char_delay_ms: 0\nexpansions: []\n")
+                .expect("config should parse");
+        backend.apply_config(&config);
+
+        backend.send_backspaces(1).expect("backspace");
+        backend
+            .send_actions(&[
+                OutputAction::Text("abcd".to_string()),
+                OutputAction::Key(SpecialKey::Tab),
+            ])
+            .expect("actions");
+
+        let expected = [
+            press_and_release(EnigoKey::Backspace).to_vec(),
+            vec![Call::Text("abc".to_string()), Call::Text("d".to_string())],
+            press_and_release(EnigoKey::Tab).to_vec(),
+        ]
+        .concat();
+        assert_eq!(fake.lock().expect("mutex poisoned").calls, expected);
+        assert!(!backend.injecting.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn injecting_flag_is_cleared_when_sending_fails() {
+        let flag = AtomicBool::new(false);
+
+        let result: anyhow::Result<()> = while_injecting(&flag, || {
+            assert!(flag.load(Ordering::Relaxed));
+            anyhow::bail!("enigo failed")
+        });
+
+        assert!(result.is_err());
+        assert!(!flag.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn keypad_and_unnamed_keys_get_their_own_mapping() {
+        assert_eq!(map_input_key(rdev::Key::KpReturn), SpecialInputKey::Enter);
+        assert_eq!(map_input_key(rdev::Key::KpDelete), SpecialInputKey::Delete);
+        assert_eq!(map_input_key(rdev::Key::Kp7), SpecialInputKey::Home);
+        assert_eq!(
+            map_input_key(rdev::Key::Unknown(191)),
+            SpecialInputKey::Unknown(191)
+        );
+        assert_eq!(map_input_key(rdev::Key::Minus), SpecialInputKey::Unknown(0));
+    }
+
+    #[test]
+    fn missing_x_extensions_are_named_in_the_error() {
+        check_extensions(|_| Ok(true)).expect("all extensions present");
+
+        let err = check_extensions(|name| Ok(name != "RECORD")).expect_err("RECORD is missing");
+        assert_eq!(
+            err.to_string(),
+            "the X server lacks the RECORD extension, which slykey needs to see the keys you type"
+        );
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+use slykey::config::Globals;
+use slykey::core::expansion::render_template_macros;
+
+#[test]
+fn timed_out_command_is_killed_with_everything_it_started() {
+    let globals = Globals::new();
+    let marker = std::env::temp_dir().join(format!("slykey-cmd-timeout-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    let started = Instant::now();
+
+    // The backgrounded subshell would leave the marker a second later; it is
+    // only stopped if the timeout kills the command's whole process group,
+    // not just the shell.
+    let err = render_template_macros(
+        &format!(
+            "{{{{CMD:timeout=300ms:(sleep 1; touch {}) & sleep 20}}}}",
+            marker.display()
+        ),
+        &globals,
+    )
+    .expect_err("command should time out");
+
+    assert!(
+        err.to_string().contains("timed out after 300ms"),
+        "unexpected error: {err}"
+    );
+    assert!(started.elapsed() < Duration::from_secs(10));
+    std::thread::sleep(Duration::from_millis(1500));
+    assert!(!marker.exists(), "background job outlived the timeout");
+}
+
+#[test]
+fn command_within_its_timeout_renders_normally() {
+    let globals = Globals::new();
+
+    let rendered = render_template_macros("[{{CMD:timeout=5s,cwd=/:pwd}}]", &globals)
+        .expect("command should finish in time");
+
+    assert_eq!(rendered, "[/]");
+}
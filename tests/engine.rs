@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use slykey::config::AppConfig;
+use slykey::core::engine::Engine;
+use slykey::core::expansion::OutputAction;
+use slykey::io::events::{KeyEvent, KeyEventKind, SpecialInputKey};
+use slykey::io::output::OutputSink;
+
+#[derive(Debug, PartialEq)]
+enum Emitted {
+    Backspaces(usize),
+    Text(String),
+    Key(String),
+}
+
+#[derive(Default)]
+struct MockSink {
+    emitted: Mutex<Vec<Emitted>>,
+}
+
+impl OutputSink for MockSink {
+    fn send_backspaces(&self, count: usize) -> Result<()> {
+        self.emitted
+            .lock()
+            .expect("sink mutex poisoned")
+            .push(Emitted::Backspaces(count));
+        Ok(())
+    }
+
+    fn send_actions(&self, actions: &[OutputAction]) -> Result<()> {
+        let mut emitted = self.emitted.lock().expect("sink mutex poisoned");
+        for action in actions {
+            match action {
+                OutputAction::Text(text) => emitted.push(Emitted::Text(text.clone())),
+                OutputAction::Key(key) => emitted.push(Emitted::Key(format!("{key:?}"))),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn config(yaml: &str) -> AppConfig {
+    let config: AppConfig = serde_yaml::from_str(yaml).expect("test config should parse");
+    config.validate().expect("test config should validate");
+    config
+}
+
+fn engine_with_sink(yaml: &str) -> (Engine, Arc<MockSink>) {
+    let sink = Arc::new(MockSink::default());
+    let mut engine = Engine::new(config(yaml));
+    engine.set_output(sink.clone());
+    (engine, sink)
+}
+
+fn press(printable: Option<char>, special: Option<SpecialInputKey>) -> KeyEvent {
+    KeyEvent {
+        kind: KeyEventKind::Press,
+        text: printable.map(String::from),
+        special,
+        is_injected: false,
+    }
+}
+
+fn type_text(engine: &mut Engine, text: &str) {
+    for c in text.chars() {
+        engine
+            .handle_event(press(Some(c), None))
+            .expect("event should be handled");
+    }
+}
+
+#[test]
+fn expands_trigger_typed_through_synthetic_events() {
+    let (mut engine, sink) = engine_with_sink(
+        "expansions:\n  - trigger: ';sig'\n    expansion: 'Best,{{KEY:ENTER}}Tyler'\n",
+    );
+
+    type_text(&mut engine, "hi ;sig");
+
+    assert_eq!(
+        *sink.emitted.lock().expect("sink mutex poisoned"),
+        vec![
+            Emitted::Backspaces(4),
+            Emitted::Text("Best,".to_string()),
+            Emitted::Key("Enter".to_string()),
+            Emitted::Text("Tyler".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn boundary_mode_waits_for_boundary_and_honors_backspace() {
+    let (mut engine, sink) = engine_with_sink(
+        "match_behavior: boundary\nexpansions:\n  - trigger: 'brb'\n    expansion: 'be right back'\n",
+    );
+
+    type_text(&mut engine, "brx");
+    engine
+        .handle_event(press(None, Some(SpecialInputKey::Backspace)))
+        .expect("backspace should be handled");
+    type_text(&mut engine, "b");
+    assert!(sink.emitted.lock().expect("sink mutex poisoned").is_empty());
+
+    type_text(&mut engine, " ");
+
+    assert_eq!(
+        *sink.emitted.lock().expect("sink mutex poisoned"),
+        vec![
+            Emitted::Backspaces(4),
+            Emitted::Text("be right back".to_string()),
+            Emitted::Text(" ".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn reload_config_swaps_triggers() {
+    let (mut engine, sink) =
+        engine_with_sink("expansions:\n  - trigger: ';a'\n    expansion: 'old'\n");
+
+    engine.reload_config(config(
+        "expansions:\n  - trigger: ';b'\n    expansion: 'new'\n",
+    ));
+    type_text(&mut engine, ";a;b");
+
+    assert_eq!(
+        *sink.emitted.lock().expect("sink mutex poisoned"),
+        vec![Emitted::Backspaces(2), Emitted::Text("new".to_string())]
+    );
+}